@@ -1,10 +1,12 @@
 extern crate tar;
 extern crate tokio;
 
+use flate2::{write::GzEncoder, Compression};
 use futures::{future::Future, stream::Stream};
+use std::collections::{HashSet, VecDeque};
 use std::ffi::{OsStr, OsString};
 use std::fs;
-use std::io;
+use std::io::{self, Write};
 use std::iter::IntoIterator;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
@@ -34,25 +36,90 @@ fn cut_path<P: AsRef<OsStr>>(p: P, max_len: usize) -> OsString {
     }
 }
 
+enum EntryName {
+    /// just file name, cut to basic tar header limit
+    Plain(OsString),
+    /// relative path within archive, stored in ustar header
+    Relative(String),
+}
+
+impl EntryName {
+    fn header(&self) -> tar::Header {
+        match self {
+            EntryName::Plain(fname) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_path(fname).expect("cannot set path in header");
+                header
+            }
+            EntryName::Relative(path) => {
+                let mut header = tar::Header::new_ustar();
+                // ustar can store up to 255 chars in name and prefix, if it does not fit use just file name
+                if header.set_path(path).is_err() {
+                    let fname = Path::new(path)
+                        .file_name()
+                        .map(|name| cut_path(name, PATH_MAX_LEN))
+                        .unwrap_or_default();
+                    header.set_path(fname).expect("cannot set path in header");
+                }
+                header
+            }
+        }
+    }
+}
+
+/// Header for directory entry, None if path is too long for ustar header
+fn dir_header(dir: &str) -> Option<tar::Header> {
+    let mut header = tar::Header::new_ustar();
+    header.set_path(format!("{}/", dir)).ok()?;
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o755);
+    Some(header)
+}
+
+/// Parent directories of relative path, top most first
+fn parent_dirs(name: &str) -> impl Iterator<Item = &str> {
+    name.match_indices('/')
+        .map(move |(idx, _)| &name[..idx])
+        .filter(|dir| !dir.is_empty())
+}
+
+/// Directories, which were not yet added to archive, for entry with relative path `name`
+fn new_dirs(name: &str, added: &mut HashSet<String>) -> VecDeque<String> {
+    parent_dirs(name)
+        .filter(|dir| added.insert(dir.to_string()))
+        .filter(|dir| dir_header(dir).is_some())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
 type PinnedFuture<T> = Pin<Box<dyn Future<Output = T> + Send + Sync>>;
+/// Called after file is added to archive with its path and size
+pub type Progress = Box<dyn Fn(&Path, u64) + Send + Sync>;
 
 #[allow(clippy::large_enum_variant)] // not a problem as there is only one instance of state
 enum TarState {
     BeforeNext,
+    Directories {
+        dirs: VecDeque<String>,
+        path: PathBuf,
+        name: Option<String>,
+    },
     NextFile {
         path: PathBuf,
+        name: Option<String>,
     },
     OpeningFile {
         file: PinnedFuture<Result<tokio_fs::File, io::Error>>,
-        fname: OsString,
+        fname: EntryName,
     },
     PrepareHeader {
-        fname: OsString,
+        fname: EntryName,
         meta: PinnedFuture<(Result<fs::Metadata, io::Error>, tokio_fs::File)>,
     },
     HeaderReady {
         file: tokio_fs::File,
-        fname: OsString,
+        fname: EntryName,
         meta: fs::Metadata,
     },
     Sending {
@@ -68,12 +135,33 @@ enum TarState {
 /// Calculates size of tar archive from list/iterator of known sizes of it's content.
 /// Works only for our case - e.g. contains files only
 ///
+/// Size can be calculated only for uncompressed archive, size of gzipped archive
+/// ([`TarStream::gzipped`]) is not known until it's created, so it has to be sent
+/// without Content-Length (chunked transfer).
+///
 pub fn calc_size<S: IntoIterator<Item = u64>>(sizes: S) -> u64 {
     sizes
         .into_iter()
         .fold(1024, |total, sz| total + 512 + 512 * ((sz + 511) / 512))
 }
 
+///
+/// Same as [`calc_size`], but for archive with directory entries
+/// ([`TarStream::include_directories`]), iterator items are (relative path, size)
+///
+/// Same limitation applies - size is only for uncompressed archive.
+///
+pub fn calc_size_with_dirs<'a, S: IntoIterator<Item = (&'a str, u64)>>(entries: S) -> u64 {
+    let mut dirs = HashSet::new();
+    let mut sizes = vec![];
+    let mut num_dirs = 0;
+    for (name, size) in entries {
+        num_dirs += new_dirs(name, &mut dirs).len() as u64;
+        sizes.push(size);
+    }
+    calc_size(sizes) + 512 * num_dirs
+}
+
 ///
 /// Tar archive as a Stream
 /// Sends chunks of tar archive, which are either tar headers or blocks of data from files
@@ -81,14 +169,18 @@ pub fn calc_size<S: IntoIterator<Item = u64>>(sizes: S) -> u64 {
 /// This tar is especially created to send content of directory in HTTP response,
 /// so it does not provide real metadata of files (not to reveal unnecessary details of local implementation).
 ///
-/// Only file name is stored in tar (limited to 100 chars), so it's not intended for hiearchical archives.
+/// Only file name is stored in tar (limited to 100 chars), unless stream is created with [`TarStream::tar_iter_named`],
+/// which stores given relative paths (up to 255 chars), so hierarchical archives can be created.
 ///
 pub struct TarStream<P> {
     state: Option<TarState>,
-    iter: Box<dyn Iterator<Item = P> + Send + Sync>,
+    iter: Box<dyn Iterator<Item = (P, Option<String>)> + Send + Sync>,
     position: usize,
     buf: [u8; BUFFER_LENGTH],
     base_dir: Option<PathBuf>,
+    added_dirs: Option<HashSet<String>>,
+    progress: Option<Progress>,
+    current_file: Option<(PathBuf, u64)>,
 }
 
 impl TarStream<PathBuf> {
@@ -111,7 +203,7 @@ impl TarStream<PathBuf> {
             };
         }
 
-        let iter = files.into_iter();
+        let iter = files.into_iter().map(|p| (p, None));
         let state = Some(TarState::BeforeNext);
         Ok(TarStream {
             state,
@@ -119,6 +211,9 @@ impl TarStream<PathBuf> {
             position: 0,
             buf: [0; BUFFER_LENGTH],
             base_dir: None,
+            added_dirs: None,
+            progress: None,
+            current_file: None,
         })
     }
 }
@@ -133,10 +228,33 @@ impl<P: AsRef<Path> + Send> TarStream<P> {
     {
         TarStream {
             state: Some(TarState::BeforeNext),
-            iter: Box::new(iter),
+            iter: Box::new(iter.map(|p| (p, None))),
             position: 0,
             buf: [0; BUFFER_LENGTH],
             base_dir: None,
+            added_dirs: None,
+            progress: None,
+            current_file: None,
+        }
+    }
+
+    ///
+    /// Create stream that tars files from given iterator of (path, name) pairs,
+    /// where name is relative path (separated by /) of the file in the archive
+    ///
+    pub fn tar_iter_named<I>(iter: I) -> Self
+    where
+        I: Iterator<Item = (P, String)> + Send + Sync + 'static,
+    {
+        TarStream {
+            state: Some(TarState::BeforeNext),
+            iter: Box::new(iter.map(|(p, name)| (p, Some(name)))),
+            position: 0,
+            buf: [0; BUFFER_LENGTH],
+            base_dir: None,
+            added_dirs: None,
+            progress: None,
+            current_file: None,
         }
     }
 
@@ -146,15 +264,53 @@ impl<P: AsRef<Path> + Send> TarStream<P> {
     {
         TarStream {
             state: Some(TarState::BeforeNext),
-            iter: Box::new(iter),
+            iter: Box::new(iter.map(|p| (p, None))),
             position: 0,
             buf: [0; BUFFER_LENGTH],
             base_dir: Some(base_dir.as_ref().into()),
+            added_dirs: None,
+            progress: None,
+            current_file: None,
         }
     }
 }
 
 impl<P> TarStream<P> {
+    ///
+    /// Adds directory entries for parent directories of relative paths
+    /// (given in [`TarStream::tar_iter_named`]), before first file in directory
+    ///
+    pub fn include_directories(mut self) -> Self {
+        self.added_dirs = Some(HashSet::new());
+        self
+    }
+
+    ///
+    /// Sets callback, which is called after each file is added to archive (with its path and size)
+    ///
+    pub fn with_progress<F>(mut self, progress: F) -> Self
+    where
+        F: Fn(&Path, u64) + Send + Sync + 'static,
+    {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    fn file_done(&mut self) {
+        if let (Some(progress), Some((path, size))) =
+            (self.progress.as_ref(), self.current_file.take())
+        {
+            progress(&path, size)
+        }
+    }
+
+    ///
+    /// Compresses archive with gzip (tar.gz), size of resulting stream is not known upfront
+    ///
+    pub fn gzipped(self) -> GzipStream<Self> {
+        GzipStream::new(self)
+    }
+
     fn full_path(&self, rel: PathBuf) -> PathBuf {
         match self.base_dir {
             Some(ref p) => p.clone().join(rel),
@@ -176,19 +332,46 @@ impl<P: AsRef<Path> + Send> Stream for TarStream<P> {
                             None => {
                                 self.state = Some(TarState::Finish { block: 0 });
                             }
-                            Some(path) => {
-                                self.state = Some(TarState::NextFile {
-                                    path: path.as_ref().to_owned(),
-                                });
+                            Some((path, name)) => {
+                                let path = path.as_ref().to_owned();
+                                let dirs = match (self.added_dirs.as_mut(), name.as_ref()) {
+                                    (Some(added), Some(name)) => new_dirs(name, added),
+                                    _ => VecDeque::new(),
+                                };
+                                self.state = Some(TarState::Directories { dirs, path, name });
+                            }
+                        },
+                        // directory entries, which precede file
+                        TarState::Directories {
+                            mut dirs,
+                            path,
+                            name,
+                        } => match dirs.pop_front().and_then(|dir| dir_header(&dir)) {
+                            Some(mut header) => {
+                                let now = SystemTime::now()
+                                    .duration_since(UNIX_EPOCH)
+                                    .unwrap()
+                                    .as_secs();
+                                header.set_mtime(now);
+                                header.set_cksum();
+                                self.state = Some(TarState::Directories { dirs, path, name });
+                                return Poll::Ready(Some(Ok(header.as_bytes().to_vec())));
                             }
+                            None => self.state = Some(TarState::NextFile { path, name }),
                         },
                         // we start with async opening of file
-                        TarState::NextFile { path } => {
-                            let fname = path
-                                .file_name()
-                                .map(|name| cut_path(name, PATH_MAX_LEN))
-                                .unwrap();
-                            let file = tokio_fs::File::open(self.full_path(path));
+                        TarState::NextFile { path, name } => {
+                            let fname = match name {
+                                Some(name) => EntryName::Relative(name),
+                                None => EntryName::Plain(
+                                    path.file_name()
+                                        .map(|name| cut_path(name, PATH_MAX_LEN))
+                                        .unwrap(),
+                                ),
+                            };
+                            let full_path = self.full_path(path);
+                            let file = tokio_fs::File::open(full_path.clone());
+                            self.current_file = Some((full_path, 0));
                             self.state = Some(TarState::OpeningFile {
                                 file: Box::pin(file),
                                 fname,
@@ -243,9 +426,11 @@ impl<P: AsRef<Path> + Send> Stream for TarStream<P> {
                                 .duration_since(UNIX_EPOCH)
                                 .unwrap()
                                 .as_secs();
-                            let mut header = tar::Header::new_gnu();
-                            header.set_path(fname).expect("cannot set path in header");
+                            let mut header = fname.header();
                             header.set_size(meta.len());
+                            if let Some((_, size)) = self.current_file.as_mut() {
+                                *size = meta.len();
+                            }
                             header.set_mode(0o644);
                             header.set_mtime(now);
                             header.set_cksum();
@@ -270,6 +455,7 @@ impl<P: AsRef<Path> + Send> Stream for TarStream<P> {
                                     let read = buf.filled().len();
                                     if read == 0 {
                                         self.state = Some(TarState::BeforeNext);
+                                        self.file_done();
                                         if pos > 0 {
                                             let rem = pos % 512;
                                             let padding_length =
@@ -316,6 +502,56 @@ impl<P: AsRef<Path> + Send> Stream for TarStream<P> {
     }
 }
 
+///
+/// Gzip compression of stream of byte chunks
+///
+pub struct GzipStream<S> {
+    inner: S,
+    encoder: Option<GzEncoder<Vec<u8>>>,
+}
+
+impl<S> GzipStream<S> {
+    pub fn new(inner: S) -> Self {
+        GzipStream {
+            inner,
+            encoder: Some(GzEncoder::new(Vec::new(), Compression::default())),
+        }
+    }
+}
+
+impl<S> Stream for GzipStream<S>
+where
+    S: Stream<Item = Result<Vec<u8>, io::Error>> + Unpin,
+{
+    type Item = Result<Vec<u8>, io::Error>;
+    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            if self.encoder.is_none() {
+                return Poll::Ready(None);
+            }
+            match Pin::new(&mut self.inner).poll_next(ctx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Ok(chunk))) => {
+                    let encoder = self.encoder.as_mut().unwrap();
+                    if let Err(e) = encoder.write_all(&chunk) {
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    let compressed = std::mem::take(encoder.get_mut());
+                    // compressor can buffer input, so continue until there is some output
+                    if !compressed.is_empty() {
+                        return Poll::Ready(Some(Ok(compressed)));
+                    }
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => {
+                    let encoder = self.encoder.take().unwrap();
+                    return Poll::Ready(Some(encoder.finish()));
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -353,6 +589,102 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_tar_named() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let tar_file_name = temp_dir.path().join("test3.tar");
+        let tar_file_name2 = tar_file_name.clone();
+        let files = ["src/lib.rs", "Cargo.toml"];
+        let sizes = files.iter().map(|f| Path::new(f).metadata().unwrap().len());
+        let expected_archive_len = calc_size(sizes);
+        let tar_stream =
+            TarStream::tar_iter_named(files.into_iter().map(|f| (PathBuf::from(f), f.to_string())));
+        let tar_file = tokio_fs::File::create(tar_file_name).await?;
+        let codec = tokio_util::codec::BytesCodec::new();
+        let mut file_sink = codec.framed(tar_file);
+        file_sink
+            .send_all(&mut tar_stream.map(|v| v.map(Bytes::from)))
+            .await?;
+
+        let archive_len = tar_file_name2.metadata().unwrap().len();
+        assert_eq!(archive_len, expected_archive_len);
+        check_archive(tar_file_name2, 2);
+        temp_dir.close().unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tar_with_dirs() -> Result<()> {
+        let temp_dir = tempdir().unwrap();
+        let tar_file_name = temp_dir.path().join("test4.tar");
+        let files = ["src/lib.rs", "Cargo.toml"];
+        let names = ["top/src/lib.rs", "top/Cargo.toml"];
+        let entries = names
+            .iter()
+            .zip(files.iter())
+            .map(|(n, f)| (*n, Path::new(f).metadata().unwrap().len()));
+        let expected_archive_len = calc_size_with_dirs(entries);
+        let tar_stream = TarStream::tar_iter_named(
+            files
+                .into_iter()
+                .zip(names)
+                .map(|(f, n)| (PathBuf::from(f), n.to_string())),
+        )
+        .include_directories();
+        let tar_file = tokio_fs::File::create(&tar_file_name).await?;
+        let codec = tokio_util::codec::BytesCodec::new();
+        let mut file_sink = codec.framed(tar_file);
+        file_sink
+            .send_all(&mut tar_stream.map(|v| v.map(Bytes::from)))
+            .await?;
+
+        let archive_len = tar_file_name.metadata().unwrap().len();
+        assert_eq!(archive_len, expected_archive_len);
+        let mut ar = tar::Archive::new(fs::File::open(tar_file_name).unwrap());
+        let entries = ar
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let e = e.unwrap();
+                (
+                    e.path().unwrap().to_string_lossy().into_owned(),
+                    e.header().entry_type(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("top/".to_string(), tar::EntryType::Directory),
+                ("top/src/".to_string(), tar::EntryType::Directory),
+                ("top/src/lib.rs".to_string(), tar::EntryType::Regular),
+                ("top/Cargo.toml".to_string(), tar::EntryType::Regular),
+            ],
+            entries
+        );
+        temp_dir.close().unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tar_gzipped() -> Result<()> {
+        let files = ["src/lib.rs", "Cargo.toml"];
+        let done = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let done2 = done.clone();
+        let tar_stream = TarStream::tar_iter(files.into_iter().map(PathBuf::from))
+            .with_progress(move |path, size| done2.lock().unwrap().push((path.to_owned(), size)))
+            .gzipped();
+        let data = tar_stream.try_concat().await?;
+        let expected = files
+            .iter()
+            .map(|f| (PathBuf::from(f), Path::new(f).metadata().unwrap().len()))
+            .collect::<Vec<_>>();
+        assert_eq!(expected, *done.lock().unwrap());
+        let mut ar = tar::Archive::new(flate2::read::GzDecoder::new(&data[..]));
+        let count = ar.entries().unwrap().count();
+        assert_eq!(2, count);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_create_tar() -> Result<()> {
         let temp_dir = tempdir().unwrap();
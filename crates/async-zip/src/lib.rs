@@ -1,12 +1,13 @@
 use std::{
-    io,
+    io::{self, Write},
     path::{Path, PathBuf},
 };
 use tokio::{fs, io::AsyncReadExt};
 
 use crate::error::Result;
-pub use crate::zip::calc_size;
+pub use crate::zip::{calc_size, Compression};
 use crate::zip::{Descriptor, Directory, FileHeader, ToBytes};
+use flate2::write::DeflateEncoder;
 use futures::{
     channel::mpsc::{channel, Receiver, Sender},
     SinkExt,
@@ -15,8 +16,14 @@ use futures::{
 mod date;
 pub mod error;
 mod zip;
+type CompressionSelector = Box<dyn Fn(&str) -> Compression + Send>;
+/// Called after file is added to archive with its path and size
+pub type Progress = Box<dyn Fn(&Path, u64) + Send>;
+
 pub struct Zipper<P> {
     files: Box<dyn Iterator<Item = (P, String)> + Send>,
+    compression: CompressionSelector,
+    progress: Option<Progress>,
 }
 
 impl<P> Zipper<P>
@@ -31,11 +38,34 @@ where
     {
         Zipper {
             files: Box::new(files),
+            compression: Box::new(|_| Compression::Store),
+            progress: None,
         }
     }
 
+    /// Sets callback, which is called after each file is added to archive (with its path and size)
+    pub fn with_progress<F>(mut self, progress: F) -> Self
+    where
+        F: Fn(&Path, u64) + Send + 'static,
+    {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Chooses compression for each entry by its name in archive.
+    /// If any entry is compressed, size of archive cannot be calculated upfront by [`calc_size`]
+    pub fn with_compression<F>(mut self, compression: F) -> Self
+    where
+        F: Fn(&str) -> Compression + Send + 'static,
+    {
+        self.compression = Box::new(compression);
+        self
+    }
+
     async fn main_loop(
         files: Box<dyn Iterator<Item = (P, String)> + Send>,
+        compression: CompressionSelector,
+        progress: Option<Progress>,
         mut sender: Sender<std::result::Result<Vec<u8>, io::Error>>,
     ) -> Result<()> {
         let mut pos: u64 = 0;
@@ -52,30 +82,57 @@ where
             let mut f = fs::File::open(&path).await?;
             let meta = f.metadata().await?;
             // send header
-            let file_header = FileHeader::new_from_name(name, meta.modified()?);
+            let entry_compression = compression(&name);
+            let file_header = FileHeader::new_from_name(name, meta.modified()?)
+                .with_compression(entry_compression);
 
             let file_header_bytes = file_header.to_bytes()?;
             let file_header_offset = pos;
             send!(file_header_bytes);
 
             let file_content_offset = pos;
+            let mut file_size: u64 = 0;
             let mut hasher = crc32fast::Hasher::new();
+            let mut encoder = match entry_compression {
+                Compression::Store => None,
+                Compression::Deflate => Some(DeflateEncoder::new(
+                    Vec::with_capacity(8 * 1024),
+                    flate2::Compression::default(),
+                )),
+            };
             loop {
                 let mut data = Vec::with_capacity(8 * 1024);
                 let read = f.read_buf(&mut data).await?;
                 if read == 0 {
                     break;
                 }
+                file_size += read as u64;
                 hasher.update(&data);
-                send!(data);
+                if let Some(encoder) = encoder.as_mut() {
+                    encoder.write_all(&data)?;
+                    // encoder buffers input, send only when there is some output
+                    let compressed = std::mem::take(encoder.get_mut());
+                    if !compressed.is_empty() {
+                        send!(compressed);
+                    }
+                } else {
+                    send!(data);
+                }
+            }
+            if let Some(encoder) = encoder {
+                let rest = encoder.finish()?;
+                send!(rest);
             }
 
-            let file_size = pos - file_content_offset;
+            let compressed_size = pos - file_content_offset;
             let crc = hasher.finalize();
-            let desc = Descriptor::new(file_size, crc);
+            let desc = Descriptor::new(file_size, compressed_size, crc);
             let desc_bytes = desc.to_bytes()?;
             send!(desc_bytes);
             dir.add_entry(file_header, desc, file_header_offset);
+            if let Some(progress) = progress.as_ref() {
+                progress(path.as_ref(), file_size)
+            }
         }
         let directory_bytes = dir.finalize(pos)?;
         sender
@@ -91,7 +148,7 @@ where
 
         tokio::spawn(async move {
             let sender = s.clone();
-            let res = Zipper::main_loop(self.files, sender).await;
+            let res = Zipper::main_loop(self.files, self.compression, self.progress, sender).await;
             if let Err(e) = res {
                 s.send(Err(e.into())).await.ok();
             }
@@ -122,7 +179,7 @@ impl Zipper<PathBuf> {
 #[cfg(test)]
 mod tests {
 
-    use super::{calc_size, Zipper};
+    use super::{calc_size, Compression, Zipper};
     use crate::error::Result;
     use futures::StreamExt;
     use std::{
@@ -199,6 +256,41 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_zip_compressed() -> Result<()> {
+        let dir = PathBuf::from("src");
+        let files = fs::read_dir(&dir)?
+            .map(|e| e.unwrap())
+            .filter(|e| e.file_type().unwrap().is_file())
+            .map(|e| (e.path(), e.file_name().into_string().unwrap()))
+            .collect::<Vec<_>>();
+        let uncompressed_size = calc_size(
+            files
+                .iter()
+                .map(|(p, n)| (p, n.as_str(), p.metadata().unwrap().len())),
+        )?;
+        let zipper = Zipper::from_iter(files.into_iter()).with_compression(|name| {
+            if name == "date.rs" {
+                Compression::Store
+            } else {
+                Compression::Deflate
+            }
+        });
+        let mut stream = zipper.zipped_stream();
+        let mut f = Cursor::new(Vec::<u8>::new());
+        while let Some(chunk) = stream.next().await {
+            f.write_all(&(chunk?)).unwrap();
+        }
+
+        assert!((f.get_ref().len() as u64) < uncompressed_size);
+
+        f.set_position(0);
+
+        test_zip(f, &dir).await;
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_zip_from_iter() -> Result<()> {
         let dir = PathBuf::from("src");
@@ -219,7 +311,13 @@ mod tests {
             .collect::<Vec<_>>();
         assert_eq!(files.len(), 4);
         let expected_size = calc_size(files.iter().map(|&(ref p, ref n, s)| (p, n.as_str(), s)))?;
-        let zipper = Zipper::from_iter(files.into_iter().map(|(p, n, _)| (p, n)));
+        let total_files = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+        let total_files2 = total_files.clone();
+        let zipper = Zipper::from_iter(files.into_iter().map(|(p, n, _)| (p, n))).with_progress(
+            move |_, _| {
+                total_files2.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            },
+        );
         let mut stream = zipper.zipped_stream();
         let mut f = Cursor::new(Vec::<u8>::new());
         while let Some(chunk) = stream.next().await {
@@ -227,6 +325,7 @@ mod tests {
         }
 
         assert_eq!(f.get_ref().len(), expected_size as usize);
+        assert_eq!(4, total_files.load(std::sync::atomic::Ordering::Relaxed));
 
         f.set_position(0);
 
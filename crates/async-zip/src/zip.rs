@@ -18,7 +18,26 @@ const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b;
 const MIN_VERSION: u16 = 20;
 const FLAGS: u16 = 0b0000_1000_0000_1000;
 const COMPRESS_STORE: u16 = 0;
+const COMPRESS_DEFLATE: u16 = 8;
 
+/// Compression method of zip entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Store,
+    Deflate,
+}
+
+impl Compression {
+    fn method(&self) -> u16 {
+        match self {
+            Compression::Store => COMPRESS_STORE,
+            Compression::Deflate => COMPRESS_DEFLATE,
+        }
+    }
+}
+
+/// Size of archive with stored (uncompressed) entries,
+/// size of archive with compressed entries cannot be known in advance
 pub fn calc_size<'a, P, I>(sizes: I) -> Result<u64>
 where
     I: IntoIterator<Item = (P, &'a str, u64)>,
@@ -55,6 +74,7 @@ pub trait ToBytes {
 pub struct FileHeader {
     file_name: String,
     modified: Timestamp,
+    compression: Compression,
 }
 
 impl FileHeader {
@@ -64,6 +84,7 @@ impl FileHeader {
         Ok(FileHeader {
             file_name,
             modified: modified.into(),
+            compression: Compression::Store,
         })
     }
 
@@ -71,8 +92,14 @@ impl FileHeader {
         FileHeader {
             file_name,
             modified: modified.into(),
+            compression: Compression::Store,
         }
     }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
 }
 
 impl ToBytes for FileHeader {
@@ -86,7 +113,7 @@ impl ToBytes for FileHeader {
         // general purpose bit flag
         h.put_u16_le(FLAGS);
         // Compression method
-        h.put_u16_le(COMPRESS_STORE);
+        h.put_u16_le(self.compression.method());
         // last mod file time and last mod file date
         h.put_u16_le(self.modified.dos_timepart());
         h.put_u16_le(self.modified.dos_datepart()?);
@@ -112,12 +139,17 @@ impl ToBytes for FileHeader {
 
 pub struct Descriptor {
     size: u64,
+    compressed_size: u64,
     crc: u32,
 }
 
 impl Descriptor {
-    pub fn new(size: u64, crc: u32) -> Self {
-        Descriptor { size, crc }
+    pub fn new(size: u64, compressed_size: u64, crc: u32) -> Self {
+        Descriptor {
+            size,
+            compressed_size,
+            crc,
+        }
     }
 }
 
@@ -125,8 +157,8 @@ impl ToBytes for Descriptor {
     fn to_bytes(&self) -> Result<Vec<u8>> {
         let mut d = BytesMut::with_capacity(DATA_DESCRIPTOR_SIZE as usize);
 
-        if self.size > u32::MAX as u64 {
-            return Err(Error::FileTooBig(self.size));
+        if self.size.max(self.compressed_size) > u32::MAX as u64 {
+            return Err(Error::FileTooBig(self.size.max(self.compressed_size)));
         }
 
         // data_descriptor header signature
@@ -134,7 +166,7 @@ impl ToBytes for Descriptor {
         // crc-32
         d.put_u32_le(self.crc);
         // compressed size
-        d.put_u32_le(self.size as u32);
+        d.put_u32_le(self.compressed_size as u32);
         // uncompressed size
         d.put_u32_le(self.size as u32);
 
@@ -165,17 +197,18 @@ impl DirectoryEntry {
         // general puprose bit flag
         buf.put_u16_le(FLAGS);
         // compression method
-        buf.put_u16_le(COMPRESS_STORE);
+        buf.put_u16_le(self.header.compression.method());
         // last mod file time + date
         buf.put_u16_le(self.header.modified.dos_timepart());
         buf.put_u16_le(self.header.modified.dos_datepart()?);
         // crc-32
         buf.put_u32_le(self.desc.crc);
         // compressed size
-        if self.desc.size > u32::MAX as u64 {
-            return Err(Error::FileTooBig(self.desc.size));
+        let max_size = self.desc.size.max(self.desc.compressed_size);
+        if max_size > u32::MAX as u64 {
+            return Err(Error::FileTooBig(max_size));
         }
-        buf.put_u32_le(self.desc.size as u32);
+        buf.put_u32_le(self.desc.compressed_size as u32);
         // uncompressed size
         buf.put_u32_le(self.desc.size as u32);
         // file name length
@@ -6,7 +6,7 @@ extern crate log;
 #[macro_use]
 extern crate derive_builder;
 
-pub use self::tree::{DirTree, SearchResult};
+pub use self::tree::{DirTree, SearchMode, SearchResult};
 use self::utils::{Cond, CondAll};
 use notify::EventKind;
 use notify::{recommended_watcher, RecursiveMode, Watcher};
@@ -153,12 +153,17 @@ impl DirCache {
         self.inner.search(query)
     }
 
-    pub fn search_collected<S, F, T>(&self, query: S, collector: F) -> Result<T, io::Error>
+    pub fn search_collected<S, F, T>(
+        &self,
+        query: S,
+        mode: SearchMode,
+        collector: F,
+    ) -> Result<T, io::Error>
     where
         S: AsRef<str>,
         F: FnOnce(SearchResult) -> T,
     {
-        self.inner.search_collected(query, collector)
+        self.inner.search_collected(query, mode, collector)
     }
 
     pub fn recent(&self) -> Result<Vec<PathBuf>, io::Error> {
@@ -213,7 +218,12 @@ impl DirCacheInner {
             .collect())
     }
 
-    fn search_collected<S, F, T>(&self, query: S, collector: F) -> Result<T, io::Error>
+    fn search_collected<S, F, T>(
+        &self,
+        query: S,
+        mode: SearchMode,
+        collector: F,
+    ) -> Result<T, io::Error>
     where
         S: AsRef<str>,
         F: FnOnce(SearchResult) -> T,
@@ -222,7 +232,9 @@ impl DirCacheInner {
         if cache.is_none() {
             return Err(io::Error::new(io::ErrorKind::Other, "cache not ready"));
         }
-        Ok(collector(cache.as_ref().unwrap().search(query)))
+        Ok(collector(
+            cache.as_ref().unwrap().search_with_mode(query, mode),
+        ))
     }
 
     fn recent(&self) -> Result<Vec<PathBuf>, io::Error> {
@@ -257,12 +269,16 @@ mod tests {
         let c = DirCache::new("test_data");
         c.load().unwrap();
         let res = c
-            .search_collected("chesterton modry", |iter| {
+            .search_collected("chesterton modry", SearchMode::Substring, |iter| {
                 iter.map(|i| i.path())
                     .collect::<std::collections::HashSet<_>>()
             })
             .unwrap();
         assert_eq!(1, res.len());
+        let res = c
+            .search_collected("modry*", SearchMode::Glob, |iter| iter.count())
+            .unwrap();
+        assert_eq!(2, res);
     }
     #[test]
     fn multithread() {
@@ -8,6 +8,7 @@ use std::fs;
 use std::io;
 use std::iter::{FromIterator, IntoIterator, Iterator, Skip};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::time::SystemTime;
 
 pub struct DirTree {
@@ -63,6 +64,72 @@ impl Ord for DirEntryTimed {
 
 pub type DirRef<'a> = NodeRef<'a, DirEntry>;
 
+/// How search terms are matched against (lowercased) entry names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SearchMode {
+    /// Term is contained anywhere in name
+    #[default]
+    Substring,
+    /// Some word of name starts with term
+    Prefix,
+    /// Whole name matches pattern, where `*` is any sequence of chars and `?` is one char
+    Glob,
+}
+
+impl SearchMode {
+    fn matches(&self, name: &str, term: &str) -> bool {
+        match self {
+            SearchMode::Substring => name.contains(term),
+            SearchMode::Prefix => name
+                .split(|c: char| !c.is_alphanumeric())
+                .any(|word| word.starts_with(term)),
+            SearchMode::Glob => glob_match(name, term),
+        }
+    }
+}
+
+impl FromStr for SearchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "substring" => Ok(SearchMode::Substring),
+            "prefix" => Ok(SearchMode::Prefix),
+            "glob" => Ok(SearchMode::Glob),
+            _ => Err(format!("Invalid search mode {}", s)),
+        }
+    }
+}
+
+fn glob_match(name: &str, pattern: &str) -> bool {
+    let name: Vec<char> = name.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    let (mut n, mut p) = (0, 0);
+    // position of last star in pattern and name position it was tried with
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, tried)) => {
+                    p = star + 1;
+                    n = tried + 1;
+                    backtrack = Some((star, n));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 pub struct SearchItem<'a>(DirRef<'a>);
 
 impl SearchItem<'_> {
@@ -92,6 +159,7 @@ impl SearchItem<'_> {
 pub struct SearchResult<'a> {
     current_node: DirRef<'a>,
     search_terms: Vec<String>,
+    mode: SearchMode,
     truncate_this_branch: bool,
     new_matched_terms: Option<BitVec>,
     matched_terms_stack: Vec<BitVec>,
@@ -165,7 +233,10 @@ impl SearchResult<'_> {
             .enumerate()
             .filter_map(|(i, term)| {
                 if !matched_terms[i] {
-                    if self.current_node.value().search_tag.contains(term) {
+                    if self
+                        .mode
+                        .matches(&self.current_node.value().search_tag, term)
+                    {
                         matched_terms.set(i, true);
                         None
                     } else {
@@ -278,10 +349,15 @@ impl DirTree {
     }
 
     pub fn search<S: AsRef<str>>(&self, query: S) -> SearchResult {
+        self.search_with_mode(query, SearchMode::Substring)
+    }
+
+    pub fn search_with_mode<S: AsRef<str>>(&self, query: S, mode: SearchMode) -> SearchResult<'_> {
         let search_terms = query
             .as_ref()
             .split(' ')
             .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
             .collect::<Vec<_>>();
         let m = BitVec::from_elem(search_terms.len(), false);
         SearchResult {
@@ -289,6 +365,7 @@ impl DirTree {
             matched_terms_stack: vec![m],
             current_node: self.tree.root(),
             search_terms,
+            mode,
             truncate_this_branch: false,
         }
     }
@@ -358,6 +435,37 @@ mod tests {
         assert_eq!(1, s.count());
     }
 
+    #[test]
+    fn test_search_modes() {
+        let c = DirTree::new("test_data").unwrap();
+        let s = c.search_with_mode("mod", SearchMode::Prefix);
+        assert_eq!(2, s.count());
+        let s = c.search_with_mode("odry", SearchMode::Prefix);
+        assert_eq!(0, s.count());
+        let s = c.search_with_mode("target", SearchMode::Prefix);
+        assert_eq!(2, s.count());
+        let s = c.search_with_mode("modry kr?z", SearchMode::Glob);
+        assert_eq!(0, s.count());
+        let s = c.search_with_mode("modry*", SearchMode::Glob);
+        assert_eq!(2, s.count());
+        let s = c.search_with_mode("chesterton* modry*", SearchMode::Glob);
+        assert_eq!(1, s.count());
+        let s = c.search_with_mode("cargo?", SearchMode::Glob);
+        assert_eq!(2, s.count());
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("modry kriz", "modry kr?z"));
+        assert!(glob_match("modry kriz", "*kriz"));
+        assert!(glob_match("modry kriz", "m*r*z"));
+        assert!(glob_match("", "*"));
+        assert!(!glob_match("modry kriz", "modry"));
+        assert!(!glob_match("modry kriz", "*kri"));
+        assert_eq!(Ok(SearchMode::Glob), "glob".parse());
+        assert!("regex".parse::<SearchMode>().is_err());
+    }
+
     #[test]
     fn test_recent() {
         let options = OptionsBuilder::default()
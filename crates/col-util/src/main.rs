@@ -30,6 +30,12 @@ enum Commands {
     Get {
         path: String,
     },
+    /// Compares cache with collection directory and reports differences (exits with code 2, if there are any)
+    Verify {
+        /// Fixes differences - updates missing and stale folders and removes orphaned ones
+        #[arg(long)]
+        repair: bool,
+    },
 }
 
 macro_rules! exit {
@@ -88,6 +94,31 @@ pub fn main() -> anyhow::Result<()> {
                 println!("{:?}", f);
             }
         }
+        Commands::Verify { repair } => {
+            let report = col.verify();
+            let sections = [
+                ("Missing", &report.missing),
+                ("Stale", &report.stale),
+                ("Orphaned", &report.orphaned),
+            ];
+            for (name, folders) in sections {
+                for folder in folders {
+                    println!("{}: {}", name, folder.to_str().unwrap_or("<NOT_UTF8>"));
+                }
+            }
+            println!(
+                "Missing {}, stale {}, orphaned {} folders",
+                report.missing.len(),
+                report.stale.len(),
+                report.orphaned.len()
+            );
+            if repair && !report.is_ok() {
+                col.repair()?;
+                println!("Cache repaired");
+            } else if !report.is_ok() {
+                std::process::exit(2);
+            }
+        }
     }
 
     Ok(())
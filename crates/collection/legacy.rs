@@ -5,7 +5,7 @@
 #[cfg(feature = "legacy-cache")]
 mod cache {
     use super::*;
-    use cachedirtree::{DirCache, OptionsBuilder};
+    use cachedirtree::{DirCache, OptionsBuilder, SearchMode};
 
     pub struct CachedSearch {
         caches: Vec<DirCache>,
@@ -33,7 +33,7 @@ mod cache {
     impl<S: AsRef<str>> SearchTrait<S> for CachedSearch {
         fn search(&self, collection: usize, query: S, ordering: FoldersOrdering) -> SearchResult {
             let mut res = self.caches[collection]
-                .search_collected(query, |iter| {
+                .search_collected(query, SearchMode::default(), |iter| {
                     let mut res = SearchResult::new();
                     iter.for_each(|e| {
                         res.subfolders.push(AudioFolderShort::from_path_and_name(
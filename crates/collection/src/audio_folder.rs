@@ -3,13 +3,21 @@ use std::collections::{HashMap, HashSet};
 use std::ffi::OsStr;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use std::{fs, mem};
 
 use super::audio_meta::*;
 use crate::collator::Collate;
-use crate::common::CollectionOptions;
+use crate::common::{CollectionOptions, CoverSource};
+use crate::cue::{chapters_from_cue, cue_file_path};
+use crate::folder_meta::{is_folder_meta, FolderMeta};
+use crate::ignore::IgnorePatterns;
 use crate::playlist::{is_playlist, Playlist};
-use crate::util::{get_file_name, get_meta, get_modified, get_real_file_type, guess_mime_type};
+use crate::silence::{adjust_chapters_to_silences, detect_silences};
+use crate::trash::TRASH_DIR;
+use crate::util::{
+    get_file_name, get_meta, get_modified, get_real_file_type, guess_mime_type, resolve_root,
+};
 use lazy_static::lazy_static;
 use regex::Regex;
 
@@ -19,6 +27,8 @@ pub struct FullAudioMeta {
     audio_meta: AudioMeta,
     has_cover: bool,
     has_description: bool,
+    /// chapters are just fixed duration parts of the file
+    split: bool,
 }
 
 pub enum DirType {
@@ -32,6 +42,7 @@ pub(crate) struct FolderOptions {
     pub chapters_duration: u32,
     pub chapters_from_duration: u32,
     pub ignore_chapters_meta: bool,
+    pub chapters_from_silence: bool,
     pub allow_symlinks: bool,
     pub no_dir_collaps: bool,
     pub natural_files_ordering: bool,
@@ -40,6 +51,11 @@ pub(crate) struct FolderOptions {
     #[cfg(feature = "tags-encoding")]
     pub tags_encoding: Option<String>,
     pub read_playlists: bool,
+    pub cover_sources: Vec<CoverSource>,
+    pub cover_names: Vec<String>,
+    pub ignore: IgnorePatterns,
+    /// additional root directories, collection directory is always first root
+    pub roots: Vec<PathBuf>,
 }
 
 impl From<CollectionOptions> for FolderOptions {
@@ -48,6 +64,7 @@ impl From<CollectionOptions> for FolderOptions {
             chapters_duration: o.chapters_duration,
             chapters_from_duration: o.chapters_from_duration,
             ignore_chapters_meta: o.ignore_chapters_meta,
+            chapters_from_silence: o.chapters_from_silence,
             allow_symlinks: o.allow_symlinks,
             no_dir_collaps: o.no_dir_collaps,
             natural_files_ordering: o.natural_files_ordering,
@@ -56,12 +73,23 @@ impl From<CollectionOptions> for FolderOptions {
             #[cfg(feature = "tags-encoding")]
             tags_encoding: o.tags_encoding,
             read_playlists: o.read_playlists,
+            cover_sources: o.cover_sources,
+            cover_names: o.cover_names,
+            // trash directory is never part of collection
+            ignore: IgnorePatterns::new(
+                &o.ignore_patterns
+                    .into_iter()
+                    .chain(std::iter::once(format!("/{}", TRASH_DIR)))
+                    .collect::<Vec<_>>(),
+            ),
+            roots: o.roots,
         }
     }
 }
 
 enum AudioInfo {
-    File(AudioFile),
+    /// audio file and if it has embedded cover
+    File(AudioFile, bool),
     Folder(AudioFolderShort),
 }
 
@@ -74,6 +102,37 @@ impl FolderLister {
     pub(crate) fn new_with_options(config: FolderOptions) -> Self {
         FolderLister { config }
     }
+
+    pub(crate) fn ignore_patterns(&self) -> &IgnorePatterns {
+        &self.config.ignore
+    }
+
+    /// All root directories of collection, base directory is first
+    pub(crate) fn roots<'a>(&'a self, base_dir: &'a Path) -> impl Iterator<Item = &'a Path> {
+        std::iter::once(base_dir).chain(self.config.roots.iter().map(PathBuf::as_path))
+    }
+
+    /// Full path in first root, which contains the path, base directory is used if path does not exist
+    pub(crate) fn resolve_path(&self, base_dir: &Path, rel_path: &Path) -> PathBuf {
+        if self.config.roots.is_empty() {
+            return base_dir.join(rel_path);
+        }
+        resolve_root(self.roots(base_dir), rel_path)
+            .unwrap_or(base_dir)
+            .join(rel_path)
+    }
+
+    /// Latest modification time of the folder across all roots
+    pub(crate) fn modified(&self, base_dir: &Path, rel_path: &Path) -> Option<SystemTime> {
+        if self.config.roots.is_empty() {
+            return get_modified(base_dir.join(rel_path));
+        }
+        self.roots(base_dir)
+            .map(|root| root.join(rel_path))
+            .filter(|p| p.exists())
+            .filter_map(get_modified)
+            .max()
+    }
 }
 
 impl FolderLister {
@@ -83,7 +142,55 @@ impl FolderLister {
         dir_path: P2,
         ordering: FoldersOrdering,
     ) -> Result<AudioFolder, io::Error> {
-        let full_path = base_dir.as_ref().join(&dir_path);
+        let (base_dir, dir_path) = (base_dir.as_ref(), dir_path.as_ref());
+        if self.config.roots.is_empty() {
+            return self.list_dir_in_root(base_dir, dir_path, ordering);
+        }
+        // same folder can be in several roots, listings are merged, first root has precedence
+        let mut listings = self
+            .roots(base_dir)
+            .filter(|root| root.join(dir_path).exists())
+            .map(|root| (root, self.list_dir_in_root(root, dir_path, ordering)));
+        let mut folder = match listings.next() {
+            Some((_, res)) => res?,
+            None => return self.list_dir_in_root(base_dir, dir_path, ordering),
+        };
+        if folder.is_file {
+            return Ok(folder);
+        }
+        let mut files_added = false;
+        for (root, res) in listings {
+            match res {
+                Ok(other) if !other.is_file => {
+                    files_added |= merge_folder(&mut folder, other, root)
+                }
+                Ok(_) => warn!(
+                    "{:?} in root {:?} is chapterized file, it will not be merged",
+                    dir_path, root
+                ),
+                Err(e) => error!("Cannot list {:?} in root {:?}: {}", dir_path, root, e),
+            }
+        }
+        if files_added {
+            if self.config.natural_files_ordering {
+                folder.files.sort_unstable_by(|a, b| a.collate_natural(b));
+            } else {
+                folder.files.sort_unstable_by(|a, b| a.collate(b));
+            }
+        }
+        folder
+            .subfolders
+            .sort_unstable_by(|a, b| a.compare_as(ordering, b));
+        Ok(folder)
+    }
+
+    fn list_dir_in_root(
+        &self,
+        base_dir: &Path,
+        dir_path: &Path,
+        ordering: FoldersOrdering,
+    ) -> Result<AudioFolder, io::Error> {
+        let full_path = base_dir.join(dir_path);
         match self.get_dir_type(&full_path)? {
             DirType::Dir => {
                 if self.is_collapsable_folder(&full_path) {
@@ -139,6 +246,7 @@ impl FolderLister {
                 start,
                 end,
                 number: count,
+                ..Default::default()
             });
             count += 1;
             start = end;
@@ -165,35 +273,44 @@ impl FolderLister {
                     audio_meta,
                     has_cover,
                     has_description,
+                    split: false,
                 })),
-                (None, Some(audio_meta)) => match chapters_from_csv(path)? {
-                    Some(chapters) => {
-                        if chapters.len() > 1 {
-                            Ok(DirType::File(FullAudioMeta {
-                                chapters,
-                                audio_meta,
-                                has_cover,
-                                has_description,
-                            }))
-                        } else {
-                            error!("Chapter file for {:?} has less then two chapters!", &path);
-                            Ok(DirType::Other)
+                (None, Some(audio_meta)) => {
+                    let chapters = match chapters_from_csv(path)? {
+                        Some(chapters) => Some(chapters),
+                        None => chapters_from_cue(path, audio_meta.duration)?,
+                    };
+                    match chapters {
+                        Some(chapters) => {
+                            if chapters.len() > 1 {
+                                Ok(DirType::File(FullAudioMeta {
+                                    chapters,
+                                    audio_meta,
+                                    has_cover,
+                                    has_description,
+                                    split: false,
+                                }))
+                            } else {
+                                error!("Chapter file for {:?} has less then two chapters!", &path);
+                                Ok(DirType::Other)
+                            }
                         }
-                    }
-                    None => {
-                        if self.is_long_file(Some(&audio_meta)) {
-                            let chapters = self.split_chapters(audio_meta.duration);
-                            Ok(DirType::File(FullAudioMeta {
-                                chapters,
-                                audio_meta,
-                                has_cover,
-                                has_description,
-                            }))
-                        } else {
-                            Ok(DirType::Other)
+                        None => {
+                            if self.is_long_file(Some(&audio_meta)) {
+                                let chapters = self.split_chapters(audio_meta.duration);
+                                Ok(DirType::File(FullAudioMeta {
+                                    chapters,
+                                    audio_meta,
+                                    has_cover,
+                                    has_description,
+                                    split: true,
+                                }))
+                            } else {
+                                Ok(DirType::Other)
+                            }
                         }
                     }
-                },
+                }
                 _ => Ok(DirType::Other),
             }
         } else {
@@ -226,11 +343,13 @@ impl FolderLister {
                 long_path, path, true,
             )?))
         } else {
+            let has_cover = meta.has_cover();
             let meta = meta.get_audio_info(&self.config.tags);
             if self.is_long_file(meta.as_ref())
                 || chapters_file_path(long_path)
                     .map(|p| p.is_file())
                     .unwrap_or(false)
+                || cue_file_path(long_path).is_some()
             {
                 // file is bigger then limit present as folder
                 Ok(AudioInfo::Folder(AudioFolderShort::from_path_complete(
@@ -245,7 +364,7 @@ impl FolderLister {
                     section: None,
                     mime: mime.to_string(),
                 };
-                Ok(AudioInfo::File(file))
+                Ok(AudioInfo::File(file, has_cover))
             }
         }
     }
@@ -261,9 +380,13 @@ impl FolderLister {
             Ok(dir_iter) => {
                 let mut files = vec![];
                 let mut subfolders = vec![];
-                let mut cover = None;
+                let cover;
+                let mut images = vec![];
+                // audio files with embedded cover
+                let mut with_cover = HashSet::new();
                 let mut description = None;
                 let mut playlist: Option<Playlist> = None;
+                let mut folder_meta: Option<FolderMeta> = None;
                 let tags;
                 let mut is_file = false;
                 let mut is_collapsed = false;
@@ -274,22 +397,39 @@ impl FolderLister {
                         Ok(f) => match get_real_file_type(&f, &full_path, allow_symlinks) {
                             Ok(ft) => {
                                 let long_path = f.path();
-                                let path = long_path.strip_prefix(&base_dir).unwrap().into();
-                                if ft.is_dir() {
+                                let path: PathBuf =
+                                    long_path.strip_prefix(&base_dir).unwrap().into();
+                                if self.config.ignore.is_ignored(&path) {
+                                    trace!("Ignoring {:?}", path);
+                                } else if ft.is_dir() {
                                     subfolders.push(AudioFolderShort::from_path_complete(
                                         long_path, path, false,
                                     )?)
                                 } else if ft.is_file() {
                                     if is_audio(&path) {
                                         match self.audio_info_for_file(path, &long_path) {
-                                            Ok(AudioInfo::File(file)) =>  files.push(file),
+                                            Ok(AudioInfo::File(file, has_cover)) =>  {
+                                                if has_cover {
+                                                    with_cover.insert(file.path.clone());
+                                                }
+                                                files.push(file)
+                                            }
                                             Ok(AudioInfo::Folder(folder)) => subfolders.push(folder),
                                             Err(e) => error!("Cannot add file {:?} because error in extraction audio meta: {}",long_path, e)
                                         }
-                                    } else if cover.is_none() && is_cover(&path) {
-                                        cover = Some(TypedFile::new(path))
+                                    } else if is_cover(&path) {
+                                        images.push(path)
                                     } else if description.is_none() && is_description(&path) {
                                         description = Some(TypedFile::new(path))
+                                    } else if folder_meta.is_none() && is_folder_meta(&path) {
+                                        folder_meta = FolderMeta::new(&long_path)
+                                            .map_err(|e| {
+                                                error!(
+                                                    "Error reading folder metadata {:?}: {}",
+                                                    long_path, e
+                                                )
+                                            })
+                                            .ok();
                                     } else if self.config.read_playlists
                                         && playlist.is_none()
                                         && is_playlist(&path)
@@ -324,13 +464,18 @@ impl FolderLister {
                     let full_path = base_dir.as_ref().join(subfolders.pop().unwrap().path);
                     match self.get_dir_type(&full_path)? {
                         DirType::File(full_meta) => {
-                            let f = self.list_dir_file(base_dir, full_path, full_meta, true)?;
+                            let f =
+                                self.list_dir_file(base_dir.as_ref(), full_path, full_meta, true)?;
                             files = f.files;
                             tags = f.tags;
                             is_file = true;
-                            if cover.is_none() {
-                                cover = f.cover;
-                            }
+                            // cover of chapterized file is already resolved including this folder images,
+                            // by default it's its embedded cover, so this folder image has precedence
+                            cover = if self.config.cover_sources.is_empty() {
+                                self.choose_cover(images).map(TypedFile::new).or(f.cover)
+                            } else {
+                                f.cover
+                            };
                             if description.is_none() {
                                 description = f.description;
                             }
@@ -375,7 +520,12 @@ impl FolderLister {
                             } else {
                                 let full_path = base_dir.as_ref().join(&item_path);
                                 match self.audio_info_for_file(item_path, &full_path) {
-                                    Ok(AudioInfo::File(file)) => files.push(file),
+                                    Ok(AudioInfo::File(file, has_cover)) => {
+                                        if has_cover {
+                                            with_cover.insert(file.path.clone());
+                                        }
+                                        files.push(file)
+                                    }
                                     Ok(AudioInfo::Folder(_)) => {
                                         warn!("PL item resolved as folder, will not be added")
                                     }
@@ -416,12 +566,17 @@ impl FolderLister {
                                             FoldersOrdering::Alphabetical,
                                             false,
                                         )?;
-                                        if !subdir.subfolders.is_empty() {
-                                            warn!("CD folder contains subfolders, these will not be visible");
-                                        }
                                         subdir.files.sort_unstable_by(file_sorter);
+                                        let chapters = self.collapsed_chapters(
+                                            base_dir.as_ref(),
+                                            mem::take(&mut subdir.subfolders),
+                                        );
                                         for mut f in subdir.files {
-                                            if let (Some(file_name), Some(subdir_name)) =
+                                            if f.section.is_some() {
+                                                // chapter of single chapterized file in CD folder, path is already complete
+                                                f.name = (prefix.clone() + " " + &f.name).into();
+                                                files.push(f);
+                                            } else if let (Some(file_name), Some(subdir_name)) =
                                                 (f.path.file_name(), subdir_name)
                                             {
                                                 f.name = (prefix.clone() + " " + &f.name).into();
@@ -439,6 +594,10 @@ impl FolderLister {
                                                 );
                                             }
                                         }
+                                        for mut f in chapters {
+                                            f.name = (prefix.clone() + " " + &f.name).into();
+                                            files.push(f);
+                                        }
                                     } else {
                                         subfolders.push(fld);
                                     }
@@ -456,23 +615,41 @@ impl FolderLister {
                         None
                     };
                     subfolders.sort_unstable_by(|a, b| a.compare_as(ordering, b));
+
+                    let first_file = files.first().filter(|_| !is_collapsed);
+                    cover = self.resolve_cover(
+                        base_dir.as_ref(),
+                        &full_path,
+                        &[CoverSource::File],
+                        || self.choose_cover(images.clone()).map(TypedFile::new),
+                        || {
+                            first_file
+                                .filter(|f| with_cover.contains(&f.path))
+                                .map(|f| TypedFile {
+                                    path: f.path.clone(),
+                                    mime: f.mime.clone(),
+                                })
+                        },
+                    );
                 }
 
-                extend_audiofolder(
-                    &full_path,
-                    AudioFolder {
-                        is_file,
-                        is_collapsed,
-                        modified: None,
-                        total_time: None,
-                        files,
-                        subfolders,
-                        cover,
-                        description,
-                        position: None,
-                        tags,
-                    },
-                )
+                let mut folder = AudioFolder {
+                    is_file,
+                    is_collapsed,
+                    modified: None,
+                    total_time: None,
+                    total_size: None,
+                    files,
+                    subfolders,
+                    cover,
+                    description,
+                    position: None,
+                    tags,
+                };
+                if let Some(folder_meta) = folder_meta {
+                    folder_meta.apply(&mut folder, base_dir.as_ref(), &full_path);
+                }
+                extend_audiofolder(base_dir.as_ref(), &full_path, folder)
             }
             Err(e) => {
                 error!("Requesting wrong directory {:?} : {}", full_path, e);
@@ -481,15 +658,64 @@ impl FolderLister {
         }
     }
 
+    /// Chapters of chapterized files in collapsed CD folder, they are merged together with other files,
+    /// other subfolders of CD folder are not visible
+    fn collapsed_chapters(
+        &self,
+        base_dir: &Path,
+        mut subfolders: Vec<AudioFolderShort>,
+    ) -> Vec<AudioFile> {
+        subfolders.sort_unstable_by(|a, b| {
+            if self.config.natural_files_ordering {
+                a.collate_natural(b)
+            } else {
+                a.collate(b)
+            }
+        });
+        let mut files = vec![];
+        for fld in subfolders {
+            let full_path = base_dir.join(&fld.path);
+            if !fld.is_file {
+                warn!(
+                    "CD folder contains subfolder {:?}, it will not be visible",
+                    full_path
+                );
+                continue;
+            }
+            match self
+                .get_dir_type(&full_path)
+                .and_then(|dir_type| match dir_type {
+                    DirType::File(full_meta) => {
+                        self.list_dir_file(base_dir, full_path.clone(), full_meta, true)
+                    }
+                    _ => Err(io::Error::other("Expecting chapterized file")),
+                }) {
+                Ok(f) => files.extend(f.files),
+                Err(e) => error!(
+                    "Cannot get chapters of file {:?} in CD folder: {}",
+                    full_path, e
+                ),
+            }
+        }
+        files
+    }
+
     #[allow(clippy::unnecessary_wraps)] // actually as its used in match with function returning results it's better to have Result return type
     fn list_dir_file<P: AsRef<Path>>(
         &self,
         base_dir: P,
         full_path: PathBuf,
-        full_meta: FullAudioMeta,
+        mut full_meta: FullAudioMeta,
         collapse: bool,
     ) -> Result<AudioFolder, io::Error> {
         let path = full_path.strip_prefix(&base_dir).unwrap();
+        if full_meta.split && self.config.chapters_from_silence {
+            // analysis takes a while, so it's done only when listing file, result is then cached
+            match detect_silences(&full_path) {
+                Ok(silences) => adjust_chapters_to_silences(&mut full_meta.chapters, &silences),
+                Err(e) => warn!("Cannot detect silences in file {:?}: {}", full_path, e),
+            }
+        }
         let mime = guess_mime_type(path);
         let mut tags = None;
         if self.config.tags.is_some() {
@@ -511,7 +737,8 @@ impl FolderLister {
                     AudioMeta {
                         bitrate: full_meta.audio_meta.bitrate,
                         duration: ((chap.end - chap.start) / 1000) as u32,
-                        tags: None, // TODO: consider extracting metadata from chapters too - but what will make sense?
+                        tags: chapter_tags(&chap, &self.config.tags),
+                        loudness: full_meta.audio_meta.loudness.clone(),
                     }
                 };
                 let (name, path) = name_and_path_for_chapter(path, &chap, collapse)?;
@@ -538,16 +765,29 @@ impl FolderLister {
                 None
             }
         };
+        // Chapterized file is presented as folder, so images from its directory are considered as its folder images
+        let cover = match full_path.parent() {
+            Some(dir) => self.resolve_cover(
+                base_dir.as_ref(),
+                dir,
+                &[CoverSource::Embedded],
+                || self.list_dir_cover(base_dir.as_ref(), dir),
+                || self_file(full_meta.has_cover),
+            ),
+            None => self_file(full_meta.has_cover),
+        };
         extend_audiofolder(
+            base_dir.as_ref(),
             &full_path,
             AudioFolder {
                 is_file: true,
                 is_collapsed: false,
                 modified: None,
                 total_time: None,
+                total_size: None,
                 files,
                 subfolders: vec![],
-                cover: self_file(full_meta.has_cover),
+                cover,
                 description: self_file(full_meta.has_description),
                 position: None,
                 tags,
@@ -556,6 +796,74 @@ impl FolderLister {
     }
 }
 
+impl FolderLister {
+    /// Resolves folder cover by trying cover sources in configured order,
+    /// or in `default_sources` order, if cover sources are not configured.
+    /// `dir` is full path of directory, which represents the folder.
+    fn resolve_cover(
+        &self,
+        base_dir: &Path,
+        dir: &Path,
+        default_sources: &[CoverSource],
+        file_cover: impl Fn() -> Option<TypedFile>,
+        embedded_cover: impl Fn() -> Option<TypedFile>,
+    ) -> Option<TypedFile> {
+        let sources = if self.config.cover_sources.is_empty() {
+            default_sources
+        } else {
+            &self.config.cover_sources
+        };
+        sources.iter().find_map(|source| match source {
+            CoverSource::File => file_cover(),
+            CoverSource::Embedded => embedded_cover(),
+            CoverSource::Parent => dir
+                .parent()
+                .filter(|p| p.starts_with(base_dir) && dir != base_dir)
+                .and_then(|p| self.list_dir_cover(base_dir, p)),
+        })
+    }
+
+    /// Finds best cover image file in directory
+    fn list_dir_cover(&self, base_dir: &Path, dir: &Path) -> Option<TypedFile> {
+        let images = fs::read_dir(dir)
+            .map_err(|e| warn!("Cannot list directory {:?} for cover: {}", dir, e))
+            .ok()?
+            .filter_map(|item| item.ok())
+            .filter(|item| {
+                get_real_file_type(item, dir, self.config.allow_symlinks)
+                    .map(|ft| ft.is_file())
+                    .unwrap_or(false)
+            })
+            .filter_map(|item| {
+                let long_path = item.path();
+                long_path
+                    .strip_prefix(base_dir)
+                    .ok()
+                    .filter(|p| is_cover(p))
+                    .map(|p| p.to_path_buf())
+            })
+            .collect();
+        self.choose_cover(images).map(TypedFile::new)
+    }
+
+    /// Chooses image, which name matches first of preferred cover names,
+    /// or first image in alphabetical order
+    fn choose_cover(&self, mut images: Vec<PathBuf>) -> Option<PathBuf> {
+        images.sort_unstable();
+        let stem = |p: &PathBuf| {
+            p.file_stem()
+                .map(|s| s.to_string_lossy().to_lowercase())
+                .unwrap_or_default()
+        };
+        self.config
+            .cover_names
+            .iter()
+            .find_map(|name| images.iter().position(|p| &stem(p) == name))
+            .map(|idx| images.swap_remove(idx))
+            .or_else(|| images.into_iter().next())
+    }
+}
+
 fn extract_folder_tags(files: &mut [AudioFile]) -> Option<HashMap<String, String>> {
     let mut iter = (files).iter();
     let mut folder_tags = iter
@@ -612,7 +920,59 @@ fn extract_folder_tags(files: &mut [AudioFile]) -> Option<HashMap<String, String
     }
 }
 
+/// Adds content of same folder from another root, items already present are kept,
+/// returns true if some files were added
+fn merge_folder(folder: &mut AudioFolder, other: AudioFolder, other_root: &Path) -> bool {
+    let files_count = folder.files.len();
+    let known_files: HashSet<PathBuf> = folder.files.iter().map(|f| f.path.clone()).collect();
+    let added_files: Vec<_> = other
+        .files
+        .into_iter()
+        .filter(|f| !known_files.contains(&f.path))
+        .collect();
+    let added_size = files_total_size(other_root, &added_files);
+    folder.total_size = Some(folder.total_size.unwrap_or(0) + added_size);
+    folder.files.extend(added_files);
+    let known_subfolders: HashSet<PathBuf> =
+        folder.subfolders.iter().map(|f| f.path.clone()).collect();
+    folder.subfolders.extend(
+        other
+            .subfolders
+            .into_iter()
+            .filter(|f| !known_subfolders.contains(&f.path)),
+    );
+    folder.cover = folder.cover.take().or(other.cover);
+    folder.description = folder.description.take().or(other.description);
+    folder.tags = folder.tags.take().or(other.tags);
+    folder.modified = folder.modified.max(other.modified);
+    folder.total_time = Some(
+        folder
+            .files
+            .iter()
+            .map(|f| f.meta.as_ref().map(|m| m.duration).unwrap_or(0))
+            .sum(),
+    );
+    folder.files.len() > files_count
+}
+
+/// Total size of audio files, chapters of same file are counted only once
+fn files_total_size(base_dir: &Path, files: &[AudioFile]) -> u64 {
+    files
+        .iter()
+        .map(|f| parse_chapter_path(&f.path).0.into_owned())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .filter_map(|p| {
+            fs::metadata(base_dir.join(&p))
+                .map_err(|e| warn!("Cannot get size of file {:?}: {}", p, e))
+                .ok()
+        })
+        .map(|m| m.len())
+        .sum()
+}
+
 fn extend_audiofolder<P: AsRef<Path>>(
+    base_dir: &Path,
     full_path: P,
     mut af: AudioFolder,
 ) -> Result<AudioFolder, io::Error> {
@@ -624,6 +984,7 @@ fn extend_audiofolder<P: AsRef<Path>>(
         .sum();
     af.modified = last_modification.map(TimeStamp::from);
     af.total_time = Some(total_time);
+    af.total_size = Some(files_total_size(base_dir, &af.files));
     Ok(af)
 }
 
@@ -649,6 +1010,20 @@ fn chapters_file_path(path: &Path) -> Option<PathBuf> {
         .map(|f| path.with_file_name(f))
 }
 
+/// Text (lyrics, transcript) for audio file is in `file.lrc` or `file.txt`
+/// (also `file.mp3.lrc` and `file.mp3.txt` are accepted), LRC is preferred
+pub fn text_sidecar_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    ["lrc", "txt"].into_iter().find_map(|ext| {
+        let mut full_name = name.to_owned();
+        full_name.push(".");
+        full_name.push(ext);
+        [path.with_file_name(full_name), path.with_extension(ext)]
+            .into_iter()
+            .find(|p| p.is_file())
+    })
+}
+
 fn chapters_from_csv(path: &Path) -> Result<Option<Vec<Chapter>>, io::Error> {
     if let Some(chapters_file) = chapters_file_path(path) {
         if chapters_file.is_file() {
@@ -678,7 +1053,7 @@ fn chapters_from_csv(path: &Path) -> Result<Option<Vec<Chapter>>, io::Error> {
                     }
                 })
                 .enumerate()
-                .map(|(number, (title, start, end))| Chapter{number: number as u32,title, start,end})
+                .map(|(number, (title, start, end))| Chapter{number: number as u32,title, start,end, ..Default::default()})
                 .collect();
             return Ok(Some(records));
         }
@@ -687,6 +1062,26 @@ fn chapters_from_csv(path: &Path) -> Result<Option<Vec<Chapter>>, io::Error> {
     Ok(None)
 }
 
+/// Chapter metadata limited to required tags
+fn chapter_tags(
+    chap: &Chapter,
+    required_tags: &Option<HashSet<String>>,
+) -> Option<HashMap<String, String>> {
+    let required_tags = required_tags.as_ref()?;
+    let tags: HashMap<_, _> = chap
+        .meta
+        .iter()
+        .map(|(k, v)| (k.to_lowercase(), v))
+        .filter(|(k, _)| required_tags.contains(k))
+        .map(|(k, v)| (k, v.clone()))
+        .collect();
+    if tags.is_empty() {
+        None
+    } else {
+        Some(tags)
+    }
+}
+
 const MAX_CHAPTER_SIZE: usize = 255;
 fn name_and_path_for_chapter(
     p: &Path,
@@ -813,6 +1208,49 @@ pub fn parse_chapter_path(p: &Path) -> (Cow<Path>, Option<TimeSpan>) {
     }
 }
 
+fn leading_number(s: &str) -> Option<u32> {
+    s.trim()
+        .split(|c: char| !c.is_ascii_digit())
+        .next()
+        .and_then(|n| n.parse().ok())
+}
+
+fn track_key(f: &AudioFile) -> (bool, u32, u64) {
+    // chapters keep their order
+    if let Some(section) = f.section.as_ref() {
+        return (false, 0, section.start);
+    }
+    let tag = |name: &str| {
+        f.meta
+            .as_ref()
+            .and_then(|m| m.tags.as_ref())
+            .and_then(|t| t.get(name))
+            .and_then(|v| leading_number(v))
+    };
+    match tag(media_info::tags::TRACK) {
+        Some(track) => (
+            false,
+            tag(media_info::tags::DISC).unwrap_or(0),
+            track.into(),
+        ),
+        None => (true, 0, 0),
+    }
+}
+
+/// Sorts files of folder listing, sort is stable, so files with same key keep listing order
+/// (it's also case for chapters, which have all same modification time)
+pub fn sort_files(files: &mut [AudioFile], ordering: FilesOrdering, base_dir: &Path) {
+    match ordering {
+        FilesOrdering::Listing => (),
+        FilesOrdering::Natural => files.sort_by(|a, b| a.collate_natural(b)),
+        FilesOrdering::Track => files.sort_by_cached_key(track_key),
+        FilesOrdering::Modified => files.sort_by_cached_key(|f| {
+            let modified = get_modified(base_dir.join(&f.path));
+            (modified.is_none(), modified)
+        }),
+    }
+}
+
 pub fn list_dir_files_only(
     base_dir: impl AsRef<Path>,
     dir_path: impl AsRef<Path>,
@@ -873,6 +1311,75 @@ pub fn list_dir_files_with_subdirs(
     )
 }
 
+const MAX_RECURSIVE_LISTING_DEPTH: usize = 32;
+
+type FileEntry = (PathBuf, String, u64);
+
+/// Lists files in whole subtree of given directory, names are paths relative to this directory
+/// (separated by /). Returns None if listing exceeds given limits for number of files
+/// or total size of files.
+pub fn list_dir_files_recursive(
+    base_dir: impl AsRef<Path>,
+    dir_path: impl AsRef<Path>,
+    allow_symlinks: bool,
+    max_files: usize,
+    max_size: u64,
+) -> Result<Option<Vec<FileEntry>>, io::Error> {
+    let base_dir = base_dir.as_ref();
+    let root = base_dir.join(&dir_path);
+    let relative_name = |p: &Path| -> Result<String, io::Error> {
+        let rel = p
+            .strip_prefix(&root)
+            .map_err(|e| io::Error::other(format!("Invalid path {}", e)))?;
+        rel.iter()
+            .map(|c| {
+                c.to_str()
+                    .ok_or_else(|| io::Error::other("Invalid file name - not UTF8"))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|parts| parts.join("/"))
+    };
+
+    let mut files = vec![];
+    let mut total_size = 0u64;
+    let mut dirs = vec![(dir_path.as_ref().to_path_buf(), 0)];
+    while let Some((dir, depth)) = dirs.pop() {
+        let listing = list_dir_files_ext(base_dir, &dir, allow_symlinks, None, relative_name)?;
+        total_size += listing.iter().map(|i| i.2).sum::<u64>();
+        files.extend(listing);
+        if files.len() > max_files || total_size > max_size {
+            debug!(
+                "Recursive listing of {:?} exceeded limits, {} files of total size {}",
+                root,
+                files.len(),
+                total_size
+            );
+            return Ok(None);
+        }
+
+        if depth >= MAX_RECURSIVE_LISTING_DEPTH {
+            warn!("Too deep directory structure at {:?}, not descending", dir);
+            continue;
+        }
+
+        let full_dir = base_dir.join(&dir);
+        let mut subdirs = vec![];
+        for item in fs::read_dir(&full_dir)? {
+            let f = item?;
+            match get_real_file_type(&f, &full_dir, allow_symlinks) {
+                Ok(ft) if ft.is_dir() => subdirs.push(dir.join(f.file_name())),
+                Ok(_) => (),
+                Err(e) => warn!("Cannot get dir entry type for {:?}, error: {}", f.path(), e),
+            }
+        }
+        // reverse order, so that subdirectories are popped in alphabetical order
+        subdirs.sort_unstable_by(|a, b| b.cmp(a));
+        dirs.extend(subdirs.into_iter().map(|d| (d, depth + 1)));
+    }
+
+    Ok(Some(files))
+}
+
 fn list_dir_files_ext<F>(
     base_dir: impl AsRef<Path>,
     dir_path: impl AsRef<Path>,
@@ -984,6 +1491,222 @@ mod tests {
         assert_eq!(num_folders, folder.subfolders.len());
     }
 
+    #[test]
+    fn test_files_total_size() {
+        let file = |p: &str| AudioFile {
+            name: p.into(),
+            path: p.into(),
+            meta: None,
+            mime: "audio/mpeg".into(),
+            section: None,
+        };
+        let base_dir = Path::new(TEST_DATA_BASE);
+        let size = |p: &str| fs::metadata(base_dir.join(p)).unwrap().len();
+        let files = vec![
+            file("test_data/02-file.opus"),
+            file("test_data/03-file.mka/001 - Chapter 1$$0-1000$$.mka"),
+            file("test_data/03-file.mka/002 - Chapter 2$$1000-2000$$.mka"),
+            file("test_data/non-existent.mp3"),
+        ];
+        assert_eq!(
+            size("test_data/02-file.opus") + size("test_data/03-file.mka"),
+            files_total_size(base_dir, &files)
+        );
+    }
+
+    fn file_with_tags(name: &str, tags: &[(&str, &str)]) -> AudioFile {
+        AudioFile {
+            name: name.into(),
+            path: name.into(),
+            meta: Some(AudioMeta {
+                duration: 1,
+                bitrate: 1,
+                tags: Some(
+                    tags.iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                ),
+                loudness: None,
+            }),
+            mime: "audio/mpeg".into(),
+            section: None,
+        }
+    }
+
+    #[test]
+    fn test_sort_files_by_track() {
+        let mut files = vec![
+            file_with_tags("a", &[("track", "2/3"), ("disc", "1")]),
+            file_with_tags("b", &[]),
+            file_with_tags("c", &[("track", "1"), ("disc", "2")]),
+            file_with_tags("d", &[("track", "10"), ("disc", "1")]),
+            file_with_tags("e", &[("track", "1/3"), ("disc", "1")]),
+        ];
+        sort_files(&mut files, FilesOrdering::Track, Path::new("/"));
+        let names: Vec<_> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(vec!["e", "a", "d", "c", "b"], names);
+
+        sort_files(&mut files, FilesOrdering::Natural, Path::new("/"));
+        let names: Vec<_> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(vec!["a", "b", "c", "d", "e"], names);
+    }
+
+    #[test]
+    fn test_list_dir_multiple_roots() {
+        let tmp_dir = tempdir::TempDir::new("AS_ROOTS_TEST").unwrap();
+        let (root1, root2) = (tmp_dir.path().join("disk1"), tmp_dir.path().join("disk2"));
+        for dir in ["author/book1", "author/shared"] {
+            fs::create_dir_all(root1.join(dir)).unwrap();
+        }
+        for dir in ["author/book2", "author/shared", "other"] {
+            fs::create_dir_all(root2.join(dir)).unwrap();
+        }
+        fs::write(root2.join("author/info.txt"), "Author").unwrap();
+        let opts = CollectionOptions {
+            roots: vec![root2.clone()],
+            ..Default::default()
+        };
+        let lister = FolderLister::new_with_options(opts.into());
+        let folder = lister
+            .list_dir(&root1, "author", FoldersOrdering::Alphabetical)
+            .unwrap();
+        let names: Vec<_> = folder.subfolders.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(vec!["book1", "book2", "shared"], names);
+        assert_eq!(
+            Some(Path::new("author/info.txt")),
+            folder.description.as_ref().map(|d| d.path.as_path())
+        );
+        let folder = lister
+            .list_dir(&root1, "", FoldersOrdering::Alphabetical)
+            .unwrap();
+        assert_eq!(2, folder.subfolders.len());
+        assert!(lister
+            .list_dir(&root1, "missing", FoldersOrdering::Alphabetical)
+            .is_err());
+        assert_eq!(
+            root2.join("author/book2"),
+            lister.resolve_path(&root1, Path::new("author/book2"))
+        );
+        assert_eq!(
+            root1.join("author/shared"),
+            lister.resolve_path(&root1, Path::new("author/shared"))
+        );
+        assert_eq!(
+            root1.join("missing"),
+            lister.resolve_path(&root1, Path::new("missing"))
+        );
+    }
+
+    #[test]
+    fn test_sort_files_by_modified() {
+        let tmp_dir = tempdir::TempDir::new("AS_SORT_TEST").unwrap();
+        let mut files = vec![];
+        for (name, age) in [("a", 10), ("b", 30), ("c", 20)] {
+            let path = tmp_dir.path().join(name);
+            let f = fs::File::create(&path).unwrap();
+            f.set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(age))
+                .unwrap();
+            files.push(file_with_tags(name, &[]));
+        }
+        files.push(file_with_tags("missing", &[]));
+        sort_files(&mut files, FilesOrdering::Modified, tmp_dir.path());
+        let names: Vec<_> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(vec!["b", "c", "a", "missing"], names);
+    }
+
+    #[test]
+    fn test_collapse_cd_folders_with_chapters() {
+        media_info::init();
+        let tmp_dir = tempdir::TempDir::new("AS_CD_TEST").unwrap();
+        let book = tmp_dir.path().join("book");
+        let test_data = Path::new(TEST_DATA_BASE).join("test_data");
+        for (cd, file) in [
+            ("CD1", "01-file.mp3"),
+            ("CD1", "01-file.mp3.chapters"),
+            ("CD2", "02-file.opus"),
+        ] {
+            let dir = book.join(cd);
+            fs::create_dir_all(&dir).unwrap();
+            fs::copy(test_data.join(file), dir.join(file)).unwrap();
+        }
+        let opts = CollectionOptions {
+            cd_folder_regex_str: Some(r"^CD\d+$".into()),
+            cd_folder_regex: Some(Regex::new(r"^CD\d+$").unwrap()),
+            ..Default::default()
+        };
+        let lister = FolderLister::new_with_options(opts.into());
+        let folder = lister
+            .list_dir(tmp_dir.path(), "book", FoldersOrdering::Alphabetical)
+            .unwrap();
+        assert!(folder.is_collapsed);
+        assert!(folder.subfolders.is_empty());
+        assert_eq!(4, folder.files.len());
+        assert!(folder.files[0].name.starts_with("CD1 "));
+        assert!(folder.files[0].section.is_some());
+        let (p, span) = parse_chapter_path(&folder.files[0].path);
+        assert_eq!(Path::new("book/CD1/01-file.mp3"), p.as_ref());
+        assert!(span.is_some());
+        let last = folder.files.last().unwrap();
+        assert_eq!(Path::new("book/CD2$$02-file.opus"), last.path);
+        assert!(last.name.starts_with("CD2 "));
+    }
+
+    #[test]
+    fn test_choose_cover() {
+        let opts = CollectionOptions {
+            cover_names: vec!["front".into(), "cover".into()],
+            ..Default::default()
+        };
+        let lister = FolderLister::new_with_options(opts.into());
+        let images = vec![
+            PathBuf::from("a/back.jpg"),
+            PathBuf::from("a/Cover.png"),
+            PathBuf::from("a/front.jpg"),
+        ];
+        assert_eq!(
+            Some(PathBuf::from("a/front.jpg")),
+            lister.choose_cover(images.clone())
+        );
+        assert_eq!(
+            Some(PathBuf::from("a/Cover.png")),
+            lister.choose_cover(images[..2].to_vec())
+        );
+        let lister = FolderLister::new_with_options(CollectionOptions::default().into());
+        assert_eq!(
+            Some(PathBuf::from("a/Cover.png")),
+            lister.choose_cover(images)
+        );
+        assert_eq!(None, lister.choose_cover(vec![]));
+    }
+
+    #[test]
+    fn test_parent_cover() {
+        let opts = CollectionOptions {
+            cover_sources: vec![CoverSource::Parent],
+            ..Default::default()
+        };
+        let lister = FolderLister::new_with_options(opts.into());
+        let base = Path::new(TEST_DATA_BASE);
+        let usak = base.join("test_data/usak");
+        let cover = lister.resolve_cover(base, &usak, &[CoverSource::File], || None, || None);
+        assert_eq!(
+            Some(PathBuf::from("test_data/cover.jpg")),
+            cover.map(|c| c.path)
+        );
+        let cover = lister.resolve_cover(base, base, &[CoverSource::File], || None, || None);
+        assert!(cover.is_none());
+
+        // default sources are used, only if cover sources are not configured
+        let lister = FolderLister::new_with_options(CollectionOptions::default().into());
+        let cover = lister.resolve_cover(base, &usak, &[CoverSource::File], || None, || None);
+        assert!(cover.is_none());
+        let cover = lister.resolve_cover(base, &usak, &[CoverSource::Parent], || None, || None);
+        assert_eq!(
+            Some(PathBuf::from("test_data/cover.jpg")),
+            cover.map(|c| c.path)
+        );
+    }
+
     #[test]
     fn test_list_dir_files_only() {
         let res = list_dir_files_only("/non-existent", "folder", false);
@@ -994,6 +1717,19 @@ mod tests {
         assert_eq!(folder.len(), 5);
     }
 
+    #[test]
+    fn test_list_dir_files_recursive() {
+        let res = list_dir_files_recursive(TEST_DATA_BASE, "test_data/", false, 100, u64::MAX);
+        let files = res.unwrap().unwrap();
+        assert_eq!(files.len(), 7);
+        assert!(files
+            .iter()
+            .any(|(_, name, _)| name == "usak/kulisak/01-chapter-1.mp3"));
+
+        let res = list_dir_files_recursive(TEST_DATA_BASE, "test_data/", false, 5, u64::MAX);
+        assert!(res.unwrap().is_none());
+    }
+
     #[test]
     fn test_json() {
         let lister = FolderLister::new_with_options(CollectionOptions::default().into());
@@ -1057,6 +1793,7 @@ mod tests {
             title: "Chapter1".into(),
             start: 1000,
             end: 2000,
+            ..Default::default()
         };
 
         let p = PathBuf::from("stoker/dracula/dracula.m4b");
@@ -1080,6 +1817,7 @@ mod tests {
             title: long_name.into(),
             start: 1000,
             end: 2000,
+            ..Default::default()
         };
 
         let correct = "stoker/dracula/dracula.m4b/001 - As I ponder the complexities of the world, I am struck by the fragility of human existence and the interconnect... tiniest microbe to the vast expanses of the universe, everything is connected in ways we may never fully comprehend.$$1000-2000$$.m4b";
@@ -1094,6 +1832,7 @@ mod tests {
             title: limit_case,
             start: 1000,
             end: 2000,
+            ..Default::default()
         };
         let p2 = PathBuf::from("");
         let (_, name) = name_and_path_for_chapter(&p2, &chap2, false).unwrap();
@@ -1109,6 +1848,7 @@ mod tests {
             title: cesky,
             start: 1000,
             end: 2000,
+            ..Default::default()
         };
         let p2 = PathBuf::from("");
         let (_, name) = name_and_path_for_chapter(&p2, &chap3, false).unwrap();
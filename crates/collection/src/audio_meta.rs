@@ -4,7 +4,8 @@ use crate::position::PositionShort;
 use crate::util::{get_file_name, get_modified, guess_mime_type};
 use mime_guess::Mime;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{hash_map::DefaultHasher, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use std::{
     cmp::Ordering,
     path::{Path, PathBuf},
@@ -13,6 +14,7 @@ use std::{
 use unicase::UniCase;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, Ord)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
 /// This is timestamp is miliseconds from start of Unix epoch
 pub struct TimeStamp(u64);
 
@@ -32,6 +34,12 @@ impl From<SystemTime> for TimeStamp {
     }
 }
 
+impl From<TimeStamp> for SystemTime {
+    fn from(t: TimeStamp) -> Self {
+        SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(t.0)
+    }
+}
+
 impl From<u64> for TimeStamp {
     fn from(n: u64) -> Self {
         TimeStamp(n)
@@ -56,8 +64,16 @@ where
     }
 }
 
+impl Hash for TimeStamp {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
 pub struct TypedFile {
+    #[cfg_attr(feature = "api-spec", schema(value_type = String))]
     pub path: PathBuf,
     pub mime: String,
 }
@@ -74,15 +90,19 @@ impl TypedFile {
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
 pub struct FileSection {
     pub start: u64,
     pub duration: Option<u64>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
 pub struct AudioFile {
     #[serde(with = "unicase_serde::unicase")]
+    #[cfg_attr(feature = "api-spec", schema(value_type = String))]
     pub name: UniCase<String>,
+    #[cfg_attr(feature = "api-spec", schema(value_type = String))]
     pub path: PathBuf,
     pub meta: Option<AudioMeta>,
     pub mime: String,
@@ -90,12 +110,15 @@ pub struct AudioFile {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
 pub struct AudioFolder {
     pub is_file: bool,
     #[serde(default)]
     pub is_collapsed: bool,
     pub modified: Option<TimeStamp>, // last modification time of this folder
     pub total_time: Option<u32>,     // total playback time of contained audio files
+    #[serde(default)]
+    pub total_size: Option<u64>, // total size in bytes of contained audio files
     pub files: Vec<AudioFile>,
     pub subfolders: Vec<AudioFolderShort>,
     pub cover: Option<TypedFile>, // cover is file in folder - either jpg or png
@@ -107,6 +130,70 @@ pub struct AudioFolder {
     pub tags: Option<HashMap<String, String>>, // metadata tags, which are applicable for whole folder
 }
 
+/// Tags are hashed sorted by key, so hash does not depend on HashMap iteration order
+fn hash_tags(tags: Option<&HashMap<String, String>>, hasher: &mut impl Hasher) {
+    let mut tags: Option<Vec<_>> = tags.map(|t| t.iter().collect());
+    if let Some(ref mut t) = tags {
+        t.sort_unstable();
+    }
+    tags.hash(hasher);
+}
+
+impl AudioFolder {
+    /// Hash of the folder listing state - folder modification time, its content, metadata and playback positions.
+    /// It's cheap alternative to hashing of whole listing, usable for instance as HTTP ETag.
+    /// Returns None if folder modification time is unknown.
+    pub fn state_hash(&self) -> Option<u64> {
+        let modified = self.modified?;
+        let mut hasher = DefaultHasher::new();
+        modified.hash(&mut hasher);
+        self.is_file.hash(&mut hasher);
+        self.is_collapsed.hash(&mut hasher);
+        self.total_time.hash(&mut hasher);
+        self.total_size.hash(&mut hasher);
+        // tags can change without change of folder modification time (e.g. folder metadata file edited in place)
+        hash_tags(self.tags.as_ref(), &mut hasher);
+        for f in &self.files {
+            f.path.hash(&mut hasher);
+            f.name.as_str().hash(&mut hasher);
+            f.mime.hash(&mut hasher);
+            f.section
+                .as_ref()
+                .map(|s| (s.start, s.duration))
+                .hash(&mut hasher);
+            f.meta.is_some().hash(&mut hasher);
+            if let Some(ref m) = f.meta {
+                m.duration.hash(&mut hasher);
+                m.bitrate.hash(&mut hasher);
+                hash_tags(m.tags.as_ref(), &mut hasher);
+                m.loudness
+                    .as_ref()
+                    .map(|l| {
+                        [l.track_gain, l.track_peak, l.album_gain, l.album_peak]
+                            .map(|v| v.map(f32::to_bits))
+                    })
+                    .hash(&mut hasher);
+            }
+        }
+        for sf in &self.subfolders {
+            sf.path.hash(&mut hasher);
+            sf.name.as_str().hash(&mut hasher);
+            sf.modified.hash(&mut hasher);
+            sf.finished.hash(&mut hasher);
+            sf.total_time.hash(&mut hasher);
+            sf.total_size.hash(&mut hasher);
+        }
+        self.cover.as_ref().map(|c| &c.path).hash(&mut hasher);
+        self.description.as_ref().map(|d| &d.path).hash(&mut hasher);
+        if let Some(ref pos) = self.position {
+            pos.path.hash(&mut hasher);
+            pos.timestamp.hash(&mut hasher);
+            pos.finished.hash(&mut hasher);
+        }
+        Some(hasher.finish())
+    }
+}
+
 #[derive(Clone, Copy)]
 pub enum FoldersOrdering {
     Alphabetical,
@@ -124,11 +211,93 @@ impl FoldersOrdering {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+/// Ordering of files in folder, `Listing` keeps order of folder listing
+/// (by name or as defined by playlist or folder metadata)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum FilesOrdering {
+    #[default]
+    Listing,
+    Natural,
+    Track, // by disc and track tags, files without track tag are last
+    Modified,
+}
+
+impl FilesOrdering {
+    pub fn from_letter(l: &str) -> Self {
+        match l {
+            "n" => FilesOrdering::Natural,
+            "t" => FilesOrdering::Track,
+            "m" => FilesOrdering::Modified,
+            _ => FilesOrdering::Listing,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
 pub struct AudioMeta {
     pub duration: u32, // duration in seconds, if available
     pub bitrate: u32,  // bitrate in kB/s
     pub tags: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loudness: Option<Loudness>, // ReplayGain like loudness info, if available
+}
+
+/// Loudness normalization info from ReplayGain (or R128) tags, gains are in dB (ReplayGain reference),
+/// peaks are linear amplitudes
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct Loudness {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl Loudness {
+    /// Difference between R128 reference (-23 LUFS) and ReplayGain reference (-18 LUFS)
+    const R128_TO_REPLAYGAIN: f32 = 5.0;
+
+    fn parse_gain(v: &str) -> Option<f32> {
+        let v = v.trim();
+        let v = v
+            .strip_suffix("dB")
+            .or_else(|| v.strip_suffix("db"))
+            .unwrap_or(v);
+        v.trim().parse::<f32>().ok().filter(|g| g.is_finite())
+    }
+
+    fn parse_r128_gain(v: &str) -> Option<f32> {
+        v.trim()
+            .parse::<i16>()
+            .ok()
+            .map(|g| f32::from(g) / 256.0 + Self::R128_TO_REPLAYGAIN)
+    }
+
+    fn parse_peak(v: &str) -> Option<f32> {
+        v.trim().parse::<f32>().ok().filter(|p| p.is_finite())
+    }
+
+    /// Creates loudness info from tags getter, None if there are no loudness tags
+    pub fn from_tags(get: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        use media_info::tags::*;
+        let gain = |rg_key, r128_key| {
+            get(rg_key)
+                .and_then(|v| Self::parse_gain(&v))
+                .or_else(|| get(r128_key).and_then(|v| Self::parse_r128_gain(&v)))
+        };
+        let l = Loudness {
+            track_gain: gain(REPLAYGAIN_TRACK_GAIN, R128_TRACK_GAIN),
+            track_peak: get(REPLAYGAIN_TRACK_PEAK).and_then(|v| Self::parse_peak(&v)),
+            album_gain: gain(REPLAYGAIN_ALBUM_GAIN, R128_ALBUM_GAIN),
+            album_peak: get(REPLAYGAIN_ALBUM_PEAK).and_then(|v| Self::parse_peak(&v)),
+        };
+        if l == Loudness::default() {
+            None
+        } else {
+            Some(l)
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -147,14 +316,23 @@ impl std::fmt::Display for TimeSpan {
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
 pub struct AudioFolderShort {
     #[serde(with = "unicase_serde::unicase")]
+    #[cfg_attr(feature = "api-spec", schema(value_type = String))]
     pub name: UniCase<String>,
     pub modified: Option<TimeStamp>,
+    #[cfg_attr(feature = "api-spec", schema(value_type = String))]
     pub path: PathBuf,
     pub is_file: bool,
     #[serde(default)]
     pub finished: bool,
+    /// Total playback time of folder audio files, as known from collection cache
+    #[serde(default)]
+    pub total_time: Option<u32>,
+    /// Total size in bytes of folder audio files, as known from collection cache
+    #[serde(default)]
+    pub total_size: Option<u64>,
 }
 
 impl AudioFolderShort {
@@ -166,6 +344,8 @@ impl AudioFolderShort {
             is_file: false,
             modified: None,
             finished: false,
+            total_time: None,
+            total_size: None,
         }
     }
 
@@ -180,6 +360,8 @@ impl AudioFolderShort {
             is_file,
             modified: get_modified(full_path).map(|t| t.into()),
             finished: false,
+            total_time: None,
+            total_size: None,
         })
     }
 
@@ -190,6 +372,8 @@ impl AudioFolderShort {
             is_file,
             modified: None,
             finished: false,
+            total_time: None,
+            total_size: None,
         }
     }
 
@@ -271,7 +455,9 @@ pub fn is_description<P: AsRef<Path>>(path: P) -> bool {
 }
 
 // have to propagate this type further - ideally this should be in separate crate with other types used throwout other crates
+pub use media_info::CbrLayout;
 pub use media_info::Chapter;
+pub use media_info::CodecKind;
 /// trait to generalize access to media metadata
 /// (so that underlying library can be easily changed)
 pub trait MediaInfo: Sized {
@@ -282,6 +468,14 @@ pub trait MediaInfo: Sized {
     fn cover(&self) -> Option<Vec<u8>>;
     fn has_description(&self) -> bool;
     fn description(&self) -> Option<String>;
+    /// lyrics or transcript embedded in tags
+    fn lyrics(&self) -> Option<String>;
+    /// codec of audio stream and its bitrate in kbps
+    fn audio_codec(&self) -> Option<(CodecKind, u32)>;
+    /// layout of audio stream, only if it has constant bitrate
+    fn cbr_layout(&mut self) -> Option<CbrLayout>;
+    /// precise duration in ms
+    fn duration_ms(&self) -> u64;
 }
 
 pub fn extract_description(file_path: impl AsRef<Path> + std::fmt::Debug) -> Option<String> {
@@ -298,6 +492,32 @@ pub fn extract_cover(file_path: impl AsRef<Path> + std::fmt::Debug) -> Option<Ve
         .and_then(|m| m.cover())
 }
 
+/// Duration of audio file in ms
+pub fn extract_duration(file_path: impl AsRef<Path> + std::fmt::Debug) -> Option<u64> {
+    get_audio_properties_uni(file_path.as_ref())
+        .map_err(|e| error!("Error {} when extracting metadata from {:?}", e, file_path))
+        .ok()
+        .map(|m| m.duration_ms())
+        .filter(|d| *d > 0)
+}
+
+pub fn extract_audio_codec(
+    file_path: impl AsRef<Path> + std::fmt::Debug,
+) -> Option<(CodecKind, u32)> {
+    get_audio_properties_uni(file_path.as_ref())
+        .map_err(|e| error!("Error {} when extracting metadata from {:?}", e, file_path))
+        .ok()
+        .and_then(|m| m.audio_codec())
+}
+
+/// Layout of constant bitrate file, so it can be seeked by byte offset
+pub fn extract_cbr_layout(file_path: impl AsRef<Path> + std::fmt::Debug) -> Option<CbrLayout> {
+    get_audio_properties_uni(file_path.as_ref())
+        .map_err(|e| error!("Error {} when extracting metadata from {:?}", e, file_path))
+        .ok()
+        .and_then(|mut m| m.cbr_layout())
+}
+
 mod libavformat {
     use super::*;
     use std::{collections::HashSet, sync::Once};
@@ -316,9 +536,10 @@ mod libavformat {
     impl MediaInfo for Info {
         fn get_audio_info(&self, required_tags: &Option<HashSet<String>>) -> Option<AudioMeta> {
             Some(AudioMeta {
-                duration: (self.media_file.duration() as f32 / 1000.0).round() as u32,
+                duration: (self.duration_ms() as f32 / 1000.0).round() as u32,
                 bitrate: self.media_file.bitrate(),
                 tags: self.collect_tags(required_tags),
+                loudness: Loudness::from_tags(|key| self.media_file.meta(key)),
             })
         }
 
@@ -368,6 +589,22 @@ mod libavformat {
                 .iter()
                 .find_map(|&key| self.media_file.meta(key))
         }
+
+        fn lyrics(&self) -> Option<String> {
+            self.media_file.lyrics()
+        }
+
+        fn audio_codec(&self) -> Option<(CodecKind, u32)> {
+            self.media_file.audio_codec()
+        }
+
+        fn cbr_layout(&mut self) -> Option<CbrLayout> {
+            self.media_file.cbr_layout()
+        }
+
+        fn duration_ms(&self) -> u64 {
+            self.media_file.duration()
+        }
     }
 
     impl Info {
@@ -437,6 +674,23 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_loudness_from_tags() {
+        let tags: HashMap<&str, &str> = [
+            ("replaygain_track_gain", "-6.50 dB"),
+            ("replaygain_track_peak", "0.988"),
+            ("r128_album_gain", "-512"),
+        ]
+        .into_iter()
+        .collect();
+        let l = Loudness::from_tags(|k| tags.get(k).map(|v| v.to_string())).unwrap();
+        assert_eq!(Some(-6.5), l.track_gain);
+        assert_eq!(Some(0.988), l.track_peak);
+        assert_eq!(Some(3.0), l.album_gain);
+        assert!(l.album_peak.is_none());
+        assert!(Loudness::from_tags(|_| None).is_none());
+    }
+
     #[test]
     fn test_is_audio() {
         assert!(is_audio("my/song.mp3"));
@@ -469,4 +723,60 @@ mod tests {
         assert!(in_future_ts > now_ts);
         assert!(in_future_ts > now);
     }
+
+    #[test]
+    fn test_state_hash() {
+        let tags = |pairs: &[(&str, &str)]| {
+            Some(
+                pairs
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<HashMap<_, _>>(),
+            )
+        };
+        let mut folder = AudioFolder {
+            is_file: false,
+            is_collapsed: false,
+            modified: Some(SystemTime::UNIX_EPOCH.into()),
+            total_time: None,
+            total_size: None,
+            files: vec![AudioFile {
+                name: UniCase::new("01".into()),
+                path: "book/01.mp3".into(),
+                meta: Some(AudioMeta {
+                    duration: 10,
+                    bitrate: 64,
+                    tags: tags(&[("title", "Chapter 1")]),
+                    loudness: None,
+                }),
+                mime: "audio/mpeg".into(),
+                section: None,
+            }],
+            subfolders: vec![],
+            cover: None,
+            description: None,
+            position: None,
+            tags: tags(&[("album", "Book"), ("artist", "Author"), ("series", "S")]),
+        };
+        let hash = folder.state_hash();
+        assert!(hash.is_some());
+        assert_eq!(hash, folder.clone().state_hash());
+        // independent of tags iteration order
+        folder.tags = tags(&[("series", "S"), ("artist", "Author"), ("album", "Book")]);
+        assert_eq!(hash, folder.state_hash());
+
+        folder.tags = tags(&[("album", "Book"), ("artist", "Other"), ("series", "S")]);
+        let hash2 = folder.state_hash();
+        assert_ne!(hash, hash2);
+
+        folder.files[0].meta.as_mut().unwrap().tags = tags(&[("title", "Chapter One")]);
+        let hash3 = folder.state_hash();
+        assert_ne!(hash2, hash3);
+
+        folder.files[0].meta.as_mut().unwrap().bitrate = 128;
+        assert_ne!(hash3, folder.state_hash());
+
+        folder.modified = None;
+        assert!(folder.state_hash().is_none());
+    }
 }
@@ -0,0 +1,168 @@
+use crate::error::{Error, Result};
+use flate2::read::GzDecoder;
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const TIMESTAMP_LEN: usize = 16;
+const GZ_EXTENSION: &str = "gz";
+
+fn split_name(base_file: &Path) -> Result<(&str, Option<&str>)> {
+    let stem = base_file
+        .file_stem()
+        .and_then(OsStr::to_str)
+        .ok_or(Error::InvalidPath)?;
+    let ext = base_file.extension().and_then(OsStr::to_str);
+    Ok((stem, ext))
+}
+
+fn with_extensions(mut name: String, ext: Option<&str>, compress: bool) -> String {
+    if let Some(ext) = ext {
+        name.push('.');
+        name.push_str(ext);
+    }
+    if compress {
+        name.push('.');
+        name.push_str(GZ_EXTENSION);
+    }
+    name
+}
+
+/// Name of file for new backup
+pub(crate) fn backup_file_name(
+    base_file: &Path,
+    timestamped: bool,
+    compress: bool,
+) -> Result<PathBuf> {
+    let (stem, ext) = split_name(base_file)?;
+    let name = if timestamped {
+        format!("{}-{}", stem, chrono::Utc::now().format(TIMESTAMP_FORMAT))
+    } else {
+        stem.to_string()
+    };
+    Ok(base_file.with_file_name(with_extensions(name, ext, compress)))
+}
+
+fn is_timestamped_backup(name: &str, stem: &str, ext: Option<&str>) -> bool {
+    name.strip_prefix(stem)
+        .and_then(|n| n.strip_prefix('-'))
+        .filter(|n| n.len() >= TIMESTAMP_LEN && n.is_char_boundary(TIMESTAMP_LEN))
+        .map(|n| {
+            let (ts, rest) = n.split_at(TIMESTAMP_LEN);
+            chrono::NaiveDateTime::parse_from_str(ts, TIMESTAMP_FORMAT).is_ok()
+                && (rest == with_extensions(String::new(), ext, false)
+                    || rest == with_extensions(String::new(), ext, true))
+        })
+        .unwrap_or(false)
+}
+
+/// Removes older timestamped backups, so only `keep` latest remain
+pub(crate) fn remove_old_backups(base_file: &Path, keep: usize) -> Result<()> {
+    let (stem, ext) = split_name(base_file)?;
+    let dir = match base_file.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let mut backups = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|name| is_timestamped_backup(name, stem, ext))
+        .collect::<Vec<_>>();
+    // timestamp format sorts chronologically, newest first
+    backups.sort_unstable_by(|a, b| b.cmp(a));
+    for name in backups.into_iter().skip(keep) {
+        let path = dir.join(name);
+        match std::fs::remove_file(&path) {
+            Ok(_) => debug!("Removed old positions backup {:?}", path),
+            Err(e) => error!("Cannot remove old positions backup {:?}: {}", path, e),
+        }
+    }
+    Ok(())
+}
+
+/// Opens backup file for reading, gzipped backups are decompressed
+pub(crate) fn open_backup(backup_file: &Path) -> Result<Box<dyn Read>> {
+    let f = BufReader::new(File::open(backup_file)?);
+    if backup_file.extension() == Some(OsStr::new(GZ_EXTENSION)) {
+        Ok(Box::new(GzDecoder::new(f)))
+    } else {
+        Ok(Box::new(f))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_backup_names() {
+        let base = Path::new("/tmp/positions.json");
+        assert_eq!(
+            Path::new("/tmp/positions.json"),
+            backup_file_name(base, false, false).unwrap()
+        );
+        assert_eq!(
+            Path::new("/tmp/positions.json.gz"),
+            backup_file_name(base, false, true).unwrap()
+        );
+        let name = backup_file_name(base, true, true).unwrap();
+        let name = name.file_name().and_then(OsStr::to_str).unwrap();
+        assert!(is_timestamped_backup(name, "positions", Some("json")));
+        assert!(is_timestamped_backup(
+            "positions-20240301T030000Z.json",
+            "positions",
+            Some("json")
+        ));
+        assert!(!is_timestamped_backup(
+            "positions-20240301T030000Z.txt",
+            "positions",
+            Some("json")
+        ));
+        assert!(!is_timestamped_backup(
+            "positions-other.json",
+            "positions",
+            Some("json")
+        ));
+        assert!(!is_timestamped_backup(
+            "positions.json",
+            "positions",
+            Some("json")
+        ));
+    }
+
+    #[test]
+    fn test_remove_old_backups() -> anyhow::Result<()> {
+        let tmp = TempDir::new("audioserve_backup")?;
+        let names = [
+            "positions-20240301T030000Z.json",
+            "positions-20240302T030000Z.json.gz",
+            "positions-20240303T030000Z.json",
+            "positions.json",
+            "other-20240301T030000Z.json",
+        ];
+        for name in names {
+            File::create(tmp.path().join(name))?.write_all(b"{}")?;
+        }
+        remove_old_backups(&tmp.path().join("positions.json"), 2)?;
+        let mut remaining = std::fs::read_dir(tmp.path())?
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(
+            vec![
+                "other-20240301T030000Z.json",
+                "positions-20240302T030000Z.json.gz",
+                "positions-20240303T030000Z.json",
+                "positions.json"
+            ],
+            remaining
+        );
+        Ok(())
+    }
+}
@@ -1,8 +1,12 @@
 use std::{
+    collections::HashMap,
     ops::Deref,
     path::{Path, PathBuf},
-    sync::Arc,
-    time::SystemTime,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, SystemTime},
 };
 
 use crossbeam_channel::Receiver;
@@ -16,30 +20,50 @@ use crate::{
     audio_folder::{DirType, FolderLister},
     audio_meta::{AudioFolder, TimeStamp},
     cache::{
+        stats::StatsCounter,
         update::{RecursiveUpdater, UpdateActionKind},
-        util::{split_path, update_path},
+        util::{
+            base_dir_available, library_author_prefix, library_key, split_library_key, split_path,
+            update_path,
+        },
+    },
+    common::{
+        ChangeListener, CollectionChange, CollectionStats, LibraryAuthor, PositionsData,
+        RemovalListener,
     },
-    common::PositionsData,
     error::{Error, Result},
-    position::{PositionItem, PositionRecord, PositionsCollector, MAX_GROUPS},
-    util::{get_file_name, get_modified},
+    ignore::IgnorePatterns,
+    position::{
+        GroupInfo, PositionItem, PositionRecord, PositionsCollector, PruneReason, PrunedPosition,
+        MAX_GROUPS,
+    },
+    util::get_file_name,
     AudioFolderShort, FoldersOrdering, Position,
 };
 
 use super::{
     update::UpdateAction,
-    util::{deser_audiofolder, parent_path},
+    util::{deser_audiofolder, kv_to_audiofolder, parent_path},
 };
 
+const LIBRARY_INDEX_TREE: &str = "lib_index";
+
 #[derive(Clone)]
 pub(crate) struct CacheInner {
     db: Db,
     pos_latest: Tree,
     pos_folder: Tree,
+    lib_index: Tree,
+    lib_paths: Tree,
     lister: FolderLister,
     base_dir: PathBuf,
     time_to_folder_end: u32,
     update_receiver: Option<Receiver<Option<UpdateAction>>>,
+    available: Arc<AtomicBool>,
+    catch_up_required: Arc<AtomicBool>,
+    stats: Arc<StatsCounter>,
+    removal_listener: Arc<RwLock<Option<RemovalListener>>>,
+    change_listener: Arc<RwLock<Option<ChangeListener>>>,
 }
 
 impl CacheInner {
@@ -53,15 +77,39 @@ impl CacheInner {
     ) -> Result<Self> {
         let pos_latest = db.open_tree("pos_latest")?;
         let pos_folder = db.open_tree("pos_folder")?;
-        Ok(CacheInner {
+        let new_library = !db
+            .tree_names()
+            .iter()
+            .any(|name| name == LIBRARY_INDEX_TREE.as_bytes());
+        // lib_index is key author/series/path, lib_paths is reverse mapping path -> lib_index key
+        let lib_index = db.open_tree(LIBRARY_INDEX_TREE)?;
+        let lib_paths = db.open_tree("lib_paths")?;
+        let stats = Arc::new(StatsCounter::from_folders(
+            db.iter()
+                .filter_map(|r| r.ok())
+                .filter_map(|(_, v)| deser_audiofolder(v)),
+        ));
+        let inner = CacheInner {
             db,
             pos_latest,
             pos_folder,
+            lib_index,
+            lib_paths,
             lister,
             base_dir,
             time_to_folder_end,
             update_receiver,
-        })
+            available: Arc::new(AtomicBool::new(true)),
+            catch_up_required: Arc::new(AtomicBool::new(false)),
+            stats,
+            removal_listener: Arc::new(RwLock::new(None)),
+            change_listener: Arc::new(RwLock::new(None)),
+        };
+        if new_library && !inner.db.is_empty() {
+            // existing cache from older version, without library index
+            inner.rebuild_library_index()?;
+        }
+        Ok(inner)
     }
 
     pub(crate) fn run_update_loop(self: Arc<Self>) {
@@ -86,6 +134,51 @@ impl CacheInner {
         self.base_dir.as_path()
     }
 
+    pub(crate) fn set_removal_listener(&self, listener: RemovalListener) {
+        *self.removal_listener.write().unwrap() = Some(listener);
+    }
+
+    fn notify_removal(&self, dir_path: &Path) {
+        if let Some(listener) = self.removal_listener.read().unwrap().as_ref() {
+            self.roots().for_each(|root| listener(&root.join(dir_path)))
+        }
+    }
+
+    pub(crate) fn set_change_listener(&self, listener: ChangeListener) {
+        *self.change_listener.write().unwrap() = Some(listener);
+    }
+
+    pub(crate) fn notify_change(&self, change: CollectionChange) {
+        if let Some(listener) = self.change_listener.read().unwrap().as_ref() {
+            listener(change)
+        }
+    }
+
+    /// `was_finished` - folder was finished before position change
+    pub(crate) fn notify_position(
+        &self,
+        group: impl AsRef<str>,
+        folder: impl AsRef<str>,
+        was_finished: bool,
+    ) {
+        let finished = !was_finished && self.is_finished(&group, &folder);
+        self.notify_change(CollectionChange::PositionChanged {
+            group: group.as_ref().to_string(),
+            folder: folder.as_ref().to_string(),
+            finished,
+        })
+    }
+
+    /// Collection directory and additional roots merged into collection
+    pub(crate) fn roots(&self) -> impl Iterator<Item = &Path> {
+        self.lister.roots(&self.base_dir)
+    }
+
+    /// Latest modification time of the folder (it can be in several roots)
+    pub(crate) fn modified<P: AsRef<Path>>(&self, rel_path: P) -> Option<SystemTime> {
+        self.lister.modified(&self.base_dir, rel_path.as_ref())
+    }
+
     pub(crate) fn list_dir<P: AsRef<Path>>(
         &self,
         dir_path: P,
@@ -100,17 +193,64 @@ impl CacheInner {
         self.db.iter()
     }
 
+    pub(crate) fn ignore_patterns(&self) -> &IgnorePatterns {
+        self.lister.ignore_patterns()
+    }
+
+    /// Last known state of collection directory, when unavailable (e.g. network share is unmounted)
+    /// collection is in degraded mode - it's served only from cache and cache is not updated
+    pub(crate) fn is_available(&self) -> bool {
+        self.available.load(Ordering::SeqCst)
+    }
+
+    /// Checks if collection directory is reachable and updates state accordingly
+    pub(crate) fn check_available(&self) -> bool {
+        let available = base_dir_available(&self.base_dir, !self.db.is_empty());
+        let was_available = self.available.swap(available, Ordering::SeqCst);
+        if was_available && !available {
+            error!(
+                "Collection directory {:?} is not available, collection is served only from cache until directory is back",
+                self.base_dir
+            );
+            self.catch_up_required.store(true, Ordering::SeqCst);
+        } else if !was_available && available {
+            info!(
+                "Collection directory {:?} is available again",
+                self.base_dir
+            );
+        }
+        available
+    }
+
+    /// True only once after collection directory became available again - then changes done meanwhile should be scanned
+    pub(crate) fn take_catch_up(&self) -> bool {
+        self.is_available() && self.catch_up_required.swap(false, Ordering::SeqCst)
+    }
+
+    /// Cached folders, which do not exist anymore (or are collapsed into parent or ignored now)
+    pub(crate) fn orphaned_folders(&self) -> Vec<String> {
+        self.iter_folders()
+            .filter_map(|e| e.ok())
+            .filter_map(|(k, _)| String::from_utf8(k.to_vec()).ok())
+            .filter(|rel_path| {
+                let full_path = self.full_path(rel_path);
+                !full_path.exists()
+                    || self.lister.is_collapsable_folder(&full_path)
+                    || self.ignore_patterns().is_ignored(Path::new(rel_path))
+            })
+            .collect()
+    }
+
     pub(crate) fn clean_up_folders(&self) {
-        for key in self.iter_folders().filter_map(|e| e.ok()).map(|(k, _)| k) {
-            if let Ok(rel_path) = std::str::from_utf8(&key) {
-                let full_path = self.base_dir.join(rel_path);
-                if !full_path.exists() || self.lister.is_collapsable_folder(&full_path) {
-                    debug!("Removing {:?} from collection cache db", full_path);
-                    self.remove(rel_path)
-                        .map_err(|e| error!("cannot remove record from db: {}", e))
-                        .ok();
-                }
-            }
+        if !self.check_available() {
+            warn!("Collection directory is not available, skipping clean up of folders");
+            return;
+        }
+        for rel_path in self.orphaned_folders() {
+            debug!("Removing {:?} from collection cache db", rel_path);
+            self.remove(&rel_path)
+                .map_err(|e| error!("cannot remove record from db: {}", e))
+                .ok();
         }
     }
 }
@@ -163,51 +303,82 @@ impl CacheInner {
 
     pub(crate) fn update<P: AsRef<Path>>(&self, dir: P, af: AudioFolder) -> Result<()> {
         let dir = dir.as_ref().to_str().ok_or(Error::InvalidCollectionPath)?;
+        let key = library_key(dir, &af);
         bincode::serialize(&af)
             .map_err(Error::from)
             .and_then(|data| self.db.insert(dir, data).map_err(Error::from))
+            .map(|old| {
+                self.stats
+                    .replace(old.and_then(deser_audiofolder).as_ref(), Some(&af))
+            })
+            .and_then(|_| self.update_library(dir, key))
             .map(|_| debug!("Cache updated for {:?}", dir))
     }
 
+    /// Lists folder directly from file system, cache is not touched
+    pub(crate) fn list_dir_uncached<P: AsRef<Path>>(&self, dir_path: P) -> Result<AudioFolder> {
+        self.lister
+            .list_dir(
+                &self.base_dir,
+                dir_path.as_ref(),
+                FoldersOrdering::Alphabetical,
+            )
+            .map_err(Error::from)
+    }
+
     pub(crate) fn force_update<P: AsRef<Path>>(
         &self,
         dir_path: P,
         ret: bool,
     ) -> Result<Option<AudioFolder>> {
-        let af = self.lister.list_dir(
-            &self.base_dir,
-            dir_path.as_ref(),
-            FoldersOrdering::Alphabetical,
-        )?;
+        let af = self.list_dir_uncached(dir_path.as_ref())?;
         let rv = if ret { Some(af.clone()) } else { None };
         self.update(dir_path, af)?;
         Ok(rv)
     }
 
     pub(crate) fn full_path<P: AsRef<Path>>(&self, rel_path: P) -> PathBuf {
-        self.base_dir.join(rel_path.as_ref())
+        self.lister.resolve_path(&self.base_dir, rel_path.as_ref())
     }
 
     pub(crate) fn remove<P: AsRef<Path>>(&self, dir_path: P) -> Result<Option<IVec>> {
         let path = dir_path.as_ref().to_str().ok_or(Error::InvalidPath)?;
-        self.db.remove(path).map_err(Error::from)
+        self.update_library(path, None)?;
+        let old = self.db.remove(path).map_err(Error::from)?;
+        if let Some(ref old) = old {
+            self.stats.replace(deser_audiofolder(old).as_ref(), None);
+        }
+        Ok(old)
     }
 
     pub(crate) fn remove_tree<P: AsRef<Path>>(&self, dir_path: P) -> Result<()> {
         let path = dir_path.as_ref().to_str().ok_or(Error::InvalidPath)?;
         let pos_batch = self.remove_positions_batch(&dir_path)?;
         let mut batch = Batch::default();
+        let mut removed = vec![];
         self.db
             .scan_prefix(path)
             .filter_map(|r| r.ok())
-            .for_each(|(key, _)| batch.remove(key));
+            .for_each(|(key, val)| {
+                batch.remove(key);
+                removed.extend(deser_audiofolder(val));
+            });
         (self.db.deref(), &self.pos_folder)
             .transaction(|(db, pos_folder)| {
                 db.apply_batch(&batch)?;
                 pos_folder.apply_batch(&pos_batch)?;
                 Ok(())
             })
-            .map_err(Error::from)
+            .map_err(Error::from)?;
+        removed
+            .iter()
+            .for_each(|af| self.stats.replace(Some(af), None));
+        self.notify_removal(dir_path.as_ref());
+        self.remove_library_tree(path)
+    }
+
+    pub(crate) fn stats(&self) -> CollectionStats {
+        self.stats.stats()
     }
 
     pub fn flush(&self) -> Result<()> {
@@ -215,6 +386,8 @@ impl CacheInner {
             self.db.flush(),
             self.pos_folder.flush(),
             self.pos_latest.flush(),
+            self.lib_index.flush(),
+            self.lib_paths.flush(),
         ];
         res.into_iter()
             .find(|r| r.is_err())
@@ -287,6 +460,53 @@ impl CacheInner {
             .map_err(Error::from)
     }
 
+    /// Clears finished flag, but keeps position in folder
+    pub(crate) fn mark_as_unfinished<P, S>(&self, group: S, path: P) -> Result<()>
+    where
+        S: AsRef<str>,
+        P: AsRef<str>,
+    {
+        self.update_folder_record(path, |folder_rec| {
+            if let Some(p) = folder_rec.get_mut(group.as_ref()) {
+                p.folder_finished = false;
+                p.timestamp = TimeStamp::now();
+            }
+        })
+    }
+
+    pub(crate) fn remove_folder_position<P, S>(&self, group: S, path: P) -> Result<()>
+    where
+        S: AsRef<str>,
+        P: AsRef<str>,
+    {
+        self.update_folder_record(path, |folder_rec| {
+            folder_rec.remove(group.as_ref());
+        })
+    }
+
+    fn update_folder_record<P, F>(&self, path: P, f: F) -> Result<()>
+    where
+        P: AsRef<str>,
+        F: Fn(&mut PositionRecord),
+    {
+        self.pos_folder
+            .transaction(|pos_folder| {
+                if let Some(mut folder_rec) = get_pos_record(path.as_ref(), pos_folder) {
+                    f(&mut folder_rec);
+                    if folder_rec.is_empty() {
+                        pos_folder.remove(path.as_ref().as_bytes())?;
+                    } else {
+                        match bincode::serialize(&folder_rec).map_err(Error::from) {
+                            Ok(data) => pos_folder.insert(path.as_ref().as_bytes(), data)?,
+                            Err(e) => return transaction::abort(e),
+                        };
+                    }
+                }
+                Ok(())
+            })
+            .map_err(Error::from)
+    }
+
     pub(crate) fn insert_position<S, P>(
         &self,
         group: S,
@@ -449,6 +669,16 @@ impl CacheInner {
             .for_each(|sf| self.update_subfolder(&group, sf))
     }
 
+    /// Fills subfolders totals from their cached records
+    pub(crate) fn update_subfolders_totals(&self, subfolders: &mut [AudioFolderShort]) {
+        for sf in subfolders.iter_mut() {
+            if let Some(af) = self.get(&sf.path) {
+                sf.total_time = af.total_time;
+                sf.total_size = af.total_size;
+            }
+        }
+    }
+
     fn positions_from_iter<I, S>(
         iter: I,
         group: S,
@@ -546,33 +776,194 @@ impl CacheInner {
     }
 
     pub(crate) fn clean_up_positions(&self) {
-        let mut batch = Batch::default();
-        self.pos_folder
-            .iter()
-            .filter_map(|r| match r {
-                Ok((k, _)) => {
-                    if !self.db.contains_key(&k).unwrap_or(false) {
-                        Some(k)
-                    } else {
-                        None
-                    }
-                }
+        if !self.check_available() {
+            warn!("Collection directory is not available, skipping clean up of positions");
+            return;
+        }
+        self.prune_positions(0, &|_| None, false)
+            .map_err(|e| error!("Cannot remove positions: {}", e))
+            .ok();
+    }
+
+    pub(crate) fn prune_positions(
+        &self,
+        collection_no: usize,
+        max_age: &dyn Fn(&str) -> Option<Duration>,
+        dry_run: bool,
+    ) -> Result<Vec<PrunedPosition>> {
+        let now = SystemTime::now();
+        let mut pruned = vec![];
+        for r in self.pos_folder.iter() {
+            let (k, v) = match r {
+                Ok(kv) => kv,
                 Err(e) => {
                     error!("Error reading from db: {}", e);
-                    None
+                    continue;
                 }
-            })
-            .for_each(|k| {
+            };
+            let folder = std::str::from_utf8(&k)?;
+            let rec: PositionRecord = bincode::deserialize(&v)?;
+            let missing = !self.db.contains_key(&k)?;
+            if missing {
                 debug!(
                     "Removing positions for directory {:?} as it does not exists",
-                    std::str::from_utf8(&k)
+                    folder
                 );
-                batch.remove(k);
-            });
+            }
+            for (group, item) in rec {
+                let reason = if missing {
+                    PruneReason::MissingFolder
+                } else if max_age(&group)
+                    .and_then(|age| now.checked_sub(age))
+                    .map(|limit| item.timestamp < TimeStamp::from(limit))
+                    .unwrap_or(false)
+                {
+                    PruneReason::Expired
+                } else {
+                    continue;
+                };
+                pruned.push(PrunedPosition {
+                    collection: collection_no,
+                    group,
+                    folder: folder.into(),
+                    file: item.file,
+                    timestamp: item.timestamp,
+                    reason,
+                });
+            }
+        }
+
+        if !dry_run && !pruned.is_empty() {
+            let mut by_folder: HashMap<&str, Vec<&PrunedPosition>> = HashMap::new();
+            for p in &pruned {
+                by_folder.entry(&p.folder).or_default().push(p);
+            }
+            for (folder, items) in by_folder {
+                // position could be updated meanwhile, so remove only if it's still same
+                self.update_folder_record(folder, |rec| {
+                    rec.retain(|group, item| {
+                        !items
+                            .iter()
+                            .any(|p| &p.group == group && p.timestamp == item.timestamp)
+                    })
+                })?;
+            }
+            self.clean_up_latest_positions();
+        }
+        Ok(pruned)
+    }
+
+    fn position_records(&self) -> impl Iterator<Item = (String, PositionRecord)> + '_ {
+        self.pos_folder.iter().filter_map(|r| {
+            let (k, v) = r.map_err(|e| error!("Error reading from db: {}", e)).ok()?;
+            let folder = String::from_utf8(k.to_vec()).ok()?;
+            let rec = bincode::deserialize::<PositionRecord>(&v)
+                .map_err(|e| error!("Db item deserialization error: {}", e))
+                .ok()?;
+            Some((folder, rec))
+        })
+    }
+
+    fn group_timestamp(&self, folder: &[u8], group: &str) -> Option<TimeStamp> {
         self.pos_folder
-            .apply_batch(batch)
-            .map_err(|e| error!("Cannot remove positions: {}", e))
-            .ok();
+            .get(folder)
+            .ok()
+            .flatten()
+            .and_then(|data| bincode::deserialize::<PositionRecord>(&data).ok())
+            .and_then(|rec| rec.get(group).map(|item| item.timestamp))
+    }
+
+    pub(crate) fn groups(&self) -> Vec<GroupInfo> {
+        let mut groups: HashMap<String, GroupInfo> = HashMap::new();
+        for (_, rec) in self.position_records() {
+            for (group, item) in rec {
+                let info = groups.entry(group).or_insert_with_key(|name| GroupInfo {
+                    name: name.clone(),
+                    last_activity: item.timestamp,
+                    positions: 0,
+                });
+                info.positions += 1;
+                if item.timestamp > info.last_activity {
+                    info.last_activity = item.timestamp;
+                }
+            }
+        }
+        groups.into_values().collect()
+    }
+
+    pub(crate) fn rename_group(&self, from: &str, to: &str) -> Result<usize> {
+        let folders: Vec<_> = self
+            .position_records()
+            .filter(|(_, rec)| rec.contains_key(from))
+            .map(|(folder, _)| folder)
+            .collect();
+        for folder in &folders {
+            self.update_folder_record(folder, |rec| {
+                if let Some(item) = rec.remove(from) {
+                    let keep_existing = rec
+                        .get(to)
+                        .map(|existing| existing.timestamp > item.timestamp)
+                        .unwrap_or(false);
+                    if !keep_existing {
+                        rec.insert(to.into(), item);
+                    }
+                }
+            })?;
+        }
+        if let Some(from_latest) = self.pos_latest.remove(from)? {
+            let is_newer = match self.pos_latest.get(to)? {
+                Some(to_latest) => {
+                    self.group_timestamp(&from_latest, to) > self.group_timestamp(&to_latest, to)
+                }
+                None => true,
+            };
+            if is_newer {
+                self.pos_latest.insert(to, from_latest)?;
+            }
+        }
+        Ok(folders.len())
+    }
+
+    pub(crate) fn delete_group(&self, group: &str) -> Result<usize> {
+        let folders: Vec<_> = self
+            .position_records()
+            .filter(|(_, rec)| rec.contains_key(group))
+            .map(|(folder, _)| folder)
+            .collect();
+        for folder in &folders {
+            self.update_folder_record(folder, |rec| {
+                rec.remove(group);
+            })?;
+        }
+        self.pos_latest.remove(group)?;
+        Ok(folders.len())
+    }
+
+    /// Removes latest position of group, if it points to folder without group's position
+    fn clean_up_latest_positions(&self) {
+        for (group, folder) in self.pos_latest.iter().filter_map(|r| {
+            r.map_err(|e| error!("Error reading latest position db: {}", e))
+                .ok()
+        }) {
+            let has_position = self
+                .pos_folder
+                .get(&folder)
+                .ok()
+                .flatten()
+                .and_then(|data| bincode::deserialize::<PositionRecord>(&data).ok())
+                .and_then(|rec| {
+                    std::str::from_utf8(&group)
+                        .ok()
+                        .map(|g| rec.contains_key(g))
+                })
+                .unwrap_or(false);
+            if !has_position {
+                self.pos_latest
+                    .compare_and_swap(&group, Some(&folder), None as Option<&[u8]>)
+                    .map_err(|e| error!("Cannot remove latest position: {}", e))
+                    .ok();
+            }
+        }
     }
 
     pub(crate) fn write_json_positions<F: std::io::Write>(&self, file: &mut F) -> Result<()> {
@@ -642,9 +1033,91 @@ impl CacheInner {
     }
 }
 
+// library index
+impl CacheInner {
+    fn update_library(&self, path: &str, key: Option<String>) -> Result<()> {
+        let old_key = match key {
+            Some(ref key) => self.lib_paths.insert(path, key.as_bytes())?,
+            None => self.lib_paths.remove(path)?,
+        };
+        if let Some(old_key) = old_key {
+            if Some(old_key.as_ref()) != key.as_ref().map(|k| k.as_bytes()) {
+                self.lib_index.remove(old_key)?;
+            }
+        }
+        if let Some(key) = key {
+            self.lib_index.insert(key, &[])?;
+        }
+        Ok(())
+    }
+
+    fn remove_library_tree(&self, path: &str) -> Result<()> {
+        let mut paths_batch = Batch::default();
+        let mut index_batch = Batch::default();
+        self.lib_paths
+            .scan_prefix(path)
+            .filter_map(|r| r.ok())
+            .filter(|(p, _)| p.len() == path.len() || p.get(path.len()) == Some(&b'/'))
+            .for_each(|(path, key)| {
+                paths_batch.remove(path);
+                index_batch.remove(key);
+            });
+        self.lib_index.apply_batch(index_batch)?;
+        self.lib_paths.apply_batch(paths_batch)?;
+        Ok(())
+    }
+
+    fn rebuild_library_index(&self) -> Result<()> {
+        info!("Building library index for collection {:?}", self.base_dir);
+        for (k, v) in self.db.iter().filter_map(|r| r.ok()) {
+            if let (Ok(path), Some(af)) = (std::str::from_utf8(&k), deser_audiofolder(&v)) {
+                self.update_library(path, library_key(path, &af))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn library_authors(
+        &self,
+        folder_allowed: &dyn Fn(&Path) -> bool,
+    ) -> Vec<LibraryAuthor> {
+        let mut authors: Vec<LibraryAuthor> = vec![];
+        for key in self.lib_index.iter().keys().filter_map(|r| r.ok()) {
+            if let Some((author, _, _)) = std::str::from_utf8(&key)
+                .ok()
+                .and_then(split_library_key)
+                .filter(|(_, _, path)| folder_allowed(Path::new(path)))
+            {
+                match authors.last_mut() {
+                    Some(last) if last.name == author => last.folders += 1,
+                    _ => authors.push(LibraryAuthor {
+                        name: author.to_string(),
+                        folders: 1,
+                    }),
+                }
+            }
+        }
+        authors
+    }
+
+    pub(crate) fn library_folders(&self, author: &str) -> Vec<(Option<String>, AudioFolderShort)> {
+        self.lib_index
+            .scan_prefix(library_author_prefix(author))
+            .keys()
+            .filter_map(|r| r.ok())
+            .filter_map(|key| {
+                let key = std::str::from_utf8(&key).ok()?;
+                let (_, series, path) = split_library_key(key)?;
+                let data = self.db.get(path).ok().flatten()?;
+                Some((series.map(str::to_string), kv_to_audiofolder(path, data)))
+            })
+            .collect()
+    }
+}
+
 // Updating based on fs events
 impl CacheInner {
-    fn force_update_recursive<P: Into<PathBuf>>(&self, folder: P) {
+    pub(crate) fn force_update_recursive<P: Into<PathBuf>>(&self, folder: P) {
         let folder = folder.into();
         let af: AudioFolderShort = AudioFolderShort {
             name: get_file_name(&folder).into(),
@@ -652,6 +1125,8 @@ impl CacheInner {
             path: folder,
             is_file: false,
             finished: false,
+            total_time: None,
+            total_size: None,
         };
         let updater = RecursiveUpdater::new(self, Some(af), false);
         updater.process();
@@ -660,8 +1135,9 @@ impl CacheInner {
     fn update_recursive_after_rename(&self, from: &Path, to: &Path) -> Result<()> {
         let mut delete_batch = Batch::default();
         let mut insert_batch = Batch::default();
+        let mut renamed_library = vec![];
 
-        let mut updated = get_modified(self.base_dir.join(to));
+        let mut updated = self.modified(to);
         debug!("Renamed root modified for {:?}", updated);
         for item in self.db.scan_prefix(from.to_str().unwrap()) {
             // safe to unwrap as we insert only valid strings
@@ -706,6 +1182,7 @@ impl CacheInner {
                 folder_rec.cover = Some(c);
             }
 
+            renamed_library.push((new_key.to_string(), library_key(new_key, &folder_rec)));
             insert_batch.insert(new_key, bincode::serialize(&folder_rec)?);
         }
 
@@ -715,12 +1192,26 @@ impl CacheInner {
                 db.apply_batch(&insert_batch)?;
                 Ok(())
             })
-            .map_err(Error::from)
+            .map_err(Error::from)?;
+        self.remove_library_tree(from.to_str().ok_or(Error::InvalidPath)?)?;
+        for (path, key) in renamed_library {
+            self.update_library(&path, key)?;
+        }
+        Ok(())
     }
 
     pub(crate) fn proceed_update(&self, update: UpdateAction) {
         debug!("Update action: {:?}", update);
+        if !self.check_available() {
+            warn!(
+                "Collection directory is not available, skipping update of {:?}",
+                update.path
+            );
+            return;
+        }
         let folder = update.path;
+        let mut changed = vec![folder.clone()];
+        let is_new = !self.has_key(&folder);
         match update.kind {
             UpdateActionKind::RefreshFolder => {
                 self.force_update(&folder, false)
@@ -734,6 +1225,10 @@ impl CacheInner {
                 self.remove_tree(&folder)
                     .map_err(|e| warn!("Error removing folder from cache: {}", e))
                     .ok();
+                if self.full_path(&folder).exists() {
+                    // folder is still present in another root
+                    self.force_update_recursive(folder);
+                }
             }
             UpdateActionKind::RenameFolder { to } => {
                 //if destination exists in cache let's delete it
@@ -742,6 +1237,7 @@ impl CacheInner {
                         .map_err(|e| warn!("Error removing folder from cache: {}", e))
                         .ok();
                 }
+                changed.push(to.clone());
                 let from = folder;
                 if let Err(e) = self.update_recursive_after_rename(&from, &to) {
                     error!("Failed to do recursive rename, error: {}, we will have to do rescan of {:?}", e, &to);
@@ -749,6 +1245,9 @@ impl CacheInner {
                         .map_err(|e| warn!("Error removing folder from cache: {}", e))
                         .ok();
                     self.force_update_recursive(&to);
+                } else {
+                    // content under old path is gone
+                    self.notify_removal(&from);
                 }
                 if let Err(e) = self.rename_positions(&from, &to) {
                     error!(
@@ -759,8 +1258,20 @@ impl CacheInner {
                         .map_err(|e| error!("Even deleting positions failed: {}", e))
                         .ok();
                 }
+                if self.full_path(&from).exists() {
+                    // folder is still present in another root
+                    self.force_update_recursive(from);
+                }
             }
         }
+        changed.into_iter().for_each(|path| {
+            let change = if is_new && self.has_key(&path) {
+                CollectionChange::FolderAdded(path)
+            } else {
+                CollectionChange::FolderChanged(path)
+            };
+            self.notify_change(change)
+        });
     }
 
     pub(crate) fn get_true_parent(&self, rel_path: &Path, full_path: &Path) -> PathBuf {
@@ -782,7 +1293,10 @@ impl CacheInner {
     where
         P: AsRef<Path>,
     {
-        full_path.as_ref().strip_prefix(&self.base_dir).unwrap() // Should be safe as is used only with this collection
+        let full_path = full_path.as_ref();
+        self.roots()
+            .find_map(|root| full_path.strip_prefix(root).ok())
+            .unwrap() // Should be safe as is used only with this collection
     }
 
     /// only for absolute paths
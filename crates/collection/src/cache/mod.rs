@@ -1,19 +1,25 @@
 use self::{
     inner::CacheInner,
-    update::{OngoingUpdater, UpdateAction},
-    util::kv_to_audiofolder,
+    update::{OngoingUpdater, ScanProgress, UpdateAction},
+    util::{kv_to_audiofolder, parent_path, split_path},
 };
 use crate::{
     audio_folder::FolderLister,
     audio_meta::{AudioFolder, FolderByModification, TimeStamp},
     cache::update::{filter_event, FilteredEvent, RecursiveUpdater},
-    common::{CollectionOptions, CollectionTrait, PositionsData, PositionsTrait},
+    common::{
+        CollectionChange, CollectionOptions, CollectionStats, CollectionTrait, LibraryAuthor,
+        PositionsData, PositionsTrait, ScanStatus, WatchMode,
+    },
     error::{Error, Result},
-    position::{Position, PositionShort, PositionsCollector},
-    util::{get_modified, spawn_named_thread},
+    position::{
+        FolderState, GroupInfo, Position, PositionShort, PositionsCollector, PrunedPosition,
+    },
+    trash,
+    util::spawn_named_thread,
     AudioFolderShort, FoldersOrdering,
 };
-use crossbeam_channel::{unbounded as channel, Receiver, Sender};
+use crossbeam_channel::{unbounded as channel, Receiver, RecvTimeoutError, Sender};
 use notify::{recommended_watcher, Event, Watcher};
 use std::{
     collections::BinaryHeap,
@@ -23,17 +29,44 @@ use std::{
     path::{Path, PathBuf},
     sync::{Arc, Condvar, Mutex},
     thread,
+    time::Duration,
 };
 
 mod inner;
+mod stats;
 mod update;
 mod util;
 
+/// How often reachability of collection directory is checked
+const AVAILABILITY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Differences between collection cache and file system
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// folders not in cache
+    pub missing: Vec<PathBuf>,
+    /// cached folders with older modification time then actual folder
+    pub stale: Vec<PathBuf>,
+    /// cached folders, which do not exist anymore
+    pub orphaned: Vec<PathBuf>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.stale.is_empty() && self.orphaned.is_empty()
+    }
+}
+
 pub struct CollectionCache {
     thread_rescan: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
     thread_events: Option<thread::JoinHandle<()>>,
     thread_updates: Option<thread::JoinHandle<()>>,
+    thread_polling: Option<thread::JoinHandle<()>>,
+    poll_stop: Option<Sender<()>>,
+    thread_monitor: Option<thread::JoinHandle<()>>,
+    monitor_stop: Option<Sender<()>>,
     cond: Arc<(Condvar, Mutex<bool>)>,
+    scan_progress: Arc<ScanProgress>,
     pub(crate) inner: Arc<CacheInner>,
     event_sender: Option<Sender<Option<Event>>>,
     update_sender: Option<Sender<Option<UpdateAction>>>,
@@ -42,6 +75,13 @@ pub struct CollectionCache {
     notify_watcher: Arc<Mutex<Option<notify::RecommendedWatcher>>>,
     pub watch_for_changes: bool,
     pub changes_debounce_interval: u32,
+    scan_threads: u32,
+    scan_io_delay: Duration,
+    poll_interval: u32,
+    watch_mode: WatchMode,
+    read_only: bool,
+    /// temporary copy of db, if original db cannot be opened in read only mode
+    db_copy: Option<PathBuf>,
 }
 
 impl CollectionCache {
@@ -52,9 +92,10 @@ impl CollectionCache {
     ) -> Result<CollectionCache> {
         let root_path = path.into();
         let db_path = CollectionCache::db_path(&root_path, &db_dir)?;
-        let mut force_update = opt.force_cache_update_on_init && !opt.passive_init;
+        let mut force_update =
+            opt.force_cache_update_on_init && !opt.passive_init && !opt.read_only;
 
-        if !opt.passive_init {
+        if !opt.passive_init && !opt.read_only {
             let mut options_file = db_path.clone();
             options_file.set_extension("options.json");
 
@@ -87,12 +128,38 @@ impl CollectionCache {
             }
         }
 
-        let db = sled::Config::default()
-            .path(&db_path)
-            .use_compression(true)
-            .flush_every_ms(Some(10_000))
-            .cache_capacity(100 * 1024 * 1024)
-            .open()?;
+        let open_db = |path: &Path| {
+            sled::Config::default()
+                .path(path)
+                .use_compression(true)
+                .flush_every_ms(Some(10_000))
+                .cache_capacity(100 * 1024 * 1024)
+                .open()
+        };
+        let mut db_copy = None;
+        let db = match open_db(&db_path) {
+            Ok(db) => db,
+            Err(e) if opt.read_only => {
+                // sled always needs write access, so use temporary copy of db for read only collection
+                let copy_path = std::env::temp_dir().join(format!(
+                    "audioserve-ro-{}-{}",
+                    db_path
+                        .file_name()
+                        .map(|n| n.to_string_lossy())
+                        .unwrap_or_default(),
+                    std::process::id()
+                ));
+                warn!(
+                    "Cannot open read only collection db {:?} ({}), will use temporary copy {:?}",
+                    db_path, e, copy_path
+                );
+                copy_dir(&db_path, &copy_path)?;
+                let db = open_db(&copy_path)?;
+                db_copy = Some(copy_path);
+                db
+            }
+            Err(e) => return Err(e.into()),
+        };
         let (update_sender, update_receiver) = if opt.watch_for_changes {
             let (s, r) = channel();
             (Some(s), Some(r))
@@ -104,6 +171,12 @@ impl CollectionCache {
         Ok(CollectionCache {
             watch_for_changes: opt.watch_for_changes,
             changes_debounce_interval: opt.changes_debounce_interval,
+            scan_threads: opt.scan_threads,
+            scan_io_delay: Duration::from_millis(opt.scan_io_delay_ms.into()),
+            poll_interval: opt.poll_interval,
+            watch_mode: WatchMode::Disabled,
+            read_only: opt.read_only,
+            db_copy,
             inner: Arc::new(CacheInner::new(
                 db,
                 FolderLister::new_with_options(opt.into()),
@@ -114,12 +187,17 @@ impl CollectionCache {
             thread_rescan: Arc::new(Mutex::new(None)),
             thread_events: None,
             thread_updates: None,
+            thread_polling: None,
+            poll_stop: None,
+            thread_monitor: None,
+            monitor_stop: None,
             cond: Arc::new((
                 Condvar::new(),
                 #[allow(clippy::mutex_atomic)]
                 // Not sure why clippy warns, as this is taken from example cor condition in std doc
                 Mutex::new(false),
             )),
+            scan_progress: Arc::new(ScanProgress::default()),
             event_sender: None,
             update_sender,
             full_initial_update_required: force_update,
@@ -172,11 +250,23 @@ impl CollectionCache {
     }
 
     pub fn init(mut self) -> Self {
+        if self.read_only {
+            info!(
+                "Collection {:?} is read only, it will not be scanned nor updated",
+                self.base_dir()
+            );
+            let (cond_var, cond_mtx) = &*self.cond;
+            *cond_mtx.lock().unwrap() = true;
+            cond_var.notify_all();
+            self.is_initialized = true;
+            return self;
+        }
         let thread = self.start_recursive_update(self.full_initial_update_required);
         *self.thread_rescan.lock().unwrap() = Some(thread);
         if self.watch_for_changes {
             self.start_update_threads();
         }
+        self.start_monitor_thread();
         self.is_initialized = true;
         self
     }
@@ -186,6 +276,8 @@ impl CollectionCache {
         let cond = self.cond.clone();
         let inner = self.inner.clone();
         let thread_rescan = self.thread_rescan.clone();
+        let scan_progress = self.scan_progress.clone();
+        let (scan_threads, scan_io_delay) = (self.scan_threads, self.scan_io_delay);
         let thread = spawn_named_thread("collection-rescan", move || {
             let (cond_var, cond_mtx) = &*cond;
             {
@@ -193,15 +285,26 @@ impl CollectionCache {
                 *started = false;
             }
 
-            // clean up non-exitent directories
-            inner.clean_up_folders();
-
-            // initial scan of directory
-            let updater = RecursiveUpdater::new(&inner, None, force_update);
-            updater.process();
-
-            // clean up positions for non existent folders
-            inner.clean_up_positions();
+            if inner.check_available() {
+                // clean up non-exitent directories
+                inner.clean_up_folders();
+
+                // initial scan of directory
+                let updater = RecursiveUpdater::new(&inner, None, force_update)
+                    .with_progress(&scan_progress)
+                    .with_throttling(scan_threads, scan_io_delay);
+                updater.process();
+
+                // clean up positions for non existent folders
+                inner.clean_up_positions();
+            } else {
+                warn!(
+                    "Collection directory {:?} is not available, scan will run when it's back",
+                    inner.base_dir()
+                );
+            }
+            scan_progress.finish();
+            inner.notify_change(CollectionChange::ScanFinished);
 
             // Notify about finish of initial scan
             {
@@ -221,13 +324,29 @@ impl CollectionCache {
         thread
     }
 
-    pub(crate) fn start_notify_watcher(&mut self) -> Receiver<Option<Event>> {
+    pub(crate) fn start_notify_watcher(
+        &mut self,
+    ) -> std::result::Result<Receiver<Option<Event>>, notify::Error> {
         let (event_sender, event_receiver) = channel::<Option<Event>>();
-        self.event_sender = Some(event_sender.clone());
-        let root_path = self.inner.base_dir().to_owned();
+        let roots: Vec<PathBuf> = self.inner.roots().map(Path::to_path_buf).collect();
+        let ignore = self.inner.ignore_patterns().clone();
+        let passing_sender = event_sender.clone();
         let event_passing_fn = move |event: std::result::Result<Event, notify::Error>| {
-            trace!("Change in collection {:?} => {:?}", root_path, event);
+            trace!("Change in collection {:?} => {:?}", roots[0], event);
             let interesting_event = match filter_event(event) {
+                FilteredEvent::Pass(evt)
+                    if !ignore.is_empty()
+                        && evt.paths.iter().all(|p| {
+                            roots
+                                .iter()
+                                .find_map(|root| p.strip_prefix(root).ok())
+                                .map(|p| ignore.is_ignored(p))
+                                .unwrap_or(false)
+                        }) =>
+                {
+                    trace!("Ignoring change in {:?}", evt.paths);
+                    return;
+                }
                 FilteredEvent::Pass(evt) => evt,
                 FilteredEvent::Rescan => {
                     info!("Rescaning of collection required");
@@ -239,7 +358,7 @@ impl CollectionCache {
                 }
                 FilteredEvent::Ignore => return,
             };
-            if let Err(e) = event_sender.send(Some(interesting_event)) {
+            if let Err(e) = passing_sender.send(Some(interesting_event)) {
                 error!(
                     "Channel to event debouncer is broken ({}), will stop thread",
                     e
@@ -247,21 +366,39 @@ impl CollectionCache {
             }
         };
 
-        let watcher = recommended_watcher(event_passing_fn)
-            .map_err(|e| error!("Failed to create fs watcher: {}", e));
-        if let Ok(mut watcher) = watcher {
-            watcher
-                .watch(self.inner.base_dir(), notify::RecursiveMode::Recursive)
-                .map_err(|e| error!("failed to start watching: {}", e))
-                .ok();
-            *self.notify_watcher.lock().unwrap() = Some(watcher);
+        let mut watcher = recommended_watcher(event_passing_fn)?;
+        // each root is watched separately
+        for root in self.inner.roots() {
+            watcher.watch(root, notify::RecursiveMode::Recursive)?;
         }
-        event_receiver
+        *self.notify_watcher.lock().unwrap() = Some(watcher);
+        self.event_sender = Some(event_sender);
+        Ok(event_receiver)
     }
 
     /// can run only once!
     pub(crate) fn start_update_threads(&mut self) {
-        let event_receiver = self.start_notify_watcher();
+        let event_receiver = match self.start_notify_watcher() {
+            Ok(r) => r,
+            Err(e) => {
+                if self.poll_interval > 0 {
+                    warn!(
+                        "Cannot watch for changes in collection {:?} ({}), will poll for changes every {}s instead",
+                        self.base_dir(),
+                        e,
+                        self.poll_interval
+                    );
+                    self.start_polling_thread();
+                } else {
+                    error!(
+                        "Cannot watch for changes in collection {:?} ({}), changes will not be detected",
+                        self.base_dir(),
+                        e
+                    );
+                }
+                return;
+            }
+        };
         let ongoing_updater = OngoingUpdater::new(
             event_receiver,
             self.update_sender.take().unwrap(),
@@ -275,9 +412,124 @@ impl CollectionCache {
         self.thread_updates = Some(spawn_named_thread("collection_updates", || {
             inner.run_update_loop()
         }));
+        self.watch_mode = WatchMode::Watching;
+    }
+
+    /// Fallback for file systems, where watching for changes is not possible -
+    /// periodically checks modification times of folders and updates changed ones
+    fn start_polling_thread(&mut self) {
+        let inner = self.inner.clone();
+        let thread_rescan = self.thread_rescan.clone();
+        let interval = Duration::from_secs(self.poll_interval.into());
+        let (stop_sender, stop_receiver) = channel::<()>();
+        self.thread_polling = Some(spawn_named_thread("collection-polling", move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                if thread_rescan.lock().unwrap().is_some() {
+                    debug!("Rescan is running, skipping poll for changes");
+                    continue;
+                }
+                if !inner.check_available() {
+                    debug!("Collection directory is not available, skipping poll for changes");
+                    continue;
+                }
+                debug!("Polling collection {:?} for changes", inner.base_dir());
+                inner.clean_up_folders();
+                RecursiveUpdater::new(&inner, None, false).process();
+            }
+        }));
+        self.poll_stop = Some(stop_sender);
+        self.watch_mode = WatchMode::Polling;
+    }
+
+    /// Periodically checks that collection directory is reachable (network share can disappear),
+    /// while it's not available watching is paused and when it's back changes are scanned
+    fn start_monitor_thread(&mut self) {
+        let inner = self.inner.clone();
+        let thread_rescan = self.thread_rescan.clone();
+        let notify_watcher = self.notify_watcher.clone();
+        let (stop_sender, stop_receiver) = channel::<()>();
+        self.thread_monitor = Some(spawn_named_thread("collection-monitor", move || {
+            let mut watch_paused = false;
+            while let Err(RecvTimeoutError::Timeout) =
+                stop_receiver.recv_timeout(AVAILABILITY_CHECK_INTERVAL)
+            {
+                let available = inner.check_available();
+                if let Some(watcher) = notify_watcher.lock().unwrap().as_mut() {
+                    if !available && !watch_paused {
+                        for root in inner.roots() {
+                            watcher
+                                .unwatch(root)
+                                .map_err(|e| debug!("Cannot unwatch {:?}: {}", root, e))
+                                .ok();
+                        }
+                        watch_paused = true;
+                    } else if available && watch_paused {
+                        watch_paused = false;
+                        for root in inner.roots() {
+                            if let Err(e) = watcher.watch(root, notify::RecursiveMode::Recursive) {
+                                error!("Cannot watch {:?} again: {}", root, e);
+                                watch_paused = true;
+                            }
+                        }
+                    }
+                }
+                if inner.take_catch_up() {
+                    if thread_rescan.lock().unwrap().is_some() {
+                        debug!("Rescan is running, no need to scan changes");
+                        continue;
+                    }
+                    info!(
+                        "Scanning changes in collection {:?} done while it was not available",
+                        inner.base_dir()
+                    );
+                    inner.clean_up_folders();
+                    RecursiveUpdater::new(&inner, None, false).process();
+                    inner.clean_up_positions();
+                }
+            }
+        }));
+        self.monitor_stop = Some(stop_sender);
+    }
+
+    /// Compares cache with file system, cache is not changed
+    pub fn verify(&self) -> VerifyReport {
+        let report = Mutex::new(VerifyReport {
+            orphaned: self
+                .inner
+                .orphaned_folders()
+                .into_iter()
+                .map(PathBuf::from)
+                .collect(),
+            ..Default::default()
+        });
+        RecursiveUpdater::new(&self.inner, None, false)
+            .with_dry_run(&report)
+            .with_throttling(self.scan_threads, self.scan_io_delay)
+            .process();
+        let mut report = report.into_inner().unwrap();
+        report.missing.sort_unstable();
+        report.stale.sort_unstable();
+        report.orphaned.sort_unstable();
+        report
+    }
+
+    /// Brings cache in sync with file system - same as rescan, but runs in current thread
+    pub fn repair(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnlyCollection);
+        }
+        self.inner.clean_up_folders();
+        RecursiveUpdater::new(&self.inner, None, false)
+            .with_throttling(self.scan_threads, self.scan_io_delay)
+            .process();
+        self.inner.clean_up_positions();
+        self.inner.flush()
+    }
+
+    pub fn is_initial_scan_done(&self) -> bool {
+        *self.cond.1.lock().unwrap()
     }
 
-    #[allow(dead_code)]
     pub fn wait_until_inital_scan_is_done(&self) {
         let (cond_var, cond_mtx) = &*self.cond;
         let mut started = cond_mtx.lock().unwrap();
@@ -291,12 +543,25 @@ impl CollectionCache {
         self.inner.get(dir)
     }
 
-    #[allow(dead_code)]
     pub fn force_update<P: AsRef<Path>>(&self, dir_path: P) -> Result<()> {
         self.inner.force_update(dir_path, false).map(|_| ())
     }
 }
 
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            std::fs::copy(entry.path(), dest)?;
+        }
+    }
+    Ok(())
+}
+
 impl CollectionTrait for CollectionCache {
     fn list_dir<P: AsRef<Path>>(
         &self,
@@ -306,7 +571,7 @@ impl CollectionTrait for CollectionCache {
     ) -> Result<AudioFolder> {
         let dir_path = dir_path.as_ref();
         let full_path = self.inner.full_path(dir_path);
-        let ts = get_modified(&full_path);
+        let ts = self.inner.modified(dir_path);
         self.inner
             .get_if_actual(dir_path, ts)
             .map(|mut af| {
@@ -317,12 +582,19 @@ impl CollectionTrait for CollectionCache {
                 af
             })
             .ok_or_else(|| {
+                if !self.inner.is_available() {
+                    // degraded mode - directory is not reachable, so only cached content can be used
+                    return self.inner.get(dir_path).ok_or(Error::CollectionUnavailable);
+                }
                 debug!("Fetching folder {:?} from file system", dir_path);
                 self.inner.list_dir(dir_path, ordering)
             })
             .or_else(|r| {
                 match r.as_ref() {
                     Ok(af_ref) => {
+                        if self.read_only || !self.inner.is_available() {
+                            return r;
+                        }
                         // We should update cache as we got new info
                         debug!("Updating cache for dir {:?}", full_path);
                         let mut af = af_ref.clone();
@@ -345,6 +617,7 @@ impl CollectionTrait for CollectionCache {
                 r
             })
             .map(|mut af| {
+                self.inner.update_subfolders_totals(&mut af.subfolders);
                 if let Some(group) = group {
                     let folder = dir_path.to_str();
                     if let Some(folder) = folder {
@@ -353,6 +626,7 @@ impl CollectionTrait for CollectionCache {
                                 path: path.to_string(),
                                 timestamp: p.timestamp,
                                 position: p.position,
+                                finished: p.folder_finished,
                             })
                         });
                         af.position = pos;
@@ -419,21 +693,96 @@ impl CollectionTrait for CollectionCache {
         result
     }
 
-    fn signal_rescan(&self) {
+    fn library_authors(&self, folder_allowed: &dyn Fn(&Path) -> bool) -> Vec<LibraryAuthor> {
+        self.inner.library_authors(folder_allowed)
+    }
+
+    fn library_folders(&self, author: &str) -> Vec<(Option<String>, AudioFolderShort)> {
+        self.inner.library_folders(author)
+    }
+
+    fn signal_rescan(&self) -> bool {
         debug!("Required rescan on collection {:?}", self.base_dir());
+        if self.read_only {
+            warn!("Collection is read only, cannot rescan");
+            return false;
+        }
         let mut running = self.thread_rescan.lock().unwrap();
         match *running {
-            Some(_) => warn!("Rescan is still running, cannot start another"),
+            Some(_) => {
+                warn!("Rescan is still running, cannot start another");
+                false
+            }
             None => {
                 let thread = self.start_recursive_update(true);
                 *running = Some(thread);
+                true
             }
         }
     }
 
+    fn scan_status(&self) -> ScanStatus {
+        ScanStatus {
+            watch_mode: self.watch_mode,
+            degraded: !self.inner.is_available(),
+            ..self.scan_progress.status()
+        }
+    }
+
+    fn stats(&self) -> Option<CollectionStats> {
+        Some(CollectionStats {
+            last_scan: self.scan_progress.status().finished,
+            ..self.inner.stats()
+        })
+    }
+
     fn base_dir(&self) -> &Path {
         self.inner.base_dir()
     }
+
+    fn full_path(&self, rel_path: &Path) -> PathBuf {
+        self.inner.full_path(rel_path)
+    }
+
+    fn refresh_folder(&self, dir_path: &Path) -> Result<()> {
+        if self.read_only || !self.inner.is_available() {
+            return Ok(());
+        }
+        self.force_update(dir_path)
+    }
+
+    fn trash_folder(&self, dir_path: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnlyCollection);
+        }
+        if !self.inner.is_available() {
+            return Err(Error::CollectionUnavailable);
+        }
+        trash::move_to_trash(self.inner.roots(), dir_path)?;
+        // watcher would do same, but cache should reflect change right now
+        self.inner.remove_tree(dir_path)?;
+        self.force_update(parent_path(dir_path))
+    }
+
+    fn restore_folder(&self, dir_path: &Path) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnlyCollection);
+        }
+        if !self.inner.is_available() {
+            return Err(Error::CollectionUnavailable);
+        }
+        let created = trash::restore_from_trash(self.inner.roots(), dir_path)?;
+        self.inner.force_update_recursive(&created);
+        self.force_update(parent_path(&created))
+    }
+
+    fn set_removal_listener(&self, listener: crate::RemovalListener) {
+        self.inner.set_removal_listener(listener)
+    }
+
+    fn set_change_listener(&self, listener: crate::ChangeListener) {
+        self.inner.set_change_listener(listener)
+    }
 }
 
 impl Drop for CollectionCache {
@@ -441,22 +790,39 @@ impl Drop for CollectionCache {
         self.event_sender.as_ref().and_then(|s| s.send(None).ok());
         // Drop watcher early - just to be sure
         self.notify_watcher.lock().unwrap().take();
+        let watching = self.watch_mode == WatchMode::Watching;
         if let Some(t) = self.thread_events.take() {
             t.join().ok();
             debug!("Events thread joined");
-        } else if self.watch_for_changes {
+        } else if watching {
             warn!("Join handle is missing for events thread")
         }
         if let Some(t) = self.thread_updates.take() {
             t.join().ok();
             debug!("Update thread joined");
-        } else if self.watch_for_changes {
+        } else if watching {
             warn!("Join handle is missing for update thread");
         }
+        // dropping sender stops polling
+        self.poll_stop.take();
+        if let Some(t) = self.thread_polling.take() {
+            t.join().ok();
+            debug!("Polling thread joined");
+        }
+        self.monitor_stop.take();
+        if let Some(t) = self.thread_monitor.take() {
+            t.join().ok();
+            debug!("Monitor thread joined");
+        }
         self.inner
             .flush()
             .map_err(|e| error!("Final flush failed: {}", e))
             .ok();
+        if let Some(ref db_copy) = self.db_copy {
+            std::fs::remove_dir_all(db_copy)
+                .map_err(|e| error!("Cannot remove temporary db copy {:?}: {}", db_copy, e))
+                .ok();
+        }
     }
 }
 
@@ -467,7 +833,13 @@ impl PositionsTrait for CollectionCache {
         S: AsRef<str>,
         P: AsRef<str>,
     {
-        self.inner.mark_as_finished(group, path, ts)
+        if self.read_only {
+            return Err(Error::ReadOnlyCollection);
+        }
+        let was_finished = self.inner.is_finished(&group, &path);
+        self.inner.mark_as_finished(&group, &path, ts)?;
+        self.inner.notify_position(group, path, was_finished);
+        Ok(())
     }
 
     fn insert_position<S, P>(
@@ -482,8 +854,33 @@ impl PositionsTrait for CollectionCache {
         S: AsRef<str>,
         P: AsRef<str>,
     {
+        if self.read_only {
+            return Err(Error::ReadOnlyCollection);
+        }
+        let folder = split_path(&path).0;
+        let was_finished = self.inner.is_finished(&group, folder);
         self.inner
-            .insert_position(group, path, position, finished, ts, false)
+            .insert_position(&group, &path, position, finished, ts, false)?;
+        self.inner.notify_position(group, folder, was_finished);
+        Ok(())
+    }
+
+    fn set_folder_state<S, P>(&self, group: S, folder: P, state: FolderState) -> Result<()>
+    where
+        S: AsRef<str>,
+        P: AsRef<str>,
+    {
+        if self.read_only {
+            return Err(Error::ReadOnlyCollection);
+        }
+        let was_finished = self.inner.is_finished(&group, &folder);
+        match state {
+            FolderState::Finished => self.inner.mark_as_finished(&group, &folder, None),
+            FolderState::Unfinished => self.inner.mark_as_unfinished(&group, &folder),
+            FolderState::Reset => self.inner.remove_folder_position(&group, &folder),
+        }?;
+        self.inner.notify_position(group, folder, was_finished);
+        Ok(())
     }
 
     fn get_position<S, P>(&self, group: S, folder: Option<P>) -> Option<Position>
@@ -511,9 +908,45 @@ impl PositionsTrait for CollectionCache {
     }
 
     fn read_json_positions(&self, data: PositionsData) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnlyCollection);
+        }
         self.inner.read_json_positions(data)
     }
 
+    fn prune_positions(
+        &self,
+        collection_no: usize,
+        max_age: &dyn Fn(&str) -> Option<Duration>,
+        dry_run: bool,
+    ) -> Result<Vec<PrunedPosition>> {
+        if self.read_only && !dry_run {
+            return Err(Error::ReadOnlyCollection);
+        }
+        if !self.inner.is_available() {
+            return Err(Error::CollectionUnavailable);
+        }
+        self.inner.prune_positions(collection_no, max_age, dry_run)
+    }
+
+    fn groups(&self) -> Vec<GroupInfo> {
+        self.inner.groups()
+    }
+
+    fn rename_group(&self, from: &str, to: &str) -> Result<usize> {
+        if self.read_only {
+            return Err(Error::ReadOnlyCollection);
+        }
+        self.inner.rename_group(from, to)
+    }
+
+    fn delete_group(&self, group: &str) -> Result<usize> {
+        if self.read_only {
+            return Err(Error::ReadOnlyCollection);
+        }
+        self.inner.delete_group(group)
+    }
+
     fn get_positions_recursive<S, P>(
         &self,
         group: S,
@@ -610,7 +1043,11 @@ mod tests {
     use serde_json::Value;
     use tempdir::TempDir;
 
-    use crate::position::PositionItem;
+    use crate::{
+        common::ScanState,
+        position::{PositionItem, PruneReason},
+        tags::{ARTIST, SERIES},
+    };
 
     use super::*;
 
@@ -642,6 +1079,74 @@ mod tests {
         assert_eq!(0, entry3.subfolders.len());
     }
 
+    #[test]
+    fn test_scan_status() {
+        env_logger::try_init().ok();
+        let (col, _tmp_dir) = create_tmp_collection();
+        assert!(col.is_initial_scan_done());
+
+        let status = col.scan_status();
+        assert_eq!(ScanState::Idle, status.state);
+        assert!(status.full);
+        assert!(status.folders_scanned >= 3);
+        assert_eq!(0, status.folders_queued);
+        assert!(status.started.is_some());
+        assert!(status.finished.unwrap() >= status.started.unwrap());
+    }
+
+    #[test]
+    fn test_library_index() {
+        env_logger::try_init().ok();
+        let (col, _tmp_dir) = create_tmp_collection();
+        assert!(col.library_authors(&|_| true).is_empty());
+
+        let mut af = col.get("usak/kulisak").unwrap();
+        af.tags = Some(
+            [
+                (ARTIST.to_string(), "Usak".to_string()),
+                (SERIES.to_string(), "Kulisaci".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        col.inner.update("usak/kulisak", af).unwrap();
+        let authors = col.library_authors(&|_| true);
+        assert_eq!(1, authors.len());
+        assert_eq!("Usak", authors[0].name);
+        assert_eq!(1, authors[0].folders);
+        assert!(col.library_authors(&|p| !p.starts_with("usak")).is_empty());
+        let folders = col.library_folders("Usak");
+        assert_eq!(1, folders.len());
+        assert_eq!(Some("Kulisaci".to_string()), folders[0].0);
+        assert_eq!(Path::new("usak/kulisak"), folders[0].1.path);
+
+        col.inner.remove_tree("usak").unwrap();
+        assert!(col.library_authors(&|_| true).is_empty());
+        assert!(col.library_folders("Usak").is_empty());
+    }
+
+    #[test]
+    fn test_parallel_scan() {
+        env_logger::try_init().ok();
+        let tmp_dir = TempDir::new("AS_CACHE_TEST").expect("Cannot create temp dir");
+        let db_path = tmp_dir.path().join("updater_db");
+        fs::create_dir(&db_path).ok();
+        let opt = CollectionOptions {
+            scan_threads: 4,
+            scan_io_delay_ms: 1,
+            ..Default::default()
+        };
+        let col = CollectionCache::new("../../test_data", db_path, opt)
+            .expect("Cannot create CollectionCache");
+        col.start_recursive_update(true);
+        col.wait_until_inital_scan_is_done();
+
+        assert!(col.get("").is_some());
+        assert!(col.get("usak/kulisak").is_some());
+        assert!(col.get("01-file.mp3").is_some());
+        assert_eq!(0, col.scan_status().folders_queued);
+    }
+
     #[test]
     fn test_cache_manipulation() -> anyhow::Result<()> {
         env_logger::try_init().ok();
@@ -677,6 +1182,38 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_verify_and_repair() -> anyhow::Result<()> {
+        env_logger::try_init().ok();
+        let tmp_dir = TempDir::new("AS_CACHE_TEST")?;
+        copy("../../test_data", tmp_dir.path(), &CopyOptions::default())?;
+        let test_data_dir = tmp_dir.path().join("test_data");
+        let db_path = tmp_dir.path().join("updater_db");
+        fs::create_dir(&db_path).ok();
+        let col = CollectionCache::new(&test_data_dir, db_path, CollectionOptions::default())
+            .expect("Cannot create CollectionCache");
+
+        let report = col.verify();
+        assert!(report.missing.contains(&PathBuf::from("usak/kulisak")));
+        assert!(report.orphaned.is_empty());
+        assert!(
+            col.get("usak/kulisak").is_none(),
+            "verify does not change cache"
+        );
+
+        col.repair()?;
+        assert!(col.verify().is_ok());
+
+        fs::remove_dir_all(test_data_dir.join("usak/kulisak"))?;
+        let report = col.verify();
+        assert_eq!(vec![PathBuf::from("usak/kulisak")], report.orphaned);
+
+        col.repair()?;
+        assert!(col.verify().is_ok());
+        assert!(col.get("usak/kulisak").is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_positions_json() -> anyhow::Result<()> {
         env_logger::try_init().ok();
@@ -827,4 +1364,146 @@ mod tests {
         assert_eq!(1, res.into_vec().len());
         Ok(())
     }
+
+    #[test]
+    fn test_prune_positions() -> anyhow::Result<()> {
+        env_logger::try_init().ok();
+        let (col, _tmp_dir) = create_tmp_collection();
+        let old_ts: TimeStamp = (SystemTime::now() - Duration::from_secs(3600 * 24 * 60)).into();
+        col.inner
+            .insert_position("ivan", "02-file.opus", 1.0, false, Some(old_ts), true)?;
+        col.insert_position("usak", "02-file.opus", 2.0, false, None)?;
+        col.insert_position(
+            "ivan",
+            "01-file.mp3/002 - Chapter 3$$2000-3000$$.mp3",
+            3.0,
+            false,
+            None,
+        )?;
+        let max_age = |group: &str| (group == "ivan").then(|| Duration::from_secs(3600 * 24 * 30));
+
+        let pruned = col.prune_positions(1, &max_age, true)?;
+        assert_eq!(1, pruned.len());
+        assert_eq!(PruneReason::Expired, pruned[0].reason);
+        assert_eq!(1, pruned[0].collection);
+        assert_eq!("", pruned[0].folder);
+        assert_eq!("ivan", pruned[0].group);
+        assert!(col.get_position("ivan", Some("")).is_some());
+
+        col.inner.remove("01-file.mp3")?;
+        let pruned = col.prune_positions(0, &max_age, false)?;
+        assert_eq!(2, pruned.len());
+        assert!(pruned
+            .iter()
+            .any(|p| p.folder == "01-file.mp3" && p.reason == PruneReason::MissingFolder));
+        assert!(col.get_position("ivan", Some("")).is_none());
+        assert!(col.get_position("ivan", Some("01-file.mp3")).is_none());
+        assert!(col.get_position::<_, &str>("ivan", None).is_none());
+        let p = col.get_position("usak", Some("")).expect("position exists");
+        assert_eq!(2.0, p.position);
+        Ok(())
+    }
+
+    #[test]
+    fn test_groups() -> anyhow::Result<()> {
+        env_logger::try_init().ok();
+        let (col, _tmp_dir) = create_tmp_collection();
+        let old_ts: TimeStamp = (SystemTime::now() - Duration::from_secs(3600)).into();
+        col.inner
+            .insert_position("ivna", "02-file.opus", 1.0, false, Some(old_ts), true)?;
+        col.insert_position(
+            "ivna",
+            "01-file.mp3/002 - Chapter 3$$2000-3000$$.mp3",
+            2.0,
+            false,
+            None,
+        )?;
+        col.insert_position("ivan", "02-file.opus", 3.0, false, None)?;
+
+        let mut groups = col.groups();
+        groups.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(2, groups.len());
+        assert_eq!("ivna", groups[1].name);
+        assert_eq!(2, groups[1].positions);
+        assert!(groups[1].last_activity > old_ts);
+
+        assert_eq!(2, col.rename_group("ivna", "ivan")?);
+        let groups = col.groups();
+        assert_eq!(1, groups.len());
+        assert_eq!(2, groups[0].positions);
+        // newer position is kept
+        let p = col.get_position("ivan", Some("")).expect("position exists");
+        assert_eq!(3.0, p.position);
+        let p = col
+            .get_position::<_, &str>("ivan", None)
+            .expect("last position exists");
+        assert_eq!(3.0, p.position);
+        assert!(col.get_position::<_, &str>("ivna", None).is_none());
+
+        assert_eq!(2, col.delete_group("ivan")?);
+        assert!(col.groups().is_empty());
+        assert!(col.get_position::<_, &str>("ivan", None).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_folder_state() -> anyhow::Result<()> {
+        env_logger::try_init().ok();
+        let (col, _tmp_dir) = create_tmp_collection();
+        col.insert_position("ivan", "02-file.opus", 1.0, false, None)?;
+        col.set_folder_state("ivan", "", FolderState::Finished)?;
+        let p = col.get_position("ivan", Some("")).expect("position exists");
+        assert!(p.folder_finished);
+        assert_eq!(p.position, 1.0);
+
+        col.set_folder_state("ivan", "", FolderState::Unfinished)?;
+        let p = col.get_position("ivan", Some("")).expect("position exists");
+        assert!(!p.folder_finished);
+        assert_eq!(p.file, "02-file.opus");
+
+        col.set_folder_state("ivan", "", FolderState::Reset)?;
+        assert!(col.get_position("ivan", Some("")).is_none());
+        // reset of non existent position is OK
+        col.set_folder_state("ivan", "", FolderState::Reset)?;
+
+        col.set_folder_state("ivan", "01-file.mp3", FolderState::Finished)?;
+        assert!(col.inner.is_finished("ivan", "01-file.mp3"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_position_change_notification() -> anyhow::Result<()> {
+        env_logger::try_init().ok();
+        let (col, _tmp_dir) = create_tmp_collection();
+        let changes = Arc::new(Mutex::new(vec![]));
+        let changes2 = changes.clone();
+        col.set_change_listener(Arc::new(move |c| {
+            // scan or watcher might be still running
+            if matches!(c, CollectionChange::PositionChanged { .. }) {
+                changes2.lock().unwrap().push(c)
+            }
+        }));
+
+        col.insert_position("ivan", "02-file.opus", 1.0, false, None)?;
+        col.set_folder_state("usak", "", FolderState::Finished)?;
+        // already finished
+        col.set_folder_state("usak", "", FolderState::Finished)?;
+        col.insert_position("ivan", "02-file.opus", 2.0, false, Some(TimeStamp::from(0)))
+            .expect_err("older position is ignored");
+
+        let position_changed = |group: &str, finished| CollectionChange::PositionChanged {
+            group: group.to_string(),
+            folder: "".to_string(),
+            finished,
+        };
+        assert_eq!(
+            vec![
+                position_changed("ivan", false),
+                position_changed("usak", true),
+                position_changed("usak", false)
+            ],
+            *changes.lock().unwrap()
+        );
+        Ok(())
+    }
 }
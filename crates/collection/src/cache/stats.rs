@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{audio_folder::parse_chapter_path, audio_meta::AudioFolder, common::CollectionStats};
+
+/// Collection statistics kept in sync with cache content - folder records are added to them
+/// when inserted into cache and subtracted when replaced or removed
+#[derive(Default)]
+pub(crate) struct StatsCounter(Mutex<CollectionStats>);
+
+impl StatsCounter {
+    pub(crate) fn from_folders(folders: impl Iterator<Item = AudioFolder>) -> Self {
+        let mut stats = CollectionStats::default();
+        folders.for_each(|af| add_folder(&mut stats, &af));
+        StatsCounter(Mutex::new(stats))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CollectionStats> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Updates statistics after folder record was replaced - old record (if any) by new one (if any)
+    pub(crate) fn replace(&self, old: Option<&AudioFolder>, new: Option<&AudioFolder>) {
+        let mut stats = self.lock();
+        if let Some(old) = old {
+            subtract_folder(&mut stats, old)
+        }
+        if let Some(new) = new {
+            add_folder(&mut stats, new)
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CollectionStats {
+        self.lock().clone()
+    }
+}
+
+/// Audio files of folder by their real path (chapters belong to same file) - mime and playback time
+fn folder_files(af: &AudioFolder) -> HashMap<PathBuf, (&str, u64)> {
+    let mut files: HashMap<PathBuf, (&str, u64)> = HashMap::new();
+    for f in &af.files {
+        let path = parse_chapter_path(&f.path).0.into_owned();
+        let duration = f.meta.as_ref().map(|m| m.duration as u64).unwrap_or(0);
+        files.entry(path).or_insert((f.mime.as_str(), 0)).1 += duration;
+    }
+    files
+}
+
+fn add_folder(stats: &mut CollectionStats, af: &AudioFolder) {
+    stats.folders += 1;
+    stats.total_size += af.total_size.unwrap_or(0);
+    for (_, (mime, duration)) in folder_files(af) {
+        stats.files += 1;
+        stats.total_time += duration;
+        let format = stats.formats.entry(mime.to_string()).or_default();
+        format.files += 1;
+        format.total_time += duration;
+    }
+}
+
+fn subtract_folder(stats: &mut CollectionStats, af: &AudioFolder) {
+    stats.folders = stats.folders.saturating_sub(1);
+    stats.total_size = stats.total_size.saturating_sub(af.total_size.unwrap_or(0));
+    for (_, (mime, duration)) in folder_files(af) {
+        stats.files = stats.files.saturating_sub(1);
+        stats.total_time = stats.total_time.saturating_sub(duration);
+        if let Some(format) = stats.formats.get_mut(mime) {
+            format.files = format.files.saturating_sub(1);
+            format.total_time = format.total_time.saturating_sub(duration);
+            if format.files == 0 {
+                stats.formats.remove(mime);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{audio_meta::AudioMeta, AudioFile};
+
+    fn folder(files: &[(&str, &str, u32)], size: u64) -> AudioFolder {
+        AudioFolder {
+            is_file: false,
+            is_collapsed: false,
+            modified: None,
+            total_time: None,
+            total_size: Some(size),
+            files: files
+                .iter()
+                .map(|(path, mime, duration)| AudioFile {
+                    name: (*path).into(),
+                    path: (*path).into(),
+                    meta: Some(AudioMeta {
+                        duration: *duration,
+                        bitrate: 64,
+                        tags: None,
+                        loudness: None,
+                    }),
+                    mime: mime.to_string(),
+                    section: None,
+                })
+                .collect(),
+            subfolders: vec![],
+            cover: None,
+            description: None,
+            position: None,
+            tags: None,
+        }
+    }
+
+    #[test]
+    fn test_stats_counter() {
+        let book = folder(
+            &[
+                ("book/01.mp3", "audio/mpeg", 100),
+                ("book/02.opus", "audio/ogg", 50),
+            ],
+            1000,
+        );
+        let chapters = folder(
+            &[
+                ("book.m4b/001 - One$$0-60000$$.m4b", "audio/mp4", 60),
+                ("book.m4b/002 - Two$$60000-90000$$.m4b", "audio/mp4", 30),
+            ],
+            500,
+        );
+        let counter = StatsCounter::from_folders([book.clone(), chapters.clone()].into_iter());
+        let stats = counter.stats();
+        assert_eq!(2, stats.folders);
+        assert_eq!(3, stats.files);
+        assert_eq!(1500, stats.total_size);
+        assert_eq!(240, stats.total_time);
+        assert_eq!(3, stats.formats.len());
+        assert_eq!(1, stats.formats["audio/mp4"].files);
+        assert_eq!(90, stats.formats["audio/mp4"].total_time);
+
+        let updated = folder(&[("book/01.mp3", "audio/mpeg", 100)], 700);
+        counter.replace(Some(&book), Some(&updated));
+        counter.replace(Some(&chapters), None);
+        let stats = counter.stats();
+        assert_eq!(1, stats.folders);
+        assert_eq!(1, stats.files);
+        assert_eq!(700, stats.total_size);
+        assert_eq!(100, stats.total_time);
+        assert_eq!(vec!["audio/mpeg"], stats.formats.keys().collect::<Vec<_>>());
+    }
+}
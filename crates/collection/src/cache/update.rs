@@ -2,7 +2,11 @@ use std::{
     cmp::Ordering,
     collections::{HashMap, VecDeque},
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering as AtomicOrdering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
     time::{Duration, Instant},
 };
 
@@ -13,9 +17,13 @@ use notify::{
     Event, EventKind,
 };
 
-use crate::{util::get_modified, AudioFolderShort};
+use crate::{
+    audio_meta::TimeStamp,
+    common::{ScanState, ScanStatus},
+    AudioFolderShort,
+};
 
-use super::{util::parent_path, CacheInner};
+use super::{util::parent_path, CacheInner, VerifyReport};
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub(crate) struct UpdateAction {
@@ -227,9 +235,28 @@ impl OngoingUpdater {
                     ))
                 }
             }
+            Modification::MovedTo(to_path) if self.inner.ignore_patterns().is_ignored(col_path) => {
+                // moved from ignored folder (e.g. restored from trash) - same as new folder
+                let dest_path = self.inner.strip_base(&to_path);
+                if self.inner.path_type(&to_path).is_dir() {
+                    result.push(UpdateAction::new(
+                        dest_path,
+                        UpdateActionKind::RefreshFolderRecursive,
+                    ));
+                }
+                result.push(UpdateAction::new(
+                    self.inner.get_true_parent(dest_path, &to_path),
+                    UpdateActionKind::RefreshFolder,
+                ));
+            }
             Modification::MovedTo(to_path) => {
                 if self.inner.path_type(path).is_dir() {
-                    if self.inner.is_collapsable_folder(&to_path) {
+                    if self.inner.is_collapsable_folder(&to_path)
+                        || self
+                            .inner
+                            .ignore_patterns()
+                            .is_ignored(self.inner.strip_base(&to_path))
+                    {
                         result.push(UpdateAction::new(col_path, UpdateActionKind::RemoveFolder));
                     } else {
                         let dest_path = self.inner.strip_base(&to_path).into();
@@ -374,12 +401,53 @@ impl OngoingUpdater {
     }
 }
 
+/// Reports progress of recursive scan, so it can be watched from other threads
+#[derive(Default)]
+pub(super) struct ScanProgress(Mutex<ScanStatus>);
+
+impl ScanProgress {
+    fn start(&self, full: bool) {
+        let mut status = self.0.lock().unwrap();
+        *status = ScanStatus {
+            state: ScanState::Running,
+            full,
+            folders_queued: 1,
+            started: Some(TimeStamp::now()),
+            ..Default::default()
+        };
+    }
+
+    fn update(&self, scanned: u64, queued: usize) {
+        let mut status = self.0.lock().unwrap();
+        status.folders_scanned = scanned;
+        status.folders_queued = queued as u64;
+    }
+
+    pub(super) fn finish(&self) {
+        let mut status = self.0.lock().unwrap();
+        status.state = ScanState::Idle;
+        status.folders_queued = 0;
+        status.finished = Some(TimeStamp::now());
+    }
+
+    pub(super) fn status(&self) -> ScanStatus {
+        self.0.lock().unwrap().clone()
+    }
+}
+
 pub(super) struct RecursiveUpdater<'a> {
     queue: VecDeque<AudioFolderShort>,
     inner: &'a CacheInner,
     force_update: bool,
+    progress: Option<&'a ScanProgress>,
+    threads: u32,
+    io_delay: Duration,
+    dry_run: Option<&'a Mutex<VerifyReport>>,
 }
 
+/// Folders waiting for scan and number of folders being scanned right now
+type ScanQueue = (VecDeque<AudioFolderShort>, usize);
+
 impl<'a> RecursiveUpdater<'a> {
     pub(super) fn new(
         inner: &'a CacheInner,
@@ -392,6 +460,8 @@ impl<'a> RecursiveUpdater<'a> {
             is_file: false,
             modified: None,
             finished: false,
+            total_time: None,
+            total_size: None,
         });
         let mut queue = VecDeque::new();
         queue.push_back(root);
@@ -399,38 +469,125 @@ impl<'a> RecursiveUpdater<'a> {
             queue,
             inner,
             force_update,
+            progress: None,
+            threads: 1,
+            io_delay: Duration::ZERO,
+            dry_run: None,
         }
     }
 
+    /// Cache is not updated, instead folders, which would be updated, are recorded in `report`
+    pub(super) fn with_dry_run(mut self, report: &'a Mutex<VerifyReport>) -> Self {
+        self.dry_run = Some(report);
+        self
+    }
+
+    /// Progress of scan will be reported to `progress` - it's started here, but caller is responsible to finish it
+    pub(super) fn with_progress(mut self, progress: &'a ScanProgress) -> Self {
+        progress.start(self.force_update);
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Limits IO load of scan - `threads` folders are scanned in parallel and each thread waits `io_delay` after each folder
+    pub(super) fn with_throttling(mut self, threads: u32, io_delay: Duration) -> Self {
+        self.threads = threads.max(1);
+        self.io_delay = io_delay;
+        self
+    }
+
     pub(super) fn process(mut self) {
-        while let Some(folder_info) = self.queue.pop_front() {
-            // process AF
-            let full_path = self.inner.base_dir().join(&folder_info.path);
-            let mod_ts = get_modified(full_path);
-            let af = match if self.force_update {
-                None
-            } else {
-                self.inner.get_if_actual(&folder_info.path, mod_ts)
-            } {
-                None => match self.inner.force_update(&folder_info.path, true) {
-                    Ok(af) => {
-                        af.unwrap() // safe to unwrap as we set ret param
+        let queue = Mutex::new((std::mem::take(&mut self.queue), 0));
+        let cond = Condvar::new();
+        let scanned = AtomicU64::new(0);
+        if self.threads > 1 {
+            thread::scope(|s| {
+                for _ in 0..self.threads {
+                    s.spawn(|| self.run_worker(&queue, &cond, &scanned));
+                }
+            })
+        } else {
+            self.run_worker(&queue, &cond, &scanned)
+        }
+    }
+
+    fn run_worker(&self, queue: &Mutex<ScanQueue>, cond: &Condvar, scanned: &AtomicU64) {
+        loop {
+            let folder_info = {
+                let mut q = queue.lock().unwrap();
+                loop {
+                    if let Some(folder_info) = q.0.pop_front() {
+                        q.1 += 1;
+                        break folder_info;
+                    } else if q.1 == 0 {
+                        // nothing to scan and nobody is scanning, so no new folders will come
+                        cond.notify_all();
+                        return;
+                    } else {
+                        q = cond.wait(q).unwrap();
                     }
+                }
+            };
+            let scanned = scanned.fetch_add(1, AtomicOrdering::Relaxed) + 1;
+            if let Some(progress) = self.progress {
+                progress.update(scanned, queue.lock().unwrap().0.len());
+            }
+            let subfolders = self.update_folder(folder_info);
+            if !self.io_delay.is_zero() {
+                thread::sleep(self.io_delay);
+            }
+            let mut q = queue.lock().unwrap();
+            q.0.extend(subfolders);
+            q.1 -= 1;
+            cond.notify_all();
+        }
+    }
+
+    /// Updates folder (if needed) and returns its subfolders
+    fn update_folder(&self, folder_info: AudioFolderShort) -> Vec<AudioFolderShort> {
+        let mod_ts = self.inner.modified(&folder_info.path);
+        let af = match if self.force_update {
+            None
+        } else {
+            self.inner.get_if_actual(&folder_info.path, mod_ts)
+        } {
+            None if self.dry_run.is_some() => {
+                let mut report = self.dry_run.unwrap().lock().unwrap();
+                if self.inner.has_key(&folder_info.path) {
+                    report.stale.push(folder_info.path.clone());
+                } else {
+                    report.missing.push(folder_info.path.clone());
+                }
+                drop(report);
+                match self.inner.list_dir_uncached(&folder_info.path) {
+                    Ok(af) => af,
                     Err(e) => {
                         error!(
-                            "Cannot update audio folder {:?}, error {}",
+                            "Cannot list audio folder {:?}, error {}",
                             folder_info.path, e
                         );
-                        continue;
+                        return vec![];
                     }
-                },
-                Some(af) => {
-                    debug!("For path {:?} using cached data", folder_info.path);
-                    af
                 }
-            };
-            self.queue.extend(af.subfolders)
-        }
+            }
+            None => match self.inner.force_update(&folder_info.path, true) {
+                Ok(af) => {
+                    af.unwrap() // safe to unwrap as we set ret param
+                }
+                Err(e) => {
+                    error!(
+                        "Cannot update audio folder {:?}, error {}",
+                        folder_info.path, e
+                    );
+                    return vec![];
+                }
+            },
+            Some(af) => {
+                debug!("For path {:?} using cached data", folder_info.path);
+                af
+            }
+        };
+        af.subfolders
     }
 }
 
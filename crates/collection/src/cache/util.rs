@@ -1,6 +1,16 @@
-use std::path::{Path, PathBuf};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
-use crate::{audio_meta::AudioFolder, AudioFolderShort};
+use crate::{
+    audio_meta::AudioFolder,
+    tags::{ALBUM_ARTIST, ARTIST, SERIES},
+    AudioFolderShort,
+};
+
+/// Separator of parts of library index key - author, series and folder path
+const LIBRARY_KEY_SEP: char = '\0';
 
 pub fn update_path(
     from: &Path,
@@ -30,9 +40,43 @@ pub fn kv_to_audiofolder<K: AsRef<str>, V: AsRef<[u8]>>(key: K, val: V) -> Audio
         is_file: folder.as_ref().map(|f| f.is_file).unwrap_or(false),
         modified: folder.as_ref().and_then(|f| f.modified),
         finished: false,
+        total_time: folder.as_ref().and_then(|f| f.total_time),
+        total_size: folder.as_ref().and_then(|f| f.total_size),
     }
 }
 
+/// Key for library index - author, series (can be empty) and folder path,
+/// folders without author tag are not indexed
+pub fn library_key(path: &str, af: &AudioFolder) -> Option<String> {
+    let tags = af.tags.as_ref()?;
+    let author = tags
+        .get(ARTIST)
+        .or_else(|| tags.get(ALBUM_ARTIST))
+        .map(|a| a.trim())
+        .filter(|a| !a.is_empty())?;
+    let series = tags.get(SERIES).map(|s| s.trim()).unwrap_or_default();
+    Some(format!(
+        "{}{sep}{}{sep}{}",
+        author,
+        series,
+        path,
+        sep = LIBRARY_KEY_SEP
+    ))
+}
+
+pub fn library_author_prefix(author: &str) -> String {
+    format!("{}{}", author, LIBRARY_KEY_SEP)
+}
+
+/// Splits library key into author, series and path
+pub fn split_library_key(key: &str) -> Option<(&str, Option<&str>, &str)> {
+    let mut parts = key.splitn(3, LIBRARY_KEY_SEP);
+    let author = parts.next()?;
+    let series = parts.next()?;
+    let path = parts.next()?;
+    Some((author, Some(series).filter(|s| !s.is_empty()), path))
+}
+
 pub fn parent_path<P: AsRef<Path>>(path: P) -> PathBuf {
     path.as_ref()
         .parent()
@@ -48,10 +92,57 @@ pub fn split_path<S: AsRef<str>>(p: &S) -> (&str, &str) {
     }
 }
 
+/// Collection directory is reachable - it can be read and if cache has some content it must not be empty,
+/// as unmounted mount point is usually just empty directory
+pub fn base_dir_available(base_dir: &Path, has_content: bool) -> bool {
+    match fs::read_dir(base_dir) {
+        Ok(mut entries) => !has_content || entries.next().is_some(),
+        Err(_) => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_library_key() {
+        let mut af = AudioFolder {
+            is_file: false,
+            is_collapsed: false,
+            modified: None,
+            total_time: None,
+            total_size: None,
+            files: vec![],
+            subfolders: vec![],
+            cover: None,
+            description: None,
+            position: None,
+            tags: None,
+        };
+        assert!(library_key("wells/man", &af).is_none());
+        af.tags = Some(
+            [(ARTIST.to_string(), "H. G. Wells".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        let key = library_key("wells/man", &af).unwrap();
+        assert_eq!(
+            Some(("H. G. Wells", None, "wells/man")),
+            split_library_key(&key)
+        );
+        assert!(key.starts_with(&library_author_prefix("H. G. Wells")));
+        af.tags
+            .as_mut()
+            .unwrap()
+            .insert(SERIES.to_string(), "Classics".to_string());
+        let key = library_key("wells/man", &af).unwrap();
+        assert_eq!(
+            Some(("H. G. Wells", Some("Classics"), "wells/man")),
+            split_library_key(&key)
+        );
+    }
+
     #[test]
     fn test_parent_path() {
         let p1 = Path::new("usak/kulisak");
@@ -59,4 +150,14 @@ mod tests {
         let p2 = Path::new("usak");
         assert_eq!(Path::new(""), parent_path(p2));
     }
+
+    #[test]
+    fn test_base_dir_available() {
+        let tmp_dir = tempdir::TempDir::new("AS_AVAILABLE_TEST").unwrap();
+        assert!(base_dir_available(tmp_dir.path(), false));
+        assert!(!base_dir_available(tmp_dir.path(), true));
+        fs::create_dir(tmp_dir.path().join("author")).unwrap();
+        assert!(base_dir_available(tmp_dir.path(), true));
+        assert!(!base_dir_available(&tmp_dir.path().join("missing"), false));
+    }
 }
@@ -2,8 +2,9 @@ use crate::{
     audio_meta::{AudioFolder, TimeStamp},
     cache::CollectionCache,
     error::{invalid_option, invalid_option_err, Error, Result},
+    ignore::{check_pattern, read_ignore_file},
     no_cache::CollectionDirect,
-    position::PositionsCollector,
+    position::{FolderState, GroupInfo, PositionsCollector, PrunedPosition},
     AudioFolderShort, FoldersOrdering, Position,
 };
 use enum_dispatch::enum_dispatch;
@@ -12,18 +13,72 @@ use regex::{Regex, RegexBuilder};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
 };
 
+/// Callback invoked with absolute path of folder, which was removed from collection
+pub type RemovalListener = std::sync::Arc<dyn Fn(&Path) + Send + Sync>;
+
+/// Change in collection, paths are relative to collection root
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CollectionChange {
+    /// folder was updated in cache (or removed from it) after change on file system
+    FolderChanged(PathBuf),
+    /// new folder was added to cache after change on file system
+    FolderAdded(PathBuf),
+    /// playback position of group in folder changed, `finished` is true if folder was just marked as finished
+    PositionChanged {
+        group: String,
+        folder: String,
+        finished: bool,
+    },
+    /// scan of collection finished
+    ScanFinished,
+}
+
+/// Callback invoked with changes in collection
+pub type ChangeListener = std::sync::Arc<dyn Fn(CollectionChange) + Send + Sync>;
+
 /// Minimum chapter duration for splitting - in minutes
 pub const MINIMUM_CHAPTER_DURATION: u32 = 10;
+/// Maximum number of threads for collection scan
+pub const MAX_SCAN_THREADS: u32 = 32;
+/// Maximum delay after folder scan - in milliseconds
+pub const MAX_SCAN_IO_DELAY: u32 = 10_000;
 
 pub enum PositionsData {
     Legacy(()),
     V1(Map<String, Value>),
 }
 
+/// Source of folder cover, used in configurable priority order
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverSource {
+    /// Image file in folder (or in directory containing chapterized audio file)
+    File,
+    /// Cover embedded in audio file
+    Embedded,
+    /// Image file in parent folder
+    Parent,
+}
+
+impl FromStr for CoverSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "file" => Ok(CoverSource::File),
+            "embedded" => Ok(CoverSource::Embedded),
+            "parent" => Ok(CoverSource::Parent),
+            other => invalid_option!("Invalid cover source {}", other),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CollectionOptions {
     #[serde(skip)]
@@ -31,6 +86,9 @@ pub struct CollectionOptions {
     pub chapters_duration: u32,
     pub chapters_from_duration: u32,
     pub ignore_chapters_meta: bool,
+    /// split points of long files without chapters are moved to nearest silence
+    #[serde(default)]
+    pub chapters_from_silence: bool,
     pub allow_symlinks: bool,
     pub no_dir_collaps: bool,
     pub natural_files_ordering: bool,
@@ -47,10 +105,37 @@ pub struct CollectionOptions {
     #[serde(skip)]
     pub time_to_end_of_folder: u32, // time before end of last file to mark folder finished
     pub read_playlists: bool,
+    /// empty means default - image in folder, for chapterized file cover embedded in it
+    #[serde(default)]
+    pub cover_sources: Vec<CoverSource>,
+    #[serde(default)]
+    pub cover_names: Vec<String>,
+    /// glob patterns of ignored files and folders, including patterns from ignore file in collection root
+    #[serde(default)]
+    pub ignore_patterns: Vec<String>,
+    /// additional root directories merged with collection directory into one tree,
+    /// if same path exists in several roots, first one wins (collection directory is first)
+    #[serde(default)]
+    pub roots: Vec<PathBuf>,
     #[serde(skip)]
     pub watch_for_changes: bool,
     #[serde(skip)]
     pub changes_debounce_interval: u32,
+    /// number of threads scanning collection
+    #[serde(skip)]
+    pub scan_threads: u32,
+    /// delay after scan of each folder, to limit IO load
+    #[serde(skip)]
+    pub scan_io_delay_ms: u32,
+    /// interval (in secs) of polling for changes, if watching for changes fails, 0 means no polling
+    #[serde(skip)]
+    pub poll_interval: u32,
+    /// collection cache is only read - no updates and no positions are written
+    #[serde(skip)]
+    pub read_only: bool,
+    /// collection can be browsed and streamed without authentication, used only by server
+    #[serde(skip)]
+    pub public: bool,
 }
 
 impl PartialEq for CollectionOptions {
@@ -59,10 +144,15 @@ impl PartialEq for CollectionOptions {
         let res = self.chapters_duration == other.chapters_duration
             && self.chapters_from_duration == other.chapters_from_duration
             && self.ignore_chapters_meta == other.ignore_chapters_meta
+            && self.chapters_from_silence == other.chapters_from_silence
             && self.allow_symlinks == other.allow_symlinks
             && self.no_dir_collaps == other.no_dir_collaps
             && self.tags == other.tags
-            && self.cd_folder_regex_str == other.cd_folder_regex_str;
+            && self.cd_folder_regex_str == other.cd_folder_regex_str
+            && self.cover_sources == other.cover_sources
+            && self.cover_names == other.cover_names
+            && self.ignore_patterns == other.ignore_patterns
+            && self.roots == other.roots;
 
         #[cfg(feature = "tags-encoding")]
         let res = res && self.tags_encoding == other.tags_encoding;
@@ -78,6 +168,7 @@ impl Default for CollectionOptions {
             chapters_duration: 0,
             chapters_from_duration: 30,
             ignore_chapters_meta: false,
+            chapters_from_silence: false,
             allow_symlinks: false,
             no_dir_collaps: false,
             natural_files_ordering: true,
@@ -89,8 +180,17 @@ impl Default for CollectionOptions {
             passive_init: false,
             time_to_end_of_folder: 10,
             read_playlists: false,
+            cover_sources: vec![],
+            cover_names: vec![],
+            ignore_patterns: vec![],
+            roots: vec![],
             watch_for_changes: true,
             changes_debounce_interval: 10,
+            scan_threads: 1,
+            scan_io_delay_ms: 0,
+            poll_interval: 300,
+            read_only: false,
+            public: false,
         }
     }
 }
@@ -123,9 +223,12 @@ impl CollectionOptions {
                     "nc" | "no-cache" => self.no_cache = bool_val()?,
                     "force-cache-update" => self.force_cache_update_on_init = bool_val()?,
                     "ignore-chapters-meta" => self.ignore_chapters_meta = bool_val()?,
+                    "chapters-from-silence" => self.chapters_from_silence = bool_val()?,
                     "allow-symlinks" => self.allow_symlinks = bool_val()?,
                     "no-dir-collaps" => self.no_dir_collaps = bool_val()?,
                     "read-playlist" => self.read_playlists = bool_val()?,
+                    "ro" | "read-only" => self.read_only = bool_val()?,
+                    "public" => self.public = bool_val()?,
                     "chapters-duration" => {
                         let val = u32_val()?;
                         if val < MINIMUM_CHAPTER_DURATION {
@@ -158,6 +261,63 @@ impl CollectionOptions {
                             invalid_option!("Some tags are required for {}", tag);
                         }
                     }
+                    "cover-sources" => {
+                        if let Some(sources) = val {
+                            let mut cover_sources = Vec::new();
+                            for source in sources.split('+').map(CoverSource::from_str) {
+                                let source = source?;
+                                if !cover_sources.contains(&source) {
+                                    cover_sources.push(source)
+                                }
+                            }
+                            self.cover_sources = cover_sources;
+                        } else {
+                            invalid_option!("Some cover sources are required for {}", tag);
+                        }
+                    }
+                    "cover-names" => {
+                        if let Some(names) = val {
+                            self.cover_names = names
+                                .split('+')
+                                .map(|s| s.trim().to_lowercase())
+                                .filter(|s| !s.is_empty())
+                                .collect();
+                        } else {
+                            invalid_option!("Some file names are required for {}", tag);
+                        }
+                    }
+                    "ignore" => {
+                        if let Some(patterns) = val {
+                            for pattern in patterns.split('+').map(|s| s.trim()) {
+                                if let Err(e) = check_pattern(pattern) {
+                                    invalid_option!(
+                                        "Invalid pattern {} for {}: {}",
+                                        pattern,
+                                        tag,
+                                        e
+                                    );
+                                }
+                                if !pattern.is_empty() {
+                                    self.ignore_patterns.push(pattern.to_string());
+                                }
+                            }
+                        } else {
+                            invalid_option!("Some patterns are required for {}", tag);
+                        }
+                    }
+                    "roots" => {
+                        if let Some(roots) = val {
+                            for root in roots.split('+').map(|s| s.trim()) {
+                                let root = PathBuf::from(root);
+                                if !root.is_dir() {
+                                    invalid_option!("Root {:?} for {} is not directory", root, tag);
+                                }
+                                self.roots.push(root);
+                            }
+                        } else {
+                            invalid_option!("Some directories are required for {}", tag);
+                        }
+                    }
                     "default-tags" => {
                         if bool_val()? {
                             self.tags = Some(BASIC_TAGS.iter().map(|i| i.to_string()).collect())
@@ -193,6 +353,29 @@ impl CollectionOptions {
                     "changes-debounce" | "changes-debounce-interval" | "debounce-interval" => {
                         self.changes_debounce_interval = u32_val()?
                     }
+                    "poll-interval" | "changes-poll-interval" => self.poll_interval = u32_val()?,
+                    "scan-threads" => {
+                        let val = u32_val()?;
+                        if !(1..=MAX_SCAN_THREADS).contains(&val) {
+                            invalid_option!(
+                                "Option {} must be between 1 and {}",
+                                tag,
+                                MAX_SCAN_THREADS
+                            );
+                        }
+                        self.scan_threads = val;
+                    }
+                    "scan-io-delay" => {
+                        let val = u32_val()?;
+                        if val > MAX_SCAN_IO_DELAY {
+                            invalid_option!(
+                                "Option {} is too big, max is {}ms",
+                                tag,
+                                MAX_SCAN_IO_DELAY
+                            );
+                        }
+                        self.scan_io_delay_ms = val;
+                    }
 
                     opt => invalid_option!("Unknown option: {}", opt),
                 }
@@ -232,12 +415,108 @@ impl CollectionOptionsMap {
     }
 
     pub fn get_col_options(&mut self, path: impl AsRef<Path>) -> CollectionOptions {
-        self.cols
+        let mut opt = self
+            .cols
             .remove(path.as_ref())
-            .unwrap_or_else(|| self.default.clone())
+            .unwrap_or_else(|| self.default.clone());
+        opt.ignore_patterns.extend(read_ignore_file(path.as_ref()));
+        opt
     }
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub enum ScanState {
+    #[default]
+    Idle,
+    Running,
+    /// collection is not cached, so there is nothing to scan
+    NotCached,
+}
+
+/// How changes in collection are detected
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub enum WatchMode {
+    /// changes are not detected, only on explicit rescan
+    #[default]
+    Disabled,
+    /// file system notifications
+    Watching,
+    /// periodic scan for modified folders, when notifications are not available
+    Polling,
+}
+
+/// State and progress of recursive scan of collection
+#[derive(Clone, Debug, Default, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct ScanStatus {
+    pub state: ScanState,
+    pub watch_mode: WatchMode,
+    /// full scan updates all folders, otherwise only changed folders are updated
+    pub full: bool,
+    pub folders_scanned: u64,
+    pub folders_queued: u64,
+    pub started: Option<TimeStamp>,
+    pub finished: Option<TimeStamp>,
+    /// collection directory is not available, collection is served only from cache
+    pub degraded: bool,
+}
+
+/// Number of audio files of one format and their playback time
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct FormatStats {
+    pub files: u64,
+    /// total playback time in seconds
+    pub total_time: u64,
+}
+
+/// Statistics of cached collection content, they are maintained as cache is updated
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct CollectionStats {
+    pub folders: u64,
+    /// audio files, chapterized file is counted once
+    pub files: u64,
+    /// total size of audio files in bytes
+    pub total_size: u64,
+    /// total playback time in seconds
+    pub total_time: u64,
+    /// breakdown of audio files by their mime type
+    pub formats: BTreeMap<String, FormatStats>,
+    /// when last scan of collection finished (since server start)
+    pub last_scan: Option<TimeStamp>,
+}
+
+/// Author in library view, built from folders tags
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct LibraryAuthor {
+    pub name: String,
+    /// number of folders of this author
+    pub folders: usize,
+}
+
+/// Folder in library view
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct LibraryFolder {
+    pub collection: usize,
+    #[serde(flatten)]
+    pub folder: AudioFolderShort,
+}
+
+/// Folders of the author in same series, folders not belonging to any series have no name
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct LibrarySeries {
+    pub name: Option<String>,
+    pub folders: Vec<LibraryFolder>,
+}
+
 #[enum_dispatch(CollectionTrait, PositionsTrait)]
 pub(crate) enum Collection {
     CollectionCache,
@@ -263,6 +542,11 @@ pub(crate) trait PositionsTrait {
         S: AsRef<str>,
         P: AsRef<str>;
 
+    fn set_folder_state<S, P>(&self, group: S, folder: P, state: FolderState) -> Result<()>
+    where
+        S: AsRef<str>,
+        P: AsRef<str>;
+
     fn get_position<S, P>(&self, group: S, folder: Option<P>) -> Option<Position>
     where
         S: AsRef<str>,
@@ -290,6 +574,26 @@ pub(crate) trait PositionsTrait {
 
     #[allow(dead_code)] // workaround for more thorough analysis of dead code in new rustc
     fn read_json_positions(&self, data: PositionsData) -> Result<()>;
+
+    /// Removes positions of folders, which are not in collection, and positions of groups,
+    /// which were not updated for longer then group's maximum age.
+    /// In dry run nothing is removed, only positions, which would be removed, are returned
+    fn prune_positions(
+        &self,
+        collection_no: usize,
+        max_age: &dyn Fn(&str) -> Option<Duration>,
+        dry_run: bool,
+    ) -> Result<Vec<PrunedPosition>>;
+
+    /// Groups, which have positions in this collection
+    fn groups(&self) -> Vec<GroupInfo>;
+
+    /// Moves all positions of group to new group name, if new group already has position in a folder,
+    /// newer position is kept. Returns number of folders with moved positions
+    fn rename_group(&self, from: &str, to: &str) -> Result<usize>;
+
+    /// Removes all positions of group, returns number of folders with removed positions
+    fn delete_group(&self, group: &str) -> Result<usize>;
 }
 
 #[enum_dispatch]
@@ -311,9 +615,40 @@ pub trait CollectionTrait {
 
     fn recent(&self, limit: usize, group: Option<String>) -> Vec<AudioFolderShort>;
 
-    fn signal_rescan(&self);
+    /// Starts full rescan of collection, returns false if rescan cannot be started (e.g. is already running)
+    fn signal_rescan(&self) -> bool;
+
+    fn scan_status(&self) -> ScanStatus;
+
+    /// Content statistics, available only for cached collection
+    fn stats(&self) -> Option<CollectionStats>;
+
+    /// Authors with number of their folders, as found in folders tags,
+    /// only folders passing folder_allowed are counted
+    fn library_authors(&self, folder_allowed: &dyn Fn(&Path) -> bool) -> Vec<LibraryAuthor>;
+
+    /// Folders of the author together with series name (if folder belongs to series)
+    fn library_folders(&self, author: &str) -> Vec<(Option<String>, AudioFolderShort)>;
 
     fn base_dir(&self) -> &Path;
+
+    /// Full path of file or folder, collection can merge several root directories
+    fn full_path(&self, rel_path: &Path) -> PathBuf;
+
+    /// Reloads folder from file system, after its files were changed by audioserve itself
+    fn refresh_folder(&self, dir_path: &Path) -> Result<()>;
+
+    /// Moves folder into trash directory
+    fn trash_folder(&self, dir_path: &Path) -> Result<()>;
+
+    /// Moves folder from trash directory back to its original place
+    fn restore_folder(&self, dir_path: &Path) -> Result<()>;
+
+    /// Sets listener, which is notified when folder is removed from collection
+    fn set_removal_listener(&self, listener: RemovalListener);
+
+    /// Sets listener, which is notified about changes in collection
+    fn set_change_listener(&self, listener: ChangeListener);
 }
 
 #[cfg(test)]
@@ -334,9 +669,43 @@ mod tests {
             .expect("valid tags");
         assert_eq!(3, opt.tags.as_ref().unwrap().len());
 
-        opt.update_from_str_options("chapters-duration=44,chapters-from-duration=200")
-            .expect("correct options");
+        opt.update_from_str_options(
+            "chapters-duration=44,chapters-from-duration=200,chapters-from-silence",
+        )
+        .expect("correct options");
         assert_eq!(44, opt.chapters_duration);
         assert_eq!(200, opt.chapters_from_duration);
+        assert!(opt.chapters_from_silence);
+
+        assert!(opt.cover_sources.is_empty());
+        opt.update_from_str_options("cover-sources=parent+file+parent,cover-names=Folder+front")
+            .expect("correct options");
+        assert_eq!(
+            vec![CoverSource::Parent, CoverSource::File],
+            opt.cover_sources
+        );
+        assert_eq!(vec!["folder", "front"], opt.cover_names);
+        opt.update_from_str_options("ignore=@eaDir+*.nfo")
+            .expect("correct options");
+        assert_eq!(vec!["@eaDir", "*.nfo"], opt.ignore_patterns);
+        assert!(opt.update_from_str_options("ignore=[invalid").is_err());
+        assert!(opt
+            .update_from_str_options("cover-sources=file+whatever")
+            .is_err());
+
+        opt.update_from_str_options("scan-threads=4,scan-io-delay=50")
+            .expect("correct options");
+        assert_eq!(4, opt.scan_threads);
+        assert_eq!(50, opt.scan_io_delay_ms);
+        assert!(opt.update_from_str_options("scan-threads=0").is_err());
+
+        opt.update_from_str_options("poll-interval=0,read-only")
+            .expect("correct options");
+        assert_eq!(0, opt.poll_interval);
+        assert!(opt.read_only);
+        assert!(!opt.public);
+        opt.update_from_str_options("public")
+            .expect("correct options");
+        assert!(opt.public);
     }
 }
@@ -0,0 +1,146 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::audio_meta::Chapter;
+
+/// Cue sheet for audio file is either `file.flac.cue` or `file.cue`
+pub(crate) fn cue_file_path(path: &Path) -> Option<PathBuf> {
+    let mut name = path.file_name()?.to_owned();
+    name.push(".cue");
+    [path.with_file_name(name), path.with_extension("cue")]
+        .into_iter()
+        .find(|p| p.is_file())
+}
+
+/// Chapters from cue sheet of the audio file, duration of the file is in seconds
+pub(crate) fn chapters_from_cue(path: &Path, duration: u32) -> io::Result<Option<Vec<Chapter>>> {
+    match cue_file_path(path) {
+        Some(cue_file) => {
+            // cue sheets are often in legacy encodings, so rather lossy then nothing
+            let data = fs::read(&cue_file)?;
+            let content = String::from_utf8_lossy(&data);
+            let chapters = parse_cue(&content, u64::from(duration) * 1000);
+            if chapters.is_none() {
+                warn!(
+                    "Cue sheet {:?} is not usable - it must reference exactly one audio file",
+                    cue_file
+                );
+            }
+            Ok(chapters)
+        }
+        None => Ok(None),
+    }
+}
+
+/// INDEX time is mm:ss:ff where ff are frames (75 per second)
+fn ms_from_cue_time(t: &str) -> Option<u64> {
+    let mut parts = t.split(':').map(str::parse::<u64>);
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(m)), Some(Ok(s)), Some(Ok(f)), None) => Some((m * 60 + s) * 1000 + f * 1000 / 75),
+        _ => None,
+    }
+}
+
+fn unquote(s: &str) -> &str {
+    let s = s.trim();
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+fn parse_cue(content: &str, total_ms: u64) -> Option<Vec<Chapter>> {
+    let mut files = 0;
+    // (title, start)
+    let mut tracks: Vec<(Option<String>, Option<u64>)> = vec![];
+    for line in content.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        let (cmd, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match cmd.to_ascii_uppercase().as_str() {
+            "FILE" => files += 1,
+            "TRACK" => tracks.push((None, None)),
+            "TITLE" => {
+                if let Some(track) = tracks.last_mut() {
+                    track.0 = Some(unquote(args).to_string())
+                }
+            }
+            "INDEX" => {
+                if let (Some(track), Some(("01", time))) = (
+                    tracks.last_mut(),
+                    args.trim().split_once(char::is_whitespace),
+                ) {
+                    track.1 = ms_from_cue_time(time.trim())
+                }
+            }
+            _ => (),
+        }
+    }
+    if files != 1 {
+        return None;
+    }
+
+    let starts: Vec<_> = tracks
+        .into_iter()
+        .filter_map(|(title, start)| start.map(|s| (title, s)))
+        .collect();
+    let chapters = starts
+        .iter()
+        .enumerate()
+        .map(|(number, (title, start))| {
+            let end = starts.get(number + 1).map(|n| n.1).unwrap_or(total_ms);
+            Chapter {
+                title: title
+                    .clone()
+                    .unwrap_or_else(|| format!("Track {:02}", number + 1)),
+                number: number as u32,
+                start: *start,
+                end,
+                ..Default::default()
+            }
+        })
+        .filter(|c| c.end > c.start)
+        .collect();
+    Some(chapters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUE: &str = r#"REM GENRE Audiobook
+PERFORMER "Author"
+TITLE "Book"
+FILE "book.flac" WAVE
+  TRACK 01 AUDIO
+    TITLE "Chapter One"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Chapter Two"
+    INDEX 00 10:01:00
+    INDEX 01 10:02:37
+  TRACK 03 AUDIO
+    INDEX 01 20:00:00
+"#;
+
+    #[test]
+    fn test_cue_time() {
+        assert_eq!(Some(602_493), ms_from_cue_time("10:02:37"));
+        assert_eq!(None, ms_from_cue_time("10:02"));
+    }
+
+    #[test]
+    fn test_parse_cue() {
+        let chapters = parse_cue(CUE, 30 * 60 * 1000).unwrap();
+        assert_eq!(3, chapters.len());
+        assert_eq!("Chapter One", chapters[0].title);
+        assert_eq!(0, chapters[0].start);
+        assert_eq!(602_493, chapters[0].end);
+        assert_eq!("Chapter Two", chapters[1].title);
+        assert_eq!(602_493, chapters[1].start);
+        assert_eq!("Track 03", chapters[2].title);
+        assert_eq!(30 * 60 * 1000, chapters[2].end);
+
+        let multi = CUE.to_string() + "FILE \"other.flac\" WAVE\n";
+        assert!(parse_cue(&multi, 1000).is_none());
+    }
+}
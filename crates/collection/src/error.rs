@@ -39,12 +39,24 @@ pub enum Error {
     #[error("Too many position groups")]
     TooManyGroups,
 
+    #[error("Settings too large: {0} bytes")]
+    SettingsTooLarge(usize),
+
+    #[error("Settings store is not available")]
+    SettingsUnavailable,
+
     #[error("Invalid path: {0}")]
     InvalidPathPrefix(#[from] StripPrefixError),
 
     #[error("Position cannot be inserted")]
     IgnoredPosition,
 
+    #[error("Collection is read only")]
+    ReadOnlyCollection,
+
+    #[error("Collection directory is not available")]
+    CollectionUnavailable,
+
     #[error("JSON error: {0}")]
     JsonError(#[from] serde_json::Error),
 
@@ -57,6 +69,12 @@ pub enum Error {
     #[error("Invalid collection option: {0}")]
     InvalidCollectionOption(String),
 
+    #[error("Folder not found: {0:?}")]
+    FolderNotFound(std::path::PathBuf),
+
+    #[error("Path already exists: {0:?}")]
+    AlreadyExists(std::path::PathBuf),
+
     #[error("Invalid regex for CD folder: {0} {1}")]
     InvalidCDFolderRegex(String, regex::Error),
 }
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use media_info::tags::{ALBUM, ARTIST, SERIES, SERIES_SEQUENCE};
+use serde::Deserialize;
+
+use crate::audio_meta::{is_cover, AudioFolder, TypedFile};
+
+const FOLDER_META_FILES: &[&str] = &["folder.yaml", "audioserve.yaml"];
+
+pub fn is_folder_meta(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| FOLDER_META_FILES.contains(&n))
+        .unwrap_or(false)
+}
+
+/// Path must be relative and must stay within folder
+fn is_valid_path(p: &Path) -> bool {
+    p.components().next().is_some() && p.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Metadata overrides for the folder, read from folder.yaml (or audioserve.yaml) file in the folder
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FolderMeta {
+    title: Option<String>,
+    author: Option<String>,
+    series: Option<String>,
+    series_sequence: Option<String>,
+    /// cover image path relative to the folder
+    cover: Option<PathBuf>,
+    /// files paths (relative to the folder) in reading order, files not listed here follow in usual order
+    order: Vec<PathBuf>,
+    /// any other folder tags
+    tags: HashMap<String, String>,
+}
+
+impl FolderMeta {
+    pub fn new(file: impl AsRef<Path>) -> Result<Self, io::Error> {
+        let f = File::open(file)?;
+        serde_yaml::from_reader(f).map_err(io::Error::other)
+    }
+
+    /// Merges metadata over those extracted from audio files,
+    /// base_dir is collection root and full_path is full path of the folder
+    pub fn apply(self, folder: &mut AudioFolder, base_dir: &Path, full_path: &Path) {
+        let mut tags = self.tags;
+        for (name, value) in [
+            (ALBUM, self.title),
+            (ARTIST, self.author),
+            (SERIES, self.series),
+            (SERIES_SEQUENCE, self.series_sequence),
+        ] {
+            if let Some(value) = value {
+                tags.insert(name.to_string(), value);
+            }
+        }
+        if !tags.is_empty() {
+            folder.tags.get_or_insert_with(HashMap::new).extend(tags);
+        }
+
+        if let Some(cover) = self.cover {
+            let cover_path = full_path.join(&cover);
+            if is_valid_path(&cover) && is_cover(&cover) && cover_path.is_file() {
+                if let Ok(p) = cover_path.strip_prefix(base_dir) {
+                    folder.cover = Some(TypedFile::new(p))
+                }
+            } else {
+                warn!(
+                    "Invalid cover {:?} in folder metadata for {:?}",
+                    cover, full_path
+                );
+            }
+        }
+
+        if !self.order.is_empty() {
+            let folder_path = full_path.strip_prefix(base_dir).unwrap_or(full_path);
+            let position = |p: &Path| {
+                p.strip_prefix(folder_path)
+                    .ok()
+                    .and_then(|p| self.order.iter().position(|o| o == p))
+                    .unwrap_or(usize::MAX)
+            };
+            // stable sort keeps original order of files not listed
+            folder.files.sort_by_key(|f| position(&f.path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_meta::AudioFile;
+
+    fn file(path: &str) -> AudioFile {
+        AudioFile {
+            name: path.into(),
+            path: path.into(),
+            meta: None,
+            section: None,
+            mime: "audio/mpeg".into(),
+        }
+    }
+
+    #[test]
+    fn test_detection() {
+        assert!(is_folder_meta("author/book/folder.yaml"));
+        assert!(is_folder_meta("audioserve.yaml"));
+        assert!(!is_folder_meta("book/other.yaml"));
+    }
+
+    #[test]
+    fn test_apply() {
+        let meta: FolderMeta = serde_yaml::from_str(
+            r#"
+title: The Invisible Man
+author: H. G. Wells
+tags:
+  genre: Sci-Fi
+cover: ../cover.jpg
+order:
+  - 03.mp3
+  - 01.mp3
+"#,
+        )
+        .unwrap();
+        let mut folder = AudioFolder {
+            is_file: false,
+            is_collapsed: false,
+            modified: None,
+            total_time: None,
+            total_size: None,
+            files: vec![
+                file("book/01.mp3"),
+                file("book/02.mp3"),
+                file("book/03.mp3"),
+            ],
+            subfolders: vec![],
+            cover: None,
+            description: None,
+            position: None,
+            tags: None,
+        };
+        meta.apply(&mut folder, Path::new("/base"), Path::new("/base/book"));
+        let tags = folder.tags.unwrap();
+        assert_eq!("The Invisible Man", tags[ALBUM]);
+        assert_eq!("H. G. Wells", tags[ARTIST]);
+        assert_eq!("Sci-Fi", tags["genre"]);
+        assert!(folder.cover.is_none());
+        let order: Vec<_> = folder.files.iter().map(|f| f.path.clone()).collect();
+        assert_eq!(
+            vec![
+                PathBuf::from("book/03.mp3"),
+                PathBuf::from("book/01.mp3"),
+                PathBuf::from("book/02.mp3")
+            ],
+            order
+        );
+    }
+}
@@ -0,0 +1,139 @@
+//! Files and folders ignored in collection - glob patterns from collection option `ignore`
+//! and from `.asignore` file in collection root (one pattern per line, `#` starts comment).
+//! Pattern without `/` matches name of file or folder anywhere in collection,
+//! pattern with `/` matches path relative to collection root. Content of ignored folder is also ignored.
+
+use glob::{MatchOptions, Pattern, PatternError};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const IGNORE_FILE: &str = ".asignore";
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+pub(crate) fn check_pattern(pattern: &str) -> Result<(), PatternError> {
+    Pattern::new(pattern).map(|_| ())
+}
+
+/// Patterns from ignore file in collection root, invalid patterns are skipped
+pub(crate) fn read_ignore_file(base_dir: &Path) -> Vec<String> {
+    let path = base_dir.join(IGNORE_FILE);
+    let content = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                error!("Cannot read ignore file {:?}: {}", path, e);
+            }
+            return vec![];
+        }
+    };
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter(|l| match check_pattern(l) {
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Invalid pattern {} in {:?}: {}", l, path, e);
+                false
+            }
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct IgnorePatterns {
+    names: Vec<Pattern>,
+    paths: Vec<Pattern>,
+}
+
+impl IgnorePatterns {
+    pub(crate) fn new<S: AsRef<str>>(patterns: &[S]) -> Self {
+        let mut res = IgnorePatterns::default();
+        for p in patterns {
+            let p = p.as_ref().trim().trim_end_matches('/');
+            let (p, anchored) = match p.strip_prefix('/') {
+                Some(p) => (p, true),
+                None => (p, false),
+            };
+            if p.is_empty() {
+                continue;
+            }
+            match Pattern::new(p) {
+                Ok(pattern) if anchored || p.contains('/') => res.paths.push(pattern),
+                Ok(pattern) => res.names.push(pattern),
+                Err(e) => warn!("Invalid ignore pattern {}: {}", p, e),
+            }
+        }
+        res
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.paths.is_empty()
+    }
+
+    /// Path is relative to collection root
+    pub(crate) fn is_ignored(&self, path: &Path) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let mut prefix = PathBuf::new();
+        for component in path.components() {
+            prefix.push(component);
+            let name = component.as_os_str().to_string_lossy();
+            if self
+                .names
+                .iter()
+                .any(|p| p.matches_with(&name, MATCH_OPTIONS))
+                || self
+                    .paths
+                    .iter()
+                    .any(|p| p.matches_path_with(&prefix, MATCH_OPTIONS))
+            {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_patterns() {
+        let ignore =
+            IgnorePatterns::new(&["@eaDir", ".st*", "*.nfo", "/samples/", "author/*/extra"]);
+        assert!(ignore.is_ignored(Path::new("@eaDir")));
+        assert!(ignore.is_ignored(Path::new("author/book/@eaDir/thumb.jpg")));
+        assert!(ignore.is_ignored(Path::new(".stfolder")));
+        assert!(ignore.is_ignored(Path::new("author/book/info.nfo")));
+        assert!(ignore.is_ignored(Path::new("samples/01.mp3")));
+        assert!(!ignore.is_ignored(Path::new("author/samples/01.mp3")));
+        assert!(ignore.is_ignored(Path::new("author/book/extra/01.mp3")));
+        assert!(!ignore.is_ignored(Path::new("author/book/cd/extra")));
+        assert!(!ignore.is_ignored(Path::new("author/book/01.mp3")));
+        assert!(!IgnorePatterns::new::<&str>(&[]).is_ignored(Path::new("@eaDir")));
+    }
+
+    #[test]
+    fn test_read_ignore_file() {
+        let tmp_dir = tempdir::TempDir::new("AS_IGNORE_TEST").unwrap();
+        assert!(read_ignore_file(tmp_dir.path()).is_empty());
+        fs::write(
+            tmp_dir.path().join(IGNORE_FILE),
+            "# Synology\n@eaDir\n\n  .stfolder  \n[invalid\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["@eaDir".to_string(), ".stfolder".to_string()],
+            read_ignore_file(tmp_dir.path())
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! Import of listening progress from other audiobook servers (Audiobookshelf, Booksonic).
+//! Their paths are matched fuzzily onto audioserve collections, as libraries are often mounted
+//! on different paths or file names were slightly normalized.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{audio_meta::TimeStamp, error::Result, position::PositionItem};
+
+/// Positions per collection, folder and group
+pub(crate) type CollectionsPositions =
+    HashMap<usize, HashMap<String, HashMap<String, PositionItem>>>;
+
+/// Adds position, if there is already position for the folder and group, newer one is kept
+pub(crate) fn add_position(
+    positions: &mut CollectionsPositions,
+    collection: usize,
+    folder: String,
+    group: String,
+    item: PositionItem,
+) {
+    positions
+        .entry(collection)
+        .or_default()
+        .entry(folder)
+        .or_default()
+        .entry(group)
+        .and_modify(|e| {
+            if item.timestamp > e.timestamp {
+                *e = item.clone();
+            }
+        })
+        .or_insert(item);
+}
+
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Matches foreign paths to audioserve collections
+pub(crate) struct PathMatcher<'a> {
+    base_dirs: &'a [PathBuf],
+}
+
+impl<'a> PathMatcher<'a> {
+    pub(crate) fn new(base_dirs: &'a [PathBuf]) -> Self {
+        PathMatcher { base_dirs }
+    }
+
+    fn find_entry(dir: &Path, name: &str) -> Option<String> {
+        if dir.join(name).exists() {
+            return Some(name.to_string());
+        }
+        let normalized = normalize_name(name);
+        if normalized.is_empty() {
+            return None;
+        }
+        std::fs::read_dir(dir)
+            .ok()?
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .find(|n| normalize_name(n) == normalized)
+    }
+
+    fn find_in_collection(base_dir: &Path, components: &[&str]) -> Option<Vec<String>> {
+        let mut dir = base_dir.to_path_buf();
+        let mut found = Vec::with_capacity(components.len());
+        for c in components {
+            let name = Self::find_entry(&dir, c)?;
+            dir.push(&name);
+            found.push(name);
+        }
+        Some(found)
+    }
+
+    /// Finds collection and path relative to its root for foreign path.
+    /// Path is matched component by component (ignoring case and non alphanumeric characters),
+    /// leading components are skipped until match is found, so absolute paths can be matched as well.
+    pub(crate) fn find(&self, path: &str) -> Option<(usize, String)> {
+        let components: Vec<_> = path
+            .split(['/', '\\'])
+            .filter(|c| !c.is_empty() && *c != ".")
+            .collect();
+        for start in 0..components.len() {
+            for (collection, base_dir) in self.base_dirs.iter().enumerate() {
+                if let Some(found) = Self::find_in_collection(base_dir, &components[start..]) {
+                    return Some((collection, found.join("/")));
+                }
+            }
+        }
+        None
+    }
+
+    /// Finds file and returns collection, folder and file name
+    fn find_file(&self, path: &str) -> Option<(usize, String, String)> {
+        let (collection, path) = self.find(path)?;
+        let (folder, file) = path.rsplit_once('/').unwrap_or(("", path.as_str()));
+        Some((collection, folder.to_string(), file.to_string()))
+    }
+}
+
+// Booksonic (and other Subsonic servers) - bookmarks as returned by getBookmarks API call in JSON format
+
+#[derive(Deserialize)]
+struct BooksonicExport {
+    #[serde(rename = "subsonic-response")]
+    response: BooksonicResponse,
+}
+
+#[derive(Deserialize)]
+struct BooksonicResponse {
+    bookmarks: BooksonicBookmarks,
+}
+
+#[derive(Deserialize)]
+struct BooksonicBookmarks {
+    #[serde(default)]
+    bookmark: Vec<BooksonicBookmark>,
+}
+
+#[derive(Deserialize)]
+struct BooksonicBookmark {
+    /// position in milliseconds
+    position: u64,
+    username: String,
+    changed: Option<String>,
+    entry: BooksonicEntry,
+}
+
+#[derive(Deserialize)]
+struct BooksonicEntry {
+    path: String,
+}
+
+fn parse_timestamp(ts: Option<&str>) -> TimeStamp {
+    ts.and_then(|ts| chrono::DateTime::parse_from_rfc3339(ts).ok())
+        .and_then(|ts| u64::try_from(ts.timestamp_millis()).ok())
+        .map(TimeStamp::from)
+        .unwrap_or_else(TimeStamp::now)
+}
+
+/// Imports Booksonic bookmarks, if `group` is not given, user name is used as group
+pub(crate) fn import_booksonic<R: std::io::Read>(
+    reader: R,
+    base_dirs: &[PathBuf],
+    group: Option<&str>,
+) -> Result<CollectionsPositions> {
+    let data: BooksonicExport = serde_json::from_reader(reader)?;
+    let matcher = PathMatcher::new(base_dirs);
+    let mut positions = CollectionsPositions::new();
+    for bookmark in data.response.bookmarks.bookmark {
+        match matcher.find_file(&bookmark.entry.path) {
+            Some((collection, folder, file)) => {
+                let item = PositionItem {
+                    file,
+                    timestamp: parse_timestamp(bookmark.changed.as_deref()),
+                    position: bookmark.position as f32 / 1000.0,
+                    folder_finished: false,
+                };
+                let group = group.map(str::to_string).unwrap_or(bookmark.username);
+                add_position(&mut positions, collection, folder, group, item);
+            }
+            None => warn!("No match for Booksonic path {}", bookmark.entry.path),
+        }
+    }
+    Ok(positions)
+}
+
+// Audiobookshelf - `mediaProgress` as returned by /api/me and `libraryItems` as returned
+// by /api/libraries/{id}/items?minified=0 (`results` of all libraries)
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AbsExport {
+    media_progress: Vec<AbsProgress>,
+    library_items: Vec<AbsItem>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AbsProgress {
+    library_item_id: String,
+    /// position in seconds from start of the book
+    #[serde(default)]
+    current_time: f64,
+    #[serde(default)]
+    is_finished: bool,
+    /// milliseconds from epoch
+    last_update: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AbsItem {
+    id: String,
+    path: String,
+    #[serde(default)]
+    is_file: bool,
+    media: AbsMedia,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AbsMedia {
+    #[serde(default)]
+    audio_files: Vec<AbsAudioFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AbsAudioFile {
+    #[serde(default)]
+    index: usize,
+    #[serde(default)]
+    duration: f64,
+    metadata: AbsFileMetadata,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AbsFileMetadata {
+    rel_path: String,
+}
+
+impl AbsItem {
+    /// Path of audio file (relative to item path) and position in this file for position in whole book
+    fn locate(&self, time: f64) -> Option<(&str, f64)> {
+        let mut files: Vec<_> = self.media.audio_files.iter().collect();
+        files.sort_by_key(|f| f.index);
+        let mut offset = 0.0;
+        for (idx, f) in files.iter().enumerate() {
+            if time < offset + f.duration || idx == files.len() - 1 {
+                return Some((&f.metadata.rel_path, (time - offset).clamp(0.0, f.duration)));
+            }
+            offset += f.duration;
+        }
+        None
+    }
+}
+
+/// Imports Audiobookshelf progress of one user into `group`
+pub(crate) fn import_audiobookshelf<R: std::io::Read>(
+    reader: R,
+    base_dirs: &[PathBuf],
+    group: &str,
+) -> Result<CollectionsPositions> {
+    let data: AbsExport = serde_json::from_reader(reader)?;
+    let items: HashMap<_, _> = data
+        .library_items
+        .iter()
+        .map(|i| (i.id.as_str(), i))
+        .collect();
+    let matcher = PathMatcher::new(base_dirs);
+    let mut positions = CollectionsPositions::new();
+    for progress in data.media_progress {
+        let item = match items.get(progress.library_item_id.as_str()) {
+            Some(item) => item,
+            None => {
+                warn!(
+                    "Missing Audiobookshelf library item {}",
+                    progress.library_item_id
+                );
+                continue;
+            }
+        };
+        let (file, position) = match item.locate(progress.current_time) {
+            Some(l) => l,
+            None => {
+                warn!("No audio files for Audiobookshelf item {}", item.path);
+                continue;
+            }
+        };
+        let path = if item.is_file {
+            item.path.clone()
+        } else {
+            format!("{}/{}", item.path, file)
+        };
+        match matcher.find_file(&path) {
+            Some((collection, folder, file)) => {
+                let item = PositionItem {
+                    file,
+                    timestamp: progress
+                        .last_update
+                        .map(TimeStamp::from)
+                        .unwrap_or_else(TimeStamp::now),
+                    position: position as f32,
+                    folder_finished: progress.is_finished,
+                };
+                add_position(&mut positions, collection, folder, group.to_string(), item);
+            }
+            None => warn!("No match for Audiobookshelf path {}", path),
+        }
+    }
+    Ok(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base_dirs() -> Vec<PathBuf> {
+        vec![PathBuf::from("../../test_data")]
+    }
+
+    #[test]
+    fn test_path_matching() {
+        let dirs = base_dirs();
+        let matcher = PathMatcher::new(&dirs);
+        assert_eq!(
+            Some((0, "usak/kulisak/01-chapter-1.mp3".to_string())),
+            matcher.find("/mnt/books/Usak/Kulisak/01 Chapter 1.mp3")
+        );
+        assert_eq!(
+            Some((0, "usak/kulisak".to_string())),
+            matcher.find("usak\\kulisak")
+        );
+        assert_eq!(None, matcher.find("/mnt/books/usak/other"));
+    }
+
+    #[test]
+    fn test_import_booksonic() {
+        let data = r#"{"subsonic-response":{"status":"ok","bookmarks":{"bookmark":[
+            {"position":12500,"username":"ivan","changed":"2024-03-01T10:00:00.000Z",
+             "entry":{"id":"1","path":"Usak/Kulisak/01-chapter-1.mp3"}}]}}}"#;
+        let res = import_booksonic(data.as_bytes(), &base_dirs(), None).unwrap();
+        let item = &res[&0]["usak/kulisak"]["ivan"];
+        assert_eq!("01-chapter-1.mp3", item.file);
+        assert_eq!(12.5, item.position);
+        assert_eq!(item.timestamp, 1709287200000u64);
+    }
+
+    #[test]
+    fn test_import_audiobookshelf() {
+        let data = r#"{"mediaProgress":[{"libraryItemId":"li_1","currentTime":70.0,
+            "isFinished":false,"lastUpdate":1709287200000}],
+            "libraryItems":[{"id":"li_1","path":"/audiobooks/usak/kulisak","isFile":false,
+            "media":{"audioFiles":[
+                {"index":2,"duration":100.0,"metadata":{"relPath":"02-chapter-2.mp3"}},
+                {"index":1,"duration":60.0,"metadata":{"relPath":"01-chapter-1.mp3"}}]}}]}"#;
+        // only first file exists in test data, so second is not matched
+        let res = import_audiobookshelf(data.as_bytes(), &base_dirs(), "ivan").unwrap();
+        assert!(res.is_empty());
+
+        let data = data.replace("\"currentTime\":70.0", "\"currentTime\":30.0");
+        let res = import_audiobookshelf(data.as_bytes(), &base_dirs(), "ivan").unwrap();
+        let item = &res[&0]["usak/kulisak"]["ivan"];
+        assert_eq!("01-chapter-1.mp3", item.file);
+        assert_eq!(30.0, item.position);
+    }
+}
@@ -1,31 +1,47 @@
 #[macro_use]
 extern crate log;
 
-pub use audio_folder::{list_dir_files_only, list_dir_files_with_subdirs, parse_chapter_path};
+pub use audio_folder::{
+    list_dir_files_only, list_dir_files_recursive, list_dir_files_with_subdirs, parse_chapter_path,
+    sort_files,
+};
 pub use audio_meta::{
-    extract_cover, extract_description, init_media_lib, AudioFile, AudioFolderShort,
-    FoldersOrdering, TimeSpan,
+    extract_audio_codec, extract_cbr_layout, extract_cover, extract_description, extract_duration,
+    init_media_lib, AudioFile, AudioFolderShort, FilesOrdering, FoldersOrdering, TimeSpan,
 };
 use audio_meta::{AudioFolder, TimeStamp};
 use cache::CollectionCache;
+pub use common::{
+    ChangeListener, CollectionChange, CollectionOptions, CollectionOptionsMap, CollectionStats,
+    CoverSource, FormatStats, LibraryAuthor, LibraryFolder, LibrarySeries, RemovalListener,
+    ScanState, ScanStatus, WatchMode,
+};
 use common::{Collection, CollectionTrait, PositionsTrait};
-pub use common::{CollectionOptions, CollectionOptionsMap};
 use error::{Error, Result};
 use legacy_pos::LegacyPositions;
 pub use media_info::tags;
+pub use media_info::ThreadConfined;
+#[cfg(feature = "tags-write")]
+pub use media_info::{
+    write::{update_tags, TagsUpdate},
+    Error as MediaInfoError,
+};
 use no_cache::CollectionDirect;
-pub use position::{Position, PositionFilter};
+pub use position::{FolderState, GroupInfo, Position, PositionFilter, PruneReason, PrunedPosition};
 use serde_json::{Map, Value};
+use settings::Settings;
+pub use settings::MAX_SETTINGS_SIZE;
 #[cfg(feature = "async")]
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fs::{File, Metadata, OpenOptions},
     io::{Read, Write},
     path::{Path, PathBuf},
     thread::JoinHandle,
 };
-pub use util::guess_mime_type;
+pub use text::extract_text;
+pub use util::{guess_mime_type, resolve_root};
 
 use crate::{
     common::PositionsData,
@@ -34,19 +50,28 @@ use crate::{
 
 pub mod audio_folder;
 pub mod audio_meta;
+mod backup;
 pub mod cache;
 pub(crate) mod collator;
 pub mod common;
+pub(crate) mod cue;
 pub mod error;
+pub(crate) mod folder_meta;
+pub(crate) mod ignore;
+mod import;
 mod legacy_pos;
 pub(crate) mod no_cache;
 pub(crate) mod playlist;
 pub mod position;
+pub(crate) mod settings;
+pub(crate) mod silence;
+pub mod text;
+pub mod trash;
 pub mod util;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const MAX_POSITIONS: usize = 1_000;
-pub use common::MINIMUM_CHAPTER_DURATION;
+pub use common::{MAX_SCAN_IO_DELAY, MAX_SCAN_THREADS, MINIMUM_CHAPTER_DURATION};
 
 fn check_version<P: AsRef<Path>>(db_dir: P) -> Result<()> {
     let db_dir = db_dir.as_ref();
@@ -66,18 +91,45 @@ fn check_version<P: AsRef<Path>>(db_dir: P) -> Result<()> {
         if !db_dir.exists() {
             std::fs::create_dir_all(db_dir)?
         }
-        let mut f = OpenOptions::new()
+        if let Err(e) = OpenOptions::new()
             .create_new(true)
             .write(true)
-            .open(version_file)?;
-        f.write_all(VERSION.as_bytes())?;
+            .open(&version_file)
+            .and_then(|mut f| f.write_all(VERSION.as_bytes()))
+        {
+            // can be read only collections cache, which is still usable
+            warn!("Cannot write version file {:?}: {}", version_file, e);
+        }
     }
 
     Ok(())
 }
 
+/// Listener of changes in any collection, called with collection number
+type CollectionsChangeListener = Arc<dyn Fn(usize, CollectionChange) + Send + Sync>;
+
 pub struct Collections {
-    caches: Vec<Collection>,
+    // collections can be added at runtime, but never removed, so collection number is stable
+    caches: RwLock<Vec<Arc<Collection>>>,
+    settings: Option<Settings>,
+    db_path: PathBuf,
+    removal_listener: RwLock<Option<RemovalListener>>,
+    change_listener: RwLock<Option<CollectionsChangeListener>>,
+}
+
+fn open_collection(
+    collection_path: PathBuf,
+    db_path: &Path,
+    opt: CollectionOptions,
+) -> Result<Collection> {
+    if opt.no_cache {
+        info!("Collection {:?} is not using cache", collection_path);
+        Ok(CollectionDirect::new(collection_path, opt).into())
+    } else {
+        CollectionCache::new(collection_path, db_path, opt)
+            .map(|cache| cache.init())
+            .map(Collection::from)
+    }
 }
 
 impl Collections {
@@ -95,26 +147,64 @@ impl Collections {
         let db_path = db_path.as_ref();
         let caches = collections_dirs
             .into_iter()
-            .map(move |collection_path| {
+            .map(|collection_path| {
                 let opt = collections_options.get_col_options(&collection_path);
-                if opt.no_cache {
-                    info!("Collection {:?} is not using cache", collection_path);
-                    Ok(CollectionDirect::new(collection_path, opt).into())
-                } else {
-                    CollectionCache::new(collection_path, db_path, opt)
-                        .map(|cache| cache.init())
-                        .map(Collection::from)
-                }
+                open_collection(collection_path, db_path, opt).map(Arc::new)
             })
             .collect::<Result<Vec<_>>>()?;
-        Ok(Collections { caches })
+        let settings = Settings::open(db_path)
+            .map_err(|e| error!("Cannot open settings store in {:?}: {}", db_path, e))
+            .ok();
+        Ok(Collections {
+            caches: RwLock::new(caches),
+            settings,
+            db_path: db_path.to_owned(),
+            removal_listener: RwLock::new(None),
+            change_listener: RwLock::new(None),
+        })
+    }
+
+    /// Adds new collection at runtime, returns its number (collections are numbered in order of addition)
+    pub fn add_collection(
+        &self,
+        collection_path: PathBuf,
+        opt: CollectionOptions,
+    ) -> Result<usize> {
+        let collection = open_collection(collection_path, &self.db_path, opt)?;
+        // listeners are locked before caches, same as when they are set, so no collection can miss them
+        let removal_listener = self.removal_listener.read().unwrap();
+        let change_listener = self.change_listener.read().unwrap();
+        let mut caches = self.caches.write().unwrap();
+        let idx = caches.len();
+        if let Some(listener) = removal_listener.as_ref() {
+            collection.set_removal_listener(listener.clone())
+        }
+        if let Some(listener) = change_listener.as_ref() {
+            let listener = listener.clone();
+            collection.set_change_listener(Arc::new(move |change| listener(idx, change)))
+        }
+        caches.push(Arc::new(collection));
+        Ok(idx)
+    }
+
+    /// Number of collections
+    pub fn count(&self) -> usize {
+        self.caches.read().unwrap().len()
+    }
+
+    /// Snapshot of current collections
+    fn caches(&self) -> Vec<Arc<Collection>> {
+        self.caches.read().unwrap().clone()
     }
 }
 
 impl Collections {
-    fn get_cache(&self, collection: usize) -> Result<&Collection> {
+    fn get_cache(&self, collection: usize) -> Result<Arc<Collection>> {
         self.caches
+            .read()
+            .unwrap()
             .get(collection)
+            .cloned()
             .ok_or(Error::MissingCollectionCache(collection))
     }
 
@@ -137,7 +227,7 @@ impl Collections {
         let col = self.get_cache(collection)?;
         col.get_folder_cover_path(dir_path).and_then(|p| {
             p.map(|p| {
-                let path = col.base_dir().join(p);
+                let path = col.full_path(&p);
                 match path.metadata() {
                     Ok(meta) => Ok((path, meta)),
                     Err(e) => Err(e),
@@ -148,9 +238,31 @@ impl Collections {
         })
     }
 
+    /// Blocks until initial scan of all cached collections is finished
+    pub fn wait_for_initial_scan(&self) {
+        for c in self.caches().iter() {
+            if let Collection::CollectionCache(c) = c.as_ref() {
+                c.wait_until_inital_scan_is_done()
+            }
+        }
+    }
+
+    /// True if initial scan of all cached collections is finished, does not block
+    pub fn is_initial_scan_done(&self) -> bool {
+        (0..self.count()).all(|c| self.is_initial_scan_done_for(c))
+    }
+
+    /// True if initial scan of collection is finished (or collection is not cached), does not block
+    pub fn is_initial_scan_done_for(&self, collection: usize) -> bool {
+        match self.get_cache(collection).as_deref() {
+            Ok(Collection::CollectionCache(c)) => c.is_initial_scan_done(),
+            _ => true,
+        }
+    }
+
     pub fn flush(&self) -> Result<()> {
         let mut result = vec![];
-        for c in &self.caches {
+        for c in self.caches().iter() {
             result.push(c.flush())
         }
         result.into_iter().find(|r| r.is_err()).unwrap_or(Ok(()))
@@ -178,6 +290,60 @@ impl Collections {
         self.get_cache(collection)
             .map(|cache| cache.recent(limit, group))
     }
+
+    /// Authors from all allowed collections (counting only allowed folders), sorted by name
+    pub fn authors(
+        &self,
+        allowed: impl Fn(usize) -> bool,
+        folder_allowed: impl Fn(&Path) -> bool,
+    ) -> Vec<LibraryAuthor> {
+        let mut authors: BTreeMap<String, usize> = BTreeMap::new();
+        for author in self
+            .caches()
+            .iter()
+            .enumerate()
+            .filter(|(collection, _)| allowed(*collection))
+            .flat_map(|(_, c)| c.library_authors(&folder_allowed))
+        {
+            *authors.entry(author.name).or_default() += author.folders;
+        }
+        authors
+            .into_iter()
+            .map(|(name, folders)| LibraryAuthor { name, folders })
+            .collect()
+    }
+
+    /// Folders of the author from all allowed collections grouped by series,
+    /// folders without series are first
+    pub fn author_series(
+        &self,
+        author: &str,
+        allowed: impl Fn(usize) -> bool,
+        folder_allowed: impl Fn(&Path) -> bool,
+    ) -> Vec<LibrarySeries> {
+        let mut series: BTreeMap<Option<String>, Vec<LibraryFolder>> = BTreeMap::new();
+        for (collection, c) in self
+            .caches()
+            .iter()
+            .enumerate()
+            .filter(|(collection, _)| allowed(*collection))
+        {
+            for (name, folder) in c
+                .library_folders(author)
+                .into_iter()
+                .filter(|(_, f)| folder_allowed(&f.path))
+            {
+                series
+                    .entry(name)
+                    .or_default()
+                    .push(LibraryFolder { collection, folder });
+            }
+        }
+        series
+            .into_iter()
+            .map(|(name, folders)| LibrarySeries { name, folders })
+            .collect()
+    }
 }
 
 // positions
@@ -220,6 +386,21 @@ impl Collections {
         )
     }
 
+    pub fn set_folder_state<S, P>(
+        &self,
+        collection: usize,
+        group: S,
+        folder: P,
+        state: FolderState,
+    ) -> Result<()>
+    where
+        S: AsRef<str>,
+        P: AsRef<str>,
+    {
+        self.get_cache(collection)?
+            .set_folder_state(group, folder, state)
+    }
+
     pub fn get_position<S, P>(&self, collection: usize, group: S, folder: P) -> Option<Position>
     where
         S: AsRef<str>,
@@ -240,7 +421,7 @@ impl Collections {
         S: AsRef<str>,
     {
         let mut res = None;
-        for c in 0..self.caches.len() {
+        for c in 0..self.count() {
             let cache = self.get_cache(c).expect("cache availavle"); // is safe, because we are iterating over known range
             let pos = cache.get_position::<_, String>(&group, None).map(|mut p| {
                 p.collection = c;
@@ -260,30 +441,253 @@ impl Collections {
     }
 
     pub fn force_rescan(self: std::sync::Arc<Self>) {
-        self.caches.iter().for_each(|c| c.signal_rescan())
+        self.caches().iter().for_each(|c| {
+            c.signal_rescan();
+        })
+    }
+
+    /// Starts full rescan of one collection, returns false if rescan is not possible now
+    pub fn force_rescan_collection(&self, collection: usize) -> Result<bool> {
+        self.get_cache(collection).map(|c| c.signal_rescan())
+    }
+
+    /// Moves folder into trash directory of collection, cache is updated immediately
+    pub fn trash_folder(&self, collection: usize, dir_path: impl AsRef<Path>) -> Result<()> {
+        self.get_cache(collection)?.trash_folder(dir_path.as_ref())
+    }
+
+    /// Restores folder from trash directory of collection to its original place, cache is updated immediately
+    pub fn restore_folder(&self, collection: usize, dir_path: impl AsRef<Path>) -> Result<()> {
+        self.get_cache(collection)?
+            .restore_folder(dir_path.as_ref())
+    }
+
+    /// Sets listener for all collections, which is called with absolute path of each folder
+    /// removed from collection cache (deleted, trashed or renamed)
+    pub fn set_removal_listener(&self, listener: impl Fn(&Path) + Send + Sync + 'static) {
+        let listener: RemovalListener = std::sync::Arc::new(listener);
+        let mut current = self.removal_listener.write().unwrap();
+        self.caches()
+            .iter()
+            .for_each(|c| c.set_removal_listener(listener.clone()));
+        *current = Some(listener);
+    }
+
+    /// Sets listener for all collections, which is called with collection number and change in it -
+    /// folder updated by watcher, position inserted or scan finished
+    pub fn set_change_listener(
+        &self,
+        listener: impl Fn(usize, CollectionChange) + Send + Sync + 'static,
+    ) {
+        let listener: CollectionsChangeListener = std::sync::Arc::new(listener);
+        let mut current = self.change_listener.write().unwrap();
+        self.caches().iter().enumerate().for_each(|(idx, c)| {
+            let listener = listener.clone();
+            c.set_change_listener(std::sync::Arc::new(move |change| listener(idx, change)))
+        });
+        *current = Some(listener);
+    }
+
+    /// Reloads folder in collection cache, should be called after audioserve itself changed files in folder
+    pub fn refresh_folder(&self, collection: usize, dir_path: impl AsRef<Path>) -> Result<()> {
+        self.get_cache(collection)?
+            .refresh_folder(dir_path.as_ref())
+    }
+
+    pub fn scan_status(&self) -> Vec<ScanStatus> {
+        self.caches().iter().map(|c| c.scan_status()).collect()
+    }
+
+    /// Content statistics of collection, None if collection is not cached
+    pub fn collection_stats(&self, collection: usize) -> Result<Option<CollectionStats>> {
+        self.get_cache(collection).map(|c| c.stats())
+    }
+
+    /// Backs up positions to JSON file. If `keep` is set, timestamped backups are created
+    /// next to `backup_file` (e.g. `positions-20240301T030000.json`) and only `keep` latest are retained,
+    /// otherwise `backup_file` itself is rewritten. With `compress` backup is gzipped (and `.gz` is added to its name).
+    pub fn backup_positions<P: Into<PathBuf>>(
+        &self,
+        backup_file: P,
+        keep: Option<usize>,
+        compress: bool,
+    ) -> Result<()> {
+        let base_file: PathBuf = backup_file.into();
+        let fname = backup::backup_file_name(&base_file, keep.is_some(), compress)?;
+        let f = std::fs::File::create(&fname)?;
+        if compress {
+            let mut f = flate2::write::GzEncoder::new(f, flate2::Compression::default());
+            self.write_positions(&mut f)?;
+            f.finish()?;
+        } else {
+            self.write_positions(&mut std::io::BufWriter::new(f))?;
+        }
+        debug!("Positions backed up to {:?}", fname);
+        if let Some(keep) = keep {
+            backup::remove_old_backups(&base_file, keep)?;
+        }
+        Ok(())
     }
 
-    pub fn backup_positions<P: Into<PathBuf>>(&self, backup_file: P) -> Result<()> {
-        let fname: PathBuf = backup_file.into();
-        let mut f = std::fs::File::create(fname)?;
+    /// Writes positions of all collections as JSON, in same format as backup file
+    pub fn write_positions<W: Write>(&self, f: &mut W) -> Result<()> {
         write!(f, "{{")?;
-        for (idx, c) in self.caches.iter().enumerate() {
+        let caches = self.caches();
+        for (idx, c) in caches.iter().enumerate() {
             write!(
                 f,
                 "\"{}\":",
                 c.base_dir().to_str().ok_or(Error::InvalidPath)?
             )?;
-            c.write_json_positions(&mut f)?;
-            if idx < self.caches.len() - 1 {
+            c.write_json_positions(f)?;
+            if idx < caches.len() - 1 {
                 writeln!(f, ",")?;
             } else {
                 writeln!(f)?;
             }
         }
         write!(f, "}}")?;
+        f.flush()?;
         Ok(())
     }
 
+    /// Restores positions from backup data (same format as backup file) into running collections,
+    /// only positions newer then existing ones are inserted. Returns number of restored collections.
+    pub fn restore_positions_live(&self, mut data: Map<String, Value>) -> Result<usize> {
+        let mut restored = 0;
+        for c in self.caches().iter() {
+            let col_data = c.base_dir().to_str().and_then(|path| data.remove(path));
+            match col_data {
+                Some(Value::Object(v)) => match c.read_json_positions(PositionsData::V1(v)) {
+                    Ok(_) => restored += 1,
+                    Err(Error::ReadOnlyCollection) => {
+                        warn!(
+                            "Positions for read only collection {:?} not restored",
+                            c.base_dir()
+                        )
+                    }
+                    Err(e) => return Err(e),
+                },
+                Some(_) => {
+                    return Err(Error::JsonSchemaError(format!(
+                        "Expected object for collection {:?}",
+                        c.base_dir()
+                    )))
+                }
+                None => debug!("No positions to restore for {:?}", c.base_dir()),
+            }
+        }
+        for path in data.keys() {
+            warn!("Positions for unknown collection {} not restored", path);
+        }
+        Ok(restored)
+    }
+
+    /// Position groups found in allowed collections, most recently active first
+    pub fn groups(&self, allowed: impl Fn(usize) -> bool) -> Vec<GroupInfo> {
+        let mut groups: HashMap<String, GroupInfo> = HashMap::new();
+        for info in self
+            .caches()
+            .iter()
+            .enumerate()
+            .filter(|(collection, _)| allowed(*collection))
+            .flat_map(|(_, c)| c.groups())
+        {
+            match groups.get_mut(&info.name) {
+                Some(existing) => {
+                    existing.positions += info.positions;
+                    if info.last_activity > existing.last_activity {
+                        existing.last_activity = info.last_activity;
+                    }
+                }
+                None => {
+                    groups.insert(info.name.clone(), info);
+                }
+            }
+        }
+        let mut groups: Vec<_> = groups.into_values().collect();
+        groups.sort_unstable_by_key(|g| std::cmp::Reverse(g.last_activity));
+        groups
+    }
+
+    /// Renames position group in all collections (read only collections are skipped) and moves its settings,
+    /// returns number of folders with renamed positions
+    pub fn rename_group(&self, from: &str, to: &str) -> Result<usize> {
+        let count = self.for_each_writable(|c| c.rename_group(from, to))?;
+        if let Some(settings) = self.settings.as_ref() {
+            settings.rename(from, to)?;
+        }
+        Ok(count)
+    }
+
+    /// Deletes all positions of group in all collections (read only collections are skipped) and its settings,
+    /// returns number of folders with deleted positions
+    pub fn delete_group(&self, group: &str) -> Result<usize> {
+        let count = self.for_each_writable(|c| c.delete_group(group))?;
+        if let Some(settings) = self.settings.as_ref() {
+            settings.remove(group)?;
+        }
+        Ok(count)
+    }
+
+    fn settings_store(&self) -> Result<&Settings> {
+        self.settings.as_ref().ok_or(Error::SettingsUnavailable)
+    }
+
+    /// Client settings of group, empty if group has no settings
+    pub fn get_settings(&self, group: &str) -> Result<Map<String, Value>> {
+        self.settings_store()?
+            .get(group)
+            .map(|s| s.unwrap_or_default())
+    }
+
+    /// Replaces client settings of group, serialized settings must fit into [`MAX_SETTINGS_SIZE`]
+    pub fn set_settings(&self, group: &str, settings: &Map<String, Value>) -> Result<()> {
+        self.settings_store()?.set(group, settings)
+    }
+
+    fn for_each_writable(&self, f: impl Fn(&Collection) -> Result<usize>) -> Result<usize> {
+        let mut count = 0;
+        for c in self.caches().iter() {
+            match f(c) {
+                Ok(n) => count += n,
+                Err(Error::ReadOnlyCollection) => {
+                    warn!(
+                        "Positions in read only collection {:?} not changed",
+                        c.base_dir()
+                    )
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Removes positions of folders, which no longer exist, and positions not updated for longer then
+    /// maximum age of their group (None - no age limit for the group). With `dry_run` positions are
+    /// only returned, not removed. Read only and unavailable collections are skipped.
+    pub fn prune_positions(
+        &self,
+        max_age: impl Fn(&str) -> Option<std::time::Duration>,
+        dry_run: bool,
+    ) -> Result<Vec<PrunedPosition>> {
+        let mut pruned = vec![];
+        for (cn, c) in self.caches().iter().enumerate() {
+            match c.prune_positions(cn, &max_age, dry_run) {
+                Ok(res) => pruned.extend(res),
+                Err(e @ (Error::ReadOnlyCollection | Error::CollectionUnavailable)) => {
+                    warn!(
+                        "Positions in collection {:?} not pruned: {}",
+                        c.base_dir(),
+                        e
+                    )
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(pruned)
+    }
+
     pub fn restore_positions<P2, P3>(
         collections_dirs: Vec<PathBuf>,
         collections_options: CollectionOptionsMap,
@@ -308,6 +712,32 @@ impl Collections {
                 db_path,
                 backup_file,
             ),
+            BackupFile::Booksonic { file, group } => {
+                let positions = import::import_booksonic(
+                    backup::open_backup(file.as_ref())?,
+                    &collections_dirs,
+                    group.as_deref(),
+                )?;
+                Ok(Collections::restore_collections_positions(
+                    collections_dirs,
+                    collections_options,
+                    db_path,
+                    positions,
+                ))
+            }
+            BackupFile::Audiobookshelf { file, group } => {
+                let positions = import::import_audiobookshelf(
+                    backup::open_backup(file.as_ref())?,
+                    &collections_dirs,
+                    &group,
+                )?;
+                Ok(Collections::restore_collections_positions(
+                    collections_dirs,
+                    collections_options,
+                    db_path,
+                    positions,
+                ))
+            }
         }?;
 
         threads.into_iter().for_each(|t| {
@@ -331,7 +761,7 @@ impl Collections {
     {
         let db_path = db_path.as_ref();
         let mut data: Map<String, Value> =
-            serde_json::from_reader(std::io::BufReader::new(File::open(backup_file)?))?;
+            serde_json::from_reader(backup::open_backup(backup_file.as_ref())?)?;
 
         let threads = collections_dirs
             .into_iter()
@@ -368,7 +798,7 @@ impl Collections {
 
     fn restore_positions_legacy<P2, P3>(
         collections_dirs: Vec<PathBuf>,
-        mut collections_options: CollectionOptionsMap,
+        collections_options: CollectionOptionsMap,
         db_path: P2,
         backup_file: P3,
     ) -> Result<Vec<JoinHandle<()>>>
@@ -376,12 +806,10 @@ impl Collections {
         P2: AsRef<Path>,
         P3: AsRef<Path>,
     {
-        let db_path = db_path.as_ref();
         let data: LegacyPositions =
-            serde_json::from_reader(std::io::BufReader::new(File::open(backup_file)?))?;
+            serde_json::from_reader(backup::open_backup(backup_file.as_ref())?)?;
 
-        let mut col_positions: HashMap<usize, HashMap<String, HashMap<String, PositionItem>>> =
-            HashMap::new();
+        let mut col_positions = import::CollectionsPositions::new();
         for (group, m) in data.table.into_iter() {
             //HACK: handle error in clent, which caused invalid positions to be inserted
             if group.starts_with("null") {
@@ -404,22 +832,26 @@ impl Collections {
                     folder_finished: false,
                 };
 
-                col_positions
-                    .entry(col_no)
-                    .or_default()
-                    .entry(path)
-                    .or_default()
-                    .entry(group.clone())
-                    .and_modify(|e| {
-                        if item.timestamp > e.timestamp {
-                            *e = item.clone();
-                        }
-                    })
-                    .or_insert(item);
+                import::add_position(&mut col_positions, col_no, path, group.clone(), item);
             }
         }
 
-        let threads = collections_dirs
+        Ok(Collections::restore_collections_positions(
+            collections_dirs,
+            collections_options,
+            db_path,
+            col_positions,
+        ))
+    }
+
+    fn restore_collections_positions<P2: AsRef<Path>>(
+        collections_dirs: Vec<PathBuf>,
+        mut collections_options: CollectionOptionsMap,
+        db_path: P2,
+        mut col_positions: import::CollectionsPositions,
+    ) -> Vec<JoinHandle<()>> {
+        let db_path = db_path.as_ref();
+        collections_dirs
             .into_iter()
             .enumerate()
             .filter_map(move |(col_no, collection_path)| {
@@ -446,9 +878,7 @@ impl Collections {
                     None
                 }
             })
-            .collect::<Vec<_>>();
-
-        Ok(threads)
+            .collect::<Vec<_>>()
     }
 }
 
@@ -458,6 +888,16 @@ where
 {
     V1(P),
     Legacy(P),
+    /// Bookmarks exported from Booksonic, if group is not given, user name is used
+    Booksonic {
+        file: P,
+        group: Option<String>,
+    },
+    /// Listening progress exported from Audiobookshelf
+    Audiobookshelf {
+        file: P,
+        group: String,
+    },
 }
 
 #[cfg(feature = "async")]
@@ -501,6 +941,48 @@ impl Collections {
     ) -> Result<()>
     where
         S: AsRef<str> + Send + 'static,
+    {
+        spawn_blocking!({ self.insert_position_struct_if_newer(group, position) })
+            .unwrap_or_else(|e| Err(Error::from(e)))
+    }
+
+    pub async fn set_folder_state_async<S, P>(
+        self: Arc<Self>,
+        collection: usize,
+        group: S,
+        folder: P,
+        state: FolderState,
+    ) -> Result<()>
+    where
+        S: AsRef<str> + Send + 'static,
+        P: AsRef<str> + Send + 'static,
+    {
+        spawn_blocking!({ self.set_folder_state(collection, group, folder, state) })
+            .unwrap_or_else(|e| Err(Error::from(e)))
+    }
+
+    /// Inserts several positions (if they are newer) in one blocking task,
+    /// returns result for each position
+    pub async fn insert_positions_if_newer_async<S>(
+        self: Arc<Self>,
+        group: S,
+        positions: Vec<Position>,
+    ) -> Result<Vec<Result<()>>>
+    where
+        S: AsRef<str> + Send + 'static,
+    {
+        spawn_blocking!({
+            positions
+                .into_iter()
+                .map(|position| self.insert_position_struct_if_newer(&group, position))
+                .collect()
+        })
+        .map_err(Error::from)
+    }
+
+    fn insert_position_struct_if_newer<S>(&self, group: S, position: Position) -> Result<()>
+    where
+        S: AsRef<str>,
     {
         let Position {
             position,
@@ -519,16 +1001,13 @@ impl Collections {
         } else {
             file
         };
-        spawn_blocking!({
-            let col = self.get_cache(collection)?;
-            if folder_finished && file_is_empty {
-                debug!("Marked {path} as finished for group {}", group.as_ref());
-                col.mark_as_finished(&group, &path, Some(timestamp))
-            } else {
-                col.insert_position(group, path, position, folder_finished, Some(timestamp))
-            }
-        })
-        .unwrap_or_else(|e| Err(Error::from(e)))
+        let col = self.get_cache(collection)?;
+        if folder_finished && file_is_empty {
+            debug!("Marked {path} as finished for group {}", group.as_ref());
+            col.mark_as_finished(&group, &path, Some(timestamp))
+        } else {
+            col.insert_position(group, path, position, folder_finished, Some(timestamp))
+        }
     }
 
     pub async fn get_positions_recursive_async<S, P>(
@@ -601,7 +1080,7 @@ impl Collections {
                 MAX_POSITIONS,
                 filter.and_then(|f| f.into_option()),
             );
-            for (cn, c) in self.caches.iter().enumerate() {
+            for (cn, c) in self.caches().iter().enumerate() {
                 c.get_all_positions_for_group(group.clone(), cn, &mut res);
             }
             res.into_vec()
@@ -618,7 +1097,7 @@ impl Collections {
     {
         spawn_blocking!({
             let mut res = None;
-            for c in 0..self.caches.len() {
+            for c in 0..self.count() {
                 let cache = self.get_cache(c).expect("cache available"); // is safe, because we are iterating over known range
                 let g: String = group.as_ref().to_owned();
                 let pos = cache.get_position::<_, String>(g, None).map(|mut p| {
@@ -643,11 +1122,41 @@ impl Collections {
         })
     }
 
-    pub async fn backup_positions_async<P>(self: Arc<Self>, backup_file: P) -> Result<()>
+    pub async fn backup_positions_async<P>(
+        self: Arc<Self>,
+        backup_file: P,
+        keep: Option<usize>,
+        compress: bool,
+    ) -> Result<()>
     where
         P: Into<PathBuf> + Send + 'static,
     {
-        spawn_blocking!({ self.backup_positions(backup_file) })
+        spawn_blocking!({ self.backup_positions(backup_file, keep, compress) })
+            .unwrap_or_else(|e| Err(Error::from(e)))
+    }
+
+    pub async fn positions_json_async(self: Arc<Self>) -> Result<Vec<u8>> {
+        spawn_blocking!({
+            let mut buf = Vec::new();
+            self.write_positions(&mut buf).map(|_| buf)
+        })
+        .unwrap_or_else(|e| Err(Error::from(e)))
+    }
+
+    pub async fn prune_positions_async(
+        self: Arc<Self>,
+        max_age: impl Fn(&str) -> Option<std::time::Duration> + Send + 'static,
+        dry_run: bool,
+    ) -> Result<Vec<PrunedPosition>> {
+        spawn_blocking!({ self.prune_positions(max_age, dry_run) })
+            .unwrap_or_else(|e| Err(Error::from(e)))
+    }
+
+    pub async fn restore_positions_live_async(
+        self: Arc<Self>,
+        data: Map<String, Value>,
+    ) -> Result<usize> {
+        spawn_blocking!({ self.restore_positions_live(data) })
             .unwrap_or_else(|e| Err(Error::from(e)))
     }
 }
@@ -5,9 +5,13 @@ use std::time::SystemTime;
 
 use crate::audio_folder::FolderLister;
 use crate::audio_meta::AudioFolder;
-use crate::common::{CollectionOptions, CollectionTrait, PositionsData, PositionsTrait};
+use crate::common::{
+    CollectionOptions, CollectionStats, CollectionTrait, LibraryAuthor, PositionsData,
+    PositionsTrait, ScanState, ScanStatus,
+};
 use crate::error::{Error, Result};
 use crate::position::PositionsCollector;
+use crate::trash;
 use crate::util::get_real_file_type;
 use crate::AudioFolderShort;
 
@@ -69,11 +73,56 @@ impl CollectionTrait for CollectionDirect {
             .search_folder_for_recent(&self.base_dir, limit)
     }
 
-    fn signal_rescan(&self) {}
+    fn signal_rescan(&self) -> bool {
+        false
+    }
+
+    fn scan_status(&self) -> ScanStatus {
+        ScanStatus {
+            state: ScanState::NotCached,
+            ..Default::default()
+        }
+    }
+
+    fn stats(&self) -> Option<CollectionStats> {
+        None
+    }
+
+    fn library_authors(&self, _folder_allowed: &dyn Fn(&Path) -> bool) -> Vec<LibraryAuthor> {
+        vec![]
+    }
+
+    fn library_folders(&self, _author: &str) -> Vec<(Option<String>, AudioFolderShort)> {
+        vec![]
+    }
 
     fn base_dir(&self) -> &Path {
         self.base_dir.as_path()
     }
+
+    fn full_path(&self, rel_path: &Path) -> PathBuf {
+        self.lister.resolve_path(&self.base_dir, rel_path)
+    }
+
+    fn refresh_folder(&self, _dir_path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn trash_folder(&self, dir_path: &Path) -> Result<()> {
+        trash::move_to_trash(self.lister.roots(&self.base_dir), dir_path)
+    }
+
+    fn restore_folder(&self, dir_path: &Path) -> Result<()> {
+        trash::restore_from_trash(self.lister.roots(&self.base_dir), dir_path).map(|_| ())
+    }
+
+    fn set_removal_listener(&self, _listener: crate::RemovalListener) {
+        // no cache to keep in sync, nothing is removed
+    }
+
+    fn set_change_listener(&self, _listener: crate::ChangeListener) {
+        // changes are not watched and there are no positions
+    }
 }
 
 impl PositionsTrait for CollectionDirect {
@@ -105,6 +154,19 @@ impl PositionsTrait for CollectionDirect {
         Ok(())
     }
 
+    fn set_folder_state<S, P>(
+        &self,
+        _group: S,
+        _folder: P,
+        _state: crate::FolderState,
+    ) -> Result<()>
+    where
+        S: AsRef<str>,
+        P: AsRef<str>,
+    {
+        Ok(())
+    }
+
     fn get_position<S, P>(&self, _group: S, _folder: Option<P>) -> Option<crate::Position>
     where
         S: AsRef<str>,
@@ -131,6 +193,27 @@ impl PositionsTrait for CollectionDirect {
         Ok(())
     }
 
+    fn prune_positions(
+        &self,
+        _collection_no: usize,
+        _max_age: &dyn Fn(&str) -> Option<std::time::Duration>,
+        _dry_run: bool,
+    ) -> Result<Vec<crate::PrunedPosition>> {
+        Ok(vec![])
+    }
+
+    fn groups(&self) -> Vec<crate::GroupInfo> {
+        vec![]
+    }
+
+    fn rename_group(&self, _from: &str, _to: &str) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn delete_group(&self, _group: &str) -> Result<usize> {
+        Ok(0)
+    }
+
     fn get_positions_recursive<S, P>(
         &self,
         _group: S,
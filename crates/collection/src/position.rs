@@ -58,10 +58,57 @@ impl Ord for Position {
 pub(crate) type PositionRecord = HashMap<String, PositionItem>;
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
 pub struct PositionShort {
     pub path: String,
     pub timestamp: TimeStamp,
     pub position: f32,
+    #[serde(default)]
+    pub finished: bool,
+}
+
+/// Explicit change of folder finished state for a group
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FolderState {
+    Finished,
+    Unfinished,
+    /// Removes any position of the group in the folder
+    Reset,
+}
+
+/// Why position was pruned
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub enum PruneReason {
+    /// Folder is no longer in collection
+    MissingFolder,
+    /// Position was not updated for longer then maximum age of its group
+    Expired,
+}
+
+/// Position removed (or to be removed in dry run) by positions pruning
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct PrunedPosition {
+    pub collection: usize,
+    pub group: String,
+    pub folder: String,
+    pub file: String,
+    pub timestamp: TimeStamp,
+    pub reason: PruneReason,
+}
+
+/// Group of clients sharing positions, as found in positions records
+#[derive(Clone, Serialize, Deserialize, PartialEq, Debug)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct GroupInfo {
+    pub name: String,
+    /// Timestamp of most recent position of the group
+    pub last_activity: TimeStamp,
+    /// Number of folders with position of the group
+    pub positions: usize,
 }
 
 pub(crate) type PositionsCollector = Collector<Position, PositionFilter>;
@@ -0,0 +1,100 @@
+//! Small key-value store of client settings per positions group (playback speed, UI preferences ...),
+//! so they roam between clients. Settings of group are one JSON object, stored in separate sled db
+//! in collections db directory.
+
+use crate::error::{Error, Result};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+/// Maximum size of serialized settings of one group
+pub const MAX_SETTINGS_SIZE: usize = 64 * 1024;
+const SETTINGS_DB: &str = "settings";
+
+pub(crate) struct Settings {
+    db: sled::Db,
+}
+
+impl Settings {
+    pub(crate) fn open(db_dir: &Path) -> Result<Self> {
+        let db = sled::Config::default()
+            .path(db_dir.join(SETTINGS_DB))
+            .flush_every_ms(Some(1_000))
+            .cache_capacity(1024 * 1024)
+            .open()?;
+        Ok(Settings { db })
+    }
+
+    pub(crate) fn get(&self, group: &str) -> Result<Option<Map<String, Value>>> {
+        self.db
+            .get(group)?
+            .map(|data| serde_json::from_slice(&data).map_err(Error::from))
+            .transpose()
+    }
+
+    /// Replaces all settings of group, empty settings are removed
+    pub(crate) fn set(&self, group: &str, settings: &Map<String, Value>) -> Result<()> {
+        if settings.is_empty() {
+            return self.remove(group).map(|_| ());
+        }
+        let data = serde_json::to_vec(settings)?;
+        if data.len() > MAX_SETTINGS_SIZE {
+            return Err(Error::SettingsTooLarge(data.len()));
+        }
+        self.db.insert(group, data)?;
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, group: &str) -> Result<bool> {
+        Ok(self.db.remove(group)?.is_some())
+    }
+
+    /// Moves settings to new group, existing settings of new group are kept
+    pub(crate) fn rename(&self, from: &str, to: &str) -> Result<()> {
+        if let Some(data) = self.db.remove(from)? {
+            if self.db.get(to)?.is_none() {
+                self.db.insert(to, data)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn as_map(v: Value) -> Map<String, Value> {
+        match v {
+            Value::Object(m) => m,
+            _ => panic!("not object"),
+        }
+    }
+
+    #[test]
+    fn test_settings() -> anyhow::Result<()> {
+        let tmp_dir = tempdir::TempDir::new("AS_SETTINGS_TEST")?;
+        let settings = Settings::open(tmp_dir.path())?;
+        assert!(settings.get("ivan")?.is_none());
+
+        let data = as_map(json!({"speed": 1.5, "theme": "dark"}));
+        settings.set("ivan", &data)?;
+        assert_eq!(Some(data), settings.get("ivan")?);
+
+        let big = "x".repeat(MAX_SETTINGS_SIZE);
+        assert!(matches!(
+            settings.set("ivan", &as_map(json!({ "big": big }))),
+            Err(Error::SettingsTooLarge(_))
+        ));
+
+        settings.set("usak", &as_map(json!({"speed": 2})))?;
+        settings.rename("ivan", "usak")?;
+        assert!(settings.get("ivan")?.is_none());
+        assert_eq!(Some(as_map(json!({"speed": 2}))), settings.get("usak")?);
+
+        settings.set("usak", &Map::new())?;
+        assert!(settings.get("usak")?.is_none());
+        assert!(!settings.remove("usak")?);
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::audio_meta::Chapter;
+
+const SILENCE_NOISE: &str = "-35dB";
+const SILENCE_MIN_DURATION: &str = "1.5";
+
+/// Runs ffmpeg silencedetect filter on the file and returns middles of detected silences in ms
+pub(crate) fn detect_silences(path: &Path) -> io::Result<Vec<u64>> {
+    let output = Command::new("ffmpeg")
+        .arg("-nostdin")
+        .args(["-v", "info", "-i"])
+        .arg(path)
+        .args([
+            "-vn",
+            "-af",
+            &format!(
+                "silencedetect=noise={}:d={}",
+                SILENCE_NOISE, SILENCE_MIN_DURATION
+            ),
+            "-f",
+            "null",
+            "-",
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "ffmpeg silence detection failed with status {}",
+            output.status
+        )));
+    }
+    Ok(parse_silences(&String::from_utf8_lossy(&output.stderr)))
+}
+
+/// Parses lines like `[silencedetect @ 0x..] silence_end: 65.2 | silence_duration: 2.1`
+fn parse_silences(output: &str) -> Vec<u64> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (_, rest) = line.split_once("silence_end:")?;
+            let (end, rest) = rest.split_once('|')?;
+            let (_, dur) = rest.split_once("silence_duration:")?;
+            let end: f64 = end.trim().parse().ok()?;
+            let dur: f64 = dur.trim().parse().ok()?;
+            Some(((end - dur / 2.0).max(0.0) * 1000.0) as u64)
+        })
+        .collect()
+}
+
+/// Replaces split points of fixed length chapters with nearest silence,
+/// if there is one within quarter of chapter length, otherwise split point is kept
+pub(crate) fn adjust_chapters_to_silences(chapters: &mut [Chapter], silences: &[u64]) {
+    if chapters.len() < 2 {
+        return;
+    }
+    let chap_length = chapters[0].end - chapters[0].start;
+    let tolerance = chap_length / 4;
+    for i in 1..chapters.len() {
+        let split = chapters[i].start;
+        let prev_start = chapters[i - 1].start;
+        let next_end = chapters[i].end;
+        let nearest = silences
+            .iter()
+            .copied()
+            .filter(|s| *s > prev_start && *s < next_end)
+            .min_by_key(|s| s.abs_diff(split));
+        if let Some(silence) = nearest.filter(|s| s.abs_diff(split) <= tolerance) {
+            chapters[i - 1].end = silence;
+            chapters[i].start = silence;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: &str = r#"Input #0, mp3, from 'book.mp3':
+  Duration: 01:00:00.00, start: 0.000000, bitrate: 64 kb/s
+[silencedetect @ 0x55d1c7a3a100] silence_start: 1190.5
+[silencedetect @ 0x55d1c7a3a100] silence_end: 1192.5 | silence_duration: 2
+[silencedetect @ 0x55d1c7a3a100] silence_start: 3000
+[silencedetect @ 0x55d1c7a3a100] silence_end: 3002 | silence_duration: 2
+size=N/A time=01:00:00.00 bitrate=N/A speed= 500x
+"#;
+
+    fn chapter(number: u32, start: u64, end: u64) -> Chapter {
+        Chapter {
+            title: format!("Part {}", number),
+            number,
+            start,
+            end,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_silences() {
+        assert_eq!(vec![1_191_500, 3_001_000], parse_silences(OUTPUT));
+    }
+
+    #[test]
+    fn test_adjust_chapters() {
+        let mut chapters = vec![
+            chapter(0, 0, 1_200_000),
+            chapter(1, 1_200_000, 2_400_000),
+            chapter(2, 2_400_000, 3_600_000),
+        ];
+        adjust_chapters_to_silences(&mut chapters, &parse_silences(OUTPUT));
+        assert_eq!(1_191_500, chapters[0].end);
+        assert_eq!(1_191_500, chapters[1].start);
+        // silence at 3001s is too far from split at 2400s
+        assert_eq!(2_400_000, chapters[1].end);
+        assert_eq!(2_400_000, chapters[2].start);
+        assert_eq!(3_600_000, chapters[2].end);
+    }
+}
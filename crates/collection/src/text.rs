@@ -0,0 +1,276 @@
+//! Text accompanying audio - lyrics or transcripts, either from sidecar `.lrc`/`.txt` file
+//! or embedded in media file tags (whole file or chapter), optionally synchronized (LRC format)
+
+use std::fs;
+use std::mem;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::audio_folder::text_sidecar_path;
+use crate::audio_meta::{get_audio_properties_uni, MediaInfo, TimeSpan};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub enum TextSource {
+    Lrc,
+    Txt,
+    Embedded,
+    Chapter,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct TextLine {
+    /// start of line in ms, only for synchronized text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct Text {
+    pub source: TextSource,
+    pub synchronized: bool,
+    pub lines: Vec<TextLine>,
+}
+
+impl Text {
+    fn new(source: TextSource, content: &str) -> Self {
+        let content = content.trim_start_matches('\u{feff}');
+        match parse_lrc(content) {
+            Some(lines) => Text {
+                source,
+                synchronized: true,
+                lines,
+            },
+            None => Text {
+                source,
+                synchronized: false,
+                lines: parse_plain(content),
+            },
+        }
+    }
+
+    /// Keeps only lines of the chapter (including line displayed at chapter start),
+    /// times are then relative to chapter start. Plain text cannot be split, so it's kept whole.
+    fn restrict_to(&mut self, span: TimeSpan) {
+        if !self.synchronized {
+            return;
+        }
+        let end = span.duration.map(|d| span.start + d);
+        let first = self
+            .lines
+            .iter()
+            .rposition(|l| l.time.unwrap_or(0) <= span.start)
+            .unwrap_or(0);
+        self.lines = mem::take(&mut self.lines)
+            .into_iter()
+            .skip(first)
+            .take_while(|l| end.map(|end| l.time.unwrap_or(0) < end).unwrap_or(true))
+            .map(|mut l| {
+                l.time = l.time.map(|t| t.saturating_sub(span.start));
+                l
+            })
+            .collect();
+    }
+}
+
+/// LRC time is mm:ss or mm:ss.xx (also with 1 or 3 fraction digits)
+fn ms_from_lrc_time(t: &str) -> Option<u64> {
+    let (min, rest) = t.split_once(':')?;
+    let (sec, frac) = match rest.split_once(['.', ':']) {
+        Some((sec, frac)) => (sec, Some(frac)),
+        None => (rest, None),
+    };
+    let min: u64 = min.trim().parse().ok()?;
+    let sec: u64 = sec.parse().ok().filter(|s| *s < 60)?;
+    let frac = match frac {
+        Some(f) if (1..=3).contains(&f.len()) && f.bytes().all(|b| b.is_ascii_digit()) => {
+            f.parse::<u64>().ok()? * 10u64.pow(3 - f.len() as u32)
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+    Some((min * 60 + sec) * 1000 + frac)
+}
+
+/// Parses LRC content, returns `None` if there is no timed line
+fn parse_lrc(content: &str) -> Option<Vec<TextLine>> {
+    let mut offset = 0i64;
+    let mut lines = vec![];
+    for line in content.lines() {
+        let mut rest = line.trim();
+        let mut times = vec![];
+        while let Some((tag, after)) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+            match ms_from_lrc_time(tag) {
+                Some(t) => times.push(t),
+                None => {
+                    // other tags are just informative
+                    if let Some((key, value)) = tag.split_once(':') {
+                        if key.trim().eq_ignore_ascii_case("offset") {
+                            offset = value.trim().parse().unwrap_or(0);
+                        }
+                    }
+                }
+            }
+            rest = after;
+        }
+        let text = rest.trim();
+        lines.extend(times.into_iter().map(|t| TextLine {
+            time: Some(t),
+            text: text.to_string(),
+        }));
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    // positive offset means that text is displayed sooner
+    for l in lines.iter_mut() {
+        l.time = l.time.map(|t| (t as i64 - offset).max(0) as u64);
+    }
+    lines.sort_by_key(|l| l.time);
+    Some(lines)
+}
+
+fn parse_plain(content: &str) -> Vec<TextLine> {
+    let lines: Vec<_> = content.lines().map(str::trim_end).collect();
+    let start = lines
+        .iter()
+        .position(|l| !l.is_empty())
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(start, |i| i + 1);
+    lines[start..end]
+        .iter()
+        .map(|l| TextLine {
+            time: None,
+            text: l.to_string(),
+        })
+        .collect()
+}
+
+fn text_from_sidecar(path: &Path) -> Option<Text> {
+    let sidecar = text_sidecar_path(path)?;
+    let source = if sidecar.extension().is_some_and(|e| e == "lrc") {
+        TextSource::Lrc
+    } else {
+        TextSource::Txt
+    };
+    // such files are often in legacy encodings, so rather lossy then nothing
+    fs::read(&sidecar)
+        .map_err(|e| error!("Cannot read text file {:?}: {}", sidecar, e))
+        .ok()
+        .map(|data| Text::new(source, &String::from_utf8_lossy(&data)))
+}
+
+/// Text for audio file or for its chapter (if span is given) - text of chapter embedded in file
+/// is preferred, then sidecar file, then text embedded in file tags
+pub fn extract_text(file_path: &Path, span: Option<TimeSpan>) -> Option<Text> {
+    let mut meta = None;
+    if let Some(span) = span {
+        meta = get_audio_properties_uni(file_path)
+            .map_err(|e| error!("Error {} when extracting metadata from {:?}", e, file_path))
+            .ok();
+        let chapter_text = meta
+            .as_ref()
+            .and_then(|m| m.get_chapters())
+            .and_then(|chapters| chapters.into_iter().find(|c| c.start == span.start))
+            .and_then(|c| c.lyrics());
+        if let Some(text) = chapter_text {
+            return Some(Text::new(TextSource::Chapter, &text));
+        }
+    }
+
+    let mut text = text_from_sidecar(file_path).or_else(|| {
+        meta.or_else(|| {
+            get_audio_properties_uni(file_path)
+                .map_err(|e| error!("Error {} when extracting metadata from {:?}", e, file_path))
+                .ok()
+        })
+        .and_then(|m| m.lyrics())
+        .map(|text| Text::new(TextSource::Embedded, &text))
+    })?;
+    if let Some(span) = span {
+        text.restrict_to(span)
+    }
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn line(time: u64, text: &str) -> TextLine {
+        TextLine {
+            time: Some(time),
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn test_lrc_time() {
+        assert_eq!(Some(62_500), ms_from_lrc_time("01:02.50"));
+        assert_eq!(Some(62_500), ms_from_lrc_time("1:02.5"));
+        assert_eq!(Some(62_005), ms_from_lrc_time("01:02.005"));
+        assert_eq!(Some(6_002_000), ms_from_lrc_time("100:02"));
+        assert_eq!(None, ms_from_lrc_time("01:72.00"));
+        assert_eq!(None, ms_from_lrc_time("ar:Author"));
+        assert_eq!(None, ms_from_lrc_time("01:02.5000"));
+    }
+
+    #[test]
+    fn test_parse_lrc() {
+        let content = "[ti:Title]\n[offset:+500]\n\n[00:10.00]Second\n[00:01.00][00:20.50] Twice \nnot timed\n[00:00.20]";
+        let lines = parse_lrc(content).unwrap();
+        assert_eq!(
+            vec![
+                line(0, ""),
+                line(500, "Twice"),
+                line(9_500, "Second"),
+                line(20_000, "Twice")
+            ],
+            lines
+        );
+        assert!(parse_lrc("[ti:Title]\nJust text").is_none());
+    }
+
+    #[test]
+    fn test_text() {
+        let text = Text::new(TextSource::Txt, "\u{feff}\n  First\r\n\nSecond  \n\n");
+        assert!(!text.synchronized);
+        let lines: Vec<_> = text.lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(vec!["  First", "", "Second"], lines);
+        assert!(text.lines.iter().all(|l| l.time.is_none()));
+
+        let mut text = Text::new(
+            TextSource::Lrc,
+            "[00:01.00]One\n[00:05.00]Two\n[00:09.00]Three\n[00:12.00]Four",
+        );
+        assert!(text.synchronized);
+        text.restrict_to(TimeSpan {
+            start: 6_000,
+            duration: Some(6_000),
+        });
+        assert_eq!(vec![line(0, "Two"), line(3_000, "Three")], text.lines);
+    }
+
+    #[test]
+    fn test_sidecar() {
+        let tmp_dir = TempDir::new("AS_TEXT").unwrap();
+        let audio = tmp_dir.path().join("01.mp3");
+        assert!(text_from_sidecar(&audio).is_none());
+        fs::write(tmp_dir.path().join("01.txt"), "Plain").unwrap();
+        let text = text_from_sidecar(&audio).unwrap();
+        assert_eq!(TextSource::Txt, text.source);
+        fs::write(tmp_dir.path().join("01.mp3.lrc"), "[00:01]Timed").unwrap();
+        let text = text_from_sidecar(&audio).unwrap();
+        assert_eq!(TextSource::Lrc, text.source);
+        assert_eq!(vec![line(1000, "Timed")], text.lines);
+    }
+}
@@ -0,0 +1,137 @@
+//! Soft delete of folders - folder is moved into `.trash` directory in root of collection,
+//! keeping its relative path, so it can be later restored to the original place.
+//! Trash directory is always ignored in collection.
+
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::error::{Error, Result};
+
+pub const TRASH_DIR: &str = ".trash";
+
+/// Path must be relative path of folder in collection, not root and not in trash
+fn check_path(rel_path: &Path) -> Result<()> {
+    let mut components = rel_path.components();
+    match components.next() {
+        Some(Component::Normal(first)) if first != TRASH_DIR => {}
+        _ => return Err(Error::InvalidCollectionPath),
+    }
+    if components.all(|c| matches!(c, Component::Normal(_))) {
+        Ok(())
+    } else {
+        Err(Error::InvalidCollectionPath)
+    }
+}
+
+fn move_folder(from: &Path, to: &Path) -> Result<()> {
+    if to.exists() {
+        return Err(Error::AlreadyExists(to.into()));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to)?;
+    Ok(())
+}
+
+/// Removes empty directories left in trash after folder was restored
+fn remove_empty_parents(root: &Path, rel_path: &Path) {
+    let trash = root.join(TRASH_DIR);
+    for dir in trash.join(rel_path).ancestors().skip(1) {
+        if dir == trash || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+/// Moves folder to trash in each root, where it exists
+pub(crate) fn move_to_trash<'a>(
+    roots: impl Iterator<Item = &'a Path>,
+    rel_path: &Path,
+) -> Result<()> {
+    check_path(rel_path)?;
+    let sources: Vec<_> = roots
+        .map(|root| (root.join(rel_path), root.join(TRASH_DIR).join(rel_path)))
+        .filter(|(from, _)| from.is_dir())
+        .collect();
+    if sources.is_empty() {
+        return Err(Error::FolderNotFound(rel_path.into()));
+    }
+    for (from, to) in sources {
+        move_folder(&from, &to)?;
+        info!("Moved folder {:?} to trash", from);
+    }
+    Ok(())
+}
+
+/// Moves folder from trash back to its original place in each root, where it is trashed.
+/// Returns topmost folder, which was created by restore (missing parent or folder itself)
+pub(crate) fn restore_from_trash<'a>(
+    roots: impl Iterator<Item = &'a Path>,
+    rel_path: &Path,
+) -> Result<PathBuf> {
+    check_path(rel_path)?;
+    let sources: Vec<_> = roots
+        .map(|root| (root, root.join(TRASH_DIR).join(rel_path)))
+        .filter(|(_, from)| from.is_dir())
+        .collect();
+    if sources.is_empty() {
+        return Err(Error::FolderNotFound(rel_path.into()));
+    }
+    let mut created = rel_path.to_path_buf();
+    for (root, from) in sources {
+        if let Some(missing) = rel_path
+            .ancestors()
+            .filter(|p| !p.as_os_str().is_empty() && !root.join(p).exists())
+            .last()
+        {
+            if missing.components().count() < created.components().count() {
+                created = missing.to_path_buf();
+            }
+        }
+        move_folder(&from, &root.join(rel_path))?;
+        remove_empty_parents(root, rel_path);
+        info!("Restored folder {:?} from trash", root.join(rel_path));
+    }
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trash_and_restore() {
+        let tmp_dir = tempdir::TempDir::new("AS_TRASH_TEST").unwrap();
+        let root = tmp_dir.path();
+        fs::create_dir_all(root.join("author/book")).unwrap();
+        fs::write(root.join("author/book/01.mp3"), b"audio").unwrap();
+
+        assert!(move_to_trash(std::iter::once(root), Path::new("")).is_err());
+        assert!(move_to_trash(std::iter::once(root), Path::new("../author")).is_err());
+        assert!(move_to_trash(std::iter::once(root), Path::new(".trash/author")).is_err());
+        assert!(matches!(
+            move_to_trash(std::iter::once(root), Path::new("author/other")),
+            Err(Error::FolderNotFound(_))
+        ));
+
+        move_to_trash(std::iter::once(root), Path::new("author/book")).unwrap();
+        assert!(!root.join("author/book").exists());
+        assert!(root.join(".trash/author/book/01.mp3").is_file());
+
+        fs::remove_dir(root.join("author")).unwrap();
+        let created = restore_from_trash(std::iter::once(root), Path::new("author/book")).unwrap();
+        assert_eq!(Path::new("author"), created);
+        assert!(root.join("author/book/01.mp3").is_file());
+        assert!(root.join(".trash").is_dir());
+        assert!(!root.join(".trash/author").exists());
+
+        fs::create_dir_all(root.join(".trash/author/book")).unwrap();
+        assert!(matches!(
+            move_to_trash(std::iter::once(root), Path::new("author/book")),
+            Err(Error::AlreadyExists(_))
+        ));
+    }
+}
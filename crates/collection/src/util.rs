@@ -6,10 +6,24 @@ use std::{io, thread};
 
 use mime_guess::Mime;
 
+use crate::audio_folder::parse_chapter_path;
+
 pub fn guess_mime_type<P: AsRef<Path>>(path: P) -> Mime {
     mime_guess::from_path(path).first_or_octet_stream()
 }
 
+/// First of collection root directories, which contains given path (relative to collection),
+/// virtual paths of chapters are resolved to real file
+pub fn resolve_root<'a, I>(roots: I, rel_path: &Path) -> Option<&'a Path>
+where
+    I: IntoIterator<Item = &'a Path>,
+{
+    let (real_path, _) = parse_chapter_path(rel_path);
+    roots
+        .into_iter()
+        .find(|root| root.join(real_path.as_ref()).exists())
+}
+
 pub fn get_file_name<P: AsRef<Path>>(path: P) -> String {
     path.as_ref()
         .file_name()
@@ -1,4 +1,4 @@
-use crate::FileModTime;
+use crate::{CacheStats, FileModTime, MaintenanceReport};
 
 use super::{error::Error, CacheInner};
 use std::fs;
@@ -27,7 +27,16 @@ pub struct Cache {
 impl Cache {
     pub fn new<P: AsRef<Path>>(root: P, max_size: u64, max_files: u64) -> Result<Self> {
         let root = root.as_ref().into();
-        CacheInner::new(root, max_size, max_files).map(|cache| Cache {
+        CacheInner::new(root, max_size, max_files, false).map(|cache| Cache {
+            inner: Arc::new(RwLock::new(cache)),
+        })
+    }
+
+    /// Creates cache, which retains rolled back entries, so they can be continued later with [`Cache::add_resume`],
+    /// retained entries are limited by same max. size and number of files as the cache
+    pub fn new_resumable<P: AsRef<Path>>(root: P, max_size: u64, max_files: u64) -> Result<Self> {
+        let root = root.as_ref().into();
+        CacheInner::new(root, max_size, max_files, true).map(|cache| Cache {
             inner: Arc::new(RwLock::new(cache)),
         })
     }
@@ -38,6 +47,11 @@ impl Cache {
         (c.max_files - c.num_files, c.max_size - c.size)
     }
 
+    /// Returns cache statistics with up to `num_oldest` least recently used keys
+    pub fn stats(&self, num_oldest: usize) -> CacheStats {
+        self.inner.read().unwrap().stats(num_oldest)
+    }
+
     pub async fn add<S: AsRef<str>>(
         &self,
         key: S,
@@ -63,6 +77,36 @@ impl Cache {
         .await?
     }
 
+    /// Continues previously rolled back entry - returns file for appending, finisher, file for reading
+    /// of already written content and its length, or None if there is no usable partial entry.
+    pub async fn add_resume<S: AsRef<str>>(
+        &self,
+        key: S,
+        mtime: FileModTime,
+    ) -> Result<Option<(tokio::fs::File, Finisher, tokio::fs::File, u64)>> {
+        let cache = self.inner.clone();
+        let key = key.as_ref().to_string();
+        spawn_blocking(move || {
+            let mut c = cache.write().expect("Cannot lock cache");
+            let (f, written, len) = match c.add_resume(key.clone(), mtime)? {
+                Some(r) => r,
+                None => return Ok(None),
+            };
+            let f2 = f.try_clone()?;
+            Ok(Some((
+                tokio::fs::File::from_std(f),
+                Finisher {
+                    cache: cache.clone(),
+                    key,
+                    file: f2,
+                },
+                tokio::fs::File::from_std(written),
+                len,
+            )))
+        })
+        .await?
+    }
+
     pub async fn get<S: AsRef<str>>(
         &self,
         key: S,
@@ -96,16 +140,29 @@ impl Cache {
 
     pub async fn save_index(&self) -> Result<()> {
         let cache = self.inner.clone();
-        spawn_blocking(move || {
-            let cache = cache.write().unwrap();
-            cache.save_index()
-        })
-        .await?
+        spawn_blocking(move || super::save_index(&cache)).await?
     }
 
     pub fn save_index_blocking(&self) -> Result<()> {
-        let cache = self.inner.write().expect("Cannot lock cache");
-        cache.save_index()
+        super::save_index(&self.inner)
+    }
+
+    /// Keys of all entries, least recently used first
+    pub fn keys(&self) -> Vec<String> {
+        self.inner.read().unwrap().files.keys().cloned().collect()
+    }
+
+    /// Removes all entries, which keys start with prefix, returns number of removed entries
+    pub async fn remove_prefix<S: AsRef<str>>(&self, prefix: S) -> Result<u64> {
+        let cache = self.inner.clone();
+        let prefix = prefix.as_ref().to_string();
+        spawn_blocking(move || super::remove_prefix(&cache, &prefix)).await?
+    }
+
+    /// Verifies index, removes orphaned files and evicts entries until cache is within target limits
+    pub async fn maintain(&self, target_size: u64, target_files: u64) -> Result<MaintenanceReport> {
+        let cache = self.inner.clone();
+        spawn_blocking(move || super::maintain(&cache, target_size, target_files)).await?
     }
 }
 
@@ -157,6 +214,31 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_async_resume() {
+        let temp_dir = tempdir().unwrap();
+        let c = Cache::new_resumable(temp_dir.path(), 10000, 10).unwrap();
+        let t = FileModTime::now();
+        let (mut f, fin) = c.add(MY_KEY, t).await.unwrap();
+        f.write_all(&MSG.as_bytes()[..5]).await.unwrap();
+        f.flush().await.unwrap();
+        fin.roll_back().await.unwrap();
+
+        let (mut f, fin, mut written, len) = c.add_resume(MY_KEY, t).await.unwrap().unwrap();
+        assert_eq!(5, len);
+        let mut s = String::new();
+        written.read_to_string(&mut s).await.unwrap();
+        assert_eq!(&MSG[..5], s);
+        f.write_all(&MSG.as_bytes()[5..]).await.unwrap();
+        f.flush().await.unwrap();
+        fin.commit().await.unwrap();
+
+        let mut f = c.get(MY_KEY, t).await.unwrap().unwrap();
+        let mut s = String::new();
+        f.read_to_string(&mut s).await.unwrap();
+        assert_eq!(MSG, s);
+    }
+
     #[tokio::test]
     async fn test_async() {
         env_logger::try_init().ok();
@@ -0,0 +1,90 @@
+//! Cheap fingerprint of file content - file size and hash of beginning and end of file.
+//! It's not full content hash, so it's intended to recognize copies of same file,
+//! not to detect all possible changes of the file.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Size of samples from beginning and end of file
+pub const SAMPLE_SIZE: u64 = 64 * 1024;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// FNV-1a is simple, but unlike std hashers stable, so fingerprints can be persisted
+struct Fnv(u64);
+
+impl Fnv {
+    fn update(&mut self, data: &[u8]) {
+        for b in data {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+}
+
+fn read_sample(f: &mut File, pos: u64, len: u64, hasher: &mut Fnv) -> io::Result<()> {
+    let mut buf = vec![0; len as usize];
+    f.seek(SeekFrom::Start(pos))?;
+    f.read_exact(&mut buf)?;
+    hasher.update(&buf);
+    Ok(())
+}
+
+/// Returns fingerprint of file content as `size-hash` (both in hex), hash is of first and last
+/// `sample_size` bytes of the file (or whole file if it's smaller then 2 * `sample_size`)
+pub fn content_fingerprint<P: AsRef<Path>>(path: P, sample_size: u64) -> io::Result<String> {
+    let mut f = File::open(path)?;
+    let size = f.metadata()?.len();
+    let mut hasher = Fnv(FNV_OFFSET);
+    hasher.update(&size.to_le_bytes());
+    if size <= 2 * sample_size {
+        read_sample(&mut f, 0, size, &mut hasher)?;
+    } else {
+        read_sample(&mut f, 0, sample_size, &mut hasher)?;
+        read_sample(&mut f, size - sample_size, sample_size, &mut hasher)?;
+    }
+    Ok(format!("{:x}-{:016x}", size, hasher.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_fingerprint() {
+        let dir = tempdir().unwrap();
+        let write = |name: &str, data: &[u8]| {
+            let path = dir.path().join(name);
+            File::create(&path).unwrap().write_all(data).unwrap();
+            path
+        };
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let a = content_fingerprint(write("a", &data), 100).unwrap();
+        let b = content_fingerprint(write("b", &data), 100).unwrap();
+        assert_eq!(a, b);
+        assert!(a.starts_with("3e8-"));
+
+        // change in sampled part
+        let mut changed = data.clone();
+        changed[999] = 0;
+        let c = content_fingerprint(write("c", &changed), 100).unwrap();
+        assert_ne!(a, c);
+
+        // change in middle is not detected, that's the price for being cheap
+        let mut changed = data.clone();
+        changed[500] = 0;
+        let d = content_fingerprint(write("d", &changed), 100).unwrap();
+        assert_eq!(a, d);
+
+        // small file is hashed whole
+        let e = content_fingerprint(write("e", &data[..150]), 100).unwrap();
+        let f = content_fingerprint(write("f", &data[..151]), 100).unwrap();
+        assert_ne!(e, f);
+
+        assert!(content_fingerprint(dir.path().join("missing"), 100).is_err());
+    }
+}
@@ -14,11 +14,12 @@ use std::fs::{self, Metadata};
 use std::io::{self, Read, Write};
 use std::ops::Add;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, SystemTime};
 //use std::time::SystemTime;
 
 pub use self::error::Error;
+pub use self::fingerprint::content_fingerprint;
 
 #[cfg(feature = "asynch")]
 pub use asynch::{Cache as AsyncCache, Finisher};
@@ -26,16 +27,21 @@ pub use asynch::{Cache as AsyncCache, Finisher};
 #[cfg(feature = "asynch")]
 mod asynch;
 mod error;
+pub mod fingerprint;
 
 const PARTIAL: &str = "partial";
 const ENTRIES: &str = "entries";
 const INDEX_OLD: &str = "index";
 const INDEX: &str = "index_v2";
+const JOURNAL: &str = "journal";
+const JOURNAL_SAVING: &str = "journal.saving";
 const MAX_KEY_SIZE: usize = 4096;
 const FILE_KEY_LEN: usize = 32;
 
 type Result<T> = std::result::Result<T, Error>;
 type CacheInnerType = Arc<RwLock<CacheInner>>;
+/// Index entry - (key, mtime, file key)
+type IndexEntry = (String, u64, String);
 
 /// On Unix we use latest of mtime or ctime,
 /// otherwise it's modified time from platform specific impl.
@@ -108,6 +114,50 @@ impl Add<Duration> for FileModTime {
     }
 }
 
+/// Cache statistics - current usage, limits and counters since cache was created
+#[derive(Debug, Clone, Default)]
+pub struct CacheStats {
+    pub size: u64,
+    pub num_files: u64,
+    pub max_size: u64,
+    pub max_files: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Keys of least recently used entries, oldest first
+    pub oldest: Vec<String>,
+}
+
+/// Result of cache maintenance - what was fixed and removed
+#[derive(Debug, Clone, Default)]
+pub struct MaintenanceReport {
+    /// entries removed to get under target limits
+    pub evicted: u64,
+    /// size of evicted entries
+    pub evicted_size: u64,
+    /// files in cache directory, which are not in index
+    pub orphaned_entries: u64,
+    /// unfinished files, which do not belong to any entry being added
+    pub orphaned_partials: u64,
+    /// entries in index, which file is missing
+    pub missing_entries: u64,
+    /// files, which could not be deleted yet (on Windows opened files cannot be deleted)
+    pub deferred: u64,
+    /// true if tracked cache size or number of files was wrong
+    pub size_corrected: bool,
+    /// cache size after maintenance
+    pub size: u64,
+    /// number of files after maintenance
+    pub num_files: u64,
+}
+
+impl MaintenanceReport {
+    /// Index was changed by maintenance, so it should be saved
+    pub fn index_changed(&self) -> bool {
+        self.evicted > 0 || self.missing_entries > 0
+    }
+}
+
 #[derive(Clone)]
 pub struct Cache {
     inner: CacheInnerType,
@@ -116,7 +166,17 @@ pub struct Cache {
 impl Cache {
     pub fn new<P: AsRef<Path>>(root: P, max_size: u64, max_files: u64) -> Result<Self> {
         let root = root.as_ref().into();
-        CacheInner::new(root, max_size, max_files).map(|cache| Cache {
+        CacheInner::new(root, max_size, max_files, false).map(|cache| Cache {
+            inner: Arc::new(RwLock::new(cache)),
+        })
+    }
+
+    /// Creates cache, which retains unfinished entries, so they can be continued later with [`Cache::add_resume`].
+    /// Retained partial entries are not persisted between cache restarts and are limited by same
+    /// max. size and number of files as the cache itself (oldest are deleted first).
+    pub fn new_resumable<P: AsRef<Path>>(root: P, max_size: u64, max_files: u64) -> Result<Self> {
+        let root = root.as_ref().into();
+        CacheInner::new(root, max_size, max_files, true).map(|cache| Cache {
             inner: Arc::new(RwLock::new(cache)),
         })
     }
@@ -131,14 +191,52 @@ impl Cache {
         })
     }
 
+    /// Continues previously unfinished entry - returns guard for appending to entry
+    /// and number of bytes already written, or None if there is no usable partial entry.
+    pub fn add_resume<S: AsRef<str>>(
+        &self,
+        key: S,
+        mtime: FileModTime,
+    ) -> Result<Option<(FileGuard, u64)>> {
+        let key: String = key.as_ref().into();
+        let mut c = self.inner.write().expect("Cannot lock cache");
+        c.add_resume(key.clone(), mtime).map(move |res| {
+            res.map(|(file, _, len)| {
+                (
+                    FileGuard {
+                        cache: self.inner.clone(),
+                        file,
+                        key,
+                    },
+                    len,
+                )
+            })
+        })
+    }
+
     pub fn get<S: AsRef<str>>(&self, key: S, mtime: FileModTime) -> Option<Result<fs::File>> {
         let mut cache = self.inner.write().expect("Cannot lock cache");
         cache.get(key, mtime)
     }
 
     pub fn save_index(&self) -> Result<()> {
-        let cache = self.inner.write().expect("Cannot lock cache");
-        cache.save_index()
+        save_index(&self.inner)
+    }
+
+    /// Saves index only if there were additions since last save (journal is not empty)
+    /// returns true if index was saved
+    pub fn save_index_if_changed(&self) -> Result<bool> {
+        if self.has_unsaved_changes() {
+            self.save_index().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Returns true if some additions are recorded only in journal and not yet in index
+    pub fn has_unsaved_changes(&self) -> bool {
+        let c = self.inner.read().unwrap();
+        c.root.join(JOURNAL).exists() || c.root.join(JOURNAL_SAVING).exists()
     }
 
     pub fn len(&self) -> u64 {
@@ -162,6 +260,54 @@ impl Cache {
         let c = self.inner.read().unwrap();
         (c.max_files - c.num_files, c.max_size - c.size)
     }
+
+    /// Returns cache statistics with up to `num_oldest` least recently used keys
+    pub fn stats(&self, num_oldest: usize) -> CacheStats {
+        self.inner.read().unwrap().stats(num_oldest)
+    }
+
+    /// Keys of all entries, least recently used first
+    pub fn keys(&self) -> Vec<String> {
+        self.inner.read().unwrap().files.keys().cloned().collect()
+    }
+
+    /// Removes all entries (including unfinished retained ones), which keys start with prefix,
+    /// returns number of removed entries
+    pub fn remove_prefix<S: AsRef<str>>(&self, prefix: S) -> Result<u64> {
+        remove_prefix(&self.inner, prefix.as_ref())
+    }
+
+    /// Verifies index against files on disk, removes orphaned files and evicts least recently used
+    /// entries until cache is within target size and number of files, index is saved if changed
+    pub fn maintain(&self, target_size: u64, target_files: u64) -> Result<MaintenanceReport> {
+        maintain(&self.inner, target_size, target_files)
+    }
+}
+
+fn remove_prefix(cache: &CacheInnerType, prefix: &str) -> Result<u64> {
+    let removed = cache
+        .write()
+        .expect("Cannot lock cache")
+        .remove_prefix(prefix)?;
+    if removed > 0 {
+        save_index(cache)?;
+    }
+    Ok(removed)
+}
+
+fn maintain(
+    cache: &CacheInnerType,
+    target_size: u64,
+    target_files: u64,
+) -> Result<MaintenanceReport> {
+    let report = cache
+        .write()
+        .expect("Cannot lock cache")
+        .maintain(target_size, target_files);
+    if report.index_changed() {
+        save_index(cache)?;
+    }
+    Ok(report)
 }
 
 impl Drop for Cache {
@@ -193,20 +339,19 @@ impl io::Write for FileGuard {
 }
 
 fn cleanup<S: AsRef<str>>(cache: &Arc<RwLock<CacheInner>>, key: S) {
-    let file_name = {
-        let mut cache = cache.write().expect("Cannot lock cache");
-        let file_key = cache.opened.remove(key.as_ref());
-        file_key.map(|k| cache.partial_path(k))
-    };
-
-    debug!("Cleanup for file {:?}", file_name);
-
-    if let Some(file_name) = file_name {
-        if file_name.exists() {
-            if let Err(e) = fs::remove_file(&file_name) {
-                error!("Cannot delete file {:?}, error {}", file_name, e)
-            }
+    let mut cache = cache.write().expect("Cannot lock cache");
+    let file_key = cache.opened.remove(key.as_ref());
+    match file_key {
+        Some(file_key) if cache.resumable => {
+            cache.retain_partial(key.as_ref().to_string(), file_key);
         }
+        Some(file_key) => {
+            let file_name = cache.partial_path(file_key);
+            debug!("Cleanup for file {:?}", file_name);
+            // file can be still opened here (guard's file is closed after drop), which matters on Windows
+            cache.delete_file(file_name);
+        }
+        None => (),
     }
 }
 
@@ -224,6 +369,95 @@ impl FileGuard {
     }
 }
 
+/// Saves index without blocking cache operations - only copy of index is taken under read lock
+/// and IO is done without lock. Concurrent saves are serialized, so latest snapshot always wins.
+///
+/// Journal is rotated together with the snapshot (additions need write lock, so journal
+/// cannot change meanwhile) and rotated part is deleted only after index is safely written.
+fn save_index(cache: &CacheInnerType) -> Result<()> {
+    let save_lock = cache.read().expect("Cannot lock cache").save_lock.clone();
+    let _guard = save_lock.lock().expect("Cannot lock index save");
+    let (root, snapshot) = {
+        let c = cache.read().expect("Cannot lock cache");
+        rotate_journal(&c.root)?;
+        (c.root.clone(), c.index_snapshot())
+    };
+    write_index(&root, &snapshot)?;
+    let saving = root.join(JOURNAL_SAVING);
+    if saving.exists() {
+        fs::remove_file(saving)?;
+    }
+    Ok(())
+}
+
+/// Moves journal aside, if previous save failed, journal is appended to journal left from it
+fn rotate_journal(root: &Path) -> Result<()> {
+    let journal = root.join(JOURNAL);
+    if !journal.exists() {
+        return Ok(());
+    }
+    let saving = root.join(JOURNAL_SAVING);
+    if saving.exists() {
+        let mut dest = fs::OpenOptions::new().append(true).open(&saving)?;
+        io::copy(&mut fs::File::open(&journal)?, &mut dest)?;
+        dest.sync_all()?;
+        fs::remove_file(journal)?;
+    } else {
+        fs::rename(journal, saving)?;
+    }
+    Ok(())
+}
+
+fn write_record<W: Write>(f: &mut W, key: &str, mtime: u64, file_key: &str) -> io::Result<()> {
+    f.write_u16::<BigEndian>(key.len() as u16)?;
+    f.write_all(key.as_bytes())?;
+    f.write_u64::<BigEndian>(mtime)?;
+    f.write_u16::<BigEndian>(file_key.len() as u16)?;
+    f.write_all(file_key.as_bytes())
+}
+
+/// Reads next index record, None at the end of file
+fn read_record<R: Read>(f: &mut R) -> Result<Option<IndexEntry>> {
+    let key_len = match f.read_u16::<BigEndian>() {
+        Ok(l) => l as usize,
+        Err(e) => match e.kind() {
+            io::ErrorKind::UnexpectedEof => return Ok(None),
+            _ => return Err(e.into()),
+        },
+    };
+
+    if key_len > MAX_KEY_SIZE {
+        return Err(Error::InvalidIndex);
+    }
+
+    let mut buf = [0_u8; MAX_KEY_SIZE];
+    f.read_exact(&mut buf[..key_len])?;
+    let key = String::from_utf8(Vec::from(&buf[..key_len])).map_err(|_| Error::InvalidIndex)?;
+    let mtime = f.read_u64::<BigEndian>()?;
+    let value_len = f.read_u16::<BigEndian>()? as usize;
+    if value_len > 2 * FILE_KEY_LEN {
+        return Err(Error::InvalidIndex);
+    }
+    f.read_exact(&mut buf[..value_len])?;
+    let value = String::from_utf8(Vec::from(&buf[..value_len])).map_err(|_| Error::InvalidIndex)?;
+    Ok(Some((key, mtime, value)))
+}
+
+fn write_index(root: &Path, entries: &[IndexEntry]) -> Result<()> {
+    let tmp_index = root.join(String::from(INDEX) + ".tmp");
+    {
+        let mut f = io::BufWriter::new(fs::File::create(&tmp_index)?);
+        for (key, mtime, file_key) in entries {
+            write_record(&mut f, key, *mtime, file_key)?;
+        }
+        f.flush()?;
+        f.get_ref().sync_all()?;
+    }
+    fs::rename(tmp_index, root.join(INDEX))?;
+
+    Ok(())
+}
+
 fn gen_cache_key() -> String {
     let mut random = [0; FILE_KEY_LEN];
     let mut rng = rand::thread_rng();
@@ -231,6 +465,24 @@ fn gen_cache_key() -> String {
     BASE64URL_NOPAD.encode(&random)
 }
 
+/// Removes files in directory, which names are not in known set, returns number of removed files
+fn remove_unknown_files(dir: &Path, known: &HashSet<&String>) -> u64 {
+    let mut removed = 0;
+    if let Ok(dir_list) = fs::read_dir(dir) {
+        for dir_entry in dir_list.flatten() {
+            if dir_entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+                if let Ok(file_name) = dir_entry.file_name().into_string() {
+                    if !known.contains(&file_name) && fs::remove_file(dir_entry.path()).is_ok() {
+                        warn!("Removed file not in index {:?}", dir_entry.path());
+                        removed += 1;
+                    }
+                }
+            }
+        }
+    }
+    removed
+}
+
 fn entry_path_helper<P: AsRef<Path>>(root: &Path, file_key: P) -> PathBuf {
     root.join(ENTRIES).join(file_key)
 }
@@ -259,11 +511,21 @@ impl AsRef<Path> for FileEntry {
 struct CacheInner {
     files: LinkedHashMap<String, FileEntry>,
     opened: HashMap<String, FileEntry>,
+    // unfinished entries retained for resume with their sizes, oldest first
+    partial: LinkedHashMap<String, (FileEntry, u64)>,
+    partial_size: u64,
+    resumable: bool,
+    // files of removed entries, which could not be deleted yet
+    deferred: Vec<PathBuf>,
+    save_lock: Arc<Mutex<()>>,
     root: PathBuf,
     max_size: u64,
     max_files: u64,
     size: u64,
     num_files: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
 }
 
 fn recreate_dir<P: AsRef<Path>>(dir: P) -> io::Result<bool> {
@@ -314,7 +576,7 @@ macro_rules! get_cleanup {
 }
 
 impl CacheInner {
-    fn new(root: PathBuf, max_size: u64, max_files: u64) -> Result<Self> {
+    fn new(root: PathBuf, max_size: u64, max_files: u64, resumable: bool) -> Result<Self> {
         let created_root = if !root.exists() {
             fs::create_dir(&root)?;
             true
@@ -334,11 +596,19 @@ impl CacheInner {
         let mut cache = CacheInner {
             files: LinkedHashMap::new(),
             opened: HashMap::new(),
+            partial: LinkedHashMap::new(),
+            partial_size: 0,
+            resumable,
+            deferred: Vec::new(),
+            save_lock: Arc::new(Mutex::new(())),
             root,
             max_size,
             max_files,
             size: 0,
             num_files: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
         };
         match cache.load_index() {
             Err(e) => {
@@ -363,6 +633,8 @@ impl CacheInner {
         } else if self.files.contains_key(&key) {
             return Err(Error::KeyAlreadyExists(key));
         }
+        // new entry replaces unfinished one
+        self.discard_partial(&key);
 
         let mut new_file_key: String;
         loop {
@@ -376,6 +648,83 @@ impl CacheInner {
         }
     }
 
+    /// Returns file for appending, file for reading already written content and its length
+    fn add_resume(
+        &mut self,
+        key: String,
+        mtime: FileModTime,
+    ) -> Result<Option<(fs::File, fs::File, u64)>> {
+        if self.opened.contains_key(&key) {
+            return Err(Error::KeyOpened(key));
+        } else if self.files.contains_key(&key) {
+            return Err(Error::KeyAlreadyExists(key));
+        }
+        let entry = match self.partial.remove(&key) {
+            Some((entry, size)) => {
+                self.partial_size -= size;
+                entry
+            }
+            None => return Ok(None),
+        };
+        let path = self.partial_path(&entry.key);
+        if mtime.as_millis() > entry.mtime {
+            debug!("Partial entry for {} is stalled", key);
+            self.delete_file(path);
+            return Ok(None);
+        }
+        let files = fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|f| fs::File::open(&path).map(|written| (f, written)));
+        match files {
+            Ok((f, written)) => {
+                let len = f.metadata()?.len();
+                self.opened.insert(key, entry);
+                Ok(Some((f, written, len)))
+            }
+            Err(e) => {
+                warn!("Cannot reopen partial entry {:?}: {}", path, e);
+                self.delete_file(path);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Retained partial entries are limited by same max. size and number of files as the cache
+    fn retain_partial(&mut self, key: String, entry: FileEntry) {
+        let path = self.partial_path(&entry.key);
+        let size = match fs::metadata(&path) {
+            Ok(meta) => meta.len(),
+            Err(e) => {
+                warn!("Cannot retain partial entry {:?}: {}", path, e);
+                self.delete_file(path);
+                return;
+            }
+        };
+        debug!("Retaining partial entry for {} of size {}", key, size);
+        self.partial_size += size;
+        if let Some((old, old_size)) = self.partial.insert(key, (entry, size)) {
+            self.partial_size -= old_size;
+            self.delete_file(self.partial_path(&old.key));
+        }
+        while self.partial.len() as u64 > self.max_files || self.partial_size > self.max_size {
+            match self.partial.pop_front() {
+                Some((_, (entry, size))) => {
+                    self.partial_size -= size;
+                    self.delete_file(self.partial_path(&entry.key));
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn discard_partial(&mut self, key: &str) {
+        if let Some((entry, size)) = self.partial.remove(key) {
+            self.partial_size -= size;
+            self.delete_file(self.partial_path(&entry.key));
+        }
+    }
+
     fn get_entry_path<S: AsRef<str>>(&mut self, key: S, mtime: FileModTime) -> Option<PathBuf> {
         let root = &self.root;
         let mut is_stalled = false;
@@ -399,6 +748,11 @@ impl CacheInner {
                 .map_err(|e| error!("Cannot remove key {} from cache: {}", key.as_ref(), e))
                 .ok();
         }
+        if res.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
 
         res
     }
@@ -428,16 +782,137 @@ impl CacheInner {
         get_cleanup!(self, res, file_name, key)
     }
 
-    // This works only on *nix, as one can delete safely opened files, Windows might require bit different approach
-    fn remove_last(&mut self) -> Result<()> {
-        if let Some((_, file_key)) = self.files.pop_front() {
-            let file_path = self.entry_path(file_key);
-            let file_size = fs::metadata(&file_path)?.len();
-            fs::remove_file(file_path)?;
-            self.num_files -= 1;
-            self.size -= file_size;
+    /// Deletes file of removed entry. On Windows opened file cannot be deleted (e.g. cached file
+    /// is just being sent to client), so its deletion is deferred and retried later.
+    fn delete_file(&mut self, path: PathBuf) {
+        match fs::remove_file(&path) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => {
+                debug!("Deferring deletion of {:?}: {}", path, e);
+                self.deferred.push(path);
+            }
+        }
+    }
+
+    /// Retries deletion of files, which could not be deleted before
+    fn retry_deferred(&mut self) {
+        if !self.deferred.is_empty() {
+            self.deferred.retain(|path| match fs::remove_file(path) {
+                Ok(()) => false,
+                Err(e) => e.kind() != io::ErrorKind::NotFound,
+            });
+        }
+    }
+
+    /// Evicts least recently used entry, returns false if there is no entry.
+    /// Entry is removed from cache even if its file cannot be deleted right now - same as on *nix,
+    /// where space of deleted file, which is still opened, is freed only after it's closed.
+    fn remove_last(&mut self) -> bool {
+        match self.files.pop_front() {
+            Some((_, file_key)) => {
+                let file_path = self.entry_path(file_key);
+                let file_size = fs::metadata(&file_path)
+                    .map(|m| m.len())
+                    .unwrap_or_else(|e| {
+                        error!("Cannot get meta for file {:?}: {}", file_path, e);
+                        0
+                    });
+                self.delete_file(file_path);
+                self.num_files -= 1;
+                self.size = self.size.saturating_sub(file_size);
+                self.evictions += 1;
+                true
+            }
+            None => false,
         }
-        Ok(())
+    }
+
+    fn stats(&self, num_oldest: usize) -> CacheStats {
+        CacheStats {
+            size: self.size,
+            num_files: self.num_files,
+            max_size: self.max_size,
+            max_files: self.max_files,
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            oldest: self.files.keys().take(num_oldest).cloned().collect(),
+        }
+    }
+
+    fn maintain(&mut self, target_size: u64, target_files: u64) -> MaintenanceReport {
+        let mut report = MaintenanceReport::default();
+
+        // verify index
+        let mut size = 0;
+        let mut missing = vec![];
+        for (key, entry) in &self.files {
+            match fs::metadata(self.entry_path(entry)) {
+                Ok(meta) => size += meta.len(),
+                Err(_) => missing.push(key.clone()),
+            }
+        }
+        for key in &missing {
+            warn!("Missing file for key {}", key);
+            self.files.remove(key);
+        }
+        report.missing_entries = missing.len() as u64;
+        let num_files = self.files.len() as u64;
+        if size != self.size || num_files != self.num_files {
+            warn!(
+                "Cache size was out of sync: {} bytes in {} files, but actual is {} bytes in {} files",
+                self.size, self.num_files, size, num_files
+            );
+            report.size_corrected = true;
+            self.size = size;
+            self.num_files = num_files;
+        }
+
+        // remove orphaned files
+        let known = self.files.values().map(|e| &e.key).collect::<HashSet<_>>();
+        report.orphaned_entries = remove_unknown_files(&self.root.join(ENTRIES), &known);
+        let known = self
+            .opened
+            .values()
+            .chain(self.partial.values().map(|(e, _)| e))
+            .map(|e| &e.key)
+            .collect::<HashSet<_>>();
+        report.orphaned_partials = remove_unknown_files(&self.root.join(PARTIAL), &known);
+
+        // trim to target limits
+        let size_before = self.size;
+        while (self.size > target_size || self.num_files > target_files) && self.remove_last() {
+            report.evicted += 1;
+        }
+        self.retry_deferred();
+        report.deferred = self.deferred.len() as u64;
+        report.evicted_size = size_before.saturating_sub(self.size);
+        report.size = self.size;
+        report.num_files = self.num_files;
+        report
+    }
+
+    fn remove_prefix(&mut self, prefix: &str) -> Result<u64> {
+        let keys: Vec<_> = self
+            .files
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        for key in &keys {
+            self.remove(key)?;
+        }
+        let partial: Vec<_> = self
+            .partial
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        for key in &partial {
+            self.discard_partial(key);
+        }
+        Ok(keys.len() as u64)
     }
 
     fn remove<S: AsRef<str>>(&mut self, key: S) -> Result<()> {
@@ -466,7 +941,7 @@ impl CacheInner {
                 }
             }
 
-            fs::remove_file(file_path)?;
+            self.delete_file(file_path);
         }
         Ok(())
     }
@@ -482,17 +957,40 @@ impl CacheInner {
             return Err(Error::FileTooBig);
         }
         let old_path = self.partial_path(file_key.clone());
+        self.retry_deferred();
         while self.size + new_file_size > self.max_size || self.num_files + 1 > self.max_files {
-            self.remove_last()?
+            if !self.remove_last() {
+                return Err(Error::InvalidCacheState(
+                    "Cache is over limit, but empty".into(),
+                ));
+            }
         }
         let new_path = self.entry_path(&file_key);
         fs::rename(old_path, &new_path)?;
+        self.append_journal(&key, &file_key);
         self.files.insert(key, file_key);
         self.num_files += 1;
         self.size += new_path.metadata().map(|m| m.len()).unwrap_or(0);
         Ok(())
     }
 
+    /// Records finished entry, so it survives crash before next index save
+    fn append_journal(&self, key: &str, entry: &FileEntry) {
+        let journal = self.root.join(JOURNAL);
+        let res = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&journal)
+            .and_then(|f| {
+                let mut f = io::BufWriter::new(f);
+                write_record(&mut f, key, entry.mtime, &entry.key)?;
+                f.flush()
+            });
+        if let Err(e) = res {
+            error!("Cannot write to cache journal {:?}: {}", journal, e)
+        }
+    }
+
     fn entry_path<P: AsRef<Path>>(&self, file_key: P) -> PathBuf {
         entry_path_helper(&self.root, file_key)
     }
@@ -501,21 +999,11 @@ impl CacheInner {
         self.root.join(PARTIAL).join(file_key)
     }
 
-    fn save_index(&self) -> Result<()> {
-        let tmp_index = self.root.join(String::from(INDEX) + ".tmp");
-        {
-            let mut f = fs::File::create(&tmp_index)?;
-            for (key, value) in self.files.iter() {
-                f.write_u16::<BigEndian>(key.len() as u16)?;
-                f.write_all(key.as_bytes())?;
-                f.write_u64::<BigEndian>(value.mtime)?;
-                f.write_u16::<BigEndian>(value.key.len() as u16)?;
-                f.write_all(value.key.as_bytes())?;
-            }
-        }
-        fs::rename(tmp_index, self.root.join(INDEX))?;
-
-        Ok(())
+    fn index_snapshot(&self) -> Vec<IndexEntry> {
+        self.files
+            .iter()
+            .map(|(key, value)| (key.clone(), value.mtime, value.key.clone()))
+            .collect()
     }
 
     fn load_index(&mut self) -> Result<bool> {
@@ -535,36 +1023,37 @@ impl CacheInner {
             return Ok(false);
         }
         let index_path = self.root.join(INDEX);
-
-        if index_path.exists() {
-            let mut index = LinkedHashMap::<String, FileEntry>::new();
-            let mut f = fs::File::open(index_path)?;
-
-            loop {
-                let key_len = match f.read_u16::<BigEndian>() {
-                    Ok(l) => l as usize,
-                    Err(e) => match e.kind() {
-                        io::ErrorKind::UnexpectedEof => break,
-                        _ => return Err(e.into()),
-                    },
-                };
-
-                if key_len > MAX_KEY_SIZE {
-                    return Err(Error::InvalidIndex);
+        let journals = [self.root.join(JOURNAL_SAVING), self.root.join(JOURNAL)];
+
+        if index_path.exists() || journals.iter().any(|j| j.exists()) {
+            let mut records = LinkedHashMap::<String, (u64, String)>::new();
+            if index_path.exists() {
+                let mut f = io::BufReader::new(fs::File::open(index_path)?);
+                while let Some((key, mtime, value)) = read_record(&mut f)? {
+                    records.insert(key, (mtime, value));
                 }
-
-                let mut buf = [0_u8; MAX_KEY_SIZE];
-                f.read_exact(&mut buf[..key_len])?;
-                let key = String::from_utf8(Vec::from(&buf[..key_len]))
-                    .map_err(|_| Error::InvalidIndex)?;
-                let mtime = f.read_u64::<BigEndian>()?;
-                let value_len = f.read_u16::<BigEndian>()? as usize;
-                if value_len > 2 * FILE_KEY_LEN {
-                    return Err(Error::InvalidIndex);
+            }
+            // additions not yet saved in index, later records are newer
+            for journal in journals.iter().filter(|j| j.exists()) {
+                let mut f = io::BufReader::new(fs::File::open(journal)?);
+                loop {
+                    match read_record(&mut f) {
+                        Ok(Some((key, mtime, value))) => {
+                            records.remove(&key);
+                            records.insert(key, (mtime, value));
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            // last record can be incomplete after crash
+                            warn!("Invalid record in cache journal {:?}: {}", journal, e);
+                            break;
+                        }
+                    }
                 }
-                f.read_exact(&mut buf[..value_len])?;
-                let value = String::from_utf8(Vec::from(&buf[..value_len]))
-                    .map_err(|_| Error::InvalidIndex)?;
+            }
+
+            let mut index = LinkedHashMap::<String, FileEntry>::new();
+            for (key, (mtime, value)) in records {
                 let file_path = self.entry_path(&value);
                 if file_path.exists() {
                     let file_size = fs::metadata(&file_path)?.len();
@@ -586,19 +1075,7 @@ impl CacheInner {
             //cleanup files not in index
             {
                 let file_keys_set = index.values().map(|e| &e.key).collect::<HashSet<&String>>();
-                let base_dir = self.root.join(ENTRIES);
-                if let Ok(dir_list) = fs::read_dir(base_dir) {
-                    for dir_entry in dir_list.flatten() {
-                        if dir_entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
-                            if let Ok(file_name) = dir_entry.file_name().into_string() {
-                                if !file_keys_set.contains(&file_name) {
-                                    fs::remove_file(dir_entry.path()).ok();
-                                    warn!("Removing file not in index {:?}", dir_entry.path());
-                                }
-                            }
-                        }
-                    }
-                }
+                remove_unknown_files(&self.root.join(ENTRIES), &file_keys_set);
             }
 
             self.files = index;
@@ -696,6 +1173,117 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_resume() {
+        env_logger::try_init().ok();
+        const MY_KEY: &str = "muj_test_1";
+        let temp_dir = tempdir().unwrap();
+        let t = FileModTime::now();
+        let c = Cache::new_resumable(temp_dir.path(), 10000, 10).unwrap();
+        assert!(c.add_resume(MY_KEY, t).unwrap().is_none());
+        {
+            let mut f = c.add(MY_KEY, t).unwrap();
+            f.write_all(b"Hello ").unwrap();
+            // dropped without finish
+        }
+        {
+            let (mut f, len) = c.add_resume(MY_KEY, t).unwrap().unwrap();
+            assert_eq!(6, len);
+            f.write_all(b"there").unwrap();
+            f.finish().unwrap();
+        }
+        let mut s = String::new();
+        c.get(MY_KEY, t)
+            .unwrap()
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!("Hello there", s);
+
+        // stalled partial entry is not resumed
+        {
+            let mut f = c.add("other", t).unwrap();
+            f.write_all(b"Hello").unwrap();
+        }
+        assert!(c
+            .add_resume("other", t + Duration::from_secs(1))
+            .unwrap()
+            .is_none());
+
+        // retained partial entries are limited by cache size
+        let temp_dir = tempdir().unwrap();
+        let c = Cache::new_resumable(temp_dir.path(), 10, 10).unwrap();
+        for key in ["first", "second"] {
+            let mut f = c.add(key, t).unwrap();
+            f.write_all(b"Hello").unwrap();
+        }
+        {
+            let mut f = c.add("third", t).unwrap();
+            f.write_all(b"Hi").unwrap();
+        }
+        assert!(c.add_resume("first", t).unwrap().is_none());
+        assert!(c.add_resume("second", t).unwrap().is_some());
+
+        // non resumable cache does not retain partial entries
+        let temp_dir = tempdir().unwrap();
+        let c = Cache::new(temp_dir.path(), 10000, 10).unwrap();
+        {
+            let mut f = c.add(MY_KEY, t).unwrap();
+            f.write_all(b"Hello ").unwrap();
+        }
+        assert!(c.add_resume(MY_KEY, t).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_save_index_concurrently() {
+        use std::thread;
+        let temp_dir = tempdir().unwrap();
+        let t = FileModTime::now();
+        {
+            let c = Cache::new(temp_dir.path(), 100_000, 100).unwrap();
+            let threads: Vec<_> = (0..4)
+                .map(|i| {
+                    let c = c.clone();
+                    thread::spawn(move || {
+                        for j in 0..10 {
+                            let mut f = c.add(format!("Key {} {}", i, j), t).unwrap();
+                            f.write_all(b"content").unwrap();
+                            f.finish().unwrap();
+                            c.save_index().unwrap();
+                        }
+                    })
+                })
+                .collect();
+            threads.into_iter().for_each(|t| t.join().unwrap());
+            c.save_index().unwrap();
+        }
+        let c = Cache::new(temp_dir.path(), 100_000, 100).unwrap();
+        assert_eq!(40, c.len());
+        assert!(c.get("Key 3 9", t).is_some());
+    }
+
+    #[test]
+    fn test_stats() {
+        let temp_dir = tempdir().unwrap();
+        let t = FileModTime::now();
+        let c = Cache::new(temp_dir.path(), 10000, 2).unwrap();
+        for key in ["first", "second", "third"] {
+            let mut f = c.add(key, t).unwrap();
+            f.write_all(b"0123456789").unwrap();
+            f.finish().unwrap();
+        }
+        assert!(c.get("second", t).is_some());
+        assert!(c.get("first", t).is_none());
+        let stats = c.stats(10);
+        assert_eq!(2, stats.num_files);
+        assert_eq!(20, stats.size);
+        assert_eq!(1, stats.hits);
+        assert_eq!(1, stats.misses);
+        assert_eq!(1, stats.evictions);
+        assert_eq!(vec!["third", "second"], stats.oldest);
+        assert_eq!(1, c.stats(1).oldest.len());
+    }
+
     #[test]
     fn test_cleanup_if_deleted() {
         env_logger::try_init().ok();
@@ -866,4 +1454,155 @@ mod tests {
 
         assert_eq!(0, list_path())
     }
+
+    #[test]
+    fn test_maintain() {
+        env_logger::try_init().ok();
+        let temp_dir = tempdir().unwrap();
+        let t = FileModTime::now();
+        let c = Cache::new(temp_dir.path(), 10_000, 10).unwrap();
+        for i in 0..5 {
+            let mut f = c.add(format!("key{}", i), t).unwrap();
+            f.write_all(&[0; 100]).unwrap();
+            f.finish().unwrap();
+        }
+        let _opened = c.add("opened", t).unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join(ENTRIES).join("orphan"), b"orphan").unwrap();
+        fs::write(root.join(PARTIAL).join("orphan"), b"orphan").unwrap();
+        let first_entry = c
+            .inner
+            .read()
+            .unwrap()
+            .files
+            .get("key0")
+            .unwrap()
+            .key
+            .clone();
+        fs::remove_file(root.join(ENTRIES).join(first_entry)).unwrap();
+
+        let report = c.maintain(250, 10).unwrap();
+        assert_eq!(1, report.missing_entries);
+        assert!(report.size_corrected);
+        assert_eq!(1, report.orphaned_entries);
+        assert_eq!(1, report.orphaned_partials);
+        assert_eq!(2, report.evicted);
+        assert_eq!(200, report.evicted_size);
+        assert_eq!(2, report.num_files);
+        assert_eq!(200, report.size);
+        assert!(c.get("key2", t).is_none());
+        assert!(c.get("key4", t).is_some());
+        assert_eq!(2, fs::read_dir(root.join(ENTRIES)).unwrap().count());
+        assert_eq!(1, fs::read_dir(root.join(PARTIAL)).unwrap().count());
+
+        let report = c.maintain(10_000, 10).unwrap();
+        assert!(!report.index_changed());
+        assert!(!report.size_corrected);
+    }
+
+    #[test]
+    fn test_remove_prefix() {
+        env_logger::try_init().ok();
+        let temp_dir = tempdir().unwrap();
+        let t = FileModTime::now();
+        let c = Cache::new(temp_dir.path(), 10_000, 10).unwrap();
+        for key in [
+            "m/book/01.mp3",
+            "m/book/02.mp3",
+            "m/book 2/01.mp3",
+            "l/book/01.mp3",
+        ] {
+            let mut f = c.add(key, t).unwrap();
+            f.write_all(b"data").unwrap();
+            f.finish().unwrap();
+        }
+        c.get("m/book/01.mp3", t).unwrap().unwrap();
+        assert_eq!(
+            vec![
+                "m/book/02.mp3",
+                "m/book 2/01.mp3",
+                "l/book/01.mp3",
+                "m/book/01.mp3"
+            ],
+            c.keys()
+        );
+
+        assert_eq!(2, c.remove_prefix("m/book/").unwrap());
+        assert_eq!(vec!["m/book 2/01.mp3", "l/book/01.mp3"], c.keys());
+        assert!(c.get("m/book/01.mp3", t).is_none());
+        assert_eq!(0, c.remove_prefix("h/").unwrap());
+        assert_eq!(2, c.len());
+        assert_eq!(8, c.stats(0).size);
+        assert!(!c.has_unsaved_changes());
+    }
+
+    #[test]
+    fn test_deferred_deletion() {
+        env_logger::try_init().ok();
+        let temp_dir = tempdir().unwrap();
+        let t = FileModTime::now();
+        let c = Cache::new(temp_dir.path(), 10_000, 2).unwrap();
+        let add = |key: &str| {
+            let mut f = c.add(key, t).unwrap();
+            f.write_all(&[0; 100]).unwrap();
+            f.finish().unwrap();
+        };
+        add("first");
+        add("second");
+        // file cannot be deleted - like opened file on Windows
+        let first_path = {
+            let c = c.inner.read().unwrap();
+            c.entry_path(&c.files.get("first").unwrap().key)
+        };
+        fs::remove_file(&first_path).unwrap();
+        fs::create_dir(&first_path).unwrap();
+
+        add("third");
+        assert_eq!(2, c.len());
+        assert!(c.get("first", t).is_none());
+        assert_eq!(1, c.inner.read().unwrap().deferred.len());
+        assert!(first_path.exists());
+
+        fs::remove_dir(&first_path).unwrap();
+        fs::write(&first_path, b"released").unwrap();
+        add("fourth");
+        assert!(!first_path.exists());
+        assert!(c.inner.read().unwrap().deferred.is_empty());
+        assert_eq!(2, c.len());
+    }
+
+    #[test]
+    fn test_journal_recovery() {
+        env_logger::try_init().ok();
+        let temp_dir = tempdir().unwrap();
+        let t = FileModTime::now();
+        {
+            let c = Cache::new(temp_dir.path(), 10_000, 10).unwrap();
+            let mut f = c.add("first", t).unwrap();
+            f.write_all(b"saved").unwrap();
+            f.finish().unwrap();
+            c.save_index().unwrap();
+            assert!(!c.has_unsaved_changes());
+
+            let mut f = c.add("second", t).unwrap();
+            f.write_all(b"journaled").unwrap();
+            f.finish().unwrap();
+            assert!(c.has_unsaved_changes());
+            // simulate crash - index is not saved on drop
+            std::mem::forget(c);
+        }
+
+        let c = Cache::new(temp_dir.path(), 10_000, 10).unwrap();
+        assert_eq!(2, c.len());
+        let mut s = String::new();
+        c.get("second", t)
+            .unwrap()
+            .unwrap()
+            .read_to_string(&mut s)
+            .unwrap();
+        assert_eq!("journaled", s);
+        assert!(c.save_index_if_changed().unwrap());
+        assert!(!c.has_unsaved_changes());
+        assert!(!c.save_index_if_changed().unwrap());
+    }
 }
@@ -1,4 +1,6 @@
 use std::{
+    collections::HashMap,
+    hash::Hash,
     sync::atomic::{AtomicU64, Ordering},
     time::Instant,
 };
@@ -26,6 +28,15 @@ impl Leaky {
         Leaky::new_with_params(rate, capacity)
     }
 
+    /// Creates new Leaky with given burst size - number of units, which can arrive at once,
+    /// if `None` burst is same as for [`Leaky::new`]
+    pub fn new_with_burst(rate: f32, burst: Option<u64>) -> Self {
+        match burst {
+            Some(burst) => Leaky::new_with_params(rate, burst),
+            None => Leaky::new(rate),
+        }
+    }
+
     /// Creates new Leaky with detailed parameters
     ///
     /// Parameters:
@@ -92,6 +103,41 @@ impl Leaky {
     }
 }
 
+/// Set of independent buckets identified by label
+pub struct LabeledLeaky<L> {
+    buckets: HashMap<L, Leaky>,
+}
+
+impl<L: Hash + Eq> Default for LabeledLeaky<L> {
+    fn default() -> Self {
+        LabeledLeaky {
+            buckets: HashMap::new(),
+        }
+    }
+}
+
+impl<L: Hash + Eq> LabeledLeaky<L> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds (or replaces) bucket for label
+    pub fn with_bucket(mut self, label: L, bucket: Leaky) -> Self {
+        self.buckets.insert(label, bucket);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Same as [`Leaky::start_one`] for bucket of given label,
+    /// `None` if there is no bucket for label (so unit is not limited)
+    pub fn start_one(&self, label: &L) -> Option<Result<u64, u64>> {
+        self.buckets.get(label).map(|b| b.start_one())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
@@ -137,6 +183,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_labeled() {
+        let leaky = LabeledLeaky::new()
+            .with_bucket("expensive", Leaky::new_with_burst(1.0, Some(2)))
+            .with_bucket("cheap", Leaky::new_with_burst(100.0, None));
+        assert!(leaky.start_one(&"expensive").unwrap().is_ok());
+        assert!(leaky.start_one(&"expensive").unwrap().is_ok());
+        assert!(leaky.start_one(&"expensive").unwrap().is_err());
+        for _i in 1..=100 {
+            assert!(leaky.start_one(&"cheap").unwrap().is_ok());
+        }
+        assert!(leaky.start_one(&"cheap").unwrap().is_err());
+        assert!(leaky.start_one(&"other").is_none());
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
     async fn test_leaky_pausing() {
         let leaky = Leaky::new_with_params(100.0, 10);
@@ -44,6 +44,7 @@ fn main() {
         }
     };
     let ffi_src = format!("src/ffi_{}.rs", ffmpeg_version);
+    println!("cargo:rerun-if-changed={}", ffi_src);
     let ffi_target =
         std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("current_ffi.rs");
     std::fs::copy(ffi_src, ffi_target).unwrap();
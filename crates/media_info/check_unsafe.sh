@@ -0,0 +1,11 @@
+#! /bin/bash
+# Checks unsafe code of this crate - miri for tests, which do not call libavformat,
+# address sanitizer for FFI tests. Requires nightly toolchain with miri and rust-src components.
+set -e -x
+
+cd "$(dirname "$0")"
+TARGET=${TARGET:-x86_64-unknown-linux-gnu}
+
+cargo +nightly miri test --features write
+RUSTFLAGS="-Zsanitizer=address" RUSTDOCFLAGS="-Zsanitizer=address" \
+    cargo +nightly test --features write --target "$TARGET" --lib
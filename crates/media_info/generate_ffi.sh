@@ -20,9 +20,36 @@ bindgen \
 --allowlist-function avformat_open_input \
 --allowlist-function avformat_find_stream_info \
 --allowlist-function avformat_close_input \
+--allowlist-function avio_alloc_context \
+--allowlist-function avio_context_free \
+--allowlist-function av_malloc \
+--allowlist-function av_freep \
+--allowlist-function av_dict_set \
+--allowlist-function av_dict_copy \
+--allowlist-function avio_open \
+--allowlist-function avio_closep \
+--allowlist-function avformat_alloc_output_context2 \
+--allowlist-function avformat_free_context \
+--allowlist-function avformat_new_stream \
+--allowlist-function avcodec_parameters_copy \
+--allowlist-function avformat_write_header \
+--allowlist-function av_read_frame \
+--allowlist-function av_interleaved_write_frame \
+--allowlist-function av_write_trailer \
+--allowlist-function av_packet_alloc \
+--allowlist-function av_packet_free \
+--allowlist-function av_new_packet \
+--allowlist-function av_packet_unref \
+--allowlist-function av_packet_rescale_ts \
 --allowlist-function av_dump_format \
 --allowlist-var AV_LOG_QUIET \
 --allowlist-var AV_DICT_IGNORE_SUFFIX \
 --allowlist-var AV_TIME_BASE \
+--allowlist-var AVSEEK_SIZE \
+--allowlist-var AVSEEK_FORCE \
+--allowlist-var AVIO_FLAG_WRITE \
+--allowlist-var AVFMT_NOFILE \
+--allowlist-var AV_PKT_FLAG_KEY \
+--allowlist-var AV_DISPOSITION_ATTACHED_PIC \
 wrapper.h -- -I $FFMPEG_VERSION \
 > src/ffi_$FFMPEG_VERSION.rs
\ No newline at end of file
@@ -3,6 +3,12 @@
 pub const AV_TIME_BASE: u32 = 1000000;
 pub const AV_LOG_QUIET: i32 = -8;
 pub const AV_DICT_IGNORE_SUFFIX: u32 = 2;
+pub const AVSEEK_SIZE: u32 = 65536;
+pub const AVSEEK_FORCE: u32 = 131072;
+pub const AVIO_FLAG_WRITE: u32 = 2;
+pub const AVFMT_NOFILE: u32 = 1;
+pub const AV_PKT_FLAG_KEY: u32 = 1;
+pub const AV_DISPOSITION_ATTACHED_PIC: u32 = 1024;
 pub const AVMediaType_AVMEDIA_TYPE_UNKNOWN: AVMediaType = -1;
 pub const AVMediaType_AVMEDIA_TYPE_VIDEO: AVMediaType = 0;
 pub const AVMediaType_AVMEDIA_TYPE_AUDIO: AVMediaType = 1;
@@ -645,6 +651,21 @@ extern "C" {
 extern "C" {
     pub fn av_dict_count(m: *const AVDictionary) -> ::std::os::raw::c_int;
 }
+extern "C" {
+    pub fn av_dict_set(
+        pm: *mut *mut AVDictionary,
+        key: *const ::std::os::raw::c_char,
+        value: *const ::std::os::raw::c_char,
+        flags: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn av_dict_copy(
+        dst: *mut *mut AVDictionary,
+        src: *const AVDictionary,
+        flags: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
 pub const AVSampleFormat_AV_SAMPLE_FMT_NONE: AVSampleFormat = -1;
 pub const AVSampleFormat_AV_SAMPLE_FMT_U8: AVSampleFormat = 0;
 pub const AVSampleFormat_AV_SAMPLE_FMT_S16: AVSampleFormat = 1;
@@ -5387,6 +5408,107 @@ extern "C" {
 extern "C" {
     pub fn avformat_close_input(s: *mut *mut AVFormatContext);
 }
+extern "C" {
+    pub fn avio_alloc_context(
+        buffer: *mut ::std::os::raw::c_uchar,
+        buffer_size: ::std::os::raw::c_int,
+        write_flag: ::std::os::raw::c_int,
+        opaque: *mut ::std::os::raw::c_void,
+        read_packet: ::std::option::Option<
+            unsafe extern "C" fn(
+                opaque: *mut ::std::os::raw::c_void,
+                buf: *mut u8,
+                buf_size: ::std::os::raw::c_int,
+            ) -> ::std::os::raw::c_int,
+        >,
+        write_packet: ::std::option::Option<
+            unsafe extern "C" fn(
+                opaque: *mut ::std::os::raw::c_void,
+                buf: *mut u8,
+                buf_size: ::std::os::raw::c_int,
+            ) -> ::std::os::raw::c_int,
+        >,
+        seek: ::std::option::Option<
+            unsafe extern "C" fn(
+                opaque: *mut ::std::os::raw::c_void,
+                offset: i64,
+                whence: ::std::os::raw::c_int,
+            ) -> i64,
+        >,
+    ) -> *mut AVIOContext;
+}
+extern "C" {
+    pub fn avio_context_free(s: *mut *mut AVIOContext);
+}
+extern "C" {
+    pub fn av_malloc(size: usize) -> *mut ::std::os::raw::c_void;
+}
+extern "C" {
+    pub fn av_freep(ptr: *mut ::std::os::raw::c_void);
+}
+extern "C" {
+    pub fn avio_open(
+        s: *mut *mut AVIOContext,
+        url: *const ::std::os::raw::c_char,
+        flags: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn avio_closep(s: *mut *mut AVIOContext) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn avformat_alloc_output_context2(
+        ctx: *mut *mut AVFormatContext,
+        oformat: *const AVOutputFormat,
+        format_name: *const ::std::os::raw::c_char,
+        filename: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn avformat_free_context(s: *mut AVFormatContext);
+}
+extern "C" {
+    pub fn avformat_new_stream(s: *mut AVFormatContext, c: *const AVCodec) -> *mut AVStream;
+}
+extern "C" {
+    pub fn avcodec_parameters_copy(
+        dst: *mut AVCodecParameters,
+        src: *const AVCodecParameters,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn avformat_write_header(
+        s: *mut AVFormatContext,
+        options: *mut *mut AVDictionary,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn av_read_frame(s: *mut AVFormatContext, pkt: *mut AVPacket) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn av_interleaved_write_frame(
+        s: *mut AVFormatContext,
+        pkt: *mut AVPacket,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn av_write_trailer(s: *mut AVFormatContext) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn av_packet_alloc() -> *mut AVPacket;
+}
+extern "C" {
+    pub fn av_packet_free(pkt: *mut *mut AVPacket);
+}
+extern "C" {
+    pub fn av_new_packet(pkt: *mut AVPacket, size: ::std::os::raw::c_int) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn av_packet_unref(pkt: *mut AVPacket);
+}
+extern "C" {
+    pub fn av_packet_rescale_ts(pkt: *mut AVPacket, tb_src: AVRational, tb_dst: AVRational);
+}
 extern "C" {
     pub fn av_dump_format(
         ic: *mut AVFormatContext,
@@ -2,10 +2,13 @@
 use encoding::{label::encoding_from_whatwg_label, DecoderTrap, EncodingRef};
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
+use std::io::{Read, Seek, SeekFrom};
 use std::marker::PhantomData;
-use std::os::raw::c_char;
+use std::mem::ManuallyDrop;
+use std::os::raw::{c_char, c_int, c_void};
 use std::ptr;
 use std::slice;
+use std::thread::{self, ThreadId};
 use thiserror::Error;
 
 #[allow(dead_code)]
@@ -16,6 +19,8 @@ use thiserror::Error;
 #[allow(clippy::type_complexity)]
 mod ffi;
 pub mod tags;
+#[cfg(feature = "write")]
+pub mod write;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -29,9 +34,40 @@ pub enum Error {
 
     #[error("Invalid encoding name {0}")]
     InvalidEncoding(String),
+
+    #[error("Invalid tag value {0}")]
+    InvalidTag(String),
+
+    #[error("Invalid path {0:?}")]
+    InvalidPath(std::path::PathBuf),
+
+    #[error("Unsupported cover image, only JPEG and PNG are supported")]
+    UnsupportedCover,
+
+    #[error("IO error: {0}")]
+    IOError(#[from] std::io::Error),
 }
 
 const CODEC_ID_MJPEG: u32 = 7;
+/// Size of buffer used by libavformat for reading from stream
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+// FFERRTAG('E','O','F',' ')
+const AVERROR_EOF: c_int = -0x2046_4F45;
+const AVERROR_EIO: c_int = -5;
+/// Number of first audio packets probed to decide, if stream has constant bitrate
+const CBR_PROBE_PACKETS: usize = 64;
+const CBR_MIN_PACKETS: usize = 8;
+/// Max. relative difference of probed bitrate from bitrate reported for stream
+const CBR_BITRATE_TOLERANCE: f64 = 0.02;
+
+/// Audio codecs, which are interesting for transcoding decisions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecKind {
+    Mp3,
+    Aac,
+    Opus,
+    Other,
+}
 
 // fn string_from_ptr(ptr: *const c_char) -> Result<Option<String>> {
 //     if ptr.is_null() {
@@ -192,17 +228,246 @@ pub fn version() -> u32 {
     unsafe { ffi::avformat_version() }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Chapter {
     pub title: String,
     pub number: u32,
     pub start: u64,
     pub end: u64,
+    /// all metadata of the chapter
+    pub meta: HashMap<String, String>,
+}
+
+/// Title can be also in different key, like title-eng or name
+fn chapter_title(meta: &HashMap<String, String>) -> Option<String> {
+    let mut keys: Vec<_> = meta
+        .keys()
+        .filter(|k| {
+            let k = k.to_lowercase();
+            k.starts_with("title") || k == "name"
+        })
+        .collect();
+    // prefer exact title, then shortest key
+    keys.sort_by_key(|k| (!k.eq_ignore_ascii_case("title"), k.len(), *k));
+    keys.first()
+        .and_then(|k| meta.get(*k))
+        .filter(|t| !t.trim().is_empty())
+        .cloned()
+}
+
+/// Lyrics (or other text transcript) can be in `lyrics`, `lyrics-<lang>` (ID3 USLT frame)
+/// or `unsyncedlyrics` key
+fn find_lyrics(meta: &HashMap<String, String>) -> Option<String> {
+    let mut keys: Vec<_> = meta
+        .keys()
+        .filter(|k| {
+            let k = k.to_lowercase();
+            k == "lyrics" || k.starts_with("lyrics-") || k == "unsyncedlyrics"
+        })
+        .collect();
+    // prefer exact lyrics, then shortest key
+    keys.sort_by_key(|k| (!k.eq_ignore_ascii_case("lyrics"), k.len(), *k));
+    keys.into_iter()
+        .filter_map(|k| meta.get(k))
+        .find(|t| !t.trim().is_empty())
+        .cloned()
+}
+
+impl Chapter {
+    pub fn lyrics(&self) -> Option<String> {
+        find_lyrics(&self.meta)
+    }
+}
+
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+// Send is required, so MediaFile opened from stream can be Send
+enum StreamSource {
+    Read(Box<dyn Read + Send>),
+    Seekable(Box<dyn ReadSeek + Send>),
+}
+
+impl StreamSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            StreamSource::Read(r) => r.read(buf),
+            StreamSource::Seekable(r) => r.read(buf),
+        }
+    }
 }
 
+unsafe extern "C" fn read_packet(opaque: *mut c_void, buf: *mut u8, buf_size: c_int) -> c_int {
+    let source = &mut *(opaque as *mut StreamSource);
+    let buf = slice::from_raw_parts_mut(buf, buf_size.max(0) as usize);
+    match source.read(buf) {
+        Ok(0) => AVERROR_EOF,
+        Ok(n) => n as c_int,
+        Err(_) => AVERROR_EIO,
+    }
+}
+
+unsafe extern "C" fn seek_packet(opaque: *mut c_void, offset: i64, whence: c_int) -> i64 {
+    let source = match &mut *(opaque as *mut StreamSource) {
+        StreamSource::Seekable(s) => s,
+        StreamSource::Read(_) => return AVERROR_EIO as i64,
+    };
+    let whence = whence & !(ffi::AVSEEK_FORCE as c_int);
+    let res = if whence == ffi::AVSEEK_SIZE as c_int {
+        // size of stream is requested, current position must be preserved
+        source.stream_position().and_then(|pos| {
+            let size = source.seek(SeekFrom::End(0))?;
+            source.seek(SeekFrom::Start(pos))?;
+            Ok(size)
+        })
+    } else {
+        let pos = match whence {
+            0 if offset >= 0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return AVERROR_EIO as i64,
+        };
+        source.seek(pos)
+    };
+    res.map(|p| p as i64).unwrap_or(AVERROR_EIO as i64)
+}
+
+/// Custom IO context reading from Rust stream, must outlive format context using it
+struct CustomIo {
+    ctx: *mut ffi::AVIOContext,
+    _source: Box<StreamSource>,
+}
+
+impl CustomIo {
+    fn new(source: StreamSource) -> Result<Self> {
+        let seekable = matches!(source, StreamSource::Seekable(_));
+        let mut source = Box::new(source);
+        unsafe {
+            let buffer = ffi::av_malloc(STREAM_BUFFER_SIZE as _) as *mut u8;
+            if buffer.is_null() {
+                return Err(Error::AllocationError);
+            }
+            let ctx = ffi::avio_alloc_context(
+                buffer,
+                STREAM_BUFFER_SIZE as c_int,
+                0,
+                source.as_mut() as *mut StreamSource as *mut c_void,
+                Some(read_packet),
+                None,
+                if seekable { Some(seek_packet) } else { None },
+            );
+            if ctx.is_null() {
+                let mut buffer = buffer;
+                ffi::av_freep(&mut buffer as *mut *mut u8 as *mut c_void);
+                return Err(Error::AllocationError);
+            }
+            Ok(CustomIo {
+                ctx,
+                _source: source,
+            })
+        }
+    }
+}
+
+impl Drop for CustomIo {
+    fn drop(&mut self) {
+        unsafe {
+            // buffer might have been reallocated by libavformat, so current one is freed
+            ffi::av_freep(&mut (*self.ctx).buffer as *mut *mut u8 as *mut c_void);
+            ffi::avio_context_free(&mut self.ctx);
+        }
+    }
+}
+
+struct Packet(*mut ffi::AVPacket);
+
+impl Packet {
+    fn new() -> Result<Self> {
+        let pkt = unsafe { ffi::av_packet_alloc() };
+        if pkt.is_null() {
+            Err(Error::AllocationError)
+        } else {
+            Ok(Packet(pkt))
+        }
+    }
+}
+
+impl Drop for Packet {
+    fn drop(&mut self) {
+        unsafe { ffi::av_packet_free(&mut self.0) }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ProbedPacket {
+    pos: i64,
+    size: i64,
+    /// in stream time base
+    duration: i64,
+}
+
+/// Layout of constant bitrate audio stream (raw frames one after another, like in MP3 or ADTS file),
+/// so that time position can be mapped to byte offset in file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CbrLayout {
+    /// offset of first audio frame
+    pub data_start: u64,
+    /// in ms
+    pub frame_duration: f64,
+    /// average size of frame in bytes (frames can differ by padding byte)
+    pub frame_size: f64,
+}
+
+impl CbrLayout {
+    /// Layout can be determined only if packets are contiguous and have same duration and size
+    /// (+/- padding byte) and their bitrate corresponds to stream bitrate (if known),
+    /// otherwise it's probably VBR stream (which can start with constant frames - like silence)
+    fn from_packets(packets: &[ProbedPacket], ms_per_tick: f64, bitrate: i64) -> Option<Self> {
+        let first = packets.first()?;
+        if packets.len() < CBR_MIN_PACKETS || first.pos < 0 || first.duration <= 0 {
+            return None;
+        }
+        let same_duration = packets.iter().all(|p| p.duration == first.duration);
+        let contiguous = packets.windows(2).all(|w| w[0].pos + w[0].size == w[1].pos);
+        let min_size = packets.iter().map(|p| p.size).min()?;
+        let max_size = packets.iter().map(|p| p.size).max()?;
+        if !same_duration || !contiguous || max_size - min_size > 1 {
+            return None;
+        }
+        let frame_duration = first.duration as f64 * ms_per_tick;
+        let frame_size = packets.iter().map(|p| p.size).sum::<i64>() as f64 / packets.len() as f64;
+        let probed_bitrate = frame_size * 8.0 * 1000.0 / frame_duration;
+        if bitrate > 0
+            && (probed_bitrate - bitrate as f64).abs() / bitrate as f64 > CBR_BITRATE_TOLERANCE
+        {
+            return None;
+        }
+        Some(CbrLayout {
+            data_start: first.pos as u64,
+            frame_duration,
+            frame_size,
+        })
+    }
+
+    /// Offset of frame playing at given time (in ms)
+    pub fn byte_offset(&self, time_ms: u64) -> u64 {
+        let frame = (time_ms as f64 / self.frame_duration).floor();
+        self.data_start + (frame * self.frame_size).round() as u64
+    }
+}
+
+/// Opened media file (or stream).
+///
+/// It is `Send` - format context is exclusively owned by this value and libavformat does not bind
+/// it to thread, where it was created, so it can be moved to other thread (like into `spawn_blocking`).
+/// It is not `Sync`, because libavformat does not guarantee that context can be used concurrently
+/// from several threads. `Stream`s are borrowed from `MediaFile`, so they are confined to its thread.
 pub struct MediaFile {
     ctx: *mut ffi::AVFormatContext,
     meta: Dictionary,
+    // dropped after ctx is closed
+    _io: Option<CustomIo>,
 }
 
 macro_rules! meta_methods {
@@ -217,26 +482,48 @@ macro_rules! meta_methods {
 
 impl MediaFile {
     pub fn open<S: AsRef<str>>(fname: S) -> Result<Self> {
+        let name = CString::new(fname.as_ref()).unwrap();
+        MediaFile::open_input(&name, None)
+    }
+
+    /// Opens media from stream, which cannot seek (like stdin or network stream),
+    /// so some formats (like mp4 with index at the end) might not be readable
+    pub fn open_stream<R: Read + Send + 'static>(stream: R) -> Result<Self> {
+        let io = CustomIo::new(StreamSource::Read(Box::new(stream)))?;
+        MediaFile::open_input(c"", Some(io))
+    }
+
+    /// Opens media from seekable stream, all formats supported by libavformat can be read
+    pub fn open_seekable_stream<R: Read + Seek + Send + 'static>(stream: R) -> Result<Self> {
+        let io = CustomIo::new(StreamSource::Seekable(Box::new(stream)))?;
+        MediaFile::open_input(c"", Some(io))
+    }
+
+    fn open_input(name: &CStr, io: Option<CustomIo>) -> Result<Self> {
         let mut ctx;
-        let meta;
         unsafe {
             ctx = ffi::avformat_alloc_context();
             assert!(ctx as usize > 0);
             //(*ctx).probesize = 5*1024*1024*1024;
-            let name = CString::new(fname.as_ref()).unwrap();
+            if let Some(io) = io.as_ref() {
+                (*ctx).pb = io.ctx;
+            }
+            // on error ctx is freed by avformat_open_input
             let ret =
                 ffi::avformat_open_input(&mut ctx, name.as_ptr(), ptr::null_mut(), ptr::null_mut());
             check_ret(ret)?;
             if ctx.is_null() {
                 return Err(Error::AllocationError);
             }
-            let ret = ffi::avformat_find_stream_info(ctx, ptr::null_mut());
-            check_ret(ret)?;
-
-            meta = Dictionary::new((*ctx).metadata);
         }
 
-        let mut mf = MediaFile { ctx, meta };
+        let mut mf = MediaFile {
+            ctx,
+            meta: Dictionary::new(ptr::null_mut()),
+            _io: io,
+        };
+        check_ret(unsafe { ffi::avformat_find_stream_info(ctx, ptr::null_mut()) })?;
+        mf.meta = Dictionary::new(unsafe { (*ctx).metadata });
 
         if mf.meta.len() == 0 && mf.streams_count() > 0 {
             //OK we do not have meta in main header, let's look at streams
@@ -263,6 +550,54 @@ impl MediaFile {
         }
     }
 
+    /// Codec of first audio stream and its bitrate in kbps (if stream bitrate is unknown, file bitrate is used)
+    pub fn audio_codec(&self) -> Option<(CodecKind, u32)> {
+        (0..self.streams_count())
+            .map(|idx| self.stream(idx))
+            .find(|s| matches!(s.kind(), StreamKind::AUDIO))
+            .map(|s| {
+                let bitrate = match s.bitrate() {
+                    0 => self.bitrate(),
+                    b => b,
+                };
+                (s.codec_kind(), bitrate)
+            })
+    }
+
+    /// Layout of first audio stream, if it has constant bitrate, it's supported only for MP3 and AAC
+    /// (in ADTS), where stream is just sequence of frames. Reads first packets from file,
+    /// so it should be called on freshly opened file.
+    pub fn cbr_layout(&mut self) -> Option<CbrLayout> {
+        let (idx, time_base, bitrate) = (0..self.streams_count())
+            .map(|idx| (idx, self.stream(idx)))
+            .find(|(_, s)| matches!(s.kind(), StreamKind::AUDIO))
+            .filter(|(_, s)| matches!(s.codec_kind(), CodecKind::Mp3 | CodecKind::Aac))
+            .map(|(idx, s)| unsafe { (idx, (*s.ctx).time_base, (*(*s.ctx).codecpar).bit_rate) })?;
+        if time_base.den == 0 {
+            return None;
+        }
+        let pkt = Packet::new().ok()?;
+        let mut packets = Vec::with_capacity(CBR_PROBE_PACKETS);
+        while packets.len() < CBR_PROBE_PACKETS {
+            unsafe {
+                if ffi::av_read_frame(self.ctx, pkt.0) < 0 {
+                    break;
+                }
+                let p = &*pkt.0;
+                if p.stream_index as usize == idx {
+                    packets.push(ProbedPacket {
+                        pos: p.pos,
+                        size: p.size.into(),
+                        duration: p.duration,
+                    });
+                }
+                ffi::av_packet_unref(pkt.0);
+            }
+        }
+        let ms_per_tick = f64::from(time_base.num) * 1000.0 / f64::from(time_base.den);
+        CbrLayout::from_packets(&packets, ms_per_tick, bitrate)
+    }
+
     fn attached_stream(&self) -> Option<Stream> {
         for idx in 0..self.streams_count() {
             let s = self.stream(idx);
@@ -306,11 +641,16 @@ impl MediaFile {
         })
     }
 
-    /// Duration in ms
+    /// Duration in ms, if container does not provide it, longest audio stream duration is used
     pub fn duration(&self) -> u64 {
         let d = unsafe { (*self.ctx).duration } / 1_000;
-        if d < 0 {
-            0
+        if d <= 0 {
+            (0..self.streams_count())
+                .map(|idx| self.stream(idx))
+                .filter(|s| matches!(s.kind(), StreamKind::AUDIO))
+                .map(|s| s.duration())
+                .max()
+                .unwrap_or(0)
         } else {
             d as u64
         }
@@ -337,6 +677,11 @@ impl MediaFile {
         self.meta.get_all()
     }
 
+    /// Embedded lyrics or transcript, can be plain text or in LRC format
+    pub fn lyrics(&self) -> Option<String> {
+        find_lyrics(&self.all_meta())
+    }
+
     pub fn chapters_count(&self) -> usize {
         unsafe { (*self.ctx).nb_chapters as usize }
     }
@@ -352,11 +697,9 @@ impl MediaFile {
             for chap in chaps {
                 let chap = **chap;
                 // TODO: May need alternate encoding also for chapter names
-                let meta = Dictionary::new(chap.metadata);
+                let meta = Dictionary::new(chap.metadata).get_all();
                 let num = chap.id;
-                let title = meta
-                    .get("title")
-                    .unwrap_or_else(|| format!("Chapter {}", num));
+                let title = chapter_title(&meta).unwrap_or_else(|| format!("Chapter {}", num));
                 let start = norm_time(chap.start, chap.time_base);
                 let end = norm_time(chap.end, chap.time_base);
                 c.push(Chapter {
@@ -364,6 +707,7 @@ impl MediaFile {
                     title,
                     start,
                     end,
+                    meta,
                 });
             }
             Some(c)
@@ -371,6 +715,10 @@ impl MediaFile {
     }
 }
 
+// SAFETY: all pointers (format context, its metadata dictionary and custom IO with its source,
+// which is also Send) are owned only by this value and are not shared with other values
+unsafe impl Send for MediaFile {}
+
 impl Drop for MediaFile {
     fn drop(&mut self) {
         unsafe {
@@ -379,6 +727,64 @@ impl Drop for MediaFile {
     }
 }
 
+/// Value confined to thread, where it was created - wrapper can be moved to other threads
+/// (e.g. kept in async task, which can be resumed in any thread of runtime), but value
+/// can be accessed only in original thread. If wrapper is dropped in other thread, value is leaked.
+pub struct ThreadConfined<T> {
+    value: ManuallyDrop<T>,
+    owner: ThreadId,
+}
+
+// SAFETY: value is never accessed nor dropped outside of owner thread
+unsafe impl<T> Send for ThreadConfined<T> {}
+
+impl<T> ThreadConfined<T> {
+    pub fn new(value: T) -> Self {
+        ThreadConfined {
+            value: ManuallyDrop::new(value),
+            owner: thread::current().id(),
+        }
+    }
+
+    pub fn is_owner_thread(&self) -> bool {
+        thread::current().id() == self.owner
+    }
+
+    /// None if called outside of owner thread
+    pub fn get(&self) -> Option<&T> {
+        self.is_owner_thread().then_some(&*self.value)
+    }
+
+    /// None if called outside of owner thread
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        if self.is_owner_thread() {
+            Some(&mut *self.value)
+        } else {
+            None
+        }
+    }
+
+    /// Returns wrapper back, if called outside of owner thread
+    pub fn into_inner(self) -> std::result::Result<T, Self> {
+        if self.is_owner_thread() {
+            let mut this = ManuallyDrop::new(self);
+            // SAFETY: value is taken only once, wrapper is not dropped
+            Ok(unsafe { ManuallyDrop::take(&mut this.value) })
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl<T> Drop for ThreadConfined<T> {
+    fn drop(&mut self) {
+        if self.is_owner_thread() {
+            // SAFETY: value is not used after drop
+            unsafe { ManuallyDrop::drop(&mut self.value) }
+        }
+    }
+}
+
 pub struct Stream<'a> {
     ctx: *mut ffi::AVStream,
     _parent: PhantomData<&'a MediaFile>,
@@ -402,11 +808,14 @@ impl Stream<'_> {
         Dictionary::new(unsafe { (*self.ctx).metadata })
     }
 
+    /// Duration in ms, 0 if unknown
     pub fn duration(&self) -> u64 {
-        norm_time(
-            unsafe { *self.ctx }.duration,
-            unsafe { *self.ctx }.time_base,
-        )
+        let d = unsafe { *self.ctx }.duration;
+        if d < 0 {
+            0
+        } else {
+            norm_time(d, unsafe { *self.ctx }.time_base)
+        }
     }
 
     pub fn frames_count(&self) -> u64 {
@@ -424,6 +833,15 @@ impl Stream<'_> {
         unsafe { *(*self.ctx).codecpar }.codec_id
     }
 
+    pub fn codec_kind(&self) -> CodecKind {
+        match self.codec_id() {
+            ffi::AVCodecID_AV_CODEC_ID_MP3 => CodecKind::Mp3,
+            ffi::AVCodecID_AV_CODEC_ID_AAC => CodecKind::Aac,
+            ffi::AVCodecID_AV_CODEC_ID_OPUS => CodecKind::Opus,
+            _ => CodecKind::Other,
+        }
+    }
+
     pub fn codec_four_cc(&self) -> String {
         let n = unsafe { *(*self.ctx).codecpar }.codec_tag;
         let bytes = n.to_le_bytes();
@@ -468,7 +886,158 @@ pub enum StreamKind {
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn test_chapter_title() {
+        let meta: HashMap<String, String> = [("title-eng", "English"), ("name", "Name")]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(Some("Name".to_string()), chapter_title(&meta));
+        let mut meta = meta;
+        meta.insert("TITLE".into(), "Title".into());
+        assert_eq!(Some("Title".to_string()), chapter_title(&meta));
+        assert_eq!(None, chapter_title(&HashMap::new()));
+    }
+
+    #[test]
+    fn test_find_lyrics() {
+        let mut meta: HashMap<String, String> = [
+            ("lyrics-eng", "English"),
+            ("UNSYNCEDLYRICS", "Unsynced"),
+            ("lyricist", "Author"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        assert_eq!(Some("English".to_string()), find_lyrics(&meta));
+        meta.insert("LYRICS".into(), " ".into());
+        assert_eq!(Some("English".to_string()), find_lyrics(&meta));
+        meta.insert("LYRICS".into(), "Lyrics".into());
+        assert_eq!(Some("Lyrics".to_string()), find_lyrics(&meta));
+        meta.retain(|k, _| k == "lyricist");
+        assert_eq!(None, find_lyrics(&meta));
+    }
+
+    #[test]
+    fn test_cbr_layout() {
+        // 128 kbps MP3 at 44.1 kHz - 1152 samples per frame, frames are 417 or 418 bytes
+        let frames = |n: i64| {
+            let mut pos = 1000;
+            (0..n)
+                .map(|i| {
+                    let size = if i % 3 == 0 { 418 } else { 417 };
+                    let p = ProbedPacket {
+                        pos,
+                        size,
+                        duration: 1152,
+                    };
+                    pos += size;
+                    p
+                })
+                .collect::<Vec<_>>()
+        };
+        let ms_per_tick = 1000.0 / 44100.0;
+        let packets = frames(60);
+        let layout = CbrLayout::from_packets(&packets, ms_per_tick, 128_000).unwrap();
+        assert_eq!(1000, layout.data_start);
+        assert_eq!(1000, layout.byte_offset(10));
+        // 100th frame
+        assert_eq!(1000 + 41_733, layout.byte_offset(2613));
+        // unknown bitrate of stream
+        assert!(CbrLayout::from_packets(&packets, ms_per_tick, 0).is_some());
+        // VBR stream with uniform start
+        assert!(CbrLayout::from_packets(&packets, ms_per_tick, 96_000).is_none());
+        // not enough packets
+        assert!(CbrLayout::from_packets(&frames(4), ms_per_tick, 128_000).is_none());
+        let mut vbr = packets.clone();
+        vbr[10].size += 100;
+        for p in vbr[11..].iter_mut() {
+            p.pos += 100;
+        }
+        assert!(CbrLayout::from_packets(&vbr, ms_per_tick, 0).is_none());
+        let mut gap = packets.clone();
+        gap[20].pos += 1;
+        assert!(CbrLayout::from_packets(&gap, ms_per_tick, 0).is_none());
+    }
+
     #[test]
+    #[cfg_attr(miri, ignore)] // FFI
+    fn test_cbr_layout_from_file() {
+        init();
+        let mut mf = MediaFile::open("test_files/test.mp3").unwrap();
+        // just check that probing works, test file can be VBR
+        if let Some(layout) = mf.cbr_layout() {
+            assert!(layout.frame_duration > 0.0);
+            assert!(layout.byte_offset(1000) > layout.data_start);
+        }
+    }
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn test_send() {
+        assert_send::<MediaFile>();
+        assert_send::<ThreadConfined<std::rc::Rc<u32>>>();
+    }
+
+    #[test]
+    fn test_thread_confined() {
+        let value = std::rc::Rc::new(1);
+        let mut confined = ThreadConfined::new(value.clone());
+        assert_eq!(Some(&1), confined.get().map(|v| &**v));
+        assert!(confined.get_mut().is_some());
+        let confined = thread::spawn(move || {
+            assert!(!confined.is_owner_thread());
+            assert!(confined.get().is_none());
+            confined.into_inner().unwrap_err()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(2, std::rc::Rc::strong_count(&value));
+        drop(confined.into_inner().ok().unwrap());
+        assert_eq!(1, std::rc::Rc::strong_count(&value));
+
+        // dropped in other thread - value is leaked
+        let confined = ThreadConfined::new(value.clone());
+        thread::spawn(move || drop(confined)).join().unwrap();
+        assert_eq!(2, std::rc::Rc::strong_count(&value));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // FFI
+    fn test_move_to_thread() {
+        init();
+        let mf = MediaFile::open("test_files/test.mp3").unwrap();
+        let title = thread::spawn(move || mf.title()).join().unwrap();
+        assert_eq!(Some("00.uvod".to_string()), title);
+
+        let f = std::fs::File::open("test_files/test.mp3").unwrap();
+        let mf = MediaFile::open_seekable_stream(f).unwrap();
+        let duration = thread::spawn(move || {
+            let d = mf.duration();
+            drop(mf);
+            d
+        })
+        .join()
+        .unwrap();
+        assert_eq!(283, duration / 1_000);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // FFI
+    fn test_open_errors() {
+        init();
+        assert!(matches!(
+            MediaFile::open("test_files/non_existent.mp3"),
+            Err(Error::AVError(_))
+        ));
+        assert!(MediaFile::open_stream(std::io::empty()).is_err());
+        assert!(MediaFile::open_seekable_stream(std::io::Cursor::new(vec![0u8; 1024])).is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // FFI
     fn test_meta() {
         init();
         let mf = MediaFile::open("test_files/test.mp3").unwrap();
@@ -488,4 +1057,23 @@ mod tests {
             ffi::av_dump_format(mf.ctx, 0, ptr::null(), 0);
         }
     }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // FFI
+    fn test_open_stream() {
+        init();
+        let f = std::fs::File::open("test_files/test.mp3").unwrap();
+        let mf = MediaFile::open_stream(f).unwrap();
+        assert_eq!("00.uvod", mf.title().unwrap());
+        assert_eq!(Some((CodecKind::Mp3, 192)), mf.audio_codec());
+
+        let f = std::fs::File::open("test_files/test.mp3").unwrap();
+        let mf = MediaFile::open_seekable_stream(f).unwrap();
+        assert_eq!(283, mf.duration() / 1_000);
+        assert_eq!("Stoparuv pruvodce po galaxii", mf.album().unwrap());
+
+        let data = std::fs::read("test_files/test.mp3").unwrap();
+        let mf = MediaFile::open_seekable_stream(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(283, mf.duration() / 1_000);
+    }
 }
@@ -29,6 +29,11 @@ title        -- name of the work.
 track        -- number of this work in the set, can be in form current/total.
 variant_bitrate -- the total bitrate of the bitrate variant that the current stream is part of
 
+Loudness tags (ReplayGain, gain in dB like "-6.50 dB", peak as linear amplitude),
+R128 (opus) gains are integers in Q7.8 format relative to -23 LUFS
+replaygain_track_gain, replaygain_track_peak, replaygain_album_gain, replaygain_album_peak,
+r128_track_gain, r128_album_gain
+
 Following tags are not from ffmpeg documentation, but work for some formats mpeg4 aka .m4b
 series       -- name of the audiobook series
 series_sequence -- specifies the part of the series
@@ -65,6 +70,13 @@ pub const SHOW: &str = "show";
 pub const EPISODE_ID: &str = "episode_id";
 pub const SEASON_NUMBER: &str = "season_number";
 
+pub const REPLAYGAIN_TRACK_GAIN: &str = "replaygain_track_gain";
+pub const REPLAYGAIN_TRACK_PEAK: &str = "replaygain_track_peak";
+pub const REPLAYGAIN_ALBUM_GAIN: &str = "replaygain_album_gain";
+pub const REPLAYGAIN_ALBUM_PEAK: &str = "replaygain_album_peak";
+pub const R128_TRACK_GAIN: &str = "r128_track_gain";
+pub const R128_ALBUM_GAIN: &str = "r128_album_gain";
+
 pub const ALLOWED_TAGS: &[&str] = &[
     ALBUM,
     ALBUM_ARTIST,
@@ -0,0 +1,309 @@
+//! Minimal tagging support - basic tags and cover are updated by remuxing file with libavformat,
+//! audio streams are copied as they are (no transcoding).
+
+use super::{check_ret, ffi, Error, MediaFile, Packet, Result, AVERROR_EOF};
+use std::ffi::CString;
+use std::fs;
+use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::slice;
+
+/// Tags to change, `None` keeps current value, empty string removes tag
+#[derive(Debug, Clone, Default)]
+pub struct TagsUpdate {
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub artist: Option<String>,
+    pub track: Option<String>,
+    /// JPEG or PNG image, replaces all covers embedded in file
+    pub cover: Option<Vec<u8>>,
+}
+
+impl TagsUpdate {
+    fn tags(&self) -> [(&'static str, Option<&String>); 4] {
+        [
+            ("title", self.title.as_ref()),
+            ("album", self.album.as_ref()),
+            ("artist", self.artist.as_ref()),
+            ("track", self.track.as_ref()),
+        ]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cover.is_none() && self.tags().iter().all(|(_, v)| v.is_none())
+    }
+
+    unsafe fn apply(&self, dic: *mut *mut ffi::AVDictionary) -> Result<()> {
+        for (key, value) in self.tags() {
+            if let Some(value) = value {
+                let key = CString::new(key).unwrap();
+                let value = if value.is_empty() {
+                    None
+                } else {
+                    Some(
+                        CString::new(value.as_str())
+                            .map_err(|_| Error::InvalidTag(value.clone()))?,
+                    )
+                };
+                check_ret(ffi::av_dict_set(
+                    dic,
+                    key.as_ptr(),
+                    value.as_ref().map(|v| v.as_ptr()).unwrap_or(ptr::null()),
+                    0,
+                ))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Codec of cover image, detected from image signature
+fn cover_codec(data: &[u8]) -> Option<ffi::AVCodecID> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(ffi::AVCodecID_AV_CODEC_ID_MJPEG)
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(ffi::AVCodecID_AV_CODEC_ID_PNG)
+    } else {
+        None
+    }
+}
+
+fn check_neg(res: c_int) -> Result<()> {
+    if res < 0 {
+        Err(Error::AVError(res))
+    } else {
+        Ok(())
+    }
+}
+
+fn path_to_cstring(path: &Path) -> Result<CString> {
+    path.to_str()
+        .and_then(|p| CString::new(p).ok())
+        .ok_or_else(|| Error::InvalidPath(path.to_owned()))
+}
+
+struct OutputContext(*mut ffi::AVFormatContext);
+
+impl Drop for OutputContext {
+    fn drop(&mut self) {
+        if self.0.is_null() {
+            return;
+        }
+        unsafe {
+            if (*(*self.0).oformat).flags & ffi::AVFMT_NOFILE as c_int == 0 {
+                ffi::avio_closep(&mut (*self.0).pb);
+            }
+            // chapters are borrowed from input context
+            (*self.0).chapters = ptr::null_mut();
+            (*self.0).nb_chapters = 0;
+            ffi::avformat_free_context(self.0);
+        }
+    }
+}
+
+/// Copies audio streams (and existing covers, if cover is not changed) of input into output,
+/// output format is same as input - guessed from input file extension
+fn remux(input: &Path, output: &Path, update: &TagsUpdate) -> Result<()> {
+    let cover = match update.cover.as_ref() {
+        Some(data) => Some((data, cover_codec(data).ok_or(Error::UnsupportedCover)?)),
+        None => None,
+    };
+    let input_name = path_to_cstring(input)?;
+    let output_name = path_to_cstring(output)?;
+    let mf = MediaFile::open(input_name.to_str()?)?;
+    let in_ctx = mf.ctx;
+    let mut out = OutputContext(ptr::null_mut());
+    unsafe {
+        check_neg(ffi::avformat_alloc_output_context2(
+            &mut out.0,
+            ptr::null_mut(),
+            ptr::null(),
+            input_name.as_ptr(),
+        ))?;
+        let out_ctx = out.0;
+        check_neg(ffi::av_dict_copy(
+            &mut (*out_ctx).metadata,
+            (*in_ctx).metadata,
+            0,
+        ))?;
+        update.apply(&mut (*out_ctx).metadata)?;
+
+        let in_streams = slice::from_raw_parts((*in_ctx).streams, mf.streams_count());
+        let mut mapping = Vec::with_capacity(in_streams.len());
+        for &in_stream in in_streams {
+            let par = (*in_stream).codecpar;
+            let is_audio = (*par).codec_type == ffi::AVMediaType_AVMEDIA_TYPE_AUDIO;
+            let is_cover =
+                (*in_stream).disposition & ffi::AV_DISPOSITION_ATTACHED_PIC as c_int != 0;
+            if !(is_audio || is_cover && cover.is_none()) {
+                mapping.push(None);
+                continue;
+            }
+            let out_stream = ffi::avformat_new_stream(out_ctx, ptr::null());
+            if out_stream.is_null() {
+                return Err(Error::AllocationError);
+            }
+            check_neg(ffi::avcodec_parameters_copy((*out_stream).codecpar, par))?;
+            (*(*out_stream).codecpar).codec_tag = 0;
+            (*out_stream).time_base = (*in_stream).time_base;
+            (*out_stream).disposition = (*in_stream).disposition;
+            check_neg(ffi::av_dict_copy(
+                &mut (*out_stream).metadata,
+                (*in_stream).metadata,
+                0,
+            ))?;
+            // some formats (like ogg) keep tags in audio stream
+            if is_audio && !(*out_stream).metadata.is_null() {
+                update.apply(&mut (*out_stream).metadata)?;
+            }
+            mapping.push(Some(((*in_stream).time_base, out_stream)));
+        }
+
+        let cover_stream = match cover {
+            Some((_, codec_id)) => {
+                let st = ffi::avformat_new_stream(out_ctx, ptr::null());
+                if st.is_null() {
+                    return Err(Error::AllocationError);
+                }
+                (*(*st).codecpar).codec_type = ffi::AVMediaType_AVMEDIA_TYPE_VIDEO;
+                (*(*st).codecpar).codec_id = codec_id;
+                (*st).disposition = ffi::AV_DISPOSITION_ATTACHED_PIC as c_int;
+                (*st).time_base = ffi::AVRational { num: 1, den: 90000 };
+                Some(st)
+            }
+            None => None,
+        };
+
+        (*out_ctx).chapters = (*in_ctx).chapters;
+        (*out_ctx).nb_chapters = (*in_ctx).nb_chapters;
+
+        if (*(*out_ctx).oformat).flags & ffi::AVFMT_NOFILE as c_int == 0 {
+            check_neg(ffi::avio_open(
+                &mut (*out_ctx).pb,
+                output_name.as_ptr(),
+                ffi::AVIO_FLAG_WRITE as c_int,
+            ))?;
+        }
+        check_neg(ffi::avformat_write_header(out_ctx, ptr::null_mut()))?;
+
+        let pkt = Packet::new()?;
+        if let (Some((data, _)), Some(st)) = (cover, cover_stream) {
+            check_ret(ffi::av_new_packet(pkt.0, data.len() as c_int))?;
+            slice::from_raw_parts_mut((*pkt.0).data, data.len()).copy_from_slice(data);
+            (*pkt.0).stream_index = (*st).index;
+            (*pkt.0).flags |= ffi::AV_PKT_FLAG_KEY as c_int;
+            (*pkt.0).pts = 0;
+            (*pkt.0).dts = 0;
+            check_neg(ffi::av_interleaved_write_frame(out_ctx, pkt.0))?;
+        }
+
+        loop {
+            let ret = ffi::av_read_frame(in_ctx, pkt.0);
+            if ret == AVERROR_EOF {
+                break;
+            }
+            check_neg(ret)?;
+            match mapping
+                .get((*pkt.0).stream_index as usize)
+                .copied()
+                .flatten()
+            {
+                Some((in_time_base, out_stream)) => {
+                    (*pkt.0).stream_index = (*out_stream).index;
+                    ffi::av_packet_rescale_ts(pkt.0, in_time_base, (*out_stream).time_base);
+                    (*pkt.0).pos = -1;
+                    // packet is unreferenced by muxer
+                    check_neg(ffi::av_interleaved_write_frame(out_ctx, pkt.0))?;
+                }
+                None => ffi::av_packet_unref(pkt.0),
+            }
+        }
+        check_neg(ffi::av_write_trailer(out_ctx))?;
+    }
+    Ok(())
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.tagging", name))
+}
+
+/// Updates tags of media file in place - file is remuxed into temporary file in same directory,
+/// which then replaces original file
+pub fn update_tags(path: impl AsRef<Path>, update: &TagsUpdate) -> Result<()> {
+    let path = path.as_ref();
+    let tmp_path = temp_path(path);
+    let res = remux(path, &tmp_path, update).and_then(|_| {
+        fs::set_permissions(&tmp_path, fs::metadata(path)?.permissions())?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    });
+    if res.is_err() {
+        fs::remove_file(&tmp_path).ok();
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cover_codec() {
+        assert_eq!(
+            Some(ffi::AVCodecID_AV_CODEC_ID_MJPEG),
+            cover_codec(&[0xFF, 0xD8, 0xFF, 0xE0, 0, 0])
+        );
+        assert_eq!(
+            Some(ffi::AVCodecID_AV_CODEC_ID_PNG),
+            cover_codec(b"\x89PNG\r\n\x1a\n\0\0")
+        );
+        assert_eq!(None, cover_codec(b"GIF89a"));
+        assert_eq!(
+            Path::new("/tmp/book/.01.mp3.tagging"),
+            temp_path(Path::new("/tmp/book/01.mp3"))
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)] // FFI
+    fn test_update_tags() {
+        crate::init();
+        let dir = std::env::temp_dir().join(format!("media_info_write_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.mp3");
+        fs::copy("test_files/test.mp3", &path).unwrap();
+        let mut cover = vec![0xFF, 0xD8, 0xFF, 0xE0];
+        cover.extend([0u8; 1024]);
+        let update = TagsUpdate {
+            title: Some("Úvod".into()),
+            album: Some("".into()),
+            cover: Some(cover.clone()),
+            ..Default::default()
+        };
+        assert!(!update.is_empty());
+        update_tags(&path, &update).unwrap();
+
+        let mf = MediaFile::open(path.to_str().unwrap()).unwrap();
+        assert_eq!("Úvod", mf.title().unwrap());
+        assert!(mf.album().is_none());
+        assert_eq!("Vojtěch Dyk", mf.artist().unwrap());
+        assert_eq!(283, mf.duration() / 1_000);
+        assert_eq!(Some(cover), mf.cover());
+        assert!(!temp_path(&path).exists());
+
+        let res = update_tags(
+            &path,
+            &TagsUpdate {
+                cover: Some(b"GIF89a".to_vec()),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(res, Err(Error::UnsupportedCover)));
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,206 @@
+//! Minimal HTTP/1.1 client for outgoing requests (webhooks, scrobbling), one request per connection.
+//! With `tls` feature also https URLs are supported, server certificates are verified against
+//! system CA bundle (file from `SSL_CERT_FILE` environment variable or usual locations).
+
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context as _};
+use bytes::Bytes;
+use http::{header, HeaderMap, Method, Request, StatusCode};
+use http_body_util::{BodyExt, Full};
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use url::Url;
+
+use crate::error::Result;
+
+/// Response status and body
+pub type ClientResponse = (StatusCode, Bytes);
+
+/// Sends request to `url` and returns response, whole request must finish within `timeout`
+pub async fn send_request(
+    method: Method,
+    url: &Url,
+    headers: &HeaderMap,
+    body: Bytes,
+    timeout: Duration,
+) -> Result<ClientResponse> {
+    tokio::time::timeout(timeout, send_request_inner(method, url, headers, body))
+        .await
+        .map_err(|_| anyhow!("request to {} timed out", url))?
+}
+
+async fn send_request_inner(
+    method: Method,
+    url: &Url,
+    headers: &HeaderMap,
+    body: Bytes,
+) -> Result<ClientResponse> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("missing host in URL"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| anyhow!("unknown port for URL"))?;
+    let authority = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    };
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let mut req = Request::builder()
+        .method(method)
+        .uri(path)
+        .header(header::HOST, authority)
+        .body(Full::new(body))?;
+    req.headers_mut()
+        .extend(headers.iter().map(|(k, v)| (k.clone(), v.clone())));
+
+    let connect = || async {
+        TcpStream::connect((host, port))
+            .await
+            .with_context(|| format!("connect to {}", host))
+    };
+    match url.scheme() {
+        "http" => send(connect().await?, req).await,
+        #[cfg(feature = "tls")]
+        "https" => {
+            let domain = rustls_pki_types::ServerName::try_from(host.to_string())?;
+            let stream = tls::connector()?.connect(domain, connect().await?).await?;
+            send(stream, req).await
+        }
+        scheme => bail!("unsupported URL scheme {}", scheme),
+    }
+}
+
+async fn send<T>(stream: T, req: Request<Full<Bytes>>) -> Result<ClientResponse>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(async move {
+        if let Err(e) = conn.await {
+            debug!("Client connection error: {}", e);
+        }
+    });
+    let resp = sender.send_request(req).await?;
+    let status = resp.status();
+    let body = resp.into_body().collect().await?.to_bytes();
+    Ok((status, body))
+}
+
+#[cfg(feature = "tls")]
+mod tls {
+    use std::sync::{Arc, OnceLock};
+    use std::{fs, io};
+
+    use anyhow::anyhow;
+    use tokio_rustls::{rustls, TlsConnector};
+
+    use crate::error::Result;
+
+    const CA_BUNDLES: &[&str] = &[
+        "/etc/ssl/certs/ca-certificates.crt",
+        "/etc/pki/tls/certs/ca-bundle.crt",
+        "/etc/ssl/ca-bundle.pem",
+        "/etc/ssl/cert.pem",
+    ];
+
+    static CONFIG: OnceLock<Option<Arc<rustls::ClientConfig>>> = OnceLock::new();
+
+    fn load_roots() -> Option<rustls::RootCertStore> {
+        let files = std::env::var("SSL_CERT_FILE")
+            .ok()
+            .into_iter()
+            .chain(CA_BUNDLES.iter().map(|f| f.to_string()));
+        for file in files {
+            if let Ok(f) = fs::File::open(&file) {
+                let mut roots = rustls::RootCertStore::empty();
+                let mut reader = io::BufReader::new(f);
+                let certs = rustls_pemfile::certs(&mut reader).filter_map(|c| c.ok());
+                let (added, _) = roots.add_parsable_certificates(certs);
+                if added > 0 {
+                    debug!("Loaded {} CA certificates from {}", added, file);
+                    return Some(roots);
+                }
+            }
+        }
+        error!("No CA certificates found, https requests will fail (set SSL_CERT_FILE)");
+        None
+    }
+
+    pub(super) fn connector() -> Result<TlsConnector> {
+        CONFIG
+            .get_or_init(|| {
+                load_roots().map(|roots| {
+                    Arc::new(
+                        rustls::ClientConfig::builder()
+                            .with_root_certificates(roots)
+                            .with_no_client_auth(),
+                    )
+                })
+            })
+            .clone()
+            .map(TlsConnector::from)
+            .ok_or_else(|| anyhow!("no CA certificates available"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_send_request() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 4096];
+            let n = socket.read(&mut buf).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 201 Created\r\ncontent-length: 2\r\n\r\nok")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_string()
+        });
+        let url = Url::parse(&format!("http://{}/hook?x=1", addr))?;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-token", "secret".parse()?);
+        let (status, body) = send_request(
+            Method::POST,
+            &url,
+            &headers,
+            Bytes::from_static(b"{}"),
+            Duration::from_secs(5),
+        )
+        .await?;
+        assert_eq!(StatusCode::CREATED, status);
+        assert_eq!(&b"ok"[..], &body[..]);
+        let req = server.await?;
+        assert!(req.starts_with("POST /hook?x=1 HTTP/1.1"));
+        assert!(req.contains("x-token: secret"));
+        assert!(req.ends_with("{}"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_scheme() {
+        let url = Url::parse("ftp://127.0.0.1:1/x").unwrap();
+        let err = send_request(
+            Method::GET,
+            &url,
+            &HeaderMap::new(),
+            Bytes::new(),
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("unsupported URL scheme"));
+    }
+}
@@ -1,6 +1,7 @@
 #[macro_use]
 extern crate log;
 
+pub mod client;
 pub mod error;
 pub mod request;
 pub mod response;
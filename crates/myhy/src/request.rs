@@ -1,7 +1,9 @@
-use std::{borrow::Cow, collections::HashMap, fmt::Display, iter::once, net::IpAddr};
+use std::{
+    borrow::Cow, collections::HashMap, fmt::Display, iter::once, net::IpAddr, time::Duration,
+};
 
-use bytes::Bytes;
-use headers::{Header, HeaderMapExt, HeaderName, HeaderValue};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use headers::{ContentLength, Header, HeaderMapExt, HeaderName, HeaderValue};
 use http::Request;
 use http_body_util::BodyExt;
 use hyper::body::{Body, Incoming};
@@ -10,6 +12,7 @@ use regex::Regex;
 use url::form_urlencoded;
 
 use crate::error;
+use crate::response::{self, HttpResponse};
 
 pub struct AcceptEncoding(HeaderValue);
 
@@ -93,6 +96,42 @@ impl Display for RemoteIpAddr {
     }
 }
 
+/// Resolves client address - if behind proxy, client address is taken from Forwarded or X-Forwarded-For headers
+#[allow(unused_variables)]
+pub fn resolve_remote_addr(
+    headers: &http::HeaderMap,
+    remote_addr: Option<IpAddr>,
+    is_behind_proxy: bool,
+) -> Option<RemoteIpAddr> {
+    #[cfg(feature = "behind-proxy")]
+    if is_behind_proxy {
+        return headers
+            .typed_get::<proxy_headers::Forwarded>()
+            .and_then(|fwd| fwd.client().copied())
+            .map(RemoteIpAddr::Proxied)
+            .or_else(|| {
+                headers
+                    .typed_get::<proxy_headers::XForwardedFor>()
+                    .map(|xfwd| RemoteIpAddr::Proxied(*xfwd.client()))
+            });
+    }
+    remote_addr.map(RemoteIpAddr::Direct)
+}
+
+/// Collapses repeated slashes in decoded path (so encoded slash `%2F` is same as `/`),
+/// path then cannot contain empty segments, trailing slash is kept
+fn normalize_path(path: &str) -> String {
+    let mut normalized = String::with_capacity(path.len() + 1);
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if normalized.is_empty() || path.ends_with('/') {
+        normalized.push('/');
+    }
+    normalized
+}
+
 pub struct GenericRequestWrapper<T> {
     request: GenericRequest<T>,
     path: String,
@@ -103,6 +142,39 @@ pub struct GenericRequestWrapper<T> {
     is_behind_proxy: bool,
     can_compress: bool,
     is_cors: bool,
+    max_body_size: Option<u64>,
+    body_read_timeout: Option<Duration>,
+}
+
+/// Error of reading request body
+#[derive(Debug)]
+pub enum BodyError<E> {
+    /// body is bigger then limit (in bytes)
+    TooLarge(u64),
+    /// whole body was not received within read timeout
+    Timeout,
+    Read(E),
+}
+
+impl<E: Display> Display for BodyError<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BodyError::TooLarge(limit) => write!(f, "Body is larger then limit {} bytes", limit),
+            BodyError::Timeout => write!(f, "Timeout while reading body"),
+            BodyError::Read(e) => write!(f, "Error reading body: {}", e),
+        }
+    }
+}
+
+impl<E> BodyError<E> {
+    /// Error response for this error
+    pub fn response(&self) -> HttpResponse {
+        match self {
+            BodyError::TooLarge(_) => response::payload_too_large(),
+            BodyError::Timeout => response::request_timeout(),
+            BodyError::Read(_) => response::bad_request(),
+        }
+    }
 }
 
 //Builder pattern for options
@@ -135,11 +207,30 @@ impl<T> GenericRequestWrapper<T> {
         self
     }
 
+    /// Limits for reading of request body - maximum size in bytes and timeout for reading whole body
+    pub fn set_body_limits(
+        mut self,
+        max_body_size: Option<u64>,
+        body_read_timeout: Option<Duration>,
+    ) -> Self {
+        self.max_body_size = max_body_size;
+        self.body_read_timeout = body_read_timeout;
+        self
+    }
+
+    /// Replaces path used for routing (original request URI is kept)
+    pub fn set_path(mut self, path: String) -> Self {
+        self.path = path;
+        self
+    }
+
+    /// Strips URL path prefix, which must match whole path segments
     pub fn set_path_prefix(mut self, path_prefix: Option<&str>) -> error::Result<Self> {
         self.path = match path_prefix {
             Some(p) => self
                 .path
                 .strip_prefix(p)
+                .filter(|rest| rest.is_empty() || rest.starts_with('/'))
                 .map(|p| p.to_string())
                 .ok_or_else(|| {
                     error!("URL path is missing prefix {}", p);
@@ -172,6 +263,7 @@ where
                 "Illegal path, contains either special directories or hidden name",
             ));
         }
+        let path = normalize_path(&path);
 
         Ok(GenericRequestWrapper {
             request,
@@ -181,6 +273,8 @@ where
             is_behind_proxy: false,
             can_compress: false,
             is_cors: false,
+            max_body_size: None,
+            body_read_timeout: None,
         })
     }
 
@@ -189,22 +283,11 @@ where
     }
 
     pub fn remote_addr(&self) -> Option<RemoteIpAddr> {
-        #[cfg(feature = "behind-proxy")]
-        if self.is_behind_proxy {
-            return self
-                .request
-                .headers()
-                .typed_get::<proxy_headers::Forwarded>()
-                .and_then(|fwd| fwd.client().copied())
-                .map(RemoteIpAddr::Proxied)
-                .or_else(|| {
-                    self.request
-                        .headers()
-                        .typed_get::<proxy_headers::XForwardedFor>()
-                        .map(|xfwd| RemoteIpAddr::Proxied(*xfwd.client()))
-                });
-        }
-        self.remote_addr.map(RemoteIpAddr::Direct)
+        resolve_remote_addr(
+            self.request.headers(),
+            self.remote_addr,
+            self.is_behind_proxy,
+        )
     }
 
     pub fn headers(&self) -> &http::HeaderMap {
@@ -267,9 +350,40 @@ where
         self.can_compress
     }
 
-    pub async fn body_bytes(&mut self) -> Result<Bytes, T::Error> {
+    /// Reads whole body, respecting limits set by `set_body_limits`
+    pub async fn body_bytes(&mut self) -> Result<Bytes, BodyError<T::Error>> {
+        let max_size = self.max_body_size;
+        let declared_size = self
+            .request
+            .headers()
+            .typed_get::<ContentLength>()
+            .map(|l| l.0);
+        if let (Some(max_size), Some(size)) = (max_size, declared_size) {
+            if size > max_size {
+                return Err(BodyError::TooLarge(max_size));
+            }
+        }
         let body = self.request.body_mut();
-        body.collect().await.map(|collected| collected.to_bytes())
+        let read = async move {
+            let mut data = BytesMut::new();
+            while let Some(frame) = body.frame().await {
+                if let Ok(chunk) = frame.map_err(BodyError::Read)?.into_data() {
+                    if let Some(max_size) = max_size {
+                        if (data.len() + chunk.remaining()) as u64 > max_size {
+                            return Err(BodyError::TooLarge(max_size));
+                        }
+                    }
+                    data.put(chunk);
+                }
+            }
+            Ok(data.freeze())
+        };
+        match self.body_read_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, read)
+                .await
+                .map_err(|_| BodyError::Timeout)?,
+            None => read.await,
+        }
     }
 
     pub fn is_cors_enabled(&self) -> bool {
@@ -300,7 +414,7 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::response::body::empty_body;
+    use crate::response::body::{empty_body, full_body, wrap_stream, HttpBody};
 
     use super::*;
 
@@ -343,4 +457,74 @@ mod tests {
         assert_eq!(params.get("a"), Some(&Cow::Borrowed("1")));
         assert_eq!(params.get("b"), Some(&Cow::Borrowed("2")));
     }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!("/", normalize_path("/"));
+        assert_eq!("/", normalize_path("//"));
+        assert_eq!("/0/folder/a/b", normalize_path("/0//folder/a///b"));
+        assert_eq!("/0/folder/", normalize_path("/0/folder//"));
+        assert_eq!("/a b/c", normalize_path("a b/c"));
+    }
+
+    fn wrapper(uri: &str) -> error::Result<GenericRequestWrapper<crate::response::body::HttpBody>> {
+        let req = Request::builder().uri(uri).body(empty_body()).unwrap();
+        GenericRequestWrapper::new(req)
+    }
+
+    #[test]
+    fn test_request_path() {
+        let req = wrapper("/0/folder/author%2F%2Fbook%20one/").unwrap();
+        assert_eq!("/0/folder/author/book one/", req.path());
+        let req = wrapper("//api/authors").unwrap();
+        assert_eq!("/api/authors", req.path());
+        assert!(wrapper("/0/folder/%2E%2E/etc").is_err());
+        assert!(wrapper("/0/folder/a%2F.hidden").is_err());
+
+        let req = wrapper("/prefix//collections").unwrap();
+        let req = req.set_path_prefix(Some("/prefix")).unwrap();
+        assert_eq!("/collections", req.path());
+        let req = wrapper("/prefix").unwrap();
+        assert_eq!("", req.set_path_prefix(Some("/prefix")).unwrap().path());
+        assert!(wrapper("/prefixed/collections")
+            .unwrap()
+            .set_path_prefix(Some("/prefix"))
+            .is_err());
+    }
+
+    fn post_request(body: HttpBody, size: Option<u64>) -> GenericRequestWrapper<HttpBody> {
+        let mut builder = Request::builder().method("POST").uri("/positions");
+        if let Some(size) = size {
+            builder = builder.header("Content-Length", size);
+        }
+        GenericRequestWrapper::new(builder.body(body).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_body_limits() {
+        let mut req = post_request(full_body("0123456789"), None);
+        assert_eq!(b"0123456789", req.body_bytes().await.unwrap().as_ref());
+
+        let mut req = post_request(full_body("0123456789"), None).set_body_limits(Some(10), None);
+        assert_eq!(10, req.body_bytes().await.unwrap().len());
+
+        let mut req = post_request(full_body("0123456789"), None).set_body_limits(Some(5), None);
+        assert!(matches!(
+            req.body_bytes().await,
+            Err(BodyError::TooLarge(5))
+        ));
+
+        // rejected by declared length, without reading the body
+        let mut req = post_request(empty_body(), Some(1_000_000)).set_body_limits(Some(5), None);
+        let err = req.body_bytes().await.unwrap_err();
+        assert!(matches!(err, BodyError::TooLarge(5)));
+        assert_eq!(413, err.response().status().as_u16());
+
+        let never_ending = wrap_stream(futures::stream::pending::<Result<Bytes, std::io::Error>>());
+        let mut req =
+            post_request(never_ending, None).set_body_limits(None, Some(Duration::from_millis(10)));
+        let err = req.body_bytes().await.unwrap_err();
+        assert!(matches!(err, BodyError::Timeout));
+        assert_eq!(408, err.response().status().as_u16());
+    }
 }
@@ -1,7 +1,13 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
 use bytes::Bytes;
 use futures_util::{Stream, StreamExt};
 use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full, StreamBody};
-use hyper::body::Frame;
+use hyper::body::{Body, Frame, SizeHint};
+use tokio::time::{Instant, Sleep};
 
 pub type HttpBody = BoxBody<Bytes, std::io::Error>;
 
@@ -25,3 +31,93 @@ where
     let body = StreamBody::new(stream.map(|res| res.map(|data| Frame::data(data.into()))));
     BodyExt::boxed(body)
 }
+
+/// Calls `f` for each data chunk of the body, as it's sent
+pub fn inspect_body<F>(body: HttpBody, mut f: F) -> HttpBody
+where
+    F: FnMut(&Bytes) + Send + Sync + 'static,
+{
+    body.map_frame(move |frame| {
+        if let Some(data) = frame.data_ref() {
+            f(data)
+        }
+        frame
+    })
+    .boxed()
+}
+
+/// Body, which is sent with limited speed (in bytes per second)
+pub struct ThrottledBody {
+    inner: HttpBody,
+    bytes_per_sec: u64,
+    start: Option<Instant>,
+    sent: u64,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl Body for ThrottledBody {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if let Some(delay) = self.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        if let Some(data) = frame
+            .as_ref()
+            .and_then(|f| f.as_ref().ok())
+            .and_then(|f| f.data_ref())
+        {
+            let start = *self.start.get_or_insert_with(Instant::now);
+            self.sent += data.len() as u64;
+            // next frame can be sent when average speed from start drops to limit
+            let next =
+                start + Duration::from_secs_f64(self.sent as f64 / self.bytes_per_sec as f64);
+            if next > Instant::now() {
+                self.delay = Some(Box::pin(tokio::time::sleep_until(next)));
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Limits speed of sending body to `bytes_per_sec`
+pub fn throttle_body(body: HttpBody, bytes_per_sec: u64) -> HttpBody {
+    ThrottledBody {
+        inner: body,
+        bytes_per_sec: bytes_per_sec.max(1),
+        start: None,
+        sent: 0,
+        delay: None,
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_throttle_body() {
+        let chunks = (0..3).map(|_| Ok::<_, std::io::Error>(vec![0u8; 1000]));
+        let body = throttle_body(wrap_stream(futures::stream::iter(chunks)), 10_000);
+        let start = Instant::now();
+        let data = body.collect().await.unwrap().to_bytes();
+        assert_eq!(3000, data.len());
+        // first chunk is sent immediately, then each one after 0.1 sec
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
@@ -3,7 +3,7 @@ use headers::{
     AccessControlAllowOrigin, AccessControlExposeHeaders, AccessControlMaxAge,
     AccessControlRequestHeaders, Header, HeaderMapExt, Origin,
 };
-use http::header::DATE;
+use http::header::{HeaderName, DATE};
 use http::{Method, Response, StatusCode};
 use std::time::Duration;
 
@@ -11,6 +11,18 @@ use super::super::request::HttpRequest;
 use super::HttpResponse;
 use super::{body::empty_body, ResponseBuilderExt};
 
+/// custom headers, which should be readable by client scripts
+const EXPOSED_HEADERS: &[&str] = &[
+    "x-transcode",
+    "x-transcode-decision",
+    "x-content-duration",
+    "x-estimated-content-length",
+    "x-error-version",
+    "x-collection-scanning",
+    "deprecation",
+    "link",
+];
+
 fn header2header<H1: Header, H2: Header>(i: H1) -> Result<impl Header, headers::Error> {
     let mut v = vec![];
     i.encode(&mut v);
@@ -27,6 +39,7 @@ pub fn add_cors_headers(mut resp: HttpResponse, origin: Option<Origin>) -> HttpR
                 headers.typed_insert(
                     vec![DATE]
                         .into_iter()
+                        .chain(EXPOSED_HEADERS.iter().map(|h| HeaderName::from_static(h)))
                         .collect::<AccessControlExposeHeaders>(),
                 );
             }
@@ -38,7 +51,7 @@ pub fn add_cors_headers(mut resp: HttpResponse, origin: Option<Origin>) -> HttpR
 
 pub fn preflight_cors_response(req: &HttpRequest) -> HttpResponse {
     let origin = req.headers().typed_get::<Origin>();
-    const ALLOWED_METHODS: &[Method] = &[Method::GET, Method::POST, Method::OPTIONS];
+    const ALLOWED_METHODS: &[Method] = &[Method::GET, Method::POST, Method::PUT, Method::OPTIONS];
 
     let mut resp_builder = Response::builder()
         .status(StatusCode::NO_CONTENT)
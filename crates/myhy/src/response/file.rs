@@ -14,7 +14,8 @@ use super::{
     add_cache_headers,
     body::wrap_stream,
     compress::{make_sense_to_compress, CompressStream},
-    not_found, ChunkStream, HttpResponse, ResponseBuilderExt, ResponseResult,
+    file_etag, not_found, not_modified, ChunkStream, Conditionals, HttpResponse,
+    ResponseBuilderExt, ResponseResult,
 };
 use crate::error::Error;
 
@@ -29,6 +30,7 @@ pub async fn serve_opened_file(
     range: Option<ByteRange>,
     caching: Option<u32>,
     mime: mime::Mime,
+    conditionals: Option<&Conditionals>,
 ) -> Result<HttpResponse, io::Error> {
     let meta = file.metadata().await?;
     let file_len = meta.len();
@@ -38,6 +40,13 @@ pub async fn serve_opened_file(
     let last_modified = meta.modified().ok();
     let mut resp = Response::builder().typed_header(ContentType::from(mime));
     resp = add_cache_headers(resp, caching, last_modified);
+    if let Some(conditionals) = conditionals {
+        let etag = file_etag(file_len, last_modified);
+        if conditionals.is_not_modified(&etag, last_modified) {
+            return Ok(not_modified(Some(etag)));
+        }
+        resp = resp.typed_header(etag);
+    }
 
     let full_range = || (0, file_len.saturating_sub(1));
 
@@ -109,15 +118,16 @@ pub async fn serve_file_from_fs(
     range: Option<ByteRange>,
     caching: Option<u32>,
     compressed: bool,
+    conditionals: Option<&Conditionals>,
 ) -> ResponseResult {
     let filename: PathBuf = full_path.into();
     match fs::File::open(&filename).await {
         Ok(file) => {
             let mime = guess_mime_type(&filename);
             if compressed {
-                serve_compressed_file(file, caching, mime).await
+                serve_compressed_file(file, caching, mime, conditionals).await
             } else {
-                serve_opened_file(file, range, caching, mime).await
+                serve_opened_file(file, range, caching, mime, conditionals).await
             }
             .map_err(Error::new)
         }
@@ -132,6 +142,7 @@ async fn serve_compressed_file(
     file: tokio::fs::File,
     caching: Option<u32>,
     mime: mime::Mime,
+    conditionals: Option<&Conditionals>,
 ) -> Result<HttpResponse, io::Error> {
     let meta = file.metadata().await?;
     let last_modified = meta.modified().ok();
@@ -139,6 +150,13 @@ async fn serve_compressed_file(
 
     let mut resp = Response::builder().typed_header(ContentType::from(mime));
     resp = add_cache_headers(resp, caching, last_modified);
+    if let Some(conditionals) = conditionals {
+        let etag = file_etag(file_size, last_modified);
+        if conditionals.is_not_modified(&etag, last_modified) {
+            return Ok(not_modified(Some(etag)));
+        }
+        resp = resp.typed_header(etag);
+    }
     resp = resp.status(StatusCode::OK);
 
     let body = if make_sense_to_compress(file_size) {
@@ -160,15 +178,17 @@ pub async fn send_file_simple<P: AsRef<Path>>(
     file_path: P,
     cache: Option<u32>,
     compressed: bool,
+    conditionals: Option<&Conditionals>,
 ) -> ResponseResult {
     let full_path = base_path.join(&file_path);
-    serve_file_from_fs(&full_path, None, cache, compressed).await
+    serve_file_from_fs(&full_path, None, cache, compressed, conditionals).await
 }
 
 pub async fn send_static_file<P: AsRef<Path> + Send>(
     base_path: &'static Path,
     file_path: P,
     cache: Option<u32>,
+    conditionals: Option<&Conditionals>,
 ) -> ResponseResult {
     let full_path = base_path.join(&file_path);
     fn append_ext(ext: impl AsRef<OsStr>, path: &PathBuf) -> PathBuf {
@@ -181,11 +201,13 @@ pub async fn send_static_file<P: AsRef<Path> + Send>(
     if let Ok(true) = fs::try_exists(&compressed_name).await {
         let mime = guess_mime_type(&full_path);
         let file = fs::File::open(compressed_name).await?;
-        let mut resp = serve_opened_file(file, None, cache, mime).await?;
-        resp.headers_mut()
-            .insert(CONTENT_ENCODING, "gzip".parse().unwrap());
+        let mut resp = serve_opened_file(file, None, cache, mime, conditionals).await?;
+        if resp.status() != StatusCode::NOT_MODIFIED {
+            resp.headers_mut()
+                .insert(CONTENT_ENCODING, "gzip".parse().unwrap());
+        }
         Ok(resp)
     } else {
-        serve_file_from_fs(&full_path, None, cache, false).await
+        serve_file_from_fs(&full_path, None, cache, false, conditionals).await
     }
 }
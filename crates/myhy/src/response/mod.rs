@@ -1,4 +1,6 @@
+use std::collections::hash_map::DefaultHasher;
 use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::task::{Context, Poll};
 use std::{future::Future, pin::Pin, time::SystemTime};
@@ -7,10 +9,11 @@ use body::empty_body;
 use bytes::Bytes;
 use futures::prelude::*;
 use headers::{
-    CacheControl, ContentEncoding, ContentLength, ContentType, Header, HeaderMapExt, LastModified,
+    CacheControl, ContentEncoding, ContentLength, ContentType, ETag, Header, HeaderMapExt,
+    IfModifiedSince, IfNoneMatch, LastModified,
 };
 use http::response::Builder;
-use http::{header, Response, StatusCode};
+use http::{header, HeaderMap, Response, StatusCode};
 use http_body_util::BodyExt;
 use hyper::body::Body;
 use mime::Mime;
@@ -28,11 +31,21 @@ pub mod file;
 const NOT_FOUND_MESSAGE: &str = "Not Found";
 const TOO_MANY_REQUESTS_MSG: &str = "Too many requests";
 const ACCESS_DENIED_MSG: &str = "Access denied";
+const FORBIDDEN_MSG: &str = "Forbidden";
 const METHOD_NOT_ALLOWED_MSG: &str = "Method not supported";
 const BAD_REQUEST_MSG: &str = "Bad request";
 const NOT_IMPLEMENTED_MSG: &str = "Not Implemented";
 const INTERNAL_SERVER_ERROR: &str = "Internal server error";
 const UNPROCESSABLE_ENTITY: &str = "Ignored";
+const CONFLICT_MSG: &str = "Conflict";
+const SERVICE_UNAVAILABLE_MSG: &str = "Service unavailable";
+const PAYLOAD_TOO_LARGE_MSG: &str = "Payload too large";
+const REQUEST_TIMEOUT_MSG: &str = "Request timeout";
+
+/// Version of JSON error envelope, it's sent in [`ERROR_VERSION_HEADER`] of error responses
+/// and must be incremented on incompatible change of the envelope
+pub const ERROR_ENVELOPE_VERSION: &str = "1";
+pub const ERROR_VERSION_HEADER: &str = "X-Error-Version";
 
 pub type HttpResponse = Response<HttpBody>;
 pub type ResponseResult = Result<HttpResponse, Error>;
@@ -60,7 +73,10 @@ where
     Response::from_parts(parts, body)
 }
 
-fn short_response(status: StatusCode, msg: &'static str) -> HttpResponse {
+fn short_response(status: StatusCode, code: &'static str, msg: &'static str) -> HttpResponse {
+    if status.is_client_error() || status.is_server_error() {
+        return error_response(status, code, msg, None);
+    }
     Response::builder()
         .status(status)
         .typed_header(ContentLength(msg.len() as u64))
@@ -69,15 +85,45 @@ fn short_response(status: StatusCode, msg: &'static str) -> HttpResponse {
         .unwrap()
 }
 
-pub fn not_found_cached(caching: Option<u32>) -> HttpResponse {
-    let mut builder = Response::builder()
-        .status(StatusCode::NOT_FOUND)
-        .typed_header(ContentLength(NOT_FOUND_MESSAGE.len() as u64))
-        .typed_header(ContentType::text());
+fn error_builder(
+    status: StatusCode,
+    code: &str,
+    msg: &str,
+    detail: Option<&str>,
+) -> (Builder, String) {
+    let body = serde_json::json!({
+        "code": code,
+        "message": msg,
+        "detail": detail,
+    })
+    .to_string();
+    let builder = Response::builder()
+        .status(status)
+        .typed_header(ContentLength(body.len() as u64))
+        .typed_header(ContentType::json())
+        .header(ERROR_VERSION_HEADER, ERROR_ENVELOPE_VERSION);
+    (builder, body)
+}
 
-    builder = add_cache_headers(builder, caching, None);
+/// Error response with JSON body (envelope) `{"code": ..., "message": ..., "detail": ...}`,
+/// where code is machine readable (snake case) identification of the error,
+/// message is short human readable description and detail (can be null) describes particular case
+pub fn error_response(
+    status: StatusCode,
+    code: &str,
+    msg: &str,
+    detail: Option<&str>,
+) -> HttpResponse {
+    let (builder, body) = error_builder(status, code, msg, detail);
+    builder.body(full_body(body)).unwrap()
+}
 
-    builder.body(full_body(NOT_FOUND_MESSAGE)).unwrap()
+pub fn not_found_cached(caching: Option<u32>) -> HttpResponse {
+    let (builder, body) =
+        error_builder(StatusCode::NOT_FOUND, "not_found", NOT_FOUND_MESSAGE, None);
+    add_cache_headers(builder, caching, None)
+        .body(full_body(body))
+        .unwrap()
 }
 
 #[inline]
@@ -89,26 +135,34 @@ where
 }
 
 macro_rules! def_resp {
-    ($($name:ident ( $code:expr, $msg:expr ));+) => {
+    ($($name:ident ( $status:expr, $code:expr, $msg:expr ));+) => {
         $(
         #[allow(dead_code)]
         pub fn $name() -> HttpResponse {
-            short_response($code, $msg)
+            short_response($status, $code, $msg)
         }
     )+
     }
 }
 
+// error responses have generic codes, more specific ones can be used with error_response
 def_resp!(
-    deny(StatusCode::UNAUTHORIZED, ACCESS_DENIED_MSG);
-    too_many_requests(StatusCode::TOO_MANY_REQUESTS, TOO_MANY_REQUESTS_MSG);
-    not_found(StatusCode::NOT_FOUND, NOT_FOUND_MESSAGE);
-    method_not_supported(StatusCode::METHOD_NOT_ALLOWED, METHOD_NOT_ALLOWED_MSG);
-    bad_request(StatusCode::BAD_REQUEST, BAD_REQUEST_MSG);
-    not_implemented(StatusCode::NOT_IMPLEMENTED, NOT_IMPLEMENTED_MSG);
-    created(StatusCode::CREATED, "");
-    internal_error(StatusCode::INTERNAL_SERVER_ERROR, INTERNAL_SERVER_ERROR);
-    ignored(StatusCode::UNPROCESSABLE_ENTITY, UNPROCESSABLE_ENTITY)
+    deny(StatusCode::UNAUTHORIZED, "access_denied", ACCESS_DENIED_MSG);
+    forbidden(StatusCode::FORBIDDEN, "forbidden", FORBIDDEN_MSG);
+    too_many_requests(StatusCode::TOO_MANY_REQUESTS, "too_many_requests", TOO_MANY_REQUESTS_MSG);
+    not_found(StatusCode::NOT_FOUND, "not_found", NOT_FOUND_MESSAGE);
+    method_not_supported(StatusCode::METHOD_NOT_ALLOWED, "method_not_supported", METHOD_NOT_ALLOWED_MSG);
+    bad_request(StatusCode::BAD_REQUEST, "bad_request", BAD_REQUEST_MSG);
+    not_implemented(StatusCode::NOT_IMPLEMENTED, "not_implemented", NOT_IMPLEMENTED_MSG);
+    created(StatusCode::CREATED, "", "");
+    accepted(StatusCode::ACCEPTED, "", "");
+    no_content(StatusCode::NO_CONTENT, "", "");
+    conflict(StatusCode::CONFLICT, "conflict", CONFLICT_MSG);
+    internal_error(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", INTERNAL_SERVER_ERROR);
+    ignored(StatusCode::UNPROCESSABLE_ENTITY, "ignored", UNPROCESSABLE_ENTITY);
+    service_unavailable(StatusCode::SERVICE_UNAVAILABLE, "service_unavailable", SERVICE_UNAVAILABLE_MSG);
+    payload_too_large(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", PAYLOAD_TOO_LARGE_MSG);
+    request_timeout(StatusCode::REQUEST_TIMEOUT, "request_timeout", REQUEST_TIMEOUT_MSG)
 );
 
 pub fn redirect_permanent(url: &str) -> HttpResponse {
@@ -165,10 +219,109 @@ where
     resp.body(full_body(buf)).map_err(Error::from).unwrap()
 }
 
+pub fn not_modified(etag: Option<ETag>) -> HttpResponse {
+    let mut builder = Response::builder().status(StatusCode::NOT_MODIFIED);
+    if let Some(etag) = etag {
+        builder = builder.typed_header(etag);
+    }
+    builder.body(empty_body()).unwrap()
+}
+
+/// Creates weak ETag from given numeric tag (usually some hash)
+pub fn weak_etag(tag: u64) -> ETag {
+    format!("W/\"{:016x}\"", tag)
+        .parse()
+        .expect("valid etag format")
+}
+
+/// Weak ETag for file content, based on file size and modification time
+pub fn file_etag(len: u64, modified: Option<SystemTime>) -> ETag {
+    let mut hasher = DefaultHasher::new();
+    len.hash(&mut hasher);
+    modified.hash(&mut hasher);
+    weak_etag(hasher.finish())
+}
+
+/// Conditional request headers used for cache revalidation
+#[derive(Debug, Clone, Default)]
+pub struct Conditionals {
+    pub if_none_match: Option<IfNoneMatch>,
+    pub if_modified_since: Option<IfModifiedSince>,
+}
+
+impl Conditionals {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Conditionals {
+            if_none_match: headers.typed_get(),
+            if_modified_since: headers.typed_get(),
+        }
+    }
+
+    /// If-None-Match takes precedence, If-Modified-Since is only considered without it (RFC 7232)
+    pub fn is_not_modified(&self, etag: &ETag, last_modified: Option<SystemTime>) -> bool {
+        if self.if_none_match.is_some() {
+            is_not_modified(etag, self.if_none_match.as_ref())
+        } else {
+            match (self.if_modified_since.as_ref(), last_modified) {
+                (Some(ims), Some(lm)) => !ims.is_modified(lm),
+                _ => false,
+            }
+        }
+    }
+}
+
+fn is_not_modified(etag: &ETag, if_none_match: Option<&IfNoneMatch>) -> bool {
+    if_none_match
+        .map(|inm| !inm.precondition_passes(etag))
+        .unwrap_or(false)
+}
+
+/// JSON response with ETag header, if etag is not provided it's calculated from JSON content.
+/// If etag matches If-None-Match header, 304 Not Modified response is returned instead.
+pub fn json_response_with_etag<T: serde::Serialize>(
+    data: &T,
+    etag: Option<ETag>,
+    if_none_match: Option<&IfNoneMatch>,
+    compress: bool,
+) -> HttpResponse {
+    if let Some(ref etag) = etag {
+        if is_not_modified(etag, if_none_match) {
+            return not_modified(Some(etag.clone()));
+        }
+    }
+    let json = serde_json::to_string(data).expect("Serialization error");
+    let etag = match etag {
+        Some(etag) => etag,
+        None => {
+            let mut hasher = DefaultHasher::new();
+            json.hash(&mut hasher);
+            let etag = weak_etag(hasher.finish());
+            if is_not_modified(&etag, if_none_match) {
+                return not_modified(Some(etag));
+            }
+            etag
+        }
+    };
+    let builder = Response::builder()
+        .typed_header(ContentType::json())
+        .typed_header(etag);
+    json_body_response(builder, json, compress)
+}
+
 pub fn json_response<T: serde::Serialize>(data: &T, compress: bool) -> HttpResponse {
     let json = serde_json::to_string(data).expect("Serialization error");
 
     let builder = Response::builder().typed_header(ContentType::json());
+    json_body_response(builder, json, compress)
+}
+
+/// Response for already serialized JSON
+pub fn json_string_response(json: String, compress: bool) -> HttpResponse {
+    let builder = Response::builder().typed_header(ContentType::json());
+    json_body_response(builder, json, compress)
+}
+
+fn json_body_response(builder: Builder, json: String, compress: bool) -> HttpResponse {
     if compress && make_sense_to_compress(json.len()) {
         compressed_response(builder, json.into_bytes())
     } else {
@@ -235,3 +388,75 @@ impl<T: AsyncRead> ChunkStream<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_error_response() {
+        let resp = error_response(
+            StatusCode::NOT_FOUND,
+            "folder_not_found",
+            "Folder not found",
+            Some("Book/CD1"),
+        );
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        assert_eq!(
+            ERROR_ENVELOPE_VERSION,
+            resp.headers().get(ERROR_VERSION_HEADER).unwrap()
+        );
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!("folder_not_found", value["code"]);
+        assert_eq!("Book/CD1", value["detail"]);
+
+        let resp = bad_request();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!("bad_request", value["code"]);
+        assert!(value["detail"].is_null());
+        assert_eq!(StatusCode::CREATED, created().status());
+    }
+
+    #[test]
+    fn test_json_etag() {
+        let data = vec!["usak", "kulisak"];
+        let resp = json_response_with_etag(&data, None, None, false);
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers().typed_get::<ETag>().expect("has etag");
+
+        let inm = IfNoneMatch::from(etag.clone());
+        let resp = json_response_with_etag(&data, None, Some(&inm), false);
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+        let resp = json_response_with_etag(&data, Some(weak_etag(1)), Some(&inm), false);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(Some(weak_etag(1)), resp.headers().typed_get::<ETag>());
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        let etag = file_etag(1024, Some(modified));
+        assert_eq!(etag, file_etag(1024, Some(modified)));
+        assert_ne!(etag, file_etag(1025, Some(modified)));
+
+        assert!(!Conditionals::default().is_not_modified(&etag, Some(modified)));
+
+        let cond = Conditionals {
+            if_none_match: Some(IfNoneMatch::from(etag.clone())),
+            if_modified_since: None,
+        };
+        assert!(cond.is_not_modified(&etag, Some(modified)));
+        assert!(!cond.is_not_modified(&weak_etag(1), Some(modified)));
+
+        let cond = Conditionals {
+            if_none_match: None,
+            if_modified_since: Some(IfModifiedSince::from(modified)),
+        };
+        assert!(cond.is_not_modified(&etag, Some(modified)));
+        let later = modified + std::time::Duration::from_secs(10);
+        assert!(!cond.is_not_modified(&etag, Some(later)));
+    }
+}
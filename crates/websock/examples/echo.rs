@@ -75,6 +75,7 @@ async fn server_upgrade(req: Request<Incoming>) -> Result<Response<Empty<Bytes>>
         process_message,
         0,
         Some(Duration::from_secs(5 * 60)),
+        Some(Duration::from_secs(30)),
     ))
 }
 
@@ -14,6 +14,7 @@ use hyper_util::rt::TokioIo;
 use std::io;
 use std::{fmt, time::Duration};
 use thiserror::Error;
+use tokio::time::{self, Instant};
 use tokio_tungstenite::{
     tungstenite::{self, protocol},
     WebSocketStream,
@@ -71,6 +72,10 @@ fn header_matches<S: AsHeaderName>(headers: &HeaderMap<HeaderValue>, name: S, va
 /// Optionally timeout can be given, which closes websocket in no
 /// message arrives within given time
 ///
+/// Optionally also ping interval can be given - server then sends ping
+/// in this interval and if there is no response (pong or any other message) till next ping,
+/// connection is considered dead and closed. Pongs do not count as messages for the timeout.
+///
 /// This function returns immediate HTTP response, which is either of status
 /// 101 Protocol upgrade, if websocket handshake is OK, or of status 400, if
 /// handshake was no successful.
@@ -80,6 +85,7 @@ pub fn spawn_websocket<T, P>(
     mut f: P,
     initial_context: T,
     timeout: Option<Duration>,
+    ping_interval: Option<Duration>,
 ) -> Response<Empty<Bytes>>
 where
     T: Send + Sync + 'static,
@@ -92,28 +98,45 @@ where
                 match ws_future.await {
                     Err(_) => error!("Failed upgrade to websocket"),
                     Ok(mut ws) => {
+                        let mut deadline = timeout.map(|d| Instant::now() + d);
+                        let mut ping_timer =
+                            ping_interval.map(|d| time::interval_at(Instant::now() + d, d));
+                        let mut awaiting_pong = false;
                         loop {
-                            let next = async {
-                                match timeout {
-                                    None => Ok(ws.next().await),
-                                    Some(d) => tokio::time::timeout(d, ws.next()).await,
-                                }
-                            };
-                            match next.await {
-                                Err(_) => {
+                            let next = tokio::select! {
+                                _ = sleep_until_opt(deadline) => {
                                     debug!("Timeout on websocket - let's close");
                                     //TODO: Send Close or just break?
                                     break;
                                 }
-
-                                Ok(None) => {
+                                _ = tick_opt(&mut ping_timer) => {
+                                    if awaiting_pong {
+                                        debug!("No response to ping on websocket - connection is dead");
+                                        break;
+                                    }
+                                    if let Err(e) = ws.send(Message::ping()).await {
+                                        error!("error sending ping message: {}", e);
+                                        break;
+                                    }
+                                    awaiting_pong = true;
+                                    continue;
+                                }
+                                next = ws.next() => next,
+                            };
+                            match next {
+                                None => {
                                     debug!("Websocket stream has ended");
                                     break;
                                 }
 
-                                Ok(Some(msg)) => {
+                                Some(msg) => {
                                     match msg {
                                         Ok(m) => {
+                                            // any message proves that connection is alive
+                                            awaiting_pong = false;
+                                            if !m.is_pong() {
+                                                deadline = timeout.map(|d| Instant::now() + d);
+                                            }
                                             let reply: Option<Message> = match m.inner {
                                                 protocol::Message::Ping(p) => {
                                                     // Send Pong for Ping
@@ -122,6 +145,7 @@ where
                                                         inner: protocol::Message::Pong(p),
                                                     })
                                                 }
+                                                protocol::Message::Pong(_) => None,
                                                 protocol::Message::Close(_) => {
                                                     debug!("Got close message from client");
                                                     // TODO: According to RFC6455 we should reply to close message - is it done by library or do we need to do it here?
@@ -160,6 +184,22 @@ where
     }
 }
 
+async fn sleep_until_opt(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => future::pending().await,
+    }
+}
+
+async fn tick_opt(timer: &mut Option<time::Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        }
+        None => future::pending().await,
+    }
+}
+
 /// This function does basic websocket handshake,
 /// return tuple of successful HTTP response (with status 101 - Protocol Upgrade) and
 /// future resolving to Websocket( struct implementing Stream and Sink of messages) or
@@ -314,6 +354,18 @@ impl Message {
         self.inner.is_ping()
     }
 
+    /// Returns true if this message is a Pong message.
+    pub fn is_pong(&self) -> bool {
+        self.inner.is_pong()
+    }
+
+    /// Constructs a new Ping `Message` with empty payload.
+    pub(crate) fn ping() -> Self {
+        Message {
+            inner: protocol::Message::Ping(Vec::new()),
+        }
+    }
+
     /// Tries to get a reference to the string text, if this is a Text message.
     pub fn to_str(&self) -> Result<&str, Error> {
         match self.inner {
@@ -337,3 +389,99 @@ impl fmt::Debug for Message {
         fmt::Debug::fmt(&self.inner, f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use std::convert::Infallible;
+    use std::sync::Mutex;
+    use tokio::io::DuplexStream;
+    use tokio::sync::oneshot::{self, error::TryRecvError};
+
+    const PING_INTERVAL: Duration = Duration::from_secs(10);
+
+    async fn echo(m: Message, _ctx: &mut oneshot::Sender<()>) -> MessageResult {
+        Ok(Some(m))
+    }
+
+    /// Connects websocket client over in memory stream, returned receiver
+    /// resolves when server side of websocket is closed (context is dropped)
+    async fn connect(
+        timeout: Option<Duration>,
+    ) -> (WebSocketStream<DuplexStream>, oneshot::Receiver<()>) {
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (closed_tx, closed_rx) = oneshot::channel();
+        let closed_tx = Mutex::new(Some(closed_tx));
+        let service = service_fn(move |req| {
+            let ctx = closed_tx.lock().unwrap().take().expect("only one request");
+            future::ok::<_, Infallible>(spawn_websocket(
+                req,
+                echo,
+                ctx,
+                timeout,
+                Some(PING_INTERVAL),
+            ))
+        });
+        tokio::spawn(
+            http1::Builder::new()
+                .serve_connection(TokioIo::new(server_io), service)
+                .with_upgrades(),
+        );
+        let (client, _) = tokio_tungstenite::client_async("ws://localhost/", client_io)
+            .await
+            .expect("websocket handshake");
+        (client, closed_rx)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unanswered_ping_closes() {
+        // client is not read, so it never answers ping
+        let (_client, closed) = connect(None).await;
+        let start = Instant::now();
+        let res = time::timeout(5 * PING_INTERVAL, closed).await;
+        assert!(matches!(res, Ok(Err(_))), "websocket should be closed");
+        let elapsed = start.elapsed();
+        // first ping is sent after one interval, on next tick it's still unanswered
+        assert!(elapsed > PING_INTERVAL);
+        assert!(elapsed <= 2 * PING_INTERVAL);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_answered_ping_keeps_open() {
+        let (mut client, mut closed) = connect(None).await;
+        let deadline = Instant::now() + 5 * PING_INTERVAL + PING_INTERVAL / 2;
+        let mut pings = 0;
+        // reading client answers pings with pongs
+        while let Ok(msg) = time::timeout_at(deadline, client.next()).await {
+            let msg = msg.expect("websocket open").expect("valid message");
+            if msg.is_ping() {
+                pings += 1;
+            }
+        }
+        assert_eq!(5, pings);
+        assert_eq!(Err(TryRecvError::Empty), closed.try_recv());
+
+        client
+            .send(protocol::Message::text("hello"))
+            .await
+            .expect("sent message");
+        let reply = client.next().await.unwrap().unwrap();
+        assert_eq!("hello", reply.to_text().unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pongs_do_not_extend_timeout() {
+        let timeout = 3 * PING_INTERVAL + PING_INTERVAL / 2;
+        let (mut client, closed) = connect(Some(timeout)).await;
+        let start = Instant::now();
+        tokio::spawn(async move { while let Some(Ok(_)) = client.next().await {} });
+        let res = time::timeout(2 * timeout, closed).await;
+        assert!(matches!(res, Ok(Err(_))), "websocket should be closed");
+        let elapsed = start.elapsed();
+        // three pings were answered in the meanwhile, but connection is closed on timeout
+        assert!(elapsed > 3 * PING_INTERVAL);
+        assert!(elapsed <= timeout);
+    }
+}
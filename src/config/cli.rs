@@ -1,23 +1,39 @@
-use std::{env, fs::File, net::SocketAddr, path::PathBuf, process::exit};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::OsString,
+    net::SocketAddr,
+    ops::{Deref, DerefMut},
+    path::PathBuf,
+    process::exit,
+};
 
+#[cfg(feature = "shared-positions")]
+use super::PositionsBackupFormat;
 use super::{
-    base_data_dir, validators::*, CollapseCDFolderConfig, Config, Cors, CorsConfig, Error, Result,
-    SslConfig, ThreadPoolConfig, BASE_DATA_DIR, FEATURES, LONG_VERSION,
+    base_data_dir, parse_ip_net, validators::*, CollapseCDFolderConfig, Config, Cors, CorsConfig,
+    Error, Result, RunCommand, SslConfig, ThreadPoolConfig, BASE_DATA_DIR, FEATURES, LONG_VERSION,
 };
 use clap::{
-    builder::FalseyValueParser, crate_authors, crate_name, value_parser, Arg, ArgAction, Command,
+    builder::FalseyValueParser, crate_authors, crate_name, parser::ValueSource, value_parser, Arg,
+    ArgAction, ArgMatches, Command,
+};
+use collection::{
+    tags::{ALLOWED_TAGS, BASIC_TAGS},
+    CoverSource,
 };
-use collection::tags::{ALLOWED_TAGS, BASIC_TAGS};
 
 const AUDIOSERVE_CONFIG: &str = "config";
 const AUDIOSERVE_FEATURES: &str = "features";
 const AUDIOSERVE_PRINT_CONFIG: &str = "print-config";
+const AUDIOSERVE_PRINT_EFFECTIVE_CONFIG: &str = "print-effective-config";
 const AUDIOSERVE_DATA_DIR: &str = "data-dir";
 const AUDIOSERVE_DEBUG: &str = "debug";
 const AUDIOSERVE_LISTEN: &str = "listen";
 const AUDIOSERVE_THREAD_POOL_LARGE: &str = "thread-pool-large";
 const AUDIOSERVE_THREAD_POOL_KEEP_ALIVE: &str = "thread-pool-keep-alive-secs";
 const AUDIOSERVE_BASE_DIRS: &str = "base-dirs";
+const AUDIOSERVE_AUTO_COLLECTIONS: &str = "auto-collections";
 const AUDIOSERVE_HELP_DIR_OPTIONS: &str = "help-dir-options";
 const AUDIOSERVE_HELP_TAGS: &str = "help-tags";
 const AUDIOSERVE_TAGS: &str = "tags";
@@ -25,9 +41,24 @@ const AUDIOSERVE_TAGS_CUSTOM: &str = "tags-custom";
 const AUDIOSERVE_NO_AUTHENTICATION: &str = "no-authentication";
 const AUDIOSERVE_SHARED_SECRET: &str = "shared-secret";
 const AUDIOSERVE_LIMIT_RATE: &str = "limit-rate";
+const AUDIOSERVE_MAX_STREAMS_PER_CLIENT: &str = "max-streams-per-client";
+const AUDIOSERVE_LIMIT_STREAM_KBPS: &str = "limit-stream-kbps";
+const AUDIOSERVE_LOGIN_MAX_FAILURES: &str = "login-max-failures";
+const AUDIOSERVE_LOGIN_LOCKOUT: &str = "login-lockout-secs";
+const AUDIOSERVE_MAX_BODY_SIZE: &str = "max-body-size";
+const AUDIOSERVE_BODY_READ_TIMEOUT: &str = "body-read-timeout-secs";
+const AUDIOSERVE_ALLOWED_CLIENTS: &str = "allowed-clients";
+const AUDIOSERVE_DENIED_CLIENTS: &str = "denied-clients";
 const AUDIOSERVE_SHARED_SECRET_FILE: &str = "shared-secret-file";
 const AUDIOSERVE_TRANSCODING_MAX_PARALLEL_PROCESSES: &str = "transcoding-max-parallel-processes";
 const AUDIOSERVE_TRANSCODING_MAX_RUNTIME: &str = "transcoding-max-runtime";
+const AUDIOSERVE_TRANSCODING_GRACE_PERIOD: &str = "transcoding-grace-period";
+const AUDIOSERVE_TRANSCODING_LOUDNESS_NORMALIZE: &str = "transcoding-loudness-normalize";
+const AUDIOSERVE_TRANSCODING_SMART_PASSTHROUGH: &str = "transcoding-smart-passthrough";
+const AUDIOSERVE_TRANSCODING_FFMPEG_PATH: &str = "transcoding-ffmpeg-path";
+const AUDIOSERVE_TRANSCODING_NICE: &str = "transcoding-nice";
+const AUDIOSERVE_TRANSCODING_IONICE: &str = "transcoding-ionice";
+const AUDIOSERVE_TRANSCODING_MEMORY_LIMIT: &str = "transcoding-memory-limit";
 const AUDIOSERVE_TOKEN_VALIDITY_DAYS: &str = "token-validity-days";
 const AUDIOSERVE_CLIENT_DIR: &str = "client-dir";
 const AUDIOSERVE_SECRET_FILE: &str = "secret-file";
@@ -35,6 +66,7 @@ const AUDIOSERVE_CORS: &str = "cors";
 const AUDIOSERVE_CORS_REGEX: &str = "cors-regex";
 const AUDIOSERVE_CHAPTERS_FROM_DURATION: &str = "chapters-from-duration";
 const AUDIOSERVE_CHAPTERS_DURATION: &str = "chapters-duration";
+const AUDIOSERVE_CHAPTERS_FROM_SILENCE: &str = "chapters-from-silence";
 const AUDIOSERVE_NO_DIR_COLLAPS: &str = "no-dir-collaps";
 const AUDIOSERVE_IGNORE_CHAPTERS_META: &str = "ignore-chapters-meta";
 const AUDIOSERVE_URL_PATH_PREFIX: &str = "url-path-prefix";
@@ -48,16 +80,33 @@ const AUDIOSERVE_ICONS_CACHE_SIZE: &str = "icons-cache-size";
 const AUDIOSERVE_ICONS_CACHE_MAX_FILES: &str = "icons-cache-max-files";
 const AUDIOSERVE_ICONS_CACHE_DISABLE: &str = "icons-cache-disable";
 const AUDIOSERVE_ICONS_CACHE_SAVE_OFTEN: &str = "icons-cache-save-often";
+const AUDIOSERVE_ICONS_CACHE_SAVE_INTERVAL: &str = "icons-cache-save-interval";
 const AUDIOSERVE_ICONS_SIZE: &str = "icons-size";
+const AUDIOSERVE_ICONS_ALLOWED_SIZES: &str = "icons-allowed-sizes";
 const AUDIOSERVE_ICONS_FAST_SCALING: &str = "icons-fast-scaling";
+const AUDIOSERVE_COVERS_CACHE_DIR: &str = "covers-cache-dir";
+const AUDIOSERVE_COVERS_CACHE_SIZE: &str = "covers-cache-size";
+const AUDIOSERVE_COVERS_CACHE_MAX_FILES: &str = "covers-cache-max-files";
+const AUDIOSERVE_COVERS_CACHE_DISABLE: &str = "covers-cache-disable";
+const AUDIOSERVE_COVERS_ALLOWED_SIZES: &str = "covers-allowed-sizes";
 const AUDIOSERVE_BEHIND_PROXY: &str = "behind-proxy";
+const AUDIOSERVE_TRUSTED_PROXIES: &str = "trusted-proxies";
 const AUDIOSERVE_DISABLE_FOLDER_DOWNLOAD: &str = "disable-folder-download";
+const AUDIOSERVE_RECURSIVE_DOWNLOAD_MAX_FILES: &str = "recursive-download-max-files";
+const AUDIOSERVE_RECURSIVE_DOWNLOAD_MAX_SIZE: &str = "recursive-download-max-size";
 const AUDIOSERVE_SSL_KEY: &str = "ssl-key";
 const AUDIOSERVE_SSL_CERT: &str = "ssl-cert";
 const AUDIOSERVE_POSITIONS_BACKUP_FILE: &str = "positions-backup-file";
 const AUDIOSERVE_POSITIONS_WS_TIMEOUT: &str = "positions-ws-timeout";
+const AUDIOSERVE_POSITIONS_WS_PING_INTERVAL: &str = "positions-ws-ping-interval";
 const AUDIOSERVE_POSITIONS_RESTORE: &str = "positions-restore";
 const AUDIOSERVE_POSITIONS_BACKUP_SCHEDULE: &str = "positions-backup-schedule";
+const AUDIOSERVE_POSITIONS_IMPORT_GROUP: &str = "positions-import-group";
+const AUDIOSERVE_POSITIONS_BACKUP_KEEP: &str = "positions-backup-keep";
+const AUDIOSERVE_POSITIONS_BACKUP_COMPRESS: &str = "positions-backup-compress";
+const AUDIOSERVE_SYNC_SESSIONS_WS_TIMEOUT: &str = "sync-sessions-ws-timeout";
+const AUDIOSERVE_SYNC_SESSIONS_MAX: &str = "sync-sessions-max";
+const AUDIOSERVE_SYNC_SESSIONS_MAX_DRIFT: &str = "sync-sessions-max-drift";
 const AUDIOSERVE_ALLOW_SYMLINKS: &str = "allow-symlinks";
 const AUDIOSERVE_TAGS_ENCODING: &str = "tags-encoding";
 const AUDIOSERVE_SEARCH_CACHE: &str = "search-cache";
@@ -66,12 +115,41 @@ const AUDIOSERVE_T_CACHE_SIZE: &str = "t-cache-size";
 const AUDIOSERVE_T_CACHE_MAX_FILES: &str = "t-cache-max-files";
 const AUDIOSERVE_T_CACHE_DISABLE: &str = "t-cache-disable";
 const AUDIOSERVE_T_CACHE_SAVE_OFTEN: &str = "t-cache-save-often";
+const AUDIOSERVE_T_CACHE_PREHEAT: &str = "t-cache-preheat";
+const AUDIOSERVE_T_CACHE_CONTENT_KEYS: &str = "t-cache-content-keys";
+const AUDIOSERVE_T_CACHE_RESUME: &str = "t-cache-resume";
 const AUDIOSERVE_NO_NATURAL_FILES_ORDERING: &str = "no-natural-files-ordering";
 const AUDIOSERVE_TIME_TO_FOLDER_END: &str = "time-to-folder-end";
 const AUDIOSERVE_READ_PLAYLIST: &str = "read-playlist";
 const AUDIOSERVE_DONT_WATCH_FOR_CHANGES: &str = "dont-watch-for-changes";
 const AUDIOSERVE_CHANGES_DEBOUNCE_INTERVAL: &str = "changes-debounce-interval";
+const AUDIOSERVE_CHANGES_POLL_INTERVAL: &str = "changes-poll-interval";
+const AUDIOSERVE_RESCAN_CONTROL_FILE: &str = "rescan-control-file";
+const AUDIOSERVE_SCAN_THREADS: &str = "scan-threads";
+const AUDIOSERVE_SCAN_IO_DELAY: &str = "scan-io-delay";
+const AUDIOSERVE_COVER_SOURCES: &str = "cover-sources";
+const AUDIOSERVE_COVER_NAMES: &str = "cover-names";
 const AUDIOSERVE_COMPRESS_RESPONSES: &str = "compress-responses";
+const AUDIOSERVE_ACCESS_LOG: &str = "access-log";
+const AUDIOSERVE_ACCESS_LOG_MAX_SIZE: &str = "access-log-max-size";
+const AUDIOSERVE_ACCESS_LOG_MAX_FILES: &str = "access-log-max-files";
+const AUDIOSERVE_SUBSONIC_API: &str = "subsonic-api";
+const AUDIOSERVE_DLNA: &str = "dlna";
+const AUDIOSERVE_DLNA_NAME: &str = "dlna-name";
+const AUDIOSERVE_DLNA_ACCOUNT: &str = "dlna-account";
+const AUDIOSERVE_MDNS: &str = "mdns";
+const AUDIOSERVE_IMPORT_DIR: &str = "import-dir";
+const AUDIOSERVE_IMPORT_COLLECTION: &str = "import-collection";
+const AUDIOSERVE_IMPORT_TRANSCODE: &str = "import-transcode";
+const AUDIOSERVE_CACHE_MAINTENANCE_SCHEDULE: &str = "cache-maintenance-schedule";
+const AUDIOSERVE_CACHE_MAINTENANCE_TARGET: &str = "cache-maintenance-target";
+
+const AUDIOSERVE_CMD_SERVE: &str = "serve";
+const AUDIOSERVE_CMD_SCAN: &str = "scan";
+const AUDIOSERVE_CMD_POSITIONS: &str = "positions";
+const AUDIOSERVE_CMD_POSITIONS_BACKUP: &str = "backup";
+const AUDIOSERVE_CMD_POSITIONS_RESTORE: &str = "restore";
+const AUDIOSERVE_CMD_CHECK_CONFIG: &str = "check-config";
 
 macro_rules! long_arg_no_env {
     ($name: ident) => {
@@ -111,6 +189,10 @@ fn create_parser() -> Command {
             .action(ArgAction::SetTrue)
             .help("Will print current config, with all other options to stdout, useful for creating config file")
             )
+        .arg(long_arg_no_env!(AUDIOSERVE_PRINT_EFFECTIVE_CONFIG)
+            .action(ArgAction::SetTrue)
+            .help("Will print every config value with its source (cli, env, file, default) and exits")
+            )
         .arg(long_arg!(AUDIOSERVE_DATA_DIR)
             .num_args(1)
             .value_parser(parent_dir_exists)
@@ -143,6 +225,11 @@ fn create_parser() -> Command {
             .value_delimiter(';')
             .help("Root directories for audio books, also referred as collections, you can also add :<options> after directory path to change collection behaviour, use --help-dir-options for more details")
 
+            )
+        .arg(long_arg!(AUDIOSERVE_AUTO_COLLECTIONS)
+            .num_args(1)
+            .value_parser(is_existing_dir)
+            .help("Directory, where each subdirectory is a collection with default options, new subdirectories are added as collections while running (without restart)")
             )
         .arg(long_arg_no_env!(AUDIOSERVE_HELP_DIR_OPTIONS)
             .action(ArgAction::SetTrue)
@@ -178,6 +265,48 @@ fn create_parser() -> Command {
             .value_parser(value_parser!(f32))
             .help("Limits number of http request to x req/sec. Assures that resources are not exhausted in case of DDoS (but will also limit you).")
             )
+        .arg(long_arg!(AUDIOSERVE_MAX_STREAMS_PER_CLIENT)
+            .num_args(1)
+            .value_parser(value_parser!(u32).range(1..))
+            .help("Maximum number of concurrent audio streams from one client IP address, over this limit requests get 429 status [default is unlimited]")
+            )
+        .arg(long_arg!(AUDIOSERVE_LIMIT_STREAM_KBPS)
+            .num_args(1)
+            .value_parser(value_parser!(u32).range(8..))
+            .help("Limits bandwidth of each audio stream and folder download to n kbps (kilobits per second) [default is unlimited]")
+            )
+        .arg(long_arg!(AUDIOSERVE_LOGIN_MAX_FAILURES)
+            .num_args(1)
+            .value_parser(value_parser!(u32))
+            .help("Number of failed logins from one IP address, after which address is temporarily locked out, 0 disables lockout [default is 10]")
+            )
+        .arg(long_arg!(AUDIOSERVE_LOGIN_LOCKOUT)
+            .num_args(1)
+            .value_parser(value_parser!(u32))
+            .help("How long (in seconds) is IP address locked out after too many failed logins [default is 900]")
+            )
+        .arg(long_arg!(AUDIOSERVE_MAX_BODY_SIZE)
+            .num_args(1)
+            .value_parser(value_parser!(u64))
+            .help("Maximum size (in bytes) of request body (POST requests like positions update or restore), bigger requests are rejected with 413 status [default is 10485760 (10MiB)]")
+            )
+        .arg(long_arg!(AUDIOSERVE_BODY_READ_TIMEOUT)
+            .num_args(1)
+            .value_parser(value_parser!(u32))
+            .help("Timeout (in seconds) for receiving whole request body, slower requests are rejected with 408 status [default is 30]")
+            )
+        .arg(long_arg!(AUDIOSERVE_ALLOWED_CLIENTS)
+            .num_args(1..100)
+            .value_delimiter(',')
+            .value_parser(parse_ip_net)
+            .help("Only clients with these IP addresses or networks (CIDR notation), separated by comma, can access server")
+            )
+        .arg(long_arg!(AUDIOSERVE_DENIED_CLIENTS)
+            .num_args(1..100)
+            .value_delimiter(',')
+            .value_parser(parse_ip_net)
+            .help("Clients with these IP addresses or networks (CIDR notation), separated by comma, are refused, has precedence over --allowed-clients")
+            )
         .arg(long_arg!(AUDIOSERVE_SHARED_SECRET_FILE)
             .num_args(1)
             // .conflicts_with(AUDIOSERVE_NO_AUTHENTICATION)
@@ -196,6 +325,38 @@ fn create_parser() -> Command {
             .value_parser(value_parser!(u32))
             .help("Max duration of transcoding process in hours. If takes longer process is killed. [default is 24h]")
             )
+        .arg(long_arg!(AUDIOSERVE_TRANSCODING_GRACE_PERIOD)
+            .num_args(1)
+            .value_parser(value_parser!(u32))
+            .help("On shutdown wait up to this number of seconds for running transcodings to finish, 0 means do not wait [default is 30s]")
+            )
+        .arg(long_arg_flag!(AUDIOSERVE_TRANSCODING_LOUDNESS_NORMALIZE)
+            .help("Normalizes loudness of transcoded audio according to ReplayGain tags in audio files (track gain)")
+            )
+        .arg(long_arg!(AUDIOSERVE_TRANSCODING_FFMPEG_PATH)
+            .num_args(1)
+            .value_parser(value_parser!(PathBuf))
+            .help("Path to ffmpeg binary [default is ffmpeg from PATH]")
+            )
+        .arg(long_arg!(AUDIOSERVE_TRANSCODING_NICE)
+            .num_args(1)
+            .allow_negative_numbers(true)
+            .value_parser(value_parser!(i32))
+            .help("Niceness of transcoding processes (-20 to 19)")
+            )
+        .arg(long_arg!(AUDIOSERVE_TRANSCODING_IONICE)
+            .num_args(1)
+            .value_parser(value_parser!(u8))
+            .help("IO priority (best effort class 0 to 7) of transcoding processes, only on Linux")
+            )
+        .arg(long_arg!(AUDIOSERVE_TRANSCODING_MEMORY_LIMIT)
+            .num_args(1)
+            .value_parser(value_parser!(u32))
+            .help("Maximum memory of one transcoding process in MB")
+            )
+        .arg(long_arg_flag!(AUDIOSERVE_TRANSCODING_SMART_PASSTHROUGH)
+            .help("Does not transcode files, which already have codec of requested transcoding and lower or same bitrate - such files are sent as they are or just remuxed into transcoding container")
+            )
         .arg(long_arg!(AUDIOSERVE_TOKEN_VALIDITY_DAYS)
             .num_args(1)
             .value_parser(value_parser!(u32))
@@ -231,6 +392,9 @@ fn create_parser() -> Command {
             .value_parser(value_parser!(u32))
             .help("If long files is presented as chapters, one chapter has x mins [default: 30]")
             )
+        .arg(long_arg_flag!(AUDIOSERVE_CHAPTERS_FROM_SILENCE)
+            .help("When long file is split into chapters, moves split points to nearest silence (detected by ffmpeg during collection scan, which can take a while)")
+            )
         .arg(long_arg_flag!(AUDIOSERVE_NO_DIR_COLLAPS)
             .help("Prevents automatic collaps/skip of directory with single chapterized audio file")
             )
@@ -280,23 +444,63 @@ fn create_parser() -> Command {
             .help("Max number of files in icons cache, when reached LRU items are deleted, [default is 1024]")
         ).arg(
             long_arg_flag!(AUDIOSERVE_ICONS_CACHE_DISABLE)
-            .conflicts_with_all([AUDIOSERVE_ICONS_CACHE_SAVE_OFTEN, AUDIOSERVE_ICONS_CACHE_MAX_FILES, AUDIOSERVE_ICONS_CACHE_SIZE, AUDIOSERVE_ICONS_CACHE_DIR])
+            .conflicts_with_all([AUDIOSERVE_ICONS_CACHE_SAVE_OFTEN, AUDIOSERVE_ICONS_CACHE_SAVE_INTERVAL, AUDIOSERVE_ICONS_CACHE_MAX_FILES, AUDIOSERVE_ICONS_CACHE_SIZE, AUDIOSERVE_ICONS_CACHE_DIR])
             .help("Icons cache is disabled.")
             )
         .arg(
             long_arg_flag!(AUDIOSERVE_ICONS_CACHE_SAVE_OFTEN)
             .help("Save additions to icons cache often, after each addition, this is normally not necessary")
         )
+        .arg(
+            long_arg!(AUDIOSERVE_ICONS_CACHE_SAVE_INTERVAL)
+            .num_args(1)
+            .value_parser(value_parser!(u32))
+            .help("Interval in seconds in which icons and covers caches indexes are saved in background, if changed, 0 means save only at exit [default is 300]")
+        )
         .arg(
             long_arg!(AUDIOSERVE_ICONS_SIZE)
             .num_args(1)
             .value_parser(value_parser!(u32))
             .help("Size of folder icon in pixels, [default is 128]")
         )
+        .arg(
+            long_arg!(AUDIOSERVE_ICONS_ALLOWED_SIZES)
+            .num_args(1..100)
+            .value_delimiter(',')
+            .value_parser(value_parser!(u32))
+            .help("Other icon sizes (in pixels), which can be requested by client, list separated by comma, [default is 64,128,256,512]")
+        )
         .arg(
             long_arg_flag!(AUDIOSERVE_ICONS_FAST_SCALING)
             .help("Use faster image scaling (linear triangle), by default slower, but better method (Lanczos3)")
         )
+        .arg(
+            long_arg!(AUDIOSERVE_COVERS_CACHE_DIR)
+            .num_args(1)
+            .value_parser(parent_dir_exists)
+            .help("Directory for cache of resized covers [default is ~/.audioserve/covers-cache]")
+        ).arg(
+            long_arg!(AUDIOSERVE_COVERS_CACHE_SIZE)
+            .num_args(1)
+            .value_parser(value_parser!(u32))
+            .help("Max size of resized covers cache in MBi for each cover size, when reached LRU items are deleted, [default is 100]")
+        ).arg(
+            long_arg!(AUDIOSERVE_COVERS_CACHE_MAX_FILES)
+            .num_args(1)
+            .value_parser(value_parser!(u32))
+            .help("Max number of files in resized covers cache for each cover size, when reached LRU items are deleted, [default is 1024]")
+        ).arg(
+            long_arg_flag!(AUDIOSERVE_COVERS_CACHE_DISABLE)
+            .conflicts_with_all([AUDIOSERVE_COVERS_CACHE_MAX_FILES, AUDIOSERVE_COVERS_CACHE_SIZE, AUDIOSERVE_COVERS_CACHE_DIR])
+            .help("Resized covers cache is disabled.")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_COVERS_ALLOWED_SIZES)
+            .num_args(1..100)
+            .value_delimiter(',')
+            .value_parser(value_parser!(u32))
+            .help("Sizes (in pixels), to which cover can be resized on client request, list separated by comma, [default is 600,1200]")
+        )
         .arg(
             long_arg_flag!(AUDIOSERVE_NO_NATURAL_FILES_ORDERING)
             .help("Disable natural ordering (first number in name is used for ordering ) of files")
@@ -313,6 +517,78 @@ fn create_parser() -> Command {
         .arg(
             long_arg_flag!(AUDIOSERVE_COMPRESS_RESPONSES)
             .help("Compress content of HTTP responses with gzip")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_ACCESS_LOG)
+            .num_args(1)
+            .value_parser(parent_dir_exists)
+            .help("File for access log, each request is logged as JSON line [default is no access log]")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_ACCESS_LOG_MAX_SIZE)
+            .num_args(1)
+            .requires(AUDIOSERVE_ACCESS_LOG)
+            .value_parser(value_parser!(u32))
+            .help("Max size of access log file in MB, when reached log file is rotated [default 10]")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_ACCESS_LOG_MAX_FILES)
+            .num_args(1)
+            .requires(AUDIOSERVE_ACCESS_LOG)
+            .value_parser(value_parser!(u32))
+            .help("Number of rotated access log files to keep [default 5]")
+        )
+        .arg(
+            long_arg_flag!(AUDIOSERVE_DLNA)
+            .help("Announces audioserve as DLNA/UPnP media server in local network, so devices like smart speakers or TVs can browse and play collections")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_DLNA_NAME)
+            .num_args(1)
+            .requires(AUDIOSERVE_DLNA)
+            .help("Name of media server shown on DLNA devices [default audioserve]")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_DLNA_ACCOUNT)
+            .num_args(1)
+            .requires(AUDIOSERVE_DLNA)
+            .help("Only collections allowed for this account are available to DLNA devices [default is all collections]")
+        )
+        .arg(
+            long_arg_flag!(AUDIOSERVE_MDNS)
+            .help("Advertises server as _audioserve._tcp service via mDNS (zeroconf), so clients in local network can discover it")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_IMPORT_DIR)
+            .num_args(1)
+            .value_parser(is_existing_dir)
+            .help("Watched import directory - audio files dropped there are moved into collection into Author/Title folder based on their tags")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_IMPORT_COLLECTION)
+            .num_args(1)
+            .requires(AUDIOSERVE_IMPORT_DIR)
+            .value_parser(value_parser!(usize))
+            .help("Index of collection (from 0), into which files are imported [default 0]")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_IMPORT_TRANSCODE)
+            .num_args(1)
+            .requires(AUDIOSERVE_IMPORT_DIR)
+            .value_parser(["l", "m", "h"])
+            .help("Transcode imported files to given quality (l, m, h) [default is to import files as they are]")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_CACHE_MAINTENANCE_SCHEDULE)
+            .num_args(1)
+            .help("Sets regular schedule for maintenance of transcoding, icons and covers caches (trimming to target size, removal of orphaned files, index check) - should be cron expression m h dom mon dow")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_CACHE_MAINTENANCE_TARGET)
+            .num_args(1)
+            .requires(AUDIOSERVE_CACHE_MAINTENANCE_SCHEDULE)
+            .value_parser(value_parser!(u8).range(1..=100))
+            .help("Caches are trimmed during maintenance to this percentage of their max size and max number of files [default 90]")
         );
 
     // deprecated
@@ -333,11 +609,58 @@ fn create_parser() -> Command {
                     .conflicts_with(AUDIOSERVE_DONT_WATCH_FOR_CHANGES)
                     .help("Internal in seconds to debounce raw notification about file changes"),
             )
+            .arg(
+                long_arg!(AUDIOSERVE_CHANGES_POLL_INTERVAL)
+                    .value_parser(value_parser!(u32))
+                    .conflicts_with(AUDIOSERVE_DONT_WATCH_FOR_CHANGES)
+                    .help("If watching for changes is not possible (network file systems, exhausted inotify watches), collection is checked for changes in this interval in seconds, 0 disables polling [default 300]"),
+            )
     }
 
+    parser = parser
+        .arg(
+            long_arg!(AUDIOSERVE_RESCAN_CONTROL_FILE)
+                .num_args(1)
+                .value_parser(parent_dir_exists)
+                .help("Control file for SIGUSR1 rescan - if file exists, only collections with indexes listed in it (separated by comma or whitespace) are rescanned and file is then deleted, otherwise all collections are rescanned"),
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_SCAN_THREADS)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .help("Number of threads scanning each collection, use more for fast disks [default 1]"),
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_SCAN_IO_DELAY)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .help("Delay in milliseconds after scan of each folder, can limit IO load of collections scan on slow disks [default 0]"),
+        );
+
+    parser = parser
+        .arg(
+            long_arg!(AUDIOSERVE_COVER_SOURCES)
+                .num_args(1..4)
+                .value_delimiter(',')
+                .value_parser(|s: &str| s.parse::<CoverSource>().map_err(|e| e.to_string()))
+                .help("Sources of folder cover in order of priority, separated by comma - possible values are file (image in folder), embedded (cover in audio file), parent (image in parent folder) [by default image in folder is used, for chapterized audio file cover embedded in it]"),
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_COVER_NAMES)
+                .num_args(1..100)
+                .value_delimiter(',')
+                .help("Preferred names (without extension) of cover image files, separated by comma, if there are more images in folder"),
+        );
+
     if cfg!(feature = "behind-proxy") {
         parser = parser.arg(long_arg_flag!(AUDIOSERVE_BEHIND_PROXY)
-                .help("Informs program that it is behind remote proxy, now used only for logging (to get true remote client ip)")
+                .help("Informs program that it is behind remote proxy, so true remote client ip is taken from Forwarded or X-Forwarded-For headers (for logging and clients access control)")
+        )
+        .arg(long_arg!(AUDIOSERVE_TRUSTED_PROXIES)
+            .num_args(1..100)
+            .value_delimiter(',')
+            .value_parser(parse_ip_net)
+            .help("IP addresses or networks (CIDR notation) of proxies, separated by comma, forwarded headers are used only from these addresses [default is to trust any address]")
         )
     }
 
@@ -345,9 +668,29 @@ fn create_parser() -> Command {
         parser = parser.arg(
             long_arg_flag!(AUDIOSERVE_DISABLE_FOLDER_DOWNLOAD)
                 .help("Disables API point for downloading whole folder"),
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_RECURSIVE_DOWNLOAD_MAX_FILES)
+            .num_args(1)
+            .value_parser(value_parser!(u32))
+            .conflicts_with(AUDIOSERVE_DISABLE_FOLDER_DOWNLOAD)
+            .help("Max number of files in recursive folder download (including subfolders) [default is 2000]")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_RECURSIVE_DOWNLOAD_MAX_SIZE)
+            .num_args(1)
+            .value_parser(value_parser!(u32))
+            .conflicts_with(AUDIOSERVE_DISABLE_FOLDER_DOWNLOAD)
+            .help("Max total size of files in MBi for recursive folder download (including subfolders) [default is 4096]")
         );
     }
 
+    if cfg!(feature = "subsonic") {
+        parser = parser.arg(long_arg_flag!(AUDIOSERVE_SUBSONIC_API).help(
+            "Enables Subsonic compatible API (on /rest/ path), so Subsonic clients can be used",
+        ));
+    }
+
     if cfg!(feature = "tls") {
         parser = parser
             .arg(
@@ -376,21 +719,64 @@ fn create_parser() -> Command {
             .value_parser(duration_secs)
             .help("Timeout in seconds for idle websocket connection use for playback position sharing [default 600s]")
         )
+        .arg(
+            long_arg!(AUDIOSERVE_POSITIONS_WS_PING_INTERVAL)
+            .value_parser(duration_secs)
+            .help("Interval in seconds, in which server pings websocket connection used for playback position sharing, to detect dead connections [default 60s]")
+        )
         .arg(
             long_arg!(AUDIOSERVE_POSITIONS_RESTORE)
             .num_args(1)
             .value_parser(positions_restore_format)
             .requires(AUDIOSERVE_POSITIONS_BACKUP_FILE)
-            .help("Restores positions from backup JSON file, value is version of file legacy is before audioserve v0.16,  v1 is current")
+            .help("Restores positions from backup JSON file, value is version of file legacy is before audioserve v0.16,  v1 is current. \
+            Can also import positions from other servers - booksonic (JSON response of getBookmarks API call) or audiobookshelf (see README for details)")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_POSITIONS_IMPORT_GROUP)
+            .num_args(1)
+            .requires(AUDIOSERVE_POSITIONS_RESTORE)
+            .help("Group into which positions are imported from other servers, required for audiobookshelf, for booksonic user name is used if not given")
         )
         .arg(
             long_arg!(AUDIOSERVE_POSITIONS_BACKUP_SCHEDULE)
             .num_args(1)
             .requires(AUDIOSERVE_POSITIONS_BACKUP_FILE)
             .help("Sets regular schedule for backing up playback position - should be cron expression m h dom mon dow- minute (m), hour (h), day of month (dom), month (mon) day of week (dow)")
+        )
+        .arg(
+            long_arg!(AUDIOSERVE_POSITIONS_BACKUP_KEEP)
+            .num_args(1)
+            .requires(AUDIOSERVE_POSITIONS_BACKUP_FILE)
+            .value_parser(value_parser!(usize))
+            .help("Keeps this number of timestamped positions backups (created next to backup file), older ones are deleted [default is None - backup file is rewritten]")
+        )
+        .arg(
+            long_arg_flag!(AUDIOSERVE_POSITIONS_BACKUP_COMPRESS)
+            .requires(AUDIOSERVE_POSITIONS_BACKUP_FILE)
+            .help("Compresses positions backups with gzip (.gz is added to file name)")
         );
     }
 
+    if cfg!(feature = "sync-sessions") {
+        parser = parser
+            .arg(
+                long_arg!(AUDIOSERVE_SYNC_SESSIONS_WS_TIMEOUT)
+                    .value_parser(duration_secs)
+                    .help("Timeout in seconds for idle websocket connection used for shared playback sessions [default 600s]"),
+            )
+            .arg(
+                long_arg!(AUDIOSERVE_SYNC_SESSIONS_MAX)
+                    .value_parser(value_parser!(usize))
+                    .help("Maximum number of concurrent shared playback sessions [default 100]"),
+            )
+            .arg(
+                long_arg!(AUDIOSERVE_SYNC_SESSIONS_MAX_DRIFT)
+                    .value_parser(value_parser!(f32))
+                    .help("Maximum difference (in seconds) of listener's position from host's position in shared playback session, before listener is asked to seek [default 2.0]"),
+            );
+    }
+
     if cfg!(feature = "symlinks") {
         parser = parser.arg(
             long_arg_flag!(AUDIOSERVE_ALLOW_SYMLINKS)
@@ -423,15 +809,62 @@ fn create_parser() -> Command {
             .help("Max number of files in transcoding cache, when reached LRU items are deleted, [default is 1024]")
         ).arg(
             long_arg_flag!(AUDIOSERVE_T_CACHE_DISABLE)
-            .conflicts_with_all([AUDIOSERVE_T_CACHE_SAVE_OFTEN, AUDIOSERVE_T_CACHE_MAX_FILES, AUDIOSERVE_T_CACHE_SIZE, AUDIOSERVE_T_CACHE_DIR])
+            .conflicts_with_all([AUDIOSERVE_T_CACHE_SAVE_OFTEN, AUDIOSERVE_T_CACHE_MAX_FILES, AUDIOSERVE_T_CACHE_SIZE, AUDIOSERVE_T_CACHE_DIR, AUDIOSERVE_T_CACHE_PREHEAT, AUDIOSERVE_T_CACHE_CONTENT_KEYS, AUDIOSERVE_T_CACHE_RESUME])
             .help("Transcoding cache is disabled. If you want to completely get rid of it, compile without 'transcoding-cache'")
             )
         .arg(
             long_arg_flag!(AUDIOSERVE_T_CACHE_SAVE_OFTEN)
             .help("Save additions to cache often, after each addition, this is normally not necessary")
         )
+        .arg(
+            long_arg!(AUDIOSERVE_T_CACHE_PREHEAT)
+            .num_args(1)
+            .value_parser(["l", "m", "h"])
+            .help("Transcode in background all files in collections to cache in given quality (l, m, h), using at most half of allowed transcoding processes")
+        )
+        .arg(
+            long_arg_flag!(AUDIOSERVE_T_CACHE_CONTENT_KEYS)
+            .help("Identify cached files by fingerprint of content (size and hash of beginning and end of file) instead of path, so identical files in different collections are transcoded only once")
+        )
+        .arg(
+            long_arg_flag!(AUDIOSERVE_T_CACHE_RESUME)
+            .help("Keep unfinished transcodings (e.g. when client disconnects) and resume them on next request, they are limited by same size and number of files as the cache")
+        )
+    }
+
+    add_subcommands(parser)
+}
+
+/// Subcommands share all arguments with main command, so arguments are made global,
+/// subcommand has to be first argument (otherwise it's taken as collection directory)
+fn add_subcommands(mut parser: Command) -> Command {
+    parser = parser
+        .subcommand(
+            Command::new(AUDIOSERVE_CMD_SERVE)
+                .about("Runs server (default if no subcommand is given)"),
+        )
+        .subcommand(
+            Command::new(AUDIOSERVE_CMD_SCAN)
+                .about("Scans collections to build or update their caches and exits"),
+        )
+        .subcommand(Command::new(AUDIOSERVE_CMD_CHECK_CONFIG).about(
+            "Validates configuration and arguments, prints effective configuration and exits",
+        ));
+
+    if cfg!(feature = "shared-positions") {
+        parser = parser.subcommand(
+            Command::new(AUDIOSERVE_CMD_POSITIONS)
+                .about("Playback positions maintenance")
+                .subcommand_required(true)
+                .subcommand(Command::new(AUDIOSERVE_CMD_POSITIONS_BACKUP).about("Backs up positions to --positions-backup-file and exits"))
+                .subcommand(Command::new(AUDIOSERVE_CMD_POSITIONS_RESTORE).about("Restores positions from --positions-backup-file and exits, format can be given with --positions-restore [default v1]")),
+        );
     }
 
+    let args: Vec<_> = parser.get_arguments().map(|a| a.get_id().clone()).collect();
+    for arg in args {
+        parser = parser.mut_arg(arg, |a| a.global(true));
+    }
     parser
 }
 
@@ -455,12 +888,14 @@ macro_rules! has_flag {
 
 macro_rules! set_config {
     ($args:ident, $cfg: expr,  Some($arg:expr)) => {
+        $args.note_source(stringify!($cfg), $arg);
         if let Some(n) = $args.remove_one($arg) {
             $cfg = Some(n);
         }
     };
 
     ($args:ident, $cfg: expr, $arg:expr) => {
+        $args.note_source(stringify!($cfg), $arg);
         if let Some(n) = $args.remove_one($arg) {
             $cfg = n;
         }
@@ -469,25 +904,244 @@ macro_rules! set_config {
 
 macro_rules! set_config_flag {
     ($args:ident, $cfg: expr, $arg:expr) => {
+        $args.note_source(stringify!($cfg), $arg);
         if has_flag!($args, $arg) {
             $cfg = true;
         }
     };
 }
 
+/// Sources of config values, which are not defaults
+#[derive(Debug, Default)]
+struct ConfigSources {
+    /// config path and argument source (cli or env)
+    args: Vec<(String, String)>,
+    /// paths of values present in config file
+    file: Vec<String>,
+}
+
+impl ConfigSources {
+    fn source_of(&self, path: &str) -> &str {
+        let covers = |p: &str| path == p || path.starts_with(p) && path[p.len()..].starts_with('.');
+        self.args
+            .iter()
+            .find(|(p, _)| covers(p))
+            .map(|(_, source)| source.as_str())
+            .or_else(|| self.file.iter().find(|p| covers(p)).map(|_| "file"))
+            .unwrap_or("default")
+    }
+}
+
+/// Argument matches, which also track from where config values come from
+struct TrackedArgs {
+    matches: ArgMatches,
+    env_names: HashMap<String, OsString>,
+    sources: ConfigSources,
+}
+
+impl TrackedArgs {
+    fn new(parser: &Command, matches: ArgMatches) -> Self {
+        let env_names = parser
+            .get_arguments()
+            .filter_map(|a| a.get_env().map(|e| (a.get_id().to_string(), e.to_owned())))
+            .collect();
+        TrackedArgs {
+            matches,
+            env_names,
+            sources: ConfigSources::default(),
+        }
+    }
+
+    /// Records source of config value at `path`, if argument is given on command line or in environment
+    fn note_source(&mut self, path: &str, arg: &str) {
+        let source = match self.matches.value_source(arg) {
+            Some(ValueSource::CommandLine) => format!("cli --{}", arg),
+            Some(ValueSource::EnvVariable) => format!(
+                "env {}",
+                self.env_names
+                    .get(arg)
+                    .map(|e| e.to_string_lossy())
+                    .unwrap_or_default()
+            ),
+            _ => return,
+        };
+        let path = path
+            .strip_prefix("config.")
+            .unwrap_or(path)
+            .replace(' ', "");
+        self.sources.args.push((path, source));
+    }
+}
+
+impl Deref for TrackedArgs {
+    type Target = ArgMatches;
+
+    fn deref(&self) -> &Self::Target {
+        &self.matches
+    }
+}
+
+impl DerefMut for TrackedArgs {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.matches
+    }
+}
+
+/// Most similar name from candidates, if it is similar enough
+fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    candidates
+        .into_iter()
+        .map(|c| (strsim::jaro_winkler(name, c), c))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, c)| c)
+}
+
+/// Warns about AUDIOSERVE_* environment variables, which are not used by any argument
+fn check_env_vars(parser: &Command) {
+    const PREFIX: &str = "AUDIOSERVE_";
+    // common prefix is not compared, otherwise everything would look similar
+    let known: Vec<_> = parser
+        .get_arguments()
+        .filter_map(|a| a.get_env().and_then(|e| e.to_str()))
+        .filter_map(|e| e.strip_prefix(PREFIX))
+        .collect();
+    for (name, _) in env::vars_os() {
+        let name = name.to_string_lossy();
+        let unknown = name.strip_prefix(PREFIX).filter(|n| !known.contains(n));
+        if let Some(unknown) = unknown {
+            let suggestion = did_you_mean(unknown, known.iter().copied())
+                .map(|s| format!(", did you mean {}{}?", PREFIX, s))
+                .unwrap_or_default();
+            eprintln!(
+                "Warning: unknown environment variable {} is ignored{}",
+                name, suggestion
+            );
+        }
+    }
+}
+
+/// Adds suggestion to serde error about unknown field or variant in config file
+fn add_suggestion(msg: String) -> String {
+    lazy_static! {
+        static ref UNKNOWN_RE: regex::Regex =
+            regex::Regex::new(r"unknown (?:field|variant) `([^`]*)`, expected (.*)").unwrap();
+        static ref NAME_RE: regex::Regex = regex::Regex::new(r"`([^`]*)`").unwrap();
+    }
+    let suggestion = UNKNOWN_RE.captures(&msg).and_then(|c| {
+        let name = c.get(1)?.as_str();
+        let expected = c.get(2)?.as_str();
+        did_you_mean(
+            name,
+            NAME_RE
+                .captures_iter(expected)
+                .filter_map(|c| c.get(1).map(|m| m.as_str())),
+        )
+        .map(|s| format!(", did you mean `{}`?", s))
+    });
+    match suggestion {
+        Some(suggestion) => msg + &suggestion,
+        None => msg,
+    }
+}
+
+fn yaml_paths(
+    value: &serde_yaml::Value,
+    prefix: &str,
+    paths: &mut Vec<(String, serde_yaml::Value)>,
+) {
+    match value {
+        serde_yaml::Value::Mapping(m) if !m.is_empty() => {
+            for (k, v) in m {
+                let key = match k {
+                    serde_yaml::Value::String(k) => k.clone(),
+                    other => serde_json::to_string(other).unwrap_or_default(),
+                };
+                let path = if prefix.is_empty() {
+                    key
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                yaml_paths(v, &path, paths)
+            }
+        }
+        _ => paths.push((prefix.to_string(), value.clone())),
+    }
+}
+
+/// Lists every config value with its source
+fn effective_config(config: &Config, sources: &ConfigSources) -> Result<String> {
+    let mut config = config.clone();
+    config.shared_secret = config.shared_secret.map(|_| "******".to_string());
+    let value = serde_yaml::to_value(&config)
+        .or_else(|e| AUDIOSERVE_error!(AUDIOSERVE_PRINT_EFFECTIVE_CONFIG, "{}", e))?;
+    let mut paths = Vec::new();
+    yaml_paths(&value, "", &mut paths);
+    let mut out = String::from("# Effective configuration, source of value is in comment\n");
+    let mut env_vars: Vec<_> = sources
+        .args
+        .iter()
+        .filter_map(|(_, source)| source.strip_prefix("env "))
+        .collect();
+    env_vars.dedup();
+    if !env_vars.is_empty() {
+        out.push_str(&format!(
+            "# Used environment variables: {}\n",
+            env_vars.join(", ")
+        ));
+    }
+    for (path, value) in paths {
+        let value = serde_json::to_string(&value).unwrap_or_default();
+        out.push_str(&format!(
+            "{}: {}  # {}\n",
+            path,
+            value,
+            sources.source_of(&path)
+        ));
+    }
+    Ok(out)
+}
+
+fn subcommand(args: &ArgMatches) -> Result<RunCommand> {
+    match args.subcommand() {
+        None => Ok(RunCommand::Serve),
+        Some((AUDIOSERVE_CMD_SERVE, _)) => Ok(RunCommand::Serve),
+        Some((AUDIOSERVE_CMD_SCAN, _)) => Ok(RunCommand::Scan),
+        Some((AUDIOSERVE_CMD_CHECK_CONFIG, _)) => Ok(RunCommand::CheckConfig),
+        #[cfg(feature = "shared-positions")]
+        Some((AUDIOSERVE_CMD_POSITIONS, sub)) => match sub.subcommand_name() {
+            Some(AUDIOSERVE_CMD_POSITIONS_BACKUP) => Ok(RunCommand::PositionsBackup),
+            Some(AUDIOSERVE_CMD_POSITIONS_RESTORE) => Ok(RunCommand::PositionsRestore),
+            _ => AUDIOSERVE_error!(AUDIOSERVE_CMD_POSITIONS, "Unknown positions subcommand"),
+        },
+        Some(_) => AUDIOSERVE_error!("subcommand", "Unknown subcommand"),
+    }
+}
+
 pub fn parse_args() -> Result<Config> {
     parse_args_from(env::args_os())
 }
 
+pub fn parse_args_from<I, T>(args: I) -> Result<Config>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<std::ffi::OsString> + Clone,
+{
+    parse_args_with_sources(args).map(|(config, _)| config)
+}
+
 // Although function  is bit too long it does not make sense to split, as it deals with each config option in very plain matter
 #[allow(clippy::cognitive_complexity)]
-pub fn parse_args_from<I, T>(args: I) -> Result<Config>
+fn parse_args_with_sources<I, T>(args: I) -> Result<(Config, ConfigSources)>
 where
     I: IntoIterator<Item = T>,
     T: Into<std::ffi::OsString> + Clone,
 {
     let p = create_parser();
-    let mut args = p.get_matches_from(args);
+    check_env_vars(&p);
+    let matches = p.clone().get_matches_from(args);
+    let mut args = TrackedArgs::new(&p, matches);
+    let command = subcommand(&args)?;
 
     if has_flag!(args, AUDIOSERVE_HELP_DIR_OPTIONS) {
         print_dir_options_help();
@@ -528,7 +1182,7 @@ where
     let mut no_authentication_confirmed = false;
 
     let mut config: Config = if let Some(config_file) = args.get_one::<PathBuf>(AUDIOSERVE_CONFIG) {
-        let f = File::open(config_file).or_else(|e| {
+        let content = std::fs::read_to_string(config_file).or_else(|e| {
             AUDIOSERVE_error!(
                 AUDIOSERVE_CONFIG,
                 "Cannot open config file {:?}, error: {}",
@@ -537,14 +1191,20 @@ where
             )
         })?;
 
-        serde_yaml::from_reader(f).or_else(|e| {
+        let config = serde_yaml::from_str(&content).or_else(|e| {
             AUDIOSERVE_error!(
                 AUDIOSERVE_CONFIG,
                 "Invalid config file {:?}, error: {}",
                 config_file,
-                e
+                add_suggestion(e.to_string())
             )
-        })?
+        })?;
+        if let Ok(value) = serde_yaml::from_str::<serde_yaml::Value>(&content) {
+            let mut paths = Vec::new();
+            yaml_paths(&value, "", &mut paths);
+            args.sources.file = paths.into_iter().map(|(p, _)| p).collect();
+        }
+        config
     } else {
         Config::default()
     };
@@ -560,11 +1220,18 @@ where
         }
     }
 
+    args.note_source("base_dirs", AUDIOSERVE_BASE_DIRS);
+    args.note_source("base_dirs_options", AUDIOSERVE_BASE_DIRS);
     if let Some(base_dirs) = args.get_many::<String>(AUDIOSERVE_BASE_DIRS) {
         for dir in base_dirs {
             config.add_base_dir(dir)?;
         }
     }
+    set_config!(
+        args,
+        config.auto_collections,
+        Some(AUDIOSERVE_AUTO_COLLECTIONS)
+    );
 
     if let Ok(port) = env::var("PORT") {
         // this is hack for heroku, which requires program to use env. variable PORT
@@ -572,10 +1239,15 @@ where
             .parse()
             .or_else(|_| AUDIOSERVE_error!(AUDIOSERVE_LISTEN, "Invalid value in $PORT"))?;
         config.listen = SocketAddr::from(([0, 0, 0, 0], port));
-    } else if let Some(addr) = args.remove_one::<SocketAddr>(AUDIOSERVE_LISTEN) {
-        config.listen = addr;
+        args.sources.args.push(("listen".into(), "env PORT".into()));
+    } else {
+        args.note_source("listen", AUDIOSERVE_LISTEN);
+        if let Some(addr) = args.remove_one::<SocketAddr>(AUDIOSERVE_LISTEN) {
+            config.listen = addr;
+        }
     }
 
+    args.note_source("thread_pool", AUDIOSERVE_THREAD_POOL_LARGE);
     if has_flag!(args, AUDIOSERVE_THREAD_POOL_LARGE) {
         config.thread_pool = ThreadPoolConfig {
             num_threads: 16,
@@ -584,6 +1256,13 @@ where
         };
     }
 
+    for arg in [
+        AUDIOSERVE_NO_AUTHENTICATION,
+        AUDIOSERVE_SHARED_SECRET,
+        AUDIOSERVE_SHARED_SECRET_FILE,
+    ] {
+        args.note_source("shared_secret", arg);
+    }
     if has_flag!(args, AUDIOSERVE_NO_AUTHENTICATION) {
         config.shared_secret = None;
         no_authentication_confirmed = true
@@ -596,28 +1275,99 @@ where
     set_config!(args, config.limit_rate, Some(AUDIOSERVE_LIMIT_RATE));
     set_config!(
         args,
-        config.transcoding.max_parallel_processes,
-        AUDIOSERVE_TRANSCODING_MAX_PARALLEL_PROCESSES
+        config.max_streams_per_client,
+        Some(AUDIOSERVE_MAX_STREAMS_PER_CLIENT)
     );
     set_config!(
         args,
-        config.transcoding.max_runtime_hours,
-        AUDIOSERVE_TRANSCODING_MAX_RUNTIME
+        config.limit_stream_kbps,
+        Some(AUDIOSERVE_LIMIT_STREAM_KBPS)
     );
-
     set_config!(
         args,
-        config.thread_pool.keep_alive,
-        Some(AUDIOSERVE_THREAD_POOL_KEEP_ALIVE)
+        config.login_throttle.max_failures,
+        AUDIOSERVE_LOGIN_MAX_FAILURES
     );
-
-    if let Some(validity) = args.remove_one::<u32>(AUDIOSERVE_TOKEN_VALIDITY_DAYS) {
-        config.token_validity_hours = validity * 24
-    }
-    set_config!(args, config.client_dir, AUDIOSERVE_CLIENT_DIR);
-    set_config!(args, config.secret_file, AUDIOSERVE_SECRET_FILE);
-
-    if has_flag!(args, AUDIOSERVE_CORS) {
+    set_config!(
+        args,
+        config.login_throttle.lockout,
+        AUDIOSERVE_LOGIN_LOCKOUT
+    );
+    set_config!(args, config.request_body.max_size, AUDIOSERVE_MAX_BODY_SIZE);
+    set_config!(
+        args,
+        config.request_body.read_timeout,
+        AUDIOSERVE_BODY_READ_TIMEOUT
+    );
+    args.note_source("allowed_clients", AUDIOSERVE_ALLOWED_CLIENTS);
+    if let Some(nets) = args.remove_many(AUDIOSERVE_ALLOWED_CLIENTS) {
+        config.allowed_clients = nets.collect();
+    }
+    args.note_source("denied_clients", AUDIOSERVE_DENIED_CLIENTS);
+    if let Some(nets) = args.remove_many(AUDIOSERVE_DENIED_CLIENTS) {
+        config.denied_clients = nets.collect();
+    }
+    set_config!(
+        args,
+        config.transcoding.max_parallel_processes,
+        AUDIOSERVE_TRANSCODING_MAX_PARALLEL_PROCESSES
+    );
+    set_config!(
+        args,
+        config.transcoding.max_runtime_hours,
+        AUDIOSERVE_TRANSCODING_MAX_RUNTIME
+    );
+    set_config!(
+        args,
+        config.transcoding.shutdown_grace_period,
+        AUDIOSERVE_TRANSCODING_GRACE_PERIOD
+    );
+    set_config_flag!(
+        args,
+        config.transcoding.loudness_normalize,
+        AUDIOSERVE_TRANSCODING_LOUDNESS_NORMALIZE
+    );
+    set_config_flag!(
+        args,
+        config.transcoding.smart_passthrough,
+        AUDIOSERVE_TRANSCODING_SMART_PASSTHROUGH
+    );
+    set_config!(
+        args,
+        config.transcoding.ffmpeg.path,
+        AUDIOSERVE_TRANSCODING_FFMPEG_PATH
+    );
+    set_config!(
+        args,
+        config.transcoding.ffmpeg.nice,
+        Some(AUDIOSERVE_TRANSCODING_NICE)
+    );
+    set_config!(
+        args,
+        config.transcoding.ffmpeg.ionice,
+        Some(AUDIOSERVE_TRANSCODING_IONICE)
+    );
+    set_config!(
+        args,
+        config.transcoding.ffmpeg.memory_limit,
+        Some(AUDIOSERVE_TRANSCODING_MEMORY_LIMIT)
+    );
+
+    set_config!(
+        args,
+        config.thread_pool.keep_alive,
+        Some(AUDIOSERVE_THREAD_POOL_KEEP_ALIVE)
+    );
+
+    args.note_source("token_validity_hours", AUDIOSERVE_TOKEN_VALIDITY_DAYS);
+    if let Some(validity) = args.remove_one::<u32>(AUDIOSERVE_TOKEN_VALIDITY_DAYS) {
+        config.token_validity_hours = validity * 24
+    }
+    set_config!(args, config.client_dir, AUDIOSERVE_CLIENT_DIR);
+    set_config!(args, config.secret_file, AUDIOSERVE_SECRET_FILE);
+
+    args.note_source("cors", AUDIOSERVE_CORS);
+    if has_flag!(args, AUDIOSERVE_CORS) {
         config.cors = match args.remove_one(AUDIOSERVE_CORS_REGEX) {
             Some(o) => Some(CorsConfig {
                 allow: Cors::default(),
@@ -627,12 +1377,17 @@ where
         }
     }
 
+    args.note_source("collapse_cd_folders", AUDIOSERVE_COLLAPSE_CD_FOLDERS);
     if has_flag!(args, AUDIOSERVE_COLLAPSE_CD_FOLDERS) {
         config.collapse_cd_folders = match args.remove_one(AUDIOSERVE_CD_FOLDER_REGEX) {
             Some(re) => Some(CollapseCDFolderConfig { regex: Some(re) }),
             None => Some(CollapseCDFolderConfig::default()),
         }
     }
+    args.note_source(
+        "natural_files_ordering",
+        AUDIOSERVE_NO_NATURAL_FILES_ORDERING,
+    );
     if has_flag!(args, AUDIOSERVE_NO_NATURAL_FILES_ORDERING) {
         config.natural_files_ordering = false;
     }
@@ -642,6 +1397,8 @@ where
         AUDIOSERVE_FORCE_CACHE_UPDATE
     );
 
+    args.note_source("tags", AUDIOSERVE_TAGS_CUSTOM);
+    args.note_source("tags", AUDIOSERVE_TAGS);
     if let Some(tags) = args.remove_many::<String>(AUDIOSERVE_TAGS_CUSTOM) {
         for t in tags {
             if !ALLOWED_TAGS.iter().any(|&allowed| t == allowed) {
@@ -663,15 +1420,24 @@ where
         }
     };
 
+    args.note_source(
+        "static_resource_cache_age",
+        AUDIOSERVE_STATIC_RESOURCE_CACHE_AGE,
+    );
     if let Some(age) = args.remove_one(AUDIOSERVE_STATIC_RESOURCE_CACHE_AGE) {
         config.static_resource_cache_age = parse_cache_age(age)?;
     }
 
+    args.note_source("folder_file_cache_age", AUDIOSERVE_FOLDER_FILE_CACHE_AGE);
     if let Some(age) = args.remove_one(AUDIOSERVE_FOLDER_FILE_CACHE_AGE) {
         config.folder_file_cache_age = parse_cache_age(age)?;
     }
 
     set_config!(args, config.icons.size, AUDIOSERVE_ICONS_SIZE);
+    args.note_source("icons.allowed_sizes", AUDIOSERVE_ICONS_ALLOWED_SIZES);
+    if let Some(sizes) = args.remove_many::<u32>(AUDIOSERVE_ICONS_ALLOWED_SIZES) {
+        config.icons.allowed_sizes = sizes.collect();
+    }
     set_config!(args, config.icons.cache_dir, AUDIOSERVE_ICONS_CACHE_DIR);
     set_config!(
         args,
@@ -693,6 +1459,32 @@ where
         config.icons.cache_save_often,
         AUDIOSERVE_ICONS_CACHE_SAVE_OFTEN
     );
+    set_config!(
+        args,
+        config.icons.cache_save_interval,
+        AUDIOSERVE_ICONS_CACHE_SAVE_INTERVAL
+    );
+
+    args.note_source("covers.allowed_sizes", AUDIOSERVE_COVERS_ALLOWED_SIZES);
+    if let Some(sizes) = args.remove_many::<u32>(AUDIOSERVE_COVERS_ALLOWED_SIZES) {
+        config.covers.allowed_sizes = sizes.collect();
+    }
+    set_config!(args, config.covers.cache_dir, AUDIOSERVE_COVERS_CACHE_DIR);
+    set_config!(
+        args,
+        config.covers.cache_max_size,
+        AUDIOSERVE_COVERS_CACHE_SIZE
+    );
+    set_config!(
+        args,
+        config.covers.cache_max_files,
+        AUDIOSERVE_COVERS_CACHE_MAX_FILES
+    );
+    set_config_flag!(
+        args,
+        config.covers.cache_disabled,
+        AUDIOSERVE_COVERS_CACHE_DISABLE
+    );
 
     set_config!(
         args,
@@ -706,6 +1498,11 @@ where
         AUDIOSERVE_CHAPTERS_FROM_DURATION
     );
     set_config!(args, config.chapters.duration, AUDIOSERVE_CHAPTERS_DURATION);
+    set_config_flag!(
+        args,
+        config.chapters.from_silence,
+        AUDIOSERVE_CHAPTERS_FROM_SILENCE
+    );
     set_config_flag!(args, config.no_dir_collaps, AUDIOSERVE_NO_DIR_COLLAPS);
     set_config_flag!(
         args,
@@ -724,6 +1521,46 @@ where
         AUDIOSERVE_COMPRESS_RESPONSES
     );
 
+    args.note_source("access_log.file", AUDIOSERVE_ACCESS_LOG);
+    if let Some(file) = args.remove_one(AUDIOSERVE_ACCESS_LOG) {
+        let access_log = config.access_log.get_or_insert_with(Default::default);
+        access_log.file = file;
+    }
+    if let Some(access_log) = config.access_log.as_mut() {
+        set_config!(args, access_log.max_size, AUDIOSERVE_ACCESS_LOG_MAX_SIZE);
+        set_config!(args, access_log.max_files, AUDIOSERVE_ACCESS_LOG_MAX_FILES);
+    }
+
+    args.note_source("dlna", AUDIOSERVE_DLNA);
+    if has_flag!(args, AUDIOSERVE_DLNA) {
+        config.dlna.get_or_insert_with(Default::default);
+    }
+    if let Some(dlna) = config.dlna.as_mut() {
+        set_config!(args, dlna.name, AUDIOSERVE_DLNA_NAME);
+        set_config!(args, dlna.account, Some(AUDIOSERVE_DLNA_ACCOUNT));
+    }
+    set_config_flag!(args, config.discovery.mdns, AUDIOSERVE_MDNS);
+
+    args.note_source("import.dir", AUDIOSERVE_IMPORT_DIR);
+    if let Some(dir) = args.remove_one(AUDIOSERVE_IMPORT_DIR) {
+        let import = config.import.get_or_insert_with(Default::default);
+        import.dir = dir;
+    }
+    if let Some(import) = config.import.as_mut() {
+        set_config!(args, import.collection, AUDIOSERVE_IMPORT_COLLECTION);
+        set_config!(args, import.transcode, Some(AUDIOSERVE_IMPORT_TRANSCODE));
+    }
+    set_config!(
+        args,
+        config.cache_maintenance.schedule,
+        Some(AUDIOSERVE_CACHE_MAINTENANCE_SCHEDULE)
+    );
+    set_config!(
+        args,
+        config.cache_maintenance.target_percent,
+        AUDIOSERVE_CACHE_MAINTENANCE_TARGET
+    );
+
     // prepared for collection changes watch to be features
     {
         set_config_flag!(
@@ -736,16 +1573,57 @@ where
             config.collections_options.changes_debounce_interval,
             AUDIOSERVE_CHANGES_DEBOUNCE_INTERVAL
         );
+        set_config!(
+            args,
+            config.collections_options.changes_poll_interval,
+            AUDIOSERVE_CHANGES_POLL_INTERVAL
+        );
+    }
+    set_config!(
+        args,
+        config.collections_options.rescan_control_file,
+        Some(AUDIOSERVE_RESCAN_CONTROL_FILE)
+    );
+    set_config!(
+        args,
+        config.collections_options.scan_threads,
+        AUDIOSERVE_SCAN_THREADS
+    );
+    set_config!(
+        args,
+        config.collections_options.scan_io_delay_ms,
+        AUDIOSERVE_SCAN_IO_DELAY
+    );
+
+    args.note_source(
+        "collections_options.cover_sources",
+        AUDIOSERVE_COVER_SOURCES,
+    );
+    if let Some(sources) = args.remove_many::<CoverSource>(AUDIOSERVE_COVER_SOURCES) {
+        let mut cover_sources = Vec::new();
+        for source in sources {
+            if !cover_sources.contains(&source) {
+                cover_sources.push(source)
+            }
+        }
+        config.collections_options.cover_sources = cover_sources;
+    }
+    args.note_source("collections_options.cover_names", AUDIOSERVE_COVER_NAMES);
+    if let Some(names) = args.remove_many::<String>(AUDIOSERVE_COVER_NAMES) {
+        config.collections_options.cover_names = names.collect();
     }
 
     // Arguments for optional features
 
+    #[cfg(feature = "symlinks")]
+    args.note_source("allow_symlinks", AUDIOSERVE_ALLOW_SYMLINKS);
     if cfg!(feature = "symlinks") && has_flag!(args, AUDIOSERVE_ALLOW_SYMLINKS) {
         config.allow_symlinks = true
     }
 
     #[cfg(feature = "tls")]
     {
+        args.note_source("ssl", AUDIOSERVE_SSL_KEY);
         if let Some(key) = args.remove_one(AUDIOSERVE_SSL_KEY) {
             let key_file = key;
             let cert_file = args.remove_one(AUDIOSERVE_SSL_CERT).unwrap();
@@ -783,6 +1661,21 @@ where
             config.transcoding.cache.save_often,
             AUDIOSERVE_T_CACHE_SAVE_OFTEN
         );
+        set_config!(
+            args,
+            config.transcoding.cache.preheat,
+            Some(AUDIOSERVE_T_CACHE_PREHEAT)
+        );
+        set_config_flag!(
+            args,
+            config.transcoding.cache.content_keys,
+            AUDIOSERVE_T_CACHE_CONTENT_KEYS
+        );
+        set_config_flag!(
+            args,
+            config.transcoding.cache.resume,
+            AUDIOSERVE_T_CACHE_RESUME
+        );
     };
 
     if cfg!(feature = "folder-download") {
@@ -791,18 +1684,40 @@ where
             config.disable_folder_download,
             AUDIOSERVE_DISABLE_FOLDER_DOWNLOAD
         );
+        set_config!(
+            args,
+            config.recursive_download.max_files,
+            AUDIOSERVE_RECURSIVE_DOWNLOAD_MAX_FILES
+        );
+        set_config!(
+            args,
+            config.recursive_download.max_size,
+            AUDIOSERVE_RECURSIVE_DOWNLOAD_MAX_SIZE
+        );
     } else {
         config.disable_folder_download = true
     }
 
     if cfg!(feature = "behind-proxy") {
         set_config_flag!(args, config.behind_proxy, AUDIOSERVE_BEHIND_PROXY);
+        args.note_source("trusted_proxies", AUDIOSERVE_TRUSTED_PROXIES);
+        if let Some(nets) = args.remove_many(AUDIOSERVE_TRUSTED_PROXIES) {
+            config.trusted_proxies = nets.collect();
+        }
     } else {
         config.behind_proxy = false;
+        config.trusted_proxies = vec![];
+    }
+
+    if cfg!(feature = "subsonic") {
+        set_config_flag!(args, config.subsonic_api, AUDIOSERVE_SUBSONIC_API);
+    } else {
+        config.subsonic_api = false;
     }
 
     #[cfg(feature = "shared-positions")]
     {
+        args.note_source("positions.restore", AUDIOSERVE_POSITIONS_RESTORE);
         if let Some(ps) = args.remove_one(AUDIOSERVE_POSITIONS_RESTORE) {
             config.positions.restore = ps;
             no_authentication_confirmed = true;
@@ -812,16 +1727,55 @@ where
             config.positions.backup_file,
             Some(AUDIOSERVE_POSITIONS_BACKUP_FILE)
         );
+        set_config!(
+            args,
+            config.positions.import_group,
+            Some(AUDIOSERVE_POSITIONS_IMPORT_GROUP)
+        );
         set_config!(
             args,
             config.positions.ws_timeout,
             AUDIOSERVE_POSITIONS_WS_TIMEOUT
         );
+        set_config!(
+            args,
+            config.positions.ws_ping_interval,
+            Some(AUDIOSERVE_POSITIONS_WS_PING_INTERVAL)
+        );
         set_config!(
             args,
             config.positions.backup_schedule,
             Some(AUDIOSERVE_POSITIONS_BACKUP_SCHEDULE)
         );
+        set_config!(
+            args,
+            config.positions.backup_keep.count,
+            Some(AUDIOSERVE_POSITIONS_BACKUP_KEEP)
+        );
+        set_config_flag!(
+            args,
+            config.positions.backup_keep.compress,
+            AUDIOSERVE_POSITIONS_BACKUP_COMPRESS
+        );
+    }
+
+    #[cfg(feature = "sync-sessions")]
+    {
+        set_config!(
+            args,
+            config.sync_sessions.ws_timeout,
+            AUDIOSERVE_SYNC_SESSIONS_WS_TIMEOUT
+        );
+        set_config!(
+            args,
+            config.sync_sessions.max_sessions,
+            AUDIOSERVE_SYNC_SESSIONS_MAX
+        );
+        set_config!(
+            args,
+            config.sync_sessions.max_drift,
+            AUDIOSERVE_SYNC_SESSIONS_MAX_DRIFT
+        );
     }
 
     #[cfg(feature = "tags-encoding")]
@@ -829,6 +1783,27 @@ where
         set_config!(args, config.tags_encoding, Some(AUDIOSERVE_TAGS_ENCODING));
     }
 
+    config.command = command;
+    #[cfg(feature = "shared-positions")]
+    match config.command {
+        RunCommand::PositionsRestore => {
+            if matches!(config.positions.restore, PositionsBackupFormat::None) {
+                config.positions.restore = PositionsBackupFormat::V1;
+            }
+        }
+        // --positions-restore without subcommand works as before
+        RunCommand::Serve if !matches!(config.positions.restore, PositionsBackupFormat::None) => {
+            config.command = RunCommand::PositionsRestore
+        }
+        _ => (),
+    }
+    if !matches!(config.command, RunCommand::Serve | RunCommand::CheckConfig) {
+        // server is not started, so authentication is not relevant
+        no_authentication_confirmed = true;
+        // and there is no need to watch for changes in collections
+        config.collections_options.dont_watch_for_changes = true;
+    }
+
     if !no_authentication_confirmed && config.shared_secret.is_none() {
         return AUDIOSERVE_error!(
             AUDIOSERVE_SHARED_SECRET,
@@ -836,14 +1811,20 @@ where
         );
     }
 
+    let auto_dirs = config.new_auto_collections();
+    config.base_dirs.extend(auto_dirs);
     config.check()?;
     config.prepare()?;
     if has_flag!(args, AUDIOSERVE_PRINT_CONFIG) {
         println!("{}", serde_yaml::to_string(&config).unwrap());
         exit(0);
     }
+    if has_flag!(args, AUDIOSERVE_PRINT_EFFECTIVE_CONFIG) {
+        print!("{}", effective_config(&config, &args.sources)?);
+        exit(0);
+    }
 
-    Ok(config)
+    Ok((config, args.sources))
 }
 
 fn print_dir_options_help() {
@@ -869,6 +1850,7 @@ allow-symlinks              <=true|false>  follow symbolic links
 no-dir-collaps              <=true|false> do not collaps directories with single chapterized audio file
 chapters-duration           =x  duration (mins) of chapter for cutting of large audio files
 chapters-from-duration      =x  min.duration (mins) of large audio file to be cut to chapters
+chapters-from-silence       <=true|false>  move split points of large audio file to nearest silence
 tags                        =tag1+tag2...  metadata tags to collect (supported tags names separated by +)
 default-tags                <=true|false>  collect default tags. Use --help-tags argument to get more 
                             information about supported metadata tags 
@@ -876,11 +1858,26 @@ read-playlist               <=true|false> will use .m3u playlist in folder to re
                             (only files in playlist are available and in its order)
 collapse-cd-folder-regex    =regex regex used to identify and collapse CD folders
                             (folders like CD1, CD2 will be merged to parent folder)
+cover-sources               =source1+source2...  sources of folder cover in order of priority, possible sources
+                            are file (image in folder), embedded (cover in audio file) and parent (image 
+                            in parent folder)
+cover-names                 =name1+name2...  preferred names (without extension) of cover images
+ignore                      =pattern1+pattern2...  glob patterns of ignored files and folders (e.g. @eaDir+.st*),
+                            patterns are also read from .asignore file in collection root
+roots                       =/dir1+/dir2...  additional directories merged with collection directory into one
+                            collection, if same path is in several directories, first one wins
 dont-watch or no-watch      <=true|false> will not watch for changes in this collection
 changes-debounce or 
 changes-debounce-interval, 
 debounce-interval           =n number of seconds to debounce raw events from inotify. Basically limits updates while
                             still collection directories changes. 
+poll-interval               =n interval (secs) of polling for changes, if watching for changes fails, 0 disables it
+scan-threads                =n number of threads scanning collection (1-32)
+scan-io-delay               =n delay (ms) after scan of each folder to limit IO load of collection scan
+read-only or ro             <=true|false> collection cache is only read, it's not scanned nor updated and positions
+                            cannot be saved (useful for caches on read only filesystems)
+public                      <=true|false> collection can be browsed and streamed without authentication
+                            (playback positions still require authentication)
 ";
     #[cfg(feature = "tags-encoding")]
     let help = help.to_string()
@@ -941,6 +1938,14 @@ mod test {
             "99",
             "--transcoding-max-runtime",
             "99",
+            "--transcoding-grace-period",
+            "99",
+            "--transcoding-loudness-normalize",
+            "--transcoding-smart-passthrough",
+            "--transcoding-nice",
+            "-5",
+            "--transcoding-memory-limit",
+            "512",
             "--token-validity-days",
             "99",
             "--client-dir",
@@ -951,6 +1956,7 @@ mod test {
             "99",
             "--chapters-duration",
             "99",
+            "--chapters-from-silence",
             "--cors",
             "--cors-regex",
             "mameluci",
@@ -960,6 +1966,11 @@ mod test {
             "test_data/as-backup-json",
             "--positions-backup-schedule",
             "3 3 * * *",
+            "--positions-ws-ping-interval",
+            "30",
+            "--positions-backup-keep",
+            "7",
+            "--positions-backup-compress",
             "--tags",
             "test_data",
             "tests",
@@ -973,18 +1984,116 @@ mod test {
         assert_eq!(Some("usak".into()), c.shared_secret);
         assert_eq!(99, c.transcoding.max_parallel_processes);
         assert_eq!(99, c.transcoding.max_runtime_hours);
+        assert_eq!(99, c.transcoding.shutdown_grace_period);
+        assert!(c.transcoding.loudness_normalize);
+        assert!(c.transcoding.smart_passthrough);
+        assert_eq!(Some(-5), c.transcoding.ffmpeg.nice);
+        assert_eq!(Some(512), c.transcoding.ffmpeg.memory_limit);
         assert_eq!(99 * 24, c.token_validity_hours);
         assert_eq!(PathBuf::from("test_data"), c.client_dir);
         assert_eq!(PathBuf::from("test_data/some_secret"), c.secret_file);
         assert_eq!(99, c.chapters.from_duration);
         assert_eq!(99, c.chapters.duration);
+        assert!(c.chapters.from_silence);
         assert!(matches!(
             c.cors.unwrap().allow,
             Cors::AllowMatchingOrigins(_)
         ));
+        assert_eq!(Some(Duration::from_secs(30)), c.positions.ws_ping_interval);
+        assert_eq!(Some(7), c.positions.backup_keep.count);
+        assert!(c.positions.backup_keep.compress);
         assert_eq!("/user/audioserve", c.url_path_prefix.unwrap())
     }
 
+    #[test]
+    #[cfg(feature = "sync-sessions")]
+    fn test_sync_sessions() {
+        init_default_config();
+        let c = parse_args_from([
+            "audioserve",
+            "--no-authentication",
+            "--sync-sessions-ws-timeout",
+            "120",
+            "--sync-sessions-max",
+            "10",
+            "--sync-sessions-max-drift",
+            "1.5",
+            "test_data",
+        ])
+        .unwrap();
+
+        assert_eq!(Duration::from_secs(120), c.sync_sessions.ws_timeout);
+        assert_eq!(10, c.sync_sessions.max_sessions);
+        assert_eq!(1.5, c.sync_sessions.max_drift);
+    }
+
+    #[test]
+    #[cfg(feature = "shared-positions")]
+    fn test_subcommands() {
+        init_default_config();
+        let c = parse_args_from(["audioserve", "--no-authentication", "test_data"]).unwrap();
+        assert_eq!(RunCommand::Serve, c.command);
+
+        let c = parse_args_from(["audioserve", "scan", "test_data"]).unwrap();
+        assert_eq!(RunCommand::Scan, c.command);
+        assert!(c.collections_options.dont_watch_for_changes);
+
+        let c = parse_args_from([
+            "audioserve",
+            "check-config",
+            "--shared-secret",
+            "usak",
+            "test_data",
+        ])
+        .unwrap();
+        assert_eq!(RunCommand::CheckConfig, c.command);
+
+        assert!(parse_args_from(["audioserve", "positions", "backup", "test_data"]).is_err());
+        let c = parse_args_from([
+            "audioserve",
+            "positions",
+            "restore",
+            "--positions-backup-file",
+            "test_data/as-backup-json",
+            "test_data",
+        ])
+        .unwrap();
+        assert_eq!(RunCommand::PositionsRestore, c.command);
+        assert!(matches!(c.positions.restore, PositionsBackupFormat::V1));
+    }
+
+    #[test]
+    #[cfg(feature = "shared-positions")]
+    fn test_positions_import() {
+        init_default_config();
+        let c = parse_args_from([
+            "audioserve",
+            "--positions-backup-file",
+            "test_data/abs-progress.json",
+            "--positions-restore",
+            "audiobookshelf",
+            "--positions-import-group",
+            "ivan",
+            "test_data",
+        ])
+        .unwrap();
+        assert!(matches!(
+            c.positions.restore,
+            PositionsBackupFormat::Audiobookshelf
+        ));
+        assert_eq!(Some("ivan"), c.positions.import_group.as_deref());
+
+        let res = parse_args_from([
+            "audioserve",
+            "--positions-backup-file",
+            "test_data/abs-progress.json",
+            "--positions-restore",
+            "audiobookshelf",
+            "test_data",
+        ]);
+        assert!(res.is_err());
+    }
+
     #[test]
     #[cfg(feature = "transcoding-cache")]
     fn test_t_cache() {
@@ -999,6 +2108,8 @@ mod test {
             "--t-cache-max-files",
             "999",
             "--t-cache-save-often",
+            "--t-cache-content-keys",
+            "--t-cache-resume",
             "test_data",
         ])
         .unwrap();
@@ -1008,6 +2119,8 @@ mod test {
         assert_eq!(999, c.transcoding.cache.max_files);
         assert!(!c.transcoding.cache.disabled);
         assert!(c.transcoding.cache.save_often);
+        assert!(c.transcoding.cache.content_keys);
+        assert!(c.transcoding.cache.resume);
     }
 
     #[test]
@@ -1031,6 +2144,151 @@ mod test {
         assert_eq!(PathBuf::from("test_data/desc.txt"), ssl.cert_file);
     }
 
+    #[test]
+    fn test_access_log() {
+        init_default_config();
+        let c = parse_args_from([
+            "audioserve",
+            "--no-authentication",
+            "--access-log",
+            "test_data/access.log",
+            "--access-log-max-files",
+            "3",
+            "test_data",
+        ])
+        .unwrap();
+
+        let access_log = c.access_log.expect("access log is configured");
+        assert_eq!(PathBuf::from("test_data/access.log"), access_log.file);
+        assert_eq!(3, access_log.max_files);
+        assert_eq!(10, access_log.max_size);
+    }
+
+    #[test]
+    fn test_dlna() {
+        init_default_config();
+        let c = parse_args_from(["audioserve", "--no-authentication", "test_data"]).unwrap();
+        assert!(c.dlna.is_none());
+
+        let c = parse_args_from([
+            "audioserve",
+            "--no-authentication",
+            "--dlna",
+            "--dlna-name",
+            "Living room books",
+            "test_data",
+        ])
+        .unwrap();
+        let dlna = c.dlna.expect("dlna is configured");
+        assert_eq!("Living room books", dlna.name);
+        assert_eq!(900, dlna.announce_interval);
+        assert!(dlna.account.is_none());
+    }
+
+    #[test]
+    fn test_cache_maintenance() {
+        init_default_config();
+        let c = parse_args_from(["audioserve", "--no-authentication", "test_data"]).unwrap();
+        assert!(c.cache_maintenance.schedule.is_none());
+        assert_eq!(90, c.cache_maintenance.target_percent);
+
+        let c = parse_args_from([
+            "audioserve",
+            "--no-authentication",
+            "--cache-maintenance-schedule",
+            "30 4 * * *",
+            "--cache-maintenance-target",
+            "75",
+            "test_data",
+        ])
+        .unwrap();
+        assert_eq!(Some("30 4 * * *"), c.cache_maintenance.schedule.as_deref());
+        assert_eq!(75, c.cache_maintenance.target_percent);
+
+        let res = parse_args_from([
+            "audioserve",
+            "--no-authentication",
+            "--cache-maintenance-schedule",
+            "every night",
+            "test_data",
+        ]);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_import() {
+        init_default_config();
+        let c = parse_args_from(["audioserve", "--no-authentication", "test_data"]).unwrap();
+        assert!(c.import.is_none());
+
+        let import_dir = tempdir::TempDir::new("AS_IMPORT_ARGS").unwrap();
+        let second_collection = tempdir::TempDir::new("AS_IMPORT_COLLECTION").unwrap();
+        let c = parse_args_from([
+            "audioserve",
+            "--no-authentication",
+            "--import-dir",
+            import_dir.path().to_str().unwrap(),
+            "--import-collection",
+            "1",
+            "--import-transcode",
+            "m",
+            "test_data",
+            second_collection.path().to_str().unwrap(),
+        ])
+        .unwrap();
+        let import = c.import.expect("import is configured");
+        assert_eq!(import_dir.path(), import.dir);
+        assert_eq!(1, import.collection);
+        assert_eq!(Some("m"), import.transcode.as_deref());
+        assert_eq!(30, import.interval);
+
+        let res = parse_args_from([
+            "audioserve",
+            "--no-authentication",
+            "--import-dir",
+            import_dir.path().to_str().unwrap(),
+            "--import-collection",
+            "1",
+            "test_data",
+        ]);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_covers() {
+        init_default_config();
+        let c = parse_args_from(["audioserve", "--no-authentication", "test_data"]).unwrap();
+        assert!(c.covers.is_allowed_size(600));
+        assert!(!c.covers.cache_disabled);
+        let c = parse_args_from([
+            "audioserve",
+            "--no-authentication",
+            "--covers-allowed-sizes",
+            "800,1600",
+            "--covers-cache-size",
+            "50",
+            "--covers-cache-max-files",
+            "200",
+            "test_data",
+        ])
+        .unwrap();
+        assert_eq!(vec![800, 1600], c.covers.allowed_sizes);
+        assert!(!c.covers.is_allowed_size(600));
+        assert_eq!(50, c.covers.cache_max_size);
+        assert_eq!(200, c.covers.cache_max_files);
+    }
+
+    #[test]
+    fn test_mdns() {
+        init_default_config();
+        let c = parse_args_from(["audioserve", "--no-authentication", "test_data"]).unwrap();
+        assert!(!c.discovery.mdns);
+        let c =
+            parse_args_from(["audioserve", "--no-authentication", "--mdns", "test_data"]).unwrap();
+        assert!(c.discovery.mdns);
+        assert_eq!("audioserve", c.discovery.name);
+    }
+
     #[test]
     #[cfg(feature = "symlinks")]
     fn test_symlinks_in_env() {
@@ -1082,4 +2340,45 @@ mod test {
         assert_eq!(Some("/user/audioserve".into()), c.url_path_prefix);
         assert!(matches!(c.cors.unwrap().allow, Cors::AllowAllOrigins));
     }
+
+    #[test]
+    fn test_effective_config() {
+        init_default_config();
+        let (c, sources) = parse_args_with_sources([
+            "audioserve",
+            "--config",
+            "test_data/sample-config.yaml",
+            "--limit-rate",
+            "10",
+            "--transcoding-max-parallel-processes",
+            "20",
+        ])
+        .unwrap();
+        assert_eq!(
+            "cli --transcoding-max-parallel-processes",
+            sources.source_of("transcoding.max_parallel_processes")
+        );
+        assert_eq!("file", sources.source_of("transcoding.max_runtime_hours"));
+        assert_eq!(
+            "file",
+            sources.source_of("transcoding.low.opus-in-ogg.bitrate")
+        );
+        assert_eq!("default", sources.source_of("transcoding.unknown"));
+        let out = effective_config(&c, &sources).unwrap();
+        assert!(out.contains("\nlimit_rate: 10.0  # cli --limit-rate\n"));
+        assert!(out.contains("\nshared_secret: \"******\"  # file\n"));
+    }
+
+    #[test]
+    fn test_suggestions() {
+        assert_eq!(
+            Some("URL_PATH_PREFIX"),
+            did_you_mean("URL_PREFIX", ["URL_PATH_PREFIX", "LISTEN"])
+        );
+        assert_eq!(None, did_you_mean("XYZ", ["LISTEN"]));
+        let msg = "transcoding: unknown field `max_parralel_processes`, expected one of \
+            `max_parallel_processes`, `max_runtime_hours` at line 3 column 3"
+            .to_string();
+        assert!(add_suggestion(msg).ends_with(", did you mean `max_parallel_processes`?"));
+    }
 }
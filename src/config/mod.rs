@@ -1,19 +1,22 @@
-use collection::MINIMUM_CHAPTER_DURATION;
+use arc_swap::ArcSwapOption;
+use collection::{CollectionOptions, CoverSource, MINIMUM_CHAPTER_DURATION};
+use ipnet::IpNet;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 pub use self::error::{Error, Result};
 use super::services::transcode::{QualityLevel, TranscodingFormat};
-use crate::services::transcode::codecs::{Bandwidth, Opus};
+use crate::services::transcode::codecs::{Bandwidth, Mp3, Opus};
 use crate::util;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 mod cli;
@@ -21,19 +24,29 @@ mod validators;
 #[macro_use]
 mod error;
 
-static mut CONFIG: Option<Config> = None;
+// Config snapshots are leaked, so references to them can be 'static, new snapshot is created
+// only on config reload, which is rare, so leaked memory is negligible
+static CONFIG: ArcSwapOption<&'static Config> = ArcSwapOption::const_empty();
+/// serializes read-modify-write updates of running config
+static CONFIG_UPDATE: Mutex<()> = Mutex::new(());
+
+/// Account used for not authenticated requests to public collections, empty name is not
+/// valid for configured accounts, so it cannot clash
+pub const GUEST_ACCOUNT: &str = "";
 
 pub const LONG_VERSION: &str = env!("AUDIOSERVE_LONG_VERSION");
 pub const FEATURES: &str = env!("AUDIOSERVE_FEATURES");
+pub const MAX_COLLECTIONS: usize = 100;
 const CD_FOLDER_RE: &str = r"^CD[ -_]?\s*\d+\s*$";
 
-// CONFIG is assured to be inited only once from main thread
+/// Returns current config, it can change on reload, so if consistent values are needed
+/// in some operation, config should be retrieved just once
 pub fn get_config() -> &'static Config {
-    // It's safe as CONFIG is initialized only once in main thread before any other
-    #[allow(static_mut_refs)]
-    unsafe {
-        CONFIG.as_ref().expect("Config is not initialized")
-    }
+    **CONFIG.load().as_ref().expect("Config is not initialized")
+}
+
+fn set_config(config: Config) {
+    CONFIG.store(Some(Arc::new(Box::leak(Box::new(config)))))
 }
 
 static mut BASE_DATA_DIR: Option<PathBuf> = None;
@@ -57,7 +70,11 @@ pub struct IconsConfig {
     pub cache_max_files: u32,
     pub cache_disabled: bool,
     pub size: u32,
+    /// Additional icon sizes, which client can request with `size` query parameter
+    pub allowed_sizes: Vec<u32>,
     pub cache_save_often: bool,
+    /// Interval in seconds for periodic saving of icons and covers cache indexes (if changed), 0 disables
+    pub cache_save_interval: u32,
     pub fast_scaling: bool,
 }
 
@@ -71,7 +88,9 @@ impl Default for IconsConfig {
             cache_max_files: 1024,
             cache_disabled: false,
             cache_save_often: false,
+            cache_save_interval: 300,
             size: 128,
+            allowed_sizes: vec![64, 128, 256, 512],
             fast_scaling: false,
         }
     }
@@ -101,8 +120,90 @@ impl IconsConfig {
             );
         }
 
+        for &sz in self.allowed_sizes.iter().chain(std::iter::once(&self.size)) {
+            if !(16..=2048).contains(&sz) {
+                return value_error!("icons.size", "Icon size {} is not in range 16-2048", sz);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks if icon size requested by client is allowed, default size is always allowed
+    pub fn is_allowed_size(&self, size: u32) -> bool {
+        size == self.size || self.allowed_sizes.contains(&size)
+    }
+}
+
+/// Resized covers (`size` query parameter of cover), each size has its own cache with given limits
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct CoversConfig {
+    pub cache_dir: PathBuf,
+    /// Max size of cache for one size in MB
+    pub cache_max_size: u32,
+    /// Max number of files in cache for one size
+    pub cache_max_files: u32,
+    pub cache_disabled: bool,
+    /// Sizes (max. of width and height), to which cover can be resized, if empty resizing is disabled
+    pub allowed_sizes: Vec<u32>,
+}
+
+impl Default for CoversConfig {
+    fn default() -> Self {
+        let data_base_dir = base_data_dir();
+        let cache_dir = data_base_dir.join("covers-cache");
+        CoversConfig {
+            cache_dir,
+            cache_max_size: 100,
+            cache_max_files: 1024,
+            cache_disabled: false,
+            allowed_sizes: vec![600, 1200],
+        }
+    }
+}
+
+impl CoversConfig {
+    pub fn check(&self) -> Result<()> {
+        if !util::parent_dir_exists(&self.cache_dir) {
+            return value_error!(
+                "covers.cache_dir",
+                "Parent directory does not exists for {:?}",
+                self.cache_dir
+            );
+        };
+
+        if self.cache_max_size < 10 {
+            return value_error!(
+                "covers.cache_max_size",
+                "Covers cache small then 10 MB does not make sense"
+            );
+        }
+
+        if self.cache_max_files < 100 {
+            return value_error!(
+                "covers.cache_max_files",
+                "Covers cache with less the 100 files does not make sense"
+            );
+        }
+
+        for &sz in &self.allowed_sizes {
+            if !(64..=4096).contains(&sz) {
+                return value_error!(
+                    "covers.allowed_sizes",
+                    "Cover size {} is not in range 64-4096",
+                    sz
+                );
+            }
+        }
+
         Ok(())
     }
+
+    pub fn is_allowed_size(&self, size: u32) -> bool {
+        self.allowed_sizes.contains(&size)
+    }
 }
 
 #[cfg(feature = "transcoding-cache")]
@@ -115,6 +216,13 @@ pub struct TranscodingCacheConfig {
     pub max_files: u32,
     pub disabled: bool,
     pub save_often: bool,
+    /// Quality level (l, m, h) to pre-transcode all collections files into cache
+    pub preheat: Option<String>,
+    /// Key cache entries by fingerprint of file content instead of file path,
+    /// so identical files in different places share cache entry
+    pub content_keys: bool,
+    /// Keep unfinished transcodings (e.g. when client disconnects), so they can be resumed
+    pub resume: bool,
 }
 
 #[cfg(feature = "transcoding-cache")]
@@ -128,12 +236,22 @@ impl Default for TranscodingCacheConfig {
             max_files: 1024,
             disabled: false,
             save_often: false,
+            preheat: None,
+            content_keys: false,
+            resume: false,
         }
     }
 }
 
 #[cfg(feature = "transcoding-cache")]
 impl TranscodingCacheConfig {
+    pub fn preheat_level(&self) -> Option<QualityLevel> {
+        if self.disabled {
+            return None;
+        }
+        self.preheat.as_ref().and_then(QualityLevel::from_letter)
+    }
+
     pub fn check(&self) -> Result<()> {
         if !util::parent_dir_exists(&self.root_dir) {
             return value_error!(
@@ -157,6 +275,75 @@ impl TranscodingCacheConfig {
             );
         }
 
+        if let Some(ref level) = self.preheat {
+            if QualityLevel::from_letter(level).is_none() {
+                return value_error!(
+                    "preheat",
+                    "Invalid quality level {}, must be one of l, m, h",
+                    level
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct FfmpegConfig {
+    /// ffmpeg binary, if it's just name, it's searched on PATH
+    pub path: PathBuf,
+    /// additional global arguments for ffmpeg, put before input file
+    pub global_args: Vec<String>,
+    /// niceness of ffmpeg process (-20 to 19)
+    pub nice: Option<i32>,
+    /// IO priority of ffmpeg process in best effort class (0 to 7), supported only on Linux
+    pub ionice: Option<u8>,
+    /// maximum memory (address space) of ffmpeg process in MB
+    pub memory_limit: Option<u32>,
+}
+
+impl Default for FfmpegConfig {
+    fn default() -> Self {
+        FfmpegConfig {
+            path: "ffmpeg".into(),
+            global_args: vec![],
+            nice: None,
+            ionice: None,
+            memory_limit: None,
+        }
+    }
+}
+
+impl FfmpegConfig {
+    pub fn check(&self) -> Result<()> {
+        if self.path.components().count() > 1 && !self.path.is_file() {
+            return value_error!("path", "ffmpeg binary {:?} does not exist", self.path);
+        }
+
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                return value_error!("nice", "Niceness must be between -20 and 19");
+            }
+        }
+
+        if let Some(ionice) = self.ionice {
+            if ionice > 7 {
+                return value_error!("ionice", "IO priority must be between 0 and 7");
+            }
+        }
+
+        if let Some(limit) = self.memory_limit {
+            if limit < 64 {
+                return value_error!(
+                    "memory_limit",
+                    "ffmpeg will not work properly with less then 64 MB of memory"
+                );
+            }
+        }
+
         Ok(())
     }
 }
@@ -167,12 +354,24 @@ impl TranscodingCacheConfig {
 pub struct TranscodingConfig {
     pub max_parallel_processes: usize,
     pub max_runtime_hours: u32,
+    pub shutdown_grace_period: u32,
+    /// apply ReplayGain (track gain) from audio file tags when transcoding
+    pub loudness_normalize: bool,
+    /// send file without transcoding (or just remuxed) if it already has codec and bitrate of required transcoding
+    pub smart_passthrough: bool,
+    pub ffmpeg: FfmpegConfig,
     #[cfg(feature = "transcoding-cache")]
     pub cache: TranscodingCacheConfig,
     low: TranscodingFormat,
     medium: TranscodingFormat,
     high: TranscodingFormat,
     alt_configs: Option<HashMap<String, TranscodingDetails>>,
+    /// named transcoding profiles, which client can choose with query parameter
+    profiles: Option<HashMap<String, TranscodingDetails>>,
+    /// maximum quality level (l, m, h) for network type hinted by client with `bw` query parameter
+    pub bandwidth_hints: HashMap<String, String>,
+    /// format for casting devices (Chromecast), must be constant bitrate mp3
+    pub cast: TranscodingFormat,
     #[serde(skip)]
     alt_configs_inner: Option<Vec<(regex::Regex, TranscodingDetails)>>,
 }
@@ -182,12 +381,22 @@ impl Default for TranscodingConfig {
         TranscodingConfig {
             max_parallel_processes: (2 * num_cpus::get()).max(4),
             max_runtime_hours: 24,
+            shutdown_grace_period: 30,
+            loudness_normalize: false,
+            smart_passthrough: false,
+            ffmpeg: FfmpegConfig::default(),
             #[cfg(feature = "transcoding-cache")]
             cache: TranscodingCacheConfig::default(),
             low: TranscodingFormat::OpusInOgg(Opus::new(32, 5, Bandwidth::SuperWideBand, true)),
             medium: TranscodingFormat::OpusInOgg(Opus::new(48, 8, Bandwidth::SuperWideBand, false)),
             high: TranscodingFormat::OpusInOgg(Opus::new(64, 10, Bandwidth::FullBand, false)),
             alt_configs: None,
+            profiles: None,
+            bandwidth_hints: [("cellular", "l")]
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            cast: TranscodingFormat::Mp3(Mp3::new(128, 3, false, false)),
             alt_configs_inner: None,
         }
     }
@@ -229,6 +438,13 @@ impl TranscodingConfig {
             return value_error!("max_runtime_hours", "Minimum time is 1 hour");
         }
 
+        if self.shutdown_grace_period > 3600 {
+            return value_error!(
+                "shutdown_grace_period",
+                "Grace period for running transcodings can be at most 1 hour"
+            );
+        }
+
         if let Some(alt_configs) = self.alt_configs.as_ref() {
             for re in alt_configs.keys() {
                 regex::Regex::new(re)
@@ -236,6 +452,44 @@ impl TranscodingConfig {
                     .or_else(|e| value_error!("alt_encodings", "Invalid User Agent regex {}", e))?
             }
         }
+        if let Some(profiles) = self.profiles.as_ref() {
+            for name in profiles.keys() {
+                if name.is_empty()
+                    || !name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                {
+                    return value_error!(
+                        "profiles",
+                        "Invalid profile name {}, only letters, digits, - and _ are allowed",
+                        name
+                    );
+                }
+            }
+        }
+
+        for (hint, level) in &self.bandwidth_hints {
+            if QualityLevel::from_letter(level).is_none() {
+                return value_error!(
+                    "bandwidth_hints",
+                    "Invalid quality level {} for {}, must be one of l, m, h",
+                    level,
+                    hint
+                );
+            }
+        }
+
+        match &self.cast {
+            TranscodingFormat::Mp3(mp3) if !mp3.is_abr() => (),
+            _ => {
+                return value_error!(
+                    "cast",
+                    "Only constant bitrate mp3 can be used for casting, as it must be seekable"
+                )
+            }
+        }
+
+        self.ffmpeg.check()?;
         #[cfg(feature = "transcoding-cache")]
         self.cache.check()?;
         Ok(())
@@ -263,12 +517,50 @@ impl TranscodingConfig {
                 )
             }
         }
+        if let Some(profiles) = self.profiles.as_mut() {
+            for (name, cfg) in profiles.iter_mut() {
+                // profile name is part of transcoding cache key
+                cfg.tag = format!("@{}", name);
+            }
+        }
         Ok(())
     }
 
+    /// Takes transcoding formats and profiles from other (already prepared) config
+    fn update_profiles(&mut self, other: TranscodingConfig) {
+        self.low = other.low;
+        self.medium = other.medium;
+        self.high = other.high;
+        self.alt_configs = other.alt_configs;
+        self.alt_configs_inner = other.alt_configs_inner;
+        self.profiles = other.profiles;
+        self.bandwidth_hints = other.bandwidth_hints;
+        self.cast = other.cast;
+    }
+
     pub fn alt_configs(&self) -> Option<&Vec<(regex::Regex, TranscodingDetails)>> {
         self.alt_configs_inner.as_ref()
     }
+
+    pub fn profile(&self, name: &str) -> Option<&TranscodingDetails> {
+        self.profiles.as_ref().and_then(|p| p.get(name))
+    }
+
+    /// Caps requested quality level according to network type hinted by client,
+    /// unknown hints are ignored
+    pub fn level_for_bandwidth(&self, level: QualityLevel, hint: &str) -> QualityLevel {
+        match self
+            .bandwidth_hints
+            .get(hint)
+            .and_then(QualityLevel::from_letter)
+        {
+            Some(max_level) => level.min(max_level),
+            None => {
+                debug!("Unknown bandwidth hint {}", hint);
+                level
+            }
+        }
+    }
 }
 
 fn generate_tag(s: &str) -> String {
@@ -316,6 +608,19 @@ impl Default for ThreadPoolConfig {
 pub struct CollectionConfig {
     pub dont_watch_for_changes: bool,
     pub changes_debounce_interval: u32,
+    /// Interval in secs of polling for changes, if watching for changes is not possible, 0 disables polling
+    pub changes_poll_interval: u32,
+    /// Priority order of sources for folder cover, if empty image in folder is used
+    /// (for chapterized audio file cover embedded in it)
+    pub cover_sources: Vec<CoverSource>,
+    /// Preferred names (without extension) of cover image files
+    pub cover_names: Vec<String>,
+    /// File with indexes of collections to rescan on SIGUSR1, if it does not exist all collections are rescanned
+    pub rescan_control_file: Option<PathBuf>,
+    /// Number of threads scanning each collection
+    pub scan_threads: u32,
+    /// Delay in ms after scan of each folder, to limit IO load
+    pub scan_io_delay_ms: u32,
 }
 
 impl Default for CollectionConfig {
@@ -323,6 +628,12 @@ impl Default for CollectionConfig {
         Self {
             dont_watch_for_changes: false,
             changes_debounce_interval: 10,
+            changes_poll_interval: 300,
+            cover_sources: vec![],
+            cover_names: vec![],
+            rescan_control_file: None,
+            scan_threads: 1,
+            scan_io_delay_ms: 0,
         }
     }
 }
@@ -338,6 +649,39 @@ impl CollectionConfig {
             );
         }
 
+        if self.changes_poll_interval > 24 * 3600 {
+            return value_error!(
+                "changes_poll_interval",
+                "Interval is too big, max is one day"
+            );
+        }
+
+        if !(1..=collection::MAX_SCAN_THREADS).contains(&self.scan_threads) {
+            return value_error!(
+                "scan_threads",
+                "Must be between 1 and {}",
+                collection::MAX_SCAN_THREADS
+            );
+        }
+
+        if self.scan_io_delay_ms > collection::MAX_SCAN_IO_DELAY {
+            return value_error!(
+                "scan_io_delay_ms",
+                "Delay is too big, max is {}ms",
+                collection::MAX_SCAN_IO_DELAY
+            );
+        }
+
+        if let Some(ref f) = self.rescan_control_file {
+            if !util::parent_dir_exists(f) {
+                return value_error!(
+                    "rescan_control_file",
+                    "Parent directory does not exists for {:?}",
+                    f
+                );
+            }
+        }
+
         Ok(())
     }
 }
@@ -373,12 +717,180 @@ impl ThreadPoolConfig {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct RecursiveDownloadConfig {
+    pub max_files: u32,
+    pub max_size: u32,
+}
+
+impl Default for RecursiveDownloadConfig {
+    fn default() -> Self {
+        RecursiveDownloadConfig {
+            max_files: 2000,
+            max_size: 4096,
+        }
+    }
+}
+
+impl RecursiveDownloadConfig {
+    pub fn check(&self) -> Result<()> {
+        if self.max_files < 1 {
+            return value_error!(
+                "recursive_download.max_files",
+                "At least one file is required"
+            );
+        }
+
+        if self.max_size < 1 {
+            return value_error!("recursive_download.max_size", "Must be at least 1 MB");
+        }
+
+        Ok(())
+    }
+
+    #[cfg_attr(not(feature = "folder-download"), allow(dead_code))]
+    pub fn max_size_bytes(&self) -> u64 {
+        u64::from(self.max_size) * 1024 * 1024
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct LoginThrottleConfig {
+    /// failed logins from one address, after which address is locked out, 0 disables lockout
+    pub max_failures: u32,
+    /// how long is address locked out (in secs)
+    pub lockout: u32,
+    /// failures from address are forgotten after this time (in secs) without failed login
+    pub failures_decay: u32,
+    /// delay (in ms) of response to failed login, it doubles with each subsequent failure
+    pub initial_delay: u32,
+    /// maximum delay (in ms) of response to failed login
+    pub max_delay: u32,
+}
+
+impl Default for LoginThrottleConfig {
+    fn default() -> Self {
+        LoginThrottleConfig {
+            max_failures: 10,
+            lockout: 900,
+            failures_decay: 3600,
+            initial_delay: 500,
+            max_delay: 8000,
+        }
+    }
+}
+
+impl LoginThrottleConfig {
+    pub fn check(&self) -> Result<()> {
+        if self.max_failures > 0 && self.lockout < 1 {
+            return value_error!("login_throttle.lockout", "Must be at least 1 sec");
+        }
+        if self.failures_decay < 1 {
+            return value_error!("login_throttle.failures_decay", "Must be at least 1 sec");
+        }
+        if self.max_delay < self.initial_delay {
+            return value_error!(
+                "login_throttle.max_delay",
+                "Must be greater or equal to initial delay"
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct RequestBodyConfig {
+    /// maximum size (in bytes) of request body (for POST requests)
+    pub max_size: u64,
+    /// timeout (in secs) for receiving whole request body
+    pub read_timeout: u32,
+}
+
+impl Default for RequestBodyConfig {
+    fn default() -> Self {
+        RequestBodyConfig {
+            max_size: 10 * 1024 * 1024,
+            read_timeout: 30,
+        }
+    }
+}
+
+impl RequestBodyConfig {
+    pub fn check(&self) -> Result<()> {
+        if self.max_size < 1024 {
+            return value_error!("request_body.max_size", "Must be at least 1024 bytes");
+        }
+        if self.read_timeout < 1 {
+            return value_error!("request_body.read_timeout", "Must be at least 1 sec");
+        }
+        Ok(())
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout.into())
+    }
+}
+
+/// Request rate limit for one class of endpoints
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// requests per second
+    pub rate: f32,
+    /// number of requests, which can come at once, default is rate rounded up
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+impl RateLimitConfig {
+    fn check(&self, name: &'static str) -> Result<()> {
+        if self.rate <= 0.0 {
+            return value_error!(name, "Rate must be positive");
+        }
+        if self.burst == Some(0) {
+            return value_error!(name, "Burst must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// Rate limits for classes of endpoints, applied in addition to `limit_rate`
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// transcoded audio and folder downloads
+    pub expensive: Option<RateLimitConfig>,
+    /// JSON API endpoints (folders, search, positions ...)
+    pub api: Option<RateLimitConfig>,
+}
+
+impl LimitsConfig {
+    pub fn check(&self) -> Result<()> {
+        if let Some(expensive) = self.expensive.as_ref() {
+            expensive.check("limits.expensive")?;
+        }
+        if let Some(api) = self.api.as_ref() {
+            api.check("limits.api")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(default)]
 #[serde(deny_unknown_fields)]
 pub struct ChaptersSize {
     pub from_duration: u32,
     pub duration: u32,
+    /// split points are moved to nearest silence
+    pub from_silence: bool,
 }
 
 impl Default for ChaptersSize {
@@ -386,6 +898,7 @@ impl Default for ChaptersSize {
         ChaptersSize {
             from_duration: 0,
             duration: 30,
+            from_silence: false,
         }
     }
 }
@@ -423,44 +936,296 @@ impl SslConfig {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum PositionsBackupFormat {
-    None,
-    Legacy,
-    V1,
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct AccessLogConfig {
+    pub file: PathBuf,
+    /// max size of log file in MB, when reached file is rotated
+    pub max_size: u32,
+    /// number of rotated files to keep
+    pub max_files: u32,
 }
 
-impl FromStr for PositionsBackupFormat {
-    type Err = Error;
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        AccessLogConfig {
+            file: PathBuf::from("access.log"),
+            max_size: 10,
+            max_files: 5,
+        }
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self> {
-        match s {
-            "legacy" => Ok(PositionsBackupFormat::Legacy),
-            "v1" => Ok(PositionsBackupFormat::V1),
-            _ => value_error!("positions-restore", "Invalid version"),
+impl AccessLogConfig {
+    pub fn check(&self) -> Result<()> {
+        if !util::parent_dir_exists(&self.file) {
+            return value_error!(
+                "access_log",
+                "Parent directory does not exists for {:?}",
+                self.file
+            );
         }
+        if self.max_size < 1 {
+            return value_error!("access_log", "Minimum size of log file is 1MB");
+        }
+        if self.max_files > 100 {
+            return value_error!("access_log", "Too many rotated log files (max is 100)");
+        }
+        Ok(())
     }
 }
 
-#[cfg(feature = "shared-positions")]
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(default)]
 #[serde(deny_unknown_fields)]
-pub struct PositionsConfig {
-    pub ws_timeout: Duration,
-    pub backup_file: Option<PathBuf>,
-    pub restore: PositionsBackupFormat,
-    pub backup_schedule: Option<String>,
+pub struct DlnaConfig {
+    /// name of media server, as shown on devices
+    pub name: String,
+    /// how often (in secs) server announces its presence in local network
+    pub announce_interval: u32,
+    /// collections available for this account are shared, all collections if not given
+    pub account: Option<String>,
 }
 
-#[cfg(feature = "shared-positions")]
-impl Default for PositionsConfig {
+impl Default for DlnaConfig {
     fn default() -> Self {
-        Self {
-            ws_timeout: Duration::from_secs(600),
-            backup_file: None,
-            restore: PositionsBackupFormat::None,
-            backup_schedule: None,
+        DlnaConfig {
+            name: "audioserve".into(),
+            announce_interval: 900,
+            account: None,
+        }
+    }
+}
+
+impl DlnaConfig {
+    pub fn check(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            return value_error!("dlna-name", "Media server name cannot be empty");
+        }
+        if self.announce_interval < 60 {
+            return value_error!("dlna", "Announce interval must be at least 60s");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// advertise server via mDNS (zeroconf) in local network
+    pub mdns: bool,
+    /// name of advertised service instance
+    pub name: String,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        DiscoveryConfig {
+            mdns: false,
+            name: "audioserve".into(),
+        }
+    }
+}
+
+impl DiscoveryConfig {
+    pub fn check(&self) -> Result<()> {
+        if self.name.trim().is_empty() || self.name.len() > 63 {
+            return value_error!("discovery", "Service name must have 1 - 63 characters");
+        }
+        Ok(())
+    }
+}
+
+/// Scheduled maintenance of file caches (transcoding, icons and covers caches)
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct CacheMaintenanceConfig {
+    /// cron expression (m h dom mon dow), if not set maintenance is not run
+    pub schedule: Option<String>,
+    /// caches are trimmed to this percentage of their max size and max files
+    pub target_percent: u8,
+}
+
+impl Default for CacheMaintenanceConfig {
+    fn default() -> Self {
+        CacheMaintenanceConfig {
+            schedule: None,
+            target_percent: 90,
+        }
+    }
+}
+
+impl CacheMaintenanceConfig {
+    pub fn check(&self) -> Result<()> {
+        if let Some(schedule) = self.schedule.as_ref() {
+            if crate::util::parse_cron(schedule).is_err() {
+                return value_error!("cache-maintenance-schedule", "Invalid cron expression");
+            }
+        }
+        if !(1..=100).contains(&self.target_percent) {
+            return value_error!(
+                "cache-maintenance-target",
+                "Target must be percentage between 1 and 100"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Watch folder import - audio files dropped into import directory are moved into collection
+/// into Author/Title folder (from tags), optionally transcoded
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct ImportConfig {
+    /// watched directory
+    pub dir: PathBuf,
+    /// index of collection, into which files are imported
+    pub collection: usize,
+    /// quality level (l, m or h) of transcoding of imported files, if not given files are imported as they are
+    pub transcode: Option<String>,
+    /// how often (in secs) import directory is checked
+    pub interval: u32,
+    /// files must not be modified for this time (in secs) before import, so they are completely copied
+    pub settle_time: u32,
+}
+
+impl Default for ImportConfig {
+    fn default() -> Self {
+        ImportConfig {
+            dir: PathBuf::new(),
+            collection: 0,
+            transcode: None,
+            interval: 30,
+            settle_time: 60,
+        }
+    }
+}
+
+impl ImportConfig {
+    pub fn check(&self, base_dirs: &[PathBuf]) -> Result<()> {
+        if !self.dir.is_dir() {
+            return value_error!("import-dir", "{:?} is not directory", self.dir);
+        }
+        let collection_dir = match base_dirs.get(self.collection) {
+            Some(d) => d,
+            None => {
+                return value_error!(
+                    "import-collection",
+                    "Invalid collection index {}",
+                    self.collection
+                )
+            }
+        };
+        let dir = match self.dir.canonicalize() {
+            Ok(d) => d,
+            Err(e) => return value_error!("import-dir", "Cannot resolve path: {}", e),
+        };
+        if base_dirs
+            .iter()
+            .filter_map(|d| d.canonicalize().ok())
+            .any(|d| dir.starts_with(&d) || d.starts_with(&dir))
+        {
+            return value_error!(
+                "import-dir",
+                "Import directory cannot be inside collection or contain it"
+            );
+        }
+        if !collection_dir.is_dir() {
+            return value_error!("import-collection", "Collection directory does not exist");
+        }
+        if let Some(ref level) = self.transcode {
+            if !["l", "m", "h"].contains(&level.as_str()) {
+                return value_error!("import-transcode", "Quality level must be l, m or h");
+            }
+        }
+        if self.interval == 0 {
+            return value_error!("import", "Check interval must be at least 1s");
+        }
+        Ok(())
+    }
+}
+
+/// What audioserve should do, all commands except serve are one shot - program exits after it's done
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RunCommand {
+    #[default]
+    Serve,
+    Scan,
+    CheckConfig,
+    #[cfg(feature = "shared-positions")]
+    PositionsBackup,
+    #[cfg(feature = "shared-positions")]
+    PositionsRestore,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PositionsBackupFormat {
+    None,
+    Legacy,
+    V1,
+    Booksonic,
+    Audiobookshelf,
+}
+
+impl FromStr for PositionsBackupFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "legacy" => Ok(PositionsBackupFormat::Legacy),
+            "v1" => Ok(PositionsBackupFormat::V1),
+            "booksonic" => Ok(PositionsBackupFormat::Booksonic),
+            "audiobookshelf" => Ok(PositionsBackupFormat::Audiobookshelf),
+            _ => value_error!("positions-restore", "Invalid version"),
+        }
+    }
+}
+
+#[cfg(feature = "shared-positions")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct PositionsConfig {
+    pub ws_timeout: Duration,
+    /// server sends pings in this interval to detect dead connections
+    pub ws_ping_interval: Option<Duration>,
+    pub backup_file: Option<PathBuf>,
+    pub restore: PositionsBackupFormat,
+    /// group into which positions are imported from other servers
+    pub import_group: Option<String>,
+    pub backup_schedule: Option<String>,
+    pub backup_keep: PositionsBackupKeep,
+    /// group -> months, positions not updated for longer are pruned, `*` applies to all other groups
+    pub prune_after: HashMap<String, u32>,
+}
+
+#[cfg(feature = "shared-positions")]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct PositionsBackupKeep {
+    /// number of timestamped backups to keep, if not set single backup file is rewritten
+    pub count: Option<usize>,
+    /// backups are compressed with gzip
+    pub compress: bool,
+}
+
+#[cfg(feature = "shared-positions")]
+impl Default for PositionsConfig {
+    fn default() -> Self {
+        Self {
+            ws_timeout: Duration::from_secs(600),
+            ws_ping_interval: Some(Duration::from_secs(60)),
+            backup_file: None,
+            restore: PositionsBackupFormat::None,
+            import_group: None,
+            backup_schedule: None,
+            backup_keep: PositionsBackupKeep::default(),
+            prune_after: HashMap::new(),
         }
     }
 }
@@ -472,11 +1237,263 @@ impl PositionsConfig {
             return value_error!("positions-ws-timeout", "Timeout must be at least 60s");
         }
 
+        if let Some(ping_interval) = self.ws_ping_interval {
+            if ping_interval < Duration::from_secs(5) || ping_interval >= self.ws_timeout {
+                return value_error!(
+                    "positions-ws-ping-interval",
+                    "Ping interval must be at least 5s and shorter then timeout"
+                );
+            }
+        }
+
         if let Some(schedule) = self.backup_schedule.as_ref() {
             if crate::util::parse_cron(schedule).is_err() {
                 return value_error!("positions-backup-schedule", "Invalid cron expression");
             }
         }
+
+        if matches!(self.restore, PositionsBackupFormat::Audiobookshelf)
+            && self.import_group.is_none()
+        {
+            return value_error!(
+                "positions-import-group",
+                "Group is required for import from Audiobookshelf"
+            );
+        }
+
+        if self.backup_keep.count == Some(0) {
+            return value_error!("positions-backup-keep", "Must keep at least one backup");
+        }
+
+        if self.prune_after.values().any(|months| *months == 0) {
+            return value_error!("positions.prune_after", "Age must be at least one month");
+        }
+        Ok(())
+    }
+
+    /// Maximum age of positions of the group, month is counted as 30 days
+    pub fn max_age(&self, group: &str) -> Option<Duration> {
+        self.prune_after
+            .get(group)
+            .or_else(|| self.prune_after.get("*"))
+            .map(|months| Duration::from_secs(u64::from(*months) * 30 * 24 * 3600))
+    }
+}
+
+#[cfg(feature = "sync-sessions")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct SyncSessionsConfig {
+    /// idle websocket connection is closed after this timeout
+    pub ws_timeout: Duration,
+    pub max_sessions: usize,
+    /// maximum number of listeners in one session, including host
+    pub max_members: usize,
+    /// how often is playback state sent to all listeners
+    pub sync_interval: Duration,
+    /// listener, whose reported position differs more (in seconds), is asked to seek
+    pub max_drift: f32,
+}
+
+#[cfg(feature = "sync-sessions")]
+impl Default for SyncSessionsConfig {
+    fn default() -> Self {
+        Self {
+            ws_timeout: Duration::from_secs(600),
+            max_sessions: 100,
+            max_members: 20,
+            sync_interval: Duration::from_secs(10),
+            max_drift: 2.0,
+        }
+    }
+}
+
+#[cfg(feature = "sync-sessions")]
+impl SyncSessionsConfig {
+    pub fn check(&self) -> Result<()> {
+        if self.ws_timeout < Duration::from_secs(60) {
+            return value_error!("sync-sessions-ws-timeout", "Timeout must be at least 60s");
+        }
+        if self.max_members < 2 {
+            return value_error!("max_members", "Session must allow at least 2 listeners");
+        }
+        if self.sync_interval < Duration::from_secs(1) {
+            return value_error!("sync_interval", "Sync interval must be at least 1s");
+        }
+        if self.max_drift < 0.1 {
+            return value_error!("sync-sessions-max-drift", "Max drift must be at least 0.1s");
+        }
+        Ok(())
+    }
+}
+
+/// URL, which can be used by outgoing HTTP client
+#[cfg(any(feature = "webhooks", feature = "scrobbler"))]
+fn is_supported_url(url: &url::Url) -> bool {
+    url.has_host() && (url.scheme() == "http" || cfg!(feature = "tls") && url.scheme() == "https")
+}
+
+/// Events, which can be sent to webhook
+#[cfg(feature = "webhooks")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebhookEvent {
+    BookFinished,
+    FolderAdded,
+    ScanFinished,
+}
+
+#[cfg(feature = "webhooks")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// http URL, https is supported only with `tls` feature
+    pub url: String,
+    /// events sent to this URL, if empty all events are sent
+    #[serde(default)]
+    pub events: HashSet<WebhookEvent>,
+    /// additional HTTP headers (like authorization)
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+#[cfg(feature = "webhooks")]
+impl WebhookConfig {
+    pub fn accepts(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+#[cfg(feature = "webhooks")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct WebhooksConfig {
+    pub hooks: Vec<WebhookConfig>,
+    /// how many times is failed delivery retried
+    pub max_retries: u32,
+    /// delay before first retry (in seconds), it doubles with each next retry
+    pub retry_delay: u32,
+    /// timeout of webhook request (in seconds)
+    pub timeout: u32,
+}
+
+#[cfg(feature = "webhooks")]
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            hooks: vec![],
+            max_retries: 5,
+            retry_delay: 10,
+            timeout: 10,
+        }
+    }
+}
+
+#[cfg(feature = "webhooks")]
+impl WebhooksConfig {
+    pub fn check(&self) -> Result<()> {
+        for hook in &self.hooks {
+            match url::Url::parse(&hook.url) {
+                Ok(url) if is_supported_url(&url) => (),
+                Ok(_) => return value_error!("webhooks", "Unsupported URL {}", hook.url),
+                Err(e) => return value_error!("webhooks", "Invalid URL {}: {}", hook.url, e),
+            }
+            for (name, value) in &hook.headers {
+                if name.parse::<myhy::header::HeaderName>().is_err()
+                    || value.parse::<myhy::header::HeaderValue>().is_err()
+                {
+                    return value_error!("webhooks", "Invalid header {}", name);
+                }
+            }
+        }
+        if self.retry_delay == 0 || self.timeout == 0 {
+            return value_error!("webhooks", "Retry delay and timeout must be at least 1s");
+        }
+        if self.max_retries > 20 {
+            return value_error!("webhooks", "Too many retries (max is 20)");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "scrobbler")]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct ScrobblerConfig {
+    /// ListenBrainz user tokens for positions groups, only groups listed here are scrobbled
+    pub tokens: HashMap<String, String>,
+    /// base URL of ListenBrainz compatible API
+    pub api_url: String,
+    /// file is considered played, when it was played for this fraction of its duration ...
+    pub min_play_fraction: f32,
+    /// ... or for this time (in seconds), whichever is shorter
+    pub min_play_time: u32,
+    /// listens waiting for submission are stored here, so they survive restart
+    pub queue_file: PathBuf,
+    /// maximum number of waiting listens, oldest are dropped
+    pub max_queue_size: usize,
+    /// timeout of submit request (in seconds)
+    pub timeout: u32,
+}
+
+#[cfg(feature = "scrobbler")]
+impl Default for ScrobblerConfig {
+    fn default() -> Self {
+        Self {
+            tokens: HashMap::new(),
+            api_url: "https://api.listenbrainz.org".into(),
+            min_play_fraction: 0.5,
+            min_play_time: 240,
+            queue_file: base_data_dir().join("scrobbler-queue.json"),
+            max_queue_size: 1000,
+            timeout: 10,
+        }
+    }
+}
+
+#[cfg(feature = "scrobbler")]
+impl ScrobblerConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        match url::Url::parse(&self.api_url) {
+            Ok(url) if is_supported_url(&url) => (),
+            Ok(_) => return value_error!("scrobbler.api_url", "Unsupported URL {}", self.api_url),
+            Err(e) => {
+                return value_error!("scrobbler.api_url", "Invalid URL {}: {}", self.api_url, e)
+            }
+        }
+        if let Some((group, _)) = self
+            .tokens
+            .iter()
+            .find(|(_, token)| token.parse::<myhy::header::HeaderValue>().is_err())
+        {
+            return value_error!("scrobbler.tokens", "Invalid token for group {}", group);
+        }
+        if !(self.min_play_fraction > 0.0 && self.min_play_fraction <= 1.0) {
+            return value_error!(
+                "scrobbler.min_play_fraction",
+                "Fraction must be in interval (0, 1]"
+            );
+        }
+        if self.min_play_time == 0 || self.timeout == 0 {
+            return value_error!("scrobbler", "Min play time and timeout must be at least 1s");
+        }
+        if !util::parent_dir_exists(&self.queue_file) {
+            return value_error!(
+                "scrobbler.queue_file",
+                "Parent directory does not exists for {:?}",
+                self.queue_file
+            );
+        }
         Ok(())
     }
 }
@@ -490,6 +1507,99 @@ pub struct CorsConfig {
     pub allow: Cors,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl std::fmt::Display for CookieSameSite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// Glob patterns of folders (relative to collection root), which account can (allow) or cannot (deny) see,
+/// if allow is empty all folders, which are not denied, are visible
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct FolderFilterConfig {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl FolderFilterConfig {
+    pub fn check(&self) -> Result<()> {
+        for pattern in self.allow.iter().chain(self.deny.iter()) {
+            if pattern.trim_matches('/').is_empty() {
+                return value_error!("folder_filters", "Empty pattern");
+            }
+            // patterns are matched by path components, so each one must be valid glob
+            for segment in pattern.split('/').filter(|s| !s.is_empty()) {
+                if let Err(e) = glob::Pattern::new(segment) {
+                    return value_error!("folder_filters", "Invalid pattern {}: {}", pattern, e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Attributes of authentication cookie, if SameSite or Secure are not set, they are
+/// determined for each request - if it's https (directly or via proxy) cookie is Secure
+/// and for cross origin requests (with CORS enabled) SameSite is None
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct CookieConfig {
+    pub name: String,
+    pub same_site: Option<CookieSameSite>,
+    pub secure: Option<bool>,
+    pub domain: Option<String>,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        CookieConfig {
+            name: "audioserve_token".into(),
+            same_site: None,
+            secure: None,
+            domain: None,
+        }
+    }
+}
+
+impl CookieConfig {
+    pub fn check(&self) -> Result<()> {
+        if self.name.is_empty()
+            || !self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return value_error!(
+                "cookie.name",
+                "Cookie name can contain only ASCII letters, digits, - and _"
+            );
+        }
+        if self.same_site == Some(CookieSameSite::None) && self.secure == Some(false) {
+            return value_error!("cookie.secure", "Cookie with SameSite=None must be secure");
+        }
+        if let Some(domain) = &self.domain {
+            if domain.is_empty()
+                || !domain
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+            {
+                return value_error!("cookie.domain", "Invalid domain {}", domain);
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct CollapseCDFolderConfig {
@@ -522,13 +1632,38 @@ pub struct Config {
     pub thread_pool: ThreadPoolConfig,
     pub base_dirs: Vec<PathBuf>,
     pub base_dirs_options: HashMap<PathBuf, String>,
+    /// indexes of collections marked with public option, available for guests
+    #[serde(skip)]
+    pub public_collections: HashSet<usize>,
+    /// additional root directories of collections (from roots option), collection index -> roots
+    #[serde(skip)]
+    pub collections_roots: HashMap<usize, Vec<PathBuf>>,
+    /// each subdirectory of this directory is collection, new subdirectories are added while running
+    pub auto_collections: Option<PathBuf>,
     pub url_path_prefix: Option<String>,
     pub shared_secret: Option<String>,
+    /// secondary secrets for accounts with restricted access, account name -> secret
+    pub accounts: HashMap<String, String>,
+    /// accounts allowed to access collection, collections not listed are accessible by all accounts,
+    /// main shared secret can always access all collections
+    pub collections_access: HashMap<PathBuf, Vec<String>>,
+    /// folders visible for account, account name -> allow/deny patterns
+    pub folder_filters: HashMap<String, FolderFilterConfig>,
     pub limit_rate: Option<f32>,
+    /// maximum of concurrent audio streams from one client address
+    pub max_streams_per_client: Option<u32>,
+    /// bandwidth limit (in kbps) for each audio stream or folder download
+    pub limit_stream_kbps: Option<u32>,
+    pub limits: LimitsConfig,
+    pub login_throttle: LoginThrottleConfig,
+    pub request_body: RequestBodyConfig,
     #[serde(with = "serde_yaml::with::singleton_map_recursive")]
     // to keep backward compatibility with existing configs
     pub transcoding: TranscodingConfig,
     pub token_validity_hours: u32,
+    /// for how long (in secs) old tokens are still valid after revocation of all tokens
+    pub token_revoke_grace: u32,
+    pub cookie: CookieConfig,
     pub secret_file: PathBuf,
     pub client_dir: PathBuf,
     pub cors: Option<CorsConfig>,
@@ -536,12 +1671,27 @@ pub struct Config {
     pub allow_symlinks: bool,
     pub search_cache: bool,
     pub disable_folder_download: bool,
+    pub recursive_download: RecursiveDownloadConfig,
     pub chapters: ChaptersSize,
     pub no_dir_collaps: bool,
     pub ignore_chapters_meta: bool,
     #[cfg(feature = "shared-positions")]
     pub positions: PositionsConfig,
+    #[cfg(feature = "sync-sessions")]
+    pub sync_sessions: SyncSessionsConfig,
+    #[cfg(feature = "webhooks")]
+    pub webhooks: WebhooksConfig,
+    #[cfg(feature = "scrobbler")]
+    pub scrobbler: ScrobblerConfig,
     pub behind_proxy: bool,
+    /// proxies, from which forwarded headers are accepted, if empty all are trusted
+    #[serde(deserialize_with = "deserialize_ip_nets")]
+    pub trusted_proxies: Vec<IpNet>,
+    /// if not empty only clients from these networks can access server
+    #[serde(deserialize_with = "deserialize_ip_nets")]
+    pub allowed_clients: Vec<IpNet>,
+    #[serde(deserialize_with = "deserialize_ip_nets")]
+    pub denied_clients: Vec<IpNet>,
     pub collections_cache_dir: PathBuf,
     pub tags: HashSet<String>,
     pub force_cache_update_on_init: bool,
@@ -552,10 +1702,23 @@ pub struct Config {
     #[cfg(feature = "tags-encoding")]
     pub tags_encoding: Option<String>,
     pub icons: IconsConfig,
+    pub covers: CoversConfig,
     pub time_to_folder_end: u32,
     pub read_playlist: bool,
     pub collections_options: CollectionConfig,
     pub compress_responses: bool,
+    pub access_log: Option<AccessLogConfig>,
+    /// Subsonic compatible API under /rest/ path
+    pub subsonic_api: bool,
+    /// DLNA/UPnP media server for devices in local network
+    pub dlna: Option<DlnaConfig>,
+    pub discovery: DiscoveryConfig,
+    /// watch folder import into collection
+    pub import: Option<ImportConfig>,
+    pub cache_maintenance: CacheMaintenanceConfig,
+    /// subcommand to run, it's given only on command line
+    #[serde(skip)]
+    pub command: RunCommand,
 }
 
 impl Config {
@@ -604,9 +1767,77 @@ impl Config {
             ),
         }
     }
+    /// Takes values, which can be changed without restart, from new config
+    fn update_reloadable(&mut self, new_config: Config) {
+        self.transcoding.update_profiles(new_config.transcoding);
+        self.limit_rate = new_config.limit_rate;
+        self.max_streams_per_client = new_config.max_streams_per_client;
+        self.limit_stream_kbps = new_config.limit_stream_kbps;
+        self.limits = new_config.limits;
+        self.login_throttle = new_config.login_throttle;
+        self.request_body = new_config.request_body;
+        self.static_resource_cache_age = new_config.static_resource_cache_age;
+        self.folder_file_cache_age = new_config.folder_file_cache_age;
+        self.cors = new_config.cors;
+        self.trusted_proxies = new_config.trusted_proxies;
+        self.allowed_clients = new_config.allowed_clients;
+        self.denied_clients = new_config.denied_clients;
+    }
+
+    fn prepare_collections(&mut self) -> Result<()> {
+        self.public_collections.clear();
+        self.collections_roots.clear();
+        for (idx, dir) in self.base_dirs.iter().enumerate() {
+            if let Some(options) = self.base_dirs_options.get(dir) {
+                let mut col_options = CollectionOptions::default();
+                if let Err(e) = col_options.update_from_str_options(options) {
+                    return value_error!("base_dirs_options", "{}", e);
+                }
+                if col_options.public {
+                    self.public_collections.insert(idx);
+                }
+                if !col_options.roots.is_empty() {
+                    self.collections_roots.insert(idx, col_options.roots);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Subdirectories of auto collections directory, which are not yet collections, sorted by path
+    pub fn new_auto_collections(&self) -> Vec<PathBuf> {
+        let dir = match self.auto_collections {
+            Some(ref d) => d,
+            None => return vec![],
+        };
+        let mut dirs: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+                .map(|e| e.path())
+                .filter(|p| p.is_dir() && !self.base_dirs.contains(p))
+                .collect(),
+            Err(e) => {
+                error!("Cannot read auto collections directory {:?}: {}", dir, e);
+                vec![]
+            }
+        };
+        dirs.sort();
+        dirs
+    }
+
     /// Any runtime optimalizations, compilatipons of config
     pub fn prepare(&mut self) -> Result<()> {
         self.transcoding.prepare()?;
+        // cover names are compared with lowercased file names
+        self.collections_options.cover_names = self
+            .collections_options
+            .cover_names
+            .iter()
+            .map(|n| n.trim().to_lowercase())
+            .filter(|n| !n.is_empty())
+            .collect();
+        self.prepare_collections()?;
 
         if let Some(ref mut cors) = self.cors {
             if let Some(ref re) = cors.regex {
@@ -633,6 +1864,57 @@ impl Config {
             return value_error!("shared_secret", "Shared secret must be at least 3 bytes");
         }
 
+        if !self.accounts.is_empty() && self.shared_secret.is_none() {
+            return value_error!("accounts", "Accounts require shared secret");
+        }
+
+        for (name, secret) in &self.accounts {
+            if name.is_empty() || name.contains(['|', '/']) {
+                return value_error!("accounts", "Invalid account name {:?}", name);
+            }
+            if secret.len() < 3 {
+                return value_error!(
+                    "accounts",
+                    "Secret for account {} must be at least 3 bytes",
+                    name
+                );
+            }
+            if Some(secret) == self.shared_secret.as_ref() {
+                return value_error!(
+                    "accounts",
+                    "Secret for account {} must differ from shared secret",
+                    name
+                );
+            }
+        }
+
+        for (dir, accounts) in &self.collections_access {
+            if !self.base_dirs.contains(dir) {
+                return value_error!(
+                    "collections_access",
+                    "{:?} is not collection directory",
+                    dir
+                );
+            }
+            if let Some(account) = accounts.iter().find(|a| !self.accounts.contains_key(*a)) {
+                return value_error!("collections_access", "Unknown account {}", account);
+            }
+        }
+
+        for (account, filter) in &self.folder_filters {
+            if !self.accounts.contains_key(account) {
+                return value_error!("folder_filters", "Unknown account {}", account);
+            }
+            filter.check()?;
+        }
+
+        if !self.trusted_proxies.is_empty() && !self.behind_proxy {
+            return value_error!(
+                "trusted_proxies",
+                "Trusted proxies can be used only when behind proxy"
+            );
+        }
+
         if self.token_validity_hours < 240 {
             return value_error!(
                 "token-validity-days",
@@ -660,23 +1942,81 @@ impl Config {
             self.ssl.as_ref().unwrap().check()?
         }
 
+        if let Some(ref access_log) = self.access_log {
+            access_log.check()?
+        }
+
+        if let Some(ref dlna) = self.dlna {
+            dlna.check()?;
+            if let Some(account) = dlna.account.as_ref() {
+                if !self.accounts.contains_key(account) {
+                    return value_error!("dlna-account", "Unknown account {}", account);
+                }
+            }
+        }
+
+        if let Some(ref import) = self.import {
+            import.check(&self.base_dirs)?;
+        }
+
+        self.cache_maintenance.check()?;
+
+        if self.max_streams_per_client == Some(0) {
+            return value_error!("max_streams_per_client", "Must be at least 1");
+        }
+        if self.limit_stream_kbps.map(|l| l < 8).unwrap_or(false) {
+            return value_error!("limit_stream_kbps", "Must be at least 8 kbps");
+        }
+
         self.transcoding.check()?;
         self.icons.check()?;
+        self.covers.check()?;
         self.thread_pool.check()?;
         self.chapters.check()?;
+        self.recursive_download.check()?;
+        self.login_throttle.check()?;
+        self.request_body.check()?;
+        self.limits.check()?;
+        self.cookie.check()?;
         #[cfg(feature = "shared-positions")]
         self.positions.check()?;
+        #[cfg(feature = "shared-positions")]
+        if matches!(
+            self.command,
+            RunCommand::PositionsBackup | RunCommand::PositionsRestore
+        ) && self.positions.backup_file.is_none()
+        {
+            return value_error!(
+                "positions-backup-file",
+                "Backup file is required for positions backup or restore"
+            );
+        }
+        #[cfg(feature = "sync-sessions")]
+        self.sync_sessions.check()?;
+        #[cfg(feature = "webhooks")]
+        self.webhooks.check()?;
+        #[cfg(feature = "scrobbler")]
+        self.scrobbler.check()?;
         self.collections_options.check()?;
+        self.discovery.check()?;
 
-        if self.base_dirs.is_empty() {
+        if let Some(ref dir) = self.auto_collections {
+            if !dir.is_dir() {
+                return value_error!("auto_collections", "{:?} is not directory", dir);
+            }
+        } else if self.base_dirs.is_empty() {
             return value_error!(
                 "base_dirs",
                 "At least one directory with audio files must be provided"
             );
         }
 
-        if self.base_dirs.len() > 100 {
-            return value_error!("base_dirs", "Too many collections directories (max is 100)");
+        if self.base_dirs.len() > MAX_COLLECTIONS {
+            return value_error!(
+                "base_dirs",
+                "Too many collections directories (max is {})",
+                MAX_COLLECTIONS
+            );
         }
 
         for d in &self.base_dirs {
@@ -701,6 +2041,49 @@ impl Config {
         Ok(())
     }
 
+    /// Root directory of collection, which contains given path (collection can merge several directories),
+    /// collection directory is returned if path does not exist
+    pub fn base_dir_for(&self, collection: usize, rel_path: impl AsRef<Path>) -> &Path {
+        let base_dir = self.base_dirs[collection].as_path();
+        match self.collections_roots.get(&collection) {
+            Some(roots) => collection::resolve_root(
+                std::iter::once(base_dir).chain(roots.iter().map(PathBuf::as_path)),
+                rel_path.as_ref(),
+            )
+            .unwrap_or(base_dir),
+            None => base_dir,
+        }
+    }
+
+    /// If collection can be accessed by account, None is for main shared secret (or no authentication)
+    /// and GUEST_ACCOUNT for not authenticated access to public collections
+    pub fn is_collection_allowed(&self, collection: usize, account: Option<&str>) -> bool {
+        match (account, self.base_dirs.get(collection)) {
+            (None, _) => true,
+            (Some(GUEST_ACCOUNT), _) => self.public_collections.contains(&collection),
+            (Some(_), None) => false,
+            (Some(account), Some(dir)) => self
+                .collections_access
+                .get(dir)
+                .map(|allowed| allowed.iter().any(|a| a == account))
+                .unwrap_or(true),
+        }
+    }
+
+    /// If forwarded headers can be used for request coming directly from this address
+    pub fn is_trusted_proxy(&self, addr: IpAddr) -> bool {
+        self.behind_proxy
+            && (self.trusted_proxies.is_empty()
+                || self.trusted_proxies.iter().any(|n| n.contains(&addr)))
+    }
+
+    /// If client address is allowed to access server - deny list has precedence over allow list
+    pub fn is_client_allowed(&self, addr: IpAddr) -> bool {
+        !self.denied_clients.iter().any(|n| n.contains(&addr))
+            && (self.allowed_clients.is_empty()
+                || self.allowed_clients.iter().any(|n| n.contains(&addr)))
+    }
+
     pub fn get_tags(&self) -> Option<HashSet<String>> {
         if self.tags.is_empty() {
             None
@@ -716,13 +2099,26 @@ impl Default for Config {
         Config {
             base_dirs: vec![],
             base_dirs_options: HashMap::new(),
+            public_collections: HashSet::new(),
+            collections_roots: HashMap::new(),
+            auto_collections: None,
             url_path_prefix: None,
             listen: ([0, 0, 0, 0], 3000u16).into(),
             thread_pool: ThreadPoolConfig::default(),
             shared_secret: None,
+            accounts: HashMap::new(),
+            collections_access: HashMap::new(),
+            folder_filters: HashMap::new(),
             limit_rate: None,
+            max_streams_per_client: None,
+            limit_stream_kbps: None,
+            limits: LimitsConfig::default(),
+            login_throttle: LoginThrottleConfig::default(),
+            request_body: RequestBodyConfig::default(),
             transcoding: TranscodingConfig::default(),
             token_validity_hours: 365 * 24,
+            token_revoke_grace: 60,
+            cookie: CookieConfig::default(),
             #[cfg(test)]
             client_dir: "test_data".into(),
             #[cfg(not(test))]
@@ -733,35 +2129,67 @@ impl Default for Config {
             allow_symlinks: false,
             search_cache: false,
             disable_folder_download: false,
+            recursive_download: RecursiveDownloadConfig::default(),
             chapters: ChaptersSize::default(),
             no_dir_collaps: false,
             ignore_chapters_meta: false,
             behind_proxy: false,
+            trusted_proxies: vec![],
+            allowed_clients: vec![],
+            denied_clients: vec![],
             collections_cache_dir: data_base_dir.join("col_db"),
             tags: HashSet::new(),
             force_cache_update_on_init: false,
             natural_files_ordering: true,
             #[cfg(feature = "shared-positions")]
             positions: Default::default(),
+            #[cfg(feature = "sync-sessions")]
+            sync_sessions: Default::default(),
+            #[cfg(feature = "webhooks")]
+            webhooks: Default::default(),
+            #[cfg(feature = "scrobbler")]
+            scrobbler: Default::default(),
             static_resource_cache_age: None,
             folder_file_cache_age: Some(24 * 3600),
             collapse_cd_folders: None,
             #[cfg(feature = "tags-encoding")]
             tags_encoding: None,
             icons: IconsConfig::default(),
+            covers: CoversConfig::default(),
             time_to_folder_end: 10,
             read_playlist: false,
             collections_options: CollectionConfig::default(),
             compress_responses: false,
+            access_log: None,
+            subsonic_api: false,
+            dlna: None,
+            discovery: DiscoveryConfig::default(),
+            import: None,
+            cache_maintenance: CacheMaintenanceConfig::default(),
+            command: RunCommand::default(),
         }
     }
 }
 
+/// Parses network in CIDR notation or single IP address
+pub fn parse_ip_net(s: &str) -> std::result::Result<IpNet, ipnet::AddrParseError> {
+    s.parse::<IpNet>()
+        .or_else(|e| s.parse::<IpAddr>().map(IpNet::from).map_err(|_| e))
+}
+
+fn deserialize_ip_nets<'de, D>(deserializer: D) -> std::result::Result<Vec<IpNet>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| parse_ip_net(s).map_err(serde::de::Error::custom))
+        .collect()
+}
+
 pub fn init_config() -> Result<()> {
     unsafe {
-        // It's safe as CONFIG is initialized only once in main thread
-        #[allow(static_mut_refs)]
-        if CONFIG.is_some() {
+        if CONFIG.load().is_some() {
             panic!("Config is already initialied")
         }
 
@@ -769,19 +2197,47 @@ pub fn init_config() -> Result<()> {
     }
 
     let config = cli::parse_args()?;
+    set_config(config);
 
-    unsafe {
-        CONFIG = Some(config);
-    }
+    Ok(())
+}
 
+/// Reloads config from arguments and config file, but only transcoding profiles, rate limit,
+/// login throttling, cache ages, CORS and clients access control are applied, other changes require restart
+pub fn reload_config() -> Result<()> {
+    let new_config = cli::parse_args()?;
+    let _guard = CONFIG_UPDATE.lock().unwrap();
+    let mut config = get_config().clone();
+    config.update_reloadable(new_config);
+    set_config(config);
     Ok(())
 }
 
+/// Registers new collection directory in running config, collection itself is added by `add_collection`,
+/// which gets index reserved for new collection - both are done under config update lock,
+/// so config is changed only if collection is added
+pub fn add_collection_dir<E>(
+    dir: PathBuf,
+    add_collection: impl FnOnce(usize) -> std::result::Result<(), E>,
+) -> std::result::Result<usize, E>
+where
+    E: From<Error>,
+{
+    let _guard = CONFIG_UPDATE.lock().unwrap();
+    let mut config = get_config().clone();
+    let index = config.base_dirs.len();
+    config.base_dirs.push(dir);
+    config.prepare_collections()?;
+    add_collection(index)?;
+    set_config(config);
+    Ok(index)
+}
+
 #[cfg(test)]
 pub mod init {
     /// Static config initialization for tests
     /// as tests are run concurrently it requires also some synchronication
-    use super::{Config, BASE_DATA_DIR, CONFIG};
+    use super::{set_config, Config, BASE_DATA_DIR};
     use std::path::PathBuf;
     use std::sync::Once;
     static INIT: Once = Once::new();
@@ -792,10 +2248,7 @@ pub mod init {
             unsafe {
                 BASE_DATA_DIR = Some(base_dir);
             }
-            let config = Config::default();
-            unsafe {
-                CONFIG = Some(config);
-            }
+            set_config(Config::default());
         });
     }
 }
@@ -834,4 +2287,408 @@ mod tests {
         let c3 = load_file("./test_data/transcodings.2.yaml");
         assert_eq!(c3.transcoding.get(QualityLevel::High).bitrate(), 96);
     }
+
+    #[test]
+    fn test_transcoding_profiles() {
+        init_default_config();
+        let yaml = r#"
+profiles:
+  car:
+    low:
+      mp3:
+        bitrate: 32
+    medium:
+      mp3:
+        bitrate: 64
+    high:
+      mp3:
+        bitrate: 128
+"#;
+        let mut t: TranscodingConfig = serde_yaml::from_str(yaml).unwrap();
+        t.check().unwrap();
+        t.prepare().unwrap();
+        let car = t.profile("car").unwrap();
+        assert_eq!("@car", car.tag);
+        assert_eq!(64, car.get(QualityLevel::Medium).bitrate());
+        assert!(t.profile("home").is_none());
+
+        let t: TranscodingConfig = serde_yaml::from_str(&yaml.replace("car:", "car/1:")).unwrap();
+        assert!(t.check().is_err());
+    }
+
+    #[test]
+    fn test_bandwidth_hints() {
+        let mut t = TranscodingConfig::default();
+        t.bandwidth_hints.insert("metered".into(), "m".into());
+        t.check().unwrap();
+        assert_eq!(
+            QualityLevel::Low,
+            t.level_for_bandwidth(QualityLevel::High, "cellular")
+        );
+        assert_eq!(
+            QualityLevel::Medium,
+            t.level_for_bandwidth(QualityLevel::High, "metered")
+        );
+        assert_eq!(
+            QualityLevel::Low,
+            t.level_for_bandwidth(QualityLevel::Low, "metered")
+        );
+        assert_eq!(
+            QualityLevel::High,
+            t.level_for_bandwidth(QualityLevel::High, "wifi")
+        );
+        t.bandwidth_hints.insert("wifi".into(), "x".into());
+        assert!(t.check().is_err());
+    }
+
+    #[test]
+    fn test_cast_transcoding() {
+        let yaml = r#"
+cast:
+  mp3:
+    bitrate: 96
+    compression_level: 2
+"#;
+        let t: TranscodingConfig = serde_yaml::from_str(yaml).unwrap();
+        t.check().unwrap();
+        assert_eq!(96, t.cast.bitrate());
+
+        let t: TranscodingConfig =
+            serde_yaml::from_str(&format!("{}    abr: true\n", yaml)).unwrap();
+        assert!(t.check().is_err());
+
+        let t: TranscodingConfig = serde_yaml::from_str(
+            r#"
+cast:
+  opus-in-ogg:
+    bitrate: 48
+    compression_level: 10
+    cutoff: FullBand
+"#,
+        )
+        .unwrap();
+        assert!(t.check().is_err());
+    }
+
+    #[test]
+    fn test_update_reloadable() {
+        init_default_config();
+        let mut config = Config::default();
+        let mut new_config = Config {
+            limit_rate: Some(10.0),
+            folder_file_cache_age: Some(60),
+            listen: "127.0.0.1:4000".parse().unwrap(),
+            ..Default::default()
+        };
+        new_config.transcoding = serde_yaml::from_str(
+            r#"
+profiles:
+  car:
+    low:
+      mp3:
+        bitrate: 32
+    medium:
+      mp3:
+        bitrate: 64
+    high:
+      mp3:
+        bitrate: 128
+"#,
+        )
+        .unwrap();
+        new_config.transcoding.prepare().unwrap();
+        new_config.transcoding.max_parallel_processes = 100;
+        config.update_reloadable(new_config);
+        assert_eq!(Some(10.0), config.limit_rate);
+        assert_eq!(Some(60), config.folder_file_cache_age);
+        assert!(config.transcoding.profile("car").is_some());
+        // not reloadable
+        assert_eq!(Config::default().listen, config.listen);
+        assert_eq!(
+            Config::default().transcoding.max_parallel_processes,
+            config.transcoding.max_parallel_processes
+        );
+    }
+
+    #[test]
+    fn test_collections_access() {
+        init_default_config();
+        let mut config = Config {
+            base_dirs: vec!["test_data".into(), "src".into()],
+            shared_secret: Some("main secret".into()),
+            accounts: [("kids".to_string(), "kids secret".to_string())]
+                .into_iter()
+                .collect(),
+            collections_access: [(PathBuf::from("src"), vec![])].into_iter().collect(),
+            ..Default::default()
+        };
+        config.check().unwrap();
+        assert!(config.is_collection_allowed(1, None));
+        assert!(config.is_collection_allowed(0, Some("kids")));
+        assert!(!config.is_collection_allowed(1, Some("kids")));
+        assert!(!config.is_collection_allowed(2, Some("kids")));
+
+        config
+            .collections_access
+            .insert("test_data".into(), vec!["other".into()]);
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn test_folder_filters() {
+        init_default_config();
+        let mut config: Config = serde_yaml::from_str(
+            "base_dirs: [test_data]
+shared_secret: main secret
+accounts: {kids: kids secret}
+folder_filters:
+  kids:
+    allow: [Children]
+    deny: [\"**/Scary*\"]",
+        )
+        .unwrap();
+        config.check().unwrap();
+        assert_eq!(vec!["Children"], config.folder_filters["kids"].allow);
+
+        config.folder_filters.get_mut("kids").unwrap().deny = vec!["[abc".into()];
+        assert!(config.check().is_err());
+        config.folder_filters.get_mut("kids").unwrap().deny = vec!["[a/b]".into()];
+        assert!(config.check().is_err());
+        config.folder_filters.clear();
+        config
+            .folder_filters
+            .insert("other".into(), FolderFilterConfig::default());
+        assert!(config.check().is_err());
+    }
+
+    #[cfg(feature = "webhooks")]
+    #[test]
+    fn test_webhooks() {
+        init_default_config();
+        let mut config: Config = serde_yaml::from_str(
+            "base_dirs: [test_data]
+webhooks:
+  hooks:
+    - url: http://localhost:8123/api/webhook/audioserve
+      events: [book-finished]
+      headers: {Authorization: Bearer xyz}
+  max_retries: 3",
+        )
+        .unwrap();
+        config.check().unwrap();
+        let hook = &config.webhooks.hooks[0];
+        assert!(hook.accepts(WebhookEvent::BookFinished));
+        assert!(!hook.accepts(WebhookEvent::ScanFinished));
+        assert_eq!(3, config.webhooks.max_retries);
+        assert_eq!(10, config.webhooks.retry_delay);
+
+        config.webhooks.hooks[0].url = "ftp://localhost/hook".into();
+        assert!(config.check().is_err());
+    }
+
+    #[cfg(feature = "scrobbler")]
+    #[test]
+    fn test_scrobbler() {
+        init_default_config();
+        let mut config: Config = serde_yaml::from_str(
+            "base_dirs: [test_data]
+scrobbler:
+  tokens: {ivan: abcd-1234}
+  api_url: http://localhost:8100
+  min_play_time: 60",
+        )
+        .unwrap();
+        config.check().unwrap();
+        assert!(config.scrobbler.is_enabled());
+        assert_eq!(0.5, config.scrobbler.min_play_fraction);
+        assert_eq!(60, config.scrobbler.min_play_time);
+
+        config.scrobbler.min_play_fraction = 1.5;
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn test_auto_collections() {
+        init_default_config();
+        let tmp_dir = tempdir::TempDir::new("AS_AUTO_COLLECTIONS").unwrap();
+        let root = tmp_dir.path();
+        for d in ["novels", "classics", ".hidden", "kids"] {
+            fs::create_dir(root.join(d)).unwrap();
+        }
+        File::create(root.join("readme.txt")).unwrap();
+
+        let mut config = Config {
+            base_dirs: vec![root.join("kids")],
+            ..Default::default()
+        };
+        assert!(config.new_auto_collections().is_empty());
+        config.auto_collections = Some(root.into());
+        assert_eq!(
+            vec![root.join("classics"), root.join("novels")],
+            config.new_auto_collections()
+        );
+
+        config.base_dirs.clear();
+        config.check().unwrap();
+        config.auto_collections = Some(root.join("readme.txt"));
+        assert!(config.check().is_err());
+    }
+
+    #[test]
+    fn test_public_collections() {
+        let mut config = Config {
+            base_dirs: vec!["src".into(), "test_data".into()],
+            base_dirs_options: [(PathBuf::from("test_data"), "public,no-cache".into())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        config.prepare().unwrap();
+        assert!(!config.is_collection_allowed(0, Some(GUEST_ACCOUNT)));
+        assert!(config.is_collection_allowed(1, Some(GUEST_ACCOUNT)));
+        assert!(config.is_collection_allowed(0, None));
+
+        config
+            .base_dirs_options
+            .insert("src".into(), "public=maybe".into());
+        assert!(config.prepare().is_err());
+    }
+
+    #[test]
+    fn test_collections_roots() {
+        let mut config = Config {
+            base_dirs: vec!["src".into()],
+            base_dirs_options: [(PathBuf::from("src"), "roots=test_data".into())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        config.prepare().unwrap();
+        assert_eq!(Path::new("src"), config.base_dir_for(0, "main.rs"));
+        assert_eq!(
+            Path::new("test_data"),
+            config.base_dir_for(0, "01-file.mp3")
+        );
+        assert_eq!(
+            Path::new("test_data"),
+            config.base_dir_for(0, "01-file.mp3$$Chapter 1$$0-1000$$.mp3")
+        );
+        assert_eq!(Path::new("src"), config.base_dir_for(0, "missing.mp3"));
+
+        config
+            .base_dirs_options
+            .insert("src".into(), "roots=non-existent".into());
+        assert!(config.prepare().is_err());
+    }
+
+    #[test]
+    fn test_cover_names() {
+        let mut config: Config =
+            serde_yaml::from_str("collections_options:\n  cover_names: [Front, ' COVER', '']")
+                .unwrap();
+        config.prepare().unwrap();
+        assert_eq!(
+            vec!["front", "cover"],
+            config.collections_options.cover_names
+        );
+    }
+
+    #[test]
+    fn test_cookie_config() {
+        let cookie: CookieConfig =
+            serde_yaml::from_str("name: my_token\nsame_site: Strict\ndomain: example.com").unwrap();
+        assert_eq!(Some(CookieSameSite::Strict), cookie.same_site);
+        assert!(cookie.check().is_ok());
+        assert!(CookieConfig {
+            name: "bad;name".into(),
+            ..Default::default()
+        }
+        .check()
+        .is_err());
+        assert!(CookieConfig {
+            same_site: Some(CookieSameSite::None),
+            secure: Some(false),
+            ..Default::default()
+        }
+        .check()
+        .is_err());
+    }
+
+    #[test]
+    fn test_request_body_config() {
+        let body: RequestBodyConfig = serde_yaml::from_str("max_size: 65536").unwrap();
+        assert_eq!(65536, body.max_size);
+        assert_eq!(Duration::from_secs(30), body.read_timeout());
+        assert!(body.check().is_ok());
+        assert!(RequestBodyConfig {
+            max_size: 100,
+            ..Default::default()
+        }
+        .check()
+        .is_err());
+        assert!(RequestBodyConfig {
+            read_timeout: 0,
+            ..Default::default()
+        }
+        .check()
+        .is_err());
+    }
+
+    #[cfg(feature = "shared-positions")]
+    #[test]
+    fn test_positions_prune_after() {
+        let positions: PositionsConfig =
+            serde_yaml::from_str("prune_after: {\"*\": 12, kids: 3}").unwrap();
+        assert!(positions.check().is_ok());
+        let month = Duration::from_secs(30 * 24 * 3600);
+        assert_eq!(Some(month * 3), positions.max_age("kids"));
+        assert_eq!(Some(month * 12), positions.max_age("ivan"));
+        assert_eq!(None, PositionsConfig::default().max_age("ivan"));
+        let positions: PositionsConfig = serde_yaml::from_str("prune_after: {kids: 0}").unwrap();
+        assert!(positions.check().is_err());
+    }
+
+    #[test]
+    fn test_limits_config() {
+        let limits: LimitsConfig = serde_yaml::from_str(
+            "expensive:
+  rate: 0.5
+  burst: 3
+api:
+  rate: 20",
+        )
+        .unwrap();
+        assert!(limits.check().is_ok());
+        assert_eq!(Some(3), limits.expensive.as_ref().unwrap().burst);
+        assert_eq!(None, limits.api.as_ref().unwrap().burst);
+        let limits: LimitsConfig = serde_yaml::from_str("api: {rate: 10, burst: 0}").unwrap();
+        assert!(limits.check().is_err());
+        let limits: LimitsConfig = serde_yaml::from_str("expensive: {rate: 0}").unwrap();
+        assert!(limits.check().is_err());
+    }
+
+    #[test]
+    fn test_clients_access() {
+        let config: Config = serde_yaml::from_str(
+            "behind_proxy: true
+trusted_proxies: [10.0.0.1]
+allowed_clients: [192.168.1.0/24, 10.0.0.0/8]
+denied_clients: [192.168.1.13]",
+        )
+        .unwrap();
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(config.is_trusted_proxy(ip("10.0.0.1")));
+        assert!(!config.is_trusted_proxy(ip("10.0.0.2")));
+        assert!(config.is_client_allowed(ip("192.168.1.12")));
+        assert!(config.is_client_allowed(ip("10.1.2.3")));
+        assert!(!config.is_client_allowed(ip("192.168.1.13")));
+        assert!(!config.is_client_allowed(ip("192.168.2.1")));
+
+        let open = Config::default();
+        assert!(open.is_client_allowed(ip("::1")));
+        assert!(!open.is_trusted_proxy(ip("127.0.0.1")));
+
+        assert!(parse_ip_net("fe80::/10").is_ok());
+        assert!(parse_ip_net("10.0.0.300").is_err());
+    }
 }
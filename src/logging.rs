@@ -0,0 +1,95 @@
+//! Logging is done by env_logger, it's just wrapped to keep recent errors in memory,
+//! so they can be shown on admin status page.
+
+use log::{Level, Log, Metadata, Record};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const MAX_RECENT_ERRORS: usize = 50;
+
+lazy_static! {
+    static ref RECENT_ERRORS: Mutex<VecDeque<LoggedError>> =
+        Mutex::new(VecDeque::with_capacity(MAX_RECENT_ERRORS));
+}
+
+#[derive(Debug, Clone)]
+pub struct LoggedError {
+    pub time: SystemTime,
+    pub target: String,
+    pub message: String,
+}
+
+struct Logger {
+    inner: env_logger::Logger,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() == Level::Error && self.inner.matches(record) {
+            remember_error(LoggedError {
+                time: SystemTime::now(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            })
+        }
+        self.inner.log(record)
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+fn remember_error(error: LoggedError) {
+    let mut errors = RECENT_ERRORS.lock().unwrap();
+    if errors.len() >= MAX_RECENT_ERRORS {
+        errors.pop_front();
+    }
+    errors.push_back(error);
+}
+
+/// Recently logged errors, newest first
+pub fn recent_errors() -> Vec<LoggedError> {
+    RECENT_ERRORS
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .cloned()
+        .collect()
+}
+
+/// Initializes logger configured by RUST_LOG environment variable (same as `env_logger::init`)
+pub fn init() {
+    let inner = env_logger::Builder::from_default_env().build();
+    let max_level = inner.filter();
+    log::set_boxed_logger(Box::new(Logger { inner })).expect("Logger already initialized");
+    log::set_max_level(max_level);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_errors() {
+        for i in 0..MAX_RECENT_ERRORS + 5 {
+            remember_error(LoggedError {
+                time: SystemTime::now(),
+                target: "test".into(),
+                message: format!("error {}", i),
+            })
+        }
+        let errors = recent_errors();
+        assert_eq!(MAX_RECENT_ERRORS, errors.len());
+        assert_eq!(
+            format!("error {}", MAX_RECENT_ERRORS + 4),
+            errors[0].message
+        );
+    }
+}
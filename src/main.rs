@@ -6,29 +6,30 @@ extern crate lazy_static;
 // #[cfg(feature = "tls")]
 // use crate::tls::TlsStream;
 use collection::{CollectionOptions, CollectionOptionsMap, Collections};
-use config::{get_config, init_config};
+use config::{get_config, init_config, RunCommand};
 use error::{bail, Context, Error};
 use futures::prelude::*;
 use myhy::server::tls::TlsConfig;
 use ring::rand::{SecureRandom, SystemRandom};
 use services::{
-    auth::SharedSecretAuthenticator, search::Search, ServiceFactory, TranscodingDetails,
+    auth::SharedSecretAuthenticator, search::Search, Counter, ServiceFactory, TranscodingDetails,
 };
 use std::fs::File;
-use std::io::{self, Read, Write};
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::process;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::{oneshot, watch};
 
 use myhy::server::HttpServer;
 
 mod config;
 mod error;
+mod logging;
 mod services;
 // #[cfg(feature = "tls")]
 // mod tls;
@@ -51,23 +52,7 @@ fn generate_server_secret<P: AsRef<Path>>(file: P) -> Result<Vec<u8>, Error> {
         let rng = SystemRandom::new();
         rng.fill(&mut random)
             .map_err(|_e| io::Error::new(io::ErrorKind::Other, "Error when generating secret"))?;
-        let mut f;
-        #[cfg(unix)]
-        {
-            use std::fs::OpenOptions;
-            use std::os::unix::fs::OpenOptionsExt;
-            f = OpenOptions::new()
-                .mode(0o600)
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(file)?
-        }
-        #[cfg(not(unix))]
-        {
-            f = File::create(file)?
-        }
-        f.write_all(&random)?;
+        util::write_secret_file(file, &random)?;
         Ok(random.to_vec())
     }
 }
@@ -89,6 +74,7 @@ fn create_collections_options() -> anyhow::Result<CollectionOptionsMap> {
     fo.allow_symlinks = c.allow_symlinks;
     fo.chapters_duration = c.chapters.duration;
     fo.chapters_from_duration = c.chapters.from_duration;
+    fo.chapters_from_silence = c.chapters.from_silence;
     fo.ignore_chapters_meta = c.ignore_chapters_meta;
     fo.no_dir_collaps = c.no_dir_collaps;
     fo.tags = c.get_tags();
@@ -99,6 +85,18 @@ fn create_collections_options() -> anyhow::Result<CollectionOptionsMap> {
     fo.read_playlists = c.read_playlist;
     fo.changes_debounce_interval = c.collections_options.changes_debounce_interval;
     fo.watch_for_changes = !c.collections_options.dont_watch_for_changes;
+    // backup only reads positions, so collections do not need to be scanned
+    #[cfg(feature = "shared-positions")]
+    {
+        fo.read_only = c.command == RunCommand::PositionsBackup;
+    }
+    fo.poll_interval = c.collections_options.changes_poll_interval;
+    fo.cover_sources
+        .clone_from(&c.collections_options.cover_sources);
+    fo.cover_names
+        .clone_from(&c.collections_options.cover_names);
+    fo.scan_threads = c.collections_options.scan_threads;
+    fo.scan_io_delay_ms = c.collections_options.scan_io_delay_ms;
 
     #[cfg(feature = "tags-encoding")]
     {
@@ -128,6 +126,54 @@ fn create_collections() -> anyhow::Result<Arc<Collections>> {
     ))
 }
 
+/// How often auto collections directory is checked for new subdirectories
+const AUTO_COLLECTIONS_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+fn add_auto_collection(collections: &Collections, dir: PathBuf) -> anyhow::Result<usize> {
+    config::add_collection_dir(dir.clone(), |idx| {
+        // collections are added only here, so they must be in sync with config
+        if collections.count() != idx {
+            bail!(
+                "Collections count {} does not match config {}",
+                collections.count(),
+                idx
+            );
+        }
+        let opt = create_collections_options()?.get_col_options(&dir);
+        collections.add_collection(dir, opt)?;
+        Ok(())
+    })
+}
+
+/// Adds new subdirectories of auto collections directory as collections
+async fn watch_auto_collections(collections: Arc<Collections>) {
+    let mut interval = tokio::time::interval(AUTO_COLLECTIONS_CHECK_INTERVAL);
+    let mut limit_reported = false;
+    loop {
+        interval.tick().await;
+        for dir in get_config().new_auto_collections() {
+            if collections.count() >= config::MAX_COLLECTIONS {
+                if !limit_reported {
+                    warn!(
+                        "Cannot add collection {:?}, maximum of {} collections reached",
+                        dir,
+                        config::MAX_COLLECTIONS
+                    );
+                    limit_reported = true;
+                }
+                break;
+            }
+            let c = collections.clone();
+            let d = dir.clone();
+            match tokio::task::spawn_blocking(move || add_auto_collection(&c, d)).await {
+                Ok(Ok(idx)) => info!("Added collection {} from directory {:?}", idx, dir),
+                Ok(Err(e)) => error!("Cannot add collection {:?}: {}", dir, e),
+                Err(e) => error!("Panic when adding collection {:?}: {}", dir, e),
+            }
+        }
+    }
+}
+
 #[cfg(feature = "shared-positions")]
 fn restore_positions<P: AsRef<Path>>(backup_file: collection::BackupFile<P>) -> anyhow::Result<()> {
     let opt = create_collections_options()?;
@@ -140,6 +186,95 @@ fn restore_positions<P: AsRef<Path>>(backup_file: collection::BackupFile<P>) ->
     .map_err(Error::new)
 }
 
+/// Config with secret hidden, so it can be logged or printed
+fn masked_config() -> config::Config {
+    let mut cfg = get_config().clone();
+    cfg.shared_secret = cfg.shared_secret.map(|_| "******".to_string()); // Do not want to write secret to log!
+    cfg
+}
+
+fn scan_collections() -> anyhow::Result<()> {
+    let start = Instant::now();
+    let collections = create_collections()?;
+    collections.wait_for_initial_scan();
+    for (status, dir) in collections
+        .scan_status()
+        .iter()
+        .zip(get_config().base_dirs.iter())
+    {
+        info!(
+            "Collection {:?} scanned {} folders",
+            dir, status.folders_scanned
+        );
+    }
+    drop(collections);
+    let msg = format!(
+        "Scan of collections finished in {:.1}s",
+        start.elapsed().as_secs_f32()
+    );
+    info!("{}", msg);
+    println!("{}", msg);
+    Ok(())
+}
+
+#[cfg(feature = "shared-positions")]
+fn backup_positions() -> anyhow::Result<()> {
+    let cfg = &get_config().positions;
+    let backup_file = cfg
+        .backup_file
+        .clone()
+        .expect("Missing backup file argument");
+    let collections = create_collections()?;
+    collections
+        .backup_positions(
+            backup_file.clone(),
+            cfg.backup_keep.count,
+            cfg.backup_keep.compress,
+        )
+        .context("Error while backing up positions")?;
+    let msg = format!("Positions backed up to {:?}", backup_file);
+    info!("{}", msg);
+    println!("{}", msg);
+    Ok(())
+}
+
+#[cfg(feature = "shared-positions")]
+fn run_positions_restore() -> anyhow::Result<()> {
+    let backup_file = get_config()
+        .positions
+        .backup_file
+        .clone()
+        .expect("Missing backup file argument");
+
+    use collection::BackupFile;
+    use config::PositionsBackupFormat::*;
+    let backup_file = match get_config().positions.restore {
+        None => unreachable!(),
+        Legacy => BackupFile::Legacy(backup_file),
+        V1 => BackupFile::V1(backup_file),
+        Booksonic => BackupFile::Booksonic {
+            file: backup_file,
+            group: get_config().positions.import_group.clone(),
+        },
+        Audiobookshelf => BackupFile::Audiobookshelf {
+            file: backup_file,
+            group: get_config()
+                .positions
+                .import_group
+                .clone()
+                .expect("Missing import group argument"),
+        },
+    };
+
+    restore_positions(backup_file).context("Error while restoring position")?;
+
+    let msg =
+        "Positions restoration is finished, exiting program, restart it now without --positions-restore arg or positions restore subcommand";
+    info!("{}", msg);
+    println!("{}", msg);
+    Ok(())
+}
+
 fn build_runtime() -> tokio::runtime::Runtime {
     let cfg = get_config();
     tokio::runtime::Builder::new_multi_thread()
@@ -154,25 +289,46 @@ fn start_server(
     rt: &tokio::runtime::Runtime,
     server_secret: Vec<u8>,
     collections: Arc<Collections>,
-) -> (oneshot::Receiver<()>, watch::Sender<()>) {
+) -> (oneshot::Receiver<()>, watch::Sender<()>, Counter) {
     let cfg = get_config();
 
     let addr = cfg.listen;
     let (stop_service_sender, stop_service_receiver) = watch::channel(());
+    let transcodings: Counter = Arc::new(AtomicUsize::new(0));
+    let transcoding = TranscodingDetails {
+        transcodings: transcodings.clone(),
+        max_transcodings: cfg.transcoding.max_parallel_processes,
+    };
     let start_server = async move {
         let authenticator = get_config().shared_secret.as_ref().map(|secret| {
             SharedSecretAuthenticator::new(secret.clone(), server_secret, cfg.token_validity_hours)
         });
-        let transcoding = TranscodingDetails {
-            transcodings: Arc::new(AtomicUsize::new(0)),
-            max_transcodings: cfg.transcoding.max_parallel_processes,
-        };
+        #[cfg(feature = "transcoding-cache")]
+        if let Some(level) = cfg.transcoding.cache.preheat_level() {
+            tokio::spawn(services::transcode::preheat::preheat_cache(
+                collections.clone(),
+                level,
+                transcoding.clone(),
+            ));
+        }
+        if let Some(schedule) = cfg.cache_maintenance.schedule.as_ref() {
+            tokio::spawn(maintain_caches_periodically(schedule));
+        }
+        let save_interval = cfg.icons.cache_save_interval;
+        if save_interval > 0 {
+            tokio::spawn(save_images_cache_periodically(Duration::from_secs(
+                save_interval.into(),
+            )));
+        }
+        if cfg.import.is_some() {
+            tokio::spawn(services::import::run_import(collections.clone()));
+        }
+        let dlna_stop_receiver = stop_service_receiver.clone();
         let svc_factory = ServiceFactory::new(
             authenticator,
             Search::new(Some(collections.clone())),
             transcoding,
             collections,
-            cfg.limit_rate,
             stop_service_receiver,
         );
         // TODO : handle file UTF8 errors
@@ -184,6 +340,22 @@ fn start_server(
         #[cfg(not(feature = "tls"))]
         let tls_config = None;
 
+        // advertised until server stops
+        let _mdns = if cfg.discovery.mdns {
+            services::discovery::advertise_mdns(tls_config.is_some())
+                .map_err(|e| error!("Cannot advertise server via mDNS: {:#}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        if cfg.dlna.is_some() {
+            tokio::spawn(services::dlna::ssdp::run_ssdp(
+                tls_config.is_some(),
+                dlna_stop_receiver,
+            ));
+        }
+
         let server: Pin<Box<dyn Future<Output = Result<(), Error>> + Send>> = {
             let is_tls = tls_config.is_some();
             let server = HttpServer::bind(addr).serve(svc_factory, tls_config);
@@ -208,7 +380,48 @@ fn start_server(
                 futures::future::ready(())
             }),
     );
-    (term_receiver, stop_service_sender)
+    (term_receiver, stop_service_sender, transcodings)
+}
+
+/// Lets running transcodings (and related cache entries) finish after server stopped
+/// accepting new connections, but waits at most for configured grace period
+async fn drain_transcodings(transcodings: Counter) {
+    const CHECK_INTERVAL: Duration = Duration::from_millis(200);
+    const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+    let pending = || {
+        let running = transcodings.load(Ordering::Acquire);
+        #[cfg(feature = "transcoding-cache")]
+        let running = running + services::transcode::cache::pending_finishes();
+        running
+    };
+
+    let grace_period = Duration::from_secs(get_config().transcoding.shutdown_grace_period.into());
+    let start = Instant::now();
+    let mut last_report = start;
+    let mut running = pending();
+    if running > 0 {
+        info!(
+            "Waiting up to {:?} for {} running transcodings to finish",
+            grace_period, running
+        );
+    }
+    while running > 0 {
+        if start.elapsed() >= grace_period {
+            warn!(
+                "Grace period elapsed, {} transcodings will be terminated",
+                running
+            );
+            return;
+        }
+        if last_report.elapsed() >= REPORT_INTERVAL {
+            info!("Still waiting for {} transcodings to finish", running);
+            last_report = Instant::now();
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        running = pending();
+    }
+    debug!("All transcodings finished");
 }
 
 #[cfg(not(unix))]
@@ -255,8 +468,137 @@ async fn watch_for_cache_update_signal(cols: Arc<Collections>) {
     use tokio::signal::unix::{signal, SignalKind};
     let mut sigusr1 = signal(SignalKind::user_defined1()).expect("Cannot create SIGUSR1 handler");
     while let Some(()) = sigusr1.recv().await {
-        info!("Received signal SIGUSR1 for full rescan of caches");
-        cols.clone().force_rescan()
+        let indexes = match get_config().collections_options.rescan_control_file {
+            Some(ref control_file) => read_rescan_control_file(control_file).await,
+            None => None,
+        };
+        match indexes {
+            Some(indexes) => {
+                info!(
+                    "Received signal SIGUSR1 for rescan of collections {:?}",
+                    indexes
+                );
+                for idx in indexes {
+                    match cols.force_rescan_collection(idx) {
+                        Ok(true) => (),
+                        Ok(false) => warn!("Rescan of collection {} cannot be started", idx),
+                        Err(e) => error!("Cannot rescan collection {}: {}", idx, e),
+                    }
+                }
+            }
+            None => {
+                info!("Received signal SIGUSR1 for full rescan of caches");
+                cols.clone().force_rescan()
+            }
+        }
+    }
+}
+
+/// Reads and removes rescan control file, None means all collections should be rescanned
+#[cfg(unix)]
+async fn read_rescan_control_file(control_file: &Path) -> Option<Vec<usize>> {
+    let content = tokio::fs::read_to_string(control_file).await.ok()?;
+    if let Err(e) = tokio::fs::remove_file(control_file).await {
+        error!(
+            "Cannot remove rescan control file {:?}: {}",
+            control_file, e
+        )
+    }
+    match util::parse_collection_indexes(&content) {
+        Ok(indexes) if !indexes.is_empty() => Some(indexes),
+        Ok(_) => None,
+        Err(e) => {
+            error!(
+                "Invalid content of rescan control file, no rescan done: {}",
+                e
+            );
+            Some(vec![])
+        }
+    }
+}
+
+/// Saves icons and covers caches indexes, so additions are not lost on crash
+async fn save_images_cache_periodically(interval: Duration) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    loop {
+        interval.tick().await;
+        match tokio::task::spawn_blocking(services::icon::cache::save_changed_indexes).await {
+            Ok(Ok(())) => (),
+            Ok(Err(e)) => error!("Error saving images cache index: {}", e),
+            Err(e) => error!("Saving of images cache index panicked: {}", e),
+        }
+    }
+}
+
+fn log_maintenance_report(
+    name: &str,
+    report: anyhow::Result<simple_file_cache::MaintenanceReport>,
+) {
+    match report {
+        Ok(r) => info!(
+            "Maintenance of {} cache: evicted {} files ({} kB), removed {} orphaned files and {} orphaned partial files, \
+            {} index entries without file{}, cache now has {} files ({} kB), {} files waiting for deletion",
+            name,
+            r.evicted,
+            r.evicted_size / 1024,
+            r.orphaned_entries,
+            r.orphaned_partials,
+            r.missing_entries,
+            if r.size_corrected {
+                ", cache size corrected"
+            } else {
+                ""
+            },
+            r.num_files,
+            r.size / 1024,
+            r.deferred
+        ),
+        Err(e) => error!("Maintenance of {} cache failed: {}", name, e),
+    }
+}
+
+/// Scheduled maintenance of file caches, as otherwise entries are evicted only when new one is added
+async fn maintain_caches_periodically(schedule: &'static str) {
+    let cron = crate::util::parse_cron(schedule).expect("invalid cron expression");
+    loop {
+        let next = cron
+            .upcoming(chrono::Local)
+            .next()
+            .map(|d| {
+                (d - chrono::Local::now())
+                    .to_std()
+                    .unwrap_or_else(|_| Duration::from_millis(100))
+            })
+            .unwrap_or_else(|| Duration::from_secs(u64::MAX));
+        tokio::time::sleep(next).await;
+        let target = get_config().cache_maintenance.target_percent;
+        debug!("Scheduled cache maintenance");
+        #[cfg(feature = "transcoding-cache")]
+        if let Some(report) = services::transcode::cache::maintain(target).await {
+            log_maintenance_report("transcoding", report)
+        }
+        match tokio::task::spawn_blocking(move || services::icon::cache::maintain_caches(target))
+            .await
+        {
+            Ok(reports) => reports
+                .into_iter()
+                .for_each(|(name, report)| log_maintenance_report(&name, report)),
+            Err(e) => error!("Images caches maintenance panicked: {}", e),
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn watch_for_config_reload_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sighup = signal(SignalKind::hangup()).expect("Cannot create SIGHUP handler");
+    while let Some(()) = sighup.recv().await {
+        info!("Received signal SIGHUP for config reload");
+        match tokio::task::spawn_blocking(config::reload_config).await {
+            Ok(Ok(())) => info!("Config reloaded"),
+            Ok(Err(e)) => error!("Config reload failed, keeping previous config: {}", e),
+            Err(e) => error!("Config reload panicked: {}", e),
+        }
     }
 }
 
@@ -288,9 +630,14 @@ async fn watch_for_positions_backup_signal(cols: Arc<Collections>) {
             Ok(Some(())) => info!("Received signal SIGUSR2 for positions backup"),
             Err(_) => debug!("scheduled positions backup"),
         }
-        if let Some(backup_file) = get_config().positions.backup_file.as_ref() {
+        let cfg = &get_config().positions;
+        if let Some(backup_file) = cfg.backup_file.as_ref() {
             cols.clone()
-                .backup_positions_async(backup_file)
+                .backup_positions_async(
+                    backup_file,
+                    cfg.backup_keep.count,
+                    cfg.backup_keep.compress,
+                )
                 .await
                 .map_err(|e| error!("Backup of positions failed: {}", e))
                 .ok();
@@ -310,46 +657,30 @@ fn main() -> anyhow::Result<()> {
     if let Err(e) = init_config() {
         return Err(Error::msg(format!("Config/Arguments error: {}", e)));
     };
-    env_logger::init();
+    logging::init();
     info!(
         "Started audioserve {} with features {}",
         config::LONG_VERSION,
         config::FEATURES
     );
     if log_enabled!(log::Level::Debug) {
-        let mut cfg = get_config().clone();
-        cfg.shared_secret = cfg.shared_secret.map(|_| "******".to_string()); // Do not want to write secret to log!
-        debug!("Started with following config {:?}", cfg);
+        debug!("Started with following config {:?}", masked_config());
     }
 
     collection::init_media_lib();
 
-    #[cfg(feature = "shared-positions")]
-    if !matches!(
-        get_config().positions.restore,
-        config::PositionsBackupFormat::None
-    ) {
-        let backup_file = get_config()
-            .positions
-            .backup_file
-            .clone()
-            .expect("Missing backup file argument");
-
-        use collection::BackupFile;
-        use config::PositionsBackupFormat::*;
-        let backup_file = match get_config().positions.restore {
-            None => unreachable!(),
-            Legacy => BackupFile::Legacy(backup_file),
-            V1 => BackupFile::V1(backup_file),
-        };
-
-        restore_positions(backup_file).context("Error while restoring position")?;
-
-        let msg =
-            "Positions restoration is finished, exiting program, restart it now without --positions-restore arg";
-        info!("{}", msg);
-        println!("{}", msg);
-        return Ok(());
+    match get_config().command {
+        RunCommand::Serve => (),
+        RunCommand::CheckConfig => {
+            println!("{}", serde_yaml::to_string(&masked_config()).unwrap());
+            println!("# Configuration is valid");
+            return Ok(());
+        }
+        RunCommand::Scan => return scan_collections(),
+        #[cfg(feature = "shared-positions")]
+        RunCommand::PositionsBackup => return backup_positions(),
+        #[cfg(feature = "shared-positions")]
+        RunCommand::PositionsRestore => return run_positions_restore(),
     }
 
     #[cfg(feature = "transcoding-cache")]
@@ -373,18 +704,48 @@ fn main() -> anyhow::Result<()> {
 
     let collections = create_collections()?;
     let runtime = build_runtime();
-    let (term_receiver, stop_service_sender) =
+    #[cfg(feature = "transcoding-cache")]
+    if !get_config().transcoding.cache.disabled {
+        let handle = runtime.handle().clone();
+        collections.set_removal_listener(move |path| {
+            let path = path.to_owned();
+            handle.spawn(async move {
+                match services::transcode::cache::remove_path(&path).await {
+                    Ok(0) => (),
+                    Ok(n) => debug!("Removed {} cached transcodings of {:?}", n, path),
+                    Err(e) => error!("Error removing cached transcodings of {:?}: {}", path, e),
+                }
+            });
+        });
+    }
+    collections.set_change_listener(services::events::publish);
+    #[cfg(feature = "webhooks")]
+    if !get_config().webhooks.hooks.is_empty() {
+        runtime.spawn(services::webhooks::run_webhooks());
+    }
+    #[cfg(feature = "scrobbler")]
+    if get_config().scrobbler.is_enabled() {
+        runtime.spawn(services::scrobbler::run_scrobbler(collections.clone()));
+    }
+    if get_config().auto_collections.is_some() {
+        runtime.spawn(watch_auto_collections(collections.clone()));
+    }
+    let (term_receiver, stop_service_sender, transcodings) =
         start_server(&runtime, server_secret, collections.clone());
 
     #[cfg(unix)]
     {
         runtime.spawn(watch_for_cache_update_signal(collections.clone()));
+        runtime.spawn(watch_for_config_reload_signal());
         #[cfg(feature = "shared-positions")]
         runtime.spawn(watch_for_positions_backup_signal(collections.clone()));
     }
 
     runtime.block_on(terminate_server(term_receiver, stop_service_sender));
 
+    // server is not accepting new connections now, give running transcodings chance to finish
+    runtime.block_on(drain_transcodings(transcodings));
+
     //graceful shutdown of server will wait till immediate tasks ends, so rather shut it down hard
     runtime.shutdown_timeout(std::time::Duration::from_millis(300));
 
@@ -418,6 +779,9 @@ fn main() -> anyhow::Result<()> {
             if let Err(e) = get_cache().save_index_blocking() {
                 error!("Error saving transcoding cache index {}", e);
             }
+            if let Err(e) = crate::services::transcode::cache::save_fingerprints() {
+                error!("Error saving content fingerprints {}", e);
+            }
         }
     }
 
@@ -427,6 +791,10 @@ fn main() -> anyhow::Result<()> {
         }
     }
 
+    if let Err(e) = crate::services::icon::cache::save_covers_index() {
+        error!("Error saving covers cache index {}", e);
+    }
+
     info!("Server finished");
 
     Ok(())
@@ -0,0 +1,190 @@
+use crate::config::{get_config, AccessLogConfig};
+use myhy::headers::{HeaderMapExt, UserAgent};
+use myhy::request::{resolve_remote_addr, HttpRequest};
+use myhy::response::{body::inspect_body, HttpResponse};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    static ref ACCESS_LOG: Option<AccessLog> = get_config()
+        .access_log
+        .as_ref()
+        .map(|cfg| AccessLog::new(cfg.clone()));
+}
+
+pub fn get_access_log() -> Option<&'static AccessLog> {
+    ACCESS_LOG.as_ref()
+}
+
+#[derive(Serialize)]
+struct AccessLogEntry {
+    /// unix timestamp in seconds (with millis precision)
+    time: f64,
+    remote_ip: Option<IpAddr>,
+    method: String,
+    path: String,
+    status: u16,
+    bytes: u64,
+    duration_ms: u64,
+    user_agent: Option<String>,
+}
+
+/// Writes access log entries as JSON lines, writing is done in separate thread
+pub struct AccessLog {
+    sender: Sender<AccessLogEntry>,
+}
+
+impl AccessLog {
+    fn new(cfg: AccessLogConfig) -> Self {
+        let (sender, receiver) = channel();
+        thread::Builder::new()
+            .name("access-log".into())
+            .spawn(move || {
+                if let Err(e) = write_log(cfg, receiver) {
+                    error!("Access log writing failed: {}", e)
+                }
+            })
+            .expect("Cannot start access log thread");
+        AccessLog { sender }
+    }
+
+    /// Starts record of the request - record is written, when response body is fully sent (or dropped)
+    pub fn start(&'static self, req: &HttpRequest, remote_addr: IpAddr) -> AccessRecord {
+        let headers = req.headers();
+        AccessRecord {
+            log: self,
+            start: Instant::now(),
+            entry: AccessLogEntry {
+                time: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_millis() as f64 / 1000.0)
+                    .unwrap_or_default(),
+                remote_ip: resolve_remote_addr(
+                    headers,
+                    Some(remote_addr),
+                    get_config().is_trusted_proxy(remote_addr),
+                )
+                .map(|a| *a.as_ref()),
+                method: req.method().to_string(),
+                path: req.uri().path().to_string(),
+                status: 0,
+                bytes: 0,
+                duration_ms: 0,
+                user_agent: headers.typed_get::<UserAgent>().map(|ua| ua.to_string()),
+            },
+        }
+    }
+}
+
+pub struct AccessRecord {
+    log: &'static AccessLog,
+    start: Instant,
+    entry: AccessLogEntry,
+}
+
+impl AccessRecord {
+    pub fn finish(mut self, resp: HttpResponse) -> HttpResponse {
+        self.entry.status = resp.status().as_u16();
+        resp.map(move |body| {
+            // whole record must be moved into closure, so it's dropped together with the body
+            let mut record = self;
+            inspect_body(body, move |data| {
+                let record = &mut record;
+                record.entry.bytes += data.len() as u64
+            })
+        })
+    }
+}
+
+impl Drop for AccessRecord {
+    fn drop(&mut self) {
+        let entry = AccessLogEntry {
+            duration_ms: self.start.elapsed().as_millis() as u64,
+            method: std::mem::take(&mut self.entry.method),
+            path: std::mem::take(&mut self.entry.path),
+            user_agent: self.entry.user_agent.take(),
+            ..self.entry
+        };
+        self.log.sender.send(entry).ok();
+    }
+}
+
+fn open_log(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((BufWriter::new(file), size))
+}
+
+fn rotated_name(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    name.into()
+}
+
+/// Rotates log files - access.log -> access.log.1 -> access.log.2 ... last one is removed
+fn rotate(path: &Path, max_files: u32) -> io::Result<()> {
+    if max_files == 0 {
+        return fs::remove_file(path);
+    }
+    let oldest = rotated_name(path, max_files);
+    if oldest.exists() {
+        fs::remove_file(oldest)?;
+    }
+    for n in (1..max_files).rev() {
+        let from = rotated_name(path, n);
+        if from.exists() {
+            fs::rename(from, rotated_name(path, n + 1))?;
+        }
+    }
+    fs::rename(path, rotated_name(path, 1))
+}
+
+fn write_log(cfg: AccessLogConfig, receiver: Receiver<AccessLogEntry>) -> io::Result<()> {
+    let max_size = u64::from(cfg.max_size) * 1024 * 1024;
+    let (mut writer, mut size) = open_log(&cfg.file)?;
+    while let Ok(mut entry) = receiver.recv() {
+        loop {
+            let mut line = serde_json::to_vec(&entry)?;
+            line.push(b'\n');
+            writer.write_all(&line)?;
+            size += line.len() as u64;
+            if size >= max_size {
+                writer.flush()?;
+                rotate(&cfg.file, cfg.max_files)?;
+                (writer, size) = open_log(&cfg.file)?;
+            }
+            match receiver.try_recv() {
+                Ok(e) => entry = e,
+                Err(_) => break,
+            }
+        }
+        writer.flush()?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_rotate() {
+        let dir = TempDir::new("access_log_test").unwrap();
+        let path = dir.path().join("access.log");
+        for i in 0..4 {
+            fs::write(&path, format!("{}", i)).unwrap();
+            rotate(&path, 2).unwrap();
+        }
+        assert!(!path.exists());
+        assert_eq!("3", fs::read_to_string(rotated_name(&path, 1)).unwrap());
+        assert_eq!("2", fs::read_to_string(rotated_name(&path, 2)).unwrap());
+        assert!(!rotated_name(&path, 3).exists());
+    }
+}
@@ -1,88 +1,1022 @@
+use std::collections::hash_map::DefaultHasher;
 use std::ffi::OsStr;
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
 use std::{path::PathBuf, sync::Arc};
 
-use collection::FoldersOrdering;
+use collection::audio_meta::AudioFolder;
+use collection::{FilesOrdering, FoldersOrdering};
 use futures::prelude::*;
 use tokio::task::spawn_blocking as blocking;
 
+use super::auth::Account;
 use super::search::{Search, SearchTrait};
-use super::types::CollectionsInfo;
 use super::types::Transcodings;
+use super::types::{
+    ApiError, CollectionScanStatus, CollectionState, CollectionStatus, CollectionsInfo,
+};
+use super::visibility::FolderFilter;
 use crate::config::get_config;
 use crate::Error;
-use myhy::response::{self, json_response, ResponseResult};
+use myhy::headers::IfNoneMatch;
+use myhy::response::{self, json_response, json_response_with_etag, weak_etag, ResponseResult};
 
+/// Part of folder listing requested by client - default is complete listing
+#[derive(Default, Hash, Debug)]
+pub struct FolderView {
+    pub files_ordering: FilesOrdering,
+    /// number of files and subfolders skipped
+    pub offset: usize,
+    /// maximum number of files and subfolders returned
+    pub limit: Option<usize>,
+    /// only these top level fields are returned
+    pub fields: Option<Vec<String>>,
+}
+
+impl FolderView {
+    fn is_complete(&self) -> bool {
+        self.offset == 0 && self.limit.is_none() && self.fields.is_none()
+    }
+
+    fn is_paged(&self) -> bool {
+        self.offset > 0 || self.limit.is_some()
+    }
+
+    fn page<T>(&self, items: &mut Vec<T>) {
+        items.drain(..self.offset.min(items.len()));
+        if let Some(limit) = self.limit {
+            items.truncate(limit);
+        }
+    }
+
+    /// Partial listing, paged listing contains total counts of files and subfolders
+    fn apply(&self, mut folder: AudioFolder) -> serde_json::Value {
+        let total_files = folder.files.len();
+        let total_subfolders = folder.subfolders.len();
+        self.page(&mut folder.files);
+        self.page(&mut folder.subfolders);
+        let mut value = serde_json::to_value(&folder).expect("Folder serialization cannot fail");
+        if let Some(map) = value.as_object_mut() {
+            if let Some(fields) = self.fields.as_ref() {
+                map.retain(|k, _| fields.iter().any(|f| f == k));
+            }
+            if self.is_paged() {
+                map.insert("total_files".into(), total_files.into());
+                map.insert("total_subfolders".into(), total_subfolders.into());
+            }
+        }
+        value
+    }
+}
+
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/{col_id}/folder/{path}",
+        tag = "Collections",
+        params(
+            ("col_id" = usize, Path, description = "collection number"),
+            ("path" = String, Path, description = "path in collection"),
+            ("ord" = Option<String>, Query, description = "folders ordering - a (alphabetical, default) or m (recent first)"),
+            ("group" = Option<String>, Query, description = "group for playback positions"),
+            ("files_ord" = Option<String>, Query, description = "files ordering - n (name, default), t (track) or m (modified)"),
+            ("offset" = Option<usize>, Query, description = "first file and subfolder returned"),
+            ("limit" = Option<usize>, Query, description = "max. number of files and subfolders returned"),
+            ("fields" = Option<String>, Query, description = "comma separated list of returned fields"),
+        ),
+        responses(
+            (status = 200, description = "Folder listing", body = collection::audio_meta::AudioFolder),
+            (status = 304, description = "Not modified"),
+            (status = 404, description = "Folder not found"),
+            (status = 503, description = "Collection is unavailable"),
+        )
+    )
+)]
+#[allow(clippy::too_many_arguments)]
 pub async fn get_folder(
     collection: usize,
     folder_path: PathBuf,
     collections: Arc<collection::Collections>,
     ordering: FoldersOrdering,
     group: Option<String>,
+    view: FolderView,
+    folder_filter: Option<FolderFilter>,
+    if_none_match: Option<IfNoneMatch>,
     compress: bool,
 ) -> ResponseResult {
-    blocking(move || collections.list_dir(collection, &folder_path, ordering, group))
-        .map_ok(move |res| match res {
-            Ok(folder) => json_response(&folder, compress),
-            Err(_) => response::not_found(),
-        })
-        .map_err(Error::new)
-        .await
+    let files_ordering = view.files_ordering;
+    let filtered = folder_filter.is_some();
+    blocking(move || {
+        collections
+            .list_dir(collection, &folder_path, ordering, group)
+            .map(|mut folder| {
+                if let Some(filter) = folder_filter {
+                    filter.apply_folder(&folder_path, &mut folder);
+                }
+                collection::sort_files(
+                    &mut folder.files,
+                    files_ordering,
+                    get_config().base_dir_for(collection, &folder_path),
+                );
+                folder
+            })
+    })
+    .map_ok(move |res| match res {
+        Ok(folder) if view.is_complete() && !filtered => {
+            let etag = folder.state_hash().map(weak_etag);
+            json_response_with_etag(&folder, etag, if_none_match.as_ref(), compress)
+        }
+        Ok(folder) => {
+            // different views of same folder must have different etags
+            let etag = folder.state_hash().map(|state| {
+                let mut hasher = DefaultHasher::new();
+                state.hash(&mut hasher);
+                view.hash(&mut hasher);
+                filtered.hash(&mut hasher);
+                weak_etag(hasher.finish())
+            });
+            let value = view.apply(folder);
+            json_response_with_etag(&value, etag, if_none_match.as_ref(), compress)
+        }
+        Err(collection::error::Error::CollectionUnavailable) => {
+            ApiError::CollectionUnavailable.response()
+        }
+        Err(_) => ApiError::FolderNotFound.response(),
+    })
+    .map_err(Error::new)
+    .await
 }
 
 const UNKNOWN_NAME: &str = "unknown";
 
-pub fn collections_list(compress: bool) -> ResponseResult {
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/collections/",
+        tag = "Collections",
+        responses(
+            (status = 200, description = "Available collections", body = CollectionsInfo),
+            (status = 304, description = "Not modified"),
+        )
+    )
+)]
+pub fn collections_list(
+    collections: &collection::Collections,
+    account: Option<&str>,
+    if_none_match: Option<IfNoneMatch>,
+    compress: bool,
+) -> ResponseResult {
+    let cfg = get_config();
+    let indexes: Vec<_> = (0..cfg.base_dirs.len())
+        .filter(|&c| cfg.is_collection_allowed(c, account))
+        .collect();
+    let scan_status = collections.scan_status();
+    let status = indexes
+        .iter()
+        .map(|&c| {
+            let scan = scan_status.get(c).cloned().unwrap_or_default();
+            let state = if scan.degraded {
+                CollectionState::Error
+            } else if !collections.is_initial_scan_done_for(c) {
+                CollectionState::Scanning
+            } else {
+                CollectionState::Ready
+            };
+            CollectionStatus {
+                state,
+                folders_scanned: scan.folders_scanned,
+                folders_queued: scan.folders_queued,
+            }
+        })
+        .collect();
     let collections = CollectionsInfo {
         version: env!("CARGO_PKG_VERSION"),
         commit: env!("AUDIOSERVE_COMMIT"),
-        folder_download: !get_config().disable_folder_download,
+        folder_download: !cfg.disable_folder_download,
         shared_positions: cfg!(feature = "shared-positions"),
-        count: get_config().base_dirs.len() as u32,
-        names: get_config()
-            .base_dirs
+        count: indexes.len() as u32,
+        names: indexes
             .iter()
-            .map(|p| {
-                p.file_name()
+            .map(|&c| {
+                cfg.base_dirs[c]
+                    .file_name()
                     .and_then(OsStr::to_str)
                     .unwrap_or(UNKNOWN_NAME)
             })
             .collect(),
+        indexes,
+        status,
+    };
+    Ok(json_response_with_etag(
+        &collections,
+        None,
+        if_none_match.as_ref(),
+        compress,
+    ))
+}
+
+/// Moves folder to collection trash or restores it from there
+pub async fn trash_folder(
+    collections: Arc<collection::Collections>,
+    collection: usize,
+    path: String,
+    restore: bool,
+) -> ResponseResult {
+    use collection::error::Error as CollectionError;
+    blocking(move || {
+        let res = if restore {
+            collections.restore_folder(collection, &path)
+        } else {
+            collections.trash_folder(collection, &path)
+        };
+        match res {
+            Ok(_) => Ok(response::no_content()),
+            Err(CollectionError::FolderNotFound(_)) => {
+                Ok(ApiError::FolderNotFound.with_detail(&path))
+            }
+            Err(CollectionError::AlreadyExists(p)) => {
+                Ok(ApiError::AlreadyExists.with_detail(p.to_string_lossy()))
+            }
+            Err(CollectionError::InvalidCollectionPath) => {
+                Ok(ApiError::InvalidParameter.with_detail("path"))
+            }
+            Err(e) => Err(Error::new(e)),
+        }
+    })
+    .await
+    .map_err(Error::new)?
+}
+
+/// Starts full rescan of given collection or of all collections
+pub fn rescan(
+    collections: Arc<collection::Collections>,
+    collection: Option<usize>,
+) -> ResponseResult {
+    let started = match collection {
+        Some(collection) => match collections.force_rescan_collection(collection) {
+            Ok(started) => started,
+            Err(e) => {
+                error!("Cannot rescan collection: {}", e);
+                return Ok(ApiError::InvalidCollection.with_detail(e));
+            }
+        },
+        None => {
+            (0..get_config().base_dirs.len())
+                .filter(|&c| collections.force_rescan_collection(c).unwrap_or(false))
+                .count()
+                > 0
+        }
+    };
+    if started {
+        Ok(response::accepted())
+    } else {
+        Ok(ApiError::ScanRunning.response())
+    }
+}
+
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/api/admin/scan-status",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "Scan status of all collections", body = Vec<CollectionScanStatus>),
+            (status = 403, description = "Not allowed for account"),
+        )
+    )
+)]
+pub fn scan_status(collections: Arc<collection::Collections>, compress: bool) -> ResponseResult {
+    let status: Vec<_> = collections
+        .scan_status()
+        .into_iter()
+        .zip(get_config().base_dirs.iter())
+        .enumerate()
+        .map(|(collection, (status, dir))| CollectionScanStatus {
+            collection,
+            name: dir
+                .file_name()
+                .and_then(OsStr::to_str)
+                .unwrap_or(UNKNOWN_NAME),
+            status,
+        })
+        .collect();
+    Ok(json_response(&status, compress))
+}
+
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/api/collections/{col_id}/stats",
+        tag = "Collections",
+        params(
+            ("col_id" = usize, Path, description = "collection number"),
+        ),
+        responses(
+            (status = 200, description = "Content statistics of collection", body = collection::CollectionStats),
+            (status = 404, description = "Invalid collection"),
+            (status = 409, description = "Collection is not cached"),
+        )
+    )
+)]
+pub fn collection_stats(
+    collections: Arc<collection::Collections>,
+    collection: usize,
+    compress: bool,
+) -> ResponseResult {
+    match collections.collection_stats(collection) {
+        Ok(Some(stats)) => Ok(json_response(&stats, compress)),
+        Ok(None) => Ok(ApiError::NotCached.response()),
+        Err(e) => {
+            error!("Cannot get collection stats: {}", e);
+            Ok(ApiError::InvalidCollection.response())
+        }
+    }
+}
+
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/api/import/status",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "Status of import from watched directory", body = super::import::ImportStatus),
+            (status = 403, description = "Not allowed for account"),
+            (status = 404, description = "Import is not configured"),
+        )
+    )
+)]
+pub fn import_status(compress: bool) -> ResponseResult {
+    match super::import::import_status() {
+        Some(status) => Ok(json_response(&status, compress)),
+        None => Ok(ApiError::ImportDisabled.response()),
+    }
+}
+
+#[derive(serde::Serialize)]
+struct HealthStatus {
+    status: &'static str,
+}
+
+/// Liveness check - server is running
+pub fn health() -> ResponseResult {
+    Ok(json_response(&HealthStatus { status: "ok" }, false))
+}
+
+/// Readiness check - server is ready after initial scan of all collections
+pub fn readiness(collections: &collection::Collections) -> ResponseResult {
+    if collections.is_initial_scan_done() {
+        Ok(json_response(&HealthStatus { status: "ready" }, false))
+    } else {
+        Ok(ApiError::NotReady.response())
+    }
+}
+
+/// Number of least recently used cache entries returned in cache stats
+#[cfg(feature = "transcoding-cache")]
+const CACHE_STATS_OLDEST: usize = 20;
+
+#[cfg(feature = "transcoding-cache")]
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/api/cache/transcoding",
+        tag = "Admin",
+        responses(
+            (status = 200, description = "Transcoding cache statistics", body = super::types::TranscodingCacheStats),
+            (status = 403, description = "Not allowed for account"),
+        )
+    )
+)]
+pub fn transcoding_cache_stats(compress: bool) -> ResponseResult {
+    use super::transcode::cache::CACHE;
+    use super::types::TranscodingCacheStats;
+    let stats = match CACHE.as_ref() {
+        Some(cache) => cache.stats(CACHE_STATS_OLDEST).into(),
+        None => TranscodingCacheStats {
+            disabled: true,
+            size: 0,
+            num_files: 0,
+            max_size: 0,
+            max_files: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+            oldest: vec![],
+        },
+    };
+    Ok(json_response(&stats, compress))
+}
+
+#[cfg(feature = "tags-write")]
+#[derive(Debug, serde::Deserialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+struct TagsEdit {
+    title: Option<String>,
+    album: Option<String>,
+    artist: Option<String>,
+    track: Option<String>,
+    /// path of image in collection, which is embedded as cover
+    cover: Option<String>,
+}
+
+#[cfg(feature = "tags-write")]
+#[derive(Debug, serde::Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+struct TagsEdited {
+    #[cfg_attr(feature = "api-spec", schema(value_type = Vec<String>))]
+    files: Vec<PathBuf>,
+}
+
+/// Audio files directly in folder
+#[cfg(feature = "tags-write")]
+fn audio_files_in(dir: &std::path::Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && collection::audio_meta::is_audio(p))
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+/// Updates tags of audio file or of all audio files in folder (title and track only for single file)
+#[cfg(feature = "tags-write")]
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        post,
+        path = "/api/admin/tags/{col_id}/{path}",
+        tag = "Admin",
+        params(
+            ("col_id" = usize, Path, description = "collection number"),
+            ("path" = String, Path, description = "path in collection"),
+        ),
+        request_body = TagsEdit,
+        responses(
+            (status = 200, description = "Updated files", body = TagsEdited),
+            (status = 400, description = "Invalid tags or cover"),
+            (status = 404, description = "Not audio file or folder"),
+            (status = 413, description = "Request body is too large"),
+        )
+    )
+)]
+pub async fn update_tags(
+    collections: Arc<collection::Collections>,
+    collection: usize,
+    path: String,
+    bytes: bytes::Bytes,
+) -> ResponseResult {
+    use crate::error::Context;
+    use std::path::Path;
+
+    let edit = match serde_json::from_slice::<TagsEdit>(&bytes) {
+        Ok(edit) => edit,
+        Err(e) => {
+            error!("Error in tags JSON: {}", e);
+            return Ok(ApiError::InvalidJson.with_detail(e));
+        }
     };
-    Ok(json_response(&collections, compress))
+    let full_path = get_config().base_dir_for(collection, &path).join(&path);
+    let is_dir = full_path.is_dir();
+    let is_audio_file = full_path.is_file() && collection::audio_meta::is_audio(&full_path);
+    if !(is_dir || is_audio_file) {
+        error!(
+            "Tags can be updated only for audio file or folder, not {}",
+            path
+        );
+        return Ok(ApiError::FileNotFound.with_detail(&path));
+    }
+    if is_dir && (edit.title.is_some() || edit.track.is_some()) {
+        error!("Title and track cannot be set for whole folder {}", path);
+        return Ok(ApiError::InvalidData.with_detail("title and track cannot be set for folder"));
+    }
+    blocking(move || {
+        let cover = match edit.cover {
+            Some(cover) => {
+                let cover_path = get_config().base_dir_for(collection, &cover).join(&cover);
+                match std::fs::read(&cover_path) {
+                    Ok(data) => Some(data),
+                    Err(e) => {
+                        error!("Cannot read cover {:?}: {}", cover_path, e);
+                        return Ok(ApiError::FileNotFound.with_detail(cover));
+                    }
+                }
+            }
+            None => None,
+        };
+        let update = collection::TagsUpdate {
+            title: edit.title,
+            album: edit.album,
+            artist: edit.artist,
+            track: edit.track,
+            cover,
+        };
+        if update.is_empty() {
+            return Ok(ApiError::InvalidData.with_detail("nothing to update"));
+        }
+        let (folder, files) = if is_dir {
+            let files = audio_files_in(&full_path)
+                .with_context(|| format!("Cannot list folder {:?}", full_path))?;
+            (Path::new(&path).to_owned(), files)
+        } else {
+            let folder = Path::new(&path)
+                .parent()
+                .map(Path::to_owned)
+                .unwrap_or_default();
+            (folder, vec![full_path])
+        };
+        let mut edited = TagsEdited {
+            files: Vec::with_capacity(files.len()),
+        };
+        for file in files {
+            match collection::update_tags(&file, &update) {
+                Ok(_) => edited
+                    .files
+                    .push(folder.join(file.file_name().unwrap_or_default())),
+                // unsupported cover is problem of request, not of file
+                Err(collection::MediaInfoError::UnsupportedCover) => {
+                    return Ok(ApiError::UnsupportedCover.response())
+                }
+                Err(e) => {
+                    return Err(Error::new(e))
+                        .with_context(|| format!("Cannot update tags of {:?}", file))
+                }
+            }
+        }
+        info!(
+            "Updated tags of {} files in {:?}",
+            edited.files.len(),
+            folder
+        );
+        if let Err(e) = collections.refresh_folder(collection, &folder) {
+            error!(
+                "Cannot refresh folder {:?} after tags update: {}",
+                folder, e
+            );
+        }
+        Ok(json_response(&edited, false))
+    })
+    .await
+    .map_err(Error::new)?
+}
+
+/// Lyrics or transcript of audio file or chapter
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/api/text/{col_id}/{path}",
+        tag = "Collections",
+        params(
+            ("col_id" = usize, Path, description = "collection number"),
+            ("path" = String, Path, description = "path in collection"),
+        ),
+        responses(
+            (status = 200, description = "Lines of text", body = collection::text::Text),
+            (status = 404, description = "Audio file not found or it has no text"),
+        )
+    )
+)]
+pub async fn text(collection: usize, path: String, compress: bool) -> ResponseResult {
+    blocking(move || {
+        let (real_path, span) = collection::parse_chapter_path(std::path::Path::new(&path));
+        let full_path = get_config().base_dir_for(collection, &path).join(real_path);
+        if !(full_path.is_file() && collection::audio_meta::is_audio(&full_path)) {
+            error!("Requesting text of non existent file {:?}", full_path);
+            return ApiError::FileNotFound.with_detail(&path);
+        }
+        match collection::extract_text(&full_path, span) {
+            Some(text) => json_response(&text, compress),
+            None => ApiError::TextNotFound.response(),
+        }
+    })
+    .await
+    .map_err(Error::new)
 }
 
 #[cfg(feature = "shared-positions")]
 pub async fn insert_position(
     collections: Arc<collection::Collections>,
     group: String,
+    account: Account,
     bytes: bytes::Bytes,
 ) -> ResponseResult {
     match serde_json::from_slice::<collection::Position>(&bytes) {
+        Ok(pos) if !get_config().is_collection_allowed(pos.collection, account.as_deref()) => {
+            Ok(ApiError::CollectionNotAllowed.response())
+        }
         Ok(pos) => match collections.insert_position_if_newer_async(group, pos).await {
             Ok(_) => Ok(response::created()),
             Err(e) => match e {
-                collection::error::Error::IgnoredPosition => Ok(response::ignored()),
+                collection::error::Error::IgnoredPosition => {
+                    Ok(ApiError::PositionIgnored.response())
+                }
                 _ => Err(Error::new(e)),
             },
         },
         Err(e) => {
             error!("Error in position JSON: {}", e);
-            Ok(response::bad_request())
+            Ok(ApiError::InvalidJson.with_detail(e))
+        }
+    }
+}
+
+#[cfg(feature = "shared-positions")]
+#[derive(Debug, serde::Deserialize)]
+struct FolderStateUpdate {
+    group: String,
+    state: collection::FolderState,
+}
+
+#[cfg(feature = "shared-positions")]
+pub async fn set_folder_state(
+    collections: Arc<collection::Collections>,
+    collection: usize,
+    folder: String,
+    bytes: bytes::Bytes,
+) -> ResponseResult {
+    let update = match serde_json::from_slice::<FolderStateUpdate>(&bytes) {
+        Ok(update) => update,
+        Err(e) => {
+            error!("Error in folder state JSON: {}", e);
+            return Ok(ApiError::InvalidJson.with_detail(e));
+        }
+    };
+    if !get_config()
+        .base_dir_for(collection, &folder)
+        .join(&folder)
+        .exists()
+    {
+        error!("Setting state of non existent folder {}", folder);
+        return Ok(ApiError::FolderNotFound.with_detail(&folder));
+    }
+    match collections
+        .set_folder_state_async(collection, update.group, folder, update.state)
+        .await
+    {
+        Ok(_) => Ok(response::created()),
+        Err(e) => Err(Error::new(e)),
+    }
+}
+
+#[cfg(feature = "shared-positions")]
+pub async fn positions_backup(
+    collections: Arc<collection::Collections>,
+    compress: bool,
+) -> ResponseResult {
+    let json = collections
+        .positions_json_async()
+        .await
+        .map_err(Error::new)?;
+    let json = String::from_utf8(json).map_err(Error::new)?;
+    Ok(response::json_string_response(json, compress))
+}
+
+#[cfg(feature = "shared-positions")]
+#[derive(Debug, serde::Serialize)]
+struct PositionsRestored {
+    restored: usize,
+}
+
+#[cfg(feature = "shared-positions")]
+pub async fn positions_restore(
+    collections: Arc<collection::Collections>,
+    bytes: bytes::Bytes,
+) -> ResponseResult {
+    let data = match serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&bytes) {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Error in positions backup JSON: {}", e);
+            return Ok(ApiError::InvalidJson.with_detail(e));
+        }
+    };
+    match collections.restore_positions_live_async(data).await {
+        Ok(restored) => {
+            info!("Positions restored for {} collections", restored);
+            Ok(json_response(&PositionsRestored { restored }, false))
+        }
+        Err(
+            e @ (collection::error::Error::JsonSchemaError(_)
+            | collection::error::Error::JsonError(_)),
+        ) => {
+            error!("Invalid positions backup data: {}", e);
+            Ok(ApiError::InvalidData.with_detail(e))
+        }
+        Err(e) => Err(Error::new(e)),
+    }
+}
+
+#[cfg(feature = "shared-positions")]
+#[derive(Debug, serde::Serialize)]
+struct PositionsPruned {
+    dry_run: bool,
+    pruned: Vec<collection::PrunedPosition>,
+}
+
+#[cfg(feature = "shared-positions")]
+pub async fn positions_prune(
+    collections: Arc<collection::Collections>,
+    dry_run: bool,
+    compress: bool,
+) -> ResponseResult {
+    let pruned = collections
+        .prune_positions_async(|group| get_config().positions.max_age(group), dry_run)
+        .await
+        .map_err(Error::new)?;
+    if !dry_run {
+        info!("Pruned {} positions", pruned.len());
+    }
+    Ok(json_response(
+        &PositionsPruned { dry_run, pruned },
+        compress,
+    ))
+}
+
+#[cfg(feature = "shared-positions")]
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/api/groups",
+        tag = "Positions",
+        responses(
+            (status = 200, description = "Positions groups, most recently active first", body = Vec<collection::GroupInfo>),
+        )
+    )
+)]
+pub async fn groups_list(
+    collections: Arc<collection::Collections>,
+    account: Account,
+    compress: bool,
+) -> ResponseResult {
+    blocking(move || {
+        let res = collections.groups(|c| get_config().is_collection_allowed(c, account.as_deref()));
+        json_response(&res, compress)
+    })
+    .await
+    .map_err(Error::new)
+}
+
+#[cfg(feature = "shared-positions")]
+#[derive(Debug, serde::Deserialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+struct GroupRename {
+    /// new name of the group
+    name: String,
+}
+
+#[cfg(feature = "shared-positions")]
+#[derive(Debug, serde::Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+struct GroupChanged {
+    /// number of folders with changed positions
+    folders: usize,
+}
+
+#[cfg(feature = "shared-positions")]
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        post,
+        path = "/api/groups/rename/{group}",
+        tag = "Positions",
+        params(
+            ("group" = String, Path, description = "current group name"),
+        ),
+        request_body = GroupRename,
+        responses(
+            (status = 200, description = "Group renamed, if new group already has position in folder, newer position is kept", body = GroupChanged),
+            (status = 400, description = "Invalid new name"),
+            (status = 404, description = "Group has no positions"),
+        )
+    )
+)]
+pub async fn group_rename(
+    collections: Arc<collection::Collections>,
+    group: String,
+    bytes: bytes::Bytes,
+) -> ResponseResult {
+    let rename = match serde_json::from_slice::<GroupRename>(&bytes) {
+        Ok(rename) => rename,
+        Err(e) => {
+            error!("Error in group rename JSON: {}", e);
+            return Ok(ApiError::InvalidJson.with_detail(e));
         }
+    };
+    if rename.name.is_empty() || rename.name.contains('/') || rename.name == group {
+        return Ok(ApiError::InvalidData.with_detail("name"));
     }
+    blocking(
+        move || match collections.rename_group(&group, &rename.name) {
+            Ok(0) => Ok(ApiError::GroupNotFound.with_detail(&group)),
+            Ok(folders) => {
+                info!("Group {} renamed to {}", group, rename.name);
+                Ok(json_response(&GroupChanged { folders }, false))
+            }
+            Err(e) => Err(Error::new(e)),
+        },
+    )
+    .await
+    .map_err(Error::new)?
+}
+
+#[cfg(feature = "shared-positions")]
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        post,
+        path = "/api/groups/delete/{group}",
+        tag = "Positions",
+        params(
+            ("group" = String, Path, description = "group name"),
+        ),
+        responses(
+            (status = 200, description = "All positions of group deleted", body = GroupChanged),
+            (status = 404, description = "Group has no positions"),
+        )
+    )
+)]
+pub async fn group_delete(
+    collections: Arc<collection::Collections>,
+    group: String,
+) -> ResponseResult {
+    blocking(move || match collections.delete_group(&group) {
+        Ok(0) => Ok(ApiError::GroupNotFound.with_detail(&group)),
+        Ok(folders) => {
+            info!("Group {} deleted", group);
+            Ok(json_response(&GroupChanged { folders }, false))
+        }
+        Err(e) => Err(Error::new(e)),
+    })
+    .await
+    .map_err(Error::new)?
+}
+
+#[cfg(feature = "shared-positions")]
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/api/settings/{group}",
+        tag = "Positions",
+        params(
+            ("group" = String, Path, description = "group name"),
+        ),
+        responses(
+            (status = 200, description = "Client settings of group, empty object if there are none", body = Object),
+        )
+    )
+)]
+pub async fn settings_get(
+    collections: Arc<collection::Collections>,
+    group: String,
+    compress: bool,
+) -> ResponseResult {
+    blocking(move || {
+        collections
+            .get_settings(&group)
+            .map(|settings| json_response(&settings, compress))
+    })
+    .await
+    .map_err(Error::new)?
+    .map_err(Error::new)
+}
+
+#[cfg(feature = "shared-positions")]
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        put,
+        path = "/api/settings/{group}",
+        tag = "Positions",
+        params(
+            ("group" = String, Path, description = "group name"),
+        ),
+        request_body(content = Object, description = "All client settings of group, empty object removes them"),
+        responses(
+            (status = 204, description = "Settings replaced"),
+            (status = 400, description = "Settings are not JSON object"),
+            (status = 413, description = "Settings are too large"),
+        )
+    )
+)]
+pub async fn settings_put(
+    collections: Arc<collection::Collections>,
+    group: String,
+    bytes: bytes::Bytes,
+) -> ResponseResult {
+    let settings =
+        match serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(&bytes) {
+            Ok(settings) => settings,
+            Err(e) => {
+                error!("Error in settings JSON: {}", e);
+                return Ok(ApiError::InvalidJson.with_detail(e));
+            }
+        };
+    blocking(move || match collections.set_settings(&group, &settings) {
+        Ok(()) => Ok(response::no_content()),
+        Err(e @ collection::error::Error::SettingsTooLarge(_)) => {
+            Ok(ApiError::SettingsTooLarge.with_detail(e))
+        }
+        Err(e) => Err(Error::new(e)),
+    })
+    .await
+    .map_err(Error::new)?
+}
+
+#[cfg(feature = "shared-positions")]
+#[derive(Debug, serde::Deserialize)]
+struct BatchPosition {
+    collection: usize,
+    path: String,
+    position: f32,
+    timestamp: collection::audio_meta::TimeStamp,
+    #[serde(default)]
+    finished: bool,
+}
+
+#[cfg(feature = "shared-positions")]
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum BatchResult {
+    Created,
+    Ignored,
+    ReadOnly,
+    Error(String),
+}
+
+#[cfg(feature = "shared-positions")]
+pub async fn insert_positions_batch(
+    collections: Arc<collection::Collections>,
+    group: String,
+    account: Account,
+    bytes: bytes::Bytes,
+    compress: bool,
+) -> ResponseResult {
+    let positions = match serde_json::from_slice::<Vec<BatchPosition>>(&bytes) {
+        Ok(positions)
+            if !positions
+                .iter()
+                .all(|p| get_config().is_collection_allowed(p.collection, account.as_deref())) =>
+        {
+            return Ok(ApiError::CollectionNotAllowed.response())
+        }
+        Ok(positions) => positions
+            .into_iter()
+            .map(|p| collection::Position {
+                timestamp: p.timestamp,
+                collection: p.collection,
+                folder: String::new(),
+                file: p.path,
+                folder_finished: p.finished,
+                position: p.position,
+            })
+            .collect(),
+        Err(e) => {
+            error!("Error in positions batch JSON: {}", e);
+            return Ok(ApiError::InvalidJson.with_detail(e));
+        }
+    };
+    let results: Vec<_> = collections
+        .insert_positions_if_newer_async(group, positions)
+        .await
+        .map_err(Error::new)?
+        .into_iter()
+        .map(|res| match res {
+            Ok(_) => BatchResult::Created,
+            Err(collection::error::Error::IgnoredPosition) => BatchResult::Ignored,
+            Err(collection::error::Error::ReadOnlyCollection) => BatchResult::ReadOnly,
+            Err(e) => {
+                error!("Error inserting batch position: {}", e);
+                BatchResult::Error(e.to_string())
+            }
+        })
+        .collect();
+    Ok(json_response(&results, compress))
 }
 
 #[cfg(feature = "shared-positions")]
 pub async fn last_position(
     collections: Arc<collection::Collections>,
     group: String,
+    account: Account,
     compress: bool,
 ) -> ResponseResult {
-    let pos = collections.get_last_position_async(group).await;
+    let folder_filter = FolderFilter::for_account(account.as_deref());
+    let pos = collections
+        .get_last_position_async(group)
+        .await
+        .filter(|p| is_position_visible(p, account.as_deref(), folder_filter.as_ref()));
     Ok(json_response(&pos, compress))
 }
 
 #[cfg(feature = "shared-positions")]
+#[allow(clippy::too_many_arguments)]
 pub async fn folder_position(
     collections: Arc<collection::Collections>,
     group: String,
@@ -90,17 +1024,22 @@ pub async fn folder_position(
     path: String,
     recursive: bool,
     filter: Option<collection::PositionFilter>,
+    folder_filter: Option<FolderFilter>,
     compress: bool,
 ) -> ResponseResult {
     if recursive {
-        let pos = collections
+        let mut pos = collections
             .get_positions_recursive_async(collection, group, path, filter)
             .await;
+        if let Some(folder_filter) = folder_filter {
+            pos.retain(|p| folder_filter.is_allowed(&p.folder));
+        }
         Ok(json_response(&pos, compress))
     } else {
         let pos = collections
             .get_position_async(collection, group, path)
-            .await;
+            .await
+            .filter(|p| folder_filter.map_or(true, |f| f.is_allowed(&p.folder)));
         Ok(json_response(&pos, compress))
     }
 }
@@ -110,47 +1049,386 @@ pub async fn all_positions(
     collections: Arc<collection::Collections>,
     group: String,
     filter: Option<collection::PositionFilter>,
+    account: Account,
     compress: bool,
 ) -> ResponseResult {
-    let pos = collections
+    let folder_filter = FolderFilter::for_account(account.as_deref());
+    let mut pos = collections
         .get_all_positions_for_group_async(group, filter)
         .await;
+    pos.retain(|p| is_position_visible(p, account.as_deref(), folder_filter.as_ref()));
     Ok(json_response(&pos, compress))
 }
 
-pub fn transcodings_list(user_agent: Option<&str>, compress: bool) -> ResponseResult {
-    let transcodings = user_agent
-        .map(Transcodings::for_user_agent)
-        .unwrap_or_default();
-    Ok(json_response(&transcodings, compress))
+/// Position is in collection and folder, which account can access
+#[cfg(feature = "shared-positions")]
+fn is_position_visible(
+    p: &collection::Position,
+    account: Option<&str>,
+    folder_filter: Option<&FolderFilter>,
+) -> bool {
+    get_config().is_collection_allowed(p.collection, account)
+        && folder_filter.map_or(true, |f| f.is_allowed(&p.folder))
 }
 
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/transcodings/",
+        tag = "Collections",
+        params(
+            ("trans-profile" = Option<String>, Query, description = "named transcoding profile"),
+        ),
+        responses(
+            (status = 200, description = "Available transcodings", body = Transcodings),
+            (status = 400, description = "Unknown transcoding profile"),
+        )
+    )
+)]
+pub fn transcodings_list(
+    user_agent: Option<&str>,
+    profile: Option<&str>,
+    if_none_match: Option<IfNoneMatch>,
+    compress: bool,
+) -> ResponseResult {
+    let transcodings = match profile {
+        Some(name) => match get_config().transcoding.profile(name) {
+            Some(profile) => Transcodings::for_profile(profile),
+            None => {
+                error!("Unknown transcoding profile {}", name);
+                return Ok(ApiError::UnknownTranscodingProfile.with_detail(name));
+            }
+        },
+        None => user_agent
+            .map(Transcodings::for_user_agent)
+            .unwrap_or_default(),
+    };
+    Ok(json_response_with_etag(
+        &transcodings,
+        None,
+        if_none_match.as_ref(),
+        compress,
+    ))
+}
+
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/api/authors",
+        tag = "Collections",
+        responses(
+            (status = 200, description = "Authors from all collections", body = Vec<collection::LibraryAuthor>),
+        )
+    )
+)]
+pub async fn authors(
+    collections: Arc<collection::Collections>,
+    account: Account,
+    compress: bool,
+) -> ResponseResult {
+    blocking(move || {
+        let folder_filter = FolderFilter::for_account(account.as_deref());
+        let res = collections.authors(
+            |c| get_config().is_collection_allowed(c, account.as_deref()),
+            |p| folder_filter.as_ref().map_or(true, |f| f.is_allowed(p)),
+        );
+        json_response(&res, compress)
+    })
+    .await
+    .map_err(Error::new)
+}
+
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/api/series/{author}",
+        tag = "Collections",
+        params(
+            ("author" = String, Path, description = "author name"),
+        ),
+        responses(
+            (status = 200, description = "Author's folders grouped by series", body = Vec<collection::LibrarySeries>),
+        )
+    )
+)]
+pub async fn author_series(
+    collections: Arc<collection::Collections>,
+    author: String,
+    account: Account,
+    compress: bool,
+) -> ResponseResult {
+    blocking(move || {
+        let folder_filter = FolderFilter::for_account(account.as_deref());
+        let res = collections.author_series(
+            &author,
+            |c| get_config().is_collection_allowed(c, account.as_deref()),
+            |p| folder_filter.as_ref().map_or(true, |f| f.is_allowed(p)),
+        );
+        json_response(&res, compress)
+    })
+    .await
+    .map_err(Error::new)
+}
+
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/{col_id}/search",
+        tag = "Collections",
+        params(
+            ("col_id" = usize, Path, description = "collection number"),
+            ("q" = String, Query, description = "search query"),
+            ("ord" = Option<String>, Query, description = "folders ordering - a (alphabetical, default) or m (recent first)"),
+            ("group" = Option<String>, Query, description = "group for playback positions"),
+        ),
+        responses(
+            (status = 200, description = "Matching folders and files", body = super::types::SearchResult),
+            (status = 400, description = "Missing query"),
+        )
+    )
+)]
 pub async fn search(
     collection: usize,
     searcher: Search<String>,
     query: String,
     ordering: FoldersOrdering,
     group: Option<String>,
+    folder_filter: Option<FolderFilter>,
     compress: bool,
 ) -> ResponseResult {
     blocking(move || {
-        let res = searcher.search(collection, query, ordering, group);
+        let mut res = searcher.search(collection, query, ordering, group);
+        if let Some(filter) = folder_filter {
+            filter.apply_search(&mut res);
+        }
         json_response(&res, compress)
     })
     .await
     .map_err(Error::new)
 }
 
+const FEED_MIME: &str = "application/rss+xml; charset=utf-8";
+
+pub fn xml_escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&apos;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// RSS feed of recently added folders in collection, base_url is used for links to web client
+pub async fn recent_feed(
+    collection: usize,
+    searcher: Search<String>,
+    base_url: String,
+    folder_filter: Option<FolderFilter>,
+    compress: bool,
+) -> ResponseResult {
+    blocking(move || {
+        let mut res = searcher.recent(collection, None);
+        if let Some(filter) = folder_filter {
+            filter.apply_search(&mut res);
+        }
+        let name = get_config().base_dirs[collection]
+            .file_name()
+            .and_then(OsStr::to_str)
+            .unwrap_or(UNKNOWN_NAME);
+        let mut feed = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<rss version=\"2.0\">
+<channel>
+<title>{title}</title>
+<link>{link}/</link>
+<description>Recently added audiobooks in collection {title}</description>
+<lastBuildDate>{date}</lastBuildDate>
+",
+            title = xml_escape(name),
+            link = xml_escape(&base_url),
+            date = httpdate::fmt_http_date(SystemTime::now())
+        );
+        for folder in res.subfolders {
+            let path = folder.path.to_string_lossy();
+            feed.push_str(&format!(
+                "<item>
+<title>{}</title>
+<link>{}/</link>
+<guid isPermaLink=\"false\">{}/{}</guid>
+<description>{}</description>
+",
+                xml_escape(&folder.name),
+                xml_escape(&base_url),
+                collection,
+                xml_escape(&path),
+                xml_escape(&path),
+            ));
+            if let Some(modified) = folder.modified {
+                feed.push_str(&format!(
+                    "<pubDate>{}</pubDate>\n",
+                    httpdate::fmt_http_date(modified.into())
+                ));
+            }
+            feed.push_str("</item>\n");
+        }
+        feed.push_str("</channel>\n</rss>\n");
+        response::data_response(feed, FEED_MIME.parse().unwrap(), None, None, compress)
+    })
+    .await
+    .map_err(Error::new)
+}
+
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/{col_id}/recent",
+        tag = "Collections",
+        params(
+            ("col_id" = usize, Path, description = "collection number"),
+            ("group" = Option<String>, Query, description = "group for playback positions"),
+        ),
+        responses(
+            (status = 200, description = "Recently modified folders", body = super::types::SearchResult),
+        )
+    )
+)]
 pub async fn recent(
     collection: usize,
     searcher: Search<String>,
     group: Option<String>,
+    folder_filter: Option<FolderFilter>,
     compress: bool,
 ) -> ResponseResult {
     blocking(move || {
-        let res = searcher.recent(collection, group);
+        let mut res = searcher.recent(collection, group);
+        if let Some(filter) = folder_filter {
+            filter.apply_search(&mut res);
+        }
         json_response(&res, compress)
     })
     .await
     .map_err(Error::new)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collection::audio_meta::AudioFolderShort;
+    use std::path::Path;
+
+    fn folder(subfolders: usize) -> AudioFolder {
+        AudioFolder {
+            is_file: false,
+            is_collapsed: false,
+            modified: None,
+            total_time: Some(100),
+            total_size: None,
+            files: vec![],
+            subfolders: (0..subfolders)
+                .map(|i| AudioFolderShort::from_path_simple(Path::new("/"), format!("/{}", i)))
+                .collect(),
+            cover: None,
+            description: None,
+            position: None,
+            tags: None,
+        }
+    }
+
+    #[test]
+    fn test_folder_view() {
+        let view = FolderView {
+            offset: 2,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let value = view.apply(folder(5));
+        assert_eq!(5, value["total_subfolders"]);
+        assert_eq!(0, value["total_files"]);
+        let names: Vec<_> = value["subfolders"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(vec!["2", "3"], names);
+        assert_eq!(100, value["total_time"]);
+
+        let view = FolderView {
+            offset: 10,
+            fields: Some(vec!["subfolders".into(), "cover".into()]),
+            ..Default::default()
+        };
+        let value = view.apply(folder(5));
+        let mut keys: Vec<_> = value.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(
+            vec!["cover", "subfolders", "total_files", "total_subfolders"],
+            keys
+        );
+        assert!(value["subfolders"].as_array().unwrap().is_empty());
+
+        let view = FolderView {
+            fields: Some(vec!["total_time".into()]),
+            ..Default::default()
+        };
+        let value = view.apply(folder(5));
+        assert_eq!(1, value.as_object().unwrap().len());
+    }
+
+    #[test]
+    fn test_api_error_from_error() {
+        use myhy::StatusCode;
+        let err = Error::new(collection::error::Error::ReadOnlyCollection);
+        let resp = ApiError::from_error(&err).map(|e| e.response());
+        assert_eq!(Some(StatusCode::CONFLICT), resp.map(|r| r.status()));
+        let err = Error::new(collection::error::Error::CollectionUnavailable)
+            .context("Cannot rename group");
+        assert!(matches!(
+            ApiError::from_error(&err),
+            Some(ApiError::CollectionUnavailable)
+        ));
+        let err = Error::new(collection::error::Error::IgnoredPosition);
+        assert!(ApiError::from_error(&err).is_none());
+    }
+
+    #[cfg(feature = "shared-positions")]
+    #[test]
+    fn test_position_visible() {
+        use crate::config::{init::init_default_config, FolderFilterConfig};
+        init_default_config();
+        let position = |folder: &str| collection::Position {
+            timestamp: collection::audio_meta::TimeStamp::now(),
+            collection: 0,
+            folder: folder.into(),
+            file: "01.mp3".into(),
+            folder_finished: false,
+            position: 10.0,
+        };
+        let filter = FolderFilter::new(&FolderFilterConfig {
+            allow: vec!["Children".into()],
+            deny: vec![],
+        });
+        assert!(is_position_visible(
+            &position("Children/Book"),
+            Some("kids"),
+            Some(&filter)
+        ));
+        assert!(!is_position_visible(
+            &position("Horror/Book"),
+            Some("kids"),
+            Some(&filter)
+        ));
+        assert!(is_position_visible(&position("Horror/Book"), None, None));
+    }
+}
@@ -0,0 +1,163 @@
+//! Archives (tar, tar.gz, zip) of folder files streamed in folder download response
+use super::types::DownloadFormat;
+use collection::audio_meta::is_audio;
+use futures::prelude::*;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// File in archive - (path, name in archive, size)
+pub type ArchiveEntry = (PathBuf, String, u64);
+pub type ArchiveBytes = Box<dyn Stream<Item = Result<Vec<u8>, io::Error>> + Unpin + Send + Sync>;
+/// Called after each file is added to archive with its path and size
+pub type FileProgress = Arc<dyn Fn(&Path, u64) + Send + Sync>;
+
+/// Common interface of archive formats
+pub trait ArchiveStream: Send {
+    /// Size of whole archive, if it can be known upfront (not for compressed archives)
+    fn size(&self) -> anyhow::Result<Option<u64>>;
+    /// Starts streaming of archive
+    fn into_stream(self: Box<Self>, progress: FileProgress) -> ArchiveBytes;
+}
+
+struct TarArchive {
+    entries: Vec<ArchiveEntry>,
+    /// files are stored with relative paths and directory entries
+    named: bool,
+    gzip: bool,
+}
+
+impl ArchiveStream for TarArchive {
+    fn size(&self) -> anyhow::Result<Option<u64>> {
+        Ok(match (self.gzip, self.named) {
+            (true, _) => None,
+            (false, true) => Some(async_tar::calc_size_with_dirs(
+                self.entries.iter().map(|e| (e.1.as_str(), e.2)),
+            )),
+            (false, false) => Some(async_tar::calc_size(self.entries.iter().map(|e| e.2))),
+        })
+    }
+
+    fn into_stream(self: Box<Self>, progress: FileProgress) -> ArchiveBytes {
+        let tar = if self.named {
+            let files = self.entries.into_iter().map(|e| (e.0, e.1));
+            async_tar::TarStream::tar_iter_named(files).include_directories()
+        } else {
+            async_tar::TarStream::tar_iter(self.entries.into_iter().map(|e| e.0))
+        }
+        .with_progress(move |path, size| progress(path, size));
+        if self.gzip {
+            Box::new(tar.gzipped())
+        } else {
+            Box::new(tar)
+        }
+    }
+}
+
+struct ZipArchive {
+    entries: Vec<ArchiveEntry>,
+    compress: bool,
+}
+
+impl ArchiveStream for ZipArchive {
+    fn size(&self) -> anyhow::Result<Option<u64>> {
+        if self.compress {
+            return Ok(None);
+        }
+        let iter = self
+            .entries
+            .iter()
+            .map(|(path, name, len)| (path, name.as_str(), *len));
+        Ok(Some(async_zip::calc_size(iter)?))
+    }
+
+    fn into_stream(self: Box<Self>, progress: FileProgress) -> ArchiveBytes {
+        let files = self.entries.into_iter().map(|e| (e.0, e.1));
+        let mut zipper = async_zip::Zipper::from_iter(files)
+            .with_progress(move |path, size| progress(path, size));
+        if self.compress {
+            // audio files are already compressed, so only other files are deflated
+            zipper = zipper.with_compression(|name| {
+                if is_audio(name) {
+                    async_zip::Compression::Store
+                } else {
+                    async_zip::Compression::Deflate
+                }
+            });
+        }
+        Box::new(zipper.zipped_stream())
+    }
+}
+
+/// Creates archive of given format, `recursive` archives keep relative paths of files,
+/// `compress` is used only for zip (tar is compressed by choosing tgz format)
+pub fn archive(
+    format: &DownloadFormat,
+    entries: Vec<ArchiveEntry>,
+    recursive: bool,
+    compress: bool,
+) -> Box<dyn ArchiveStream> {
+    match format {
+        DownloadFormat::Tar | DownloadFormat::Tgz => Box::new(TarArchive {
+            entries,
+            named: recursive,
+            gzip: *format == DownloadFormat::Tgz,
+        }),
+        DownloadFormat::Zip => Box::new(ZipArchive { entries, compress }),
+    }
+}
+
+/// Statistics of one archive download, logged when archive stream is finished (or dropped)
+pub struct ArchiveStats {
+    name: String,
+    total_files: usize,
+    files: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl ArchiveStats {
+    pub fn new(name: String, total_files: usize) -> Arc<Self> {
+        Arc::new(ArchiveStats {
+            name,
+            total_files,
+            files: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        })
+    }
+
+    /// Wraps archive stream, so that files and bytes sent are counted
+    pub fn track(self: Arc<Self>, archive: Box<dyn ArchiveStream>) -> ArchiveBytes {
+        let stats = self.clone();
+        let progress: FileProgress = Arc::new(move |path, size| {
+            trace!(
+                "Added {:?} ({} bytes) to archive {}",
+                path,
+                size,
+                stats.name
+            );
+            stats.files.fetch_add(1, Ordering::Relaxed);
+        });
+        Box::new(archive.into_stream(progress).inspect_ok(move |chunk| {
+            self.bytes.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }))
+    }
+}
+
+impl Drop for ArchiveStats {
+    fn drop(&mut self) {
+        let files = self.files.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        if files < self.total_files as u64 {
+            info!(
+                "Download of archive {} interrupted after {} of {} files ({} bytes)",
+                self.name, files, self.total_files, bytes
+            );
+        } else {
+            info!(
+                "Served archive {} with {} files ({} bytes)",
+                self.name, files, bytes
+            );
+        }
+    }
+}
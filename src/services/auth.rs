@@ -1,5 +1,7 @@
-use crate::config::get_config;
+use crate::config::{get_config, CookieSameSite, LoginThrottleConfig};
 use crate::error::{bail, Result};
+use crate::util;
+use arc_swap::ArcSwap;
 use data_encoding::BASE64;
 use futures::{future, prelude::*};
 use myhy::header::SET_COOKIE;
@@ -11,24 +13,28 @@ use myhy::Body;
 use myhy::{Method, Response};
 use ring::rand::{SecureRandom, SystemRandom};
 use ring::{
+    constant_time::verify_slices_are_equal,
     digest::{digest, SHA256},
     hmac,
 };
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::pin::Pin;
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use std::{borrow, time::Duration};
 use thiserror::Error;
 use tokio::time::sleep;
 use url::form_urlencoded;
 
-use myhy::request::GenericRequestWrapper;
+use myhy::request::{BodyError, GenericRequestWrapper};
 use myhy::response::{self, HttpResponse};
 
+/// Secondary account of authenticated client, None is for main shared secret
+pub type Account = Option<String>;
+
 pub enum AuthResult<T, B> {
     Authenticated {
-        #[allow(dead_code)]
         credentials: T,
         request: GenericRequestWrapper<B>,
     },
@@ -42,16 +48,28 @@ pub trait Authenticator<B>: Send + Sync {
     fn authenticate(&self, req: GenericRequestWrapper<B>) -> AuthFuture<Self::Credentials, B>;
 }
 
-#[derive(Clone, Debug)]
 struct Secrets {
     shared_secret: String,
-    server_secret: Vec<u8>,
+    server_keys: ArcSwap<ServerKeys>,
     token_validity_hours: u32,
+    /// setup tokens can be used only once, used tokens are kept until they expire
+    used_setup_tokens: Mutex<HashMap<[u8; 32], u64>>,
+}
+
+/// For how long (in secs) is setup token from pairing QR code valid
+const SETUP_TOKEN_VALIDITY: u64 = 600;
+
+/// Server secrets used to sign tokens, previous secret is still accepted
+/// for a while after secret rotation
+struct ServerKeys {
+    current: Vec<u8>,
+    previous: Option<(Vec<u8>, Instant)>,
 }
 
 #[derive(Clone)]
 pub struct SharedSecretAuthenticator {
     secrets: Arc<Secrets>,
+    throttle: Arc<LoginThrottle>,
 }
 
 impl SharedSecretAuthenticator {
@@ -59,17 +77,109 @@ impl SharedSecretAuthenticator {
         SharedSecretAuthenticator {
             secrets: Arc::new(Secrets {
                 shared_secret,
-                server_secret,
+                server_keys: ArcSwap::from_pointee(ServerKeys {
+                    current: server_secret,
+                    previous: None,
+                }),
                 token_validity_hours,
+                used_setup_tokens: Mutex::new(HashMap::new()),
             }),
+            throttle: Arc::new(LoginThrottle::default()),
+        }
+    }
+}
+
+/// Above this number of tracked addresses records, which are not relevant anymore, are removed
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct FailedLogins {
+    failures: u32,
+    last_failure: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl FailedLogins {
+    fn is_locked(&self, now: Instant) -> bool {
+        self.locked_until.map(|t| t > now).unwrap_or(false)
+    }
+
+    fn is_expired(&self, now: Instant, decay: Duration) -> bool {
+        !self.is_locked(now)
+            && (self.locked_until.is_some() || now.duration_since(self.last_failure) >= decay)
+    }
+}
+
+/// Tracks failed logins per client address to slow down brute force attacks on shared secret
+#[derive(Default)]
+struct LoginThrottle {
+    clients: Mutex<HashMap<IpAddr, FailedLogins>>,
+}
+
+impl LoginThrottle {
+    /// Remaining lockout time, if address is locked out
+    fn locked_out(&self, addr: IpAddr, now: Instant) -> Option<Duration> {
+        let clients = self.clients.lock().expect("Poisoned lock");
+        clients
+            .get(&addr)
+            .filter(|r| r.is_locked(now))
+            .and_then(|r| r.locked_until)
+            .map(|t| t.duration_since(now))
+    }
+
+    /// Records failed login and returns delay, which should be applied before response
+    fn failed(&self, addr: IpAddr, cfg: &LoginThrottleConfig, now: Instant) -> Duration {
+        let decay = Duration::from_secs(cfg.failures_decay.into());
+        let mut clients = self.clients.lock().expect("Poisoned lock");
+        if clients.len() >= MAX_TRACKED_CLIENTS {
+            clients.retain(|_, r| !r.is_expired(now, decay));
+        }
+        let record = clients.entry(addr).or_insert(FailedLogins {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        });
+        if record.is_expired(now, decay) {
+            record.failures = 0;
+            record.locked_until = None;
+        }
+        record.failures += 1;
+        record.last_failure = now;
+
+        if cfg.max_failures > 0 && record.failures >= cfg.max_failures {
+            record.locked_until = Some(now + Duration::from_secs(cfg.lockout.into()));
+            warn!(
+                "Client {} is locked out for {} secs after {} failed logins",
+                addr, cfg.lockout, record.failures
+            );
+        } else if record.failures > 1 {
+            warn!(
+                "Repeated failed login from client {} ({} failures)",
+                addr, record.failures
+            );
         }
+
+        let delay = u64::from(cfg.initial_delay)
+            .saturating_mul(1 << (record.failures - 1).min(16))
+            .min(cfg.max_delay.into());
+        Duration::from_millis(delay)
+    }
+
+    fn succeeded(&self, addr: IpAddr) {
+        self.clients.lock().expect("Poisoned lock").remove(&addr);
+    }
+}
+
+/// Delay before response to failed login, it grows with repeated failures from same address
+fn failed_login_delay(throttle: &LoginThrottle, client_addr: Option<IpAddr>) -> Duration {
+    match client_addr {
+        Some(addr) => throttle.failed(addr, &get_config().login_throttle, Instant::now()),
+        None => Duration::from_millis(get_config().login_throttle.initial_delay.into()),
     }
 }
 
-const COOKIE_NAME: &str = "audioserve_token";
 const COOKIE_DELETE_DATE: &str = "Thu, 01 Jan 1970 00:00:00 GMT";
 
-fn deny<B>(req: &GenericRequestWrapper<B>) -> Result<AuthResult<(), B>>
+fn deny<B>(req: &GenericRequestWrapper<B>) -> Result<AuthResult<Account, B>>
 where
     B: Body + Send + Sync + 'static + Unpin,
 {
@@ -80,14 +190,14 @@ where
     if req
         .headers()
         .typed_get::<Cookie>()
-        .map(|c| c.get(COOKIE_NAME).is_some())
+        .map(|c| c.get(&get_config().cookie.name).is_some())
         .unwrap_or(false)
     {
         resp.headers_mut().append(
             SET_COOKIE,
             HeaderValue::from_str(&format!(
                 "{}=; Expires={}; {}",
-                COOKIE_NAME,
+                get_config().cookie.name,
                 COOKIE_DELETE_DATE,
                 cookie_params(req)
             ))
@@ -98,15 +208,64 @@ where
     Ok(AuthResult::Rejected(resp))
 }
 
-fn cookie_params<B>(req: &GenericRequestWrapper<B>) -> &'static str
+fn cookie_params<B>(req: &GenericRequestWrapper<B>) -> String
 where
     B: Body + Send + Sync + 'static + Unpin,
 {
-    if req.is_https() && req.is_cors_enabled() {
-        "SameSite=None; Secure"
-    } else {
-        "SameSite=Lax"
+    let cfg = &get_config().cookie;
+    let secure = cfg.secure.unwrap_or_else(|| req.is_https());
+    let same_site = cfg.same_site.unwrap_or({
+        if secure && req.is_cors_enabled() {
+            CookieSameSite::None
+        } else {
+            CookieSameSite::Lax
+        }
+    });
+    let mut params = format!("SameSite={}", same_site);
+    if secure || same_site == CookieSameSite::None {
+        params.push_str("; Secure");
+    }
+    if let Some(domain) = &cfg.domain {
+        params.push_str("; Domain=");
+        params.push_str(domain);
     }
+    params
+}
+
+/// Token from Authorization header or from cookie
+pub fn request_token(headers: &myhy::header::HeaderMap) -> Option<String> {
+    headers
+        .typed_get::<Authorization<Bearer>>()
+        .map(|a| a.0.token().to_owned())
+        .or_else(|| {
+            headers.typed_get::<Cookie>().and_then(|c| {
+                c.get(&get_config().cookie.name)
+                    .map(borrow::ToOwned::to_owned)
+            })
+        })
+}
+
+/// Response with new token in body and also in cookie
+fn token_response<B>(req: &GenericRequestWrapper<B>, token: String) -> HttpResponse
+where
+    B: Body + Send + Sync + 'static + Unpin,
+{
+    Response::builder()
+        .typed_header(ContentType::text())
+        .typed_header(ContentLength(token.len() as u64))
+        .header(
+            SET_COOKIE,
+            format!(
+                "{}={}; Max-Age={}; {}",
+                get_config().cookie.name,
+                token,
+                get_config().token_validity_hours * 3600,
+                cookie_params(req)
+            )
+            .as_str(),
+        )
+        .body(full_body(token))
+        .unwrap()
 }
 
 impl<B> Authenticator<B> for SharedSecretAuthenticator
@@ -115,15 +274,37 @@ where
     B::Error: Into<crate::error::Error> + Send + Sync + 'static,
     B::Data: Send,
 {
-    type Credentials = ();
-    fn authenticate(&self, mut req: GenericRequestWrapper<B>) -> AuthFuture<(), B> {
+    type Credentials = Account;
+    fn authenticate(&self, mut req: GenericRequestWrapper<B>) -> AuthFuture<Account, B> {
+        #[cfg(feature = "subsonic")]
+        if super::subsonic::is_subsonic_request(req.path()) {
+            return self.authenticate_subsonic(req);
+        }
         // this is part where client can authenticate itself and get token
         if req.method() == Method::POST && req.path() == "/authenticate" {
             debug!("Authentication request");
             let auth = self.secrets.clone();
-            return Box::pin(async move {
+            let throttle = self.throttle.clone();
+            let client_addr = req.remote_addr().map(|a| *a.as_ref());
+            if let Some(remaining) =
+                client_addr.and_then(|addr| throttle.locked_out(addr, Instant::now()))
+            {
+                warn!(
+                    "Login attempt from locked out client {:?}, remaining lockout {} secs",
+                    client_addr,
+                    remaining.as_secs()
+                );
+                return Box::pin(future::ok(AuthResult::Rejected(
+                    response::too_many_requests(),
+                )));
+            }
+            Box::pin(async move {
                 match req.body_bytes().await {
-                    Err(e) => bail!(e),
+                    Err(BodyError::Read(e)) => bail!(e),
+                    Err(e) => {
+                        error!("Authentication request body exceeds limits");
+                        Ok(AuthResult::Rejected(e.response()))
+                    }
                     Ok(b) => {
                         let content_type = req
                             .headers()
@@ -151,28 +332,33 @@ where
                             error!("Content-Type header is missing");
                             return deny(&req);
                         };
-                        if let Some(secret) = params.get("secret") {
+                        if let Some(setup_token) = params.get("setup_token") {
+                            debug!("Authenticating with setup token");
+                            if let Some(account) = auth.setup_token_account(setup_token) {
+                                debug!("Client paired, account {:?}", account);
+                                if let Some(addr) = client_addr {
+                                    throttle.succeeded(addr);
+                                }
+                                let token = auth.new_auth_token(account);
+                                Ok(AuthResult::LoggedIn(token_response(&req, token)))
+                            } else {
+                                error!(
+                                    "Invalid authentication: invalid or used setup token, client: {:?}",
+                                    req.remote_addr()
+                                );
+                                sleep(failed_login_delay(&throttle, client_addr)).await;
+                                deny(&req)
+                            }
+                        } else if let Some(secret) = params.get("secret") {
                             debug!("Authenticating user");
-                            if auth.auth_token_ok(secret) {
-                                debug!("Authentication success");
-
-                                let token = auth.new_auth_token();
-                                let resp = Response::builder()
-                                    .typed_header(ContentType::text())
-                                    .typed_header(ContentLength(token.len() as u64))
-                                    .header(
-                                        SET_COOKIE,
-                                        format!(
-                                            "{}={}; Max-Age={}; {}",
-                                            COOKIE_NAME,
-                                            token,
-                                            get_config().token_validity_hours * 3600,
-                                            cookie_params(&req)
-                                        )
-                                        .as_str(),
-                                    );
-
-                                Ok(AuthResult::LoggedIn(resp.body(full_body(token)).unwrap()))
+                            if let Some(account) = auth.auth_token_account(secret) {
+                                debug!("Authentication success, account {:?}", account);
+                                if let Some(addr) = client_addr {
+                                    throttle.succeeded(addr);
+                                }
+
+                                let token = auth.new_auth_token(account);
+                                Ok(AuthResult::LoggedIn(token_response(&req, token)))
                             } else {
                                 error!(
                                     "Invalid authentication: invalid shared secret, client: {:?}",
@@ -180,7 +366,8 @@ where
                                 );
                                 // Let's not return failure immediately, because somebody is using wrong shared secret
                                 // Legitimate user can wait a bit, but for brute force attack it can be advantage not to reply quickly
-                                sleep(Duration::from_millis(500)).await;
+                                // and delay grows with repeated failures from same address
+                                sleep(failed_login_delay(&throttle, client_addr)).await;
                                 deny(&req)
                             }
                         } else {
@@ -192,19 +379,10 @@ where
                         }
                     }
                 }
-            });
+            })
         } else {
             // And in this part we check token
-            let mut token = req
-                .headers()
-                .typed_get::<Authorization<Bearer>>()
-                .map(|a| a.0.token().to_owned());
-            if token.is_none() {
-                token = req
-                    .headers()
-                    .typed_get::<Cookie>()
-                    .and_then(|c| c.get(COOKIE_NAME).map(borrow::ToOwned::to_owned));
-            }
+            let token = request_token(req.headers());
 
             if token.is_none() {
                 error!(
@@ -214,25 +392,164 @@ where
                 );
                 return Box::pin(future::ready(deny(&req)));
             }
-            if !self.secrets.token_ok(&token.unwrap()) {
+            let account = match self.secrets.token_account(&token.unwrap()) {
+                Some(account) => account,
+                None => {
+                    error!(
+                        "Invalid access: invalid token on path {}, client: {:?}",
+                        req.path(),
+                        req.remote_addr()
+                    );
+                    return Box::pin(future::ready(deny(&req)));
+                }
+            };
+            // revocation of all tokens is done here, as it needs access to server secret
+            if req.method() == Method::POST && req.path() == "/api/auth/revoke-all" {
+                if account.is_some() {
+                    return Box::pin(future::ok(AuthResult::Rejected(response::forbidden())));
+                }
+                let auth = self.secrets.clone();
+                return Box::pin(async move {
+                    let grace = Duration::from_secs(get_config().token_revoke_grace.into());
+                    auth.rotate_server_secret(grace)?;
+                    warn!(
+                        "All tokens revoked by client {:?}, old tokens are valid for next {} secs",
+                        req.remote_addr(),
+                        grace.as_secs()
+                    );
+                    let token = auth.new_auth_token(None);
+                    Ok(AuthResult::LoggedIn(token_response(&req, token)))
+                });
+            }
+            // setup token for pairing is also signed by server secret
+            if req.method() == Method::GET && req.path() == "/api/pairing-qr" {
+                if account.is_some() {
+                    return Box::pin(future::ok(AuthResult::Rejected(response::forbidden())));
+                }
+                let pairing_account = req.params().get_string("account");
+                if let Some(ref name) = pairing_account {
+                    if !get_config().accounts.contains_key(name) {
+                        error!("Pairing for unknown account {}", name);
+                        return Box::pin(future::ok(AuthResult::Rejected(response::bad_request())));
+                    }
+                }
+                let (setup_token, expires) = self.secrets.new_setup_token(pairing_account);
+                let resp = super::pairing::pairing_qr_response(
+                    &super::server_url(&req),
+                    get_config().url_path_prefix.as_deref().unwrap_or_default(),
+                    &setup_token,
+                    expires,
+                )
+                .map(AuthResult::Rejected);
+                return Box::pin(future::ready(resp));
+            }
+            // If everything is ok we return credentials (account) and we return back request
+            Box::pin(future::ok(AuthResult::Authenticated {
+                request: req,
+                credentials: account,
+            }))
+        }
+    }
+}
+
+#[cfg(feature = "subsonic")]
+impl SharedSecretAuthenticator {
+    /// Subsonic clients do not use tokens, they send credentials with every request
+    fn authenticate_subsonic<B>(&self, req: GenericRequestWrapper<B>) -> AuthFuture<Account, B>
+    where
+        B: Body + Send + Sync + 'static + Unpin,
+    {
+        use super::subsonic::{error_response, Credentials, ErrorCode};
+        let reject = |code, message| {
+            Box::pin(future::ok(AuthResult::Rejected(error_response(
+                &req.params(),
+                code,
+                message,
+            ))))
+        };
+        let client_addr = req.remote_addr().map(|a| *a.as_ref());
+        if client_addr
+            .and_then(|addr| self.throttle.locked_out(addr, Instant::now()))
+            .is_some()
+        {
+            warn!(
+                "Subsonic login attempt from locked out client {:?}",
+                client_addr
+            );
+            return reject(ErrorCode::WrongCredentials, "Too many failed logins");
+        }
+        let (user, credentials) = match Credentials::from_params(&req.params()) {
+            Some(c) => c,
+            None => {
+                error!(
+                    "Invalid Subsonic authentication: missing credentials, client: {:?}",
+                    req.remote_addr()
+                );
+                return reject(
+                    ErrorCode::MissingParameter,
+                    "Required authentication parameters are missing",
+                );
+            }
+        };
+        match self.secrets.subsonic_account(&user, &credentials) {
+            Some(account) => {
+                if let Some(addr) = client_addr {
+                    self.throttle.succeeded(addr);
+                }
+                Box::pin(future::ok(AuthResult::Authenticated {
+                    request: req,
+                    credentials: account,
+                }))
+            }
+            None => {
                 error!(
-                    "Invalid access: invalid token on path {}, client: {:?}",
-                    req.path(),
+                    "Invalid Subsonic authentication for user {}, client: {:?}",
+                    user,
                     req.remote_addr()
                 );
-                return Box::pin(future::ready(deny(&req)));
+                let delay = failed_login_delay(&self.throttle, client_addr);
+                let resp = error_response(
+                    &req.params(),
+                    ErrorCode::WrongCredentials,
+                    "Wrong username or password",
+                );
+                Box::pin(async move {
+                    sleep(delay).await;
+                    Ok(AuthResult::Rejected(resp))
+                })
             }
         }
-        // If everything is ok we return credentials (in this case they are just unit type) and we return back request
-        Box::pin(future::ok(AuthResult::Authenticated {
-            request: req,
-            credentials: (),
-        }))
     }
 }
 
 impl Secrets {
-    fn auth_token_ok(&self, token: &str) -> bool {
+    /// Account names are used as Subsonic user names, any other name can be used with main shared secret
+    #[cfg(feature = "subsonic")]
+    fn subsonic_account(
+        &self,
+        user: &str,
+        credentials: &super::subsonic::Credentials,
+    ) -> Option<Account> {
+        match get_config().accounts.get(user) {
+            Some(secret) => credentials.matches(secret).then(|| Some(user.to_string())),
+            None => credentials.matches(&self.shared_secret).then_some(None),
+        }
+    }
+
+    /// Checks client secret against shared secret and then against secrets of accounts,
+    /// returns matching account
+    fn auth_token_account(&self, token: &str) -> Option<Account> {
+        if Self::auth_token_ok(&self.shared_secret, token) {
+            return Some(None);
+        }
+        get_config()
+            .accounts
+            .iter()
+            .find(|(_, secret)| Self::auth_token_ok(secret, token))
+            .map(|(name, _)| Some(name.clone()))
+    }
+
+    fn auth_token_ok(shared_secret: &str, token: &str) -> bool {
         let parts = token
             .split('|')
             .filter_map(|s| match BASE64.decode(s.as_bytes()) {
@@ -251,27 +568,102 @@ impl Secrets {
                 error!("Random salt must be 32 bytes");
                 return false;
             }
-            let mut hash2 = self.shared_secret.clone().into_bytes();
+            let mut hash2 = shared_secret.as_bytes().to_vec();
             let hash = &parts[1];
             hash2.extend(&parts[0]);
             let hash2 = digest(&SHA256, &hash2);
 
-            return hash2.as_ref() == &hash[..];
+            return verify_slices_are_equal(hash2.as_ref(), &hash[..]).is_ok();
         } else {
             error!("Incorrectly formed login token - {} parts", parts.len())
         }
         false
     }
-    fn new_auth_token(&self) -> String {
-        Token::new(self.token_validity_hours, &self.server_secret).into()
+    fn new_auth_token(&self, account: Account) -> String {
+        Token::new(
+            self.token_validity_hours,
+            &self.server_keys.load().current,
+            account,
+        )
+        .into()
+    }
+
+    fn setup_key(server_key: &[u8]) -> Vec<u8> {
+        // different key, so setup token cannot be used as normal token and vice versa
+        [server_key, b"|setup"].concat()
+    }
+
+    /// Short lived token for client pairing, which can be exchanged for normal token, with its expiration time
+    fn new_setup_token(&self, account: Account) -> (String, u64) {
+        let key = Self::setup_key(&self.server_keys.load().current);
+        let token = Token::with_validity(SETUP_TOKEN_VALIDITY, &key, account);
+        let expires = token.validity();
+        (token.into(), expires)
+    }
+
+    /// Account of valid setup token, token is then marked as used
+    fn setup_token_account(&self, token: &str) -> Option<Account> {
+        let token = token.parse::<Token>().ok()?;
+        let keys = self.server_keys.load();
+        if !token.is_valid(&Self::setup_key(&keys.current)) {
+            return None;
+        }
+        if let Some(account) = token.account.as_ref() {
+            if !get_config().accounts.contains_key(account) {
+                warn!("Setup token for unknown account {}", account);
+                return None;
+            }
+        }
+        let mut used = self.used_setup_tokens.lock().expect("Poisoned lock");
+        let now = now();
+        used.retain(|_, expires| *expires > now);
+        if used.insert(token.random, token.validity()).is_some() {
+            warn!("Setup token was already used");
+            return None;
+        }
+        Some(token.account)
+    }
+
+    /// Generates new server secret, which invalidates all existing tokens,
+    /// but previous secret is still accepted during grace period
+    fn rotate_server_secret(&self, grace: Duration) -> Result<()> {
+        let mut new_secret = vec![0u8; 32];
+        SystemRandom::new()
+            .fill(&mut new_secret)
+            .map_err(|_| crate::error::Error::msg("Error when generating secret"))?;
+        util::write_secret_file(&get_config().secret_file, &new_secret)?;
+        let old_secret = self.server_keys.load().current.clone();
+        self.server_keys.store(Arc::new(ServerKeys {
+            current: new_secret,
+            previous: Some((old_secret, Instant::now() + grace)),
+        }));
+        Ok(())
     }
 
-    fn token_ok(&self, token: &str) -> bool {
+    fn is_token_valid(&self, token: &Token) -> bool {
+        let keys = self.server_keys.load();
+        token.is_valid(&keys.current)
+            || keys
+                .previous
+                .as_ref()
+                .map(|(secret, until)| Instant::now() < *until && token.is_valid(secret))
+                .unwrap_or(false)
+    }
+
+    /// Account of valid token, token for account, which is not configured anymore, is invalid
+    fn token_account(&self, token: &str) -> Option<Account> {
         match token.parse::<Token>() {
-            Ok(token) => token.is_valid(&self.server_secret),
+            Ok(token) if self.is_token_valid(&token) => match token.account {
+                Some(account) if !get_config().accounts.contains_key(&account) => {
+                    warn!("Token for unknown account {}", account);
+                    None
+                }
+                account => Some(account),
+            },
+            Ok(_) => None,
             Err(e) => {
                 warn!("Invalid token: {}", e);
-                false
+                None
             }
         }
     }
@@ -282,13 +674,12 @@ struct Token {
     random: [u8; 32],
     validity: [u8; 8],
     signature: [u8; 32],
+    /// account is appended after signature, token without account is for main shared secret
+    account: Account,
 }
 
-fn prepare_data(r: &[u8; 32], v: [u8; 8]) -> [u8; 40] {
-    let mut to_sign = [0u8; 40];
-    to_sign[0..32].copy_from_slice(&r[..]);
-    to_sign[32..40].copy_from_slice(&v[..]);
-    to_sign
+fn prepare_data(r: &[u8; 32], v: [u8; 8], account: Option<&str>) -> Vec<u8> {
+    [&r[..], &v[..], account.unwrap_or_default().as_bytes()].concat()
 }
 
 fn now() -> u64 {
@@ -299,14 +690,18 @@ fn now() -> u64 {
 }
 
 impl Token {
-    fn new(token_validity_hours: u32, secret: &[u8]) -> Self {
+    fn new(token_validity_hours: u32, secret: &[u8], account: Account) -> Self {
+        Token::with_validity(u64::from(token_validity_hours) * 3600, secret, account)
+    }
+
+    fn with_validity(validity_secs: u64, secret: &[u8], account: Account) -> Self {
         let mut random = [0u8; 32];
         let rng = SystemRandom::new();
         rng.fill(&mut random)
             .expect("Cannot generate random number");
-        let validity: u64 = now() + u64::from(token_validity_hours) * 3600;
+        let validity: u64 = now() + validity_secs;
         let validity: [u8; 8] = validity.to_be_bytes();
-        let to_sign = prepare_data(&random, validity);
+        let to_sign = prepare_data(&random, validity, account.as_deref());
         let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
         let sig = hmac::sign(&key, &to_sign);
         let slice = sig.as_ref();
@@ -318,12 +713,13 @@ impl Token {
             random,
             validity,
             signature,
+            account,
         }
     }
 
     fn is_valid(&self, secret: &[u8]) -> bool {
         let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
-        let data = prepare_data(&self.random, self.validity);
+        let data = prepare_data(&self.random, self.validity, self.account.as_deref());
         if hmac::verify(&key, &data, &self.signature).is_err() {
             return false;
         };
@@ -339,7 +735,13 @@ impl Token {
 
 impl From<Token> for String {
     fn from(token: Token) -> String {
-        let data = [&token.random[..], &token.validity[..], &token.signature[..]].concat();
+        let data = [
+            &token.random[..],
+            &token.validity[..],
+            &token.signature[..],
+            token.account.unwrap_or_default().as_bytes(),
+        ]
+        .concat();
         BASE64.encode(&data)
     }
 }
@@ -351,6 +753,9 @@ enum TokenError {
 
     #[error("Invalid token encoding")]
     InvalidEncoding(#[from] ::data_encoding::DecodeError),
+
+    #[error("Invalid account in token")]
+    AccountEncoding(#[from] ::std::string::FromUtf8Error),
 }
 
 impl ::std::str::FromStr for Token {
@@ -358,7 +763,7 @@ impl ::std::str::FromStr for Token {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let bytes = BASE64.decode(s.as_bytes())?;
-        if bytes.len() != 72 {
+        if bytes.len() < 72 {
             return Err(TokenError::InvalidSize);
         };
         let mut random = [0u8; 32];
@@ -368,11 +773,17 @@ impl ::std::str::FromStr for Token {
         random.copy_from_slice(&bytes[0..32]);
         validity.copy_from_slice(&bytes[32..40]);
         signature.copy_from_slice(&bytes[40..72]);
+        let account = if bytes.len() > 72 {
+            Some(String::from_utf8(bytes[72..].to_vec())?)
+        } else {
+            None
+        };
 
         Ok(Token {
             random,
             validity,
             signature,
+            account,
         })
     }
 }
@@ -389,7 +800,7 @@ mod tests {
 
     #[test]
     fn test_token() {
-        let token = Token::new(24, b"my big secret");
+        let token = Token::new(24, b"my big secret", None);
         assert!(token.is_valid(b"my big secret"));
         let orig_token = token.clone();
         let serialized_token: String = token.into();
@@ -401,6 +812,109 @@ mod tests {
         assert!(new_token.validity() - now() <= 24 * 3600);
     }
 
+    #[test]
+    fn test_token_with_account() {
+        let token = Token::new(24, b"my big secret", Some("kids".into()));
+        let serialized_token: String = token.clone().into();
+        let new_token: Token = serialized_token.parse().unwrap();
+        assert_eq!(token, new_token);
+        assert_eq!(Some("kids"), new_token.account.as_deref());
+        assert!(new_token.is_valid(b"my big secret"));
+
+        let mut bytes = BASE64.decode(serialized_token.as_bytes()).unwrap();
+        bytes.truncate(72);
+        bytes.extend(b"admin");
+        let forged: Token = BASE64.encode(&bytes).parse().unwrap();
+        assert!(!forged.is_valid(b"my big secret"));
+    }
+
+    #[test]
+    fn test_previous_server_secret() {
+        let aut = SharedSecretAuthenticator::new("shared".into(), b"old secret".to_vec(), 24);
+        let secrets = aut.secrets;
+        let old_token = Token::new(24, b"old secret", None);
+        assert!(secrets.is_token_valid(&old_token));
+
+        secrets.server_keys.store(Arc::new(ServerKeys {
+            current: b"new secret".to_vec(),
+            previous: Some((
+                b"old secret".to_vec(),
+                Instant::now() + Duration::from_secs(60),
+            )),
+        }));
+        assert!(secrets.is_token_valid(&old_token));
+        assert!(secrets.is_token_valid(&Token::new(24, b"new secret", None)));
+
+        secrets.server_keys.store(Arc::new(ServerKeys {
+            current: b"new secret".to_vec(),
+            previous: Some((b"old secret".to_vec(), Instant::now())),
+        }));
+        assert!(!secrets.is_token_valid(&old_token));
+    }
+
+    #[test]
+    fn test_setup_token() {
+        init_default_config();
+        let aut = SharedSecretAuthenticator::new("shared".into(), b"server secret".to_vec(), 24);
+        let secrets = aut.secrets;
+        let (token, expires) = secrets.new_setup_token(None);
+        assert!(expires - now() <= SETUP_TOKEN_VALIDITY);
+        let parsed: Token = token.parse().unwrap();
+        // setup token cannot be used as normal token
+        assert!(!secrets.is_token_valid(&parsed));
+        assert_eq!(Some(None), secrets.setup_token_account(&token));
+        // can be used only once
+        assert_eq!(None, secrets.setup_token_account(&token));
+
+        // normal token cannot be used as setup token
+        let token: String = Token::new(24, b"server secret", None).into();
+        assert_eq!(None, secrets.setup_token_account(&token));
+    }
+
+    #[test]
+    fn test_login_throttle() {
+        let cfg = LoginThrottleConfig {
+            max_failures: 3,
+            lockout: 60,
+            failures_decay: 600,
+            initial_delay: 100,
+            max_delay: 300,
+        };
+        let throttle = LoginThrottle::default();
+        let addr: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let now = Instant::now();
+        assert_eq!(Duration::from_millis(100), throttle.failed(addr, &cfg, now));
+        assert_eq!(Duration::from_millis(200), throttle.failed(addr, &cfg, now));
+        assert!(throttle.locked_out(addr, now).is_none());
+        assert_eq!(Duration::from_millis(300), throttle.failed(addr, &cfg, now));
+        assert_eq!(
+            Some(Duration::from_secs(60)),
+            throttle.locked_out(addr, now)
+        );
+        assert!(throttle.locked_out(other, now).is_none());
+
+        // lockout expires and failures are forgotten
+        let later = now + Duration::from_secs(61);
+        assert!(throttle.locked_out(addr, later).is_none());
+        assert_eq!(
+            Duration::from_millis(100),
+            throttle.failed(addr, &cfg, later)
+        );
+
+        // failures decay
+        let much_later = later + Duration::from_secs(600);
+        assert_eq!(
+            Duration::from_millis(100),
+            throttle.failed(addr, &cfg, much_later)
+        );
+        throttle.succeeded(addr);
+        assert_eq!(
+            Duration::from_millis(100),
+            throttle.failed(addr, &cfg, much_later)
+        );
+    }
+
     fn build_request(body: impl Into<Bytes>, json: bool) -> GenericRequestWrapper<Full<Bytes>> {
         let b = Full::new(body.into());
         let req = Request::builder()
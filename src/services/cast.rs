@@ -0,0 +1,299 @@
+//! Casting devices (like Chromecast) load media by themselves, so they cannot authenticate -
+//! instead media URLs for casting are signed and signature is valid only for limited time.
+
+use super::auth::Account;
+use super::types::CastInfo;
+use crate::config::{get_config, GUEST_ACCOUNT};
+use crate::error::Error;
+use collection::{parse_chapter_path, AudioFile, Collections, FoldersOrdering};
+use data_encoding::BASE64URL_NOPAD;
+use myhy::header::{ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS};
+use myhy::headers::HeaderValue;
+use myhy::request::QueryParams;
+use myhy::response::{self, json_response, HttpResponse, ResponseResult};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use ring::hmac;
+use ring::rand::SystemRandom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::spawn_blocking as blocking;
+use url::form_urlencoded;
+
+/// Prefix of signed media URLs
+pub const CAST_PREFIX: &str = "/cast/";
+/// Prefix of signed cover URLs
+pub const CAST_COVER_PREFIX: &str = "/cast-cover/";
+
+/// How long signed URL is valid (in secs), must be enough for playback of whole file
+const CAST_URL_VALIDITY: u64 = 12 * 3600;
+
+/// Characters escaped in URL path segment
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+lazy_static! {
+    /// Random key, so signed URLs are invalidated by server restart
+    static ref CAST_KEY: hmac::Key = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+        .expect("Cannot generate cast key");
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Invalid system time")
+        .as_secs()
+}
+
+fn signed_data(path: &str, account: Option<&str>, expires: u64) -> String {
+    // main shared secret and accounts must be distinguished, as guest account has empty name
+    let account = match account {
+        None => "*".to_string(),
+        Some(a) => format!("@{}", a),
+    };
+    format!("{}\n{}\n{}", expires, account, path)
+}
+
+/// Query string with signature for path (as received by server, e.g. without url_path_prefix)
+pub fn signed_query(path: &str, account: Option<&str>) -> String {
+    let expires = now() + CAST_URL_VALIDITY;
+    let sig = hmac::sign(&CAST_KEY, signed_data(path, account, expires).as_bytes());
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    query
+        .append_pair("exp", &expires.to_string())
+        .append_pair("sig", &BASE64URL_NOPAD.encode(sig.as_ref()));
+    if let Some(account) = account {
+        query.append_pair("acc", account);
+    }
+    query.finish()
+}
+
+/// Account for which the path was signed, if signature is valid and not expired
+pub fn signed_account(path: &str, params: &QueryParams) -> Option<Account> {
+    let expires: u64 = params.get("exp")?.parse().ok()?;
+    if expires <= now() {
+        debug!("Signed cast URL expired");
+        return None;
+    }
+    let sig = BASE64URL_NOPAD.decode(params.get("sig")?.as_bytes()).ok()?;
+    let account = params.get("acc").map(|a| a.to_string());
+    hmac::verify(
+        &CAST_KEY,
+        signed_data(path, account.as_deref(), expires).as_bytes(),
+        &sig,
+    )
+    .ok()?;
+    match account {
+        Some(account)
+            if account != GUEST_ACCOUNT && !get_config().accounts.contains_key(&account) =>
+        {
+            warn!("Signed cast URL for unknown account {}", account);
+            None
+        }
+        account => Some(account),
+    }
+}
+
+pub fn encode_path(path: &Path) -> String {
+    path.to_string_lossy()
+        .split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Signed URL for path, prefix must not need URL encoding (e.g. `/cast/0/`)
+pub fn signed_url(base_url: &str, prefix: &str, path: &Path, account: Option<&str>) -> String {
+    let server_path = format!("{}{}", prefix, path.to_string_lossy());
+    format!(
+        "{}{}{}?{}",
+        base_url,
+        prefix,
+        encode_path(path),
+        signed_query(&server_path, account)
+    )
+}
+
+/// Casting device loads media from different origin
+pub fn add_cast_headers(mut resp: HttpResponse) -> HttpResponse {
+    let headers = resp.headers_mut();
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    headers.insert(
+        ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static("Content-Length, Content-Range, Accept-Ranges"),
+    );
+    resp
+}
+
+fn find_file(folder_files: Vec<AudioFile>, path: &Path) -> Option<AudioFile> {
+    folder_files.into_iter().find(|f| f.path == path)
+}
+
+/// Metadata for casting device with signed URLs for media and cover
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/api/cast/{col_id}/{path}",
+        tag = "Collections",
+        params(
+            ("col_id" = usize, Path, description = "collection number"),
+            ("path" = String, Path, description = "path in collection"),
+        ),
+        responses(
+            (status = 200, description = "Cast metadata with signed URLs", body = CastInfo),
+            (status = 404, description = "Audio file not found"),
+        )
+    )
+)]
+pub async fn cast_info(
+    collection: usize,
+    path: PathBuf,
+    collections: Arc<Collections>,
+    account: Account,
+    base_url: String,
+    transcoding: super::TranscodingDetails,
+    compress: bool,
+) -> ResponseResult {
+    let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let file_path = path.clone();
+    let folder = match blocking(move || {
+        collections.list_dir(collection, parent, FoldersOrdering::Alphabetical, None)
+    })
+    .await
+    .map_err(Error::new)?
+    {
+        Ok(folder) => folder,
+        Err(e) => {
+            error!("Cannot list folder for cast: {}", e);
+            return Ok(response::not_found());
+        }
+    };
+    let cover = folder.cover;
+    let album = folder
+        .tags
+        .as_ref()
+        .and_then(|t| t.get("album").cloned())
+        .or_else(|| {
+            path.parent()
+                .and_then(Path::file_name)
+                .map(|n| n.to_string_lossy().into())
+        });
+    let file = match find_file(folder.files, &file_path) {
+        Some(file) => file,
+        None => {
+            error!("File {:?} for cast not found", file_path);
+            return Ok(response::not_found());
+        }
+    };
+
+    let (real_path, span) = parse_chapter_path(&file_path);
+    let native = span.is_none() && super::files::CAST_NATIVE_MIMES.contains(&file.mime.as_str());
+    let mime = if native {
+        file.mime.clone()
+    } else {
+        super::transcode::ChosenTranscoding::for_cast()
+            .format
+            .mime()
+            .to_string()
+    };
+
+    #[cfg(feature = "transcoding-cache")]
+    if !native && !get_config().transcoding.cache.disabled {
+        // start transcoding early, so file is hopefully ready, when casting device requests it
+        let full_path = get_config()
+            .base_dir_for(collection, &real_path)
+            .join(real_path);
+        tokio::spawn(async move {
+            if let Err(e) = super::files::prepare_cast_file(full_path, span, transcoding).await {
+                error!("Error when preparing file for casting: {}", e)
+            }
+        });
+    }
+    #[cfg(not(feature = "transcoding-cache"))]
+    let _ = (real_path, transcoding);
+
+    let title = file
+        .meta
+        .as_ref()
+        .and_then(|m| m.tags.as_ref())
+        .and_then(|t| t.get("title").cloned())
+        .unwrap_or_else(|| file.name.to_string());
+    let info = CastInfo {
+        title,
+        album,
+        duration: file.meta.as_ref().map(|m| m.duration),
+        mime,
+        media_url: signed_url(
+            &base_url,
+            &format!("{}{}/", CAST_PREFIX, collection),
+            &file_path,
+            account.as_deref(),
+        ),
+        cover_url: cover.map(|c| {
+            signed_url(
+                &base_url,
+                &format!("{}{}/", CAST_COVER_PREFIX, collection),
+                &c.path,
+                account.as_deref(),
+            )
+        }),
+    };
+    Ok(json_response(&info, compress))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init::init_default_config;
+    use bytes::Bytes;
+    use http_body_util::Full;
+    use myhy::request::GenericRequestWrapper;
+    use myhy::Request;
+
+    fn params_for(query: &str, f: impl FnOnce(&QueryParams)) {
+        let req = Request::builder()
+            .uri(format!("/cast/0/a?{}", query))
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let req = GenericRequestWrapper::new(req).unwrap();
+        f(&req.params())
+    }
+
+    #[test]
+    fn test_signed_query() {
+        init_default_config();
+        let path = "/cast/0/author/book/01.opus";
+        let query = signed_query(path, None);
+        params_for(&query, |p| {
+            assert_eq!(Some(None), signed_account(path, p));
+            assert_eq!(None, signed_account("/cast/0/author/book/02.opus", p));
+        });
+
+        let query = signed_query(path, Some(GUEST_ACCOUNT));
+        params_for(&query, |p| {
+            assert_eq!(Some(Some(GUEST_ACCOUNT.into())), signed_account(path, p))
+        });
+        // guest URL must not become URL for main shared secret
+        let query = query.replace("&acc=", "");
+        params_for(&query, |p| assert_eq!(None, signed_account(path, p)));
+
+        // unknown account
+        let query = signed_query(path, Some("john"));
+        params_for(&query, |p| assert_eq!(None, signed_account(path, p)));
+
+        let query = signed_query(path, None).replace("exp=", "exp=1");
+        params_for(&query, |p| assert_eq!(None, signed_account(path, p)));
+    }
+
+    #[test]
+    fn test_encode_path() {
+        assert_eq!(
+            "author/book%20one/01%20%23%3F.mp3",
+            encode_path(Path::new("author/book one/01 #?.mp3"))
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Advertisement of server via mDNS (zeroconf), so clients in local network can discover it.
+
+use crate::config::get_config;
+use crate::error::{Context, Error};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+/// Service type advertised in local network
+pub const MDNS_SERVICE_TYPE: &str = "_audioserve._tcp.local.";
+
+/// Service is advertised while this value lives
+pub struct MdnsAdvertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Drop for MdnsAdvertisement {
+    fn drop(&mut self) {
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            error!("Error when unregistering mDNS service: {}", e);
+        }
+        self.daemon.shutdown().ok();
+    }
+}
+
+fn host_name() -> String {
+    #[cfg(unix)]
+    let name = nix::unistd::gethostname()
+        .ok()
+        .and_then(|n| n.into_string().ok());
+    #[cfg(not(unix))]
+    let name: Option<String> = None;
+    let name = name
+        .as_deref()
+        .and_then(|n| n.split('.').next())
+        .filter(|n| !n.is_empty())
+        .unwrap_or("audioserve");
+    format!("{}.local.", name)
+}
+
+/// TXT records describing how to connect to server
+fn txt_properties(is_tls: bool) -> Vec<(&'static str, String)> {
+    let cfg = get_config();
+    vec![
+        ("port", cfg.listen.port().to_string()),
+        (
+            "path",
+            cfg.url_path_prefix
+                .clone()
+                .unwrap_or_else(|| "/".to_string()),
+        ),
+        ("tls", is_tls.to_string()),
+        ("version", env!("CARGO_PKG_VERSION").to_string()),
+    ]
+}
+
+pub fn advertise_mdns(is_tls: bool) -> Result<MdnsAdvertisement, Error> {
+    let cfg = get_config();
+    let properties = txt_properties(is_tls);
+    let listen_ip = cfg.listen.ip();
+    let info = if listen_ip.is_unspecified() {
+        ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            &cfg.discovery.name,
+            &host_name(),
+            (),
+            cfg.listen.port(),
+            &properties[..],
+        )
+        .map(ServiceInfo::enable_addr_auto)
+    } else {
+        ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            &cfg.discovery.name,
+            &host_name(),
+            listen_ip,
+            cfg.listen.port(),
+            &properties[..],
+        )
+    }
+    .context("invalid mDNS service")?;
+    let fullname = info.get_fullname().to_string();
+    let daemon = ServiceDaemon::new().context("cannot start mDNS daemon")?;
+    daemon
+        .register(info)
+        .context("cannot register mDNS service")?;
+    info!("Server advertised via mDNS as {}", fullname);
+    Ok(MdnsAdvertisement { daemon, fullname })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_name() {
+        let name = host_name();
+        assert!(name.ends_with(".local."));
+        assert!(!name.trim_end_matches(".local.").contains('.'));
+    }
+}
@@ -0,0 +1,643 @@
+//! Minimal DLNA/UPnP media server, so devices in local network (smart speakers, TVs ...) can discover
+//! audioserve (see [ssdp]) and browse collections with ContentDirectory service.
+//! Devices cannot authenticate, so DLNA endpoints are available only for clients from local network
+//! and audio files are streamed from signed `/audio/` URLs.
+
+use super::api::xml_escape;
+use super::cast::signed_url;
+use super::visibility::FolderFilter;
+use crate::config::get_config;
+use crate::error::Error;
+use collection::audio_meta::AudioFolder;
+use collection::{AudioFile, Collections, FoldersOrdering};
+use myhy::headers::HeaderValue;
+use myhy::request::RequestWrapper;
+use myhy::response::{self, data_response, HttpResponse, ResponseResult};
+use myhy::{Method, StatusCode};
+use ring::digest::{digest, SHA256};
+use std::net::IpAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::task::spawn_blocking as blocking;
+
+pub mod ssdp;
+
+/// All DLNA endpoints are under this path
+pub const DLNA_PREFIX: &str = "/dlna/";
+const DESCRIPTION_PATH: &str = "description.xml";
+const MEDIA_SERVER_DEVICE: &str = "urn:schemas-upnp-org:device:MediaServer:1";
+const CONTENT_DIRECTORY_SERVICE: &str = "urn:schemas-upnp-org:service:ContentDirectory:1";
+const CONNECTION_MANAGER_SERVICE: &str = "urn:schemas-upnp-org:service:ConnectionManager:1";
+const CONTENT_DIRECTORY_SCPD: &str = include_str!("ContentDirectory.xml");
+const CONNECTION_MANAGER_SCPD: &str = include_str!("ConnectionManager.xml");
+/// Id of root container, which contains collections
+const ROOT_ID: &str = "0";
+/// SOAP requests are small, larger body is not read
+const MAX_SOAP_BODY: u64 = 64 * 1024;
+/// Formats offered in ConnectionManager protocol info
+const SOURCE_MIMES: &[&str] = &[
+    "audio/mpeg",
+    "audio/mp4",
+    "audio/aac",
+    "audio/flac",
+    "audio/ogg",
+    "audio/webm",
+    "audio/x-wav",
+];
+
+lazy_static! {
+    /// Device must have same id after restart, so it's derived from configuration
+    pub static ref DEVICE_UUID: String = device_uuid(
+        get_config().dlna.as_ref().map(|d| d.name.as_str()).unwrap_or_default(),
+        &get_config().base_dirs,
+    );
+}
+
+fn device_uuid(name: &str, base_dirs: &[PathBuf]) -> String {
+    let mut data = name.to_string();
+    for dir in base_dirs {
+        data.push('\n');
+        data.push_str(&dir.to_string_lossy());
+    }
+    let hash = digest(&SHA256, data.as_bytes());
+    let hex = data_encoding::HEXLOWER.encode(&hash.as_ref()[..16]);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Private, loopback and link local addresses
+fn is_local_address(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(a) => a.is_private() || a.is_loopback() || a.is_link_local(),
+        IpAddr::V6(a) => match a.to_ipv4_mapped() {
+            Some(a) => is_local_address(IpAddr::V4(a)),
+            None => {
+                a.is_loopback()
+                    || (a.segments()[0] & 0xfe00) == 0xfc00
+                    || (a.segments()[0] & 0xffc0) == 0xfe80
+            }
+        },
+    }
+}
+
+pub fn is_dlna_request(req: &RequestWrapper) -> bool {
+    get_config().dlna.is_some()
+        && req.path().starts_with(DLNA_PREFIX)
+        && req
+            .remote_addr()
+            .map(|a| is_local_address(*a.as_ref()))
+            .unwrap_or(false)
+}
+
+fn xml_response(body: impl Into<String>) -> HttpResponse {
+    data_response(
+        body.into(),
+        "text/xml; charset=utf-8".parse().unwrap(),
+        None,
+        None,
+        false,
+    )
+}
+
+fn device_description() -> String {
+    let name = get_config()
+        .dlna
+        .as_ref()
+        .map(|d| d.name.as_str())
+        .unwrap_or_default();
+    let service = |service_type: &str, name: &str| {
+        format!(
+            "<service><serviceType>{}</serviceType><serviceId>urn:upnp-org:serviceId:{}</serviceId>\
+<SCPDURL>{}.xml</SCPDURL><controlURL>control/{}</controlURL><eventSubURL>event/{}</eventSubURL></service>",
+            service_type, name, name, name, name
+        )
+    };
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+<root xmlns=\"urn:schemas-upnp-org:device-1-0\" xmlns:dlna=\"urn:schemas-dlna-org:device-1-0\">\
+<specVersion><major>1</major><minor>0</minor></specVersion><device>\
+<deviceType>{}</deviceType><friendlyName>{}</friendlyName>\
+<manufacturer>audioserve</manufacturer><manufacturerURL>https://github.com/izderadicka/audioserve</manufacturerURL>\
+<modelName>audioserve</modelName><modelNumber>{}</modelNumber><UDN>uuid:{}</UDN>\
+<dlna:X_DLNADOC>DMS-1.50</dlna:X_DLNADOC><serviceList>{}{}</serviceList></device></root>",
+        MEDIA_SERVER_DEVICE,
+        xml_escape(name),
+        env!("CARGO_PKG_VERSION"),
+        *DEVICE_UUID,
+        service(CONTENT_DIRECTORY_SERVICE, "ContentDirectory"),
+        service(CONNECTION_MANAGER_SERVICE, "ConnectionManager"),
+    )
+}
+
+fn soap_response(service: &str, action: &str, args: &[(&str, &str)]) -> HttpResponse {
+    let mut body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+<s:Body><u:{}Response xmlns:u=\"{}\">",
+        action, service
+    );
+    for (name, value) in args {
+        body.push_str(&format!("<{}>{}</{}>", name, xml_escape(value), name));
+    }
+    body.push_str(&format!("</u:{}Response></s:Body></s:Envelope>", action));
+    xml_response(body)
+}
+
+/// UPnP error, codes are defined by UPnP architecture and ContentDirectory specifications
+fn soap_fault(code: u16, description: &str) -> HttpResponse {
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+<s:Body><s:Fault><faultcode>s:Client</faultcode><faultstring>UPnPError</faultstring><detail>\
+<UPnPError xmlns=\"urn:schemas-upnp-org:control-1-0\"><errorCode>{}</errorCode><errorDescription>{}</errorDescription></UPnPError>\
+</detail></s:Fault></s:Body></s:Envelope>",
+        code,
+        xml_escape(description)
+    );
+    let mut resp = xml_response(body);
+    *resp.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+    resp
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Value of action argument from SOAP envelope, arguments are simple elements without namespace
+fn soap_arg(body: &str, name: &str) -> Option<String> {
+    let open = format!("<{}", name);
+    let mut rest = body;
+    while let Some(idx) = rest.find(&open) {
+        rest = &rest[idx + open.len()..];
+        if !rest.starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            continue;
+        }
+        let tag_end = rest.find('>')?;
+        if rest[..tag_end].ends_with('/') {
+            return Some(String::new());
+        }
+        let content = &rest[tag_end + 1..];
+        let close = content.find(&format!("</{}>", name))?;
+        return Some(xml_unescape(&content[..close]));
+    }
+    None
+}
+
+/// Service and action from SOAPACTION header like `"urn:schemas-upnp-org:service:ContentDirectory:1#Browse"`
+fn soap_action(req: &RequestWrapper) -> Option<(String, String)> {
+    let action = req.headers().get("soapaction")?.to_str().ok()?;
+    let (service, action) = action.trim().trim_matches('"').split_once('#')?;
+    Some((service.to_string(), action.to_string()))
+}
+
+/// Browsed object - root container, or folder or file in collection
+#[derive(Debug, PartialEq)]
+enum Object {
+    Root,
+    Path(usize, PathBuf),
+}
+
+impl Object {
+    fn parse(id: &str) -> Option<Self> {
+        if id == ROOT_ID {
+            return Some(Object::Root);
+        }
+        let (collection, path) = id.split_once(':')?;
+        let collection: usize = collection.parse().ok()?;
+        let path = PathBuf::from(path);
+        if collection >= get_config().base_dirs.len()
+            || path
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return None;
+        }
+        Some(Object::Path(collection, path))
+    }
+}
+
+fn object_id(collection: usize, path: &Path) -> String {
+    format!("{}:{}", collection, path.to_string_lossy())
+}
+
+fn parent_object_id(collection: usize, path: &Path) -> String {
+    match path.parent() {
+        Some(parent) => object_id(collection, parent),
+        None => ROOT_ID.to_string(),
+    }
+}
+
+fn collection_name(collection: usize) -> String {
+    get_config().base_dirs[collection]
+        .file_name()
+        .map(|n| n.to_string_lossy().into())
+        .unwrap_or_else(|| collection.to_string())
+}
+
+fn format_duration(secs: u32) -> String {
+    format!("{}:{:02}:{:02}.000", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn container_didl(id: &str, parent_id: &str, title: &str, child_count: Option<usize>) -> String {
+    format!(
+        "<container id=\"{}\" parentID=\"{}\" restricted=\"1\"{}><dc:title>{}</dc:title>\
+<upnp:class>object.container.storageFolder</upnp:class></container>",
+        xml_escape(id),
+        xml_escape(parent_id),
+        child_count
+            .map(|c| format!(" childCount=\"{}\"", c))
+            .unwrap_or_default(),
+        xml_escape(title)
+    )
+}
+
+/// Context needed to create URLs of audio files and covers
+struct UrlContext {
+    base_url: String,
+    account: Option<String>,
+}
+
+fn item_didl(
+    collection: usize,
+    file: &AudioFile,
+    folder: &AudioFolder,
+    ctx: &UrlContext,
+) -> String {
+    let tag = |name: &str| {
+        file.meta
+            .as_ref()
+            .and_then(|m| m.tags.as_ref())
+            .and_then(|t| t.get(name))
+            .or_else(|| folder.tags.as_ref().and_then(|t| t.get(name)))
+            .cloned()
+    };
+    let mut didl = format!(
+        "<item id=\"{}\" parentID=\"{}\" restricted=\"1\"><dc:title>{}</dc:title>\
+<upnp:class>object.item.audioItem.musicTrack</upnp:class>",
+        xml_escape(&object_id(collection, &file.path)),
+        xml_escape(&parent_object_id(collection, &file.path)),
+        xml_escape(&tag("title").unwrap_or_else(|| file.name.to_string()))
+    );
+    if let Some(album) = tag("album") {
+        didl.push_str(&format!("<upnp:album>{}</upnp:album>", xml_escape(&album)));
+    }
+    if let Some(artist) = tag("artist") {
+        didl.push_str(&format!(
+            "<upnp:artist>{0}</upnp:artist><dc:creator>{0}</dc:creator>",
+            xml_escape(&artist)
+        ));
+    }
+    if let Some(cover) = &folder.cover {
+        let url = signed_url(
+            &ctx.base_url,
+            &format!("/{}/cover/", collection),
+            &cover.path,
+            ctx.account.as_deref(),
+        );
+        didl.push_str(&format!(
+            "<upnp:albumArtURI>{}</upnp:albumArtURI>",
+            xml_escape(&url)
+        ));
+    }
+    let url = signed_url(
+        &ctx.base_url,
+        &format!("/{}/audio/", collection),
+        &file.path,
+        ctx.account.as_deref(),
+    );
+    didl.push_str(&format!(
+        "<res protocolInfo=\"http-get:*:{}:DLNA.ORG_OP=01\"{}>{}</res></item>",
+        xml_escape(&file.mime),
+        file.meta
+            .as_ref()
+            .map(|m| format!(" duration=\"{}\"", format_duration(m.duration)))
+            .unwrap_or_default(),
+        xml_escape(&url)
+    ));
+    didl
+}
+
+fn folder_children(collection: usize, folder: &AudioFolder, ctx: &UrlContext) -> Vec<String> {
+    folder
+        .subfolders
+        .iter()
+        .map(|f| {
+            container_didl(
+                &object_id(collection, &f.path),
+                &parent_object_id(collection, &f.path),
+                f.name.as_str(),
+                None,
+            )
+        })
+        .chain(
+            folder
+                .files
+                .iter()
+                .map(|f| item_didl(collection, f, folder, ctx)),
+        )
+        .collect()
+}
+
+async fn list_dir(
+    collections: Arc<Collections>,
+    collection: usize,
+    path: PathBuf,
+    ctx: &UrlContext,
+) -> Result<Option<AudioFolder>, Error> {
+    let folder_filter = FolderFilter::for_account(ctx.account.as_deref());
+    blocking(move || {
+        if let Some(ref filter) = folder_filter {
+            if !filter.is_visible(&path) {
+                return None;
+            }
+        }
+        let mut folder = collections
+            .list_dir(collection, &path, FoldersOrdering::Alphabetical, None)
+            .ok()?;
+        if let Some(filter) = folder_filter {
+            filter.apply_folder(&path, &mut folder);
+        }
+        Some(folder)
+    })
+    .await
+    .map_err(Error::new)
+}
+
+/// Objects returned by Browse action, if object exists
+async fn browse_objects(
+    object: Object,
+    children: bool,
+    collections: Arc<Collections>,
+    ctx: &UrlContext,
+) -> Result<Option<Vec<String>>, Error> {
+    let account = ctx.account.as_deref();
+    let allowed: Vec<usize> = (0..get_config().base_dirs.len())
+        .filter(|c| get_config().is_collection_allowed(*c, account))
+        .collect();
+    Ok(match object {
+        Object::Root if children => Some(
+            allowed
+                .into_iter()
+                .map(|c| {
+                    container_didl(
+                        &object_id(c, Path::new("")),
+                        ROOT_ID,
+                        &collection_name(c),
+                        None,
+                    )
+                })
+                .collect(),
+        ),
+        Object::Root => {
+            let name = get_config()
+                .dlna
+                .as_ref()
+                .map(|d| d.name.clone())
+                .unwrap_or_default();
+            Some(vec![container_didl(
+                ROOT_ID,
+                "-1",
+                &name,
+                Some(allowed.len()),
+            )])
+        }
+        Object::Path(collection, _) if !allowed.contains(&collection) => None,
+        Object::Path(collection, path) if children => list_dir(collections, collection, path, ctx)
+            .await?
+            .map(|folder| folder_children(collection, &folder, ctx)),
+        Object::Path(collection, path) if path.parent().is_none() => Some(vec![container_didl(
+            &object_id(collection, &path),
+            ROOT_ID,
+            &collection_name(collection),
+            None,
+        )]),
+        Object::Path(collection, path) => {
+            match list_dir(collections.clone(), collection, path.clone(), ctx).await? {
+                Some(folder) => Some(vec![container_didl(
+                    &object_id(collection, &path),
+                    &parent_object_id(collection, &path),
+                    &path
+                        .file_name()
+                        .map(|n| n.to_string_lossy())
+                        .unwrap_or_default(),
+                    Some(folder.subfolders.len() + folder.files.len()),
+                )]),
+                // not a folder, so it can be file
+                None => {
+                    let parent = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                    list_dir(collections, collection, parent, ctx)
+                        .await?
+                        .and_then(|folder| {
+                            folder
+                                .files
+                                .iter()
+                                .find(|f| f.path == path)
+                                .map(|f| vec![item_didl(collection, f, &folder, ctx)])
+                        })
+                }
+            }
+        }
+    })
+}
+
+async fn browse(body: &str, collections: Arc<Collections>, ctx: UrlContext) -> ResponseResult {
+    let object = match soap_arg(body, "ObjectID").and_then(|id| Object::parse(&id)) {
+        Some(o) => o,
+        None => return Ok(soap_fault(701, "No such object")),
+    };
+    let children = match soap_arg(body, "BrowseFlag").as_deref() {
+        Some("BrowseDirectChildren") => true,
+        Some("BrowseMetadata") => false,
+        _ => return Ok(soap_fault(402, "Invalid args")),
+    };
+    let start: usize = soap_arg(body, "StartingIndex")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    let count: usize = soap_arg(body, "RequestedCount")
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(0);
+    debug!(
+        "DLNA browse {:?} children {}, from {} count {}",
+        object, children, start, count
+    );
+    let objects = match browse_objects(object, children, collections, &ctx).await? {
+        Some(objects) => objects,
+        None => return Ok(soap_fault(701, "No such object")),
+    };
+    let total = objects.len();
+    let returned: Vec<_> = objects
+        .into_iter()
+        .skip(start)
+        .take(if count == 0 { usize::MAX } else { count })
+        .collect();
+    let result = format!(
+        "<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" \
+xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\">{}</DIDL-Lite>",
+        returned.concat()
+    );
+    Ok(soap_response(
+        CONTENT_DIRECTORY_SERVICE,
+        "Browse",
+        &[
+            ("Result", &result),
+            ("NumberReturned", &returned.len().to_string()),
+            ("TotalMatches", &total.to_string()),
+            ("UpdateID", "0"),
+        ],
+    ))
+}
+
+async fn control(mut req: RequestWrapper, collections: Arc<Collections>) -> ResponseResult {
+    let (service, action) = match soap_action(&req) {
+        Some(a) => a,
+        None => return Ok(soap_fault(401, "Invalid action")),
+    };
+    debug!("DLNA action {}#{}", service, action);
+    let source_info = SOURCE_MIMES
+        .iter()
+        .map(|m| format!("http-get:*:{}:*", m))
+        .collect::<Vec<_>>()
+        .join(",");
+    match (service.as_str(), action.as_str()) {
+        (CONTENT_DIRECTORY_SERVICE, "Browse") => {
+            let too_big = req
+                .headers()
+                .get("content-length")
+                .and_then(|l| l.to_str().ok())
+                .and_then(|l| l.parse::<u64>().ok())
+                .map(|l| l > MAX_SOAP_BODY)
+                .unwrap_or(true);
+            if too_big {
+                error!("Invalid DLNA request body size");
+                return Ok(response::bad_request());
+            }
+            let ctx = UrlContext {
+                base_url: super::base_url(&req),
+                account: get_config().dlna.as_ref().and_then(|d| d.account.clone()),
+            };
+            match req.body_bytes().await {
+                Ok(body) => browse(&String::from_utf8_lossy(&body), collections, ctx).await,
+                Err(e) => {
+                    error!("Error reading DLNA request body: {}", e);
+                    Ok(e.response())
+                }
+            }
+        }
+        (CONTENT_DIRECTORY_SERVICE, "GetSearchCapabilities") => {
+            Ok(soap_response(&service, &action, &[("SearchCaps", "")]))
+        }
+        (CONTENT_DIRECTORY_SERVICE, "GetSortCapabilities") => {
+            Ok(soap_response(&service, &action, &[("SortCaps", "")]))
+        }
+        (CONTENT_DIRECTORY_SERVICE, "GetSystemUpdateID") => {
+            Ok(soap_response(&service, &action, &[("Id", "0")]))
+        }
+        (CONNECTION_MANAGER_SERVICE, "GetProtocolInfo") => Ok(soap_response(
+            &service,
+            &action,
+            &[("Source", &source_info), ("Sink", "")],
+        )),
+        (CONNECTION_MANAGER_SERVICE, "GetCurrentConnectionIDs") => {
+            Ok(soap_response(&service, &action, &[("ConnectionIDs", "0")]))
+        }
+        (CONNECTION_MANAGER_SERVICE, "GetCurrentConnectionInfo") => Ok(soap_response(
+            &service,
+            &action,
+            &[
+                ("RcsID", "-1"),
+                ("AVTransportID", "-1"),
+                ("ProtocolInfo", ""),
+                ("PeerConnectionManager", ""),
+                ("PeerConnectionID", "-1"),
+                ("Direction", "Output"),
+                ("Status", "OK"),
+            ],
+        )),
+        _ => {
+            error!("Unsupported DLNA action {}#{}", service, action);
+            Ok(soap_fault(401, "Invalid action"))
+        }
+    }
+}
+
+/// Server does not send any events, but some devices require successful subscription
+fn subscribe_response() -> HttpResponse {
+    let mut resp = xml_response("");
+    let headers = resp.headers_mut();
+    headers.insert(
+        "SID",
+        HeaderValue::from_str(&format!("uuid:{}", *DEVICE_UUID)).unwrap(),
+    );
+    headers.insert("TIMEOUT", HeaderValue::from_static("Second-1800"));
+    resp
+}
+
+pub async fn dlna_service(req: RequestWrapper, collections: Arc<Collections>) -> ResponseResult {
+    let path = req.path()[DLNA_PREFIX.len()..].to_string();
+    let method = req.method().clone();
+    match (method, path.as_str()) {
+        (Method::GET, DESCRIPTION_PATH) => Ok(xml_response(device_description())),
+        (Method::GET, "ContentDirectory.xml") => Ok(xml_response(CONTENT_DIRECTORY_SCPD)),
+        (Method::GET, "ConnectionManager.xml") => Ok(xml_response(CONNECTION_MANAGER_SCPD)),
+        (Method::POST, "control/ContentDirectory" | "control/ConnectionManager") => {
+            control(req, collections).await
+        }
+        (m, p) if p.starts_with("event/") && m.as_str() == "SUBSCRIBE" => Ok(subscribe_response()),
+        (m, p) if p.starts_with("event/") && m.as_str() == "UNSUBSCRIBE" => Ok(xml_response("")),
+        _ => Ok(response::not_found()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_soap_arg() {
+        let body = r#"<?xml version="1.0"?><s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+<s:Body><u:Browse xmlns:u="urn:schemas-upnp-org:service:ContentDirectory:1">
+<ObjectID>0:author/A &amp; B</ObjectID><BrowseFlag>BrowseDirectChildren</BrowseFlag>
+<Filter>*</Filter><StartingIndex>10</StartingIndex><RequestedCount>20</RequestedCount><SortCriteria/>
+</u:Browse></s:Body></s:Envelope>"#;
+        assert_eq!(Some("0:author/A & B".into()), soap_arg(body, "ObjectID"));
+        assert_eq!(Some("10".into()), soap_arg(body, "StartingIndex"));
+        assert_eq!(Some("".into()), soap_arg(body, "SortCriteria"));
+        assert_eq!(None, soap_arg(body, "Object"));
+    }
+
+    #[test]
+    fn test_local_address() {
+        assert!(is_local_address("192.168.1.10".parse().unwrap()));
+        assert!(is_local_address("10.0.0.1".parse().unwrap()));
+        assert!(is_local_address("::ffff:172.16.0.1".parse().unwrap()));
+        assert!(is_local_address("fe80::1".parse().unwrap()));
+        assert!(is_local_address("fd00::1".parse().unwrap()));
+        assert!(!is_local_address("8.8.8.8".parse().unwrap()));
+        assert!(!is_local_address("2001:db8::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_device_uuid() {
+        let dirs = vec![PathBuf::from("/audiobooks")];
+        let uuid = device_uuid("audioserve", &dirs);
+        assert_eq!(36, uuid.len());
+        assert_eq!(uuid, device_uuid("audioserve", &dirs));
+        assert_ne!(uuid, device_uuid("other", &dirs));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!("0:00:59.000", format_duration(59));
+        assert_eq!("1:01:01.000", format_duration(3661));
+    }
+}
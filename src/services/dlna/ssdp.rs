@@ -0,0 +1,252 @@
+//! SSDP - devices find media server by multicast search, server also periodically announces
+//! its presence (and departure on shutdown).
+
+use super::{
+    is_local_address, CONNECTION_MANAGER_SERVICE, CONTENT_DIRECTORY_SERVICE, DESCRIPTION_PATH,
+    DEVICE_UUID, DLNA_PREFIX, MEDIA_SERVER_DEVICE,
+};
+use crate::config::get_config;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+
+const SSDP_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_PORT: u16 = 1900;
+const MAX_MESSAGE_SIZE: usize = 2048;
+
+/// Notification types, which are announced, and also search targets, to which server responds
+fn targets(uuid: &str) -> [String; 5] {
+    [
+        "upnp:rootdevice".into(),
+        format!("uuid:{}", uuid),
+        MEDIA_SERVER_DEVICE.into(),
+        CONTENT_DIRECTORY_SERVICE.into(),
+        CONNECTION_MANAGER_SERVICE.into(),
+    ]
+}
+
+fn usn(target: &str, uuid: &str) -> String {
+    if target.starts_with("uuid:") {
+        target.to_string()
+    } else {
+        format!("uuid:{}::{}", uuid, target)
+    }
+}
+
+fn server_header() -> String {
+    format!(
+        "{}/1.0 UPnP/1.0 audioserve/{}",
+        std::env::consts::OS,
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Search target of M-SEARCH request, None for any other message
+fn parse_search(msg: &str) -> Option<String> {
+    let mut lines = msg.lines();
+    if !lines
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("M-SEARCH * HTTP/1.1")
+    {
+        return None;
+    }
+    let mut target = None;
+    let mut discover = false;
+    for (name, value) in lines.filter_map(|l| l.split_once(':')) {
+        let value = value.trim();
+        match name.trim().to_ascii_uppercase().as_str() {
+            "ST" => target = Some(value.to_string()),
+            "MAN" => discover = value.trim_matches('"') == "ssdp:discover",
+            _ => (),
+        }
+    }
+    target.filter(|_| discover)
+}
+
+fn matching_targets(search_target: &str, uuid: &str) -> Vec<String> {
+    targets(uuid)
+        .into_iter()
+        .filter(|t| search_target == "ssdp:all" || t == search_target)
+        .collect()
+}
+
+fn search_response(target: &str, location: &str, max_age: u32) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age={}\r\nEXT:\r\nLOCATION: {}\r\nSERVER: {}\r\nST: {}\r\nUSN: {}\r\n\r\n",
+        max_age,
+        location,
+        server_header(),
+        target,
+        usn(target, &DEVICE_UUID)
+    )
+}
+
+fn notify_message(target: &str, location: &str, max_age: u32, alive: bool) -> String {
+    format!(
+        "NOTIFY * HTTP/1.1\r\nHOST: {}:{}\r\nCACHE-CONTROL: max-age={}\r\nLOCATION: {}\r\nNT: {}\r\nNTS: {}\r\nSERVER: {}\r\nUSN: {}\r\n\r\n",
+        SSDP_ADDR,
+        SSDP_PORT,
+        max_age,
+        location,
+        target,
+        if alive { "ssdp:alive" } else { "ssdp:byebye" },
+        server_header(),
+        usn(target, &DEVICE_UUID)
+    )
+}
+
+/// Address of this server, as seen from peer
+fn local_ip_for(peer: SocketAddr) -> Option<IpAddr> {
+    let listen = get_config().listen.ip();
+    if !listen.is_unspecified() {
+        return Some(listen);
+    }
+    // no packet is sent, connect just finds route to peer
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect(peer).ok()?;
+    socket.local_addr().ok().map(|a| a.ip())
+}
+
+/// URL of device description
+fn location(local_ip: IpAddr, is_tls: bool) -> String {
+    let host = match local_ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{}]", ip),
+    };
+    format!(
+        "{}://{}:{}{}{}{}",
+        if is_tls { "https" } else { "http" },
+        host,
+        get_config().listen.port(),
+        get_config().url_path_prefix.as_deref().unwrap_or_default(),
+        DLNA_PREFIX,
+        DESCRIPTION_PATH
+    )
+}
+
+fn bind_socket() -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // other UPnP software can also listen on SSDP port
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SSDP_PORT)).into())?;
+    socket.join_multicast_v4(&SSDP_ADDR, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_ttl_v4(2)?;
+    UdpSocket::from_std(socket.into())
+}
+
+async fn notify_all(socket: &UdpSocket, is_tls: bool, max_age: u32, alive: bool) {
+    let multicast = SocketAddr::from((SSDP_ADDR, SSDP_PORT));
+    let location = match local_ip_for(multicast) {
+        Some(ip) => location(ip, is_tls),
+        None => {
+            error!("Cannot determine local address for SSDP announcement");
+            return;
+        }
+    };
+    for target in targets(&DEVICE_UUID) {
+        let msg = notify_message(&target, &location, max_age, alive);
+        if let Err(e) = socket.send_to(msg.as_bytes(), multicast).await {
+            error!("Error sending SSDP notification: {}", e);
+            return;
+        }
+    }
+}
+
+async fn respond_to_search(
+    socket: &UdpSocket,
+    msg: &[u8],
+    peer: SocketAddr,
+    is_tls: bool,
+    max_age: u32,
+) {
+    let search_target = match std::str::from_utf8(msg).ok().and_then(parse_search) {
+        Some(t) => t,
+        None => return,
+    };
+    if !is_local_address(peer.ip()) {
+        debug!("Ignoring SSDP search from {}", peer);
+        return;
+    }
+    let location = match local_ip_for(peer) {
+        Some(ip) => location(ip, is_tls),
+        None => return,
+    };
+    for target in matching_targets(&search_target, &DEVICE_UUID) {
+        let resp = search_response(&target, &location, max_age);
+        if let Err(e) = socket.send_to(resp.as_bytes(), peer).await {
+            error!("Error sending SSDP response to {}: {}", peer, e);
+            return;
+        }
+    }
+}
+
+/// Runs until server is stopped, DLNA must be configured
+pub async fn run_ssdp(is_tls: bool, mut stop_receiver: watch::Receiver<()>) {
+    let cfg = match get_config().dlna.as_ref() {
+        Some(cfg) => cfg,
+        None => return,
+    };
+    let socket = match bind_socket() {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Cannot listen for SSDP, DLNA will not be available: {}", e);
+            return;
+        }
+    };
+    let max_age = 2 * cfg.announce_interval;
+    let mut announce = tokio::time::interval(Duration::from_secs(cfg.announce_interval.into()));
+    let mut buf = [0u8; MAX_MESSAGE_SIZE];
+    info!(
+        "DLNA media server {} (uuid:{}) started",
+        cfg.name, *DEVICE_UUID
+    );
+    loop {
+        tokio::select! {
+            _ = announce.tick() => notify_all(&socket, is_tls, max_age, true).await,
+            res = socket.recv_from(&mut buf) => match res {
+                Ok((len, peer)) => respond_to_search(&socket, &buf[..len], peer, is_tls, max_age).await,
+                Err(e) => error!("Error receiving SSDP message: {}", e),
+            },
+            _ = stop_receiver.changed() => {
+                notify_all(&socket, is_tls, max_age, false).await;
+                break;
+            }
+        }
+    }
+    debug!("SSDP stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_search() {
+        let msg = "M-SEARCH * HTTP/1.1\r\nHOST: 239.255.255.250:1900\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: ssdp:all\r\n\r\n";
+        assert_eq!(Some("ssdp:all".into()), parse_search(msg));
+        let msg = "M-SEARCH * HTTP/1.1\r\nMAN: \"ssdp:other\"\r\nST: ssdp:all\r\n\r\n";
+        assert_eq!(None, parse_search(msg));
+        let msg = "NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\nNTS: ssdp:alive\r\n\r\n";
+        assert_eq!(None, parse_search(msg));
+    }
+
+    #[test]
+    fn test_matching_targets() {
+        assert_eq!(5, matching_targets("ssdp:all", "1234").len());
+        assert_eq!(
+            vec![MEDIA_SERVER_DEVICE.to_string()],
+            matching_targets(MEDIA_SERVER_DEVICE, "1234")
+        );
+        assert_eq!(
+            vec!["uuid:1234".to_string()],
+            matching_targets("uuid:1234", "1234")
+        );
+        assert!(matching_targets("urn:schemas-upnp-org:device:MediaRenderer:1", "1234").is_empty());
+        assert_eq!("uuid:1234", usn("uuid:1234", "1234"));
+        assert_eq!("uuid:1234::upnp:rootdevice", usn("upnp:rootdevice", "1234"));
+    }
+}
@@ -0,0 +1,270 @@
+//! Server-Sent Events stream of changes in collections, so clients do not need to poll.
+//! Changes are reported by collections change listener and distributed via broadcast channel
+//! to all connections, each connection filters them by its own query parameters.
+
+use std::{collections::HashSet, path::Path, time::Duration};
+
+use bytes::Bytes;
+use collection::CollectionChange;
+use myhy::header::{CACHE_CONTROL, CONTENT_TYPE};
+use myhy::request::QueryParams;
+use myhy::response::{body::wrap_stream, HttpResponse};
+use myhy::Response;
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc};
+
+use super::auth::Account;
+use super::visibility::FolderFilter;
+use crate::config::get_config;
+use crate::error::{bail, Result};
+
+const EVENTS_CAPACITY: usize = 256;
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(30);
+const EVENT_TYPES: &[&str] = &["folder", "position", "scan-finished"];
+
+lazy_static! {
+    static ref EVENTS: broadcast::Sender<Event> = broadcast::channel(EVENTS_CAPACITY).0;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+    /// folder was updated after change on disk, `added` if it's new folder
+    Folder {
+        collection: usize,
+        path: String,
+        added: bool,
+    },
+    /// playback position of group changed, `finished` if folder was just finished
+    Position {
+        collection: usize,
+        group: String,
+        folder: String,
+        finished: bool,
+    },
+    ScanFinished {
+        collection: usize,
+    },
+    /// connection was too slow and some events were lost, client should reload its state
+    Lagged {
+        missed: u64,
+    },
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Folder { .. } => "folder",
+            Event::Position { .. } => "position",
+            Event::ScanFinished { .. } => "scan-finished",
+            Event::Lagged { .. } => "lagged",
+        }
+    }
+
+    fn to_sse(&self) -> Bytes {
+        let data = serde_json::to_string(self).expect("event serialization");
+        format!("event: {}\ndata: {}\n\n", self.name(), data).into()
+    }
+}
+
+/// Change listener for collections, passes change to all connected clients
+pub fn publish(collection: usize, change: CollectionChange) {
+    let event = match change {
+        CollectionChange::FolderChanged(path) => Event::Folder {
+            collection,
+            path: path.to_string_lossy().into(),
+            added: false,
+        },
+        CollectionChange::FolderAdded(path) => Event::Folder {
+            collection,
+            path: path.to_string_lossy().into(),
+            added: true,
+        },
+        CollectionChange::PositionChanged {
+            group,
+            folder,
+            finished,
+        } => Event::Position {
+            collection,
+            group,
+            folder,
+            finished,
+        },
+        CollectionChange::ScanFinished => Event::ScanFinished { collection },
+    };
+    // error just means that no client is connected
+    EVENTS.send(event).ok();
+}
+
+/// Receiver of all events, for other consumers then SSE clients
+#[cfg_attr(
+    not(any(feature = "webhooks", feature = "scrobbler")),
+    allow(dead_code)
+)]
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    EVENTS.subscribe()
+}
+
+/// Filter of events for one connection
+pub struct EventsFilter {
+    types: Option<HashSet<&'static str>>,
+    collections: Option<HashSet<usize>>,
+    /// position events are sent only for this group
+    group: Option<String>,
+    /// only events in this folder and its subfolders
+    path: Option<String>,
+    account: Account,
+    folder_filter: Option<FolderFilter>,
+}
+
+impl EventsFilter {
+    pub fn from_params(params: &QueryParams, account: Account) -> Result<Self> {
+        let types = match params.get("types") {
+            Some(types) => Some(
+                types
+                    .split(',')
+                    .map(|t| match EVENT_TYPES.iter().find(|n| **n == t.trim()) {
+                        Some(name) => Ok(*name),
+                        None => bail!("unknown event type {}", t),
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            None => None,
+        };
+        let collections = params
+            .get("collections")
+            .map(|c| c.split(',').map(|n| n.trim().parse::<usize>()).collect())
+            .transpose()?;
+        Ok(EventsFilter {
+            types,
+            collections,
+            group: params.get_string("group"),
+            path: params.get_string("path"),
+            folder_filter: FolderFilter::for_account(account.as_deref()),
+            account,
+        })
+    }
+
+    fn in_path(&self, path: &str) -> bool {
+        self.path
+            .as_ref()
+            .map(|prefix| Path::new(path).starts_with(prefix))
+            .unwrap_or(true)
+    }
+
+    fn matches(&self, event: &Event) -> bool {
+        let collection = match event {
+            Event::Folder { collection, .. }
+            | Event::Position { collection, .. }
+            | Event::ScanFinished { collection } => *collection,
+            Event::Lagged { .. } => return true,
+        };
+        if !self
+            .types
+            .as_ref()
+            .map(|t| t.contains(event.name()))
+            .unwrap_or(true)
+            || !self
+                .collections
+                .as_ref()
+                .map(|c| c.contains(&collection))
+                .unwrap_or(true)
+            || !get_config().is_collection_allowed(collection, self.account.as_deref())
+        {
+            return false;
+        }
+        match event {
+            Event::Folder { path, .. } => {
+                self.in_path(path)
+                    && self
+                        .folder_filter
+                        .as_ref()
+                        .map(|f| f.is_visible(path))
+                        .unwrap_or(true)
+            }
+            Event::Position { group, folder, .. } => {
+                self.group.as_ref() == Some(group)
+                    && self.in_path(folder)
+                    && self
+                        .folder_filter
+                        .as_ref()
+                        .map(|f| f.is_allowed(folder))
+                        .unwrap_or(true)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Streams events matching filter, until client disconnects
+pub fn events_stream(filter: EventsFilter) -> HttpResponse {
+    let mut events = subscribe();
+    let (tx, rx) = mpsc::channel::<Bytes>(16);
+    tokio::spawn(async move {
+        let mut keep_alive = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+        loop {
+            let data = tokio::select! {
+                evt = events.recv() => match evt {
+                    Ok(evt) if filter.matches(&evt) => evt.to_sse(),
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        Event::Lagged { missed }.to_sse()
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = keep_alive.tick() => Bytes::from_static(b": keep-alive\n\n"),
+            };
+            if tx.send(data).await.is_err() {
+                debug!("Events client disconnected");
+                break;
+            }
+        }
+    });
+    let stream = futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv()
+            .await
+            .map(|data| (Ok::<_, std::io::Error>(data), rx))
+    });
+    Response::builder()
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        // do not buffer in reverse proxy
+        .header("X-Accel-Buffering", "no")
+        .body(wrap_stream(stream))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_sse() {
+        let evt = Event::Position {
+            collection: 1,
+            group: "ivan".into(),
+            folder: "a/b".into(),
+            finished: true,
+        };
+        assert_eq!(
+            "event: position\ndata: {\"type\":\"position\",\"collection\":1,\"group\":\"ivan\",\"folder\":\"a/b\",\"finished\":true}\n\n",
+            std::str::from_utf8(&evt.to_sse()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_in_path() {
+        let filter = EventsFilter {
+            types: None,
+            collections: None,
+            group: None,
+            path: Some("a/b".into()),
+            account: None,
+            folder_filter: None,
+        };
+        assert!(filter.in_path("a/b"));
+        assert!(filter.in_path("a/b/c"));
+        assert!(!filter.in_path("a/bc"));
+        assert!(!filter.in_path("a"));
+    }
+}
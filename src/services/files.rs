@@ -1,28 +1,39 @@
 //#[cfg(feature = "folder-download")]
 use super::{
-    icon::icon_response,
-    transcode::{guess_format, AudioFilePath, ChosenTranscoding, QualityLevel, Transcoder},
+    icon::{icon_response, resized_cover_response},
+    markdown,
+    transcode::{
+        guess_format, AudioFilePath, ChosenTranscoding, QualityLevel, Transcoder,
+        TranscodingDecision,
+    },
     types::*,
     Counter,
 };
 use crate::{config::get_config, error::Error};
 use collection::{
-    audio_meta::is_audio, extract_cover, extract_description, parse_chapter_path, TimeSpan,
+    audio_meta::{get_audio_properties_uni, is_audio, MediaInfo},
+    extract_cbr_layout, extract_cover, extract_description, extract_duration, guess_mime_type,
+    parse_chapter_path, ThreadConfined, TimeSpan,
 };
 use futures::prelude::*;
-use myhy::headers::{ContentLength, ContentType};
+use myhy::header::VARY;
+use myhy::headers::{ContentLength, ContentType, HeaderMapExt, HeaderValue};
 use myhy::response::{
     self,
     body::wrap_stream,
     data_response,
     file::{send_file_simple, serve_file_from_fs, ByteRange},
-    not_found, not_found_cached, ResponseBuilderExt, ResponseResult,
+    file_etag, not_found, not_found_cached, not_modified, Conditionals, ResponseBuilderExt,
+    ResponseResult,
 };
 use myhy::Response;
 
 use std::{
     ffi::OsStr,
+    io,
+    ops::Bound,
     path::{Path, PathBuf},
+    pin::Pin,
     sync::{atomic::Ordering, Arc},
 };
 use tokio::task::spawn_blocking as blocking;
@@ -96,10 +107,12 @@ async fn serve_file_cached_or_transcoded(
             } else {
                 debug!("Sending file {:?} from transcoded cache", &full_path);
                 let mime = transcoding_quality.format.mime();
-                serve_opened_file(f, range, None, mime).await.map_err(|e| {
-                    error!("Error sending cached file: {}", e);
-                    Error::new(e).context("sending cached file")
-                })
+                serve_opened_file(f, range, None, mime, None)
+                    .await
+                    .map_err(|e| {
+                        error!("Error sending cached file: {}", e);
+                        Error::new(e).context("sending cached file")
+                    })
             }
         }
         None => {
@@ -156,6 +169,10 @@ async fn serve_file_transcoded(
         transcoding_quality.format.mime()
     };
 
+    let bitrate = match transcoding_quality.level {
+        QualityLevel::Passthrough => 0,
+        _ => transcoding_quality.format.bitrate(),
+    };
     let transcoder = Transcoder::new(transcoding_quality);
     let params = transcoder.transcoding_params();
 
@@ -173,16 +190,162 @@ async fn serve_file_transcoded(
         return Ok(response::not_found());
     }
 
-    transcoder
-        .transcode(full_path, seek, span, counter)
+    let duration = transcoded_duration(full_path.as_ref(), seek, span).await;
+
+    let retry_file = full_path.clone();
+    let stream = transcoder
+        .clone()
+        .transcode(full_path, seek, span, counter.clone())
+        .await?;
+    let stream = match with_first_chunk(stream).await {
+        Some(stream) => stream,
+        None => {
+            // most likely problem with cache or transient failure of ffmpeg, it's worth to try once more
+            warn!(
+                "Transcoding of file {:?} failed before sending any data, retrying without cache",
+                retry_file
+            );
+            counter.fetch_add(1, Ordering::Release);
+            Box::pin(transcoder.transcode_uncached(retry_file, seek, span, counter)?)
+        }
+    };
+
+    let mut builder = Response::builder()
+        .typed_header(ContentType::from(mime))
+        .header("X-Transcode", params.as_bytes());
+    if let Some(duration) = duration {
+        builder = builder.header("X-Content-Duration", format!("{:.3}", duration));
+        if bitrate > 0 {
+            let size = (duration * bitrate as f32 * 125.0) as u64;
+            builder = builder.header("X-Estimated-Content-Length", size.to_string());
+        }
+    }
+    Ok(builder.body(wrap_stream(stream)).unwrap())
+}
+
+type TranscodedBody = Pin<Box<dyn Stream<Item = Result<Vec<u8>, io::Error>> + Send + Sync>>;
+
+/// Waits for first chunk of transcoded stream, returns None if stream failed or ended without any data
+async fn with_first_chunk<S>(mut stream: S) -> Option<TranscodedBody>
+where
+    S: Stream<Item = Result<Vec<u8>, io::Error>> + Send + Sync + Unpin + 'static,
+{
+    match stream.next().await {
+        Some(Ok(chunk)) => Some(Box::pin(stream::once(future::ok(chunk)).chain(stream))),
+        Some(Err(e)) => {
+            error!("Error reading transcoded stream: {}", e);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Duration (in secs) of transcoded stream, so client can show total time, even if content length is unknown
+async fn transcoded_duration(
+    full_path: &Path,
+    seek: Option<f32>,
+    span: Option<TimeSpan>,
+) -> Option<f32> {
+    let duration = match span.and_then(|s| s.duration) {
+        Some(d) => d,
+        None => {
+            let path = full_path.to_owned();
+            let start = span.map(|s| s.start).unwrap_or(0);
+            blocking(move || extract_duration(&path))
+                .await
+                .ok()
+                .flatten()?
+                .saturating_sub(start)
+        }
+    };
+    let duration = duration as f32 / 1000.0 - seek.unwrap_or(0.0);
+    if duration > 0.0 {
+        Some(duration)
+    } else {
+        None
+    }
+}
+
+/// Only if smart passthrough is enabled, otherwise file is always transcoded
+async fn transcoding_decision(
+    full_path: &Path,
+    transcoding_quality: &ChosenTranscoding,
+) -> Option<TranscodingDecision> {
+    let cfg = &get_config().transcoding;
+    // normalization needs decoding, so file must be transcoded
+    if !cfg.smart_passthrough || cfg.loudness_normalize {
+        return None;
+    }
+    let path = full_path.to_owned();
+    let format = transcoding_quality.format.clone();
+    blocking(move || {
+        // libav context is used and closed only in this blocking thread
+        let media = get_audio_properties_uni(&path)
+            .map_err(|e| error!("Error {} when extracting metadata from {:?}", e, path))
+            .ok()
+            .map(ThreadConfined::new);
+        media
+            .as_ref()
+            .and_then(|m| m.get())
+            .and_then(|m| m.audio_codec())
+            .map(|(codec, bitrate)| {
+                TranscodingDecision::decide(codec, bitrate, &guess_mime_type(&path), &format)
+            })
+            .unwrap_or(TranscodingDecision::Transcode)
+    })
+    .await
+    .map_err(|e| error!("Error when deciding about transcoding: {}", e))
+    .ok()
+}
+
+/// File with constant bitrate can be seeked by sending it from byte offset (as range response),
+/// if client requests range starting after beginning of file, it continues previous response,
+/// which already started from seek offset
+async fn cbr_seek_range(
+    full_path: &Path,
+    seek: f32,
+    range: Option<ByteRange>,
+) -> Option<ByteRange> {
+    let path = full_path.to_owned();
+    let layout = blocking(move || extract_cbr_layout(&path))
         .await
-        .map(move |stream| {
-            Response::builder()
-                .typed_header(ContentType::from(mime))
-                .header("X-Transcode", params.as_bytes())
-                .body(wrap_stream(stream))
-                .unwrap()
-        })
+        .ok()
+        .flatten()?;
+    let offset = layout.byte_offset((seek.max(0.0) * 1000.0).round() as u64);
+    debug!(
+        "File {:?} has constant bitrate, seeking to byte offset {}",
+        full_path, offset
+    );
+    Some(seek_range(offset, range))
+}
+
+fn seek_range(offset: u64, range: Option<ByteRange>) -> ByteRange {
+    match range {
+        Some((Bound::Included(start), end)) if start > 0 => (Bound::Included(start), end),
+        _ => (Bound::Included(offset), Bound::Unbounded),
+    }
+}
+
+/// Limits played part of file (or chapter) to max_duration secs from seek position,
+/// limit is then applied by ffmpeg as duration of span
+fn limit_span(
+    span: Option<TimeSpan>,
+    seek: Option<f32>,
+    max_duration: Option<f32>,
+) -> Option<TimeSpan> {
+    let max_duration = match max_duration {
+        Some(d) => d,
+        None => return span,
+    };
+    let limit = ((seek.unwrap_or(0.0) + max_duration) * 1000.0).round() as u64;
+    let span = span.unwrap_or(TimeSpan {
+        start: 0,
+        duration: None,
+    });
+    Some(TimeSpan {
+        start: span.start,
+        duration: Some(span.duration.map_or(limit, |d| d.min(limit))),
+    })
 }
 
 pub async fn send_file<P: AsRef<Path>>(
@@ -190,26 +353,92 @@ pub async fn send_file<P: AsRef<Path>>(
     file_path: P,
     range: Option<ByteRange>,
     seek: Option<f32>,
+    max_duration: Option<f32>,
     transcoding: super::TranscodingDetails,
     transcoding_quality: Option<ChosenTranscoding>,
 ) -> ResponseResult {
     let (real_path, span) = parse_chapter_path(file_path.as_ref());
+    let span = limit_span(span, seek, max_duration);
     let full_path = base_path.join(real_path);
     if let Some(transcoding_quality) = transcoding_quality {
         debug!(
             "Sending file transcoded in quality {:?}",
             transcoding_quality.level
         );
-        serve_file_cached_or_transcoded(
-            full_path,
-            seek,
-            span,
-            range,
-            transcoding,
-            transcoding_quality,
-        )
-        .await
-    } else if span.is_some() {
+        let mut range = range;
+        let decision = match transcoding_decision(&full_path, &transcoding_quality).await {
+            // plain file cannot be cut, so it has to be remuxed
+            Some(TranscodingDecision::Passthrough) if span.is_some() => {
+                Some(TranscodingDecision::Remux)
+            }
+            // and it can be seeked only if it has constant bitrate
+            Some(TranscodingDecision::Passthrough) if seek.is_some() => {
+                match cbr_seek_range(&full_path, seek.unwrap_or_default(), range).await {
+                    Some(seek_range) => {
+                        range = Some(seek_range);
+                        Some(TranscodingDecision::Passthrough)
+                    }
+                    None => Some(TranscodingDecision::Remux),
+                }
+            }
+            d => d,
+        };
+        let resp = match decision {
+            // time limited stream is not worth caching
+            None | Some(TranscodingDecision::Transcode) if max_duration.is_some() => {
+                serve_file_transcoded_checked(
+                    AudioFilePath::Original(full_path),
+                    seek,
+                    span,
+                    transcoding,
+                    transcoding_quality,
+                )
+                .await
+            }
+            None | Some(TranscodingDecision::Transcode) => {
+                serve_file_cached_or_transcoded(
+                    full_path,
+                    seek,
+                    span,
+                    range,
+                    transcoding,
+                    transcoding_quality,
+                )
+                .await
+            }
+            Some(TranscodingDecision::Passthrough) => {
+                debug!("File {:?} already has required codec and format", full_path);
+                serve_file_from_fs(&full_path, range, None, false, None).await
+            }
+            Some(TranscodingDecision::Remux) => {
+                debug!("File {:?} already has required codec, remuxing", full_path);
+                serve_file_transcoded_checked(
+                    AudioFilePath::Transcoded(full_path),
+                    seek,
+                    span,
+                    transcoding,
+                    transcoding_quality,
+                )
+                .await
+            }
+        };
+        match decision {
+            Some(decision) => resp.map(|mut r| {
+                r.headers_mut().insert(
+                    "X-Transcode-Decision",
+                    HeaderValue::from_static(decision.as_str()),
+                );
+                r
+            }),
+            None => resp,
+        }
+    } else if let Some(seek_range) = match seek {
+        Some(seek) if span.is_none() => cbr_seek_range(&full_path, seek, range).await,
+        _ => None,
+    } {
+        debug!("Sending file from fs from seek position");
+        serve_file_from_fs(&full_path, Some(seek_range), None, false, None).await
+    } else if span.is_some() || seek.is_some() {
         debug!("Sending part of file remuxed");
         serve_file_transcoded_checked(
             AudioFilePath::Original(full_path),
@@ -221,31 +450,215 @@ pub async fn send_file<P: AsRef<Path>>(
         .await
     } else {
         debug!("Sending file directly from fs");
-        serve_file_from_fs(&full_path, range, None, false).await
+        serve_file_from_fs(&full_path, range, None, false, None).await
     }
 }
 
+/// Audio formats, which casting devices can play directly
+pub const CAST_NATIVE_MIMES: &[&str] = &["audio/mpeg", "audio/mp4", "audio/aac", "audio/flac"];
+
+/// Sends audio file to casting device, which needs seekable file with known length -
+/// so file is either sent as it is, if device can play it, or it's transcoded to constant bitrate
+/// and sent from transcoding cache, when transcoding is finished
+pub async fn send_cast_file<P: AsRef<Path>>(
+    base_path: &'static Path,
+    file_path: P,
+    range: Option<ByteRange>,
+    transcoding: super::TranscodingDetails,
+) -> ResponseResult {
+    let (real_path, span) = parse_chapter_path(file_path.as_ref());
+    let full_path = base_path.join(real_path);
+    if span.is_none() && CAST_NATIVE_MIMES.contains(&guess_mime_type(&full_path).essence_str()) {
+        debug!("Sending file {:?} for casting as it is", full_path);
+        return serve_file_from_fs(&full_path, range, None, false, None).await;
+    }
+
+    #[cfg(feature = "transcoding-cache")]
+    if !get_config().transcoding.cache.disabled {
+        use myhy::response::file::serve_opened_file;
+        if !tokio::fs::metadata(&full_path)
+            .await
+            .map(|m| m.is_file())
+            .unwrap_or(false)
+        {
+            error!("Requesting non existent file for casting {:?}", full_path);
+            return Ok(response::not_found());
+        }
+        let mime = ChosenTranscoding::for_cast().format.mime();
+        return match prepare_cast_file(full_path, span, transcoding).await? {
+            Some(f) => serve_opened_file(f, range, None, mime, None)
+                .await
+                .map_err(|e| {
+                    error!("Error sending cached file: {}", e);
+                    Error::new(e).context("sending cached file for casting")
+                }),
+            None => Ok(response::too_many_requests()),
+        };
+    }
+
+    warn!(
+        "Transcoding cache is not available, so file {:?} is not seekable when casting",
+        full_path
+    );
+    serve_file_transcoded_checked(
+        AudioFilePath::Original(full_path),
+        None,
+        span,
+        transcoding,
+        ChosenTranscoding::for_cast(),
+    )
+    .await
+}
+
+#[cfg(feature = "transcoding-cache")]
+lazy_static! {
+    static ref CAST_PREPARING: std::sync::Mutex<std::collections::HashSet<String>> =
+        Default::default();
+}
+
+/// How often to check, if transcoded file for casting is already in cache
+#[cfg(feature = "transcoding-cache")]
+const CAST_PREPARE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+/// How many times to check for transcoded file, after transcoding finished (cache entry is committed asynchronously)
+#[cfg(feature = "transcoding-cache")]
+const CAST_COMMIT_CHECKS: u32 = 20;
+
+/// Removes cache key from keys being prepared for casting
+#[cfg(feature = "transcoding-cache")]
+struct CastPreparing(String);
+
+#[cfg(feature = "transcoding-cache")]
+impl Drop for CastPreparing {
+    fn drop(&mut self) {
+        CAST_PREPARING
+            .lock()
+            .expect("Poisoned lock")
+            .remove(&self.0);
+    }
+}
+
+/// Returns transcoded file for casting from cache, if it's not there, file is transcoded into cache first
+/// (or it waits, if same file is already being transcoded). None is returned, if there is no free transcoding slot.
+#[cfg(feature = "transcoding-cache")]
+pub async fn prepare_cast_file(
+    full_path: PathBuf,
+    span: Option<TimeSpan>,
+    transcoding: super::TranscodingDetails,
+) -> crate::error::Result<Option<tokio::fs::File>> {
+    use super::transcode::cache::{cache_key_async, get_cache};
+
+    let quality = ChosenTranscoding::for_cast();
+    let mut commit_checks: Option<u32> = None;
+    loop {
+        let (key, meta) = cache_key_async(&full_path, &quality, span).await?;
+        if let Some((f, _)) = get_cache().get2(&key, meta.into()).await? {
+            return Ok(Some(f));
+        }
+        match commit_checks.as_mut() {
+            Some(n) if *n >= CAST_COMMIT_CHECKS => {
+                crate::error::bail!(
+                    "Transcoded file {:?} for casting is not in cache",
+                    full_path
+                )
+            }
+            Some(n) => {
+                *n += 1;
+                tokio::time::sleep(CAST_PREPARE_INTERVAL).await;
+                continue;
+            }
+            None => (),
+        }
+        if !CAST_PREPARING
+            .lock()
+            .expect("Poisoned lock")
+            .insert(key.clone())
+        {
+            debug!(
+                "File {:?} is already being transcoded for casting",
+                full_path
+            );
+            tokio::time::sleep(CAST_PREPARE_INTERVAL).await;
+            continue;
+        }
+        let _preparing = CastPreparing(key);
+
+        let counter = transcoding.transcodings.clone();
+        let running_transcodings = counter.load(Ordering::Acquire);
+        if running_transcodings >= transcoding.max_transcodings {
+            warn!(
+                "Max transcodings reached {}/{}, cannot transcode for casting",
+                running_transcodings, transcoding.max_transcodings
+            );
+            return Ok(None);
+        }
+        counter.fetch_add(1, Ordering::Release);
+        debug!("Transcoding file {:?} for casting", full_path);
+        let stream = Transcoder::new(quality.clone())
+            .transcode(
+                AudioFilePath::Original(full_path.clone()),
+                None,
+                span,
+                counter,
+            )
+            .await?;
+        // just drain stream, it's written to cache on the way
+        stream.for_each(|_| future::ready(())).await;
+        commit_checks = Some(0);
+    }
+}
+
+/// Sends description, if `as_html` is requested, markdown or plain text description is rendered to HTML
 pub async fn send_description(
     base_path: &'static Path,
     file_path: impl AsRef<Path> + Send + 'static,
     cache: Option<u32>,
     can_compress: bool,
+    conditionals: Conditionals,
+    as_html: bool,
 ) -> ResponseResult {
-    send_folder_metadata(
-        base_path,
-        file_path,
-        "text/plain",
-        cache,
-        |p| extract_description(p).map(|s| s.into()),
-        can_compress,
-    )
-    .await
+    let mut resp = if as_html && guess_mime_type(&file_path).subtype() != "html" {
+        send_extracted(
+            base_path.join(file_path),
+            "text/html; charset=utf-8",
+            cache,
+            |p| {
+                let text = if is_audio(&p) {
+                    extract_description(p)
+                } else {
+                    std::fs::read(&p)
+                        .map_err(|e| error!("Cannot read description {:?}: {}", p, e))
+                        .ok()
+                        .map(|data| String::from_utf8_lossy(&data).into_owned())
+                };
+                text.map(|t| markdown::to_html(&t).into())
+            },
+            can_compress,
+            conditionals,
+        )
+        .await?
+    } else {
+        send_folder_metadata(
+            base_path,
+            file_path,
+            "text/plain",
+            cache,
+            |p| extract_description(p).map(|s| s.into()),
+            can_compress,
+            conditionals,
+        )
+        .await?
+    };
+    // representation can differ based on Accept header
+    resp.headers_mut()
+        .insert(VARY, HeaderValue::from_static("Accept"));
+    Ok(resp)
 }
 
 pub async fn send_cover(
     base_path: &'static Path,
     file_path: impl AsRef<Path> + Send + 'static,
     cache: Option<u32>,
+    conditionals: Conditionals,
 ) -> ResponseResult {
     send_folder_metadata(
         base_path,
@@ -254,10 +667,28 @@ pub async fn send_cover(
         cache,
         extract_cover,
         false,
+        conditionals,
     )
     .await
 }
 
+/// Cover resized to requested size, resized covers are cached
+pub async fn send_resized_cover(
+    base_path: &'static Path,
+    file_path: PathBuf,
+    size: u32,
+) -> ResponseResult {
+    let full_path = base_path.join(file_path);
+    blocking(move || match std::fs::metadata(&full_path) {
+        Ok(meta) if meta.is_file() => resized_cover_response(&full_path, size, meta.into()),
+        Ok(_) => Ok(not_found()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(not_found()),
+        Err(e) => Err(Error::new(e)),
+    })
+    .await
+    .map_err(Error::new)?
+}
+
 pub async fn send_folder_metadata(
     base_path: &'static Path,
     file_path: impl AsRef<Path>,
@@ -265,41 +696,74 @@ pub async fn send_folder_metadata(
     cache: Option<u32>,
     extractor: impl FnOnce(PathBuf) -> Option<Vec<u8>> + Send + 'static,
     compressed: bool,
+    conditionals: Conditionals,
 ) -> ResponseResult {
     if is_audio(&file_path) {
-        // extract description from audio file
-        let full_path = base_path.join(file_path);
-        let fut = blocking(move || {
-            let last_modified = std::fs::metadata(&full_path)
-                .and_then(|meta| meta.modified())
-                .ok();
-            let data = extractor(full_path);
-            match data {
-                None => not_found(),
-                Some(data) => data_response(
+        // extract metadata from audio file
+        send_extracted(
+            base_path.join(file_path),
+            mime,
+            cache,
+            extractor,
+            compressed,
+            conditionals,
+        )
+        .await
+    } else {
+        send_file_simple(base_path, file_path, cache, compressed, Some(&conditionals)).await
+    }
+}
+
+/// Sends data extracted from file, validators are based on the file
+async fn send_extracted(
+    full_path: PathBuf,
+    mime: impl AsRef<str> + Send + 'static,
+    cache: Option<u32>,
+    extractor: impl FnOnce(PathBuf) -> Option<Vec<u8>> + Send + 'static,
+    compressed: bool,
+    conditionals: Conditionals,
+) -> ResponseResult {
+    let fut = blocking(move || {
+        let meta = std::fs::metadata(&full_path).ok();
+        let last_modified = meta.as_ref().and_then(|meta| meta.modified().ok());
+        // validators are based on source file, so extraction can be skipped if client has it
+        let etag = meta.map(|meta| file_etag(meta.len(), last_modified));
+        if let Some(ref etag) = etag {
+            if conditionals.is_not_modified(etag, last_modified) {
+                return not_modified(Some(etag.clone()));
+            }
+        }
+        let data = extractor(full_path);
+        match data {
+            None => not_found(),
+            Some(data) => {
+                let mut resp = data_response(
                     data,
                     mime.as_ref().parse().unwrap(),
                     cache,
                     last_modified,
                     compressed,
-                ),
+                );
+                if let Some(etag) = etag {
+                    resp.headers_mut().typed_insert(etag);
+                }
+                resp
             }
-        })
-        .map_err(Error::from);
-        fut.await
-    } else {
-        send_file_simple(base_path, file_path, cache, compressed).await
-    }
+        }
+    })
+    .map_err(Error::from);
+    fut.await
 }
 
 pub async fn send_folder_icon(
     collection: usize,
     folder_path: PathBuf,
+    size: u32,
     collections: Arc<collection::Collections>,
 ) -> ResponseResult {
     blocking(
         move || match collections.get_folder_cover_path(collection, folder_path) {
-            Ok(Some((p, meta))) => icon_response(p, meta.into()),
+            Ok(Some((p, meta))) => icon_response(p, size, meta.into()),
             Ok(None) => Ok(not_found_cached(get_config().folder_file_cache_age)),
             Err(e) => {
                 error!("error while getting folder icon: {}", e);
@@ -320,8 +784,12 @@ pub async fn download_folder(
     base_path: &'static Path,
     folder_path: PathBuf,
     format: DownloadFormat,
+    recursive: bool,
+    compress: bool,
     include_subfolders: Option<regex::Regex>,
+    folder_filter: Option<super::visibility::FolderFilter>,
 ) -> ResponseResult {
+    use super::archive::{self, ArchiveStats};
     use anyhow::Context;
     use myhy::header::CONTENT_DISPOSITION;
     let full_path = base_path.join(&folder_path);
@@ -337,7 +805,7 @@ pub async fn download_folder(
         }
     };
     if meta.is_file() {
-        serve_file_from_fs(&full_path, None, None, false).await
+        serve_file_from_fs(&full_path, None, None, false, None).await
     } else {
         let mut download_name = folder_path
             .file_name()
@@ -349,52 +817,62 @@ pub async fn download_folder(
 
         let dir_listing = blocking(move || {
             let allow_symlinks = get_config().allow_symlinks;
-            if let Some(folder_re) = include_subfolders {
+            let listing = if recursive {
+                let limits = &get_config().recursive_download;
+                collection::list_dir_files_recursive(
+                    base_path,
+                    &folder_path,
+                    allow_symlinks,
+                    limits.max_files as usize,
+                    limits.max_size_bytes(),
+                )
+            } else if let Some(folder_re) = include_subfolders {
                 collection::list_dir_files_with_subdirs(
                     base_path,
                     &folder_path,
                     allow_symlinks,
                     folder_re,
                 )
+                .map(Some)
             } else {
-                collection::list_dir_files_only(base_path, &folder_path, allow_symlinks)
+                collection::list_dir_files_only(base_path, &folder_path, allow_symlinks).map(Some)
+            };
+            // hidden subfolders are left out
+            match folder_filter {
+                Some(filter) => listing.map(|files| {
+                    files.map(|mut files| {
+                        files.retain(|(_, name, _)| filter.is_allowed(folder_path.join(name)));
+                        files
+                    })
+                }),
+                None => listing,
             }
         })
         .await;
         match dir_listing {
-            Ok(Ok(folder)) => {
-                let total_len: u64 = match format {
-                    DownloadFormat::Tar => {
-                        let lens_iter = folder.iter().map(|i| i.2);
-                        async_tar::calc_size(lens_iter)
-                    }
-                    DownloadFormat::Zip => {
-                        let iter = folder
-                            .iter()
-                            .map(|&(ref path, ref name, len)| (path, name.as_str(), len));
-                        async_zip::calc_size(iter).context("calc zip size")?
-                    }
-                };
-
+            Ok(Ok(None)) => {
+                error!("Folder {:?} is too big for recursive download", full_path);
+                let limits = &get_config().recursive_download;
+                Ok(ApiError::DownloadTooLarge.with_detail(format!(
+                    "max {} files and {} MB",
+                    limits.max_files, limits.max_size
+                )))
+            }
+            Ok(Ok(Some(folder))) => {
+                let total_files = folder.len();
+                let archive = archive::archive(&format, folder, recursive, compress);
+                // size of compressed archive is not known, so it's sent chunked
+                let total_len = archive.size().context("calc archive size")?;
                 debug!("Total len of folder is {:?}", total_len);
-
-                let stream: Box<dyn Stream<Item = _> + Unpin + Send + Sync> = match format {
-                    DownloadFormat::Tar => {
-                        let files = folder.into_iter().map(|i| i.0);
-                        Box::new(async_tar::TarStream::tar_iter(files))
-                    }
-                    DownloadFormat::Zip => {
-                        let files = folder.into_iter().map(|i| (i.0, i.1));
-                        let zipper = async_zip::Zipper::from_iter(files);
-                        Box::new(zipper.zipped_stream())
-                    }
-                };
+                let stream = ArchiveStats::new(download_name.clone(), total_files).track(archive);
 
                 let disposition = format!("attachment; filename=\"{}\"", download_name);
-                let builder = Response::builder()
+                let mut builder = Response::builder()
                     .typed_header(ContentType::from(format.mime()))
-                    .header(CONTENT_DISPOSITION, disposition.as_bytes())
-                    .typed_header(ContentLength(total_len));
+                    .header(CONTENT_DISPOSITION, disposition.as_bytes());
+                if let Some(total_len) = total_len {
+                    builder = builder.typed_header(ContentLength(total_len));
+                }
                 Ok(builder.body(wrap_stream(stream)).unwrap())
             }
             Ok(Err(e)) => Err(Error::new(e).context("listing directory")),
@@ -402,3 +880,35 @@ pub async fn download_folder(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seek_range() {
+        let from_offset = (Bound::Included(1000), Bound::Unbounded);
+        assert_eq!(from_offset, seek_range(1000, None));
+        assert_eq!(
+            from_offset,
+            seek_range(1000, Some((Bound::Included(0), Bound::Included(1))))
+        );
+        let continued = (Bound::Included(5000), Bound::Included(9999));
+        assert_eq!(continued, seek_range(1000, Some(continued)));
+    }
+
+    #[test]
+    fn test_limit_span() {
+        assert!(limit_span(None, Some(10.0), None).is_none());
+        let span = limit_span(None, Some(10.0), Some(60.0)).unwrap();
+        assert_eq!((0, Some(70_000)), (span.start, span.duration));
+        let chapter = Some(TimeSpan {
+            start: 100_000,
+            duration: Some(50_000),
+        });
+        let span = limit_span(chapter, None, Some(30.5)).unwrap();
+        assert_eq!((100_000, Some(30_500)), (span.start, span.duration));
+        let span = limit_span(chapter, Some(40.0), Some(30.0)).unwrap();
+        assert_eq!((100_000, Some(50_000)), (span.start, span.duration));
+    }
+}
@@ -0,0 +1,250 @@
+//! Home screen of client - books in progress and recently added folders from all collections in one response
+
+use super::auth::Account;
+use super::cast::encode_path;
+use super::visibility::FolderFilter;
+use crate::config::get_config;
+use crate::Error;
+use collection::audio_meta::{AudioFolderShort, TimeStamp};
+use collection::{Collections, FoldersOrdering, PositionFilter};
+use myhy::response::{json_response, ResponseResult};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::task::spawn_blocking as blocking;
+
+/// Default number of items in each home screen list
+pub const DEFAULT_HOME_LIMIT: usize = 10;
+/// Maximum number of items in each home screen list
+pub const MAX_HOME_LIMIT: usize = 100;
+
+#[derive(Serialize)]
+struct InProgress {
+    collection: usize,
+    folder: String,
+    file: String,
+    position: f32,
+    timestamp: TimeStamp,
+    name: String,
+    icon: Option<String>,
+    total_time: Option<u32>,
+    /// percent of whole folder listened
+    progress: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct RecentlyAdded {
+    collection: usize,
+    name: String,
+    path: PathBuf,
+    modified: Option<TimeStamp>,
+    total_time: Option<u32>,
+    total_size: Option<u64>,
+    finished: bool,
+    icon: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Home {
+    in_progress: Vec<InProgress>,
+    recently_added: Vec<RecentlyAdded>,
+}
+
+fn icon_url(base_url: &str, collection: usize, path: &Path) -> String {
+    format!("{}/{}/icon/{}", base_url, collection, encode_path(path))
+}
+
+/// Percent of folder listened, files are (path, duration) in playback order
+fn progress<'a>(
+    files: impl IntoIterator<Item = (&'a Path, u32)>,
+    total_time: u32,
+    file: &Path,
+    position: f32,
+) -> Option<f32> {
+    if total_time == 0 {
+        return None;
+    }
+    let mut elapsed = 0u64;
+    for (path, duration) in files {
+        if path == file {
+            let listened = elapsed as f32 + position;
+            return Some((listened / total_time as f32 * 100.0).clamp(0.0, 100.0));
+        }
+        elapsed += u64::from(duration);
+    }
+    None
+}
+
+fn in_progress(
+    collections: &Collections,
+    positions: Vec<collection::Position>,
+    base_url: &str,
+) -> Vec<InProgress> {
+    positions
+        .into_iter()
+        .filter_map(|pos| {
+            // folder might have been removed since position was saved
+            let folder = collections
+                .list_dir(
+                    pos.collection,
+                    &pos.folder,
+                    FoldersOrdering::Alphabetical,
+                    None,
+                )
+                .map_err(|e| debug!("Folder of position {:?} not available: {}", pos, e))
+                .ok()?;
+            let folder_path = Path::new(&pos.folder);
+            let progress = folder.total_time.and_then(|total| {
+                progress(
+                    folder.files.iter().map(|f| {
+                        (
+                            f.path.as_path(),
+                            f.meta.as_ref().map(|m| m.duration).unwrap_or(0),
+                        )
+                    }),
+                    total,
+                    &folder_path.join(&pos.file),
+                    pos.position,
+                )
+            });
+            Some(InProgress {
+                name: folder_path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                icon: folder
+                    .cover
+                    .as_ref()
+                    .map(|_| icon_url(base_url, pos.collection, folder_path)),
+                total_time: folder.total_time,
+                progress,
+                collection: pos.collection,
+                folder: pos.folder,
+                file: pos.file,
+                position: pos.position,
+                timestamp: pos.timestamp,
+            })
+        })
+        .collect()
+}
+
+fn recently_added(
+    collections: &Collections,
+    group: &str,
+    account: Account,
+    folder_filter: Option<FolderFilter>,
+    limit: usize,
+    base_url: &str,
+) -> Vec<RecentlyAdded> {
+    let mut recent: Vec<(usize, AudioFolderShort)> = (0..get_config().base_dirs.len())
+        .filter(|c| get_config().is_collection_allowed(*c, account.as_deref()))
+        .flat_map(|c| {
+            collections
+                .recent(c, limit, Some(group.to_string()))
+                .map_err(|e| error!("Cannot get recent folders for collection {}: {}", c, e))
+                .unwrap_or_default()
+                .into_iter()
+                .map(move |f| (c, f))
+        })
+        .filter(|(_, f)| {
+            folder_filter
+                .as_ref()
+                .map(|filter| filter.is_allowed(&f.path))
+                .unwrap_or(true)
+        })
+        .collect();
+    recent.sort_by_key(|(_, f)| Reverse(f.modified));
+    recent.truncate(limit);
+    recent
+        .into_iter()
+        .map(|(collection, folder)| {
+            let has_cover = collections
+                .get_folder_cover_path(collection, &folder.path)
+                .ok()
+                .flatten()
+                .is_some();
+            RecentlyAdded {
+                icon: has_cover.then(|| icon_url(base_url, collection, &folder.path)),
+                collection,
+                name: folder.name.into_inner(),
+                path: folder.path,
+                modified: folder.modified,
+                total_time: folder.total_time,
+                total_size: folder.total_size,
+                finished: folder.finished,
+            }
+        })
+        .collect()
+}
+
+/// Unfinished books with latest playback positions and recently added folders from all allowed collections
+pub async fn home(
+    collections: Arc<Collections>,
+    group: String,
+    account: Account,
+    limit: usize,
+    base_url: String,
+    compress: bool,
+) -> ResponseResult {
+    let mut positions = collections
+        .clone()
+        .get_all_positions_for_group_async(
+            group.clone(),
+            Some(PositionFilter::new(Some(false), None, None)),
+        )
+        .await;
+    let folder_filter = FolderFilter::for_account(account.as_deref());
+    positions.retain(|p| {
+        get_config().is_collection_allowed(p.collection, account.as_deref())
+            && folder_filter
+                .as_ref()
+                .map(|filter| filter.is_allowed(&p.folder))
+                .unwrap_or(true)
+    });
+    positions.truncate(limit);
+    blocking(move || {
+        let home = Home {
+            in_progress: in_progress(&collections, positions, &base_url),
+            recently_added: recently_added(
+                &collections,
+                &group,
+                account,
+                folder_filter,
+                limit,
+                &base_url,
+            ),
+        };
+        json_response(&home, compress)
+    })
+    .await
+    .map_err(Error::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress() {
+        let files = [
+            (Path::new("book/01.mp3"), 100),
+            (Path::new("book/02.mp3"), 200),
+            (Path::new("book/03.mp3"), 100),
+        ];
+        assert_eq!(
+            Some(50.0),
+            progress(files, 400, Path::new("book/02.mp3"), 100.0)
+        );
+        assert_eq!(
+            Some(0.0),
+            progress(files, 400, Path::new("book/01.mp3"), 0.0)
+        );
+        assert_eq!(
+            Some(100.0),
+            progress(files, 400, Path::new("book/03.mp3"), 150.0)
+        );
+        assert_eq!(None, progress(files, 400, Path::new("book/04.mp3"), 1.0));
+        assert_eq!(None, progress(files, 0, Path::new("book/01.mp3"), 1.0));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::config::get_config;
-use simple_file_cache::{Cache, FileModTime};
-use std::borrow::Cow;
+use simple_file_cache::{Cache, FileModTime, MaintenanceReport};
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::Path;
@@ -25,34 +25,134 @@ lazy_static! {
             )
         }
     };
+    /// Resized covers - separate cache (in subdirectory) for each allowed size, so each size has own limits
+    pub static ref COVERS_CACHE: Option<HashMap<u32, Cache>> = {
+        let cfg = &get_config().covers;
+        if cfg.cache_disabled {
+            None
+        } else {
+            Some(
+                cfg.allowed_sizes
+                    .iter()
+                    .map(|&size| {
+                        let cache_dir = cfg.cache_dir.join(size.to_string());
+                        fs::create_dir_all(&cache_dir)
+                            .expect("Cannot create directory for covers cache");
+                        let cache = Cache::new(
+                            cache_dir,
+                            u64::from(cfg.cache_max_size) * 1024 * 1024,
+                            cfg.cache_max_files.into(),
+                        )
+                        .expect("Cannot create covers cache");
+                        (size, cache)
+                    })
+                    .collect(),
+            )
+        }
+    };
 }
 
-pub fn cached_icon(file: impl AsRef<Path>, mtime: FileModTime) -> Option<File> {
-    let key = cache_key(&file);
-    get_cache()
-        .get(key.as_ref(), mtime)
-        .transpose()
-        .unwrap_or_else(|e| {
-            error!("Icons cache error: {}", e);
-            None
-        })
+fn get_cached(cache: &Cache, key: &str, mtime: FileModTime) -> Option<File> {
+    cache.get(key, mtime).transpose().unwrap_or_else(|e| {
+        error!("Images cache error: {}", e);
+        None
+    })
 }
 
-pub fn cache_icon(
-    file: impl AsRef<Path>,
+fn add_to_cache(
+    cache: &Cache,
+    key: String,
     data: impl AsRef<[u8]>,
     mtime: FileModTime,
 ) -> anyhow::Result<()> {
-    let key = cache_key(&file);
-    let mut f = get_cache().add(key, mtime)?;
+    let mut f = cache.add(key, mtime)?;
     f.write_all(data.as_ref())?;
     f.finish()?;
+    if get_config().icons.cache_save_often {
+        cache.save_index()?;
+    }
+    Ok(())
+}
+
+pub fn cached_icon(file: impl AsRef<Path>, size: u32, mtime: FileModTime) -> Option<File> {
+    get_cached(get_cache(), &cache_key(&file, size), mtime)
+}
+
+pub fn cache_icon(
+    file: impl AsRef<Path>,
+    size: u32,
+    data: impl AsRef<[u8]>,
+    mtime: FileModTime,
+) -> anyhow::Result<()> {
+    add_to_cache(get_cache(), cache_key(&file, size), data, mtime)
+}
+
+fn covers_cache(size: u32) -> Option<&'static Cache> {
+    COVERS_CACHE.as_ref().and_then(|caches| caches.get(&size))
+}
+
+pub fn cached_cover(file: impl AsRef<Path>, size: u32, mtime: FileModTime) -> Option<File> {
+    covers_cache(size).and_then(|cache| get_cached(cache, &cache_key(&file, size), mtime))
+}
+
+pub fn cache_cover(
+    file: impl AsRef<Path>,
+    size: u32,
+    data: impl AsRef<[u8]>,
+    mtime: FileModTime,
+) -> anyhow::Result<()> {
+    match covers_cache(size) {
+        Some(cache) => add_to_cache(cache, cache_key(&file, size), data, mtime),
+        None => Ok(()),
+    }
+}
+
+/// Saves indexes of icons and covers caches, which changed since last save
+pub fn save_changed_indexes() -> anyhow::Result<()> {
+    let caches = CACHE
+        .iter()
+        .chain(COVERS_CACHE.iter().flat_map(|caches| caches.values()));
+    for cache in caches {
+        if cache.save_index_if_changed()? {
+            debug!("Saved images cache index");
+        }
+    }
+    Ok(())
+}
+
+/// Trims icons and covers caches to given percentage of their limits and removes orphaned files,
+/// returns report for each cache with its name
+pub fn maintain_caches(target_percent: u8) -> Vec<(String, anyhow::Result<MaintenanceReport>)> {
+    let pct = u64::from(target_percent);
+    let maintain = |cache: &Cache| {
+        cache
+            .maintain(cache.max_size() * pct / 100, cache.max_files() * pct / 100)
+            .map_err(anyhow::Error::from)
+    };
+    let mut reports = vec![];
+    if let Some(cache) = CACHE.as_ref() {
+        reports.push(("icons".to_string(), maintain(cache)));
+    }
+    if let Some(caches) = COVERS_CACHE.as_ref() {
+        for (size, cache) in caches {
+            reports.push((format!("covers {}", size), maintain(cache)));
+        }
+    }
+    reports
+}
+
+pub fn save_covers_index() -> anyhow::Result<()> {
+    if let Some(caches) = COVERS_CACHE.as_ref() {
+        for cache in caches.values() {
+            cache.save_index()?;
+        }
+    }
     Ok(())
 }
 
 //TODO: not ideal as potential collisions for non-unicode names
-pub fn cache_key<P: AsRef<Path>>(file: &P) -> Cow<'_, str> {
-    file.as_ref().to_string_lossy()
+pub fn cache_key<P: AsRef<Path>>(file: &P, size: u32) -> String {
+    format!("{}@{}", file.as_ref().to_string_lossy(), size)
 }
 
 pub fn get_cache() -> &'static Cache {
@@ -1,14 +1,16 @@
 use anyhow::Result;
 use collection::{audio_meta::is_audio, extract_cover};
-use image::ImageFormat;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
 use image::ImageReader;
+use image::{DynamicImage, ImageFormat};
 use simple_file_cache::FileModTime;
 use std::{
     io::{Cursor, Read},
     path::Path,
 };
 
-use self::cache::{cache_icon, cached_icon};
+use self::cache::{cache_cover, cache_icon, cached_cover, cached_icon};
 use crate::config::get_config;
 use myhy::response::{data_response, HttpResponse};
 
@@ -16,11 +18,12 @@ pub mod cache;
 
 pub fn icon_response(
     path: impl AsRef<Path> + std::fmt::Debug,
+    size: u32,
     mtime: FileModTime,
 ) -> Result<HttpResponse> {
     let cache_enabled = !get_config().icons.cache_disabled;
     let data = match if cache_enabled {
-        cached_icon(&path, mtime)
+        cached_icon(&path, size, mtime)
     } else {
         None
     } {
@@ -30,9 +33,9 @@ pub fn icon_response(
             data
         }
         None => {
-            let data = scale_cover(&path)?;
+            let data = scale_cover(&path, size)?;
             if cache_enabled {
-                cache_icon(path, &data, mtime)
+                cache_icon(path, size, &data, mtime)
                     .unwrap_or_else(|e| error!("error adding icon to cache: {}", e));
             }
             data
@@ -48,8 +51,39 @@ pub fn icon_response(
     ))
 }
 
-pub fn scale_cover(path: impl AsRef<Path> + std::fmt::Debug) -> Result<Vec<u8>> {
-    use image::imageops::FilterType;
+/// Cover resized to fit into size x size square (smaller covers are not enlarged), as JPEG
+pub fn resized_cover_response(
+    path: impl AsRef<Path> + std::fmt::Debug,
+    size: u32,
+    mtime: FileModTime,
+) -> Result<HttpResponse> {
+    let data = match cached_cover(&path, size, mtime) {
+        Some(mut f) => {
+            let mut data = Vec::with_capacity(64 * 1024);
+            f.read_to_end(&mut data)?;
+            data
+        }
+        None => {
+            let data = resize_cover(&path, size)?;
+            cache_cover(path, size, &data, mtime)
+                .unwrap_or_else(|e| error!("error adding cover to cache: {}", e));
+            data
+        }
+    };
+
+    Ok(data_response(
+        data,
+        mime::IMAGE_JPEG,
+        get_config().folder_file_cache_age,
+        None,
+        false,
+    ))
+}
+
+/// Quality of resized covers
+const COVER_JPEG_QUALITY: u8 = 85;
+
+fn load_cover(path: impl AsRef<Path> + std::fmt::Debug) -> Result<DynamicImage> {
     let img = if is_audio(&path) {
         let data = extract_cover(&path)
             .ok_or_else(|| anyhow::Error::msg("Cover is missing, but is expected"))?;
@@ -59,7 +93,11 @@ pub fn scale_cover(path: impl AsRef<Path> + std::fmt::Debug) -> Result<Vec<u8>>
     } else {
         ImageReader::open(&path)?.with_guessed_format()?.decode()?
     };
-    let sz = get_config().icons.size;
+    Ok(img)
+}
+
+pub fn scale_cover(path: impl AsRef<Path> + std::fmt::Debug, sz: u32) -> Result<Vec<u8>> {
+    let img = load_cover(path)?;
     let scaled = img.resize(
         sz,
         sz,
@@ -75,6 +113,18 @@ pub fn scale_cover(path: impl AsRef<Path> + std::fmt::Debug) -> Result<Vec<u8>>
     Ok(data)
 }
 
+/// Covers are photos, so JPEG is used and always best scaling method
+pub fn resize_cover(path: impl AsRef<Path> + std::fmt::Debug, sz: u32) -> Result<Vec<u8>> {
+    let mut img = load_cover(path)?;
+    if img.width() > sz || img.height() > sz {
+        img = img.resize(sz, sz, FilterType::Lanczos3);
+    }
+    let mut data = Vec::with_capacity(64 * 1024);
+    let encoder = JpegEncoder::new_with_quality(&mut data, COVER_JPEG_QUALITY);
+    DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)?;
+    Ok(data)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -82,13 +132,35 @@ mod tests {
     #[test]
     fn test_scale_image() -> anyhow::Result<()> {
         init_default_config();
-        let mut data = scale_cover("test_data/cover.jpg")?;
-        let mut buf = Cursor::new(&mut data);
-        let img2 = ImageReader::with_format(&mut buf, image::ImageFormat::Png).decode()?;
-        let sz = get_config().icons.size;
-        assert_eq!(sz, img2.width());
-        assert_eq!(sz, img2.height());
-        assert!(data.len() > 1024);
+        for sz in [get_config().icons.size, 64] {
+            let mut data = scale_cover("test_data/cover.jpg", sz)?;
+            let mut buf = Cursor::new(&mut data);
+            let img2 = ImageReader::with_format(&mut buf, image::ImageFormat::Png).decode()?;
+            assert_eq!(sz, img2.width());
+            assert_eq!(sz, img2.height());
+            assert!(data.len() > 1024);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_resize_cover() -> anyhow::Result<()> {
+        init_default_config();
+        let orig = ImageReader::open("test_data/cover.jpg")?.decode()?;
+        let sz = orig.width().max(orig.height()) / 2;
+        let data = resize_cover("test_data/cover.jpg", sz)?;
+        let img = ImageReader::with_format(Cursor::new(&data), ImageFormat::Jpeg).decode()?;
+        assert_eq!(sz, img.width().max(img.height()));
+        // aspect ratio is kept
+        let ratio = |w: u32, h: u32| w as f32 / h as f32;
+        assert!(
+            (ratio(orig.width(), orig.height()) - ratio(img.width(), img.height())).abs() < 0.02
+        );
+
+        // small cover is not enlarged
+        let data = resize_cover("test_data/cover.jpg", 4096)?;
+        let img = ImageReader::with_format(Cursor::new(&data), ImageFormat::Jpeg).decode()?;
+        assert_eq!(orig.width(), img.width());
         Ok(())
     }
 }
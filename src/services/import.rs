@@ -0,0 +1,533 @@
+//! Watch folder import - audio files dropped into import directory are moved into collection,
+//! into Author/Title folder based on their tags, optionally transcoded to configured quality.
+//! Each directory in import directory is imported as one unit (usually one book), audio files directly
+//! in import directory are imported one by one. Unit is imported only when none of its files was
+//! modified for settle time, so it's completely copied. Units, which cannot be imported,
+//! are moved to `.failed` directory.
+
+use super::transcode::{ChosenTranscoding, QualityLevel, Transcoder};
+use crate::config::{get_config, ImportConfig};
+use crate::error::{bail, Context, Result};
+use collection::audio_meta::{get_audio_properties_uni, is_audio, MediaInfo, TimeStamp};
+use collection::{tags, Collections};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+const FAILED_DIR: &str = ".failed";
+const UNKNOWN_AUTHOR: &str = "Unknown Author";
+const MAX_RECENT_RECORDS: usize = 20;
+
+lazy_static! {
+    static ref STATUS: Mutex<ImportStatus> = Mutex::new(ImportStatus::default());
+}
+
+/// Result of import of one unit
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct ImportRecord {
+    /// name of file or directory in import directory
+    pub source: String,
+    /// path of imported folder in collection
+    #[cfg_attr(feature = "api-spec", schema(value_type = Option<String>))]
+    pub target: Option<PathBuf>,
+    pub files: usize,
+    pub error: Option<String>,
+    pub time: TimeStamp,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct ImportStatus {
+    #[cfg_attr(feature = "api-spec", schema(value_type = String))]
+    pub dir: PathBuf,
+    pub collection: usize,
+    pub transcode: Option<String>,
+    /// when import directory was last checked
+    pub last_check: Option<TimeStamp>,
+    /// units waiting until they are settled
+    pub pending: usize,
+    pub imported: u64,
+    pub failed: u64,
+    /// most recent imports, newest first
+    #[cfg_attr(feature = "api-spec", schema(value_type = Vec<ImportRecord>))]
+    pub recent: VecDeque<ImportRecord>,
+}
+
+impl ImportStatus {
+    fn add_record(&mut self, record: ImportRecord) {
+        if record.error.is_some() {
+            self.failed += 1
+        } else {
+            self.imported += 1
+        }
+        self.recent.push_front(record);
+        self.recent.truncate(MAX_RECENT_RECORDS);
+    }
+}
+
+/// Current status of import, None if import is not configured
+pub fn import_status() -> Option<ImportStatus> {
+    get_config()
+        .import
+        .as_ref()
+        .map(|_| STATUS.lock().expect("Poisoned lock").clone())
+}
+
+/// File or directory in import directory, which is imported at once
+#[derive(Debug, Clone)]
+struct ImportUnit {
+    source: PathBuf,
+    /// all files in unit, relative to source for directory
+    files: Vec<PathBuf>,
+}
+
+impl ImportUnit {
+    fn is_dir(&self) -> bool {
+        self.source.is_dir()
+    }
+
+    fn name(&self) -> String {
+        self.source
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    fn file_path(&self, file: &Path) -> PathBuf {
+        if self.is_dir() {
+            self.source.join(file)
+        } else {
+            self.source.clone()
+        }
+    }
+}
+
+fn is_hidden(p: &Path) -> bool {
+    p.file_name()
+        .map(|n| n.to_string_lossy().starts_with('.'))
+        .unwrap_or(false)
+}
+
+/// Collects files of directory recursively, returns latest modification time of files and directories
+fn collect_files(
+    dir: &Path,
+    rel_path: &Path,
+    files: &mut Vec<PathBuf>,
+    mut latest: SystemTime,
+) -> Result<SystemTime> {
+    latest = latest.max(fs::metadata(dir)?.modified()?);
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            latest = collect_files(&path, &rel_path.join(entry.file_name()), files, latest)?;
+        } else {
+            latest = latest.max(meta.modified()?);
+            files.push(rel_path.join(entry.file_name()));
+        }
+    }
+    Ok(latest)
+}
+
+/// Returns units ready for import and number of units, which are not settled yet
+fn find_ready_units(dir: &Path, settle_time: Duration) -> Result<(Vec<ImportUnit>, usize)> {
+    let now = SystemTime::now();
+    let is_settled = |t: SystemTime| {
+        now.duration_since(t)
+            .map(|d| d >= settle_time)
+            .unwrap_or(false)
+    };
+    let mut ready = vec![];
+    let mut pending = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if is_hidden(&path) {
+            continue;
+        }
+        let meta = entry.metadata()?;
+        if meta.is_dir() {
+            let mut files = vec![];
+            let latest = collect_files(&path, Path::new(""), &mut files, SystemTime::UNIX_EPOCH)?;
+            if files.is_empty() {
+                continue;
+            }
+            if is_settled(latest) {
+                files.sort();
+                ready.push(ImportUnit {
+                    source: path,
+                    files,
+                })
+            } else {
+                pending += 1
+            }
+        } else if is_audio(&path) {
+            if is_settled(meta.modified()?) {
+                ready.push(ImportUnit {
+                    files: vec![PathBuf::from(entry.file_name())],
+                    source: path,
+                })
+            } else {
+                pending += 1
+            }
+        }
+    }
+    ready.sort_by(|a, b| a.source.cmp(&b.source));
+    Ok((ready, pending))
+}
+
+/// Makes name usable as directory name
+fn sanitize_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    name.trim().trim_start_matches('.').trim().to_string()
+}
+
+/// Author and title folder names from tags
+fn folder_names(tags: Option<&HashMap<String, String>>, fallback_title: &str) -> (String, String) {
+    let tag = |names: &[&str]| {
+        names.iter().find_map(|n| {
+            tags.and_then(|t| t.get(*n))
+                .map(|v| sanitize_name(v))
+                .filter(|v| !v.is_empty())
+        })
+    };
+    let author = tag(&[tags::ALBUM_ARTIST, tags::ARTIST]).unwrap_or_else(|| UNKNOWN_AUTHOR.into());
+    let title = tag(&[tags::ALBUM])
+        .or_else(|| Some(sanitize_name(fallback_title)).filter(|t| !t.is_empty()))
+        .unwrap_or_else(|| "Unknown Title".into());
+    (author, title)
+}
+
+/// What should be done with the unit
+#[derive(Debug)]
+struct ImportPlan {
+    /// relative path in collection
+    target: PathBuf,
+    collection_dir: PathBuf,
+    /// source path, destination path and if file should be transcoded
+    files: Vec<(PathBuf, PathBuf, bool)>,
+}
+
+fn plan_import(
+    unit: &ImportUnit,
+    collection_dir: &Path,
+    extension: Option<&str>,
+) -> Result<ImportPlan> {
+    let first = match unit.files.iter().find(|f| is_audio(f)) {
+        Some(f) => unit.file_path(f),
+        None => bail!("No audio files"),
+    };
+    let required_tags: HashSet<String> = [tags::ALBUM_ARTIST, tags::ARTIST, tags::ALBUM]
+        .iter()
+        .map(|t| t.to_string())
+        .collect();
+    let meta = get_audio_properties_uni(&first)
+        .with_context(|| format!("Cannot read metadata of {:?}", first))?
+        .get_audio_info(&Some(required_tags));
+    let fallback_title = if unit.is_dir() {
+        unit.name()
+    } else {
+        first
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+    let (author, title) =
+        folder_names(meta.as_ref().and_then(|m| m.tags.as_ref()), &fallback_title);
+    let target = Path::new(&author).join(title);
+    let target_dir = collection_dir.join(&target);
+
+    let mut files = Vec::with_capacity(unit.files.len());
+    for f in &unit.files {
+        let transcode = extension.is_some() && is_audio(f);
+        let mut dest = target_dir.join(f);
+        if transcode {
+            dest.set_extension(extension.unwrap());
+        }
+        if dest.exists() {
+            bail!("File {:?} already exists in collection", dest);
+        }
+        files.push((unit.file_path(f), dest, transcode));
+    }
+    Ok(ImportPlan {
+        target,
+        collection_dir: collection_dir.to_owned(),
+        files,
+    })
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_err() {
+        // probably different filesystem
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+async fn import_file(from: &Path, to: &Path, transcoder: Option<&Transcoder>) -> Result<()> {
+    match transcoder {
+        Some(transcoder) => {
+            if let Some(parent) = to.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            transcoder.transcode_to_file(from, to).await
+        }
+        None => {
+            let (from, to) = (from.to_owned(), to.to_owned());
+            tokio::task::spawn_blocking(move || move_file(&from, &to)).await?
+        }
+    }
+}
+
+async fn execute_plan(plan: &ImportPlan, transcoder: Option<&Transcoder>) -> Result<()> {
+    for (i, (from, to, transcode)) in plan.files.iter().enumerate() {
+        let transcoder = transcoder.filter(|_| *transcode);
+        if let Err(e) = import_file(from, to, transcoder).await {
+            // files already moved are returned back, transcoded ones are removed,
+            // so collection is not left with incomplete folder
+            if from.exists() {
+                fs::remove_file(to).ok();
+            }
+            for (from, to, transcode) in &plan.files[..i] {
+                if *transcode {
+                    fs::remove_file(to).ok();
+                } else if let Err(e) = move_file(to, from) {
+                    error!("Cannot return imported file {:?} back: {}", to, e);
+                }
+            }
+            remove_empty_dirs(&plan.collection_dir.join(&plan.target));
+            for dir in plan.target.ancestors().skip(1) {
+                if dir.as_os_str().is_empty()
+                    || fs::remove_dir(plan.collection_dir.join(dir)).is_err()
+                {
+                    break;
+                }
+            }
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Removes directory, if it contains only empty directories
+fn remove_empty_dirs(dir: &Path) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                remove_empty_dirs(&entry.path())
+            }
+        }
+    }
+    fs::remove_dir(dir).ok();
+}
+
+/// Moves unit to failed directory, so it's not tried again
+fn move_to_failed(dir: &Path, unit: &ImportUnit) -> Result<()> {
+    let failed_dir = dir.join(FAILED_DIR);
+    fs::create_dir_all(&failed_dir)?;
+    let name = unit.name();
+    let mut dest = failed_dir.join(&name);
+    let mut n = 0;
+    while dest.exists() {
+        n += 1;
+        dest = failed_dir.join(format!("{}.{}", name, n));
+    }
+    fs::rename(&unit.source, dest)?;
+    Ok(())
+}
+
+fn remove_source(unit: &ImportUnit) -> Result<()> {
+    if unit.is_dir() {
+        fs::remove_dir_all(&unit.source)?
+    } else if unit.source.exists() {
+        fs::remove_file(&unit.source)?
+    }
+    Ok(())
+}
+
+async fn import_unit(
+    unit: &ImportUnit,
+    collections: &Arc<Collections>,
+    cfg: &'static ImportConfig,
+    transcoder: Option<&Transcoder>,
+    extension: Option<&'static str>,
+) -> Result<PathBuf> {
+    let collection_dir = get_config().base_dirs[cfg.collection].clone();
+    let plan = {
+        let unit = unit.clone();
+        tokio::task::spawn_blocking(move || plan_import(&unit, &collection_dir, extension))
+            .await??
+    };
+    execute_plan(&plan, transcoder).await?;
+    if let Err(e) = remove_source(unit) {
+        error!("Cannot remove imported source {:?}: {}", unit.source, e)
+    }
+    let collections = collections.clone();
+    let target = plan.target.clone();
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        for folder in target.ancestors() {
+            collections.refresh_folder(cfg.collection, folder)?;
+        }
+        Ok(())
+    })
+    .await?
+    .unwrap_or_else(|e| error!("Cannot refresh imported folder: {}", e));
+    Ok(plan.target)
+}
+
+async fn check_import_dir(
+    collections: &Arc<Collections>,
+    cfg: &'static ImportConfig,
+    transcoder: Option<&Transcoder>,
+    extension: Option<&'static str>,
+) -> Result<()> {
+    let dir = cfg.dir.clone();
+    let settle_time = Duration::from_secs(cfg.settle_time.into());
+    let (units, pending) =
+        tokio::task::spawn_blocking(move || find_ready_units(&dir, settle_time)).await??;
+    {
+        let mut status = STATUS.lock().expect("Poisoned lock");
+        status.last_check = Some(TimeStamp::now());
+        status.pending = pending;
+    }
+    for unit in units {
+        let res = import_unit(&unit, collections, cfg, transcoder, extension).await;
+        let record = match res {
+            Ok(target) => {
+                info!("Imported {:?} into {:?}", unit.source, target);
+                ImportRecord {
+                    source: unit.name(),
+                    target: Some(target),
+                    files: unit.files.len(),
+                    error: None,
+                    time: TimeStamp::now(),
+                }
+            }
+            Err(e) => {
+                error!("Import of {:?} failed: {:#}", unit.source, e);
+                if let Err(e) = move_to_failed(&cfg.dir, &unit) {
+                    error!("Cannot move {:?} to failed: {}", unit.source, e)
+                }
+                ImportRecord {
+                    source: unit.name(),
+                    target: None,
+                    files: unit.files.len(),
+                    error: Some(format!("{:#}", e)),
+                    time: TimeStamp::now(),
+                }
+            }
+        };
+        STATUS.lock().expect("Poisoned lock").add_record(record);
+    }
+    Ok(())
+}
+
+/// Regularly checks import directory and imports settled files
+pub async fn run_import(collections: Arc<Collections>) {
+    let cfg = match get_config().import.as_ref() {
+        Some(cfg) => cfg,
+        None => return,
+    };
+    let transcoding = cfg
+        .transcode
+        .as_ref()
+        .and_then(QualityLevel::from_letter)
+        .map(|level| ChosenTranscoding::for_level_and_user_agent(level, None));
+    let extension = transcoding.as_ref().and_then(|t| t.format.extension());
+    if transcoding.is_some() && extension.is_none() {
+        error!("Transcoding format cannot be used for import, files will be imported as they are");
+    }
+    let transcoder = transcoding
+        .filter(|_| extension.is_some())
+        .map(Transcoder::new);
+    {
+        let mut status = STATUS.lock().expect("Poisoned lock");
+        status.dir = cfg.dir.clone();
+        status.collection = cfg.collection;
+        status.transcode = cfg.transcode.clone();
+    }
+    info!("Watching import directory {:?}", cfg.dir);
+    let mut interval = tokio::time::interval(Duration::from_secs(cfg.interval.into()));
+    loop {
+        interval.tick().await;
+        if let Err(e) = check_import_dir(&collections, cfg, transcoder.as_ref(), extension).await {
+            error!("Error checking import directory {:?}: {:#}", cfg.dir, e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_folder_names() {
+        assert_eq!("AC_DC", sanitize_name(" AC/DC "));
+        assert_eq!("hidden", sanitize_name("..hidden"));
+
+        let mut tags = HashMap::new();
+        tags.insert(tags::ARTIST.to_string(), "Reader".to_string());
+        tags.insert(tags::ALBUM.to_string(), "Book: Part 1".to_string());
+        assert_eq!(
+            ("Reader".to_string(), "Book_ Part 1".to_string()),
+            folder_names(Some(&tags), "dir")
+        );
+        tags.insert(tags::ALBUM_ARTIST.to_string(), "Author".to_string());
+        assert_eq!("Author", folder_names(Some(&tags), "dir").0);
+        assert_eq!(
+            (UNKNOWN_AUTHOR.to_string(), "dir".to_string()),
+            folder_names(None, "dir")
+        );
+    }
+
+    #[test]
+    fn test_find_ready_units() {
+        let tmp_dir = tempdir::TempDir::new("AS_IMPORT_TEST").unwrap();
+        let dir = tmp_dir.path();
+        fs::create_dir_all(dir.join("book/cd1")).unwrap();
+        fs::write(dir.join("book/cd1/01.mp3"), b"audio").unwrap();
+        fs::write(dir.join("book/cover.jpg"), b"image").unwrap();
+        fs::create_dir(dir.join("empty")).unwrap();
+        fs::create_dir_all(dir.join(".failed/old")).unwrap();
+        fs::write(dir.join(".failed/old/01.mp3"), b"audio").unwrap();
+        fs::write(dir.join("single.mp3"), b"audio").unwrap();
+        fs::write(dir.join("notes.txt"), b"text").unwrap();
+
+        let (units, pending) = find_ready_units(dir, Duration::from_secs(3600)).unwrap();
+        assert!(units.is_empty());
+        assert_eq!(2, pending);
+
+        let (units, pending) = find_ready_units(dir, Duration::ZERO).unwrap();
+        assert_eq!(0, pending);
+        assert_eq!(2, units.len());
+        assert_eq!(
+            vec![PathBuf::from("cd1/01.mp3"), PathBuf::from("cover.jpg")],
+            units[0].files
+        );
+        assert_eq!(
+            dir.join("book/cover.jpg"),
+            units[0].file_path(Path::new("cover.jpg"))
+        );
+        assert_eq!(
+            dir.join("single.mp3"),
+            units[1].file_path(&units[1].files[0])
+        );
+        assert_eq!("single.mp3", units[1].name());
+    }
+}
@@ -0,0 +1,420 @@
+//! Simple Markdown to HTML renderer for folder descriptions
+//!
+//! Supports only common subset of Markdown - headings, paragraphs, lists, block quotes,
+//! code blocks, rules, emphasis, code spans, links and images.
+//! Raw HTML is not supported - all text is escaped, so result is safe to show in client,
+//! also links are restricted to http(s) and mailto schemes (or relative links).
+
+/// Renders Markdown text to HTML fragment
+pub fn to_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() * 5 / 4);
+    render_blocks(&text.lines().collect::<Vec<_>>(), &mut out);
+    out
+}
+
+fn render_blocks(lines: &[&str], out: &mut String) {
+    let mut para: Vec<&str> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i].trim_start();
+        if line.is_empty() {
+            flush_paragraph(&mut para, out);
+            i += 1;
+        } else if line.starts_with("```") || line.starts_with("~~~") {
+            flush_paragraph(&mut para, out);
+            let fence = &line[..3];
+            out.push_str("<pre><code>");
+            i += 1;
+            while i < lines.len() && !lines[i].trim_start().starts_with(fence) {
+                escape_into(lines[i], out);
+                out.push('\n');
+                i += 1;
+            }
+            out.push_str("</code></pre>\n");
+            // skip closing fence
+            i += 1;
+        } else if let Some((level, content)) = heading(line) {
+            flush_paragraph(&mut para, out);
+            out.push_str(&format!("<h{}>", level));
+            render_inline(content, out);
+            out.push_str(&format!("</h{}>\n", level));
+            i += 1;
+        } else if is_rule(line) {
+            flush_paragraph(&mut para, out);
+            out.push_str("<hr>\n");
+            i += 1;
+        } else if line.starts_with('>') {
+            flush_paragraph(&mut para, out);
+            let mut quoted = Vec::new();
+            while i < lines.len() {
+                match lines[i].trim_start().strip_prefix('>') {
+                    Some(l) => quoted.push(l.strip_prefix(' ').unwrap_or(l)),
+                    None => break,
+                }
+                i += 1;
+            }
+            out.push_str("<blockquote>\n");
+            render_blocks(&quoted, out);
+            out.push_str("</blockquote>\n");
+        } else if let Some((ordered, _)) = list_item(line) {
+            flush_paragraph(&mut para, out);
+            i = render_list(lines, i, ordered, out);
+        } else {
+            para.push(line);
+            i += 1;
+        }
+    }
+    flush_paragraph(&mut para, out);
+}
+
+fn render_list(lines: &[&str], mut i: usize, ordered: bool, out: &mut String) -> usize {
+    let tag = if ordered { "ol" } else { "ul" };
+    out.push_str(&format!("<{}>\n", tag));
+    let mut item: Vec<&str> = Vec::new();
+    let flush_item = |item: &mut Vec<&str>, out: &mut String| {
+        if !item.is_empty() {
+            out.push_str("<li>");
+            render_lines(item, out);
+            out.push_str("</li>\n");
+            item.clear();
+        }
+    };
+    while i < lines.len() {
+        let line = lines[i].trim_start();
+        if line.is_empty() {
+            // list continues only if next non empty line is item of same list
+            let next = lines[i..]
+                .iter()
+                .map(|l| l.trim_start())
+                .find(|l| !l.is_empty());
+            match next.and_then(list_item) {
+                Some((o, _)) if o == ordered => i += 1,
+                _ => break,
+            }
+        } else if let Some((o, content)) = list_item(line) {
+            if o != ordered || is_rule(line) {
+                break;
+            }
+            flush_item(&mut item, out);
+            item.push(content);
+            i += 1;
+        } else if heading(line).is_some() || line.starts_with('>') || line.starts_with("```") {
+            break;
+        } else {
+            item.push(line);
+            i += 1;
+        }
+    }
+    flush_item(&mut item, out);
+    out.push_str(&format!("</{}>\n", tag));
+    i
+}
+
+fn flush_paragraph(para: &mut Vec<&str>, out: &mut String) {
+    if !para.is_empty() {
+        out.push_str("<p>");
+        render_lines(para, out);
+        out.push_str("</p>\n");
+        para.clear();
+    }
+}
+
+/// Renders lines of one block, line ending with two spaces or backslash is hard break
+fn render_lines(lines: &[&str], out: &mut String) {
+    for (idx, line) in lines.iter().enumerate() {
+        let last = idx == lines.len() - 1;
+        let hard_break = !last && (line.ends_with("  ") || line.ends_with('\\'));
+        let line = if hard_break {
+            line.trim_end_matches('\\').trim_end()
+        } else {
+            line.trim_end()
+        };
+        render_inline(line, out);
+        if hard_break {
+            out.push_str("<br>");
+        }
+        if !last {
+            out.push('\n');
+        }
+    }
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&level) {
+        let rest = &line[level..];
+        if rest.is_empty() || rest.starts_with(' ') {
+            return Some((level, rest.trim().trim_end_matches('#').trim_end()));
+        }
+    }
+    None
+}
+
+fn is_rule(line: &str) -> bool {
+    let mut chars = line.chars().filter(|c| !c.is_whitespace());
+    match chars.next() {
+        Some(c @ ('-' | '*' | '_')) => {
+            let rest: Vec<_> = chars.collect();
+            rest.len() >= 2 && rest.iter().all(|x| *x == c)
+        }
+        _ => false,
+    }
+}
+
+/// Returns if item is ordered and item content
+fn list_item(line: &str) -> Option<(bool, &str)> {
+    for marker in ["- ", "* ", "+ "] {
+        if let Some(content) = line.strip_prefix(marker) {
+            return Some((false, content.trim_start()));
+        }
+    }
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    if (1..10).contains(&digits) {
+        let rest = &line[digits..];
+        if let Some(content) = rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") ")) {
+            return Some((true, content.trim_start()));
+        }
+    }
+    None
+}
+
+fn render_inline(text: &str, out: &mut String) {
+    let chars: Vec<char> = text.chars().collect();
+    render_inline_chars(&chars, out)
+}
+
+fn render_inline_chars(chars: &[char], out: &mut String) {
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' if chars.get(i + 1).is_some_and(|n| n.is_ascii_punctuation()) => {
+                escape_char(chars[i + 1], out);
+                i += 2;
+            }
+            '`' => match find(chars, i + 1, &['`']) {
+                Some(end) => {
+                    out.push_str("<code>");
+                    chars[i + 1..end].iter().for_each(|c| escape_char(*c, out));
+                    out.push_str("</code>");
+                    i = end + 1;
+                }
+                None => {
+                    escape_char(c, out);
+                    i += 1;
+                }
+            },
+            '*' | '_' => {
+                let intraword = c == '_' && i > 0 && chars[i - 1].is_alphanumeric();
+                let double = chars.get(i + 1) == Some(&c);
+                let marker: &[char] = if double { &[c, c] } else { &[c] };
+                let start = i + marker.len();
+                let end = if intraword {
+                    None
+                } else {
+                    find_emphasis_end(chars, start, marker)
+                };
+                match end {
+                    Some(end) => {
+                        let tag = if double { "strong" } else { "em" };
+                        out.push_str(&format!("<{}>", tag));
+                        render_inline_chars(&chars[start..end], out);
+                        out.push_str(&format!("</{}>", tag));
+                        i = end + marker.len();
+                    }
+                    None => {
+                        marker.iter().for_each(|c| escape_char(*c, out));
+                        i = start;
+                    }
+                }
+            }
+            '!' if chars.get(i + 1) == Some(&'[') => match parse_link(chars, i + 1) {
+                Some((alt, url, next)) => {
+                    if is_safe_url(&url) {
+                        out.push_str("<img src=\"");
+                        escape_into(&url, out);
+                        out.push_str("\" alt=\"");
+                        alt.iter().for_each(|c| escape_char(*c, out));
+                        out.push_str("\">");
+                    } else {
+                        alt.iter().for_each(|c| escape_char(*c, out));
+                    }
+                    i = next;
+                }
+                None => {
+                    escape_char(c, out);
+                    i += 1;
+                }
+            },
+            '[' => match parse_link(chars, i) {
+                Some((label, url, next)) => {
+                    if is_safe_url(&url) {
+                        out.push_str("<a href=\"");
+                        escape_into(&url, out);
+                        out.push_str("\">");
+                        render_inline_chars(label, out);
+                        out.push_str("</a>");
+                    } else {
+                        render_inline_chars(label, out);
+                    }
+                    i = next;
+                }
+                None => {
+                    escape_char(c, out);
+                    i += 1;
+                }
+            },
+            '<' => {
+                let autolink = find(chars, i + 1, &['>']).and_then(|end| {
+                    let url: String = chars[i + 1..end].iter().collect();
+                    (url.contains("://") && is_safe_url(&url) && !url.contains(char::is_whitespace))
+                        .then_some((url, end))
+                });
+                match autolink {
+                    Some((url, end)) => {
+                        out.push_str("<a href=\"");
+                        escape_into(&url, out);
+                        out.push_str("\">");
+                        escape_into(&url, out);
+                        out.push_str("</a>");
+                        i = end + 1;
+                    }
+                    None => {
+                        escape_char(c, out);
+                        i += 1;
+                    }
+                }
+            }
+            _ => {
+                escape_char(c, out);
+                i += 1;
+            }
+        }
+    }
+}
+
+fn find(chars: &[char], from: usize, pattern: &[char]) -> Option<usize> {
+    (from..chars.len()).find(|&i| chars[i..].starts_with(pattern))
+}
+
+fn find_emphasis_end(chars: &[char], start: usize, marker: &[char]) -> Option<usize> {
+    if chars.get(start).map(|c| c.is_whitespace()).unwrap_or(true) {
+        return None;
+    }
+    let mut from = start + 1;
+    while let Some(end) = find(chars, from, marker) {
+        let single_in_double = marker.len() == 1 && chars.get(end + 1) == Some(&marker[0]);
+        if !chars[end - 1].is_whitespace() && !single_in_double {
+            return Some(end);
+        }
+        from = end + if single_in_double { 2 } else { 1 };
+    }
+    None
+}
+
+/// Parses [label](url) starting at opening bracket, returns label, url and index after link
+fn parse_link(chars: &[char], start: usize) -> Option<(&[char], String, usize)> {
+    let mut depth = 0;
+    let mut label_end = None;
+    for (i, c) in chars.iter().enumerate().skip(start) {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    label_end = Some(i);
+                    break;
+                }
+            }
+            _ => (),
+        }
+    }
+    let label_end = label_end?;
+    if chars.get(label_end + 1) != Some(&'(') {
+        return None;
+    }
+    let url_end = find(chars, label_end + 2, &[')'])?;
+    let target: String = chars[label_end + 2..url_end].iter().collect();
+    // optional title is ignored
+    let url = target.split_whitespace().next().unwrap_or_default();
+    let url = url
+        .strip_prefix('<')
+        .and_then(|u| u.strip_suffix('>'))
+        .unwrap_or(url);
+    Some((&chars[start + 1..label_end], url.to_string(), url_end + 1))
+}
+
+fn is_safe_url(url: &str) -> bool {
+    let scheme_end = url.find(':');
+    let path_start = url.find(['/', '?', '#']);
+    match (scheme_end, path_start) {
+        (Some(s), Some(p)) if p < s => true,
+        (Some(s), _) => {
+            let scheme = url[..s].to_ascii_lowercase();
+            matches!(scheme.as_str(), "http" | "https" | "mailto")
+        }
+        (None, _) => !url.is_empty(),
+    }
+}
+
+fn escape_into(s: &str, out: &mut String) {
+    s.chars().for_each(|c| escape_char(c, out))
+}
+
+fn escape_char(c: char, out: &mut String) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        '\'' => out.push_str("&#39;"),
+        c => out.push(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks() {
+        let md = "# Title #\n\nFirst line\nsecond line  \nthird\n\n- one\n- two\n  continued\n\n1. first\n2. second\n\n> quote\n\n---\n```\nlet x = <y>;\n```";
+        let html = to_html(md);
+        assert_eq!(
+            "<h1>Title</h1>\n<p>First line\nsecond line<br>\nthird</p>\n\
+             <ul>\n<li>one</li>\n<li>two\ncontinued</li>\n</ul>\n\
+             <ol>\n<li>first</li>\n<li>second</li>\n</ol>\n\
+             <blockquote>\n<p>quote</p>\n</blockquote>\n<hr>\n\
+             <pre><code>let x = &lt;y&gt;;\n</code></pre>\n",
+            html
+        );
+    }
+
+    #[test]
+    fn test_inline() {
+        let html = to_html("**Bold** and *em* and `a<b` and snake_case_name and 2 * 3 * 4");
+        assert_eq!(
+            "<p><strong>Bold</strong> and <em>em</em> and <code>a&lt;b</code> and snake_case_name and 2 * 3 * 4</p>\n",
+            html
+        );
+        let html = to_html("[Author](https://example.com/a?b=1&c=2 \"title\") ![cover](cover.jpg)");
+        assert_eq!(
+            "<p><a href=\"https://example.com/a?b=1&amp;c=2\">Author</a> <img src=\"cover.jpg\" alt=\"cover\"></p>\n",
+            html
+        );
+    }
+
+    #[test]
+    fn test_sanitization() {
+        let html =
+            to_html("<script>alert('x')</script> [click](javascript:alert(1)) <https://ok.com>");
+        assert_eq!(
+            "<p>&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt; click) <a href=\"https://ok.com\">https://ok.com</a></p>\n",
+            html
+        );
+        assert!(!is_safe_url("JavaScript:x"));
+        assert!(is_safe_url("mailto:me@example.com"));
+        assert!(is_safe_url("../cover.jpg"));
+        assert!(is_safe_url("/path?x=a:b"));
+    }
+}
@@ -1,45 +1,80 @@
-use self::auth::{AuthResult, Authenticator};
+use self::auth::{Account, AuthResult, Authenticator};
+use self::router::{Route, RouteError, RouteMatch};
 use self::search::Search;
 use self::transcode::QualityLevel;
-use crate::config::{get_config, Cors};
+use self::types::ApiError;
+use crate::config::{get_config, Cors, LimitsConfig, GUEST_ACCOUNT};
 use crate::error;
 use crate::services::transcode::ChosenTranscoding;
-use myhy::request::{is_cors_matching_origin, HttpRequest, QueryParams, RequestWrapper};
-use myhy::response::body::HttpBody;
+use myhy::request::{
+    is_cors_matching_origin, resolve_remote_addr, GenericRequestWrapper, HttpRequest, QueryParams,
+    RequestWrapper,
+};
+use myhy::response::body::{inspect_body, throttle_body, HttpBody};
 use myhy::response::{
     self,
     cors::{add_cors_headers, preflight_cors_response},
-    file::send_static_file,
-    HttpResponse, ResponseFuture, ResponseResult,
+    file::{send_static_file, ByteRange},
+    Conditionals, HttpResponse, ResponseFuture, ResponseResult,
 };
 
-use collection::{Collections, FoldersOrdering};
+use arc_swap::ArcSwapOption;
+use collection::{Collections, FilesOrdering, FoldersOrdering};
 use futures::{future, TryFutureExt};
-use leaky_cauldron::Leaky;
-use myhy::headers::{HeaderMapExt, Origin, Range, UserAgent};
+use leaky_cauldron::{LabeledLeaky, Leaky};
+use myhy::header::{HeaderValue, ACCEPT, HOST};
+use myhy::headers::{HeaderMapExt, IfNoneMatch, Origin, Range, UserAgent};
 use myhy::Incoming;
 use myhy::Method;
 use myhy::Service;
 
+#[cfg(feature = "folder-download")]
 use regex::Regex;
 use std::{
-    net::SocketAddr,
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{atomic::AtomicUsize, Arc, Mutex},
 };
 use tokio::sync::watch;
 
+pub mod access_log;
 pub mod api;
+#[cfg(feature = "folder-download")]
+mod archive;
 pub mod auth;
+mod cast;
+pub mod discovery;
+pub mod dlna;
+pub mod events;
 mod files;
+#[cfg(feature = "shared-positions")]
+mod home;
 pub mod icon;
+pub mod import;
+mod markdown;
+mod pairing;
+mod peaks;
 #[cfg(feature = "shared-positions")]
 pub mod position;
+mod router;
+#[cfg(feature = "scrobbler")]
+pub mod scrobbler;
 pub mod search;
+#[cfg(feature = "api-spec")]
+mod spec;
+mod status_page;
+#[cfg(feature = "subsonic")]
+mod subsonic;
+#[cfg(feature = "sync-sessions")]
+mod sync_session;
 pub mod transcode;
 mod types;
+mod visibility;
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
 
-type Counter = Arc<AtomicUsize>;
+pub type Counter = Arc<AtomicUsize>;
 
 #[derive(Clone)]
 pub struct TranscodingDetails {
@@ -47,9 +82,127 @@ pub struct TranscodingDetails {
     pub max_transcodings: usize,
 }
 
+/// Class of endpoints, which can have its own rate limit in `limits` config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum EndpointClass {
+    Expensive,
+    Api,
+}
+
+/// Limits rate of requests according to `limit_rate` and `limits` in current config,
+/// so limit can change on config reload
+#[derive(Default)]
+pub struct RateLimiter {
+    current: ArcSwapOption<(f32, Leaky)>,
+    classes: ArcSwapOption<(LimitsConfig, LabeledLeaky<EndpointClass>)>,
+}
+
+impl RateLimiter {
+    /// Returns false, if request should be rejected
+    fn start_one(&self) -> bool {
+        let rate = get_config().limit_rate;
+        let mut current = self.current.load();
+        if current.as_ref().map(|c| c.0) != rate {
+            debug!("Rate limit set to {:?}", rate);
+            self.current
+                .store(rate.map(|r| Arc::new((r, Leaky::new(r)))));
+            current = self.current.load();
+        }
+        current
+            .as_ref()
+            .map(|c| c.1.start_one().is_ok())
+            .unwrap_or(true)
+    }
+
+    /// Returns false, if request of given class should be rejected
+    fn start_one_for(&self, class: EndpointClass) -> bool {
+        let limits = &get_config().limits;
+        let mut current = self.classes.load();
+        if current.as_ref().map(|c| &c.0) != Some(limits) {
+            debug!("Endpoint classes rate limits set to {:?}", limits);
+            let bucket = |limit: &crate::config::RateLimitConfig| {
+                Leaky::new_with_burst(limit.rate, limit.burst.map(u64::from))
+            };
+            let mut leaky = LabeledLeaky::new();
+            if let Some(limit) = limits.expensive.as_ref() {
+                leaky = leaky.with_bucket(EndpointClass::Expensive, bucket(limit));
+            }
+            if let Some(limit) = limits.api.as_ref() {
+                leaky = leaky.with_bucket(EndpointClass::Api, bucket(limit));
+            }
+            self.classes.store(Some(Arc::new((limits.clone(), leaky))));
+            current = self.classes.load();
+        }
+        current
+            .as_ref()
+            .and_then(|c| c.1.start_one(&class))
+            .map(|res| res.is_ok())
+            .unwrap_or(true)
+    }
+}
+
+/// Counts active audio streams per client address, so their number can be limited
+/// by `max_streams_per_client` in current config
+#[derive(Default)]
+pub struct StreamLimiter {
+    active: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl StreamLimiter {
+    /// Returns `None`, if client already has maximum number of streams,
+    /// otherwise guard, which keeps stream counted until dropped
+    fn start_stream(self: &Arc<Self>, addr: IpAddr, max: Option<usize>) -> Option<StreamGuard> {
+        let mut active = self.active.lock().unwrap();
+        let count = active.entry(addr).or_insert(0);
+        if max.map(|max| *count >= max).unwrap_or(false) {
+            return None;
+        }
+        *count += 1;
+        Some(StreamGuard {
+            limiter: self.clone(),
+            addr,
+        })
+    }
+
+    #[cfg(all(test, feature = "shared-positions"))]
+    fn active_streams(&self, addr: IpAddr) -> usize {
+        self.active.lock().unwrap().get(&addr).copied().unwrap_or(0)
+    }
+}
+
+struct StreamGuard {
+    limiter: Arc<StreamLimiter>,
+    addr: IpAddr,
+}
+
+impl StreamGuard {
+    /// Stream is counted until response body is sent (or dropped)
+    fn attach(self, resp: HttpResponse) -> HttpResponse {
+        resp.map(move |body| {
+            let guard = self;
+            inspect_body(body, move |_| {
+                let _ = &guard;
+            })
+        })
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        let mut active = self.limiter.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.addr) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.addr);
+            }
+        }
+    }
+}
+
 pub struct ServiceFactory<T> {
     authenticator: Option<Arc<dyn Authenticator<Incoming, Credentials = T>>>,
-    rate_limitter: Option<Arc<Leaky>>,
+    rate_limitter: Arc<RateLimiter>,
+    stream_limiter: Arc<StreamLimiter>,
     search: Search<String>,
     transcoding: TranscodingDetails,
     collections: Arc<Collections>,
@@ -62,7 +215,6 @@ impl<T> ServiceFactory<T> {
         search: Search<String>,
         transcoding: TranscodingDetails,
         collections: Arc<Collections>,
-        rate_limit: Option<f32>,
         stop_service_receiver: watch::Receiver<()>,
     ) -> Self
     where
@@ -71,7 +223,8 @@ impl<T> ServiceFactory<T> {
         ServiceFactory {
             authenticator: auth
                 .map(|a| Arc::new(a) as Arc<dyn Authenticator<Incoming, Credentials = T>>),
-            rate_limitter: rate_limit.map(|l| Arc::new(Leaky::new(l))),
+            rate_limitter: Arc::new(RateLimiter::default()),
+            stream_limiter: Arc::new(StreamLimiter::default()),
             search,
             transcoding,
             collections,
@@ -82,7 +235,7 @@ impl<T> ServiceFactory<T> {
 
 impl<T> myhy::server::ServiceFactory for ServiceFactory<T>
 where
-    T: Send + 'static,
+    T: Into<Account> + Send + 'static,
 {
     type Body = HttpBody;
     type Error = error::Error;
@@ -98,6 +251,7 @@ where
             },
             authenticator: self.authenticator.clone(),
             rate_limitter: self.rate_limitter.clone(),
+            stream_limiter: self.stream_limiter.clone(),
             remote_addr,
             is_ssl,
         }
@@ -121,16 +275,12 @@ type OptionalAuthenticatorType<T> = Option<Arc<dyn Authenticator<Incoming, Crede
 pub struct MainService<T> {
     pub state: ServiceComponents,
     pub authenticator: OptionalAuthenticatorType<T>,
-    pub rate_limitter: Option<Arc<Leaky>>,
+    pub rate_limitter: Arc<RateLimiter>,
+    pub stream_limiter: Arc<StreamLimiter>,
     pub remote_addr: SocketAddr,
     pub is_ssl: bool,
 }
 
-// use only on checked prefixes
-fn get_subpath(path: &str, prefix: &str) -> PathBuf {
-    Path::new(&path).strip_prefix(prefix).unwrap().to_path_buf()
-}
-
 const STATIC_FILE_NAMES: &[&str] = &[
     "/bundle.js",
     "/bundle.css",
@@ -142,6 +292,15 @@ const STATIC_FILE_NAMES: &[&str] = &[
 
 const STATIC_DIR: &str = "/static/";
 
+/// liveness and readiness checks (for container orchestration, load balancers)
+const HEALTH_PATH: &str = "/healthz";
+const READINESS_PATH: &str = "/readyz";
+
+/// query parameter to choose named transcoding profile
+const TRANSCODING_PROFILE_PARAM: &str = "trans-profile";
+/// query parameter with network type hint from client (e.g. cellular, wifi)
+const BANDWIDTH_HINT_PARAM: &str = "bw";
+
 fn is_static_file(path: &str) -> bool {
     STATIC_FILE_NAMES.contains(&path) || path.starts_with(STATIC_DIR)
 }
@@ -158,20 +317,41 @@ fn is_cors_enabled_for_request(req: &HttpRequest) -> bool {
 }
 
 #[allow(clippy::type_complexity)]
-impl<C: Send + 'static> Service<HttpRequest> for MainService<C> {
+impl<C: Into<Account> + Send + 'static> Service<HttpRequest> for MainService<C> {
     type Response = HttpResponse;
     type Error = error::Error;
     type Future = ResponseFuture;
 
     fn call(&self, req: HttpRequest) -> Self::Future {
+        match access_log::get_access_log() {
+            Some(access_log) => {
+                let record = access_log.start(&req, self.remote_addr.ip());
+                Box::pin(self.handle(req).map_ok(|resp| record.finish(resp)))
+            }
+            None => self.handle(req),
+        }
+    }
+}
+
+impl<C: Into<Account> + Send + 'static> MainService<C> {
+    fn handle(&self, req: HttpRequest) -> ResponseFuture {
         let state = self.state.clone();
+        let peer_addr = self.remote_addr.ip();
+        // forwarded headers are used only from trusted proxies
+        let behind_proxy = get_config().is_trusted_proxy(peer_addr);
+
+        let client_addr = resolve_remote_addr(req.headers(), Some(peer_addr), behind_proxy)
+            .map(|a| *a.as_ref())
+            .unwrap_or(peer_addr);
+        if !get_config().is_client_allowed(client_addr) {
+            debug!("Rejecting request from not allowed client {}", client_addr);
+            return response::fut(response::forbidden);
+        }
 
         //Limit rate of requests if configured
-        if let Some(ref limiter) = self.rate_limitter {
-            if limiter.start_one().is_err() {
-                debug!("Rejecting request due to rate limit");
-                return response::fut(response::too_many_requests);
-            }
+        if !self.rate_limitter.start_one() {
+            debug!("Rejecting request due to rate limit");
+            return response::fut(response::too_many_requests);
         }
 
         let cors = is_cors_enabled_for_request(&req);
@@ -190,11 +370,15 @@ impl<C: Send + 'static> Service<HttpRequest> for MainService<C> {
         let req = match RequestWrapper::new(req)
             .and_then(|req| req.set_path_prefix(path_prefix))
             .map(|req| {
-                req.set_remote_addr(Some(self.remote_addr.ip()))
+                req.set_remote_addr(Some(peer_addr))
                     .set_is_ssl(self.is_ssl)
                     .set_is_cors(cors)
-                    .set_is_behind_proxy(get_config().behind_proxy)
+                    .set_is_behind_proxy(behind_proxy)
                     .set_can_compress(get_config().compress_responses)
+                    .set_body_limits(
+                        Some(get_config().request_body.max_size),
+                        Some(get_config().request_body.read_timeout()),
+                    )
             }) {
             Ok(r) => r,
             Err(e) => {
@@ -209,22 +393,62 @@ impl<C: Send + 'static> Service<HttpRequest> for MainService<C> {
             }
         }
 
+        // versioned API paths are routed as original unversioned paths
+        let (req, versioned) = match router::unversioned_path(req.path()) {
+            Some(path) => (req.set_path(path), true),
+            None => (req, false),
+        };
+
+        if let Some(class) = endpoint_class(&req) {
+            if !self.rate_limitter.start_one_for(class) {
+                debug!("Rejecting request due to {:?} endpoints rate limit", class);
+                return response::fut(response::too_many_requests);
+            }
+        }
+
+        // limit concurrent streams from one client
+        let stream_guard = if is_stream_request(&req) {
+            let max = get_config().max_streams_per_client.map(|m| m as usize);
+            match self.stream_limiter.start_stream(client_addr, max) {
+                Some(guard) => Some(guard),
+                None => {
+                    debug!(
+                        "Rejecting stream, client {} has too many streams",
+                        client_addr
+                    );
+                    return response::fut(response::too_many_requests);
+                }
+            }
+        } else {
+            None
+        };
+
         Box::pin(
-            MainService::<C>::process_request(state, self.authenticator.clone(), req).or_else(
-                |e| {
-                    error!("Request processing error: {}", e);
-                    future::ok(response::internal_error())
-                },
-            ),
+            MainService::<C>::process_request(state, self.authenticator.clone(), req, versioned)
+                .map_ok(|resp| match stream_guard {
+                    Some(guard) => guard.attach(resp),
+                    None => resp,
+                })
+                .or_else(|e| match ApiError::from_error(&e) {
+                    Some(api_error) => {
+                        debug!("Request rejected: {}", e);
+                        future::ok(api_error.response())
+                    }
+                    None => {
+                        error!("Request processing error: {}", e);
+                        future::ok(response::internal_error())
+                    }
+                }),
         )
     }
 }
 
-impl<C: Send + 'static> MainService<C> {
+impl<C: Into<Account> + Send + 'static> MainService<C> {
     async fn process_request(
         subservices: ServiceComponents,
         authenticator: OptionalAuthenticatorType<C>,
         req: RequestWrapper,
+        versioned: bool,
     ) -> ResponseResult {
         //static files
         if req.method() == Method::GET {
@@ -233,6 +457,7 @@ impl<C: Send + 'static> MainService<C> {
                     &get_config().client_dir,
                     "index.html",
                     get_config().static_resource_cache_age,
+                    Some(&Conditionals::from_headers(req.headers())),
                 )
                 .await;
             } else if is_static_file(req.path()) {
@@ -240,252 +465,621 @@ impl<C: Send + 'static> MainService<C> {
                     &get_config().client_dir,
                     &req.path()[1..],
                     get_config().static_resource_cache_age,
+                    Some(&Conditionals::from_headers(req.headers())),
                 )
                 .await;
             }
         }
+        // health checks do not need authentication
+        if req.method() == Method::GET {
+            match req.path() {
+                HEALTH_PATH => return api::health(),
+                READINESS_PATH => return api::readiness(&subservices.collections),
+                _ => (),
+            }
+        }
+        if dlna::is_dlna_request(&req) {
+            return dlna::dlna_service(req, subservices.collections).await;
+        }
         // from here everything must be authenticated
         let cors = req.is_cors_enabled();
+        let successor = if versioned {
+            None
+        } else {
+            router::successor_path(req.path()).map(|path| {
+                get_config()
+                    .url_path_prefix
+                    .as_deref()
+                    .unwrap_or_default()
+                    .to_string()
+                    + &path
+            })
+        };
         let origin = req.headers().typed_get::<Origin>();
+        let cast_account = authenticator
+            .as_ref()
+            .and_then(|_| signed_cast_account(&req));
 
         let resp = match authenticator {
+            Some(_) if is_guest_request(&req) => {
+                debug!("Guest access to {}", req.path());
+                MainService::<C>::process_authenticated(
+                    req,
+                    subservices,
+                    Some(GUEST_ACCOUNT.to_string()),
+                )
+                .await
+            }
+            Some(_) if cast_account.is_some() => {
+                debug!("Signed cast access to {}", req.path());
+                MainService::<C>::process_authenticated(req, subservices, cast_account.flatten())
+                    .await
+            }
             Some(ref auth) => {
                 let auth_result = auth.authenticate(req).await;
 
                 match auth_result {
-                    Ok(AuthResult::Authenticated { request, .. }) => {
-                        MainService::<C>::process_authenticated(request, subservices).await
+                    Ok(AuthResult::Authenticated {
+                        request,
+                        credentials,
+                    }) => {
+                        MainService::<C>::process_authenticated(
+                            request,
+                            subservices,
+                            credentials.into(),
+                        )
+                        .await
                     }
                     Ok(AuthResult::LoggedIn(resp)) | Ok(AuthResult::Rejected(resp)) => Ok(resp),
                     Err(e) => Err(e),
                 }
             }
-            None => MainService::<C>::process_authenticated(req, subservices).await,
+            None => MainService::<C>::process_authenticated(req, subservices, None).await,
         };
         resp.map(move |r| if cors { add_cors_headers(r, origin) } else { r })
+            .map(move |r| match successor {
+                Some(successor) => router::add_deprecation_headers(r, &successor),
+                None => r,
+            })
     }
 
     async fn process_authenticated(
         mut req: RequestWrapper,
         subservices: ServiceComponents,
+        account: Account,
     ) -> ResponseResult {
+        #[cfg(feature = "subsonic")]
+        if subsonic::is_subsonic_request(req.path()) {
+            return subsonic::subsonic_service(req, subservices, account).await;
+        }
         let params = req.params();
         let path = req.path();
+        let is_allowed =
+            |collection: usize| get_config().is_collection_allowed(collection, account.as_deref());
+        // admin operations are only for main shared secret
+        let is_admin = account.is_none();
         let ServiceComponents {
             search,
             transcoding,
             collections,
         } = subservices;
-        match *req.method() {
-            Method::GET => {
-                if path.starts_with("/collections") {
-                    api::collections_list(req.can_compress())
-                } else if path.starts_with("/transcodings") {
-                    let user_agent = req.headers().typed_get::<UserAgent>();
-                    api::transcodings_list(
-                        user_agent.as_ref().map(|h| h.as_str()),
-                        req.can_compress(),
-                    )
-                } else if cfg!(feature = "shared-positions") && path.starts_with("/positions") {
-                    // positions API
-                    #[cfg(feature = "shared-positions")]
-                    match extract_group(path) {
-                        PositionGroup::Group(group) => match position_params(&params) {
-                            Ok(p) => {
-                                api::all_positions(collections, group, Some(p), req.can_compress())
-                                    .await
-                            }
-
-                            Err(e) => {
-                                error!("Invalid timestamp param: {}", e);
-                                response::fut(response::bad_request).await
-                            }
-                        },
-                        PositionGroup::Last(group) => {
-                            api::last_position(collections, group, req.can_compress()).await
-                        }
-                        PositionGroup::Path {
-                            collection,
-                            group,
-                            path,
-                        } => {
-                            let recursive = req.params().exists("rec");
-                            let filter = match position_params(&params) {
-                                Ok(p) => p,
-
-                                Err(e) => {
-                                    error!("Invalid timestamp param: {}", e);
-                                    return Ok(response::bad_request());
-                                }
-                            };
-                            api::folder_position(
+        let RouteMatch {
+            route,
+            collection,
+            rest,
+        } = match router::route(req.method(), path) {
+            Ok(m) => m,
+            Err(RouteError::MethodNotAllowed) => return Ok(response::method_not_supported()),
+            Err(RouteError::NotFound) => {
+                error!("Invalid path requested {}", path);
+                return Ok(response::not_found());
+            }
+        };
+        if !is_admin && route.is_admin_only() {
+            error!("Admin API {} is not allowed for account", path);
+            return Ok(response::forbidden());
+        }
+        if let Some(c) = collection.or_else(|| route.is_collection_action().then_some(0)) {
+            if c >= get_config().base_dirs.len() || !is_allowed(c) {
+                error!("Collection {} is invalid or not allowed", c);
+                // not revealing that collection exists
+                return Ok(ApiError::InvalidCollection.response());
+            }
+        }
+        let folder_filter = visibility::FolderFilter::for_account(account.as_deref());
+        if let Some(filter) = folder_filter.as_ref() {
+            let visible = match route {
+                Route::Folder => filter.is_visible(rest),
+                Route::Audio
+                | Route::Download
+                | Route::Cover
+                | Route::Icon
+                | Route::Desc
+                | Route::Peaks
+                | Route::Text
+                | Route::CastFile
+                | Route::CastCover
+                | Route::CastInfo => filter.is_allowed(rest),
+                _ => true,
+            };
+            if !visible {
+                error!("Path {} is not visible for account", rest);
+                return Ok(if route == Route::Folder {
+                    ApiError::FolderNotFound.response()
+                } else {
+                    response::not_found()
+                });
+            }
+        }
+        let collection_index = collection.unwrap_or(0);
+        // path in collection follows action segment, it's needed to find collection root containing it
+        let base_dir = || get_config().base_dir_for(collection_index, rest);
+        match route {
+            Route::Collections => api::collections_list(
+                &collections,
+                account.as_deref(),
+                req.headers().typed_get::<IfNoneMatch>(),
+                req.can_compress(),
+            ),
+            Route::Transcodings => {
+                let user_agent = req.headers().typed_get::<UserAgent>();
+                let params = req.params();
+                api::transcodings_list(
+                    user_agent.as_ref().map(|h| h.as_str()),
+                    params.get(TRANSCODING_PROFILE_PARAM).map(|p| p.as_ref()),
+                    req.headers().typed_get::<IfNoneMatch>(),
+                    req.can_compress(),
+                )
+            }
+            #[cfg(feature = "shared-positions")]
+            Route::Positions => {
+                // positions API
+                match extract_group(path) {
+                    PositionGroup::Group(group) => match position_params(&params) {
+                        Ok(p) => {
+                            api::all_positions(
                                 collections,
                                 group,
-                                collection,
-                                path,
-                                recursive,
-                                Some(filter),
+                                Some(p),
+                                account,
                                 req.can_compress(),
                             )
                             .await
                         }
-                        PositionGroup::Malformed => Ok(response::bad_request()),
+
+                        Err(e) => {
+                            error!("Invalid timestamp param: {}", e);
+                            Ok(ApiError::InvalidParameter.with_detail(e))
+                        }
+                    },
+                    PositionGroup::Last(group) => {
+                        api::last_position(collections, group, account, req.can_compress()).await
                     }
-                    #[cfg(not(feature = "shared-positions"))]
-                    unimplemented!();
-                } else if cfg!(feature = "shared-positions") && path.starts_with("/position") {
-                    #[cfg(not(feature = "shared-positions"))]
-                    unimplemented!();
-                    #[cfg(feature = "shared-positions")]
-                    self::position::position_service(req, collections)
-                } else {
-                    let (path, colllection_index) = match extract_collection_number(path) {
-                        Ok(r) => r,
-                        Err(_) => {
-                            error!("Invalid collection number");
-                            return Ok(response::not_found());
+                    PositionGroup::Path {
+                        collection,
+                        group,
+                        path,
+                    } => {
+                        if !is_allowed(collection) {
+                            return Ok(ApiError::InvalidCollection.response());
                         }
-                    };
+                        let recursive = req.params().exists("rec");
+                        let filter = match position_params(&params) {
+                            Ok(p) => p,
 
-                    let base_dir = &get_config().base_dirs[colllection_index];
-                    let ord = params
-                        .get("ord")
-                        .map(|l| FoldersOrdering::from_letter(l))
-                        .unwrap_or(FoldersOrdering::Alphabetical);
-                    if path.starts_with("/audio/") {
-                        MainService::<C>::serve_audio(&req, base_dir, path, transcoding).await
-                    } else if path.starts_with("/folder/") {
-                        let group = params.get_string("group");
-                        api::get_folder(
-                            colllection_index,
-                            get_subpath(path, "/folder/"),
+                            Err(e) => {
+                                error!("Invalid timestamp param: {}", e);
+                                return Ok(ApiError::InvalidParameter.with_detail(e));
+                            }
+                        };
+                        api::folder_position(
                             collections,
-                            ord,
                             group,
+                            collection,
+                            path,
+                            recursive,
+                            Some(filter),
+                            visibility::FolderFilter::for_account(account.as_deref()),
                             req.can_compress(),
                         )
                         .await
-                    } else if !get_config().disable_folder_download && path.starts_with("/download")
-                    {
-                        #[cfg(feature = "folder-download")]
-                        {
-                            let format = params
-                                .get("fmt")
-                                .and_then(|f| f.parse::<types::DownloadFormat>().ok())
-                                .unwrap_or_default();
-                            let recursive = params
-                                .get("collapsed")
-                                .and_then(|_| get_config().collapse_cd_folders.as_ref())
-                                .and_then(|c| c.regex.as_ref())
-                                .and_then(|re| Regex::new(re).ok());
-                            files::download_folder(
-                                base_dir,
-                                get_subpath(path, "/download/"),
-                                format,
-                                recursive,
-                            )
-                            .await
-                        }
-                        #[cfg(not(feature = "folder-download"))]
-                        {
-                            error!("folder download not ");
-                            Ok(response::not_found())
-                        }
-                    } else if path == "/search" {
-                        if let Some(search_string) = params.get_string("q") {
-                            let group = params.get_string("group");
-                            api::search(
-                                colllection_index,
-                                search,
-                                search_string,
-                                ord,
+                    }
+                    PositionGroup::Malformed | PositionGroup::Batch(_) => {
+                        Ok(ApiError::InvalidParameter.with_detail("group"))
+                    }
+                }
+            }
+            Route::Events => match events::EventsFilter::from_params(&params, account) {
+                Ok(filter) => Ok(events::events_stream(filter)),
+                Err(e) => {
+                    error!("Invalid events params: {}", e);
+                    Ok(ApiError::InvalidParameter.with_detail(e))
+                }
+            },
+            #[cfg(feature = "shared-positions")]
+            Route::Home => {
+                let group = path.strip_prefix("/api/home/").unwrap_or_default();
+                if group.is_empty() || group.contains('/') {
+                    return Ok(ApiError::InvalidParameter.with_detail("group"));
+                }
+                let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+                    None => home::DEFAULT_HOME_LIMIT,
+                    Some(Ok(limit)) if limit > 0 && limit <= home::MAX_HOME_LIMIT => limit,
+                    Some(_) => {
+                        error!("Invalid home screen limit");
+                        return Ok(ApiError::InvalidParameter.with_detail("limit"));
+                    }
+                };
+                home::home(
+                    collections,
+                    group.to_string(),
+                    account,
+                    limit,
+                    base_url(&req),
+                    req.can_compress(),
+                )
+                .await
+            }
+            #[cfg(feature = "api-spec")]
+            Route::Spec => spec::spec_response(req.can_compress()),
+            Route::Authors => api::authors(collections, account, req.can_compress()).await,
+            Route::Series if !rest.is_empty() => {
+                api::author_series(collections, rest.to_string(), account, req.can_compress()).await
+            }
+            Route::Series => Ok(ApiError::MissingParameter.with_detail("author")),
+            Route::Peaks => {
+                let resolution = match params.get("res").map(|r| r.parse::<usize>()) {
+                    None => peaks::DEFAULT_PEAKS_RESOLUTION,
+                    Some(Ok(res)) if res > 0 && res <= peaks::MAX_PEAKS_RESOLUTION => res,
+                    Some(_) => {
+                        error!("Invalid peaks resolution requested");
+                        return Ok(ApiError::InvalidParameter.with_detail("res"));
+                    }
+                };
+                peaks::send_peaks(
+                    base_dir(),
+                    PathBuf::from(rest),
+                    resolution,
+                    transcoding,
+                    req.can_compress(),
+                )
+                .await
+            }
+            Route::Text => api::text(collection_index, rest.to_string(), req.can_compress()).await,
+            Route::ScanStatus => api::scan_status(collections, req.can_compress()),
+            Route::ImportStatus => api::import_status(req.can_compress()),
+            Route::StatusPage => status_page::status_page(collections, req.can_compress()),
+            Route::CollectionStats => {
+                api::collection_stats(collections, collection_index, req.can_compress())
+            }
+            #[cfg(feature = "shared-positions")]
+            Route::PositionsBackup => api::positions_backup(collections, req.can_compress()).await,
+            #[cfg(feature = "transcoding-cache")]
+            Route::TranscodingCache => api::transcoding_cache_stats(req.can_compress()),
+            #[cfg(feature = "shared-positions")]
+            Route::PositionSocket => self::position::position_service(req, collections, account),
+            #[cfg(feature = "sync-sessions")]
+            Route::SyncSession => {
+                let path = path.to_string();
+                self::sync_session::sync_service(req, &path, account)
+            }
+            Route::CastInfo => {
+                cast::cast_info(
+                    collection_index,
+                    PathBuf::from(rest),
+                    collections,
+                    account,
+                    base_url(&req),
+                    transcoding,
+                    req.can_compress(),
+                )
+                .await
+            }
+            Route::CastFile => {
+                let range = match requested_range(&req) {
+                    Ok(range) => range,
+                    Err(resp) => return Ok(resp()),
+                };
+                files::send_cast_file(base_dir(), rest, range, transcoding)
+                    .await
+                    .map(cast::add_cast_headers)
+            }
+            Route::CastCover => files::send_cover(
+                base_dir(),
+                PathBuf::from(rest),
+                get_config().folder_file_cache_age,
+                Conditionals::from_headers(req.headers()),
+            )
+            .await
+            .map(cast::add_cast_headers),
+            Route::FeedRecent => {
+                api::recent_feed(
+                    collection_index,
+                    search,
+                    base_url(&req),
+                    folder_filter,
+                    req.can_compress(),
+                )
+                .await
+            }
+            Route::Audio => MainService::<C>::serve_audio(&req, base_dir(), rest, transcoding)
+                .await
+                .map(throttle_response),
+            Route::Folder => {
+                let group = params.get_string("group");
+                let view = match folder_view_params(&params) {
+                    Ok(view) => view,
+                    Err(e) => {
+                        error!("Invalid folder listing params: {}", e);
+                        return Ok(ApiError::InvalidParameter.with_detail(e));
+                    }
+                };
+                let scanning = !collections.is_initial_scan_done_for(collection_index);
+                api::get_folder(
+                    collection_index,
+                    PathBuf::from(rest),
+                    collections,
+                    folders_ordering(&params),
+                    group,
+                    view,
+                    folder_filter,
+                    req.headers().typed_get::<IfNoneMatch>(),
+                    req.can_compress(),
+                )
+                .await
+                .map(|resp| add_scanning_header(resp, scanning))
+            }
+            #[cfg(feature = "folder-download")]
+            Route::Download if !get_config().disable_folder_download => {
+                let format = params
+                    .get("fmt")
+                    .and_then(|f| f.parse::<types::DownloadFormat>().ok())
+                    .unwrap_or_default();
+                let recursive = params.exists("recursive");
+                let compress = params.exists("compress");
+                let collapsed = params
+                    .get("collapsed")
+                    .and_then(|_| get_config().collapse_cd_folders.as_ref())
+                    .and_then(|c| c.regex.as_ref())
+                    .and_then(|re| Regex::new(re).ok());
+                files::download_folder(
+                    base_dir(),
+                    PathBuf::from(rest),
+                    format,
+                    recursive,
+                    compress,
+                    collapsed,
+                    folder_filter,
+                )
+                .await
+                .map(throttle_response)
+            }
+            Route::Search => {
+                if let Some(search_string) = params.get_string("q") {
+                    let group = params.get_string("group");
+                    let scanning = !collections.is_initial_scan_done_for(collection_index);
+                    api::search(
+                        collection_index,
+                        search,
+                        search_string,
+                        folders_ordering(&params),
+                        group,
+                        folder_filter,
+                        req.can_compress(),
+                    )
+                    .await
+                    .map(|resp| add_scanning_header(resp, scanning))
+                } else {
+                    error!("q parameter is missing in search");
+                    Ok(ApiError::MissingParameter.with_detail("q"))
+                }
+            }
+            Route::Recent => {
+                let group = params.get_string("group");
+                let scanning = !collections.is_initial_scan_done_for(collection_index);
+                api::recent(
+                    collection_index,
+                    search,
+                    group,
+                    folder_filter,
+                    req.can_compress(),
+                )
+                .await
+                .map(|resp| add_scanning_header(resp, scanning))
+            }
+            Route::Cover => match params.get("size").map(|s| s.parse::<u32>()) {
+                None => {
+                    files::send_cover(
+                        base_dir(),
+                        PathBuf::from(rest),
+                        get_config().folder_file_cache_age,
+                        Conditionals::from_headers(req.headers()),
+                    )
+                    .await
+                }
+                Some(Ok(size)) if get_config().covers.is_allowed_size(size) => {
+                    files::send_resized_cover(base_dir(), PathBuf::from(rest), size).await
+                }
+                Some(_) => {
+                    error!("Invalid cover size requested");
+                    Ok(ApiError::InvalidParameter.with_detail("size"))
+                }
+            },
+            Route::Icon => {
+                let size = match params.get("size").map(|s| s.parse::<u32>()) {
+                    None => get_config().icons.size,
+                    Some(Ok(size)) if get_config().icons.is_allowed_size(size) => size,
+                    Some(_) => {
+                        error!("Invalid icon size requested");
+                        return Ok(ApiError::InvalidParameter.with_detail("size"));
+                    }
+                };
+                files::send_folder_icon(collection_index, PathBuf::from(rest), size, collections)
+                    .await
+            }
+            Route::Desc => {
+                let as_html = params.get("fmt").map(|f| f == "html").unwrap_or_else(|| {
+                    req.headers()
+                        .get(ACCEPT)
+                        .and_then(|v| v.to_str().ok())
+                        .map(|v| v.contains("text/html"))
+                        .unwrap_or(false)
+                });
+                files::send_description(
+                    base_dir(),
+                    PathBuf::from(rest),
+                    get_config().folder_file_cache_age,
+                    req.can_compress(),
+                    Conditionals::from_headers(req.headers()),
+                    as_html,
+                )
+                .await
+            }
+            Route::Rescan => api::rescan(collections, collection),
+            #[cfg(feature = "tags-write")]
+            Route::Tags if rest.is_empty() => Ok(ApiError::MissingParameter.with_detail("path")),
+            #[cfg(feature = "tags-write")]
+            Route::Tags => {
+                let path = rest.to_string();
+                match req.body_bytes().await {
+                    Ok(bytes) => api::update_tags(collections, collection_index, path, bytes).await,
+                    Err(e) => {
+                        error!("Error reading POST body: {}", e);
+                        Ok(e.response())
+                    }
+                }
+            }
+            Route::Trash | Route::Restore if rest.is_empty() => {
+                Ok(ApiError::MissingParameter.with_detail("path"))
+            }
+            Route::Trash | Route::Restore => {
+                api::trash_folder(
+                    collections,
+                    collection_index,
+                    rest.to_string(),
+                    route == Route::Restore,
+                )
+                .await
+            }
+            #[cfg(feature = "shared-positions")]
+            Route::PositionsPrune => {
+                let dry_run = params
+                    .get("dry_run")
+                    .map(|v| v != "0" && v != "false")
+                    .unwrap_or(false);
+                api::positions_prune(collections, dry_run, req.can_compress()).await
+            }
+            #[cfg(feature = "shared-positions")]
+            Route::Groups => api::groups_list(collections, account, req.can_compress()).await,
+            #[cfg(feature = "shared-positions")]
+            Route::GroupRename | Route::GroupDelete if rest.is_empty() => {
+                Ok(ApiError::MissingParameter.with_detail("group"))
+            }
+            #[cfg(feature = "shared-positions")]
+            Route::GroupDelete => api::group_delete(collections, rest.to_string()).await,
+            #[cfg(feature = "shared-positions")]
+            Route::GroupRename => {
+                let group = rest.to_string();
+                match req.body_bytes().await {
+                    Ok(bytes) => api::group_rename(collections, group, bytes).await,
+                    Err(e) => {
+                        error!("Error reading POST body: {}", e);
+                        Ok(e.response())
+                    }
+                }
+            }
+            #[cfg(feature = "shared-positions")]
+            Route::Settings | Route::SettingsUpdate if rest.is_empty() => {
+                Ok(ApiError::MissingParameter.with_detail("group"))
+            }
+            #[cfg(feature = "shared-positions")]
+            Route::Settings | Route::SettingsUpdate if rest.contains('/') => {
+                Ok(ApiError::InvalidParameter.with_detail("group"))
+            }
+            #[cfg(feature = "shared-positions")]
+            Route::Settings => {
+                api::settings_get(collections, rest.to_string(), req.can_compress()).await
+            }
+            #[cfg(feature = "shared-positions")]
+            Route::SettingsUpdate => {
+                let group = rest.to_string();
+                match req.body_bytes().await {
+                    Ok(bytes) => api::settings_put(collections, group, bytes).await,
+                    Err(e) => {
+                        error!("Error reading PUT body: {}", e);
+                        Ok(e.response())
+                    }
+                }
+            }
+            #[cfg(feature = "shared-positions")]
+            Route::PositionsRestore => match req.body_bytes().await {
+                Ok(bytes) => api::positions_restore(collections, bytes).await,
+                Err(e) => {
+                    error!("Error reading POST body: {}", e);
+                    Ok(e.response())
+                }
+            },
+            #[cfg(feature = "shared-positions")]
+            Route::FolderState => {
+                let folder = rest.to_string();
+                match req.body_bytes().await {
+                    Ok(bytes) => {
+                        api::set_folder_state(collections, collection_index, folder, bytes).await
+                    }
+                    Err(e) => {
+                        error!("Error reading POST body: {}", e);
+                        Ok(e.response())
+                    }
+                }
+            }
+            #[cfg(feature = "shared-positions")]
+            Route::PositionUpdate => {
+                let (group, batch) = match extract_group(path) {
+                    PositionGroup::Group(group) => (group, false),
+                    PositionGroup::Batch(group) => (group, true),
+                    _ => return Ok(ApiError::InvalidParameter.with_detail("group")),
+                };
+                let is_json = req
+                    .headers()
+                    .get("Content-Type")
+                    .and_then(|v| {
+                        v.to_str()
+                            .ok()
+                            .map(|s| s.to_lowercase().eq("application/json"))
+                    })
+                    .unwrap_or(false);
+                if is_json {
+                    let compress = req.can_compress();
+                    match req.body_bytes().await {
+                        Ok(bytes) if batch => {
+                            api::insert_positions_batch(
+                                collections,
                                 group,
-                                req.can_compress(),
+                                account,
+                                bytes,
+                                compress,
                             )
                             .await
-                        } else {
-                            error!("q parameter is missing in search");
-                            Ok(response::bad_request())
                         }
-                    } else if path.starts_with("/recent") {
-                        let group = params.get_string("group");
-                        api::recent(colllection_index, search, group, req.can_compress()).await
-                    } else if path.starts_with("/cover/") {
-                        files::send_cover(
-                            base_dir,
-                            get_subpath(path, "/cover"),
-                            get_config().folder_file_cache_age,
-                        )
-                        .await
-                    } else if path.starts_with("/icon/") {
-                        files::send_folder_icon(
-                            colllection_index,
-                            get_subpath(path, "/icon/"),
-                            collections,
-                        )
-                        .await
-                    } else if path.starts_with("/desc/") {
-                        files::send_description(
-                            base_dir,
-                            get_subpath(path, "/desc"),
-                            get_config().folder_file_cache_age,
-                            req.can_compress(),
-                        )
-                        .await
-                    } else {
-                        error!("Invalid path requested {}", path);
-                        Ok(response::not_found())
-                    }
-                }
-            }
-
-            Method::POST => {
-                #[cfg(feature = "shared-positions")]
-                if path.starts_with("/positions") {
-                    match extract_group(path) {
-                        PositionGroup::Group(group) => {
-                            let is_json = req
-                                .headers()
-                                .get("Content-Type")
-                                .and_then(|v| {
-                                    v.to_str()
-                                        .ok()
-                                        .map(|s| s.to_lowercase().eq("application/json"))
-                                })
-                                .unwrap_or(false);
-                            if is_json {
-                                match req.body_bytes().await {
-                                    Ok(bytes) => {
-                                        api::insert_position(collections, group, bytes).await
-                                    }
-                                    Err(e) => {
-                                        error!("Error reading POST body: {}", e);
-                                        Ok(response::bad_request())
-                                    }
-                                }
-                            } else {
-                                error!("Not JSON content type");
-                                Ok(response::bad_request())
-                            }
+                        Ok(bytes) => api::insert_position(collections, group, account, bytes).await,
+                        Err(e) => {
+                            error!("Error reading POST body: {}", e);
+                            Ok(e.response())
                         }
-                        _ => Ok(response::bad_request()),
                     }
                 } else {
-                    Ok(response::not_found())
+                    error!("Not JSON content type");
+                    Ok(ApiError::InvalidJson.with_detail("Content-Type must be application/json"))
                 }
-
-                #[cfg(not(feature = "shared-positions"))]
-                Ok(response::method_not_supported())
             }
-
-            _ => Ok(response::method_not_supported()),
+            _ => Ok(response::not_found()),
         }
     }
 
     async fn serve_audio(
         req: &RequestWrapper,
         base_dir: &'static Path,
-        path: &str,
+        file_path: &str,
         transcoding: TranscodingDetails,
     ) -> ResponseResult {
         let params = req.params();
@@ -496,34 +1090,47 @@ impl<C: Send + 'static> MainService<C> {
             req.headers()
         );
 
-        let range = req.headers().typed_get::<Range>();
-
-        let bytes_range = match range.map(|r| r.satisfiable_ranges(u64::MAX).collect::<Vec<_>>()) {
-            Some(bytes_ranges) => {
-                if bytes_ranges.is_empty() {
-                    error!("Range header without range bytes");
-                    return Ok(response::bad_request());
-                } else if bytes_ranges.len() > 1 {
-                    error!("Range with multiple ranges is not supported");
-                    return Ok(response::not_implemented());
-                } else {
-                    Some(bytes_ranges[0])
-                }
+        let bytes_range = match requested_range(req) {
+            Ok(range) => range,
+            Err(resp) => return Ok(resp()),
+        };
+        let seek: Option<f32> = params.get("seek").and_then(|s| s.parse().ok());
+        let max_duration = match params.get("max_duration").map(|d| d.parse::<f32>()) {
+            None => None,
+            Some(Ok(d)) if d > 0.0 => Some(d),
+            Some(_) => {
+                error!("Invalid max_duration parameter");
+                return Ok(ApiError::InvalidParameter.with_detail("max_duration"));
             }
-
+        };
+        let profile = match params.get(TRANSCODING_PROFILE_PARAM) {
+            Some(name) => match get_config().transcoding.profile(name) {
+                Some(profile) => Some(profile),
+                None => {
+                    error!("Unknown transcoding profile {}", name);
+                    return Ok(ApiError::UnknownTranscodingProfile.with_detail(name));
+                }
+            },
             None => None,
         };
-        let seek: Option<f32> = params.get("seek").and_then(|s| s.parse().ok());
         let transcoding_quality: Option<ChosenTranscoding> = params
             .get("trans")
             .and_then(|t| QualityLevel::from_letter(&t))
-            .map(|level| ChosenTranscoding::for_level_and_user_agent(level, user_agent));
+            .map(|level| match params.get(BANDWIDTH_HINT_PARAM) {
+                Some(hint) => get_config().transcoding.level_for_bandwidth(level, hint),
+                None => level,
+            })
+            .map(|level| match profile {
+                Some(profile) => ChosenTranscoding::for_level_and_profile(level, profile),
+                None => ChosenTranscoding::for_level_and_user_agent(level, user_agent),
+            });
 
         files::send_file(
             base_dir,
-            get_subpath(path, "/audio/"),
+            PathBuf::from(file_path),
             bytes_range,
             seek,
+            max_duration,
             transcoding,
             transcoding_quality,
         )
@@ -531,24 +1138,165 @@ impl<C: Send + 'static> MainService<C> {
     }
 }
 
-lazy_static! {
-    static ref COLLECTION_NUMBER_RE: Regex = Regex::new(r"^/(\d+)/.+").unwrap();
+fn folders_ordering(params: &QueryParams) -> FoldersOrdering {
+    params
+        .get("ord")
+        .map(|l| FoldersOrdering::from_letter(l))
+        .unwrap_or(FoldersOrdering::Alphabetical)
+}
+
+/// URL of server as it's seen by client, without URL path prefix
+fn server_url<B>(req: &GenericRequestWrapper<B>) -> String
+where
+    B: myhy::Body + Send + Sync + 'static + Unpin,
+{
+    let host = req
+        .headers()
+        .get(HOST)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_string())
+        .unwrap_or_else(|| get_config().listen.to_string());
+    format!(
+        "{}://{}",
+        if req.is_https() { "https" } else { "http" },
+        host
+    )
 }
 
-fn extract_collection_number(path: &str) -> Result<(&str, usize), ()> {
-    let matches = COLLECTION_NUMBER_RE.captures(path);
-    if let Some(matches) = matches {
-        let cnum = matches.get(1).unwrap();
-        // match gives us char position it's safe to slice
-        let new_path = &path[cnum.end()..];
-        // and cnum is guarateed to contain digits only
-        let cnum: usize = cnum.as_str().parse().unwrap();
-        if cnum >= get_config().base_dirs.len() {
-            return Err(());
+/// Base URL of server as it's seen by client
+fn base_url(req: &RequestWrapper) -> String {
+    format!(
+        "{}{}",
+        server_url(req),
+        get_config().url_path_prefix.as_deref().unwrap_or_default()
+    )
+}
+
+/// Single byte range from Range header, error response builder if range is not supported
+fn requested_range(req: &RequestWrapper) -> Result<Option<ByteRange>, fn() -> HttpResponse> {
+    let range = req.headers().typed_get::<Range>();
+    match range.map(|r| r.satisfiable_ranges(u64::MAX).collect::<Vec<_>>()) {
+        Some(bytes_ranges) => {
+            if bytes_ranges.is_empty() {
+                error!("Range header without range bytes");
+                Err(response::bad_request)
+            } else if bytes_ranges.len() > 1 {
+                error!("Range with multiple ranges is not supported");
+                Err(response::not_implemented)
+            } else {
+                Ok(Some(bytes_ranges[0]))
+            }
         }
-        Ok((new_path, cnum))
-    } else {
-        Ok((path, 0))
+
+        None => Ok(None),
+    }
+}
+
+/// Account, for which URL was signed, casting and DLNA devices cannot authenticate
+fn signed_cast_account(req: &RequestWrapper) -> Option<Account> {
+    let path = req.path();
+    match router::route(req.method(), path) {
+        Ok(RouteMatch {
+            route: Route::CastFile | Route::CastCover,
+            ..
+        }) => cast::signed_account(path, &req.params()),
+        Ok(_) if is_collection_media_path(path) => cast::signed_account(path, &req.params()),
+        _ => None,
+    }
+}
+
+/// Listing is served from partially built collection cache, while initial scan is running
+const COLLECTION_SCANNING_HEADER: &str = "X-Collection-Scanning";
+
+fn add_scanning_header(mut resp: HttpResponse, scanning: bool) -> HttpResponse {
+    if scanning {
+        resp.headers_mut()
+            .insert(COLLECTION_SCANNING_HEADER, HeaderValue::from_static("true"));
+    }
+    resp
+}
+
+/// Limits bandwidth of response body, if `limit_stream_kbps` is configured
+fn throttle_response(resp: HttpResponse) -> HttpResponse {
+    match get_config().limit_stream_kbps {
+        Some(kbps) => resp.map(|body| throttle_body(body, kbps as u64 * 1000 / 8)),
+        None => resp,
+    }
+}
+
+/// Requests streaming audio file (possibly transcoded)
+fn is_stream_request(req: &RequestWrapper) -> bool {
+    matches!(
+        router::route(req.method(), req.path()),
+        Ok(RouteMatch {
+            route: Route::Audio | Route::CastFile,
+            ..
+        })
+    )
+}
+
+/// Class of request for rate limiting, `None` for static files and media other then transcoded audio
+fn endpoint_class(req: &RequestWrapper) -> Option<EndpointClass> {
+    let route = router::route(req.method(), req.path()).ok()?.route;
+    match route {
+        Route::Download => Some(EndpointClass::Expensive),
+        Route::Audio
+            if req
+                .params()
+                .get("trans")
+                .and_then(QualityLevel::from_letter)
+                .is_some() =>
+        {
+            Some(EndpointClass::Expensive)
+        }
+        Route::Audio
+        | Route::CastFile
+        | Route::CastCover
+        | Route::Cover
+        | Route::Icon
+        | Route::Desc
+        | Route::PositionSocket
+        | Route::Events => None,
+        _ => Some(EndpointClass::Api),
+    }
+}
+
+/// Audio files and covers in collection can be also accessed with signed URL (used by DLNA devices)
+fn is_collection_media_path(path: &str) -> bool {
+    matches!(
+        router::route(&Method::GET, path),
+        Ok(RouteMatch {
+            route: Route::Audio | Route::Cover,
+            collection: Some(_),
+            ..
+        })
+    )
+}
+
+/// Request without token either for collections and transcodings list, if there are some public collections,
+/// or for browsing, streaming and recent feed of public collection (without positions, which always require authentication)
+fn is_guest_request(req: &RequestWrapper) -> bool {
+    if get_config().public_collections.is_empty() || auth::request_token(req.headers()).is_some() {
+        return false;
+    }
+    let m = match router::route(req.method(), req.path()) {
+        Ok(m) => m,
+        Err(_) => return false,
+    };
+    let is_public = |c: Option<usize>| {
+        c.map(|c| get_config().public_collections.contains(&c))
+            .unwrap_or(false)
+    };
+    match m.route {
+        Route::Collections | Route::Transcodings => true,
+        Route::FeedRecent | Route::CastInfo | Route::CastFile | Route::CastCover => {
+            is_public(m.collection)
+        }
+        // collection must be explicit in path for guests
+        route if route.is_collection_action() => {
+            !req.params().exists("group") && is_public(m.collection)
+        }
+        _ => false,
     }
 }
 
@@ -557,6 +1305,7 @@ fn extract_collection_number(path: &str) -> Result<(&str, usize), ()> {
 enum PositionGroup {
     Group(String),
     Last(String),
+    Batch(String),
     Path {
         group: String,
         collection: usize,
@@ -565,6 +1314,34 @@ enum PositionGroup {
     Malformed,
 }
 
+fn folder_view_params(params: &QueryParams) -> error::Result<api::FolderView> {
+    let offset = params
+        .get("offset")
+        .map(|o| o.parse::<usize>())
+        .transpose()?
+        .unwrap_or(0);
+    let limit = params
+        .get("limit")
+        .map(|l| l.parse::<usize>())
+        .transpose()?;
+    let fields = params.get("fields").map(|f| {
+        f.split(',')
+            .map(|f| f.trim().to_string())
+            .filter(|f| !f.is_empty())
+            .collect()
+    });
+    let files_ordering = params
+        .get("files_ord")
+        .map(|o| FilesOrdering::from_letter(o))
+        .unwrap_or_default();
+    Ok(api::FolderView {
+        files_ordering,
+        offset,
+        limit,
+        fields,
+    })
+}
+
 #[cfg(feature = "shared-positions")]
 fn position_params(params: &QueryParams) -> error::Result<collection::PositionFilter> {
     use collection::{audio_meta::TimeStamp, PositionFilter};
@@ -603,6 +1380,8 @@ fn extract_group(path: &str) -> PositionGroup {
             if last == "last" {
                 //only last position
                 return PositionGroup::Last(group);
+            } else if last == "batch" {
+                return PositionGroup::Batch(group);
             } else if let Ok(collection) = last.parse::<usize>() {
                 if let Some(path) = segments.next() {
                     return PositionGroup::Path {
@@ -624,6 +1403,40 @@ fn extract_group(path: &str) -> PositionGroup {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_collection_media_path() {
+        assert!(is_collection_media_path("/0/audio/author/book/01.mp3"));
+        assert!(is_collection_media_path("/12/cover/author/book/cover.jpg"));
+        assert!(!is_collection_media_path("/0/folder/author"));
+        assert!(!is_collection_media_path("/x/audio/author/book/01.mp3"));
+        assert!(!is_collection_media_path("/audio/0/01.mp3"));
+    }
+
+    #[test]
+    fn test_stream_limiter() {
+        let limiter = Arc::new(StreamLimiter::default());
+        let addr: IpAddr = "192.168.1.10".parse().unwrap();
+        let other: IpAddr = "192.168.1.11".parse().unwrap();
+        let g1 = limiter.start_stream(addr, Some(2)).unwrap();
+        let g2 = limiter.start_stream(addr, Some(2)).unwrap();
+        assert!(limiter.start_stream(addr, Some(2)).is_none());
+        assert!(limiter.start_stream(other, Some(2)).is_some());
+        assert_eq!(2, limiter.active_streams(addr));
+        drop(g1);
+        let g3 = limiter.start_stream(addr, Some(2)).unwrap();
+        drop(g2);
+        drop(g3);
+        assert_eq!(0, limiter.active_streams(addr));
+        assert!(limiter.active.lock().unwrap().is_empty());
+        // unlimited
+        let guards: Vec<_> = (0..10)
+            .map(|_| limiter.start_stream(addr, None).unwrap())
+            .collect();
+        assert_eq!(10, limiter.active_streams(addr));
+        drop(guards);
+        assert_eq!(0, limiter.active_streams(addr));
+    }
+
     #[test]
     fn test_extract_group() {
         if let PositionGroup::Group(x) = extract_group("/positions/usak") {
@@ -651,6 +1464,12 @@ mod tests {
             panic!("group does not match")
         }
 
+        if let PositionGroup::Batch(x) = extract_group("/positions/usak/batch") {
+            assert_eq!(x, "usak");
+        } else {
+            panic!("group does not match")
+        }
+
         if let PositionGroup::Malformed = extract_group("/positions/chcip/pes") {
         } else {
             panic!("should be invalid")
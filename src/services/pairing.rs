@@ -0,0 +1,92 @@
+//! Pairing of mobile client - QR code contains everything client needs to connect to server,
+//! setup token in it is exchanged for normal authentication token.
+
+use crate::error::Result;
+use image::{GrayImage, ImageFormat, Luma};
+use myhy::response::{data_response, HttpResponse};
+use qrcode::{Color, QrCode};
+use serde::Serialize;
+use std::io::Cursor;
+
+/// Size of one QR code module in pixels
+const MODULE_SIZE: usize = 8;
+/// Empty modules around QR code, 4 is minimum required by QR specification
+const QUIET_ZONE: usize = 4;
+
+#[derive(Serialize)]
+struct PairingInfo<'a> {
+    url: &'a str,
+    path_prefix: &'a str,
+    setup_token: &'a str,
+    /// unix timestamp (in secs), when setup token expires
+    expires: u64,
+}
+
+/// JSON encoded pairing info
+fn pairing_data(url: &str, path_prefix: &str, setup_token: &str, expires: u64) -> String {
+    serde_json::to_string(&PairingInfo {
+        url,
+        path_prefix,
+        setup_token,
+        expires,
+    })
+    .expect("Pairing info serialization cannot fail")
+}
+
+fn qr_png(data: &str) -> Result<Vec<u8>> {
+    let code = QrCode::new(data.as_bytes())?;
+    let width = code.width();
+    let colors = code.to_colors();
+    let size = ((width + 2 * QUIET_ZONE) * MODULE_SIZE) as u32;
+    let img = GrayImage::from_fn(size, size, |x, y| {
+        let (col, row) = (x as usize / MODULE_SIZE, y as usize / MODULE_SIZE);
+        let is_dark = (QUIET_ZONE..QUIET_ZONE + width).contains(&col)
+            && (QUIET_ZONE..QUIET_ZONE + width).contains(&row)
+            && colors[(row - QUIET_ZONE) * width + col - QUIET_ZONE] == Color::Dark;
+        Luma([if is_dark { 0 } else { 255 }])
+    });
+    let mut png = Vec::with_capacity(4096);
+    img.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// PNG image with QR code, it must not be cached, as it contains secret token
+pub fn pairing_qr_response(
+    url: &str,
+    path_prefix: &str,
+    setup_token: &str,
+    expires: u64,
+) -> Result<HttpResponse> {
+    let png = qr_png(&pairing_data(url, path_prefix, setup_token, expires))?;
+    Ok(data_response(png, mime::IMAGE_PNG, None, None, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::ImageReader;
+
+    #[test]
+    fn test_pairing_data() {
+        let data = pairing_data("https://example.com", "/audio", "abcd", 1000);
+        assert_eq!(
+            r#"{"url":"https://example.com","path_prefix":"/audio","setup_token":"abcd","expires":1000}"#,
+            data
+        );
+    }
+
+    #[test]
+    fn test_qr_png() {
+        let png = qr_png(&pairing_data("https://example.com", "", "abcd", 1000)).unwrap();
+        let img = ImageReader::with_format(Cursor::new(png), ImageFormat::Png)
+            .decode()
+            .unwrap()
+            .into_luma8();
+        assert_eq!(img.width(), img.height());
+        assert_eq!(0, img.width() as usize % MODULE_SIZE);
+        // quiet zone is white, finder pattern in top left corner starts with dark module
+        assert_eq!(255, img.get_pixel(0, 0)[0]);
+        let corner = (QUIET_ZONE * MODULE_SIZE) as u32;
+        assert_eq!(0, img.get_pixel(corner, corner)[0]);
+    }
+}
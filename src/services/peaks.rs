@@ -0,0 +1,263 @@
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::Ordering;
+
+use collection::{audio_meta::is_audio, parse_chapter_path, TimeSpan};
+use myhy::response::{self, json_response, ResponseResult};
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking as blocking;
+
+use crate::config::get_config;
+use crate::Error;
+
+pub const DEFAULT_PEAKS_RESOLUTION: usize = 1000;
+pub const MAX_PEAKS_RESOLUTION: usize = 10_000;
+const SAMPLE_RATE: u32 = 8000;
+/// peaks are first collected for blocks of 10ms and then downsampled to requested resolution
+const BLOCK_SAMPLES: usize = SAMPLE_RATE as usize / 100;
+
+/// Peaks of audio file, values are normalized to 0 - 1 range
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct Peaks {
+    resolution: usize,
+    peaks: Vec<f32>,
+}
+
+/// Reads raw mono s16le samples and returns peak (max. absolute value) of each block
+fn block_peaks(input: impl Read) -> io::Result<Vec<u16>> {
+    let mut input = BufReader::with_capacity(64 * 1024, input);
+    let mut buf = [0u8; BLOCK_SAMPLES * 2];
+    let mut blocks = vec![];
+    loop {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match input.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        if filled >= 2 {
+            let peak = buf[..filled]
+                .chunks_exact(2)
+                .map(|s| i16::from_le_bytes([s[0], s[1]]).unsigned_abs())
+                .max()
+                .unwrap_or(0);
+            blocks.push(peak);
+        }
+        if filled < buf.len() {
+            break;
+        }
+    }
+    Ok(blocks)
+}
+
+fn downsample(blocks: &[u16], resolution: usize) -> Vec<f32> {
+    if blocks.is_empty() {
+        return vec![];
+    }
+    let len = blocks.len();
+    (0..resolution)
+        .map(|i| {
+            let start = i * len / resolution;
+            let end = ((i + 1) * len / resolution).clamp(start + 1, len);
+            let peak = blocks[start..end].iter().copied().max().unwrap_or(0);
+            (f32::from(peak) / 32768.0 * 1000.0).round() / 1000.0
+        })
+        .collect()
+}
+
+fn compute_peaks(file: &Path, span: Option<TimeSpan>, resolution: usize) -> io::Result<Peaks> {
+    let ffmpeg = &get_config().transcoding.ffmpeg;
+    let mut cmd = Command::new(&ffmpeg.path);
+    cmd.arg("-nostdin").args(["-v", "error"]);
+    cmd.args(&ffmpeg.global_args);
+    if let Some(span) = span {
+        cmd.arg("-ss")
+            .arg(format!("{:.3}", span.start as f64 / 1000.0));
+        if let Some(duration) = span.duration {
+            cmd.arg("-t")
+                .arg(format!("{:.3}", duration as f64 / 1000.0));
+        }
+    }
+    cmd.arg("-i")
+        .arg(file)
+        .args(["-vn", "-ac", "1", "-ar", &SAMPLE_RATE.to_string()])
+        .args(["-f", "s16le", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    let mut child = cmd.spawn()?;
+    let blocks = block_peaks(child.stdout.take().expect("stdout is piped"));
+    let status = child.wait()?;
+    let blocks = blocks?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "ffmpeg failed with status {}",
+            status
+        )));
+    }
+    Ok(Peaks {
+        resolution,
+        peaks: downsample(&blocks, resolution),
+    })
+}
+
+#[cfg(feature = "transcoding-cache")]
+fn cache_key(file: &Path, span: Option<TimeSpan>, resolution: usize) -> String {
+    let mut key = format!("#peaks{}", resolution);
+    key.push_str(&file.to_string_lossy());
+    if let Some(span) = span {
+        key.push('/');
+        key.push_str(&span.to_string());
+    }
+    key
+}
+
+#[cfg(feature = "transcoding-cache")]
+async fn cached_peaks(key: &str, meta: std::fs::Metadata) -> Option<Peaks> {
+    use super::transcode::cache::get_cache;
+    use tokio::io::AsyncReadExt;
+
+    if get_config().transcoding.cache.disabled {
+        return None;
+    }
+    let mut f = get_cache()
+        .get(key, meta.into())
+        .await
+        .unwrap_or_else(|e| {
+            error!("Cache lookup error: {}", e);
+            None
+        })?;
+    let mut data = vec![];
+    f.read_to_end(&mut data)
+        .await
+        .map_err(|e| error!("Error reading cached peaks: {}", e))
+        .ok()?;
+    serde_json::from_slice(&data)
+        .map_err(|e| error!("Invalid cached peaks: {}", e))
+        .ok()
+}
+
+#[cfg(feature = "transcoding-cache")]
+async fn cache_peaks(key: &str, meta: std::fs::Metadata, peaks: &Peaks) {
+    use super::transcode::cache::get_cache;
+    use tokio::io::AsyncWriteExt;
+
+    if get_config().transcoding.cache.disabled {
+        return;
+    }
+    let data = serde_json::to_vec(peaks).expect("Serialization error");
+    match get_cache().add(key, meta.into()).await {
+        Ok((mut f, finisher)) => {
+            let res = match f.write_all(&data).await {
+                Ok(_) => finisher.commit().await,
+                Err(e) => {
+                    error!("Error writing peaks to cache: {}", e);
+                    finisher.roll_back().await
+                }
+            };
+            if let Err(e) = res {
+                error!("Error finishing peaks cache entry: {}", e)
+            }
+        }
+        Err(e) => debug!("Cannot add peaks to cache: {}", e),
+    }
+}
+
+#[cfg_attr(
+    feature = "api-spec",
+    utoipa::path(
+        get,
+        path = "/api/peaks/{col_id}/{path}",
+        tag = "Collections",
+        params(
+            ("col_id" = usize, Path, description = "collection number"),
+            ("path" = String, Path, description = "path in collection"),
+            ("res" = Option<usize>, Query, description = "number of peaks, default 1000, max. 10000"),
+        ),
+        responses(
+            (status = 200, description = "Peaks of audio file, values are in range 0 - 1", body = Peaks),
+            (status = 400, description = "Invalid resolution"),
+            (status = 404, description = "Audio file not found"),
+            (status = 429, description = "Number of transcodings over limit"),
+        )
+    )
+)]
+pub async fn send_peaks(
+    base_path: &'static Path,
+    file_path: PathBuf,
+    resolution: usize,
+    transcoding: super::TranscodingDetails,
+    compress: bool,
+) -> ResponseResult {
+    let (real_path, span) = parse_chapter_path(&file_path);
+    let full_path = base_path.join(real_path);
+    let meta = match tokio::fs::metadata(&full_path).await {
+        Ok(meta) if meta.is_file() && is_audio(&full_path) => meta,
+        _ => {
+            error!("Requesting peaks of non existent file {:?}", full_path);
+            return Ok(response::not_found());
+        }
+    };
+
+    #[cfg(feature = "transcoding-cache")]
+    let key = cache_key(&full_path, span, resolution);
+    #[cfg(feature = "transcoding-cache")]
+    if let Some(peaks) = cached_peaks(&key, meta.clone()).await {
+        debug!("Sending peaks of {:?} from cache", full_path);
+        return Ok(json_response(&peaks, compress));
+    }
+    #[cfg(not(feature = "transcoding-cache"))]
+    let _ = meta;
+
+    // generating peaks requires decoding of whole file, so it's limited same as transcodings
+    let counter = transcoding.transcodings;
+    let running = counter.fetch_add(1, Ordering::AcqRel);
+    if running >= transcoding.max_transcodings {
+        counter.fetch_sub(1, Ordering::AcqRel);
+        warn!(
+            "Max transcodings reached {}/{}, cannot generate peaks",
+            running, transcoding.max_transcodings
+        );
+        return Ok(response::too_many_requests());
+    }
+    let res = blocking(move || compute_peaks(&full_path, span, resolution)).await;
+    counter.fetch_sub(1, Ordering::AcqRel);
+    let peaks = res.map_err(Error::new)?.map_err(|e| {
+        error!("Error generating peaks: {}", e);
+        Error::new(e).context("generating peaks")
+    })?;
+
+    #[cfg(feature = "transcoding-cache")]
+    cache_peaks(&key, meta, &peaks).await;
+
+    Ok(json_response(&peaks, compress))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_peaks() {
+        let samples: Vec<u8> = (0..BLOCK_SAMPLES as i16 * 2 + 1)
+            .flat_map(|i| (-i).to_le_bytes())
+            .collect();
+        let blocks = block_peaks(&samples[..]).unwrap();
+        let b = BLOCK_SAMPLES as u16;
+        assert_eq!(vec![b - 1, 2 * b - 1, 2 * b], blocks);
+    }
+
+    #[test]
+    fn test_downsample() {
+        let blocks = [0, 16384, 32767, 100, 3277, 0, 0];
+        let peaks = downsample(&blocks, 3);
+        assert_eq!(vec![0.5, 1.0, 0.1], peaks);
+        assert_eq!(10, downsample(&blocks, 10).len());
+        assert!(downsample(&[], 10).is_empty());
+    }
+}
@@ -1,10 +1,13 @@
+use super::auth::Account;
 use super::RequestWrapper;
 use crate::config::get_config;
 use crate::error::{bail, Context, Error};
 use collection::audio_meta::TimeStamp;
 use collection::{Collections, Position};
+use myhy::header::{HeaderMap, HeaderValue, SEC_WEBSOCKET_PROTOCOL};
 use myhy::response::box_websocket_response;
 use myhy::response::ResponseResult;
+use myhy::StatusCode;
 
 use serde::Serialize;
 use std::str::FromStr;
@@ -12,6 +15,11 @@ use std::sync::Arc;
 use websock::{self as ws, spawn_websocket};
 use ws::{Message, MessageResult};
 
+mod v2;
+
+const PROTOCOL_V1: &str = "audioserve-position-v1";
+const PROTOCOL_V2: &str = "audioserve-position-v2";
+
 #[derive(Clone, Debug, PartialEq, Eq, Default)]
 struct Location {
     collection: usize,
@@ -130,6 +138,12 @@ impl FromStr for Msg {
 struct Ctx {
     col: Arc<Collections>,
     loc: Location,
+    account: Account,
+}
+
+/// Collection access check for account of websocket client
+fn is_allowed(account: &Account, collection: usize) -> bool {
+    get_config().is_collection_allowed(collection, account.as_deref())
 }
 
 async fn process_message(m: Message, ctx: &mut Ctx) -> MessageResult {
@@ -143,6 +157,13 @@ async fn process_message(m: Message, ctx: &mut Ctx) -> MessageResult {
                 file_path,
                 timestamp,
             } => match file_path {
+                Some(file_loc) if !is_allowed(&ctx.account, file_loc.collection) => {
+                    error!(
+                        "Position for collection {} is not allowed",
+                        file_loc.collection
+                    );
+                    Ok(None)
+                }
                 Some(file_loc) => {
                     ctx.loc = file_loc.clone();
                     if let Some(ts) = timestamp {
@@ -191,7 +212,10 @@ async fn process_message(m: Message, ctx: &mut Ctx) -> MessageResult {
                 }
             },
             Msg::GenericQuery { group } => {
-                let last = col.get_last_position_async(group).await;
+                let last = col
+                    .get_last_position_async(group)
+                    .await
+                    .filter(|p| is_allowed(&ctx.account, p.collection));
                 let res = Reply {
                     folder: None,
                     last: last.map(PositionCompatible::from),
@@ -206,10 +230,18 @@ async fn process_message(m: Message, ctx: &mut Ctx) -> MessageResult {
                 let last = col
                     .clone()
                     .get_last_position_async(folder_path.group.clone())
-                    .await;
-                let folder = col
-                    .get_position_async(folder_path.collection, folder_path.group, folder_path.path)
-                    .await;
+                    .await
+                    .filter(|p| is_allowed(&ctx.account, p.collection));
+                let folder = if is_allowed(&ctx.account, folder_path.collection) {
+                    col.get_position_async(
+                        folder_path.collection,
+                        folder_path.group,
+                        folder_path.path,
+                    )
+                    .await
+                } else {
+                    None
+                };
                 let res = Reply {
                     last: if last != folder {
                         last.map(PositionCompatible::from)
@@ -231,17 +263,56 @@ async fn process_message(m: Message, ctx: &mut Ctx) -> MessageResult {
     }
 }
 
-pub fn position_service(req: RequestWrapper, col: Arc<Collections>) -> ResponseResult {
+/// Protocol version is negotiated with websocket subprotocol, if client does not request any, v1 is used
+fn requested_protocol(headers: &HeaderMap) -> Option<&'static str> {
+    let requested: Vec<_> = headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+    [PROTOCOL_V2, PROTOCOL_V1]
+        .into_iter()
+        .find(|p| requested.contains(p))
+}
+
+pub fn position_service(
+    req: RequestWrapper,
+    col: Arc<Collections>,
+    account: Account,
+) -> ResponseResult {
     debug!("We got these headers on websocket: {:?}", req.headers());
-    let res = spawn_websocket(
-        req.into_request(),
-        process_message,
-        Ctx {
-            col,
-            loc: Location::default(),
-        },
-        Some(get_config().positions.ws_timeout),
-    );
+    let protocol = requested_protocol(req.headers());
+    let timeout = Some(get_config().positions.ws_timeout);
+    let ping_interval = get_config().positions.ws_ping_interval;
+    let mut res = if protocol == Some(PROTOCOL_V2) {
+        spawn_websocket(
+            req.into_request(),
+            v2::process_message,
+            v2::Ctx { col, account },
+            timeout,
+            ping_interval,
+        )
+    } else {
+        spawn_websocket(
+            req.into_request(),
+            process_message,
+            Ctx {
+                col,
+                loc: Location::default(),
+                account,
+            },
+            timeout,
+            ping_interval,
+        )
+    };
+    if let Some(protocol) = protocol {
+        if res.status() == StatusCode::SWITCHING_PROTOCOLS {
+            res.headers_mut()
+                .insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(protocol));
+        }
+    }
 
     Ok(box_websocket_response(res))
 }
@@ -249,6 +320,24 @@ pub fn position_service(req: RequestWrapper, col: Arc<Collections>) -> ResponseR
 #[cfg(test)]
 mod test {
     use super::*;
+    #[test]
+    fn test_requested_protocol() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(None, requested_protocol(&headers));
+        headers.insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static("chat, audioserve-position-v2"),
+        );
+        assert_eq!(Some(PROTOCOL_V2), requested_protocol(&headers));
+        headers.insert(
+            SEC_WEBSOCKET_PROTOCOL,
+            HeaderValue::from_static("audioserve-position-v1"),
+        );
+        assert_eq!(Some(PROTOCOL_V1), requested_protocol(&headers));
+        headers.insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static("chat"));
+        assert_eq!(None, requested_protocol(&headers));
+    }
+
     #[test]
     fn test_position_location() {
         let l = Location {
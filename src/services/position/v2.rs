@@ -0,0 +1,265 @@
+//! Version 2 of websocket position protocol - JSON messages, each request
+//! can have `id`, which is then returned in response, so client can match them.
+//! Position updates are acknowledged with server timestamp.
+
+use super::is_allowed;
+use crate::error::Error;
+use crate::services::auth::Account;
+use collection::audio_meta::TimeStamp;
+use collection::{Collections, Position};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use websock::{Message, MessageResult};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(super) struct PositionUpdate {
+    collection: usize,
+    /// path of audio file
+    path: String,
+    position: f32,
+    /// time of position in miliseconds from epoch, if present position is only
+    /// inserted if there is not newer one
+    #[serde(default)]
+    timestamp: Option<u64>,
+    #[serde(default)]
+    folder_finished: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(super) enum Request {
+    Insert {
+        id: Option<u64>,
+        group: String,
+        #[serde(flatten)]
+        update: PositionUpdate,
+    },
+    Query {
+        id: Option<u64>,
+        group: String,
+        collection: Option<usize>,
+        folder: Option<String>,
+    },
+    /// Batch of positions, typically collected while client was offline
+    Sync {
+        id: Option<u64>,
+        group: String,
+        positions: Vec<PositionUpdate>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Response {
+    Ack {
+        id: Option<u64>,
+        timestamp: TimeStamp,
+    },
+    Positions {
+        id: Option<u64>,
+        folder: Option<Position>,
+        last: Option<Position>,
+    },
+    Error {
+        id: Option<u64>,
+        message: String,
+    },
+}
+
+impl Response {
+    fn into_message(self) -> Message {
+        Message::text(serde_json::to_string(&self).expect("Serialization error"))
+    }
+}
+
+async fn insert(
+    col: Arc<Collections>,
+    group: String,
+    update: PositionUpdate,
+) -> collection::error::Result<()> {
+    match update.timestamp {
+        Some(ts) => {
+            let position = Position {
+                timestamp: ts.into(),
+                collection: update.collection,
+                folder: String::new(),
+                file: update.path,
+                folder_finished: update.folder_finished,
+                position: update.position,
+            };
+            col.insert_position_if_newer_async(group, position).await
+        }
+        None => {
+            col.insert_position_async(
+                update.collection,
+                group,
+                update.path,
+                update.position,
+                update.folder_finished,
+            )
+            .await
+        }
+    }
+}
+
+pub(super) struct Ctx {
+    pub col: Arc<Collections>,
+    pub account: Account,
+}
+
+pub(super) async fn process_message(m: Message, ctx: &mut Ctx) -> MessageResult {
+    debug!("Got message {:?}", m);
+    let request = m
+        .to_str()
+        .map_err(Error::new)
+        .and_then(|s| serde_json::from_str::<Request>(s).map_err(Error::new));
+    let col = ctx.col.clone();
+    let response = match request {
+        Ok(Request::Insert { update, id, .. }) if !is_allowed(&ctx.account, update.collection) => {
+            Response::Error {
+                id,
+                message: format!("Collection {} is not allowed", update.collection),
+            }
+        }
+        Ok(Request::Insert { id, group, update }) => match insert(col, group, update).await {
+            Ok(_) => Response::Ack {
+                id,
+                timestamp: TimeStamp::now(),
+            },
+            Err(e) => {
+                error!("Cannot insert position: {}", e);
+                Response::Error {
+                    id,
+                    message: format!("Cannot insert position: {}", e),
+                }
+            }
+        },
+        Ok(Request::Sync {
+            id,
+            group,
+            positions,
+        }) => {
+            let total = positions.len();
+            let mut failed = 0;
+            for update in positions {
+                if !is_allowed(&ctx.account, update.collection) {
+                    error!("Synced position for not allowed collection");
+                    failed += 1;
+                } else if let Err(e) = insert(col.clone(), group.clone(), update).await {
+                    error!("Cannot insert synced position: {}", e);
+                    failed += 1;
+                }
+            }
+            if failed == 0 {
+                Response::Ack {
+                    id,
+                    timestamp: TimeStamp::now(),
+                }
+            } else {
+                Response::Error {
+                    id,
+                    message: format!("{} of {} positions failed", failed, total),
+                }
+            }
+        }
+        Ok(Request::Query {
+            id,
+            group,
+            collection,
+            folder,
+        }) => {
+            let last = col
+                .clone()
+                .get_last_position_async(group.clone())
+                .await
+                .filter(|p| is_allowed(&ctx.account, p.collection));
+            let folder = match (collection, folder) {
+                (Some(collection), Some(folder)) if is_allowed(&ctx.account, collection) => {
+                    col.get_position_async(collection, group, folder).await
+                }
+                _ => None,
+            };
+            Response::Positions {
+                id,
+                last: if last != folder { last } else { None },
+                folder,
+            }
+        }
+        Err(e) => {
+            error!("Position message error: {}", e);
+            Response::Error {
+                id: None,
+                message: format!("Invalid message: {}", e),
+            }
+        }
+    };
+    Ok(Some(response.into_message()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v2_requests() {
+        let r: Request = serde_json::from_str(
+            r#"{"type":"insert","id":1,"group":"g","collection":0,"path":"book/01.mp3","position":12.5}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            Request::Insert {
+                id: Some(1),
+                group: "g".into(),
+                update: PositionUpdate {
+                    collection: 0,
+                    path: "book/01.mp3".into(),
+                    position: 12.5,
+                    timestamp: None,
+                    folder_finished: false
+                }
+            },
+            r
+        );
+
+        let r: Request = serde_json::from_str(
+            r#"{"type":"sync","group":"g","positions":[{"collection":1,"path":"a/b.mp3","position":1,"timestamp":1614963001000}]}"#,
+        )
+        .unwrap();
+        match r {
+            Request::Sync {
+                id: None,
+                positions,
+                ..
+            } => {
+                assert_eq!(1, positions.len());
+                assert_eq!(Some(1614963001000), positions[0].timestamp);
+            }
+            _ => panic!("Invalid message"),
+        }
+
+        let r: Request = serde_json::from_str(r#"{"type":"query","id":3,"group":"g"}"#).unwrap();
+        assert_eq!(
+            Request::Query {
+                id: Some(3),
+                group: "g".into(),
+                collection: None,
+                folder: None
+            },
+            r
+        );
+
+        assert!(serde_json::from_str::<Request>(r#"{"type":"delete","group":"g"}"#).is_err());
+    }
+
+    #[test]
+    fn test_v2_response() {
+        let r = Response::Ack {
+            id: Some(7),
+            timestamp: 1000u64.into(),
+        };
+        assert_eq!(
+            r#"{"type":"ack","id":7,"timestamp":1000}"#,
+            serde_json::to_string(&r).unwrap()
+        );
+    }
+}
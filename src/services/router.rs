@@ -0,0 +1,637 @@
+//! Matching of request paths to API routes. Path is matched by segments against route patterns,
+//! pattern segment is either literal, `{col}` (collection number) or `*` (rest of path, can be empty).
+//! Path is already percent-decoded and normalized by request wrapper, so encoded slashes are same as slashes
+//! and there are no empty segments.
+//!
+//! API is available under versioned prefix `/api/v1`, original unversioned paths still work,
+//! but responses to them contain `Deprecation` header and `Link` header with successor (versioned) path.
+
+use myhy::response::HttpResponse;
+use myhy::Method;
+
+pub const API_V1_PREFIX: &str = "/api/v1";
+
+/// When unversioned paths were deprecated (as unix timestamp)
+const UNVERSIONED_DEPRECATED_AT: u64 = 1_792_281_600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    Authenticate,
+    RevokeAll,
+    PairingQr,
+    Collections,
+    CollectionStats,
+    Transcodings,
+    Positions,
+    PositionUpdate,
+    PositionSocket,
+    SyncSession,
+    FeedRecent,
+    CastFile,
+    CastCover,
+    CastInfo,
+    Home,
+    Events,
+    Spec,
+    Authors,
+    Series,
+    Peaks,
+    Text,
+    ScanStatus,
+    Rescan,
+    Tags,
+    Trash,
+    Restore,
+    ImportStatus,
+    Admin,
+    StatusPage,
+    PositionsBackup,
+    PositionsRestore,
+    PositionsPrune,
+    Groups,
+    GroupRename,
+    GroupDelete,
+    Settings,
+    SettingsUpdate,
+    TranscodingCache,
+    FolderState,
+    // actions within collection
+    Audio,
+    Folder,
+    Download,
+    Search,
+    Recent,
+    Cover,
+    Icon,
+    Desc,
+}
+
+impl Route {
+    /// Routes available only for main shared secret
+    pub fn is_admin_only(&self) -> bool {
+        matches!(
+            self,
+            Route::ScanStatus
+                | Route::Rescan
+                | Route::Tags
+                | Route::Trash
+                | Route::Restore
+                | Route::ImportStatus
+                | Route::Admin
+                | Route::StatusPage
+                | Route::PositionsBackup
+                | Route::PositionsRestore
+                | Route::PositionsPrune
+                | Route::GroupRename
+                | Route::GroupDelete
+                | Route::TranscodingCache
+        )
+    }
+
+    /// Actions within collection, where collection number is optional (defaults to 0)
+    pub fn is_collection_action(&self) -> bool {
+        matches!(
+            self,
+            Route::Audio
+                | Route::Folder
+                | Route::Download
+                | Route::Search
+                | Route::Recent
+                | Route::Cover
+                | Route::Icon
+                | Route::Desc
+        )
+    }
+
+    /// URLs of these routes are given to third parties (cast devices, podcast apps)
+    /// or opened directly in browser, so they stay unversioned
+    fn is_versioned(&self) -> bool {
+        !matches!(
+            self,
+            Route::FeedRecent | Route::CastFile | Route::CastCover | Route::StatusPage
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RouteMatch<'a> {
+    pub route: Route,
+    /// collection number, if it is in path
+    pub collection: Option<usize>,
+    /// rest of path matched by `*`, without leading slash
+    pub rest: &'a str,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RouteError {
+    NotFound,
+    MethodNotAllowed,
+}
+
+struct RouteDef {
+    method: Method,
+    pattern: &'static str,
+    route: Route,
+}
+
+const fn get(pattern: &'static str, route: Route) -> RouteDef {
+    RouteDef {
+        method: Method::GET,
+        pattern,
+        route,
+    }
+}
+
+const fn post(pattern: &'static str, route: Route) -> RouteDef {
+    RouteDef {
+        method: Method::POST,
+        pattern,
+        route,
+    }
+}
+
+const fn put(pattern: &'static str, route: Route) -> RouteDef {
+    RouteDef {
+        method: Method::PUT,
+        pattern,
+        route,
+    }
+}
+
+// order matters - first matching route is used
+const ROUTES: &[RouteDef] = &[
+    post("authenticate", Route::Authenticate),
+    get("collections", Route::Collections),
+    get("transcodings", Route::Transcodings),
+    get("positions/*", Route::Positions),
+    post("positions/*", Route::PositionUpdate),
+    get("position", Route::PositionSocket),
+    get("sync/*", Route::SyncSession),
+    get("feed-recent/{col}", Route::FeedRecent),
+    get("cast/{col}/*", Route::CastFile),
+    get("cast-cover/{col}/*", Route::CastCover),
+    post("api/auth/revoke-all", Route::RevokeAll),
+    get("api/pairing-qr", Route::PairingQr),
+    get("api/home/*", Route::Home),
+    get("api/events", Route::Events),
+    get("api/spec.json", Route::Spec),
+    get("api/authors", Route::Authors),
+    get("api/collections/{col}/stats", Route::CollectionStats),
+    get("api/series/*", Route::Series),
+    get("api/peaks/{col}/*", Route::Peaks),
+    get("api/text/{col}/*", Route::Text),
+    get("api/cast/{col}/*", Route::CastInfo),
+    get("api/admin/scan-status", Route::ScanStatus),
+    post("api/admin/rescan", Route::Rescan),
+    post("api/admin/rescan/{col}", Route::Rescan),
+    post("api/admin/tags/{col}/*", Route::Tags),
+    post("api/admin/trash/{col}/*", Route::Trash),
+    post("api/admin/restore/{col}/*", Route::Restore),
+    get("api/import/status", Route::ImportStatus),
+    get("api/admin/*", Route::Admin),
+    post("api/admin/*", Route::Admin),
+    get("admin", Route::StatusPage),
+    get("api/positions/backup", Route::PositionsBackup),
+    post("api/positions/restore", Route::PositionsRestore),
+    post("api/positions/prune", Route::PositionsPrune),
+    get("api/groups", Route::Groups),
+    post("api/groups/rename/*", Route::GroupRename),
+    post("api/groups/delete/*", Route::GroupDelete),
+    get("api/settings/*", Route::Settings),
+    put("api/settings/*", Route::SettingsUpdate),
+    get("api/cache/transcoding", Route::TranscodingCache),
+    post("api/folder-state/{col}/*", Route::FolderState),
+    get("{col}/audio/*", Route::Audio),
+    get("audio/*", Route::Audio),
+    get("{col}/folder/*", Route::Folder),
+    get("folder/*", Route::Folder),
+    get("{col}/download/*", Route::Download),
+    get("download/*", Route::Download),
+    get("{col}/search", Route::Search),
+    get("search", Route::Search),
+    get("{col}/recent", Route::Recent),
+    get("recent", Route::Recent),
+    get("{col}/cover/*", Route::Cover),
+    get("cover/*", Route::Cover),
+    get("{col}/icon/*", Route::Icon),
+    get("icon/*", Route::Icon),
+    get("{col}/desc/*", Route::Desc),
+    get("desc/*", Route::Desc),
+];
+
+fn match_pattern<'a>(pattern: &str, path: &'a str) -> Option<(Option<usize>, &'a str)> {
+    let mut collection = None;
+    let mut rest = path.strip_prefix('/').unwrap_or(path);
+    for expected in pattern.split('/') {
+        if expected == "*" {
+            return Some((collection, rest));
+        }
+        let (segment, remaining) = rest.split_once('/').unwrap_or((rest, ""));
+        if segment.is_empty() {
+            return None;
+        }
+        if expected == "{col}" {
+            if !segment.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            collection = Some(segment.parse().ok()?);
+        } else if expected != segment {
+            return None;
+        }
+        rest = remaining;
+    }
+    // only trailing slash can remain
+    rest.is_empty().then_some((collection, rest))
+}
+
+fn find_route<'a>(method: Option<&Method>, path: &'a str) -> Result<RouteMatch<'a>, RouteError> {
+    let mut error = RouteError::NotFound;
+    for def in ROUTES {
+        if let Some((collection, rest)) = match_pattern(def.pattern, path) {
+            if method.map(|m| *m == def.method).unwrap_or(true) {
+                return Ok(RouteMatch {
+                    route: def.route,
+                    collection,
+                    rest,
+                });
+            }
+            error = RouteError::MethodNotAllowed;
+        }
+    }
+    Err(error)
+}
+
+/// Finds route for request method and (unversioned) path
+pub fn route<'a>(method: &Method, path: &'a str) -> Result<RouteMatch<'a>, RouteError> {
+    find_route(Some(method), path)
+}
+
+/// Translates versioned path `/api/v1/...` to path used internally for routing,
+/// returns `None` if path is not versioned or there is no versioned route for it
+pub fn unversioned_path(path: &str) -> Option<String> {
+    let rest = path.strip_prefix(API_V1_PREFIX)?;
+    if !rest.starts_with('/') {
+        return None;
+    }
+    let api_path = format!("/api{}", rest);
+    let path = match find_route(None, &api_path) {
+        Ok(m) if !m.route.is_collection_action() => api_path,
+        _ => rest.to_string(),
+    };
+    match find_route(None, &path) {
+        Ok(m) if m.route.is_versioned() => Some(path),
+        _ => None,
+    }
+}
+
+/// Versioned successor of deprecated unversioned path
+pub fn successor_path(path: &str) -> Option<String> {
+    find_route(None, path)
+        .ok()
+        .filter(|m| m.route.is_versioned())
+        .map(|_| {
+            format!(
+                "{}{}",
+                API_V1_PREFIX,
+                path.strip_prefix("/api").unwrap_or(path)
+            )
+        })
+}
+
+/// Marks response to deprecated path, successor is full path (including any URL path prefix)
+pub fn add_deprecation_headers(mut resp: HttpResponse, successor: &str) -> HttpResponse {
+    let headers = resp.headers_mut();
+    headers.insert(
+        "Deprecation",
+        format!("@{}", UNVERSIONED_DEPRECATED_AT).parse().unwrap(),
+    );
+    if let Ok(link) = format!("<{}>; rel=\"successor-version\"", successor).parse() {
+        headers.insert(myhy::header::LINK, link);
+    }
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_route(path: &str) -> Option<(Route, Option<usize>, &str)> {
+        route(&Method::GET, path)
+            .ok()
+            .map(|m| (m.route, m.collection, m.rest))
+    }
+
+    fn post_route(path: &str) -> Option<(Route, Option<usize>, &str)> {
+        route(&Method::POST, path)
+            .ok()
+            .map(|m| (m.route, m.collection, m.rest))
+    }
+
+    #[test]
+    fn test_match_pattern() {
+        assert_eq!(
+            Some((None, "")),
+            match_pattern("collections", "/collections")
+        );
+        assert_eq!(
+            Some((None, "")),
+            match_pattern("collections", "/collections/")
+        );
+        assert_eq!(None, match_pattern("collections", "/collectionsx"));
+        assert_eq!(None, match_pattern("collections", "/collections/x"));
+        assert_eq!(
+            Some((Some(12), "a/b")),
+            match_pattern("{col}/folder/*", "/12/folder/a/b")
+        );
+        assert_eq!(
+            Some((Some(0), "")),
+            match_pattern("{col}/folder/*", "/0/folder/")
+        );
+        assert_eq!(
+            Some((Some(0), "")),
+            match_pattern("{col}/folder/*", "/0/folder")
+        );
+        assert_eq!(
+            Some((Some(0), "a/")),
+            match_pattern("{col}/folder/*", "/0/folder/a/")
+        );
+        assert_eq!(None, match_pattern("{col}/folder/*", "/x/folder/a"));
+        assert_eq!(None, match_pattern("{col}/folder/*", "/+1/folder/a"));
+        assert_eq!(
+            None,
+            match_pattern("{col}/folder/*", "/99999999999999999999999/folder/a")
+        );
+        assert_eq!(None, match_pattern("{col}/folder/*", "/0"));
+        assert_eq!(None, match_pattern("api/authors", "/api"));
+        assert_eq!(None, match_pattern("api/authors", "/"));
+    }
+
+    #[test]
+    fn test_route() {
+        assert_eq!(
+            Some((Route::Collections, None, "")),
+            get_route("/collections/")
+        );
+        assert_eq!(
+            Some((Route::Transcodings, None, "")),
+            get_route("/transcodings")
+        );
+        assert_eq!(
+            Some((Route::Positions, None, "group")),
+            get_route("/positions/group")
+        );
+        assert_eq!(
+            Some((Route::PositionUpdate, None, "group")),
+            post_route("/positions/group")
+        );
+        assert_eq!(
+            Some((Route::PositionSocket, None, "")),
+            get_route("/position")
+        );
+        assert_eq!(
+            Some((Route::SyncSession, None, "abc")),
+            get_route("/sync/abc")
+        );
+        assert_eq!(
+            Some((Route::FeedRecent, Some(1), "")),
+            get_route("/feed-recent/1/")
+        );
+        assert_eq!(
+            Some((Route::CastFile, Some(0), "a.mp3")),
+            get_route("/cast/0/a.mp3")
+        );
+        assert_eq!(
+            Some((Route::CastCover, Some(0), "c.jpg")),
+            get_route("/cast-cover/0/c.jpg")
+        );
+        assert_eq!(
+            Some((Route::CastInfo, Some(0), "a.mp3")),
+            get_route("/api/cast/0/a.mp3")
+        );
+        assert_eq!(
+            Some((Route::Home, None, "group")),
+            get_route("/api/home/group")
+        );
+        assert_eq!(Some((Route::Authors, None, "")), get_route("/api/authors"));
+        assert_eq!(
+            Some((Route::CollectionStats, Some(1), "")),
+            get_route("/api/collections/1/stats")
+        );
+        assert_eq!(
+            Some((Route::Series, None, "AC/DC")),
+            get_route("/api/series/AC/DC")
+        );
+        assert_eq!(
+            Some((Route::Peaks, Some(0), "a.mp3")),
+            get_route("/api/peaks/0/a.mp3")
+        );
+        assert_eq!(
+            Some((Route::Text, Some(2), "a/b.mp3")),
+            get_route("/api/text/2/a/b.mp3")
+        );
+        assert_eq!(
+            Some((Route::ScanStatus, None, "")),
+            get_route("/api/admin/scan-status")
+        );
+        assert_eq!(
+            Some((Route::Rescan, None, "")),
+            post_route("/api/admin/rescan")
+        );
+        assert_eq!(
+            Some((Route::Rescan, Some(1), "")),
+            post_route("/api/admin/rescan/1")
+        );
+        assert_eq!(
+            Some((Route::Admin, None, "rescan/x")),
+            post_route("/api/admin/rescan/x")
+        );
+        assert_eq!(
+            Some((Route::Tags, Some(0), "a.mp3")),
+            post_route("/api/admin/tags/0/a.mp3")
+        );
+        assert_eq!(
+            Some((Route::Trash, Some(1), "author/book")),
+            post_route("/api/admin/trash/1/author/book")
+        );
+        assert_eq!(
+            Some((Route::Restore, Some(1), "author/book")),
+            post_route("/api/admin/restore/1/author/book")
+        );
+        assert_eq!(
+            Some((Route::ImportStatus, None, "")),
+            get_route("/api/import/status")
+        );
+        assert_eq!(
+            Some((Route::Admin, None, "other")),
+            get_route("/api/admin/other")
+        );
+        assert_eq!(Some((Route::StatusPage, None, "")), get_route("/admin"));
+        assert_eq!(
+            Some((Route::PositionsBackup, None, "")),
+            get_route("/api/positions/backup")
+        );
+        assert_eq!(
+            Some((Route::PositionsRestore, None, "")),
+            post_route("/api/positions/restore")
+        );
+        assert_eq!(
+            Some((Route::PositionsPrune, None, "")),
+            post_route("/api/positions/prune")
+        );
+        assert_eq!(Some((Route::Groups, None, "")), get_route("/api/groups"));
+        assert_eq!(Some((Route::Events, None, "")), get_route("/api/events"));
+        assert_eq!(
+            Some((Route::GroupRename, None, "ivna")),
+            post_route("/api/groups/rename/ivna")
+        );
+        assert_eq!(
+            Some((Route::GroupDelete, None, "ivna")),
+            post_route("/api/groups/delete/ivna")
+        );
+        assert_eq!(
+            Some((Route::Settings, None, "ivna")),
+            get_route("/api/settings/ivna")
+        );
+        assert_eq!(
+            Ok(RouteMatch {
+                route: Route::SettingsUpdate,
+                collection: None,
+                rest: "ivna"
+            }),
+            route(&Method::PUT, "/api/settings/ivna")
+        );
+        assert_eq!(
+            Some((Route::TranscodingCache, None, "")),
+            get_route("/api/cache/transcoding")
+        );
+        assert_eq!(
+            Some((Route::FolderState, Some(0), "")),
+            post_route("/api/folder-state/0")
+        );
+        assert_eq!(
+            Some((Route::Authenticate, None, "")),
+            post_route("/authenticate")
+        );
+        assert_eq!(
+            Some((Route::RevokeAll, None, "")),
+            post_route("/api/auth/revoke-all")
+        );
+        assert_eq!(
+            Some((Route::PairingQr, None, "")),
+            get_route("/api/pairing-qr")
+        );
+        assert_eq!(Some((Route::Spec, None, "")), get_route("/api/spec.json"));
+        assert!(Route::TranscodingCache.is_admin_only());
+        assert!(Route::PositionsPrune.is_admin_only());
+        assert!(Route::GroupDelete.is_admin_only());
+        assert!(Route::StatusPage.is_admin_only());
+        assert!(!Route::Groups.is_admin_only());
+        assert!(!Route::Folder.is_admin_only());
+    }
+
+    #[test]
+    fn test_collection_routes() {
+        assert_eq!(
+            Some((Route::Folder, Some(1), "a/b")),
+            get_route("/1/folder/a/b")
+        );
+        assert_eq!(Some((Route::Folder, None, "")), get_route("/folder/"));
+        assert_eq!(
+            Some((Route::Audio, Some(0), "a b.mp3")),
+            get_route("/0/audio/a b.mp3")
+        );
+        assert_eq!(
+            Some((Route::Audio, None, "a.mp3")),
+            get_route("/audio/a.mp3")
+        );
+        assert_eq!(
+            Some((Route::Download, Some(0), "a")),
+            get_route("/0/download/a")
+        );
+        assert_eq!(Some((Route::Search, Some(3), "")), get_route("/3/search"));
+        assert_eq!(Some((Route::Search, None, "")), get_route("/search"));
+        assert_eq!(Some((Route::Recent, Some(0), "")), get_route("/0/recent"));
+        assert_eq!(
+            Some((Route::Cover, Some(0), "a/c.jpg")),
+            get_route("/0/cover/a/c.jpg")
+        );
+        assert_eq!(Some((Route::Icon, Some(0), "a")), get_route("/0/icon/a"));
+        assert_eq!(
+            Some((Route::Desc, None, "a/info.txt")),
+            get_route("/desc/a/info.txt")
+        );
+        assert!(Route::Desc.is_collection_action());
+        assert!(!Route::Peaks.is_collection_action());
+    }
+
+    #[test]
+    fn test_route_errors() {
+        assert_eq!(Err(RouteError::NotFound), route(&Method::GET, "/1/unknown"));
+        assert_eq!(Err(RouteError::NotFound), route(&Method::GET, "/rest/ping"));
+        assert_eq!(
+            Err(RouteError::NotFound),
+            route(&Method::GET, "/x/folder/a")
+        );
+        assert_eq!(Err(RouteError::NotFound), route(&Method::GET, "/"));
+        assert_eq!(
+            Err(RouteError::NotFound),
+            route(&Method::GET, "/api/authors/x")
+        );
+        assert_eq!(
+            Err(RouteError::MethodNotAllowed),
+            route(&Method::POST, "/0/folder/a")
+        );
+        assert_eq!(
+            Err(RouteError::MethodNotAllowed),
+            route(&Method::GET, "/authenticate")
+        );
+        assert_eq!(
+            Err(RouteError::MethodNotAllowed),
+            route(&Method::DELETE, "/positions/group")
+        );
+    }
+
+    #[test]
+    fn test_versioned_paths() {
+        assert_eq!(
+            Some("/collections".to_string()),
+            unversioned_path("/api/v1/collections")
+        );
+        assert_eq!(
+            Some("/api/authors".to_string()),
+            unversioned_path("/api/v1/authors")
+        );
+        assert_eq!(
+            Some("/0/folder/a/b".to_string()),
+            unversioned_path("/api/v1/0/folder/a/b")
+        );
+        assert_eq!(
+            Some("/api/cast/0/a.mp3".to_string()),
+            unversioned_path("/api/v1/cast/0/a.mp3")
+        );
+        assert_eq!(
+            Some("/positions/group".to_string()),
+            unversioned_path("/api/v1/positions/group")
+        );
+        assert_eq!(
+            Some("/authenticate".to_string()),
+            unversioned_path("/api/v1/authenticate")
+        );
+        assert_eq!(None, unversioned_path("/api/v1/feed-recent/0"));
+        assert_eq!(None, unversioned_path("/api/v1collections"));
+        assert_eq!(None, unversioned_path("/api/v1/unknown"));
+        assert_eq!(None, unversioned_path("/api/authors"));
+
+        assert_eq!(
+            Some("/api/v1/admin/scan-status".to_string()),
+            successor_path("/api/admin/scan-status")
+        );
+        assert_eq!(
+            Some("/api/v1/0/audio/a.mp3".to_string()),
+            successor_path("/0/audio/a.mp3")
+        );
+        assert_eq!(None, successor_path("/cast/0/a.mp3"));
+        assert_eq!(None, successor_path("/admin"));
+        assert_eq!(None, successor_path("/rest/ping"));
+    }
+}
@@ -0,0 +1,502 @@
+//! Scrobbling of played files to ListenBrainz (or compatible service) for positions groups with
+//! configured token. Playback is inferred from position updates - file is scrobbled once it was
+//! played long enough. Listens are queued in file and submitted in background, so they survive
+//! restart or temporary unavailability of the service.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use collection::audio_meta::AudioMeta;
+use collection::{tags, Collections, FoldersOrdering, Position};
+use myhy::client::send_request;
+use myhy::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use myhy::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::{broadcast, Notify};
+use tokio::task::spawn_blocking as blocking;
+use url::Url;
+
+use super::events::{self, Event};
+use crate::config::get_config;
+use crate::error::{Context, Result};
+
+const SUBMIT_PATH: &str = "1/submit-listens";
+const UNKNOWN_ARTIST: &str = "Unknown Artist";
+/// position advancing faster then this (relative to wall clock) is considered to be seek
+const MAX_PLAYBACK_SPEED: f32 = 4.0;
+/// tolerance (in seconds) for irregular position updates
+const POSITION_SLACK: f32 = 10.0;
+/// delay before first retry (in seconds), it doubles with each next retry
+const RETRY_DELAY: u64 = 30;
+const MAX_RETRY_EXPONENT: u32 = 7;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct AdditionalInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_ms: Option<u64>,
+    submission_client: String,
+    submission_client_version: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TrackMetadata {
+    artist_name: String,
+    track_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    release_name: Option<String>,
+    additional_info: AdditionalInfo,
+}
+
+impl TrackMetadata {
+    /// Metadata from tags, if tags are not available (they must be enabled in config)
+    /// audioserve folder convention Author/Book is used
+    fn new(folder: &str, path: &str, meta: Option<&AudioMeta>) -> Self {
+        let tag = |names: &[&str]| {
+            meta.and_then(|m| m.tags.as_ref()).and_then(|tags| {
+                names
+                    .iter()
+                    .filter_map(|n| tags.get(*n))
+                    .map(|v| v.trim())
+                    .find(|v| !v.is_empty())
+                    .map(str::to_string)
+            })
+        };
+        let folder_name = |name: Option<&std::ffi::OsStr>| name.map(|n| n.to_string_lossy().into());
+        let folder = Path::new(folder);
+        TrackMetadata {
+            artist_name: tag(&[tags::ARTIST, tags::ALBUM_ARTIST])
+                .or_else(|| folder_name(folder.iter().next()))
+                .unwrap_or_else(|| UNKNOWN_ARTIST.into()),
+            track_name: tag(&[tags::TITLE]).unwrap_or_else(|| {
+                let path = Path::new(path);
+                path.file_stem()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy()
+                    .into()
+            }),
+            release_name: tag(&[tags::ALBUM]).or_else(|| folder_name(folder.file_name())),
+            additional_info: AdditionalInfo {
+                duration_ms: meta.map(|m| m.duration as u64 * 1000).filter(|d| *d > 0),
+                submission_client: env!("CARGO_PKG_NAME").into(),
+                submission_client_version: env!("CARGO_PKG_VERSION").into(),
+            },
+        }
+    }
+}
+
+/// Played file waiting for submission
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Listen {
+    group: String,
+    /// unix time (in seconds), when playback started
+    listened_at: u64,
+    track_metadata: TrackMetadata,
+}
+
+/// Listens waiting for submission, persisted in file
+struct Queue {
+    listens: VecDeque<Listen>,
+    file: PathBuf,
+    max_size: usize,
+}
+
+impl Queue {
+    fn load(file: &Path, max_size: usize) -> Self {
+        let listens = match fs::read(file) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| error!("Invalid scrobbler queue file {:?}: {}", file, e))
+                .unwrap_or_default(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => {
+                error!("Cannot read scrobbler queue file {:?}: {}", file, e);
+                VecDeque::new()
+            }
+        };
+        Queue {
+            listens,
+            file: file.to_owned(),
+            max_size,
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let tmp_file = self.file.with_extension("tmp");
+        fs::write(&tmp_file, serde_json::to_vec(&self.listens)?)
+            .and_then(|_| fs::rename(&tmp_file, &self.file))
+            .with_context(|| format!("Cannot save scrobbler queue to {:?}", self.file))
+    }
+
+    fn push(&mut self, listen: Listen) {
+        while self.listens.len() >= self.max_size {
+            if let Some(dropped) = self.listens.pop_front() {
+                warn!(
+                    "Scrobbler queue is full, dropping listen of {}",
+                    dropped.track_metadata.track_name
+                );
+            }
+        }
+        self.listens.push_back(listen);
+        self.save_or_log();
+    }
+
+    /// Removes listen, if it's still first in queue
+    fn remove_first(&mut self, listen: &Listen) {
+        if self.listens.front() == Some(listen) {
+            self.listens.pop_front();
+            self.save_or_log();
+        }
+    }
+
+    fn save_or_log(&self) {
+        if let Err(e) = self.save() {
+            error!("{:#}", e)
+        }
+    }
+}
+
+/// Playback of one file by a group
+struct Play {
+    file: String,
+    started: SystemTime,
+    position: f32,
+    updated: Instant,
+    /// how long (in seconds) was file played, seeks are not counted
+    played: f32,
+    /// file must be played at least this time (in seconds) to be scrobbled
+    threshold: f32,
+    scrobbled: bool,
+    track: TrackMetadata,
+}
+
+impl Play {
+    fn new(file: String, position: f32, now: Instant, track: TrackMetadata) -> Self {
+        let cfg = &get_config().scrobbler;
+        let min_time = cfg.min_play_time as f32;
+        let threshold = match track.additional_info.duration_ms {
+            Some(ms) => (ms as f32 / 1000.0 * cfg.min_play_fraction).min(min_time),
+            None => min_time,
+        };
+        Play {
+            file,
+            started: SystemTime::now(),
+            position,
+            updated: now,
+            played: 0.0,
+            threshold,
+            scrobbled: false,
+            track,
+        }
+    }
+
+    /// Returns true, if file was just played long enough to be scrobbled
+    fn advance(&mut self, position: f32, now: Instant) -> bool {
+        let delta = position - self.position;
+        let elapsed = now.duration_since(self.updated).as_secs_f32();
+        if delta > 0.0 && delta <= elapsed * MAX_PLAYBACK_SPEED + POSITION_SLACK {
+            self.played += delta;
+        }
+        self.position = position;
+        self.updated = now;
+        if !self.scrobbled && self.played >= self.threshold {
+            self.scrobbled = true;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn file_path(pos: &Position) -> String {
+    if pos.folder.is_empty() {
+        pos.file.clone()
+    } else {
+        format!("{}/{}", pos.folder, pos.file)
+    }
+}
+
+async fn lookup_track(
+    collections: Arc<Collections>,
+    collection: usize,
+    folder: String,
+    path: String,
+) -> TrackMetadata {
+    let meta = blocking({
+        let folder = folder.clone();
+        let path = path.clone();
+        move || {
+            collections
+                .list_dir(collection, &folder, FoldersOrdering::Alphabetical, None)
+                .map(|f| {
+                    f.files
+                        .into_iter()
+                        .find(|f| f.path == Path::new(&path))
+                        .and_then(|f| f.meta)
+                })
+        }
+    })
+    .await
+    .map_err(|e| error!("Scrobbler lookup panicked: {}", e))
+    .ok()
+    .and_then(|res| {
+        res.map_err(|e| error!("Cannot list folder {} for scrobbler: {}", folder, e))
+            .ok()
+    })
+    .flatten();
+    TrackMetadata::new(&folder, &path, meta.as_ref())
+}
+
+/// Tracks playback of groups and scrobbles played files, runs until event bus is closed
+pub async fn run_scrobbler(collections: Arc<Collections>) {
+    let cfg = &get_config().scrobbler;
+    let queue = Arc::new(Mutex::new(Queue::load(&cfg.queue_file, cfg.max_queue_size)));
+    let queued = Arc::new(Notify::new());
+    tokio::spawn(submit_listens(queue.clone(), queued.clone()));
+    info!("Scrobbling playback of {} group(s)", cfg.tokens.len());
+
+    let mut plays: HashMap<(usize, String), Play> = HashMap::new();
+    let mut events = events::subscribe();
+    loop {
+        let (collection, group, folder) = match events.recv().await {
+            Ok(Event::Position {
+                collection,
+                group,
+                folder,
+                ..
+            }) if cfg.tokens.contains_key(&group) => (collection, group, folder),
+            Ok(_) => continue,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("Scrobbler missed {} events", missed);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let Some(pos) = collections.get_position(collection, &group, &folder) else {
+            continue;
+        };
+        let path = file_path(&pos);
+        let now = Instant::now();
+        let key = (collection, group);
+        match plays.get_mut(&key) {
+            Some(play) if play.file == path => {
+                if play.advance(pos.position, now) {
+                    debug!("Scrobbling {} for group {}", path, key.1);
+                    let listened_at = play
+                        .started
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or_default();
+                    queue.lock().unwrap().push(Listen {
+                        group: key.1.clone(),
+                        listened_at,
+                        track_metadata: play.track.clone(),
+                    });
+                    queued.notify_one();
+                }
+            }
+            _ => {
+                let track =
+                    lookup_track(collections.clone(), collection, pos.folder, path.clone()).await;
+                plays.insert(key, Play::new(path, pos.position, now, track));
+            }
+        }
+    }
+}
+
+enum Submission {
+    Done,
+    Retry,
+    Rejected,
+}
+
+async fn submit(url: &Url, listen: &Listen) -> Submission {
+    let cfg = &get_config().scrobbler;
+    let Some(token) = cfg
+        .tokens
+        .get(&listen.group)
+        .and_then(|t| HeaderValue::from_str(&format!("Token {}", t)).ok())
+    else {
+        return Submission::Rejected;
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(AUTHORIZATION, token);
+    let body = json!({
+        "listen_type": "single",
+        "payload": [{
+            "listened_at": listen.listened_at,
+            "track_metadata": listen.track_metadata,
+        }]
+    });
+    let timeout = Duration::from_secs(cfg.timeout as u64);
+    match send_request(
+        Method::POST,
+        url,
+        &headers,
+        body.to_string().into(),
+        timeout,
+    )
+    .await
+    {
+        Ok((status, _)) if status.is_success() => Submission::Done,
+        Ok((status, body)) => {
+            error!(
+                "Scrobbling for group {} failed with status {}: {}",
+                listen.group,
+                status,
+                String::from_utf8_lossy(&body)
+            );
+            if status.is_server_error()
+                || status == StatusCode::TOO_MANY_REQUESTS
+                || status == StatusCode::REQUEST_TIMEOUT
+            {
+                Submission::Retry
+            } else {
+                Submission::Rejected
+            }
+        }
+        Err(e) => {
+            error!("Scrobbling for group {} failed: {}", listen.group, e);
+            Submission::Retry
+        }
+    }
+}
+
+/// Submits queued listens one by one in order, failed submissions are retried
+async fn submit_listens(queue: Arc<Mutex<Queue>>, queued: Arc<Notify>) {
+    let api_url = &get_config().scrobbler.api_url;
+    // URL is validated in config check
+    let url = Url::parse(&format!(
+        "{}/{}",
+        api_url.trim_end_matches('/'),
+        SUBMIT_PATH
+    ))
+    .expect("valid scrobbler URL");
+    let mut attempt = 0;
+    loop {
+        let next = queue.lock().unwrap().listens.front().cloned();
+        let Some(listen) = next else {
+            queued.notified().await;
+            continue;
+        };
+        match submit(&url, &listen).await {
+            Submission::Done => {
+                debug!("Scrobbled {}", listen.track_metadata.track_name);
+                attempt = 0;
+                queue.lock().unwrap().remove_first(&listen);
+            }
+            Submission::Rejected => {
+                warn!(
+                    "Dropping rejected listen of {}",
+                    listen.track_metadata.track_name
+                );
+                attempt = 0;
+                queue.lock().unwrap().remove_first(&listen);
+            }
+            Submission::Retry => {
+                tokio::time::sleep(retry_delay(attempt)).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+fn retry_delay(attempt: u32) -> Duration {
+    Duration::from_secs(RETRY_DELAY * 2u64.pow(attempt.min(MAX_RETRY_EXPONENT)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init::init_default_config;
+
+    fn meta(duration: u32, tags: &[(&str, &str)]) -> AudioMeta {
+        AudioMeta {
+            duration,
+            bitrate: 64,
+            tags: Some(
+                tags.iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ),
+            loudness: None,
+        }
+    }
+
+    #[test]
+    fn test_track_metadata() {
+        let track = TrackMetadata::new(
+            "Adams Douglas/Hitchhiker",
+            "Adams Douglas/Hitchhiker/01-chapter.mp3",
+            None,
+        );
+        assert_eq!("Adams Douglas", track.artist_name);
+        assert_eq!("01-chapter", track.track_name);
+        assert_eq!(Some("Hitchhiker".into()), track.release_name);
+        assert_eq!(None, track.additional_info.duration_ms);
+
+        let m = meta(
+            300,
+            &[
+                (tags::TITLE, "Chapter 1"),
+                (tags::ALBUM_ARTIST, "Douglas Adams"),
+            ],
+        );
+        let track = TrackMetadata::new("Adams Douglas/Hitchhiker", "01.mp3", Some(&m));
+        assert_eq!("Douglas Adams", track.artist_name);
+        assert_eq!("Chapter 1", track.track_name);
+        assert_eq!(Some(300_000), track.additional_info.duration_ms);
+
+        let track = TrackMetadata::new("", "song.mp3", None);
+        assert_eq!(UNKNOWN_ARTIST, track.artist_name);
+        assert_eq!(None, track.release_name);
+    }
+
+    #[test]
+    fn test_play() {
+        init_default_config();
+        let now = Instant::now();
+        let m = meta(100, &[]);
+        let track = TrackMetadata::new("a", "a/b.mp3", Some(&m));
+        let mut play = Play::new("a/b.mp3".into(), 0.0, now, track.clone());
+        assert_eq!(50.0, play.threshold);
+        // seek is not counted
+        assert!(!play.advance(60.0, now + Duration::from_secs(5)));
+        assert_eq!(0.0, play.played);
+        assert!(!play.advance(80.0, now + Duration::from_secs(25)));
+        assert!(play.advance(120.0, now + Duration::from_secs(65)));
+        assert!(!play.advance(150.0, now + Duration::from_secs(95)));
+
+        let long = meta(36_000, &[]);
+        let track = TrackMetadata::new("a", "a/b.mp3", Some(&long));
+        let play = Play::new("a/b.mp3".into(), 0.0, now, track);
+        assert_eq!(get_config().scrobbler.min_play_time as f32, play.threshold);
+    }
+
+    #[test]
+    fn test_queue() -> anyhow::Result<()> {
+        let tmp_dir = tempdir::TempDir::new("AS_SCROBBLER_TEST")?;
+        let file = tmp_dir.path().join("queue.json");
+        let mut queue = Queue::load(&file, 2);
+        assert!(queue.listens.is_empty());
+        let listen = |n: u64| Listen {
+            group: "ivan".into(),
+            listened_at: n,
+            track_metadata: TrackMetadata::new("a", "a/b.mp3", None),
+        };
+        queue.push(listen(1));
+        queue.push(listen(2));
+        queue.push(listen(3));
+        assert_eq!(2, queue.listens.len());
+        queue.remove_first(&listen(3));
+        assert_eq!(2, queue.listens.len());
+        queue.remove_first(&listen(2));
+
+        let queue = Queue::load(&file, 2);
+        assert_eq!(vec![listen(3)], Vec::from(queue.listens));
+        Ok(())
+    }
+}
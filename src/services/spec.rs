@@ -0,0 +1,113 @@
+//! OpenAPI specification of JSON API, generated from handlers annotations and response types,
+//! so it does not drift from implementation (docs/audioserve-api-v1.yaml describes whole API)
+
+use myhy::response::{json_string_response, ResponseResult};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use super::{api, cast, peaks};
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearerAuth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some(
+                        "audioserve custom authentication scheme, get token from /authenticate endpoint",
+                    ))
+                    .build(),
+            ),
+        );
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "audioserve API"),
+    paths(
+        api::collections_list,
+        api::transcodings_list,
+        api::get_folder,
+        api::search,
+        api::recent,
+        api::authors,
+        api::author_series,
+        api::text,
+        api::scan_status,
+        api::collection_stats,
+        api::import_status,
+        peaks::send_peaks,
+        cast::cast_info,
+    ),
+    modifiers(&BearerAuth),
+    security(("bearerAuth" = []))
+)]
+struct ApiDoc;
+
+#[cfg(feature = "transcoding-cache")]
+#[derive(OpenApi)]
+#[openapi(paths(api::transcoding_cache_stats))]
+struct TranscodingCacheApiDoc;
+
+#[cfg(feature = "tags-write")]
+#[derive(OpenApi)]
+#[openapi(paths(api::update_tags))]
+struct TagsWriteApiDoc;
+
+#[cfg(feature = "shared-positions")]
+#[derive(OpenApi)]
+#[openapi(paths(
+    api::groups_list,
+    api::group_rename,
+    api::group_delete,
+    api::settings_get,
+    api::settings_put
+))]
+struct PositionsApiDoc;
+
+fn spec() -> utoipa::openapi::OpenApi {
+    #[allow(unused_mut)]
+    let mut spec = ApiDoc::openapi();
+    #[cfg(feature = "transcoding-cache")]
+    spec.merge(TranscodingCacheApiDoc::openapi());
+    #[cfg(feature = "tags-write")]
+    spec.merge(TagsWriteApiDoc::openapi());
+    #[cfg(feature = "shared-positions")]
+    spec.merge(PositionsApiDoc::openapi());
+    spec
+}
+
+lazy_static! {
+    static ref SPEC_JSON: String = serde_json::to_string(&spec()).expect("Serialization error");
+}
+
+pub fn spec_response(compress: bool) -> ResponseResult {
+    Ok(json_string_response(SPEC_JSON.clone(), compress))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec() {
+        let spec = ApiDoc::openapi();
+        assert!(spec.paths.paths.contains_key("/{col_id}/folder/{path}"));
+        let schemas = &spec.components.as_ref().unwrap().schemas;
+        for name in [
+            "AudioFolder",
+            "AudioFile",
+            "CollectionsInfo",
+            "CollectionStatus",
+            "Text",
+            "Peaks",
+        ] {
+            assert!(schemas.contains_key(name), "missing schema {}", name);
+        }
+    }
+}
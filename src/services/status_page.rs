@@ -0,0 +1,231 @@
+//! Simple status page for admins, rendered on server, so no client build is needed.
+//! It shows same data as admin status APIs (scan status, cache stats) plus recently logged errors.
+
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use collection::{Collections, ScanState, ScanStatus};
+use myhy::response::{data_response, ResponseResult};
+use serde::Serialize;
+use simple_file_cache::CacheStats;
+
+use super::api::xml_escape as escape;
+use super::icon::cache::{CACHE as ICONS_CACHE, COVERS_CACHE};
+use crate::config::{get_config, FEATURES, LONG_VERSION};
+use crate::logging::{recent_errors, LoggedError};
+
+const HTML_MIME: &str = "text/html; charset=utf-8";
+const UNKNOWN_NAME: &str = "unknown";
+
+const STYLE: &str = "body{font-family:sans-serif;margin:1em 2em}\
+table{border-collapse:collapse;margin-bottom:1em}\
+th,td{border:1px solid #ccc;padding:0.2em 0.6em;text-align:left}\
+th{background:#eee}.error{color:#b00}";
+
+fn format_time(t: SystemTime) -> String {
+    chrono::DateTime::<chrono::Local>::from(t)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+/// Name of enum variant as it appears in JSON API
+fn label<T: Serialize>(v: &T) -> String {
+    match serde_json::to_value(v) {
+        Ok(serde_json::Value::String(s)) => s,
+        _ => UNKNOWN_NAME.into(),
+    }
+}
+
+fn table(out: &mut String, header: &[&str], rows: impl Iterator<Item = Vec<String>>) {
+    out.push_str("<table><tr>");
+    for h in header {
+        write!(out, "<th>{}</th>", h).unwrap();
+    }
+    out.push_str("</tr>");
+    for row in rows {
+        out.push_str("<tr>");
+        for cell in row {
+            write!(out, "<td>{}</td>", escape(&cell)).unwrap();
+        }
+        out.push_str("</tr>");
+    }
+    out.push_str("</table>");
+}
+
+fn collection_row(collection: usize, status: &ScanStatus) -> Vec<String> {
+    let name = get_config()
+        .base_dirs
+        .get(collection)
+        .and_then(|d| d.file_name())
+        .and_then(OsStr::to_str)
+        .unwrap_or(UNKNOWN_NAME);
+    let state = if status.degraded {
+        format!("{} (degraded)", label(&status.state))
+    } else if status.state == ScanState::Running && status.full {
+        format!("{} (full)", label(&status.state))
+    } else {
+        label(&status.state)
+    };
+    vec![
+        collection.to_string(),
+        name.to_string(),
+        state,
+        label(&status.watch_mode),
+        status.folders_scanned.to_string(),
+        status.folders_queued.to_string(),
+        status
+            .finished
+            .map(|t| format_time(t.into()))
+            .unwrap_or_default(),
+    ]
+}
+
+fn cache_row(name: String, stats: Option<CacheStats>) -> Vec<String> {
+    const MB: u64 = 1024 * 1024;
+    match stats {
+        Some(s) => vec![
+            name,
+            format!("{} / {}", s.num_files, s.max_files),
+            format!(
+                "{} / {} MB ({}%)",
+                s.size / MB,
+                s.max_size / MB,
+                (s.size * 100).checked_div(s.max_size).unwrap_or(0)
+            ),
+            format!("{} / {} / {}", s.hits, s.misses, s.evictions),
+        ],
+        None => vec![name, "disabled".into(), String::new(), String::new()],
+    }
+}
+
+fn caches_stats() -> Vec<(String, Option<CacheStats>)> {
+    let mut caches = vec![(
+        "icons".to_string(),
+        ICONS_CACHE.as_ref().map(|c| c.stats(0)),
+    )];
+    match COVERS_CACHE.as_ref() {
+        Some(covers) => {
+            let mut sizes: Vec<_> = covers.keys().collect();
+            sizes.sort();
+            caches.extend(
+                sizes
+                    .into_iter()
+                    .map(|size| (format!("covers {}", size), Some(covers[size].stats(0)))),
+            )
+        }
+        None => caches.push(("covers".into(), None)),
+    }
+    #[cfg(feature = "transcoding-cache")]
+    caches.push((
+        "transcoding".into(),
+        super::transcode::cache::CACHE.as_ref().map(|c| c.stats(0)),
+    ));
+    caches
+}
+
+fn render(
+    scan_status: &[ScanStatus],
+    caches: Vec<(String, Option<CacheStats>)>,
+    errors: &[LoggedError],
+) -> String {
+    let mut out = String::with_capacity(8 * 1024);
+    write!(
+        out,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+        <title>Audioserve status</title><style>{}</style></head><body>\
+        <h1>Audioserve status</h1><p>Version {}, features: {}</p>",
+        STYLE,
+        escape(LONG_VERSION),
+        escape(FEATURES)
+    )
+    .unwrap();
+
+    out.push_str("<h2>Collections</h2>");
+    table(
+        &mut out,
+        &[
+            "#",
+            "Name",
+            "Scan state",
+            "Watching",
+            "Folders scanned",
+            "Folders queued",
+            "Last scan finished",
+        ],
+        scan_status
+            .iter()
+            .enumerate()
+            .map(|(i, s)| collection_row(i, s)),
+    );
+
+    out.push_str("<h2>Caches</h2>");
+    table(
+        &mut out,
+        &["Cache", "Files", "Size", "Hits / misses / evictions"],
+        caches
+            .into_iter()
+            .map(|(name, stats)| cache_row(name, stats)),
+    );
+
+    out.push_str("<h2>Recent errors</h2>");
+    if errors.is_empty() {
+        out.push_str("<p>No errors</p>");
+    } else {
+        out.push_str("<div class=\"error\">");
+        table(
+            &mut out,
+            &["Time", "Module", "Message"],
+            errors
+                .iter()
+                .map(|e| vec![format_time(e.time), e.target.clone(), e.message.clone()]),
+        );
+        out.push_str("</div>");
+    }
+    out.push_str("</body></html>");
+    out
+}
+
+pub fn status_page(collections: Arc<Collections>, compress: bool) -> ResponseResult {
+    let html = render(&collections.scan_status(), caches_stats(), &recent_errors());
+    Ok(data_response(
+        html,
+        HTML_MIME.parse().unwrap(),
+        None,
+        None,
+        compress,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::init::init_default_config;
+
+    #[test]
+    fn test_render() {
+        init_default_config();
+        let errors = vec![LoggedError {
+            time: SystemTime::now(),
+            target: "audioserve".into(),
+            message: "Cannot open <file>".into(),
+        }];
+        let caches = vec![(
+            "icons".into(),
+            Some(CacheStats {
+                size: 50 * 1024 * 1024,
+                max_size: 100 * 1024 * 1024,
+                num_files: 10,
+                max_files: 1024,
+                ..Default::default()
+            }),
+        )];
+        let html = render(&[ScanStatus::default()], caches, &errors);
+        assert!(html.contains("<td>50 / 100 MB (50%)</td>"));
+        assert!(html.contains("Cannot open &lt;file&gt;"));
+        assert!(html.contains("<td>idle</td>"));
+        assert!(html.ends_with("</body></html>"));
+    }
+}
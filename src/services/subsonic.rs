@@ -0,0 +1,615 @@
+//! Subsonic (and OpenSubsonic) API compatibility layer, so existing Subsonic clients can be used
+//! with audioserve. Collections are music folders, folders are directories and playback positions
+//! (for group given by user name) are bookmarks.
+
+use super::auth::Account;
+use super::transcode::{ChosenTranscoding, QualityLevel};
+use super::{files, requested_range, ServiceComponents};
+use crate::config::get_config;
+use crate::error::Error;
+use collection::audio_meta::AudioFolder;
+use collection::{AudioFile, AudioFolderShort, FolderState, FoldersOrdering};
+use data_encoding::{BASE64URL_NOPAD, HEXLOWER, HEXLOWER_PERMISSIVE};
+use md5::{Digest, Md5};
+use myhy::request::{QueryParams, RequestWrapper};
+use myhy::response::ResponseResult;
+use myhy::response::{data_response, json_response, Conditionals, HttpResponse};
+use ring::constant_time::verify_slices_are_equal;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use tokio::task::spawn_blocking as blocking;
+
+/// All Subsonic API methods are under this path
+pub const SUBSONIC_PREFIX: &str = "/rest/";
+const API_VERSION: &str = "1.16.1";
+const XML_NAMESPACE: &str = "http://subsonic.org/restapi";
+/// Positions group used, when no user name is given (without authentication)
+const DEFAULT_GROUP: &str = "subsonic";
+
+pub fn is_subsonic_request(path: &str) -> bool {
+    get_config().subsonic_api && path.starts_with(SUBSONIC_PREFIX)
+}
+
+/// Subsonic API error codes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    Generic = 0,
+    MissingParameter = 10,
+    WrongCredentials = 40,
+    NotAuthorized = 50,
+    NotFound = 70,
+}
+
+/// Credentials sent by client in query parameters - either password (plain or hex encoded)
+/// or token, which is md5 hash of password and salt
+#[derive(Debug, PartialEq, Eq)]
+pub enum Credentials {
+    Password(String),
+    Token { token: String, salt: String },
+}
+
+impl Credentials {
+    /// User name and credentials from request
+    pub fn from_params(params: &QueryParams) -> Option<(String, Credentials)> {
+        let user = params.get_string("u")?;
+        let credentials = match params.get("p") {
+            Some(p) => match p.strip_prefix("enc:") {
+                Some(hex) => HEXLOWER_PERMISSIVE
+                    .decode(hex.as_bytes())
+                    .ok()
+                    .and_then(|p| String::from_utf8(p).ok())
+                    .map(Credentials::Password)?,
+                None => Credentials::Password(p.to_string()),
+            },
+            None => Credentials::Token {
+                token: params.get("t")?.to_lowercase(),
+                salt: params.get_string("s")?,
+            },
+        };
+        Some((user, credentials))
+    }
+
+    pub fn matches(&self, secret: &str) -> bool {
+        match self {
+            Credentials::Password(p) => {
+                verify_slices_are_equal(p.as_bytes(), secret.as_bytes()).is_ok()
+            }
+            Credentials::Token { token, salt } => {
+                let data = [secret.as_bytes(), salt.as_bytes()].concat();
+                let expected = HEXLOWER.encode(&Md5::digest(&data));
+                verify_slices_are_equal(expected.as_bytes(), token.as_bytes()).is_ok()
+            }
+        }
+    }
+}
+
+fn xml_element(name: &str, value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            out.push('<');
+            out.push_str(name);
+            let mut has_children = false;
+            for (key, v) in map {
+                let attr = match v {
+                    Value::String(s) => s.clone(),
+                    Value::Bool(b) => b.to_string(),
+                    Value::Number(n) => n.to_string(),
+                    Value::Null => continue,
+                    Value::Object(_) | Value::Array(_) => {
+                        has_children = true;
+                        continue;
+                    }
+                };
+                out.push_str(&format!(" {}=\"{}\"", key, super::api::xml_escape(&attr)));
+            }
+            if !has_children {
+                out.push_str("/>");
+                return;
+            }
+            out.push('>');
+            for (key, v) in map {
+                if matches!(v, Value::Object(_) | Value::Array(_)) {
+                    xml_element(key, v, out);
+                }
+            }
+            out.push_str(&format!("</{}>", name));
+        }
+        Value::Array(items) => {
+            for item in items {
+                xml_element(name, item, out)
+            }
+        }
+        Value::Null => (),
+        v => {
+            let text = match v {
+                Value::String(s) => s.clone(),
+                v => v.to_string(),
+            };
+            out.push_str(&format!(
+                "<{}>{}</{}>",
+                name,
+                super::api::xml_escape(&text),
+                name
+            ));
+        }
+    }
+}
+
+/// Subsonic JSON format is derived from XML - scalar values are attributes and objects
+/// (or arrays of objects) are child elements
+fn to_xml(name: &str, value: &Value) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml_element(name, value, &mut out);
+    out
+}
+
+fn subsonic_response(
+    params: &QueryParams,
+    content: Map<String, Value>,
+    compress: bool,
+) -> HttpResponse {
+    let mut root = Map::new();
+    root.insert(
+        "status".into(),
+        json!(if content.contains_key("error") {
+            "failed"
+        } else {
+            "ok"
+        }),
+    );
+    root.insert("version".into(), json!(API_VERSION));
+    root.insert("type".into(), json!("audioserve"));
+    root.insert("serverVersion".into(), json!(env!("CARGO_PKG_VERSION")));
+    root.insert("openSubsonic".into(), json!(true));
+    root.extend(content);
+    if params.get("f").map(|f| f == "json").unwrap_or(false) {
+        json_response(&json!({ "subsonic-response": root }), compress)
+    } else {
+        root.insert("xmlns".into(), json!(XML_NAMESPACE));
+        data_response(
+            to_xml("subsonic-response", &Value::Object(root)),
+            "text/xml; charset=utf-8".parse().unwrap(),
+            None,
+            None,
+            compress,
+        )
+    }
+}
+
+/// Errors are returned with HTTP status 200, as required by Subsonic API
+pub fn error_response(params: &QueryParams, code: ErrorCode, message: &str) -> HttpResponse {
+    let mut content = Map::new();
+    content.insert(
+        "error".into(),
+        json!({"code": code as u32, "message": message}),
+    );
+    subsonic_response(params, content, false)
+}
+
+fn ok_response(params: &QueryParams, name: &str, value: Value, compress: bool) -> HttpResponse {
+    let mut content = Map::new();
+    content.insert(name.into(), value);
+    subsonic_response(params, content, compress)
+}
+
+/// Ids are opaque for clients, they encode collection and path
+fn encode_id(collection: usize, path: &Path) -> String {
+    BASE64URL_NOPAD.encode(format!("{}:{}", collection, path.to_string_lossy()).as_bytes())
+}
+
+fn decode_id(id: &str) -> Option<(usize, PathBuf)> {
+    let id = String::from_utf8(BASE64URL_NOPAD.decode(id.as_bytes()).ok()?).ok()?;
+    let (collection, path) = id.split_once(':')?;
+    let collection: usize = collection.parse().ok()?;
+    let path = PathBuf::from(path);
+    if collection >= get_config().base_dirs.len()
+        || path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+    Some((collection, path))
+}
+
+fn collection_name(collection: usize) -> String {
+    get_config().base_dirs[collection]
+        .file_name()
+        .map(|n| n.to_string_lossy().into())
+        .unwrap_or_else(|| collection.to_string())
+}
+
+fn parent_id(collection: usize, path: &Path) -> String {
+    encode_id(collection, path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+fn directory_child(collection: usize, folder: &AudioFolderShort) -> Value {
+    json!({
+        "id": encode_id(collection, &folder.path),
+        "parent": parent_id(collection, &folder.path),
+        "isDir": true,
+        "title": folder.name.as_str(),
+    })
+}
+
+fn file_child(collection: usize, file: &AudioFile, folder: &AudioFolder) -> Value {
+    let tag = |name: &str| {
+        file.meta
+            .as_ref()
+            .and_then(|m| m.tags.as_ref())
+            .and_then(|t| t.get(name))
+            .or_else(|| folder.tags.as_ref().and_then(|t| t.get(name)))
+            .cloned()
+    };
+    let mut child = json!({
+        "id": encode_id(collection, &file.path),
+        "parent": parent_id(collection, &file.path),
+        "isDir": false,
+        "title": tag("title").unwrap_or_else(|| file.name.to_string()),
+        "album": tag("album").or_else(|| file.path.parent().and_then(Path::file_name).map(|n| n.to_string_lossy().into())),
+        "artist": tag("artist"),
+        "contentType": file.mime,
+        "suffix": file.path.extension().and_then(OsStr::to_str),
+        "path": file.path.to_string_lossy(),
+        "type": "music",
+        "mediaType": "song",
+    });
+    if let Some(meta) = &file.meta {
+        child["duration"] = json!(meta.duration);
+        child["bitRate"] = json!(meta.bitrate);
+    }
+    if let Some(cover) = &folder.cover {
+        child["coverArt"] = json!(encode_id(collection, &cover.path));
+    }
+    if let Value::Object(map) = &mut child {
+        map.retain(|_, v| !v.is_null());
+    }
+    child
+}
+
+/// Transcoding quality for requested max. bitrate (in kbps), highest quality, which fits, is used
+fn quality_for_bitrate(max_bitrate: u32) -> QualityLevel {
+    let cfg = &get_config().transcoding;
+    [QualityLevel::High, QualityLevel::Medium]
+        .into_iter()
+        .find(|l| cfg.get(*l).bitrate() <= max_bitrate)
+        .unwrap_or(QualityLevel::Low)
+}
+
+fn to_iso_time(ts: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(ts).to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+pub async fn subsonic_service(
+    req: RequestWrapper,
+    subservices: ServiceComponents,
+    account: Account,
+) -> ResponseResult {
+    let params = req.params();
+    // folder filters are not enforced in subsonic API, so rather refuse such accounts
+    if super::visibility::FolderFilter::for_account(account.as_deref()).is_some() {
+        return Ok(error_response(
+            &params,
+            ErrorCode::NotAuthorized,
+            "Account has folder filters, which are not supported in subsonic API",
+        ));
+    }
+    let compress = req.can_compress();
+    let method = req.path()[SUBSONIC_PREFIX.len()..].trim_end_matches(".view");
+    let is_allowed =
+        |collection: usize| get_config().is_collection_allowed(collection, account.as_deref());
+    let user = params
+        .get_string("u")
+        .unwrap_or_else(|| DEFAULT_GROUP.to_string());
+    let id = params
+        .get("id")
+        .map(|id| decode_id(id).filter(|(c, _)| is_allowed(*c)));
+    let ServiceComponents {
+        transcoding,
+        collections,
+        ..
+    } = subservices;
+    debug!("Subsonic API method {}", method);
+
+    macro_rules! required_id {
+        () => {
+            match id {
+                Some(Some(id)) => id,
+                Some(None) => {
+                    return Ok(error_response(
+                        &params,
+                        ErrorCode::NotFound,
+                        "Requested item not found",
+                    ))
+                }
+                None => {
+                    return Ok(error_response(
+                        &params,
+                        ErrorCode::MissingParameter,
+                        "Required parameter id is missing",
+                    ))
+                }
+            }
+        };
+    }
+
+    let resp = match method {
+        "ping" => subsonic_response(&params, Map::new(), false),
+        "getLicense" => ok_response(&params, "license", json!({"valid": true}), compress),
+        "getOpenSubsonicExtensions" => {
+            ok_response(&params, "openSubsonicExtensions", json!([]), compress)
+        }
+        "getMusicFolders" => {
+            let folders: Vec<_> = (0..get_config().base_dirs.len())
+                .filter(|c| is_allowed(*c))
+                .map(|c| json!({"id": c, "name": collection_name(c)}))
+                .collect();
+            ok_response(
+                &params,
+                "musicFolders",
+                json!({ "musicFolder": folders }),
+                compress,
+            )
+        }
+        "getIndexes" => {
+            let requested: Option<usize> = params.get("musicFolderId").and_then(|c| c.parse().ok());
+            let cols: Vec<usize> = (0..get_config().base_dirs.len())
+                .filter(|c| is_allowed(*c) && requested.map(|r| r == *c).unwrap_or(true))
+                .collect();
+            let roots = blocking(move || {
+                cols.into_iter()
+                    .filter_map(|c| {
+                        collections
+                            .list_dir(c, "", FoldersOrdering::Alphabetical, None)
+                            .map_err(|e| error!("Cannot list collection {}: {}", c, e))
+                            .ok()
+                            .map(|f| (c, f))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await
+            .map_err(Error::new)?;
+            let mut index: BTreeMap<String, Vec<Value>> = BTreeMap::new();
+            let mut children = vec![];
+            for (collection, folder) in roots {
+                for subfolder in &folder.subfolders {
+                    let letter = subfolder
+                        .name
+                        .chars()
+                        .next()
+                        .map(|c| c.to_uppercase().to_string())
+                        .unwrap_or_default();
+                    index.entry(letter).or_default().push(json!({
+                        "id": encode_id(collection, &subfolder.path),
+                        "name": subfolder.name.as_str(),
+                    }));
+                }
+                children.extend(
+                    folder
+                        .files
+                        .iter()
+                        .map(|f| file_child(collection, f, &folder)),
+                );
+            }
+            let index: Vec<_> = index
+                .into_iter()
+                .map(|(name, artists)| json!({"name": name, "artist": artists}))
+                .collect();
+            ok_response(
+                &params,
+                "indexes",
+                json!({"lastModified": 0, "ignoredArticles": "", "index": index, "child": children}),
+                compress,
+            )
+        }
+        "getMusicDirectory" => {
+            let (collection, path) = required_id!();
+            let folder_path = path.clone();
+            let folder = blocking(move || {
+                collections.list_dir(collection, folder_path, FoldersOrdering::Alphabetical, None)
+            })
+            .await
+            .map_err(Error::new)?;
+            match folder {
+                Ok(folder) => {
+                    let mut children: Vec<_> = folder
+                        .subfolders
+                        .iter()
+                        .map(|f| directory_child(collection, f))
+                        .collect();
+                    children.extend(
+                        folder
+                            .files
+                            .iter()
+                            .map(|f| file_child(collection, f, &folder)),
+                    );
+                    let mut directory = json!({
+                        "id": encode_id(collection, &path),
+                        "name": path
+                            .file_name()
+                            .map(|n| n.to_string_lossy().into())
+                            .unwrap_or_else(|| collection_name(collection)),
+                        "child": children,
+                    });
+                    if path.parent().is_some() {
+                        directory["parent"] = json!(parent_id(collection, &path));
+                    }
+                    ok_response(&params, "directory", directory, compress)
+                }
+                Err(e) => {
+                    error!("Cannot list directory {:?}: {}", path, e);
+                    error_response(&params, ErrorCode::NotFound, "Directory not found")
+                }
+            }
+        }
+        "stream" | "download" => {
+            let (collection, path) = required_id!();
+            let range = match requested_range(&req) {
+                Ok(range) => range,
+                Err(resp) => return Ok(resp()),
+            };
+            let max_bitrate: u32 = params
+                .get("maxBitRate")
+                .and_then(|b| b.parse().ok())
+                .unwrap_or(0);
+            let raw =
+                method == "download" || params.get("format").map(|f| f == "raw").unwrap_or(false);
+            let transcoding_quality = if raw || max_bitrate == 0 {
+                None
+            } else {
+                Some(ChosenTranscoding::for_level_and_user_agent(
+                    quality_for_bitrate(max_bitrate),
+                    None,
+                ))
+            };
+            let seek: Option<f32> = params
+                .get("timeOffset")
+                .and_then(|s| s.parse().ok())
+                .filter(|s| *s > 0.0);
+            return files::send_file(
+                get_config().base_dir_for(collection, &path),
+                path,
+                range,
+                seek,
+                None,
+                transcoding,
+                transcoding_quality,
+            )
+            .await;
+        }
+        "getCoverArt" => {
+            let (collection, path) = required_id!();
+            return files::send_cover(
+                get_config().base_dir_for(collection, &path),
+                path,
+                get_config().folder_file_cache_age,
+                Conditionals::from_headers(req.headers()),
+            )
+            .await;
+        }
+        "getBookmarks" => {
+            let positions = collections
+                .clone()
+                .get_all_positions_for_group_async(user.clone(), None)
+                .await;
+            let bookmarks: Vec<_> = positions
+                .into_iter()
+                .filter(|p| is_allowed(p.collection))
+                .map(|p| {
+                    let path = Path::new(&p.folder).join(&p.file);
+                    let changed = to_iso_time(p.timestamp.into());
+                    json!({
+                        "position": (p.position * 1000.0) as u64,
+                        "username": user,
+                        "comment": "",
+                        "created": changed,
+                        "changed": changed,
+                        "entry": {
+                            "id": encode_id(p.collection, &path),
+                            "parent": parent_id(p.collection, &path),
+                            "isDir": false,
+                            "title": p.file,
+                            "path": path.to_string_lossy(),
+                        }
+                    })
+                })
+                .collect();
+            ok_response(
+                &params,
+                "bookmarks",
+                json!({ "bookmark": bookmarks }),
+                compress,
+            )
+        }
+        "createBookmark" | "deleteBookmark" => {
+            let (collection, path) = required_id!();
+            let res = if method == "createBookmark" {
+                let position: f32 = match params.get("position").and_then(|p| p.parse::<u64>().ok())
+                {
+                    Some(p) => p as f32 / 1000.0,
+                    None => {
+                        return Ok(error_response(
+                            &params,
+                            ErrorCode::MissingParameter,
+                            "Required parameter position is missing",
+                        ))
+                    }
+                };
+                blocking(move || {
+                    collections.insert_position(
+                        collection,
+                        user,
+                        path.to_string_lossy(),
+                        position,
+                        false,
+                    )
+                })
+                .await
+            } else {
+                let folder = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                blocking(move || {
+                    collections.set_folder_state(
+                        collection,
+                        user,
+                        folder.to_string_lossy(),
+                        FolderState::Reset,
+                    )
+                })
+                .await
+            }
+            .map_err(Error::new)?;
+            match res {
+                Ok(()) => subsonic_response(&params, Map::new(), false),
+                Err(collection::error::Error::ReadOnlyCollection) => {
+                    error_response(&params, ErrorCode::NotAuthorized, "Collection is read only")
+                }
+                Err(e) => {
+                    error!("Error when updating bookmark: {}", e);
+                    error_response(&params, ErrorCode::Generic, "Cannot update bookmark")
+                }
+            }
+        }
+        _ => {
+            error!("Unsupported Subsonic API method {}", method);
+            error_response(&params, ErrorCode::Generic, "Unsupported API method")
+        }
+    };
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credentials() {
+        // example from Subsonic API documentation
+        let token = Credentials::Token {
+            token: "26719a1196d2a940705a59634eb18eab".into(),
+            salt: "c19b2d".into(),
+        };
+        assert!(token.matches("sesame"));
+        assert!(!token.matches("sesame2"));
+        assert!(Credentials::Password("sesame".into()).matches("sesame"));
+    }
+
+    #[test]
+    fn test_xml() {
+        let value = json!({
+            "status": "ok",
+            "directory": {
+                "id": "1",
+                "name": "A & B",
+                "child": [{"id": "2", "isDir": false, "duration": 10}, {"id": "3", "title": null}],
+            }
+        });
+        assert_eq!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<r status=\"ok\"><directory id=\"1\" name=\"A &amp; B\">\
+<child duration=\"10\" id=\"2\" isDir=\"false\"/><child id=\"3\"/></directory></r>",
+            to_xml("r", &value)
+        );
+    }
+}
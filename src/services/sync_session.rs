@@ -0,0 +1,525 @@
+//! Shared playback sessions ("listen together") - one listener hosts session, others join it.
+//! Host controls playback (play, pause, seek), which is relayed to all listeners, server also
+//! keeps playback state and regularly sends it to listeners and asks them to seek,
+//! if their position drifts too far from host's position.
+
+use super::auth::Account;
+use super::types::ApiError;
+use super::RequestWrapper;
+use crate::config::get_config;
+use data_encoding::HEXLOWER;
+use myhy::response::{self, box_websocket_response, ResponseResult};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::sync::broadcast;
+use websock::{self as ws, upgrade_connection, Message, WebSocket};
+
+const BROADCAST_CAPACITY: usize = 64;
+
+lazy_static! {
+    static ref SESSIONS: Mutex<HashMap<String, Session>> = Mutex::new(HashMap::new());
+}
+
+/// Messages sent by client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Event {
+    Play {
+        file: Option<String>,
+        position: f64,
+    },
+    Pause {
+        position: f64,
+    },
+    Seek {
+        file: Option<String>,
+        position: f64,
+    },
+    /// Regular report of current position
+    Position {
+        position: f64,
+    },
+}
+
+/// Messages sent by server, beside relayed events
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Notice<'a> {
+    Session { id: &'a str, host: bool },
+    State(StateSnapshot<'a>),
+    Members { count: usize },
+    Closed,
+    Error { message: &'a str },
+}
+
+#[derive(Debug, Serialize)]
+struct StateSnapshot<'a> {
+    file: Option<&'a str>,
+    position: f64,
+    playing: bool,
+}
+
+#[derive(Debug)]
+struct PlaybackState {
+    file: Option<String>,
+    position: f64,
+    playing: bool,
+    updated: Instant,
+}
+
+impl Default for PlaybackState {
+    fn default() -> Self {
+        PlaybackState {
+            file: None,
+            position: 0.0,
+            playing: false,
+            updated: Instant::now(),
+        }
+    }
+}
+
+impl PlaybackState {
+    fn current_position(&self) -> f64 {
+        if self.playing {
+            self.position + self.updated.elapsed().as_secs_f64()
+        } else {
+            self.position
+        }
+    }
+
+    fn apply(&mut self, event: &Event) {
+        match event {
+            Event::Play { file, position } => {
+                if file.is_some() {
+                    self.file.clone_from(file)
+                }
+                self.position = *position;
+                self.playing = true;
+            }
+            Event::Pause { position } => {
+                self.position = *position;
+                self.playing = false;
+            }
+            Event::Seek { file, position } => {
+                if file.is_some() {
+                    self.file.clone_from(file)
+                }
+                self.position = *position;
+            }
+            Event::Position { position } => self.position = *position,
+        }
+        self.updated = Instant::now();
+    }
+
+    fn snapshot(&self) -> StateSnapshot<'_> {
+        StateSnapshot {
+            file: self.file.as_deref(),
+            position: self.current_position(),
+            playing: self.playing,
+        }
+    }
+
+    /// Seek event, which listener has to do to get to common position
+    fn correction(&self, position: f64, max_drift: f64) -> Option<Event> {
+        let current = self.current_position();
+        if (current - position).abs() > max_drift {
+            Some(Event::Seek {
+                file: self.file.clone(),
+                position: current,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Broadcasted message with id of member who caused it, so it is not sent back to sender,
+/// and collection of file in message, so it is sent only to members, who can access it
+type Broadcast = (u64, Option<usize>, String);
+
+/// Collection of file given as collection number and path
+fn file_collection(file: &str) -> Option<usize> {
+    file.split('/').next().and_then(|c| c.parse().ok())
+}
+
+fn event_file(event: &Event) -> Option<&str> {
+    match event {
+        Event::Play { file, .. } | Event::Seek { file, .. } => file.as_deref(),
+        _ => None,
+    }
+}
+
+enum JoinError {
+    NotFound,
+    Full,
+    NotAllowed,
+}
+
+struct Session {
+    state: PlaybackState,
+    tx: broadcast::Sender<Broadcast>,
+    members: usize,
+    next_member: u64,
+}
+
+fn to_json<T: Serialize>(msg: &T) -> String {
+    serde_json::to_string(msg).expect("Serialization error")
+}
+
+fn new_session_id() -> String {
+    let mut random = [0u8; 8];
+    SystemRandom::new()
+        .fill(&mut random)
+        .expect("Cannot generate random number");
+    HEXLOWER.encode(&random)
+}
+
+struct Member {
+    session_id: String,
+    id: u64,
+    host: bool,
+    account: Account,
+}
+
+impl Member {
+    fn is_collection_allowed(&self, collection: usize) -> bool {
+        get_config().is_collection_allowed(collection, self.account.as_deref())
+    }
+
+    /// File (if given) is in collection, which member can access
+    fn can_access(&self, file: Option<&str>) -> bool {
+        file.map_or(true, |f| {
+            file_collection(f).is_some_and(|c| self.is_collection_allowed(c))
+        })
+    }
+
+    fn create_session(account: Account) -> Option<(Self, broadcast::Receiver<Broadcast>)> {
+        let mut sessions = SESSIONS.lock().expect("Poisoned lock");
+        if sessions.len() >= get_config().sync_sessions.max_sessions {
+            return None;
+        }
+        let session_id = new_session_id();
+        let (tx, rx) = broadcast::channel(BROADCAST_CAPACITY);
+        sessions.insert(
+            session_id.clone(),
+            Session {
+                state: PlaybackState::default(),
+                tx,
+                members: 1,
+                next_member: 1,
+            },
+        );
+        Some((
+            Member {
+                session_id,
+                id: 0,
+                host: true,
+                account,
+            },
+            rx,
+        ))
+    }
+
+    fn join_session(
+        session_id: &str,
+        account: Account,
+    ) -> Result<(Self, broadcast::Receiver<Broadcast>), JoinError> {
+        let mut sessions = SESSIONS.lock().expect("Poisoned lock");
+        let session = sessions.get_mut(session_id).ok_or(JoinError::NotFound)?;
+        if session.members >= get_config().sync_sessions.max_members {
+            return Err(JoinError::Full);
+        }
+        let member = Member {
+            session_id: session_id.to_string(),
+            id: session.next_member,
+            host: false,
+            account,
+        };
+        if !member.can_access(session.state.file.as_deref()) {
+            return Err(JoinError::NotAllowed);
+        }
+        session.members += 1;
+        session.next_member += 1;
+        let _ = session.tx.send((
+            member.id,
+            None,
+            to_json(&Notice::Members {
+                count: session.members,
+            }),
+        ));
+        let rx = session.tx.subscribe();
+        Ok((member, rx))
+    }
+
+    fn with_session<F, T>(&self, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut Session) -> T,
+    {
+        SESSIONS
+            .lock()
+            .expect("Poisoned lock")
+            .get_mut(&self.session_id)
+            .map(f)
+    }
+
+    fn state_message(&self) -> Option<String> {
+        self.with_session(|s| {
+            self.can_access(s.state.file.as_deref())
+                .then(|| to_json(&Notice::State(s.state.snapshot())))
+        })
+        .flatten()
+    }
+
+    /// Processes message from client and returns optional direct reply
+    fn process(&self, event: Event) -> Option<String> {
+        if self.host {
+            let file = event_file(&event);
+            if !self.can_access(file) {
+                return Some(to_json(&Notice::Error {
+                    message: ApiError::CollectionNotAllowed.message(),
+                }));
+            }
+            let collection = file.and_then(file_collection);
+            self.with_session(|s| {
+                s.state.apply(&event);
+                if !matches!(event, Event::Position { .. }) {
+                    let _ = s.tx.send((self.id, collection, to_json(&event)));
+                }
+            });
+            None
+        } else {
+            match event {
+                Event::Position { position } => {
+                    let max_drift = f64::from(get_config().sync_sessions.max_drift);
+                    self.with_session(|s| s.state.correction(position, max_drift))
+                        .flatten()
+                        .map(|e| to_json(&e))
+                }
+                _ => Some(to_json(&Notice::Error {
+                    message: "Only host can control playback",
+                })),
+            }
+        }
+    }
+}
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        let mut sessions = SESSIONS.lock().expect("Poisoned lock");
+        if self.host {
+            if let Some(session) = sessions.remove(&self.session_id) {
+                debug!("Sync session {} closed by host", self.session_id);
+                let _ = session.tx.send((self.id, None, to_json(&Notice::Closed)));
+            }
+        } else if let Some(session) = sessions.get_mut(&self.session_id) {
+            session.members -= 1;
+            let _ = session.tx.send((
+                self.id,
+                None,
+                to_json(&Notice::Members {
+                    count: session.members,
+                }),
+            ));
+        }
+    }
+}
+
+async fn run_member(
+    mut ws: WebSocket<()>,
+    member: Member,
+    mut rx: broadcast::Receiver<Broadcast>,
+) -> Result<(), ws::Error> {
+    let cfg = &get_config().sync_sessions;
+    ws.send(Message::text(to_json(&Notice::Session {
+        id: &member.session_id,
+        host: member.host,
+    })))
+    .await?;
+    if let Some(state) = member.state_message() {
+        ws.send(Message::text(state)).await?;
+    }
+
+    let mut sync_timer = tokio::time::interval(cfg.sync_interval);
+    let mut deadline = tokio::time::Instant::now() + cfg.ws_timeout;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep_until(deadline) => {
+                debug!("Timeout on sync session websocket - let's close");
+                break;
+            }
+            msg = ws.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(e)) => {
+                        error!("Sync session message error: {}", e);
+                        continue;
+                    }
+                    None => break,
+                };
+                deadline = tokio::time::Instant::now() + cfg.ws_timeout;
+                if !msg.is_text() {
+                    continue;
+                }
+                let reply = match serde_json::from_str::<Event>(msg.to_str()?) {
+                    Ok(event) => member.process(event),
+                    Err(e) => {
+                        error!("Invalid sync session message: {}", e);
+                        Some(to_json(&Notice::Error { message: "Invalid message" }))
+                    }
+                };
+                if let Some(reply) = reply {
+                    ws.send(Message::text(reply)).await?;
+                }
+            }
+            msg = rx.recv() => match msg {
+                Ok((from, collection, text)) => {
+                    if from != member.id && collection.map_or(true, |c| member.is_collection_allowed(c)) {
+                        ws.send(Message::text(text)).await?;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    if let Some(state) = member.state_message() {
+                        ws.send(Message::text(state)).await?;
+                    }
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = sync_timer.tick(), if member.host => {
+                member.with_session(|s| {
+                    if s.state.playing {
+                        let collection = s.state.file.as_deref().and_then(file_collection);
+                        let _ = s.tx.send((member.id, collection, to_json(&Notice::State(s.state.snapshot()))));
+                    }
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Websocket endpoint `/sync` creates new session, `/sync/{session_id}` joins existing one
+pub fn sync_service(req: RequestWrapper, path: &str, account: Account) -> ResponseResult {
+    let session_id = path
+        .strip_prefix("/sync")
+        .map(|p| p.trim_matches('/'))
+        .unwrap_or_default();
+    let (member, rx) = if session_id.is_empty() {
+        match Member::create_session(account) {
+            Some(m) => m,
+            None => {
+                warn!("Maximum number of sync sessions reached");
+                return Ok(response::too_many_requests());
+            }
+        }
+    } else {
+        match Member::join_session(session_id, account) {
+            Ok(m) => m,
+            Err(JoinError::Full) => {
+                warn!("Sync session {} is full", session_id);
+                return Ok(response::too_many_requests());
+            }
+            Err(JoinError::NotFound) => {
+                error!("Sync session {} does not exist", session_id);
+                return Ok(response::not_found());
+            }
+            Err(JoinError::NotAllowed) => {
+                warn!(
+                    "Sync session {} plays collection not allowed for account",
+                    session_id
+                );
+                return Ok(ApiError::CollectionNotAllowed.response());
+            }
+        }
+    };
+
+    match upgrade_connection(req.into_request(), ()) {
+        Err(r) => Ok(box_websocket_response(r)),
+        Ok((r, ws_future)) => {
+            tokio::spawn(async move {
+                match ws_future.await {
+                    Ok(ws) => {
+                        if let Err(e) = run_member(ws, member, rx).await {
+                            error!("Sync session websocket error: {}", e);
+                        }
+                    }
+                    Err(_) => error!("Failed upgrade to websocket"),
+                }
+            });
+            Ok(box_websocket_response(r))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_format() {
+        let e: Event =
+            serde_json::from_str(r#"{"type":"play","file":"0/book/01.mp3","position":12.5}"#)
+                .unwrap();
+        assert_eq!(
+            Event::Play {
+                file: Some("0/book/01.mp3".into()),
+                position: 12.5
+            },
+            e
+        );
+        let e: Event = serde_json::from_str(r#"{"type":"position","position":3}"#).unwrap();
+        assert_eq!(Event::Position { position: 3.0 }, e);
+        assert_eq!(
+            r#"{"type":"members","count":2}"#,
+            to_json(&Notice::Members { count: 2 })
+        );
+    }
+
+    #[test]
+    fn test_file_access() {
+        crate::config::init::init_default_config();
+        assert_eq!(Some(1), file_collection("1/book/01.mp3"));
+        assert_eq!(None, file_collection("book/01.mp3"));
+        let member = Member {
+            session_id: "none".into(),
+            id: 1,
+            host: false,
+            account: None,
+        };
+        assert!(member.can_access(None));
+        assert!(member.can_access(Some("0/book/01.mp3")));
+        assert!(!member.can_access(Some("book/01.mp3")));
+    }
+
+    #[test]
+    fn test_playback_state() {
+        let mut state = PlaybackState::default();
+        state.apply(&Event::Seek {
+            file: Some("0/book/01.mp3".into()),
+            position: 100.0,
+        });
+        assert!(!state.playing);
+        assert_eq!(100.0, state.current_position());
+        assert!(state.correction(101.0, 2.0).is_none());
+        assert_eq!(
+            Some(Event::Seek {
+                file: Some("0/book/01.mp3".into()),
+                position: 100.0
+            }),
+            state.correction(110.0, 2.0)
+        );
+
+        state.apply(&Event::Play {
+            file: None,
+            position: 50.0,
+        });
+        assert!(state.playing);
+        assert_eq!(Some("0/book/01.mp3"), state.file.as_deref());
+        assert!(state.current_position() >= 50.0);
+        state.apply(&Event::Pause { position: 60.0 });
+        assert!(!state.playing);
+        assert_eq!(60.0, state.current_position());
+    }
+}
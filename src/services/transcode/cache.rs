@@ -1,8 +1,15 @@
 use crate::config::get_config;
 use crate::services::transcode::TimeSpan;
-use simple_file_cache::AsyncCache as Cache;
+use serde::{Deserialize, Serialize};
+use simple_file_cache::{
+    content_fingerprint, fingerprint::SAMPLE_SIZE, AsyncCache as Cache, MaintenanceReport,
+};
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, Metadata};
-use std::path::Path;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
 
 use super::ChosenTranscoding;
 
@@ -16,29 +23,151 @@ lazy_static! {
             if !cache_dir.exists() {
                 fs::create_dir(cache_dir).expect("Cannot create directory for cache")
             }
+            let max_size = u64::from(cfg.transcoding.cache.max_size) * 1024 * 1024;
+            let max_files = cfg.transcoding.cache.max_files.into();
             Some(
-                Cache::new(
-                    cache_dir,
-                    u64::from(cfg.transcoding.cache.max_size) * 1024 * 1024,
-                    cfg.transcoding.cache.max_files.into(),
-                )
+                if cfg.transcoding.cache.resume {
+                    Cache::new_resumable(cache_dir, max_size, max_files)
+                } else {
+                    Cache::new(cache_dir, max_size, max_files)
+                }
                 .expect("Cannot create cache"),
             )
         }
     };
+    /// Known content fingerprints of files, so unchanged files are not read again
+    /// and their content keyed entries can be found, when files are removed
+    static ref FINGERPRINTS: Mutex<HashMap<PathBuf, KnownFingerprint>> =
+        Mutex::new(load_fingerprints());
 }
 
+/// File in cache root directory, where known fingerprints are saved
+const FINGERPRINTS_FILE: &str = "fingerprints.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KnownFingerprint {
+    size: u64,
+    mtime: SystemTime,
+    fingerprint: String,
+}
+
+fn fingerprints_path() -> PathBuf {
+    get_config()
+        .transcoding
+        .cache
+        .root_dir
+        .join(FINGERPRINTS_FILE)
+}
+
+fn load_fingerprints() -> HashMap<PathBuf, KnownFingerprint> {
+    let path = fingerprints_path();
+    if !path.exists() {
+        return HashMap::new();
+    }
+    fs::read(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|data| serde_json::from_slice(&data).map_err(anyhow::Error::from))
+        .unwrap_or_else(|e| {
+            error!("Cannot load content fingerprints from {:?}: {}", path, e);
+            HashMap::new()
+        })
+}
+
+/// Saves known content fingerprints into cache directory, if content keys are used
+pub fn save_fingerprints() -> anyhow::Result<()> {
+    if !get_config().transcoding.cache.content_keys {
+        return Ok(());
+    }
+    let data = serde_json::to_vec(&*FINGERPRINTS.lock().expect("Poisoned lock"))?;
+    let path = fingerprints_path();
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data)?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Returns content fingerprint of file, remembered one is used, if file size and mtime did not change
+fn file_fingerprint(path: PathBuf, meta: &Metadata) -> std::io::Result<String> {
+    let size = meta.len();
+    let mtime = meta.modified()?;
+    if let Some(known) = FINGERPRINTS.lock().expect("Poisoned lock").get(&path) {
+        if known.size == size && known.mtime == mtime {
+            return Ok(known.fingerprint.clone());
+        }
+    }
+    let fingerprint = content_fingerprint(&path, SAMPLE_SIZE)?;
+    FINGERPRINTS.lock().expect("Poisoned lock").insert(
+        path,
+        KnownFingerprint {
+            size,
+            mtime,
+            fingerprint: fingerprint.clone(),
+        },
+    );
+    Ok(fingerprint)
+}
+
+/// Forgets fingerprints of file or folder (absolute path) and returns those, which are not used
+/// by any other known file
+fn forget_fingerprints(path: &Path) -> HashSet<String> {
+    let mut known = FINGERPRINTS.lock().expect("Poisoned lock");
+    let mut forgotten = HashSet::new();
+    known.retain(|p, f| {
+        if p.starts_with(path) {
+            forgotten.insert(f.fingerprint.clone());
+            false
+        } else {
+            true
+        }
+    });
+    for f in known.values() {
+        forgotten.remove(&f.fingerprint);
+    }
+    forgotten
+}
+
+/// Number of cache entries, which are waiting to be committed or rolled back
+static PENDING_FINISHES: AtomicUsize = AtomicUsize::new(0);
+
+/// Marks cache entry as pending until it's dropped - e.g. after it's committed or rolled back
+pub struct PendingFinish(());
+
+impl PendingFinish {
+    pub fn start() -> Self {
+        PENDING_FINISHES.fetch_add(1, Ordering::AcqRel);
+        PendingFinish(())
+    }
+}
+
+impl Drop for PendingFinish {
+    fn drop(&mut self) {
+        PENDING_FINISHES.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+pub fn pending_finishes() -> usize {
+    PENDING_FINISHES.load(Ordering::Acquire)
+}
+
+/// Prefix of file identification in key, when it's content fingerprint,
+/// paths are absolute, so they cannot collide with it
+const CONTENT_KEY_PREFIX: char = '#';
+
 //TODO: not ideal as potential collisions for non-unicode names
 pub fn cache_key<P: AsRef<Path>>(
     file: P,
     quality: &ChosenTranscoding,
     span: Option<TimeSpan>,
 ) -> String {
+    cache_key_for(&file.as_ref().to_string_lossy(), quality, span)
+}
+
+fn cache_key_for(file_id: &str, quality: &ChosenTranscoding, span: Option<TimeSpan>) -> String {
     let mut key: String = quality.level.to_letter().into();
     if !quality.tag.is_empty() {
         key.push_str(quality.tag);
     }
-    key.push_str(&file.as_ref().to_string_lossy());
+    key.push_str(file_id);
 
     if let Some(span) = span {
         key.push('/');
@@ -53,7 +182,29 @@ pub async fn cache_key_async<P: AsRef<Path>>(
     span: Option<TimeSpan>,
 ) -> std::io::Result<(String, Metadata)> {
     let meta = tokio::fs::metadata(&file).await?;
-    let key: String = cache_key(file, quality, span);
+    let key = if get_config().transcoding.cache.content_keys {
+        let path = file.as_ref().to_owned();
+        let file_meta = meta.clone();
+        let fingerprint =
+            tokio::task::spawn_blocking(move || file_fingerprint(path, &file_meta)).await;
+        match fingerprint {
+            Ok(Ok(fp)) => cache_key_for(&format!("{}{}", CONTENT_KEY_PREFIX, fp), quality, span),
+            Ok(Err(e)) => {
+                warn!(
+                    "Cannot fingerprint file {:?}, using path as cache key: {}",
+                    file.as_ref(),
+                    e
+                );
+                cache_key(file, quality, span)
+            }
+            Err(e) => {
+                error!("Fingerprinting task failed: {}", e);
+                cache_key(file, quality, span)
+            }
+        }
+    } else {
+        cache_key(file, quality, span)
+    };
     Ok((key, meta))
 }
 
@@ -61,6 +212,61 @@ pub fn get_cache() -> &'static Cache {
     CACHE.as_ref().unwrap()
 }
 
+/// Trims cache to given percentage of its limits and removes orphaned files, None if cache is disabled
+pub async fn maintain(target_percent: u8) -> Option<anyhow::Result<MaintenanceReport>> {
+    let cache = CACHE.as_ref()?;
+    let stats = cache.stats(0);
+    let pct = u64::from(target_percent);
+    Some(
+        cache
+            .maintain(stats.max_size * pct / 100, stats.max_files * pct / 100)
+            .await
+            .map_err(anyhow::Error::from),
+    )
+}
+
+/// Prefixes of keys (all qualities) belonging to file or folder path - e.g. file itself,
+/// its time spans and all files within folder
+fn path_prefixes<'a>(keys: impl Iterator<Item = &'a String>, path: &str) -> HashSet<String> {
+    let mut prefixes = HashSet::new();
+    for key in keys {
+        if let Some(idx) = key.find(path) {
+            let (quality, rest) = (&key[..idx], &key[idx + path.len()..]);
+            if quality.contains(MAIN_SEPARATOR) {
+                continue;
+            }
+            if rest.is_empty() {
+                prefixes.insert(key.clone());
+            } else if rest.starts_with('/') || rest.starts_with(MAIN_SEPARATOR) {
+                prefixes.insert(format!("{}{}{}", quality, path, &rest[..1]));
+            }
+        }
+    }
+    prefixes
+}
+
+/// Removes all cached transcodings of file or folder (absolute path), returns number of removed entries.
+/// Entries keyed by content fingerprint are removed, if no other known file has same content.
+pub async fn remove_path(path: &Path) -> anyhow::Result<u64> {
+    let cache = match CACHE.as_ref() {
+        Some(c) => c,
+        None => return Ok(0),
+    };
+    let keys = cache.keys();
+    let mut prefixes = path_prefixes(keys.iter(), &path.to_string_lossy());
+    for fingerprint in forget_fingerprints(path) {
+        prefixes.extend(path_prefixes(
+            keys.iter(),
+            &format!("{}{}", CONTENT_KEY_PREFIX, fingerprint),
+        ));
+    }
+    let mut removed = 0;
+    for prefix in prefixes {
+        removed += cache.remove_prefix(prefix).await?;
+    }
+    Ok(removed)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -83,4 +289,63 @@ mod tests {
         );
         assert_eq!("mabcd/home/ivan/neco/0-5", key);
     }
+
+    #[test]
+    fn test_path_prefixes() {
+        let keys: Vec<String> = [
+            "m/audio/book/01.mp3",
+            "mabcd/audio/book/02.mp3/0-5",
+            "l/audio/book/01.mp3",
+            "m/audio/book 2/01.mp3",
+            "m/audio/other/book/01.mp3",
+            "h/audio/book.mp3",
+        ]
+        .iter()
+        .map(|s| s.replace('/', std::path::MAIN_SEPARATOR_STR))
+        .collect();
+        let path = format!("{0}audio{0}book", MAIN_SEPARATOR);
+        let mut prefixes: Vec<_> = path_prefixes(keys.iter(), &path).into_iter().collect();
+        prefixes.sort();
+        let expected: Vec<_> = ["l/audio/book/", "m/audio/book/", "mabcd/audio/book/"]
+            .iter()
+            .map(|s| s.replace('/', std::path::MAIN_SEPARATOR_STR))
+            .collect();
+        assert_eq!(expected, prefixes);
+
+        let path = format!("{0}audio{0}book.mp3", MAIN_SEPARATOR);
+        let prefixes: Vec<_> = path_prefixes(keys.iter(), &path).into_iter().collect();
+        assert_eq!(vec![keys[5].clone()], prefixes);
+    }
+
+    #[test]
+    fn test_content_cache_key() {
+        let quality = ChosenTranscoding {
+            level: QualityLevel::Low,
+            format: TranscodingFormat::Remux,
+            tag: "",
+        };
+        let fp = content_fingerprint("test_data/01-file.mp3", SAMPLE_SIZE).unwrap();
+        let key = cache_key_for(&format!("{}{}", CONTENT_KEY_PREFIX, fp), &quality, None);
+        assert_eq!(format!("l#{}", fp), key);
+    }
+
+    #[test]
+    fn test_forget_fingerprints() {
+        crate::config::init::init_default_config();
+        let known = |fingerprint: &str| KnownFingerprint {
+            size: 1,
+            mtime: SystemTime::UNIX_EPOCH,
+            fingerprint: fingerprint.into(),
+        };
+        {
+            let mut fps = FINGERPRINTS.lock().unwrap();
+            fps.insert("/forget/book/01.mp3".into(), known("1-a"));
+            fps.insert("/forget/book/02.mp3".into(), known("1-b"));
+            fps.insert("/forget/copy/02.mp3".into(), known("1-b"));
+        }
+        let forgotten = forget_fingerprints(Path::new("/forget/book"));
+        assert_eq!(HashSet::from(["1-a".to_string()]), forgotten);
+        let forgotten = forget_fingerprints(Path::new("/forget/copy/02.mp3"));
+        assert_eq!(HashSet::from(["1-b".to_string()]), forgotten);
+    }
 }
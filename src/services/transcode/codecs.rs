@@ -88,6 +88,21 @@ pub struct Mp3 {
     mono: bool,
 }
 
+impl Mp3 {
+    pub fn new(bitrate: u32, compression_level: u8, abr: bool, mono: bool) -> Self {
+        Mp3 {
+            bitrate,
+            compression_level,
+            abr,
+            mono,
+        }
+    }
+
+    pub fn is_abr(&self) -> bool {
+        self.abr
+    }
+}
+
 impl AudioCodec for Mp3 {
     fn quality_args(&self) -> Vec<Cow<'static, str>> {
         let mut v = vec![];
@@ -1,7 +1,7 @@
 use self::codecs::*;
 use crate::config::get_config;
 use crate::error::{bail, Result};
-use collection::TimeSpan;
+use collection::{audio_meta::CodecKind, TimeSpan};
 use futures::prelude::*;
 use mime::Mime;
 use myhy::response::ChunkStream;
@@ -9,17 +9,21 @@ use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::ffi::OsStr;
 use std::fmt::Debug;
+use std::path::Path;
 #[cfg(feature = "transcoding-cache")]
 use std::pin::Pin;
 use std::process::Stdio;
 use std::sync::atomic::Ordering;
 use std::time::{Duration, Instant};
-use tokio::process::{ChildStdout, Command};
+use tokio::io::AsyncReadExt;
+use tokio::process::{ChildStderr, ChildStdout, Command};
 use tokio::time::timeout;
 
 #[cfg(feature = "transcoding-cache")]
 pub mod cache;
 pub mod codecs;
+#[cfg(feature = "transcoding-cache")]
+pub mod preheat;
 
 pub struct AudioFormat {
     pub ffmpeg: &'static str,
@@ -95,6 +99,29 @@ impl TranscodingFormat {
         }
     }
 
+    /// codec produced by this transcoding
+    pub fn codec_kind(&self) -> Option<CodecKind> {
+        match self {
+            TranscodingFormat::OpusInOgg(_) | TranscodingFormat::OpusInWebm(_) => {
+                Some(CodecKind::Opus)
+            }
+            TranscodingFormat::Mp3(_) => Some(CodecKind::Mp3),
+            TranscodingFormat::AacInAdts(_) => Some(CodecKind::Aac),
+            TranscodingFormat::Remux => None,
+        }
+    }
+
+    /// file extension for file in this format, Remux has no own format
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            TranscodingFormat::OpusInOgg(_) => Some("opus"),
+            TranscodingFormat::OpusInWebm(_) => Some("webm"),
+            TranscodingFormat::Mp3(_) => Some("mp3"),
+            TranscodingFormat::AacInAdts(_) => Some("aac"),
+            TranscodingFormat::Remux => None,
+        }
+    }
+
     pub fn mime(&self) -> Mime {
         let m = match self {
             TranscodingFormat::OpusInOgg(_) => "audio/ogg",
@@ -123,6 +150,27 @@ impl ChosenTranscoding {
         }
     }
 
+    /// Named profile from config, profile name is used as tag
+    pub fn for_level_and_profile(
+        level: QualityLevel,
+        profile: &'static crate::config::TranscodingDetails,
+    ) -> Self {
+        Self {
+            format: profile.get(level),
+            level,
+            tag: profile.tag.as_str(),
+        }
+    }
+
+    /// Constant bitrate transcoding for casting devices, it has own tag in cache
+    pub fn for_cast() -> Self {
+        Self {
+            format: get_config().transcoding.cast.clone(),
+            level: QualityLevel::High,
+            tag: "cast",
+        }
+    }
+
     pub fn for_level_and_user_agent(level: QualityLevel, user_agent: Option<&str>) -> Self {
         let cfg = &get_config().transcoding;
         if let Some(user_agent) = user_agent {
@@ -146,7 +194,47 @@ impl ChosenTranscoding {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Copy)]
+/// How to send file, when transcoding is requested
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranscodingDecision {
+    Transcode,
+    /// file already has required codec and is in required container
+    Passthrough,
+    /// file already has required codec, but in different container, so only audio stream is copied
+    Remux,
+}
+
+impl TranscodingDecision {
+    /// Decides on codec, bitrate (kbps) and mime type of the file, bitrate can be slightly over
+    /// requested one (10%), because of variable bitrate files
+    pub fn decide(
+        codec: CodecKind,
+        bitrate: u32,
+        file_mime: &Mime,
+        format: &TranscodingFormat,
+    ) -> Self {
+        if format.codec_kind() != Some(codec)
+            || bitrate == 0
+            || bitrate * 10 > format.bitrate() * 11
+        {
+            TranscodingDecision::Transcode
+        } else if file_mime.essence_str() == format.mime().essence_str() {
+            TranscodingDecision::Passthrough
+        } else {
+            TranscodingDecision::Remux
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TranscodingDecision::Transcode => "transcode",
+            TranscodingDecision::Passthrough => "passthrough",
+            TranscodingDecision::Remux => "remux",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Copy)]
 pub enum QualityLevel {
     Low,
     Medium,
@@ -210,8 +298,12 @@ impl Transcoder {
     }
 
     fn base_ffmpeg(&self, seek: Option<f32>, span: Option<TimeSpan>) -> Command {
-        let mut cmd = Command::new("ffmpeg");
+        let ffmpeg = &get_config().transcoding.ffmpeg;
+        let mut cmd = Command::new(&ffmpeg.path);
         cmd.args(["-nostdin", "-v", "error"]);
+        cmd.args(&ffmpeg.global_args);
+        #[cfg(unix)]
+        limit_process(&mut cmd, ffmpeg);
         let offset = span.as_ref().map(|s| s.start).unwrap_or(0) as f32;
         let time = span.and_then(|s| s.duration).unwrap_or(0);
         let seek = seek.unwrap_or(0f32);
@@ -256,14 +348,23 @@ impl Transcoder {
         let mut cmd = self.base_ffmpeg(seek, span);
         let targs = self.quality.format.args();
         self.input_file_args(&mut cmd, file);
+        if get_config().transcoding.loudness_normalize {
+            // uses ReplayGain side data of input, file without ReplayGain is not changed
+            cmd.args(["-af", "volume=replaygain=track:replaygain_noclip=1"]);
+        }
         cmd.args(targs.codec_args)
-            .args(targs.quality_args.iter().map(|i| i.as_ref()))
-            .arg("-f")
+            .args(targs.quality_args.iter().map(|i| i.as_ref()));
+        #[cfg(feature = "transcoding-cache")]
+        if get_config().transcoding.cache.resume {
+            // resuming relies on same output for same input, so it's safe to skip already cached part
+            cmd.args(["-fflags", "+bitexact", "-flags:a", "+bitexact"]);
+        }
+        cmd.arg("-f")
             .arg(targs.format)
             .arg("pipe:1")
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
-            .stderr(Stdio::inherit());
+            .stderr(Stdio::piped());
         cmd
     }
 
@@ -289,10 +390,40 @@ impl Transcoder {
             .arg("pipe:1")
             .stdin(Stdio::null())
             .stdout(Stdio::piped())
-            .stderr(Stdio::inherit());
+            .stderr(Stdio::piped());
         cmd
     }
 
+    /// Transcodes whole file into output file, unlike streaming transcoding metadata are kept
+    pub async fn transcode_to_file(&self, file: &Path, output: &Path) -> Result<()> {
+        let targs = self.quality.format.args();
+        if targs.format.is_empty() {
+            bail!("Cannot transcode to file without target format");
+        }
+        let mut cmd = self.base_ffmpeg(None, None);
+        cmd.arg("-i")
+            .arg(file)
+            .args(["-y", "-map_metadata", "0", "-map", "a"])
+            .args(targs.codec_args)
+            .args(targs.quality_args.iter().map(|i| i.as_ref()))
+            .arg("-f")
+            .arg(targs.format)
+            .arg(output)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        let res = cmd.output().await?;
+        if !res.status.success() {
+            bail!(
+                "Transcoding of {:?} failed with code {:?}, ffmpeg output:\n{}",
+                file,
+                res.status.code(),
+                String::from_utf8_lossy(&res.stderr)
+            );
+        }
+        Ok(())
+    }
+
     pub fn transcoding_params(&self) -> String {
         format!(
             "codec={}; bitrate={}",
@@ -309,13 +440,22 @@ impl Transcoder {
         span: Option<TimeSpan>,
         counter: super::Counter,
     ) -> impl Future<Output = Result<ChunkStream<ChildStdout>>> {
-        future::ready(
-            self.transcode_inner(file, seek, span, counter)
-                .map(|(stream, f)| {
-                    tokio::spawn(f);
-                    stream
-                }),
-        )
+        future::ready(self.transcode_uncached(file, seek, span, counter))
+    }
+
+    /// Transcodes without storing result into the cache
+    pub fn transcode_uncached<S: AsRef<OsStr> + Send + Debug + 'static>(
+        &self,
+        file: AudioFilePath<S>,
+        seek: Option<f32>,
+        span: Option<TimeSpan>,
+        counter: super::Counter,
+    ) -> Result<ChunkStream<ChildStdout>> {
+        self.transcode_inner(file, seek, span, counter)
+            .map(|(stream, f)| {
+                tokio::spawn(f);
+                stream
+            })
     }
 
     #[cfg(feature = "transcoding-cache")]
@@ -349,7 +489,25 @@ impl Transcoder {
         let (key, meta) = cache::cache_key_async(file.as_ref().as_ref(), &self.quality, span)
             .await
             .map_err(|e| crate::error::Error::msg(format!("Cache key error: {}", e)))?;
-        match get_cache().add(key, meta.into()).await {
+        let entry = if get_config().transcoding.cache.resume {
+            match get_cache().add_resume(&key, meta.clone().into()).await {
+                Ok(Some((cache_file, cache_finish, written, len))) => {
+                    debug!("Resuming cache entry {} after {} bytes", key, len);
+                    Ok((cache_file, cache_finish, Some((written, len))))
+                }
+                Ok(None) => get_cache()
+                    .add(key, meta.into())
+                    .await
+                    .map(|(f, finish)| (f, finish, None)),
+                Err(e) => Err(e),
+            }
+        } else {
+            get_cache()
+                .add(key, meta.into())
+                .await
+                .map(|(f, finish)| (f, finish, None))
+        };
+        match entry {
             Err(e) => {
                 warn!("Cannot create cache entry: {}", e);
                 self.transcode_inner(file, seek, span, counter)
@@ -358,11 +516,12 @@ impl Transcoder {
                         Box::pin(stream) as TranscodedStream
                     })
             }
-            Ok((cache_file, cache_finish)) => {
+            Ok((cache_file, cache_finish, written)) => {
+                let pending = cache::PendingFinish::start();
                 self.transcode_inner(file, seek, span, counter)
-                    .map(|(mut stream, f)| {
+                    .map(|(stream, f)| {
                         tokio::spawn(f.then(|res| {
-                            match res {
+                            let finish = match res {
                                 Ok(()) => cache_finish
                                     .commit()
                                     .map_err(|e| error!("Error in cache: {}", e))
@@ -381,12 +540,18 @@ impl Transcoder {
                                     .roll_back()
                                     .map_err(|e| error!("Error in cache: {}", e))
                                     .boxed(),
-                            }
+                            };
+                            finish.map(move |res| {
+                                drop(pending);
+                                res
+                            })
                         }));
                         let cache_sink = tokio_util::codec::FramedWrite::new(
                             cache_file,
                             self::vec_codec::VecEncoder,
                         );
+                        let skip = written.as_ref().map_or(0, |(_, len)| *len);
+                        let mut stream = skip_bytes(stream, skip);
                         let (tx, rx) = mpsc::channel(64);
                         let mut tx = cache_sink
                             .fanout(tx.sink_map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
@@ -397,7 +562,13 @@ impl Transcoder {
                             }
                         };
                         tokio::spawn(f);
-                        Box::pin(rx.map(Ok)) as TranscodedStream
+                        match written {
+                            Some((written, len)) => Box::pin(
+                                ChunkStream::new_with_limit(written, len).chain(rx.map(Ok)),
+                            )
+                                as TranscodedStream,
+                            None => Box::pin(rx.map(Ok)) as TranscodedStream,
+                        }
                     })
             }
         }
@@ -424,6 +595,16 @@ impl Transcoder {
         };
         match cmd.spawn() {
             Ok(mut child) => {
+                let stderr = child
+                    .stderr
+                    .take()
+                    .map(|err| tokio::spawn(read_stderr(err)));
+                let error_output = || async move {
+                    match stderr {
+                        Some(task) => task.await.unwrap_or_default(),
+                        None => String::new(),
+                    }
+                };
                 if let Some(out) = child.stdout.take() {
                     let start = Instant::now();
                     let stream = ChunkStream::new(out);
@@ -448,10 +629,12 @@ impl Transcoder {
                                         Instant::now() - start);
                                         Ok(())
                                     } else {
-                                        warn!(
-                                            "Transconding of file {:?} failed with code {:?}",
+                                        let output = error_output().await;
+                                        error!(
+                                            "Transconding of file {:?} failed with code {:?}, ffmpeg output:\n{}",
                                             file.as_ref(),
-                                            res.code()
+                                            res.code(),
+                                            output
                                         );
                                         Err(())
                                     }
@@ -493,6 +676,74 @@ impl Transcoder {
     }
 }
 
+/// Applies niceness, IO priority and memory limit to spawned process
+#[cfg(unix)]
+fn limit_process(cmd: &mut Command, cfg: &crate::config::FfmpegConfig) {
+    use nix::libc;
+
+    let nice = cfg.nice;
+    let ionice = cfg.ionice;
+    let memory_limit = cfg
+        .memory_limit
+        .map(|m| libc::rlim_t::from(m) * 1024 * 1024);
+    if nice.is_none() && ionice.is_none() && memory_limit.is_none() {
+        return;
+    }
+    // only async signal safe calls are used in child process
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(level) = ionice {
+                const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+                const IOPRIO_CLASS_BE: libc::c_long = 2;
+                const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+                let prio = IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT | libc::c_long::from(level);
+                if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, prio) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            let _ = ionice;
+            if let Some(limit) = memory_limit {
+                let rlim = libc::rlimit {
+                    rlim_cur: limit,
+                    rlim_max: limit,
+                };
+                if libc::setrlimit(libc::RLIMIT_AS, &rlim) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Max. size of ffmpeg error output kept for logging
+const MAX_STDERR_SIZE: usize = 8 * 1024;
+
+/// Reads error output of ffmpeg process, only last part is kept
+async fn read_stderr(mut err: ChildStderr) -> String {
+    let mut output = Vec::new();
+    let mut buf = [0u8; 1024];
+    loop {
+        match err.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                output.extend_from_slice(&buf[..n]);
+                if output.len() > MAX_STDERR_SIZE {
+                    output.drain(..output.len() - MAX_STDERR_SIZE);
+                }
+            }
+        }
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
 pub fn guess_format<P: AsRef<std::path::Path>>(p: P) -> AudioFormat {
     const DEFAULT_FORMAT: (&str, &str) = ("matroska", "audio/x-matroska"); // matroska is fairly universal, so it's good chance that audio stream will fit in
     let t = match p.as_ref().extension() {
@@ -514,6 +765,30 @@ pub fn guess_format<P: AsRef<std::path::Path>>(p: P) -> AudioFormat {
     }
 }
 
+/// Drops first `skip` bytes of the stream - used when resuming partially cached transcoding
+#[cfg(feature = "transcoding-cache")]
+fn skip_bytes<S>(stream: S, mut skip: u64) -> impl Stream<Item = std::io::Result<Vec<u8>>> + Unpin
+where
+    S: Stream<Item = std::io::Result<Vec<u8>>> + Unpin,
+{
+    stream.filter_map(move |chunk| {
+        let res = match chunk {
+            Ok(mut data) if skip > 0 => {
+                let n = skip.min(data.len() as u64) as usize;
+                skip -= n as u64;
+                data.drain(..n);
+                if data.is_empty() {
+                    None
+                } else {
+                    Some(Ok(data))
+                }
+            }
+            other => Some(other),
+        };
+        future::ready(res)
+    })
+}
+
 #[cfg(feature = "transcoding-cache")]
 mod vec_codec {
     use bytes::BufMut;
@@ -647,4 +922,45 @@ mod tests {
         )
         .await;
     }
+
+    #[test]
+    fn test_transcoding_decision() {
+        let format =
+            TranscodingFormat::OpusInOgg(Opus::new(48, 8, Bandwidth::SuperWideBand, false));
+        let ogg: Mime = "audio/ogg".parse().unwrap();
+        let webm: Mime = "audio/webm".parse().unwrap();
+        let decide = TranscodingDecision::decide;
+        assert_eq!(
+            TranscodingDecision::Passthrough,
+            decide(CodecKind::Opus, 50, &ogg, &format)
+        );
+        assert_eq!(
+            TranscodingDecision::Remux,
+            decide(CodecKind::Opus, 32, &webm, &format)
+        );
+        assert_eq!(
+            TranscodingDecision::Transcode,
+            decide(CodecKind::Opus, 64, &ogg, &format)
+        );
+        assert_eq!(
+            TranscodingDecision::Transcode,
+            decide(CodecKind::Mp3, 32, &ogg, &format)
+        );
+        assert_eq!(
+            TranscodingDecision::Transcode,
+            decide(CodecKind::Opus, 0, &ogg, &format)
+        );
+    }
+
+    #[cfg(feature = "transcoding-cache")]
+    #[tokio::test]
+    async fn test_skip_bytes() {
+        let chunks: Vec<std::io::Result<Vec<u8>>> =
+            vec![Ok(vec![1, 2, 3]), Ok(vec![4, 5]), Ok(vec![6, 7, 8])];
+        let res: Vec<u8> = skip_bytes(stream::iter(chunks), 4)
+            .try_concat()
+            .await
+            .unwrap();
+        assert_eq!(vec![5, 6, 7, 8], res);
+    }
 }
@@ -0,0 +1,114 @@
+use super::cache::{cache_key_async, get_cache};
+use super::{AudioFilePath, ChosenTranscoding, QualityLevel, Transcoder};
+use crate::config::get_config;
+use crate::services::TranscodingDetails;
+use collection::{parse_chapter_path, Collections, FoldersOrdering};
+use futures::prelude::*;
+use std::path::PathBuf;
+use std::sync::{atomic::Ordering, Arc};
+use std::time::Duration;
+use tokio::task::spawn_blocking as blocking;
+
+/// How long to wait, when server is busy with other transcodings
+const BUSY_WAIT: Duration = Duration::from_secs(10);
+/// Preheating stops, when cache free capacity goes below this percentage
+const MIN_FREE_CAPACITY_PERCENT: u64 = 10;
+
+/// Walks all collections and transcodes audio files into transcoding cache,
+/// one file at a time and only when there are enough free transcoding slots
+pub async fn preheat_cache(
+    collections: Arc<Collections>,
+    level: QualityLevel,
+    transcoding: TranscodingDetails,
+) {
+    let quality = ChosenTranscoding::for_level_and_user_agent(level, None);
+    info!("Starting transcoding cache preheat for quality {:?}", level);
+    let mut count = 0;
+    for collection in 0..get_config().base_dirs.len() {
+        let mut folders = vec![PathBuf::new()];
+        while let Some(folder_path) = folders.pop() {
+            let cols = collections.clone();
+            let folder = match blocking(move || {
+                cols.list_dir(collection, folder_path, FoldersOrdering::Alphabetical, None)
+            })
+            .await
+            {
+                Ok(Ok(folder)) => folder,
+                Ok(Err(e)) => {
+                    warn!("Cannot list folder for cache preheat: {}", e);
+                    continue;
+                }
+                Err(e) => {
+                    error!("Error in cache preheat listing: {}", e);
+                    continue;
+                }
+            };
+            folders.extend(folder.subfolders.into_iter().rev().map(|f| f.path));
+
+            for file in folder.files {
+                if !has_free_capacity() {
+                    info!(
+                        "Transcoding cache preheat stopped as cache is almost full, preheated {} files",
+                        count
+                    );
+                    return;
+                }
+                wait_for_free_slot(&transcoding).await;
+                let (real_path, span) = parse_chapter_path(&file.path);
+                let full_path = get_config()
+                    .base_dir_for(collection, &real_path)
+                    .join(real_path);
+                match cache_key_async(&full_path, &quality, span).await {
+                    Ok((key, meta)) => {
+                        if let Ok(Some(_)) = get_cache().get(key, meta.into()).await {
+                            continue;
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Cannot get cache key for {:?}: {}", full_path, e);
+                        continue;
+                    }
+                }
+
+                transcoding.transcodings.fetch_add(1, Ordering::Release);
+                let transcoder = Transcoder::new(quality.clone());
+                match transcoder
+                    .transcode(
+                        AudioFilePath::Original(full_path.clone()),
+                        None,
+                        span,
+                        transcoding.transcodings.clone(),
+                    )
+                    .await
+                {
+                    Ok(stream) => {
+                        // just drain stream, it's written to cache on the way
+                        stream.for_each(|_| future::ready(())).await;
+                        debug!("Preheated cache with {:?}", full_path);
+                        count += 1;
+                    }
+                    Err(e) => error!("Cannot transcode {:?} for cache: {}", full_path, e),
+                }
+            }
+        }
+    }
+    info!(
+        "Transcoding cache preheat finished, preheated {} files",
+        count
+    );
+}
+
+fn has_free_capacity() -> bool {
+    let cfg = &get_config().transcoding.cache;
+    let (free_files, free_size) = get_cache().free_capacity();
+    free_files * 100 > u64::from(cfg.max_files) * MIN_FREE_CAPACITY_PERCENT
+        && free_size * 100 > u64::from(cfg.max_size) * 1024 * 1024 * MIN_FREE_CAPACITY_PERCENT
+}
+
+/// Preheating can use only half of allowed transcoding processes, rest is left for clients
+async fn wait_for_free_slot(transcoding: &TranscodingDetails) {
+    let limit = (transcoding.max_transcodings / 2).max(1);
+    while transcoding.transcodings.load(Ordering::Acquire) >= limit {
+        tokio::time::sleep(BUSY_WAIT).await;
+    }
+}
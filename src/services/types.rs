@@ -1,9 +1,13 @@
 use super::transcode::{QualityLevel, TranscodingFormat};
 use crate::config::get_config;
-use collection::{AudioFile, AudioFolderShort};
+use collection::{AudioFile, AudioFolderShort, ScanStatus};
+use myhy::response::{error_response, HttpResponse};
+use myhy::StatusCode;
 use serde::Serialize;
+use std::fmt::Display;
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
 pub struct CollectionsInfo {
     pub version: &'static str,
     pub commit: &'static str,
@@ -11,9 +15,57 @@ pub struct CollectionsInfo {
     pub shared_positions: bool,
     pub count: u32,
     pub names: Vec<&'static str>,
+    /// collection index for each name, as some collections might not be accessible
+    pub indexes: Vec<usize>,
+    /// status for each name
+    pub status: Vec<CollectionStatus>,
+}
+
+/// Readiness of collection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub enum CollectionState {
+    /// initial scan is running, listings might be incomplete
+    Scanning,
+    Ready,
+    /// collection directory is not available, collection is served only from cache
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct CollectionStatus {
+    pub state: CollectionState,
+    pub folders_scanned: u64,
+    pub folders_queued: u64,
+}
+
+/// Metadata of audio file for casting device
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct CastInfo {
+    pub title: String,
+    pub album: Option<String>,
+    /// duration in seconds
+    pub duration: Option<u32>,
+    pub mime: String,
+    /// signed URL, which can be used without authentication
+    pub media_url: String,
+    pub cover_url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct CollectionScanStatus {
+    pub collection: usize,
+    pub name: &'static str,
+    #[serde(flatten)]
+    pub status: ScanStatus,
 }
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
 pub struct TranscodingSummary {
     bitrate: u32,
     name: &'static str,
@@ -29,6 +81,7 @@ impl From<TranscodingFormat> for TranscodingSummary {
 }
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
 pub struct Transcodings {
     pub max_transcodings: usize,
     pub low: TranscodingSummary,
@@ -53,6 +106,15 @@ impl Transcodings {
         }
     }
 
+    pub fn for_profile(profile: &crate::config::TranscodingDetails) -> Self {
+        Transcodings {
+            max_transcodings: get_config().transcoding.max_parallel_processes,
+            low: profile.get(QualityLevel::Low).into(),
+            medium: profile.get(QualityLevel::Medium).into(),
+            high: profile.get(QualityLevel::High).into(),
+        }
+    }
+
     pub fn for_user_agent(user_agent: &str) -> Self {
         let alt_configs = get_config().transcoding.alt_configs();
         if let Some(alt_configs) = alt_configs {
@@ -75,7 +137,195 @@ impl Transcodings {
     }
 }
 
+#[cfg(feature = "transcoding-cache")]
+#[derive(Debug, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
+pub struct TranscodingCacheStats {
+    pub disabled: bool,
+    pub size: u64,
+    pub num_files: u64,
+    pub max_size: u64,
+    pub max_files: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub oldest: Vec<String>,
+}
+
+#[cfg(feature = "transcoding-cache")]
+impl From<simple_file_cache::CacheStats> for TranscodingCacheStats {
+    fn from(s: simple_file_cache::CacheStats) -> Self {
+        TranscodingCacheStats {
+            disabled: false,
+            size: s.size,
+            num_files: s.num_files,
+            max_size: s.max_size,
+            max_files: s.max_files,
+            hits: s.hits,
+            misses: s.misses,
+            evictions: s.evictions,
+            oldest: s.oldest,
+        }
+    }
+}
+
+/// Errors of JSON API with stable machine readable codes, sent as error envelope
+/// `{"code", "message", "detail"}` - new codes can be added, but existing ones must not change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiError {
+    InvalidCollection,
+    #[cfg_attr(
+        not(any(feature = "shared-positions", feature = "sync-sessions")),
+        allow(dead_code)
+    )]
+    CollectionNotAllowed,
+    CollectionUnavailable,
+    FolderNotFound,
+    FileNotFound,
+    TextNotFound,
+    #[cfg_attr(
+        not(any(feature = "tags-write", feature = "shared-positions")),
+        allow(dead_code)
+    )]
+    InvalidJson,
+    InvalidParameter,
+    MissingParameter,
+    #[cfg_attr(
+        not(any(feature = "tags-write", feature = "shared-positions")),
+        allow(dead_code)
+    )]
+    InvalidData,
+    UnknownTranscodingProfile,
+    #[cfg_attr(not(feature = "tags-write"), allow(dead_code))]
+    UnsupportedCover,
+    ReadOnly,
+    ScanRunning,
+    #[cfg_attr(not(feature = "shared-positions"), allow(dead_code))]
+    PositionIgnored,
+    NotReady,
+    NotCached,
+    AlreadyExists,
+    ImportDisabled,
+    #[cfg_attr(not(feature = "shared-positions"), allow(dead_code))]
+    GroupNotFound,
+    #[cfg_attr(not(feature = "shared-positions"), allow(dead_code))]
+    SettingsTooLarge,
+    #[cfg_attr(not(feature = "folder-download"), allow(dead_code))]
+    DownloadTooLarge,
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidCollection => "invalid_collection",
+            ApiError::CollectionNotAllowed => "collection_not_allowed",
+            ApiError::CollectionUnavailable => "collection_unavailable",
+            ApiError::FolderNotFound => "folder_not_found",
+            ApiError::FileNotFound => "file_not_found",
+            ApiError::TextNotFound => "text_not_found",
+            ApiError::InvalidJson => "invalid_json",
+            ApiError::InvalidParameter => "invalid_parameter",
+            ApiError::MissingParameter => "missing_parameter",
+            ApiError::InvalidData => "invalid_data",
+            ApiError::UnsupportedCover => "unsupported_cover",
+            ApiError::UnknownTranscodingProfile => "unknown_transcoding_profile",
+            ApiError::ReadOnly => "read_only",
+            ApiError::ScanRunning => "scan_running",
+            ApiError::PositionIgnored => "position_ignored",
+            ApiError::NotReady => "not_ready",
+            ApiError::NotCached => "not_cached",
+            ApiError::AlreadyExists => "already_exists",
+            ApiError::ImportDisabled => "import_disabled",
+            ApiError::GroupNotFound => "group_not_found",
+            ApiError::SettingsTooLarge => "settings_too_large",
+            ApiError::DownloadTooLarge => "download_too_large",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidCollection
+            | ApiError::FolderNotFound
+            | ApiError::FileNotFound
+            | ApiError::TextNotFound
+            | ApiError::ImportDisabled
+            | ApiError::GroupNotFound => StatusCode::NOT_FOUND,
+            ApiError::CollectionNotAllowed => StatusCode::FORBIDDEN,
+            ApiError::CollectionUnavailable | ApiError::NotReady => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::InvalidJson
+            | ApiError::InvalidParameter
+            | ApiError::MissingParameter
+            | ApiError::InvalidData
+            | ApiError::UnsupportedCover
+            | ApiError::UnknownTranscodingProfile => StatusCode::BAD_REQUEST,
+            ApiError::ReadOnly
+            | ApiError::ScanRunning
+            | ApiError::NotCached
+            | ApiError::AlreadyExists => StatusCode::CONFLICT,
+            ApiError::PositionIgnored => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::SettingsTooLarge | ApiError::DownloadTooLarge => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            ApiError::InvalidCollection => "Invalid collection",
+            ApiError::CollectionNotAllowed => "Collection is not allowed for this account",
+            ApiError::CollectionUnavailable => "Collection is temporarily unavailable",
+            ApiError::FolderNotFound => "Folder not found",
+            ApiError::FileNotFound => "File not found",
+            ApiError::TextNotFound => "File has no text",
+            ApiError::InvalidJson => "Invalid JSON in request body",
+            ApiError::InvalidParameter => "Invalid request parameter",
+            ApiError::MissingParameter => "Required parameter is missing",
+            ApiError::InvalidData => "Invalid data",
+            ApiError::UnsupportedCover => "Unsupported cover image",
+            ApiError::UnknownTranscodingProfile => "Unknown transcoding profile",
+            ApiError::ReadOnly => "Collection is read only",
+            ApiError::ScanRunning => "Scan is already running",
+            ApiError::PositionIgnored => "Newer position already exists",
+            ApiError::NotReady => "Initial scan of collections is not finished yet",
+            ApiError::NotCached => "Collection is not cached",
+            ApiError::AlreadyExists => "Target path already exists",
+            ApiError::ImportDisabled => "Import directory is not configured",
+            ApiError::GroupNotFound => "Positions group not found",
+            ApiError::SettingsTooLarge => "Settings are too large",
+            ApiError::DownloadTooLarge => "Folder is too large for download",
+        }
+    }
+
+    pub fn response(self) -> HttpResponse {
+        error_response(self.status(), self.code(), self.message(), None)
+    }
+
+    /// Maps errors of collections, which are caused by state of collection rather then by failure,
+    /// so handlers can just propagate them
+    pub fn from_error(e: &crate::error::Error) -> Option<Self> {
+        e.chain()
+            .find_map(|c| c.downcast_ref::<collection::error::Error>())
+            .and_then(|e| match e {
+                collection::error::Error::ReadOnlyCollection => Some(ApiError::ReadOnly),
+                collection::error::Error::CollectionUnavailable => {
+                    Some(ApiError::CollectionUnavailable)
+                }
+                _ => None,
+            })
+    }
+
+    pub fn with_detail(self, detail: impl Display) -> HttpResponse {
+        error_response(
+            self.status(),
+            self.code(),
+            self.message(),
+            Some(&detail.to_string()),
+        )
+    }
+}
+
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "api-spec", derive(utoipa::ToSchema))]
 pub struct SearchResult {
     pub files: Vec<AudioFile>,
     pub subfolders: Vec<AudioFolderShort>,
@@ -91,6 +341,8 @@ mod download_format {
     #[derive(Debug, Clone, PartialEq, Eq)]
     pub enum DownloadFormat {
         Tar,
+        /// gzipped tar, size is not known upfront
+        Tgz,
         Zip,
     }
 
@@ -98,6 +350,7 @@ mod download_format {
         pub fn extension(&self) -> &'static str {
             match self {
                 DownloadFormat::Tar => ".tar",
+                DownloadFormat::Tgz => ".tar.gz",
                 DownloadFormat::Zip => ".zip",
             }
         }
@@ -105,6 +358,7 @@ mod download_format {
         pub fn mime(&self) -> mime::Mime {
             match self {
                 DownloadFormat::Tar => "application/x-tar".parse().unwrap(),
+                DownloadFormat::Tgz => "application/gzip".parse().unwrap(),
                 DownloadFormat::Zip => "application/zip".parse().unwrap(),
             }
         }
@@ -116,6 +370,7 @@ mod download_format {
         fn from_str(s: &str) -> Result<Self, Self::Err> {
             match s {
                 "tar" => Ok(DownloadFormat::Tar),
+                "tgz" => Ok(DownloadFormat::Tgz),
                 "zip" => Ok(DownloadFormat::Zip),
                 _ => Err(Error::msg("Invalid download archive format tag")),
             }
@@ -0,0 +1,226 @@
+//! Folders visible to account - allow and deny glob patterns from `folder_filters` config.
+//! Patterns are matched against paths relative to collection root, component by component,
+//! so `Children` matches folder `Children` in any collection root and `*/Kids` matches `Kids`
+//! subfolder of any top level folder, `**` matches any number of components.
+//! Everything within matching folder is matched too. Deny patterns have precedence over allow patterns.
+//! Folders on the way to allowed folders are visible (so they can be browsed), but their files are not.
+
+use super::types::SearchResult;
+use crate::config::{get_config, FolderFilterConfig};
+use collection::audio_meta::AudioFolder;
+use glob::Pattern;
+use std::path::{Component, Path};
+
+const ANY_COMPONENTS: &str = "**";
+
+enum Segment {
+    Any,
+    Name(Pattern),
+}
+
+type PathPattern = Vec<Segment>;
+
+fn parse(pattern: &str) -> Option<PathPattern> {
+    pattern
+        .trim_matches('/')
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            if s == ANY_COMPONENTS {
+                Some(Segment::Any)
+            } else {
+                Pattern::new(s)
+                    .map_err(|e| error!("Invalid folder filter pattern {}: {}", pattern, e))
+                    .ok()
+                    .map(Segment::Name)
+            }
+        })
+        .collect()
+}
+
+fn components(path: &Path) -> Vec<&str> {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(c) => c.to_str(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Pattern matches exactly these components
+fn matches(pattern: &[Segment], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((Segment::Any, rest)) => (0..=path.len()).any(|i| matches(rest, &path[i..])),
+        Some((Segment::Name(p), rest)) => match path.split_first() {
+            Some((name, path_rest)) => p.matches(name) && matches(rest, path_rest),
+            None => false,
+        },
+    }
+}
+
+/// Pattern matches path or some of its parents
+fn matches_within(pattern: &[Segment], path: &[&str]) -> bool {
+    (1..=path.len()).any(|i| matches(pattern, &path[..i]))
+}
+
+/// Pattern can match some descendant of path
+fn leads_to(pattern: &[Segment], path: &[&str]) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (_, None) => true,
+        (None, Some(_)) => false,
+        (Some((Segment::Any, _)), Some(_)) => true,
+        (Some((Segment::Name(p), rest)), Some((name, path_rest))) => {
+            p.matches(name) && leads_to(rest, path_rest)
+        }
+    }
+}
+
+pub struct FolderFilter {
+    allow: Vec<PathPattern>,
+    deny: Vec<PathPattern>,
+    /// some pattern is invalid, so nothing is visible, rather than showing what should be hidden
+    invalid: bool,
+}
+
+impl FolderFilter {
+    pub fn new(cfg: &FolderFilterConfig) -> Self {
+        let parse_all = |patterns: &[String]| patterns.iter().map(|p| parse(p)).collect();
+        let allow: Option<Vec<_>> = parse_all(&cfg.allow);
+        let deny: Option<Vec<_>> = parse_all(&cfg.deny);
+        let invalid = allow.is_none() || deny.is_none();
+        FolderFilter {
+            allow: allow.unwrap_or_default(),
+            deny: deny.unwrap_or_default(),
+            invalid,
+        }
+    }
+
+    /// Filter for account, None if account can see everything
+    pub fn for_account(account: Option<&str>) -> Option<Self> {
+        account
+            .and_then(|a| get_config().folder_filters.get(a))
+            .map(FolderFilter::new)
+    }
+
+    fn is_denied(&self, path: &[&str]) -> bool {
+        self.invalid || self.deny.iter().any(|p| matches_within(p, path))
+    }
+
+    /// Content of path (file or folder) can be accessed
+    pub fn is_allowed(&self, path: impl AsRef<Path>) -> bool {
+        let path = components(path.as_ref());
+        !self.is_denied(&path)
+            && (self.allow.is_empty() || self.allow.iter().any(|p| matches_within(p, &path)))
+    }
+
+    /// Folder can be listed - either it's allowed or it's on the way to allowed folder
+    pub fn is_visible(&self, path: impl AsRef<Path>) -> bool {
+        let path = components(path.as_ref());
+        !self.is_denied(&path)
+            && (self.allow.is_empty()
+                || self
+                    .allow
+                    .iter()
+                    .any(|p| matches_within(p, &path) || leads_to(p, &path)))
+    }
+
+    /// Removes from folder listing what is not visible
+    pub fn apply_folder(&self, folder_path: &Path, folder: &mut AudioFolder) {
+        folder.subfolders.retain(|f| self.is_visible(&f.path));
+        if !self.is_allowed(folder_path) {
+            folder.files.clear();
+            folder.cover = None;
+            folder.description = None;
+        } else {
+            folder.files.retain(|f| self.is_allowed(&f.path));
+        }
+    }
+
+    pub fn apply_search(&self, res: &mut SearchResult) {
+        res.subfolders.retain(|f| self.is_visible(&f.path));
+        res.files.retain(|f| self.is_allowed(&f.path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(allow: &[&str], deny: &[&str]) -> FolderFilter {
+        FolderFilter::new(&FolderFilterConfig {
+            allow: allow.iter().map(|s| s.to_string()).collect(),
+            deny: deny.iter().map(|s| s.to_string()).collect(),
+        })
+    }
+
+    #[test]
+    fn test_allow() {
+        let f = filter(&["Children", "*/Kids"], &[]);
+        assert!(f.is_allowed("Children"));
+        assert!(f.is_allowed("Children/Book/01.mp3"));
+        assert!(f.is_allowed("Author/Kids/01.mp3"));
+        assert!(!f.is_allowed("Author/Adult/01.mp3"));
+        assert!(!f.is_allowed("Children2"));
+        assert!(!f.is_allowed(""));
+        assert!(!f.is_allowed("Author"));
+
+        assert!(f.is_visible(""));
+        assert!(f.is_visible("Author"));
+        assert!(f.is_visible("Children/Book"));
+        assert!(!f.is_visible("Author/Adult"));
+        assert!(!f.is_visible("Other/Adult"));
+    }
+
+    #[test]
+    fn test_deny() {
+        let f = filter(&[], &["Horror", "**/Scary*"]);
+        assert!(f.is_allowed("Children/Book"));
+        assert!(f.is_allowed(""));
+        assert!(!f.is_allowed("Horror/Book/01.mp3"));
+        assert!(!f.is_visible("Horror"));
+        assert!(!f.is_visible("Children/Scary Book"));
+        assert!(!f.is_allowed("Scary Book/01.mp3"));
+
+        let f = filter(&["Children/**"], &["Children/Scary"]);
+        assert!(f.is_allowed("Children/Book"));
+        assert!(f.is_visible(""));
+        assert!(!f.is_visible("Children/Scary"));
+    }
+
+    #[test]
+    fn test_apply_folder() {
+        use collection::audio_meta::{AudioFolderShort, TypedFile};
+        let f = filter(&["Children"], &[]);
+        let mut folder = AudioFolder {
+            is_file: false,
+            is_collapsed: false,
+            modified: None,
+            total_time: None,
+            total_size: None,
+            files: vec![],
+            subfolders: ["/Children", "/Horror"]
+                .iter()
+                .map(|p| AudioFolderShort::from_path_simple(Path::new("/"), p))
+                .collect(),
+            cover: Some(TypedFile::new("cover.jpg")),
+            description: None,
+            position: None,
+            tags: None,
+        };
+        f.apply_folder(Path::new(""), &mut folder);
+        assert_eq!(1, folder.subfolders.len());
+        assert_eq!(Path::new("Children"), folder.subfolders[0].path);
+        assert!(folder.cover.is_none());
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        let f = filter(&["[a/b]"], &[]);
+        assert!(!f.is_allowed("Children/Book"));
+        assert!(!f.is_visible(""));
+
+        let f = filter(&[], &["[abc"]);
+        assert!(!f.is_allowed("Children/Book"));
+    }
+}
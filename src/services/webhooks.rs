@@ -0,0 +1,177 @@
+//! Webhooks - JSON notifications POSTed to configured URLs on selected events (book finished,
+//! new folder added, scan finished). Events are taken from same event bus as SSE stream,
+//! failed deliveries are retried with exponential backoff.
+
+use std::time::{Duration, SystemTime};
+
+use bytes::Bytes;
+use myhy::client::send_request;
+use myhy::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use myhy::{Method, StatusCode};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use url::Url;
+
+use super::events::{self, Event};
+use crate::config::{get_config, WebhookConfig, WebhookEvent};
+
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    event: WebhookEvent,
+    /// unix time in milliseconds
+    timestamp: u64,
+    collection: usize,
+    folder: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group: Option<&'a str>,
+}
+
+impl<'a> Payload<'a> {
+    fn from_event(evt: &'a Event) -> Option<Self> {
+        let (event, collection, folder, group) = match evt {
+            Event::Folder {
+                collection,
+                path,
+                added: true,
+            } => (WebhookEvent::FolderAdded, *collection, path.as_str(), None),
+            Event::Position {
+                collection,
+                group,
+                folder,
+                finished: true,
+            } => (
+                WebhookEvent::BookFinished,
+                *collection,
+                folder.as_str(),
+                Some(group.as_str()),
+            ),
+            Event::ScanFinished { collection } => {
+                (WebhookEvent::ScanFinished, *collection, "", None)
+            }
+            _ => return None,
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Some(Payload {
+            event,
+            timestamp,
+            collection,
+            folder,
+            group,
+        })
+    }
+}
+
+/// Dispatches events to webhooks, runs until event bus is closed
+pub async fn run_webhooks() {
+    let cfg = &get_config().webhooks;
+    info!("Sending events to {} webhook(s)", cfg.hooks.len());
+    let mut events = events::subscribe();
+    loop {
+        let evt = match events.recv().await {
+            Ok(evt) => evt,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("Webhooks missed {} events", missed);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let Some(payload) = Payload::from_event(&evt) else {
+            continue;
+        };
+        let body: Bytes = match serde_json::to_vec(&payload) {
+            Ok(body) => body.into(),
+            Err(e) => {
+                error!("Cannot serialize webhook payload: {}", e);
+                continue;
+            }
+        };
+        for hook in cfg.hooks.iter().filter(|h| h.accepts(payload.event)) {
+            tokio::spawn(deliver(hook, body.clone()));
+        }
+    }
+}
+
+async fn deliver(hook: &'static WebhookConfig, body: Bytes) {
+    let cfg = &get_config().webhooks;
+    // both URL and headers are validated in config check
+    let url = Url::parse(&hook.url).expect("valid webhook URL");
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    for (name, value) in &hook.headers {
+        if let (Ok(name), Ok(value)) = (name.parse::<HeaderName>(), value.parse::<HeaderValue>()) {
+            headers.insert(name, value);
+        }
+    }
+    let timeout = Duration::from_secs(cfg.timeout as u64);
+    let mut attempt = 0;
+    loop {
+        let res = send_request(Method::POST, &url, &headers, body.clone(), timeout).await;
+        let retry = match res {
+            Ok((status, _)) if status.is_success() => {
+                debug!("Webhook {} delivered", hook.url);
+                return;
+            }
+            Ok((status, _)) => {
+                error!("Webhook {} failed with status {}", hook.url, status);
+                // other client errors will not be fixed by retry
+                is_retriable(status)
+            }
+            Err(e) => {
+                error!("Webhook {} failed: {}", hook.url, e);
+                true
+            }
+        };
+        if !retry || attempt >= cfg.max_retries {
+            warn!("Giving up delivery to webhook {}", hook.url);
+            return;
+        }
+        tokio::time::sleep(retry_delay(cfg.retry_delay, attempt)).await;
+        attempt += 1;
+    }
+}
+
+pub(crate) fn is_retriable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+pub(crate) fn retry_delay(base_secs: u32, attempt: u32) -> Duration {
+    Duration::from_secs(base_secs as u64 * 2u64.pow(attempt.min(16)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payload() {
+        let evt = Event::Position {
+            collection: 1,
+            group: "ivan".into(),
+            folder: "Adams Douglas".into(),
+            finished: true,
+        };
+        let payload = Payload::from_event(&evt).unwrap();
+        assert_eq!(WebhookEvent::BookFinished, payload.event);
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!("book-finished", json["event"]);
+        assert_eq!("ivan", json["group"]);
+
+        let evt = Event::Folder {
+            collection: 0,
+            path: "a".into(),
+            added: false,
+        };
+        assert!(Payload::from_event(&evt).is_none());
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(Duration::from_secs(10), retry_delay(10, 0));
+        assert_eq!(Duration::from_secs(40), retry_delay(10, 2));
+    }
+}
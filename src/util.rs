@@ -1,4 +1,6 @@
+use std::io::{self, Write};
 use std::path::Path;
+use std::str::FromStr;
 
 /// exists or is current dir
 pub fn parent_dir_exists<P: AsRef<Path>>(p: &P) -> bool {
@@ -8,8 +10,51 @@ pub fn parent_dir_exists<P: AsRef<Path>>(p: &P) -> bool {
     }
 }
 
-#[cfg(feature = "shared-positions")]
+/// Writes secret to file, which is readable only by owner (on unix)
+pub fn write_secret_file<P: AsRef<Path>>(file: P, secret: &[u8]) -> io::Result<()> {
+    let mut f;
+    #[cfg(unix)]
+    {
+        use std::fs::OpenOptions;
+        use std::os::unix::fs::OpenOptionsExt;
+        f = OpenOptions::new()
+            .mode(0o600)
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(file)?
+    }
+    #[cfg(not(unix))]
+    {
+        f = std::fs::File::create(file)?
+    }
+    f.write_all(secret)
+}
+
 pub fn parse_cron<S: AsRef<str>>(exp: S) -> crate::error::Result<cron::Schedule> {
     let exp = format!("0 {} *", exp.as_ref());
     exp.parse().map_err(crate::Error::from)
 }
+
+/// Parses list of collection indexes separated by commas or whitespace
+pub fn parse_collection_indexes(s: &str) -> crate::error::Result<Vec<usize>> {
+    s.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| usize::from_str(part).map_err(crate::Error::from))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_collection_indexes() {
+        assert_eq!(
+            vec![0, 2, 3],
+            parse_collection_indexes("0, 2\n3\n").unwrap()
+        );
+        assert!(parse_collection_indexes("").unwrap().is_empty());
+        assert!(parse_collection_indexes("1,x").is_err());
+    }
+}